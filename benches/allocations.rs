@@ -0,0 +1,185 @@
+//! Counts the allocations a session makes per frame once it runs in a steady state. Run with `cargo bench --bench allocations`.
+//! Unlike the other benchmarks, this measures allocations instead of time, so it prints one line per scenario.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ggrs::testing::LoopbackNetwork;
+use ggrs::{FixedInputConfig, GgrsRequest, P2PSession, PlayerType, SessionBuilder, SessionState};
+
+type BenchConfig = FixedInputConfig<4, u64>;
+
+const WARM_UP_FRAMES: u32 = 200;
+const MEASURED_FRAMES: u32 = 1000;
+const FRAME_DURATION: Duration = Duration::from_millis(16);
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts every allocation of the process.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A game whose state is the sum of all inputs so far.
+#[derive(Default)]
+struct Game {
+    state: u64,
+}
+
+impl Game {
+    fn handle_requests<'a>(
+        &mut self,
+        requests: impl IntoIterator<Item = &'a GgrsRequest<BenchConfig>>,
+    ) {
+        for request in requests {
+            match request {
+                GgrsRequest::SaveGameState { cell, frame } => {
+                    cell.save(*frame, Some(self.state), None)
+                }
+                GgrsRequest::LoadGameState { cell, .. } => self.state = cell.load().unwrap(),
+                GgrsRequest::AdvanceFrame { inputs } => {
+                    for (input, _) in inputs {
+                        self.state += u32::from_le_bytes(*input) as u64;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Runs `frame` for the warm-up frames, then returns the allocations per frame of the measured frames.
+fn allocations_per_frame(mut frame: impl FnMut(u32)) -> f64 {
+    for i in 0..WARM_UP_FRAMES {
+        frame(i);
+    }
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for i in WARM_UP_FRAMES..WARM_UP_FRAMES + MEASURED_FRAMES {
+        frame(i);
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / MEASURED_FRAMES as f64
+}
+
+/// A session with two local players, which rolls back a few frames every 10 frames.
+fn local_session() -> P2PSession<BenchConfig> {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 7777);
+    SessionBuilder::<BenchConfig>::new()
+        .with_input_history(8)
+        .add_player(PlayerType::Local, 0)
+        .unwrap()
+        .add_player(PlayerType::Local, 1)
+        .unwrap()
+        .start_p2p_session(LoopbackNetwork::new(0).socket(addr))
+        .unwrap()
+}
+
+fn add_local_inputs(sess: &mut P2PSession<BenchConfig>, frame: u32) {
+    if frame % 10 == 9 {
+        sess.force_rollback_to(sess.current_frame() - 5).unwrap();
+    }
+    for handle in 0..2 {
+        sess.add_local_input(handle, frame.to_le_bytes()).unwrap();
+    }
+}
+
+fn bench_local_advance_frame() -> f64 {
+    let mut sess = local_session();
+    let mut game = Game::default();
+    allocations_per_frame(|frame| {
+        add_local_inputs(&mut sess, frame);
+        game.handle_requests(&sess.advance_frame().unwrap());
+    })
+}
+
+fn bench_local_advance_frame_into() -> f64 {
+    let mut sess = local_session();
+    let mut game = Game::default();
+    let mut requests = Vec::new();
+    allocations_per_frame(|frame| {
+        add_local_inputs(&mut sess, frame);
+        sess.advance_frame_into(&mut requests).unwrap();
+        game.handle_requests(&requests);
+    })
+}
+
+/// Two sessions over an in-memory network whose differing inputs cause rollbacks on both sides. Besides the sessions, this
+/// counts the packets they encode and decode and the messages the loopback sockets queue.
+fn bench_p2p_advance_frame_into() -> f64 {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8888);
+    let network = LoopbackNetwork::new(0);
+    let mut sessions = [
+        SessionBuilder::<BenchConfig>::new()
+            .add_player(PlayerType::Local, 0)
+            .unwrap()
+            .add_player(PlayerType::Remote(addr2), 1)
+            .unwrap()
+            .start_p2p_session(network.socket(addr1))
+            .unwrap(),
+        SessionBuilder::<BenchConfig>::new()
+            .add_player(PlayerType::Remote(addr1), 0)
+            .unwrap()
+            .add_player(PlayerType::Local, 1)
+            .unwrap()
+            .start_p2p_session(network.socket(addr2))
+            .unwrap(),
+    ];
+    while sessions
+        .iter()
+        .any(|sess| sess.current_state() != SessionState::Running)
+    {
+        sessions
+            .iter_mut()
+            .for_each(|sess| drop(sess.poll_remote_clients()));
+    }
+
+    let mut games = [Game::default(), Game::default()];
+    let mut requests = [Vec::new(), Vec::new()];
+    allocations_per_frame(|frame| {
+        // the sessions run on the simulated clock at 60 frames per second, so they stay below the receive rate limit
+        network.advance(FRAME_DURATION);
+        network.enter(|| {
+            for (handle, sess) in sessions.iter_mut().enumerate() {
+                sess.poll_remote_clients();
+                let input = frame / (handle as u32 * 2 + 1);
+                sess.add_local_input(handle, input.to_le_bytes()).unwrap();
+                sess.advance_frame_into(&mut requests[handle]).unwrap();
+                games[handle].handle_requests(&requests[handle]);
+            }
+        })
+    })
+}
+
+fn main() {
+    println!(
+        "local_advance_frame        {:>8.2} allocations per frame",
+        bench_local_advance_frame()
+    );
+    println!(
+        "local_advance_frame_into   {:>8.2} allocations per frame",
+        bench_local_advance_frame_into()
+    );
+    println!(
+        "p2p_advance_frame_into     {:>8.2} allocations per frame",
+        bench_p2p_advance_frame_into()
+    );
+}
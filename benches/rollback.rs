@@ -0,0 +1,243 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ggrs::testing::{LoopbackNetwork, LoopbackSocket};
+use ggrs::{
+    parse_packet, Config, GgrsRequest, Message, NonBlockingSocket, P2PSession, PlayerType,
+    SessionBuilder, SessionState, StateBytes,
+};
+
+#[path = "../tests/stubs.rs"]
+mod stubs;
+
+use stubs::{GameStub, StubConfig, StubInput};
+
+const FRAME_DURATION: Duration = Duration::from_millis(16);
+
+/// Wraps a loopback socket and records the messages it sends.
+struct RecordingSocket {
+    inner: LoopbackSocket<SocketAddr>,
+    sent: Arc<Mutex<Vec<Message>>>,
+}
+
+impl NonBlockingSocket<SocketAddr> for RecordingSocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        self.sent.lock().unwrap().push(msg.clone());
+        self.inner.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        self.inner.receive_all_messages()
+    }
+}
+
+/// Starts two sessions over an in-memory network without latency, so that benchmarks measure GGRS and not the operating
+/// system. The messages the first session sends are recorded in `sent`, if given.
+fn p2p_sessions(
+    network: &LoopbackNetwork<SocketAddr>,
+    sent: Option<Arc<Mutex<Vec<Message>>>>,
+) -> (P2PSession<StubConfig>, P2PSession<StubConfig>) {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8888);
+
+    let builder1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)
+        .unwrap()
+        .add_player(PlayerType::Remote(addr2), 1)
+        .unwrap();
+    let mut sess1 = match sent {
+        Some(sent) => builder1.start_p2p_session(RecordingSocket {
+            inner: network.socket(addr1),
+            sent,
+        }),
+        None => builder1.start_p2p_session(network.socket(addr1)),
+    }
+    .unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)
+        .unwrap()
+        .add_player(PlayerType::Local, 1)
+        .unwrap()
+        .start_p2p_session(network.socket(addr2))
+        .unwrap();
+
+    while sess1.current_state() != SessionState::Running
+        || sess2.current_state() != SessionState::Running
+    {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    (sess1, sess2)
+}
+
+fn bench_synctest_advance_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("synctest_advance_frame");
+    for check_distance in [1, 2, 4, 7] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(check_distance),
+            &check_distance,
+            |b, &check_distance| {
+                let mut sess = SessionBuilder::<StubConfig>::new()
+                    .with_check_distance(check_distance)
+                    .start_synctest_session()
+                    .unwrap();
+                let mut stub = GameStub::new();
+                let mut frame = 0;
+                b.iter(|| {
+                    for handle in 0..2 {
+                        sess.add_local_input(handle, StubInput { inp: frame })
+                            .unwrap();
+                    }
+                    stub.handle_requests(sess.advance_frame().unwrap());
+                    frame += 1;
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+/// A large state of which only a few bytes change every frame, as is typical for games with big worlds.
+#[derive(Clone)]
+struct BigState(Vec<u8>);
+
+impl StateBytes for BigState {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self(bytes.to_vec())
+    }
+}
+
+struct BigStateConfig;
+
+impl Config for BigStateConfig {
+    type Input = StubInput;
+    type State = BigState;
+    type Address = SocketAddr;
+}
+
+fn bench_state_diffing(c: &mut Criterion) {
+    const STATE_SIZE: usize = 64 * 1024;
+
+    let mut group = c.benchmark_group("synctest_state_diffing");
+    for state_diffing in [false, true] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(state_diffing),
+            &state_diffing,
+            |b, &state_diffing| {
+                let mut sess = SessionBuilder::<BigStateConfig>::new()
+                    .with_state_diffing(state_diffing)
+                    .start_synctest_session()
+                    .unwrap();
+                let mut state = BigState(vec![0; STATE_SIZE]);
+                let mut frame = 0;
+                b.iter(|| {
+                    for handle in 0..2 {
+                        sess.add_local_input(handle, StubInput { inp: frame })
+                            .unwrap();
+                    }
+                    for request in sess.advance_frame().unwrap() {
+                        match request {
+                            GgrsRequest::SaveGameState { cell, frame } => {
+                                cell.save(frame, Some(state.clone()), Some(frame as u128))
+                            }
+                            GgrsRequest::LoadGameState { cell, .. } => state = cell.load().unwrap(),
+                            GgrsRequest::AdvanceFrame { inputs } => {
+                                let pos = (inputs[0].0.inp as usize * 31) % STATE_SIZE;
+                                state.0[pos] = state.0[pos].wrapping_add(1);
+                            }
+                            _ => (),
+                        }
+                    }
+                    frame += 1;
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_p2p_advance_frame(c: &mut Criterion) {
+    c.bench_function("p2p_advance_frame", |b| {
+        let network = LoopbackNetwork::new(0);
+        let (mut sess1, mut sess2) = p2p_sessions(&network, None);
+        let mut stub1 = GameStub::new();
+        let mut stub2 = GameStub::new();
+        let mut frame = 0;
+        b.iter(|| {
+            // the sessions run on the simulated clock at 60 frames per second, so they stay below the receive rate limit
+            network.advance(FRAME_DURATION);
+            network.enter(|| {
+                sess1.poll_remote_clients();
+                sess2.poll_remote_clients();
+
+                // differing inputs cause mispredictions and thus rollbacks on both sides
+                sess1.add_local_input(0, StubInput { inp: frame }).unwrap();
+                stub1.handle_requests(sess1.advance_frame().unwrap());
+                sess2
+                    .add_local_input(1, StubInput { inp: frame / 3 })
+                    .unwrap();
+                stub2.handle_requests(sess2.advance_frame().unwrap());
+            });
+            frame += 1;
+        });
+    });
+}
+
+fn bench_message_serialization(c: &mut Criterion) {
+    // record the messages of a running session to get realistic input packets
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let (mut sess1, mut sess2) = p2p_sessions(&LoopbackNetwork::new(0), Some(sent.clone()));
+    let mut stub1 = GameStub::new();
+    let mut stub2 = GameStub::new();
+    for frame in 0..100 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        sess1.add_local_input(0, StubInput { inp: frame }).unwrap();
+        stub1.handle_requests(sess1.advance_frame().unwrap());
+        sess2.add_local_input(1, StubInput { inp: frame }).unwrap();
+        stub2.handle_requests(sess2.advance_frame().unwrap());
+    }
+    let messages = sent.lock().unwrap().clone();
+    let encoded: Vec<Vec<u8>> = messages
+        .iter()
+        .map(|msg| {
+            let mut buffer = Vec::new();
+            msg.encode_into(&mut buffer);
+            buffer
+        })
+        .collect();
+
+    c.bench_function("message_encode", |b| {
+        let mut buffer = Vec::new();
+        b.iter(|| {
+            for msg in &messages {
+                buffer.clear();
+                black_box(msg).encode_into(&mut buffer);
+                black_box(&buffer);
+            }
+        })
+    });
+    c.bench_function("message_decode", |b| {
+        b.iter(|| {
+            for bytes in &encoded {
+                black_box(parse_packet(black_box(bytes)).unwrap());
+            }
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_synctest_advance_frame,
+    bench_state_diffing,
+    bench_p2p_advance_frame,
+    bench_message_serialization
+);
+criterion_main!(benches);
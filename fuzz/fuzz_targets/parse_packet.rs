@@ -0,0 +1,14 @@
+#![no_main]
+
+use ggrs::parse_packet;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // parsing must never panic, and every parsed message must survive a round trip
+    if let Ok(msg) = parse_packet(data) {
+        let mut buffer = Vec::new();
+        msg.encode_into(&mut buffer);
+        assert_eq!(buffer, data);
+        assert_eq!(parse_packet(&buffer), Ok(msg));
+    }
+});
@@ -0,0 +1,60 @@
+#![no_main]
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use bytemuck::{Pod, Zeroable};
+use ggrs::{parse_packet, Config, Message, NonBlockingSocket, PlayerType, SessionBuilder};
+use libfuzzer_sys::fuzz_target;
+
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Pod, Zeroable)]
+struct FuzzInput {
+    inp: u32,
+}
+
+struct FuzzConfig;
+
+impl Config for FuzzConfig {
+    type Input = FuzzInput;
+    type State = u32;
+    type Address = SocketAddr;
+}
+
+/// Delivers the fuzzed packets as if they were sent by the remote player, and discards everything sent.
+struct FuzzSocket {
+    remote: SocketAddr,
+    packets: Vec<Vec<u8>>,
+}
+
+impl NonBlockingSocket<SocketAddr> for FuzzSocket {
+    fn send_to(&mut self, _msg: &Message, _addr: &SocketAddr) {}
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        self.packets
+            .drain(..)
+            .filter_map(|packet| parse_packet(&packet).ok())
+            .map(|msg| (self.remote, msg))
+            .collect()
+    }
+}
+
+fuzz_target!(|packets: Vec<Vec<u8>>| {
+    // feeding arbitrary packets into a session must never panic
+    let remote = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 7777);
+    let socket = FuzzSocket { remote, packets };
+    let mut sess = SessionBuilder::<FuzzConfig>::new()
+        .add_player(PlayerType::Local, 0)
+        .unwrap()
+        .add_player(PlayerType::Remote(remote), 1)
+        .unwrap()
+        .start_p2p_session(socket)
+        .unwrap();
+
+    sess.poll_remote_clients();
+    for _ in 0..4 {
+        if sess.add_local_input(0, FuzzInput { inp: 0 }).is_ok() {
+            let _ = sess.advance_frame();
+        }
+        sess.poll_remote_clients();
+    }
+});
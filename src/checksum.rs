@@ -0,0 +1,185 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use twox_hash::XxHash64;
+
+use crate::{sync_layer::GameStateCell, StateBytes};
+
+/// A [`StateHasher`] computes the checksum of a saved state from its bytes, if the game saved it without a checksum
+/// (see [`SessionBuilder::with_state_hasher()`]). The checksums decide whether peers report a desync, so contexts that rely on
+/// these verdicts, like adjudicating ranked matches, should use a collision-resistant hash like the `Blake3Hasher` of the
+/// `blake3` feature. All peers and spectators of a session need to use the same hasher.
+///
+/// [`SessionBuilder::with_state_hasher()`]: crate::SessionBuilder::with_state_hasher
+#[cfg(feature = "sync-send")]
+pub trait StateHasher: Debug + Send + Sync {
+    /// Returns the checksum of the given state bytes.
+    fn hash(&self, bytes: &[u8]) -> u128;
+}
+
+/// A [`StateHasher`] computes the checksum of a saved state from its bytes, if the game saved it without a checksum
+/// (see [`SessionBuilder::with_state_hasher()`]). The checksums decide whether peers report a desync, so contexts that rely on
+/// these verdicts, like adjudicating ranked matches, should use a collision-resistant hash like the `Blake3Hasher` of the
+/// `blake3` feature. All peers and spectators of a session need to use the same hasher.
+///
+/// [`SessionBuilder::with_state_hasher()`]: crate::SessionBuilder::with_state_hasher
+#[cfg(not(feature = "sync-send"))]
+pub trait StateHasher: Debug {
+    /// Returns the checksum of the given state bytes.
+    fn hash(&self, bytes: &[u8]) -> u128;
+}
+
+/// The checksum GGRS computes from the bytes of a saved state if the game saved it without a checksum,
+/// see [`SessionBuilder::with_auto_checksums()`]. [`ChecksumKind::XxHash64`] is the default [`StateHasher`].
+///
+/// [`SessionBuilder::with_auto_checksums()`]: crate::SessionBuilder::with_auto_checksums
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChecksumKind {
+    /// The Fletcher-32 checksum of the bytes, read as little endian 16 bit words. Cheap to compute, but only 32 bits wide
+    /// and blind to some changes, like a word flipping between `0x0000` and `0xffff`.
+    Fletcher32,
+    /// The 64 bit xxHash of the bytes. Still fast, and far less likely to miss a desync.
+    #[default]
+    XxHash64,
+}
+
+impl ChecksumKind {
+    /// Returns the checksum of the given bytes.
+    pub fn checksum(self, bytes: &[u8]) -> u128 {
+        match self {
+            ChecksumKind::Fletcher32 => fletcher32(bytes) as u128,
+            ChecksumKind::XxHash64 => XxHash64::oneshot(0, bytes) as u128,
+        }
+    }
+}
+
+impl StateHasher for ChecksumKind {
+    fn hash(&self, bytes: &[u8]) -> u128 {
+        self.checksum(bytes)
+    }
+}
+
+/// A [`StateHasher`] using the first 128 bits of the cryptographic BLAKE3 hash, so that states with the same checksum can be
+/// trusted to be the same. Slower than a [`ChecksumKind`]. Requires the `blake3` feature.
+#[cfg(feature = "blake3")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Blake3Hasher;
+
+#[cfg(feature = "blake3")]
+impl StateHasher for Blake3Hasher {
+    fn hash(&self, bytes: &[u8]) -> u128 {
+        let hash = blake3::hash(bytes);
+        let (first, _) = hash
+            .as_bytes()
+            .split_first_chunk::<16>()
+            .expect("Hash too short");
+        u128::from_le_bytes(*first)
+    }
+}
+
+/// Fills in the checksums of saved states the game did not provide a checksum for.
+pub(crate) struct AutoChecksums<S> {
+    hasher: Arc<dyn StateHasher>,
+    to_bytes: fn(&S) -> Vec<u8>,
+}
+
+impl<S: StateBytes> AutoChecksums<S> {
+    pub(crate) fn new(hasher: Arc<dyn StateHasher>) -> Self {
+        Self {
+            hasher,
+            to_bytes: S::to_bytes,
+        }
+    }
+}
+
+impl<S: Clone> AutoChecksums<S> {
+    /// Computes the checksum of the state in the cell if it has none. Must be called before the state is compacted by the
+    /// state diffing, as the cell does not contain a state anymore afterwards.
+    pub(crate) fn fill(&self, cell: &GameStateCell<S>) {
+        cell.map_state(|state| {
+            if state.checksum.is_none() {
+                if let Some(data) = &state.data {
+                    state.checksum = Some(self.hasher.hash(&(self.to_bytes)(data)));
+                }
+            }
+        });
+    }
+}
+
+impl<S> Clone for AutoChecksums<S> {
+    fn clone(&self) -> Self {
+        Self {
+            hasher: self.hasher.clone(),
+            to_bytes: self.to_bytes,
+        }
+    }
+}
+
+impl<S> std::fmt::Debug for AutoChecksums<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AutoChecksums")
+            .field("hasher", &self.hasher)
+            .finish_non_exhaustive()
+    }
+}
+
+fn fletcher32(bytes: &[u8]) -> u32 {
+    // the sums are reduced every few thousand words, long before they could overflow
+    const BLOCK_WORDS: usize = 4096;
+    let (mut sum1, mut sum2) = (0_u64, 0_u64);
+    for block in bytes.chunks(2 * BLOCK_WORDS) {
+        for word in block.chunks(2) {
+            // an odd trailing byte is padded with a zero
+            sum1 += u16::from_le_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u64;
+            sum2 += sum1;
+        }
+        sum1 %= 0xffff;
+        sum2 %= 0xffff;
+    }
+    ((sum2 as u32) << 16) | sum1 as u32
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod checksum_tests {
+    use super::*;
+
+    #[test]
+    fn test_fletcher32() {
+        assert_eq!(ChecksumKind::Fletcher32.checksum(b"abcde"), 0xF04FC729);
+        assert_eq!(ChecksumKind::Fletcher32.checksum(b"abcdef"), 0x56502D2A);
+        assert_eq!(ChecksumKind::Fletcher32.checksum(b"abcdefgh"), 0xEBE19591);
+
+        // long enough to be reduced in between, compared to reducing after every word
+        let bytes: Vec<u8> = (0..3 * 8192 + 1).map(|i| (i * 7) as u8).collect();
+        let (mut sum1, mut sum2) = (0, 0);
+        for word in bytes.chunks(2) {
+            let word = u16::from_le_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32;
+            sum1 = (sum1 + word) % 0xffff;
+            sum2 = (sum2 + sum1) % 0xffff;
+        }
+        assert_eq!(fletcher32(&bytes), (sum2 << 16) | sum1);
+    }
+
+    #[test]
+    fn test_xxhash64() {
+        assert_eq!(ChecksumKind::XxHash64.checksum(b""), 0xEF46DB3751D8E999);
+        assert_eq!(ChecksumKind::default().hash(b""), 0xEF46DB3751D8E999);
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_blake3() {
+        // the first 16 bytes of af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262
+        assert_eq!(
+            Blake3Hasher.hash(b""),
+            u128::from_le_bytes([
+                0xaf, 0x13, 0x49, 0xb9, 0xf5, 0xf9, 0xa1, 0xa6, 0xa0, 0x40, 0x4d, 0xea, 0x36, 0xdc,
+                0xc9, 0x49
+            ])
+        );
+    }
+}
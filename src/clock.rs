@@ -0,0 +1,135 @@
+use std::cell::Cell;
+use std::fmt::Debug;
+use std::sync::OnceLock;
+
+use instant::{Duration, Instant};
+
+thread_local! {
+    /// If set, the clock of this thread is simulated: it reads the given instant and time since the epoch in ms plus the
+    /// elapsed simulated time. See [`crate::testing::LoopbackNetwork::enter()`].
+    static SIMULATED: Cell<Option<(Instant, u128, Duration)>> = const { Cell::new(None) };
+}
+
+/// A [`Clock`] is the source of time for all timers of a session, like timeouts, pings and the frame advantage
+/// (see [`SessionBuilder::with_clock()`]). Implement it for consoles with their own time APIs, or for test harnesses that
+/// need full control over time. [`StdClock`] is the default.
+///
+/// [`SessionBuilder::with_clock()`]: crate::SessionBuilder::with_clock
+#[cfg(feature = "sync-send")]
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current time in microseconds. It must never decrease, but it can start at any value.
+    fn now_micros(&self) -> u64;
+
+    /// Returns the current time in ms since the unix epoch. It is only used to agree on the start time of a synchronized start
+    /// with the remote clients and to timestamp traced packets. By default, this reads the time of the [`StdClock`].
+    fn millis_since_epoch(&self) -> u128 {
+        StdClock.millis_since_epoch()
+    }
+}
+
+/// A [`Clock`] is the source of time for all timers of a session, like timeouts, pings and the frame advantage
+/// (see [`SessionBuilder::with_clock()`]). Implement it for consoles with their own time APIs, or for test harnesses that
+/// need full control over time. [`StdClock`] is the default.
+///
+/// [`SessionBuilder::with_clock()`]: crate::SessionBuilder::with_clock
+#[cfg(not(feature = "sync-send"))]
+pub trait Clock: Debug {
+    /// Returns the current time in microseconds. It must never decrease, but it can start at any value.
+    fn now_micros(&self) -> u64;
+
+    /// Returns the current time in ms since the unix epoch. It is only used to agree on the start time of a synchronized start
+    /// with the remote clients and to timestamp traced packets. By default, this reads the time of the [`StdClock`].
+    fn millis_since_epoch(&self) -> u128 {
+        StdClock.millis_since_epoch()
+    }
+}
+
+/// The default [`Clock`], based on the monotonic clock and the system time of the standard library, or the clock of the
+/// browser on wasm. It follows the simulated clock of a [`LoopbackNetwork`] inside of [`LoopbackNetwork::enter()`].
+///
+/// [`LoopbackNetwork`]: crate::testing::LoopbackNetwork
+/// [`LoopbackNetwork::enter()`]: crate::testing::LoopbackNetwork::enter
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StdClock;
+
+impl Clock for StdClock {
+    fn now_micros(&self) -> u64 {
+        let now = match SIMULATED.with(Cell::get) {
+            Some((start, _, elapsed)) => start + elapsed,
+            None => Instant::now(),
+        };
+        now.saturating_duration_since(base()).as_micros() as u64
+    }
+
+    fn millis_since_epoch(&self) -> u128 {
+        if let Some((_, start_millis, elapsed)) = SIMULATED.with(Cell::get) {
+            return start_millis + elapsed.as_millis();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            // a host clock set before the epoch counts as the epoch
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            js_sys::Date::new_0().get_time() as u128
+        }
+    }
+}
+
+/// The instant all times of a [`Clock`] are counted from, so they can be used like any other instant.
+fn base() -> Instant {
+    static BASE: OnceLock<Instant> = OnceLock::new();
+    *BASE.get_or_init(Instant::now)
+}
+
+/// Returns the current instant of the given clock, which the timers of a session are based on.
+pub(crate) fn now(clock: &dyn Clock) -> Instant {
+    base() + Duration::from_micros(clock.now_micros())
+}
+
+/// Runs `f` with the clock of this thread simulated to be `elapsed` after `start` and `start_millis` ms since the epoch.
+pub(crate) fn simulate<R>(
+    start: Instant,
+    start_millis: u128,
+    elapsed: Duration,
+    f: impl FnOnce() -> R,
+) -> R {
+    /// Restores the previous clock of this thread when dropped, even if `f` panics.
+    struct Restore(Option<(Instant, u128, Duration)>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SIMULATED.with(|simulated| simulated.set(self.0));
+        }
+    }
+
+    let _restore = Restore(
+        SIMULATED.with(|simulated| simulated.replace(Some((start, start_millis, elapsed)))),
+    );
+    f()
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod clock_tests {
+    use super::*;
+
+    #[test]
+    fn test_simulate_restores_clock_on_panic() {
+        let start = Instant::now();
+        let result = std::panic::catch_unwind(|| {
+            simulate(start, 0, Duration::from_secs(1), || {
+                panic!("the game panicked")
+            })
+        });
+        assert!(result.is_err());
+        assert_eq!(SIMULATED.with(Cell::get), None);
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::hint::black_box;
+
+use crate::Frame;
+
+/// The rounding mode of floating point operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RoundingMode {
+    /// Round to the nearest representable value, ties to even. This is the default on all platforms.
+    Nearest,
+    /// Round towards negative infinity.
+    Down,
+    /// Round towards positive infinity.
+    Up,
+    /// Round towards zero.
+    TowardZero,
+}
+
+/// The floating point environment the current thread computes with, as far as it influences the results of the game
+/// simulation. Requires the `debug-tools` feature.
+///
+/// It is measured by computing with values that are rounded or flushed differently in every mode, so it needs no access to
+/// the control registers of the FPU and works on every platform. A game simulation is only deterministic across machines if
+/// all clients simulate in the same environment, which is the default one unless a library, like a graphics or audio driver,
+/// changes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FloatEnvironment {
+    /// The rounding mode of floating point operations.
+    pub rounding: RoundingMode,
+    /// If `true`, results too small to be represented as a normal number are flushed to zero instead of being denormal.
+    pub flush_to_zero: bool,
+    /// If `true`, denormal operands are treated as zero.
+    pub denormals_are_zero: bool,
+}
+
+impl FloatEnvironment {
+    /// The default floating point environment, which all clients should simulate in.
+    pub const DEFAULT: Self = Self {
+        rounding: RoundingMode::Nearest,
+        flush_to_zero: false,
+        denormals_are_zero: false,
+    };
+
+    /// Measures the floating point environment of the current thread.
+    pub fn current() -> Self {
+        // the values pass through `black_box`, so the operations are executed at runtime instead of being folded by the compiler
+        let one = black_box(1.0f32);
+        let quarter = black_box(f32::EPSILON / 4.0);
+        let three_quarters = black_box(f32::EPSILON * 3.0 / 4.0);
+        let rounding = if one + quarter > one {
+            RoundingMode::Up
+        } else if -one - quarter < -one {
+            RoundingMode::Down
+        } else if one + three_quarters == one {
+            RoundingMode::TowardZero
+        } else {
+            RoundingMode::Nearest
+        };
+
+        let min_positive = black_box(f32::MIN_POSITIVE);
+        let denormal = black_box(f32::from_bits(1));
+        Self {
+            rounding,
+            flush_to_zero: min_positive / black_box(2.0) == 0.0,
+            denormals_are_zero: min_positive + denormal == min_positive,
+        }
+    }
+}
+
+impl Default for FloatEnvironment {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A change of the floating point environment, reported by [`DeterminismGuard::check()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FloatEnvironmentChange {
+    /// The frame at which the change was noticed. It happened since the previous check.
+    pub frame: Frame,
+    /// The environment before the change.
+    pub before: FloatEnvironment,
+    /// The environment after the change.
+    pub after: FloatEnvironment,
+}
+
+impl fmt::Display for FloatEnvironmentChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The floating point environment changed before frame {} from {:?} to {:?}. Simulating in a different environment than other clients leads to desyncs.",
+            self.frame, self.before, self.after
+        )
+    }
+}
+
+/// Watches the floating point environment the game is simulated in, a common source of desyncs between machines. Requires the
+/// `debug-tools` feature.
+///
+/// Create the guard on the thread that simulates the game and call [`check()`] once per frame, e.g. in a [`SyncTestSession`] run
+/// or in debug builds. It reports every change of the rounding mode or the handling of denormal numbers since the previous check,
+/// which narrows down the code that changed it. Independent of changes, [`DeterminismGuard::is_default()`] tells whether the
+/// environment differs from the one other clients most likely simulate in.
+///
+/// ```
+/// # use ggrs::DeterminismGuard;
+/// let mut guard = DeterminismGuard::new();
+/// for frame in 0..60 {
+///     // simulate the frame...
+///     if let Some(change) = guard.check(frame) {
+///         eprintln!("{change}");
+///     }
+/// }
+/// ```
+///
+/// [`check()`]: Self#method.check
+/// [`SyncTestSession`]: crate::SyncTestSession
+#[derive(Debug, Clone)]
+pub struct DeterminismGuard {
+    environment: FloatEnvironment,
+}
+
+impl DeterminismGuard {
+    /// Creates a guard that compares against the current floating point environment.
+    pub fn new() -> Self {
+        Self {
+            environment: FloatEnvironment::current(),
+        }
+    }
+
+    /// Returns the environment measured by the latest check.
+    pub fn environment(&self) -> FloatEnvironment {
+        self.environment
+    }
+
+    /// Returns `true` if the environment measured by the latest check is the default one.
+    pub fn is_default(&self) -> bool {
+        self.environment == FloatEnvironment::DEFAULT
+    }
+
+    /// Measures the floating point environment and returns how it changed since the previous check, if it did. `frame` is the
+    /// frame about to be simulated and only used to report the change.
+    pub fn check(&mut self, frame: Frame) -> Option<FloatEnvironmentChange> {
+        self.compare(frame, FloatEnvironment::current())
+    }
+
+    fn compare(
+        &mut self,
+        frame: Frame,
+        current: FloatEnvironment,
+    ) -> Option<FloatEnvironmentChange> {
+        let before = std::mem::replace(&mut self.environment, current);
+        (before != current).then_some(FloatEnvironmentChange {
+            frame,
+            before,
+            after: current,
+        })
+    }
+}
+
+impl Default for DeterminismGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod determinism_tests {
+    use super::*;
+
+    #[test]
+    fn test_default_environment() {
+        assert_eq!(FloatEnvironment::current(), FloatEnvironment::DEFAULT);
+        let mut guard = DeterminismGuard::new();
+        assert!(guard.is_default());
+        assert_eq!(guard.check(0), None);
+    }
+
+    #[test]
+    fn test_change_reported_once() {
+        let mut guard = DeterminismGuard::new();
+        let changed = FloatEnvironment {
+            flush_to_zero: true,
+            denormals_are_zero: true,
+            ..FloatEnvironment::DEFAULT
+        };
+
+        let change = guard.compare(5, changed).unwrap();
+        assert_eq!(change.frame, 5);
+        assert_eq!(change.before, FloatEnvironment::DEFAULT);
+        assert_eq!(change.after, changed);
+        assert!(!guard.is_default());
+        assert_eq!(guard.compare(6, changed), None);
+
+        // changing back is a change as well
+        assert!(guard.compare(7, FloatEnvironment::DEFAULT).is_some());
+        assert!(guard.is_default());
+    }
+}
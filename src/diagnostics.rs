@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+use crate::{DesyncDetection, Frame, MemoryReport, NetworkStats, PlayerHandle, RollbackStats};
+
+/// A [`DiagnosticsReport`] sums up the health of a [`P2PSession`] since it started, see [`P2PSession::export_diagnostics()`].
+/// It can be serialized with any [`serde`] format, so it can be sent to a telemetry backend after a match and aggregated
+/// across many players.
+///
+/// [`P2PSession`]: crate::P2PSession
+/// [`P2PSession::export_diagnostics()`]: crate::P2PSession::export_diagnostics
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiagnosticsReport {
+    /// The current frame of the session.
+    pub frame: Frame,
+    /// The last frame for which the inputs of all players have been received.
+    pub confirmed_frame: Frame,
+    /// The rollbacks done and avoided so far.
+    pub rollback_stats: RollbackStats,
+    /// The number of times the session could not advance because it reached the prediction threshold.
+    pub stalls: usize,
+    /// The number of desyncs detected so far.
+    pub desyncs: usize,
+    /// The remote clients and spectators, ordered by player handle.
+    pub peers: Vec<PeerDiagnostics>,
+    /// The bytes the session currently holds.
+    pub memory: MemoryReport,
+    /// The settings the session was started with.
+    pub settings: SessionSettings,
+}
+
+/// The diagnostics of a single remote client or spectator, part of a [`DiagnosticsReport`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerDiagnostics {
+    /// The handles of the players behind the remote client, or the handle of the spectator.
+    pub handles: Vec<PlayerHandle>,
+    /// Whether the remote client is a spectator.
+    pub spectator: bool,
+    /// The current network statistics, or `None` if the remote client has not synchronized yet.
+    pub network_stats: Option<NetworkStats>,
+    /// The address the remote client presented as reachable at from outside during synchronization, if it knows one.
+    pub external_addr: Option<SocketAddr>,
+}
+
+/// The settings of a session, part of a [`DiagnosticsReport`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSettings {
+    /// The number of players.
+    pub num_players: usize,
+    /// The number of spectators.
+    pub num_spectators: usize,
+    /// The maximum number of frames the session predicts ahead.
+    pub max_prediction: usize,
+    /// The input delay of the local players.
+    pub input_delay: usize,
+    /// Whether sparse saving is enabled.
+    pub sparse_saving: bool,
+    /// Whether the session runs delay-based instead of rolling back.
+    pub delay_based: bool,
+    /// How desyncs are detected.
+    pub desync_detection: DesyncDetection,
+}
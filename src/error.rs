@@ -2,15 +2,47 @@ use std::error::Error;
 use std::fmt;
 use std::fmt::Display;
 
-use crate::Frame;
+use crate::{Frame, PlayerHandle};
 
 /// This enum contains all error messages this library can return. Most API functions will generally return a [`Result<(), GgrsError>`].
 ///
 /// [`Result<(), GgrsError>`]: std::result::Result
 #[derive(Debug, Clone, PartialEq, Hash)]
+#[non_exhaustive]
 pub enum GgrsError {
     /// When the prediction threshold has been reached, we cannot accept more inputs from the local player.
     PredictionThreshold,
+    /// The given player handle does not refer to a player that the request can be made for.
+    InvalidPlayerHandle {
+        /// The invalid handle.
+        handle: PlayerHandle,
+    },
+    /// The given player has already been disconnected.
+    PlayerDisconnected {
+        /// The handle of the disconnected player.
+        handle: PlayerHandle,
+    },
+    /// No input has been added for a local player before advancing the frame.
+    MissingInput {
+        /// The handle of the player without input.
+        handle: PlayerHandle,
+        /// The frame for which the input is missing.
+        frame: Frame,
+    },
+    /// Input has already been added for a local player since the frame was last advanced, and input coalescing is turned off.
+    InputAlreadyAdded {
+        /// The handle of the player the input has been added for.
+        handle: PlayerHandle,
+        /// The frame the input has been added for.
+        frame: Frame,
+    },
+    /// The state of the given frame cannot be loaded, because it is outside of the rollback window or has not been saved.
+    StateUnavailable {
+        /// The frame that should have been loaded.
+        frame: Frame,
+        /// The current frame of the session.
+        current_frame: Frame,
+    },
     /// You made an invalid request, usually by using wrong parameters for function calls.
     InvalidRequest {
         /// Further specifies why the request was invalid.
@@ -29,6 +61,10 @@ pub enum GgrsError {
     NotSynchronized,
     /// The spectator got so far behind the host that catching up is impossible.
     SpectatorTooFarBehind,
+    /// The session reached [`MAX_FRAME`] and cannot advance any further.
+    ///
+    /// [`MAX_FRAME`]: crate::MAX_FRAME
+    FrameLimitReached,
 }
 
 impl Display for GgrsError {
@@ -40,6 +76,36 @@ impl Display for GgrsError {
                     "Prediction threshold is reached, cannot proceed without catching up."
                 )
             }
+            GgrsError::InvalidPlayerHandle { handle } => {
+                write!(f, "Invalid player handle {} for this request.", handle)
+            }
+            GgrsError::PlayerDisconnected { handle } => {
+                write!(f, "Player {} has already been disconnected.", handle)
+            }
+            GgrsError::MissingInput { handle, frame } => {
+                write!(
+                    f,
+                    "Missing input of local player {} for frame {} while advancing the frame.",
+                    handle, frame
+                )
+            }
+            GgrsError::InputAlreadyAdded { handle, frame } => {
+                write!(
+                    f,
+                    "Input of local player {} for frame {} has already been added.",
+                    handle, frame
+                )
+            }
+            GgrsError::StateUnavailable {
+                frame,
+                current_frame,
+            } => {
+                write!(
+                    f,
+                    "Cannot load the state of frame {} on frame {}, it is outside of the rollback window or has not been saved.",
+                    frame, current_frame
+                )
+            }
             GgrsError::InvalidRequest { info } => {
                 write!(f, "Invalid Request: {}", info)
             }
@@ -65,8 +131,93 @@ impl Display for GgrsError {
                     "The spectator got so far behind the host that catching up is impossible."
                 )
             }
+            GgrsError::FrameLimitReached => {
+                write!(
+                    f,
+                    "The session reached frame {} and cannot advance any further.",
+                    crate::MAX_FRAME
+                )
+            }
         }
     }
 }
 
 impl Error for GgrsError {}
+
+/// Describes why a received packet could not be parsed by [`parse_packet()`].
+///
+/// [`parse_packet()`]: crate::parse_packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PacketError {
+    /// The packet ended before the message was complete.
+    Truncated,
+    /// The packet is bigger than any valid message.
+    Oversized {
+        /// The length of the packet.
+        len: usize,
+        /// The maximum length of a valid packet.
+        max: usize,
+    },
+    /// The packet contains a message type that does not exist.
+    UnknownMessageType {
+        /// The unknown message type tag.
+        tag: u32,
+    },
+    /// The packet contains an address family that does not exist.
+    UnknownAddressFamily {
+        /// The unknown address family tag.
+        tag: u32,
+    },
+    /// The packet contains a boolean that is neither 0 nor 1.
+    InvalidBool {
+        /// The invalid value.
+        value: u8,
+    },
+    /// The packet contains an optional value whose tag is neither 0 nor 1.
+    InvalidOptionTag {
+        /// The invalid value.
+        value: u8,
+    },
+    /// The packet contains more bytes than the message it encodes.
+    TrailingBytes {
+        /// The number of excess bytes.
+        len: usize,
+    },
+}
+
+impl Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::Truncated => write!(f, "The packet is truncated."),
+            PacketError::Oversized { len, max } => {
+                write!(
+                    f,
+                    "The packet is {} bytes long, but may be at most {} bytes long.",
+                    len, max
+                )
+            }
+            PacketError::UnknownMessageType { tag } => {
+                write!(f, "The packet contains the unknown message type {}.", tag)
+            }
+            PacketError::UnknownAddressFamily { tag } => {
+                write!(f, "The packet contains the unknown address family {}.", tag)
+            }
+            PacketError::InvalidBool { value } => {
+                write!(
+                    f,
+                    "The packet contains the invalid boolean value {}.",
+                    value
+                )
+            }
+            PacketError::InvalidOptionTag { value } => {
+                write!(f, "The packet contains the invalid option tag {}.", value)
+            }
+            PacketError::TrailingBytes { len } => {
+                write!(f, "The packet contains {} trailing bytes.", len)
+            }
+        }
+    }
+}
+
+impl Error for PacketError {}
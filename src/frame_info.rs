@@ -1,23 +1,27 @@
-use crate::{Frame, NULL_FRAME};
+use crate::state_diff::StateDelta;
+use crate::Frame;
 
 /// Represents the game state of your game for a single frame. The `data` holds the game state, `frame` indicates the associated frame number
 /// and `checksum` can additionally be provided for use during a `SyncTestSession`.
 #[derive(Debug, Clone)]
 pub(crate) struct GameState<S: Clone> {
-    /// The frame to which this info belongs to.
-    pub frame: Frame,
+    /// The frame to which this info belongs to, or `None` if nothing has been saved yet.
+    pub frame: Option<Frame>,
     /// The game state
     pub data: Option<S>,
     /// The checksum of the gamestate.
     pub checksum: Option<u128>,
+    /// The game state as a delta to the next newer state, if state diffing is enabled and the state has been compacted.
+    pub delta: Option<StateDelta>,
 }
 
 impl<S: Clone> Default for GameState<S> {
     fn default() -> Self {
         Self {
-            frame: NULL_FRAME,
+            frame: None,
             data: None,
             checksum: None,
+            delta: None,
         }
     }
 }
@@ -34,8 +38,8 @@ where
         + bytemuck::CheckedBitPattern
         + bytemuck::Zeroable,
 {
-    /// The frame to which this info belongs to. -1/[`NULL_FRAME`] represents an invalid frame
-    pub frame: Frame,
+    /// The frame to which this info belongs to, or `None` for an input that belongs to no frame
+    pub frame: Option<Frame>,
     /// The input struct given by the user
     pub input: I,
 }
@@ -50,10 +54,13 @@ impl<
     > PlayerInput<I>
 {
     pub(crate) fn new(frame: Frame, input: I) -> Self {
-        Self { frame, input }
+        Self {
+            frame: Some(frame),
+            input,
+        }
     }
 
-    pub(crate) fn blank_input(frame: Frame) -> Self {
+    pub(crate) fn blank_input(frame: Option<Frame>) -> Self {
         Self {
             frame,
             input: I::zeroed(),
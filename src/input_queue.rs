@@ -1,9 +1,28 @@
 use crate::frame_info::PlayerInput;
-use crate::{Config, Frame, InputStatus, NULL_FRAME};
+#[cfg(feature = "debug-tools")]
+use crate::NULL_FRAME;
+use crate::{Config, Frame, InputStatus};
 use std::cmp;
 
-/// The length of the input queue. This describes the number of inputs GGRS can hold at the same time per player.
-const INPUT_QUEUE_LENGTH: usize = 128;
+/// The default length of the input queue. This describes the number of inputs GGRS can hold at the same time per player.
+pub(crate) const DEFAULT_INPUT_QUEUE_LENGTH: usize = 128;
+
+/// A snapshot of the input queue of a single player, for visualizing prediction depth and mispredictions. Requires the `debug-tools` feature.
+#[cfg(feature = "debug-tools")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputQueueSnapshot {
+    /// The oldest frame still held in the queue, or [`NULL_FRAME`] if the queue is empty.
+    pub first_frame: Frame,
+    /// The newest frame added to the queue, or [`NULL_FRAME`] if no input has been added yet.
+    pub last_added_frame: Frame,
+    /// The number of inputs held in the queue.
+    pub length: usize,
+    /// The input delay of the player in frames.
+    pub delay: usize,
+    /// The first frame for which a predicted input turned out to be wrong, if any. This is reset once the session has rolled back,
+    /// so take the snapshot before advancing the frame to count mispredictions.
+    pub first_incorrect_prediction: Option<Frame>,
+}
 
 /// `InputQueue` handles inputs for a single player and saves them in a circular array. Valid Inputs are between `head` and `tail`.
 #[derive(Debug, Clone)]
@@ -19,13 +38,17 @@ where
     length: usize,
     /// Denotes if we still are in the first frame, an edge case to be considered by some methods.
     first_frame: bool,
-
-    /// The last frame added by the user
-    last_added_frame: Frame,
-    /// The first frame in the queue that is known to be an incorrect prediction
-    first_incorrect_frame: Frame,
-    /// The last frame that has been requested. We make sure to never delete anything after this, as we would throw away important data.
-    last_requested_frame: Frame,
+    /// The frame the session starts at. The first input added to the queue belongs to this frame.
+    start_frame: Frame,
+
+    /// The last frame added by the user, if any
+    last_added_frame: Option<Frame>,
+    /// The first frame in the queue that is known to be an incorrect prediction, if any
+    first_incorrect_frame: Option<Frame>,
+    /// The last frame that has been requested, if any. We make sure to never delete anything after this, as we would throw away important data.
+    last_requested_frame: Option<Frame>,
+    /// Set when inputs arrived that confirmed all predictions made since the last reset.
+    predictions_confirmed: bool,
 
     /// The delay in frames by which inputs are sent back to the user. This can be set during initialization.
     frame_delay: usize,
@@ -34,44 +57,93 @@ where
     inputs: Vec<PlayerInput<T::Input>>,
     /// A pre-allocated prediction we are going to use to return predictions from.
     prediction: PlayerInput<T::Input>,
+    /// If set, only the bits set in the mask are predicted to repeat the last input, all others are predicted to be cleared.
+    sticky_mask: Option<T::Input>,
 }
 
 impl<T: Config> InputQueue<T> {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(length: usize) -> Self {
         Self {
             head: 0,
             tail: 0,
             length: 0,
             frame_delay: 0,
             first_frame: true,
-            last_added_frame: NULL_FRAME,
-            first_incorrect_frame: NULL_FRAME,
-            last_requested_frame: NULL_FRAME,
-            prediction: PlayerInput::blank_input(NULL_FRAME),
-            inputs: vec![PlayerInput::blank_input(NULL_FRAME); INPUT_QUEUE_LENGTH],
+            start_frame: 0,
+            last_added_frame: None,
+            first_incorrect_frame: None,
+            last_requested_frame: None,
+            predictions_confirmed: false,
+            prediction: PlayerInput::blank_input(None),
+            sticky_mask: None,
+            inputs: vec![PlayerInput::blank_input(None); length],
         }
     }
 
-    pub(crate) fn first_incorrect_frame(&self) -> Frame {
+    pub(crate) fn first_incorrect_frame(&self) -> Option<Frame> {
         self.first_incorrect_frame
     }
 
+    pub(crate) fn last_added_frame(&self) -> Option<Frame> {
+        self.last_added_frame
+    }
+
+    pub(crate) fn frame_delay(&self) -> usize {
+        self.frame_delay
+    }
+
     pub(crate) fn set_frame_delay(&mut self, delay: usize) {
         self.frame_delay = delay;
     }
 
+    /// Lets the queue start at the given frame instead of frame 0. Must be called before any input has been added.
+    pub(crate) fn set_start_frame(&mut self, frame: Frame) {
+        assert!(self.first_frame && frame >= 0);
+        self.start_frame = frame;
+        // inputs are stored at the position of their frame, see confirmed_input()
+        self.head = frame as usize % self.inputs.len();
+        self.tail = self.head;
+    }
+
+    /// Lets predictions repeat only the bits of the last input that are set in the mask and clear all others.
+    pub(crate) fn set_sticky_mask(&mut self, mask: Option<T::Input>) {
+        self.sticky_mask = mask;
+    }
+
+    /// Returns the bytes allocated for the queued inputs.
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.inputs.capacity() * std::mem::size_of::<PlayerInput<T::Input>>()
+    }
+
+    #[cfg(feature = "debug-tools")]
+    pub(crate) fn snapshot(&self) -> InputQueueSnapshot {
+        InputQueueSnapshot {
+            first_frame: self.inputs[self.tail].frame.unwrap_or(NULL_FRAME),
+            last_added_frame: self.last_added_frame.unwrap_or(NULL_FRAME),
+            length: self.length,
+            delay: self.frame_delay,
+            first_incorrect_prediction: self.first_incorrect_frame,
+        }
+    }
+
     pub(crate) fn reset_prediction(&mut self) {
-        self.prediction.frame = NULL_FRAME;
-        self.first_incorrect_frame = NULL_FRAME;
-        self.last_requested_frame = NULL_FRAME;
+        self.prediction.frame = None;
+        self.first_incorrect_frame = None;
+        self.last_requested_frame = None;
+        self.predictions_confirmed = false;
+    }
+
+    /// Returns whether inputs confirmed all predictions since the last call, and resets the flag.
+    pub(crate) fn take_predictions_confirmed(&mut self) -> bool {
+        std::mem::take(&mut self.predictions_confirmed)
     }
 
     /// Returns a `PlayerInput`, but only if the input for the requested frame is confirmed.
     /// In contrast to `input()`, this will not return a prediction if there is no confirmed input for the frame, but panic instead.
     pub(crate) fn confirmed_input(&self, requested_frame: Frame) -> PlayerInput<T::Input> {
-        let offset = requested_frame as usize % INPUT_QUEUE_LENGTH;
+        let offset = requested_frame as usize % self.inputs.len();
 
-        if self.inputs[offset].frame == requested_frame {
+        if self.inputs[offset].frame == Some(requested_frame) {
             return self.inputs[offset];
         }
 
@@ -82,20 +154,27 @@ impl<T: Config> InputQueue<T> {
     /// Discards confirmed frames up to given `frame` from the queue. All confirmed frames are guaranteed to be synchronized between players, so there is no need to save the inputs anymore.
     pub(crate) fn discard_confirmed_frames(&mut self, mut frame: Frame) {
         // we only drop frames until the last frame that was requested, otherwise we might delete data still needed
-        if self.last_requested_frame != NULL_FRAME {
-            frame = cmp::min(frame, self.last_requested_frame);
+        if let Some(last_requested_frame) = self.last_requested_frame {
+            frame = cmp::min(frame, last_requested_frame);
         }
 
+        // nothing has been added yet, so there is nothing to delete
+        let (Some(last_added_frame), Some(first_frame)) =
+            (self.last_added_frame, self.inputs[self.tail].frame)
+        else {
+            return;
+        };
+
         // move the tail to "delete inputs", wrap around if necessary
-        if frame >= self.last_added_frame {
+        if frame >= last_added_frame {
             // delete all but most recent
             self.tail = self.head;
             self.length = 1;
-        } else if frame <= self.inputs[self.tail].frame {
+        } else if frame <= first_frame {
             // we don't need to delete anything
         } else {
-            let offset = (frame - (self.inputs[self.tail].frame)) as usize;
-            self.tail = (self.tail + offset) % INPUT_QUEUE_LENGTH;
+            let offset = (frame - first_frame) as usize;
+            self.tail = (self.tail + offset) % self.inputs.len();
             self.length -= offset;
         }
     }
@@ -104,59 +183,79 @@ impl<T: Config> InputQueue<T> {
     pub(crate) fn input(&mut self, requested_frame: Frame) -> (T::Input, InputStatus) {
         // No one should ever try to grab any input when we have a prediction error.
         // Doing so means that we're just going further down the wrong path. Assert this to verify that it's true.
-        assert!(self.first_incorrect_frame == NULL_FRAME);
+        assert!(self.first_incorrect_frame.is_none());
 
         // Remember the last requested frame number for later. We'll need this in add_input() to drop out of prediction mode.
-        self.last_requested_frame = requested_frame;
+        self.last_requested_frame = Some(requested_frame);
 
         // assert that we request a frame that still exists
-        assert!(requested_frame >= self.inputs[self.tail].frame);
+        let first_frame = self.inputs[self.tail].frame;
+        assert!(first_frame.is_none_or(|first_frame| requested_frame >= first_frame));
 
         // We currently don't have a prediction frame
-        if self.prediction.frame < 0 {
+        if self.prediction.frame.is_none() {
             //  If the frame requested is in our range, fetch it out of the queue and return it.
-            let mut offset: usize = (requested_frame - self.inputs[self.tail].frame) as usize;
-
-            if offset < self.length {
-                offset = (offset + self.tail) % INPUT_QUEUE_LENGTH;
-                assert!(self.inputs[offset].frame == requested_frame);
-                return (self.inputs[offset].input, InputStatus::Confirmed);
+            if let Some(first_frame) = first_frame {
+                let mut offset: usize = (requested_frame - first_frame) as usize;
+
+                if offset < self.length {
+                    offset = (offset + self.tail) % self.inputs.len();
+                    assert!(self.inputs[offset].frame == Some(requested_frame));
+                    return (self.inputs[offset].input, InputStatus::Confirmed);
+                }
             }
 
             // The requested frame isn't in the queue. This means we need to return a prediction frame. Predict that the user will do the same thing they did last time.
-            if requested_frame == 0 || self.last_added_frame == NULL_FRAME {
-                // basing new prediction frame from nothing, since we are on frame 0 or we have no frames yet
-                self.prediction = PlayerInput::blank_input(self.prediction.frame);
-            } else {
+            self.prediction = match self.last_added_frame {
                 // basing new prediction frame from previously added frame
-                let previous_position = match self.head {
-                    0 => INPUT_QUEUE_LENGTH - 1,
-                    _ => self.head - 1,
-                };
-                self.prediction = self.inputs[previous_position];
-            }
-            // update the prediction's frame
-            self.prediction.frame += 1;
+                Some(last_added_frame) if requested_frame != self.start_frame => {
+                    let previous_position = match self.head {
+                        0 => self.inputs.len() - 1,
+                        _ => self.head - 1,
+                    };
+                    let last_input = self.inputs[previous_position].input;
+                    PlayerInput::new(last_added_frame + 1, self.predict_from(last_input))
+                }
+                // basing new prediction frame from nothing, since we are on the start frame or we have no frames yet
+                _ => PlayerInput::blank_input(Some(self.start_frame)),
+            };
         }
 
         // We must be predicting, so we return the prediction frame contents. We are adjusting the prediction to have the requested frame.
-        assert!(self.prediction.frame != NULL_FRAME);
+        assert!(self.prediction.frame.is_some());
         let prediction_to_return = self.prediction; // PlayerInput has copy semantics
         (prediction_to_return.input, InputStatus::Predicted)
     }
 
+    /// Predicts the input following the given one: the sticky bits repeat, the others are cleared.
+    fn predict_from(&self, last_input: T::Input) -> T::Input {
+        let Some(mask) = &self.sticky_mask else {
+            return last_input;
+        };
+        let bytes: Vec<u8> = bytemuck::bytes_of(&last_input)
+            .iter()
+            .zip(bytemuck::bytes_of(mask))
+            .map(|(byte, mask)| byte & mask)
+            .collect();
+        // clearing bits of e.g. an enum does not necessarily result in a valid input
+        bytemuck::checked::try_pod_read_unaligned(&bytes).unwrap_or(last_input)
+    }
+
     /// Adds an input frame to the queue. Will consider the set frame delay.
-    pub(crate) fn add_input(&mut self, input: PlayerInput<T::Input>) -> Frame {
+    /// Returns the frame the input was added at, or `None` if the input was dropped.
+    pub(crate) fn add_input(&mut self, input: PlayerInput<T::Input>) -> Option<Frame> {
+        let input_frame = input
+            .frame
+            .expect("InputQueue::add_input(): The input does not belong to a frame");
         // Verify that inputs are passed in sequentially by the user, regardless of frame delay.
-        assert!(
-            self.last_added_frame == NULL_FRAME
-                || input.frame + self.frame_delay as i32 == self.last_added_frame + 1
-        );
+        assert!(self.last_added_frame.is_none_or(|last_added_frame| {
+            input_frame + self.frame_delay as i32 == last_added_frame + 1
+        }));
 
         // Move the queue head to the correct point in preparation to input the frame into the queue.
-        let new_frame = self.advance_queue_head(input.frame);
+        let new_frame = self.advance_queue_head(input_frame);
         // if the frame is valid, then add the input
-        if new_frame != NULL_FRAME {
+        if let Some(new_frame) = new_frame {
             self.add_input_by_frame(input, new_frame);
         }
         new_frame
@@ -166,60 +265,65 @@ impl<T: Config> InputQueue<T> {
     /// Returns the frame number
     fn add_input_by_frame(&mut self, input: PlayerInput<T::Input>, frame_number: Frame) {
         let previous_position = match self.head {
-            0 => INPUT_QUEUE_LENGTH - 1,
+            0 => self.inputs.len() - 1,
             _ => self.head - 1,
         };
 
-        assert!(self.last_added_frame == NULL_FRAME || frame_number == self.last_added_frame + 1);
-        assert!(frame_number == 0 || self.inputs[previous_position].frame == frame_number - 1);
+        assert!(self
+            .last_added_frame
+            .is_none_or(|last_added_frame| frame_number == last_added_frame + 1));
+        assert!(
+            frame_number == self.start_frame
+                || self.inputs[previous_position].frame == Some(frame_number - 1)
+        );
 
         // Add the frame to the back of the queue
-        self.inputs[self.head] = input;
-        self.inputs[self.head].frame = frame_number;
-        self.head = (self.head + 1) % INPUT_QUEUE_LENGTH;
+        self.inputs[self.head] = PlayerInput::new(frame_number, input.input);
+        self.head = (self.head + 1) % self.inputs.len();
         self.length += 1;
-        assert!(self.length <= INPUT_QUEUE_LENGTH);
+        assert!(self.length <= self.inputs.len());
         self.first_frame = false;
-        self.last_added_frame = frame_number;
+        self.last_added_frame = Some(frame_number);
 
         // We have been predicting. See if the inputs we've gotten match what we've been predicting. If so, don't worry about it.
-        if self.prediction.frame != NULL_FRAME {
-            assert!(frame_number == self.prediction.frame);
+        if let Some(prediction_frame) = self.prediction.frame {
+            assert!(frame_number == prediction_frame);
 
             // Remember the first input which was incorrect so we can report it
-            if self.first_incorrect_frame == NULL_FRAME && !self.prediction.equal(&input, true) {
-                self.first_incorrect_frame = frame_number;
+            if self.first_incorrect_frame.is_none() && !self.prediction.equal(&input, true) {
+                self.first_incorrect_frame = Some(frame_number);
             }
 
             // If this input is the same frame as the last one requested and we still haven't found any mispredicted inputs, we can exit prediction mode.
             // Otherwise, advance the prediction frame count up.
-            if self.prediction.frame == self.last_requested_frame
-                && self.first_incorrect_frame == NULL_FRAME
+            if self.last_requested_frame == Some(prediction_frame)
+                && self.first_incorrect_frame.is_none()
             {
-                self.prediction.frame = NULL_FRAME;
+                self.prediction.frame = None;
+                self.predictions_confirmed = true;
             } else {
-                self.prediction.frame += 1;
+                self.prediction.frame = Some(prediction_frame + 1);
             }
         }
     }
 
     /// Advances the queue head to the next frame and either drops inputs or fills the queue if the input delay has changed since the last frame.
-    fn advance_queue_head(&mut self, mut input_frame: Frame) -> Frame {
+    /// Returns the frame the input should be added at, or `None` if it should be dropped.
+    fn advance_queue_head(&mut self, mut input_frame: Frame) -> Option<Frame> {
         let previous_position = match self.head {
-            0 => INPUT_QUEUE_LENGTH - 1,
+            0 => self.inputs.len() - 1,
             _ => self.head - 1,
         };
 
-        let mut expected_frame = if self.first_frame {
-            0
-        } else {
-            self.inputs[previous_position].frame + 1
+        let mut expected_frame = match self.inputs[previous_position].frame {
+            Some(previous_frame) if !self.first_frame => previous_frame + 1,
+            _ => self.start_frame,
         };
 
         input_frame += self.frame_delay as i32;
         //  This can occur when the frame delay has dropped since the last time we shoved a frame into the system. In this case, there's no room on the queue. Toss it.
         if expected_frame > input_frame {
-            return NULL_FRAME;
+            return None;
         }
 
         // This can occur when the frame delay has been increased since the last time we shoved a frame into the system.
@@ -231,11 +335,14 @@ impl<T: Config> InputQueue<T> {
         }
 
         let previous_position = match self.head {
-            0 => INPUT_QUEUE_LENGTH - 1,
+            0 => self.inputs.len() - 1,
             _ => self.head - 1,
         };
-        assert!(input_frame == 0 || input_frame == self.inputs[previous_position].frame + 1);
-        input_frame
+        assert!(
+            input_frame == self.start_frame
+                || self.inputs[previous_position].frame == Some(input_frame - 1)
+        );
+        Some(input_frame)
     }
 }
 
@@ -269,7 +376,7 @@ mod input_queue_tests {
     #[test]
     #[should_panic]
     fn test_add_input_wrong_frame() {
-        let mut queue = InputQueue::<TestConfig>::new();
+        let mut queue = InputQueue::<TestConfig>::new(DEFAULT_INPUT_QUEUE_LENGTH);
         let input = PlayerInput::new(0, TestInput { inp: 0 });
         queue.add_input(input); // fine
         let input_wrong_frame = PlayerInput::new(3, TestInput { inp: 0 });
@@ -279,7 +386,7 @@ mod input_queue_tests {
     #[test]
     #[should_panic]
     fn test_add_input_twice() {
-        let mut queue = InputQueue::<TestConfig>::new();
+        let mut queue = InputQueue::<TestConfig>::new(DEFAULT_INPUT_QUEUE_LENGTH);
         let input = PlayerInput::new(0, TestInput { inp: 0 });
         queue.add_input(input); // fine
         queue.add_input(input); // not fine
@@ -287,22 +394,22 @@ mod input_queue_tests {
 
     #[test]
     fn test_add_input_sequentially() {
-        let mut queue = InputQueue::<TestConfig>::new();
+        let mut queue = InputQueue::<TestConfig>::new(DEFAULT_INPUT_QUEUE_LENGTH);
         for i in 0..10 {
             let input = PlayerInput::new(i, TestInput { inp: 0 });
             queue.add_input(input);
-            assert_eq!(queue.last_added_frame, i);
+            assert_eq!(queue.last_added_frame, Some(i));
             assert_eq!(queue.length, (i + 1) as usize);
         }
     }
 
     #[test]
     fn test_input_sequentially() {
-        let mut queue = InputQueue::<TestConfig>::new();
+        let mut queue = InputQueue::<TestConfig>::new(DEFAULT_INPUT_QUEUE_LENGTH);
         for i in 0..10 {
             let input = PlayerInput::new(i, TestInput { inp: i as u8 });
             queue.add_input(input);
-            assert_eq!(queue.last_added_frame, i);
+            assert_eq!(queue.last_added_frame, Some(i));
             assert_eq!(queue.length, (i + 1) as usize);
             let (input_in_queue, _status) = queue.input(i);
             assert_eq!(input_in_queue.inp, i as u8);
@@ -311,17 +418,82 @@ mod input_queue_tests {
 
     #[test]
     fn test_delayed_inputs() {
-        let mut queue = InputQueue::<TestConfig>::new();
+        let mut queue = InputQueue::<TestConfig>::new(DEFAULT_INPUT_QUEUE_LENGTH);
         let delay: i32 = 2;
         queue.set_frame_delay(delay as usize);
         for i in 0..10 {
             let input = PlayerInput::new(i, TestInput { inp: i as u8 });
             queue.add_input(input);
-            assert_eq!(queue.last_added_frame, i + delay);
+            assert_eq!(queue.last_added_frame, Some(i + delay));
             assert_eq!(queue.length, (i + delay + 1) as usize);
             let (input_in_queue, _status) = queue.input(i);
             let correct_input = std::cmp::max(0, i - delay) as u8;
             assert_eq!(input_in_queue.inp, correct_input);
         }
     }
+
+    #[test]
+    fn test_start_frame() {
+        let mut queue = InputQueue::<TestConfig>::new(DEFAULT_INPUT_QUEUE_LENGTH);
+        queue.set_start_frame(1000);
+        queue.set_frame_delay(2);
+        assert_eq!(queue.input(1000).1, InputStatus::Predicted);
+        for i in 1000..1010 {
+            let input = TestInput {
+                inp: (i - 999) as u8,
+            };
+            queue.add_input(PlayerInput::new(i, input));
+            assert_eq!(queue.last_added_frame, Some(i + 2));
+        }
+        // the frames before the first delayed input are blank
+        assert_eq!(queue.confirmed_input(1001).input.inp, 0);
+        assert_eq!(queue.confirmed_input(1005).input.inp, 4);
+    }
+
+    #[cfg(feature = "debug-tools")]
+    #[test]
+    fn test_snapshot() {
+        let mut queue = InputQueue::<TestConfig>::new(DEFAULT_INPUT_QUEUE_LENGTH);
+        queue.set_frame_delay(1);
+        let snapshot = queue.snapshot();
+        assert_eq!(snapshot.first_frame, NULL_FRAME);
+        assert_eq!(snapshot.length, 0);
+
+        queue.add_input(PlayerInput::new(0, TestInput { inp: 0 }));
+        // frame 2 is predicted from the last input, which turns out to be wrong
+        let (_, status) = queue.input(2);
+        assert_eq!(status, InputStatus::Predicted);
+        queue.add_input(PlayerInput::new(1, TestInput { inp: 5 }));
+
+        let snapshot = queue.snapshot();
+        assert_eq!(snapshot.first_frame, 0);
+        assert_eq!(snapshot.last_added_frame, 2);
+        assert_eq!(snapshot.length, 3);
+        assert_eq!(snapshot.delay, 1);
+        assert_eq!(snapshot.first_incorrect_prediction, Some(2));
+    }
+
+    #[test]
+    fn test_sticky_mask() {
+        let mut queue = InputQueue::<TestConfig>::new(DEFAULT_INPUT_QUEUE_LENGTH);
+        queue.set_sticky_mask(Some(TestInput { inp: 0b1111_0000 }));
+        queue.add_input(PlayerInput::new(0, TestInput { inp: 0b1010_1010 }));
+
+        // the instant bits are predicted to be cleared, for all predicted frames
+        for frame in 1..4 {
+            let (input, status) = queue.input(frame);
+            assert_eq!(input.inp, 0b1010_0000);
+            assert_eq!(status, InputStatus::Predicted);
+        }
+        for frame in 1..4 {
+            let input = TestInput { inp: 0b1010_0000 };
+            queue.add_input(PlayerInput::new(frame, input));
+        }
+        assert_eq!(queue.first_incorrect_frame(), None);
+
+        // without a mask, the whole input repeats
+        queue.set_sticky_mask(None);
+        queue.add_input(PlayerInput::new(4, TestInput { inp: 0b1010_1010 }));
+        assert_eq!(queue.input(5).0.inp, 0b1010_1010);
+    }
 }
@@ -2,40 +2,109 @@
 //! GGRS (good game rollback system) is a reimagination of the GGPO network SDK written in 100% safe Rust 🦀.
 //! The callback-style API from the original library has been replaced with a much saner, simpler control flow.
 //! Instead of registering callback functions, GGRS returns a list of requests for the user to fulfill.
+//! The types needed to run a session can be imported at once from the [`prelude`].
 
 #![forbid(unsafe_code)] // let us try
 #![deny(missing_docs)]
 #![deny(rustdoc::broken_intra_doc_links)]
 //#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
-use std::{fmt::Debug, hash::Hash};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Debug, hash::Hash, marker::PhantomData, net::SocketAddr};
 
-pub use error::GgrsError;
+#[cfg(feature = "blake3")]
+pub use checksum::Blake3Hasher;
+pub use checksum::{ChecksumKind, StateHasher};
+pub use clock::{Clock, StdClock};
+#[cfg(feature = "debug-tools")]
+pub use determinism::{DeterminismGuard, FloatEnvironment, FloatEnvironmentChange, RoundingMode};
+pub use diagnostics::{DiagnosticsReport, PeerDiagnostics, SessionSettings};
+pub use error::{GgrsError, PacketError};
+#[cfg(feature = "debug-tools")]
+pub use input_queue::InputQueueSnapshot;
+pub use memory::MemoryReport;
+#[cfg(feature = "lz4")]
+pub use network::codec::Lz4Codec;
+#[cfg(feature = "zstd")]
+pub use network::codec::ZstdCodec;
+pub use network::codec::{Codec, IdentityCodec};
+#[cfg(feature = "ggpo-compat")]
+pub use network::ggpo_socket::GgpoCompatSocket;
+pub use network::handshake::{HandshakeMismatch, HandshakeVerifier, MAX_HANDSHAKE_PAYLOAD_SIZE};
 pub use network::messages::Message;
-pub use network::network_stats::NetworkStats;
+pub use network::network_stats::{NetworkSample, NetworkStats};
+#[cfg(not(target_arch = "wasm32"))]
+pub use network::probe::probe;
+pub use network::probe::{ConnectionProbe, ConnectionQualityReport};
+#[cfg(feature = "quinn")]
+pub use network::quinn_socket::QuinnSocket;
+pub use network::reliable::MAX_MESSAGE_SIZE;
+pub use network::socket_scheduler::{ScheduledSocket, SocketScheduler};
+#[cfg(feature = "stun")]
+pub use network::stun::discover_public_addr;
+#[cfg(feature = "net-trace")]
+pub use network::trace::{PacketDirection, PacketObserver, PacketTrace};
 pub use network::udp_socket::UdpNonBlockingSocket;
+pub use network::wire;
+pub use network::wire::{parse_packet, PROTOCOL_VERSION};
+pub use poll_summary::PollSummary;
+pub use replay::Replay;
 pub use sessions::builder::SessionBuilder;
-pub use sessions::p2p_session::P2PSession;
+pub use sessions::local_session::LocalSession;
+pub use sessions::p2p_session::{P2PSession, RollbackCause, RollbackStats, StallCause};
 pub use sessions::p2p_spectator_session::SpectatorSession;
-pub use sessions::sync_test_session::SyncTestSession;
+pub use sessions::session::Session;
+pub use sessions::sync_test_session::{InputScript, SyncTestSession};
 pub use sync_layer::GameStateCell;
+#[cfg(feature = "debug-tools")]
+pub use sync_layer::{SaveSlotSnapshot, SavedStatesSnapshot};
 
+pub(crate) mod checksum;
+pub(crate) mod clock;
+#[cfg(feature = "debug-tools")]
+pub(crate) mod determinism;
+pub(crate) mod diagnostics;
 pub(crate) mod error;
 pub(crate) mod frame_info;
 pub(crate) mod input_queue;
+pub(crate) mod memory;
+pub(crate) mod poll_summary;
+pub mod prelude;
+pub(crate) mod replay;
+pub(crate) mod state_diff;
 pub(crate) mod sync_layer;
+pub mod testing;
 pub(crate) mod time_sync;
 pub(crate) mod sessions {
     pub(crate) mod builder;
+    pub(crate) mod frame_pacer;
+    pub(crate) mod local_session;
     pub(crate) mod p2p_session;
     pub(crate) mod p2p_spectator_session;
+    pub(crate) mod playback;
+    pub(crate) mod session;
     pub(crate) mod sync_test_session;
 }
 pub(crate) mod network {
+    pub(crate) mod codec;
     pub(crate) mod compression;
+    #[cfg(feature = "ggpo-compat")]
+    pub(crate) mod ggpo_socket;
+    pub(crate) mod handshake;
     pub(crate) mod messages;
     pub(crate) mod network_stats;
+    pub(crate) mod probe;
     pub(crate) mod protocol;
+    #[cfg(feature = "quinn")]
+    pub(crate) mod quinn_socket;
+    pub(crate) mod reliable;
+    pub(crate) mod session_socket;
+    pub(crate) mod socket_scheduler;
+    #[cfg(feature = "stun")]
+    pub(crate) mod stun;
+    #[cfg(feature = "net-trace")]
+    pub(crate) mod trace;
     pub(crate) mod udp_socket;
+    pub mod wire;
 }
 
 // #############
@@ -44,17 +113,24 @@ pub(crate) mod network {
 
 /// Internally, -1 represents no frame / invalid frame.
 pub const NULL_FRAME: i32 = -1;
-/// A frame is a single step of execution.
+/// A frame is a single step of execution. Frames count up from 0 and never wrap around: once a session reaches [`MAX_FRAME`],
+/// advancing it fails with [`GgrsError::FrameLimitReached`].
 pub type Frame = i32;
+/// The last frame a session can reach, about 414 days into a session running at 60 fps. It leaves headroom below [`Frame::MAX`],
+/// so adding input delay or the prediction window to the current frame cannot overflow.
+pub const MAX_FRAME: Frame = Frame::MAX - (1 << 16);
 /// Each player is identified by a player handle.
 pub type PlayerHandle = usize;
+/// A persistent identity of a player supplied by you, like an account id or a UUID. Unlike the [`PlayerHandle`] and the address,
+/// it stays the same when a player rejoins in a new session, so you can map them back to the same character slot.
+pub type PlayerId = u128;
 
 // #############
 // #   ENUMS   #
 // #############
 
 /// Desync detection by comparing checksums between peers.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DesyncDetection {
     /// Desync detection is turned on with a specified interval rate given by the user.
     On {
@@ -65,17 +141,36 @@ pub enum DesyncDetection {
     Off,
 }
 
+/// How a [`P2PSession`] handles the first frames, for which no remote inputs can have arrived yet.
+/// All peers of a session need to use the same policy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum StartupPolicy {
+    /// The first frames are advanced with predicted remote inputs, which are corrected with a rollback once the inputs arrive.
+    #[default]
+    Predict,
+    /// The first frame stalls with [`GgrsError::PredictionThreshold`] until the first inputs of all remote players have arrived.
+    WaitForRemoteInputs,
+    /// The first frames are advanced with blank inputs for all players, giving the peers time to exchange their first inputs.
+    /// Local inputs added during these frames are ignored.
+    InitialDelay {
+        /// The number of frames advanced with blank inputs.
+        frames: usize,
+    },
+}
+
 /// Defines the three types of players that GGRS considers:
 /// - local players, who play on the local device,
 /// - remote players, who play on other devices and
 /// - spectators, who are remote players that do not contribute to the game input.
+///
 /// Both [`PlayerType::Remote`] and [`PlayerType::Spectator`] have a socket address associated with them.
-#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, Default)]
 pub enum PlayerType<A>
 where
     A: Clone + PartialEq + Eq + Hash,
 {
     /// This player plays on the local device.
+    #[default]
     Local,
     /// This player plays on a remote device identified by the socket address.
     Remote(A),
@@ -83,21 +178,55 @@ where
     Spectator(A),
 }
 
-impl<A: Clone + PartialEq + Eq + Hash> Default for PlayerType<A> {
-    fn default() -> Self {
-        Self::Local
-    }
-}
-
-/// A session is always in one of these states. You can query the current state of a session via [`current_state`].
+/// A session is always in one of these states. You can query the current state of a session via [`current_state`],
+/// changes are also announced with [`GgrsEvent::SessionStateChanged`].
+///
+/// The states follow each other in order: a session configured with the [`SessionBuilder`] starts out [`Synchronizing`] with
+/// its remote clients, is [`Running`] once all of them have synchronized and stays [`Closed`] after calling `close()`.
+/// A session without remote clients is [`Running`] right away.
 ///
 /// [`current_state`]: P2PSession#method.current_state
+/// [`Synchronizing`]: SessionState::Synchronizing
+/// [`Running`]: SessionState::Running
+/// [`Closed`]: SessionState::Closed
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum SessionState {
     /// When synchronizing, the session attempts to establish a connection to the remote clients.
     Synchronizing,
     /// When running, the session has synchronized and is ready to take and transmit player input.
     Running,
+    /// The session has been closed with [`P2PSession::close()`] or [`SpectatorSession::close()`]. It no longer exchanges packets
+    /// with the remote clients and cannot advance any further.
+    Closed,
+}
+
+/// The state of the connection to a single remote client or spectator. Query it via [`P2PSession::connection_status()`]
+/// or [`SpectatorSession::connection_status()`], changes are also announced with [`GgrsEvent::ConnectionStateChanged`].
+///
+/// [`P2PSession::connection_status()`]: P2PSession#method.connection_status
+/// [`SpectatorSession::connection_status()`]: SpectatorSession#method.connection_status
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConnectionState {
+    /// The session is synchronizing with the remote client.
+    Synchronizing {
+        /// The fraction of the required synchronization roundtrips that have been completed, between 0.0 and 1.0.
+        progress: f32,
+    },
+    /// The handshake made no progress within the timeout set with [`SessionBuilder::with_sync_timeout()`]. Retry with
+    /// [`P2PSession::retry_synchronization()`] or give up on the remote client with [`P2PSession::disconnect_player()`].
+    SynchronizationFailed,
+    /// The connection is synchronized and running.
+    Running,
+    /// No packets have been received from the remote client for a while. The remote client will be disconnected
+    /// if this lasts until the disconnect timeout. [`GgrsEvent::ConnectionStateChanged`] reports this state once, when the
+    /// interruption starts, while querying the state returns the current duration.
+    Interrupted {
+        /// The time that has passed since the last packet from the remote client was received, in the clock of the session.
+        elapsed: std::time::Duration,
+    },
+    /// The remote client has disconnected or has been disconnected.
+    Disconnected,
 }
 
 /// [`InputStatus`] will always be given together with player inputs when requested to advance the frame.
@@ -112,7 +241,9 @@ pub enum InputStatus {
 }
 
 /// Notifications that you can receive from the session. Handling them is up to the user.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// New events can be added without a major release, so matching on them needs a wildcard arm.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum GgrsEvent<T>
 where
     T: Config,
@@ -153,6 +284,113 @@ where
         /// Amount of frames recommended to be skipped in order to let other clients catch up.
         skip_frames: u32,
     },
+    /// Sent periodically if time sync suggestions are enabled. Games that support time dilation can run their simulation at `speed_factor` times
+    /// the regular speed to stay in sync with the other clients without visibly skipping frames.
+    TimeSyncSuggestion {
+        /// Suggested simulation speed relative to the regular speed, computed from the smoothed frame advantage.
+        /// Values below 1.0 mean this client is ahead and should slow down, values above 1.0 mean it should speed up.
+        speed_factor: f64,
+    },
+    /// Sent periodically if FPS mismatch detection is enabled and the remote client advances frames at a rate that deviates from the local fps by more than the tolerance.
+    /// This usually means that one of the clients cannot keep up with the intended simulation speed.
+    FpsMismatch {
+        /// The address of the endpoint.
+        addr: T::Address,
+        /// The fps the remote client declared during synchronization.
+        declared_fps: u32,
+        /// The rate in frames per second at which the remote client advanced during the last measurement.
+        observed_fps: f64,
+    },
+    /// The remote client uses a different [`PROTOCOL_VERSION`], so the session will never synchronize with it.
+    /// Sent once per remote client, when its first synchronization request arrives.
+    ProtocolMismatch {
+        /// The address of the endpoint.
+        addr: T::Address,
+        /// The protocol version of the remote client.
+        remote_version: u32,
+    },
+    /// The handshake verifier rejected the payload the remote client presented during synchronization, see
+    /// [`SessionBuilder::with_handshake_verifier()`]. The session disconnects the client, which is asked to disconnect as well.
+    HandshakeRejected {
+        /// The address of the endpoint.
+        addr: T::Address,
+    },
+    /// The remote client uses a different value for a setting all clients have to share. The session disconnects the client,
+    /// which is asked to disconnect as well.
+    HandshakeMismatch {
+        /// The address of the endpoint.
+        addr: T::Address,
+        /// The setting that differs.
+        mismatch: HandshakeMismatch,
+    },
+    /// The handshake with a remote client made no progress within the timeout set with [`SessionBuilder::with_sync_timeout()`],
+    /// so the session stopped synchronizing with it. Retry with [`P2PSession::retry_synchronization()`] or give up on the
+    /// remote client with [`P2PSession::disconnect_player()`], which lets the session start without it.
+    SynchronizationFailed {
+        /// The lowest handle of the players or spectators behind the remote client.
+        handle: PlayerHandle,
+        /// The number of synchronization requests sent to the remote client.
+        attempts: u32,
+    },
+    /// Sent once a remote client has synchronized for each of its players that has a [`PlayerId`], see [`SessionBuilder::with_player_id()`].
+    PlayerIdentified {
+        /// The address of the endpoint.
+        addr: T::Address,
+        /// The handle of the player in this session.
+        handle: PlayerHandle,
+        /// The persistent id of the player.
+        player_id: PlayerId,
+    },
+    /// Sent for every player of a disconnected remote client if disconnected players are removed, see
+    /// [`SessionBuilder::with_disconnected_player_removal()`]. From `frame` on, the inputs of the player are left out of
+    /// [`GgrsRequest::AdvanceFrame`] and the inputs of all players with higher handles move up by one slot.
+    PlayerRemoved {
+        /// The handle of the removed player.
+        handle: PlayerHandle,
+        /// The first frame without inputs of the player.
+        frame: Frame,
+    },
+    /// A player moved to another client, see [`P2PSession::hand_over_player()`], [`P2PSession::promote_spectator()`] and
+    /// [`P2PSession::demote_player()`]. From `frame` on, the inputs of the player come from the client that is now listed for it
+    /// in [`P2PSession::players()`]. If that is this client, add local input for the player from now on.
+    PlayerHandedOver {
+        /// The handle of the player.
+        handle: PlayerHandle,
+        /// The first frame with inputs from the new client.
+        frame: Frame,
+    },
+    /// Sent whenever the [`ConnectionState`] of a remote client changes, in addition to the more specific events above.
+    ConnectionStateChanged {
+        /// The address of the endpoint.
+        addr: T::Address,
+        /// The new state of the connection.
+        state: ConnectionState,
+    },
+    /// Sent whenever the [`SessionState`] of the session changes: once it is running after all remote clients synchronized,
+    /// and once it has been closed.
+    SessionStateChanged {
+        /// The new state of the session.
+        state: SessionState,
+    },
+    /// Sent for every reason of a rollback, right before its requests are handed out, if enabled with
+    /// [`SessionBuilder::with_rollback_events()`]. A rollback with several reasons, like two mispredicted players, is reported by
+    /// several events with the same frames.
+    Rollback {
+        /// The frame the rollback started from.
+        from_frame: Frame,
+        /// The frame the rollback loaded.
+        to_frame: Frame,
+        /// Why the session rolled back.
+        cause: RollbackCause,
+    },
+    /// Sent whenever [`P2PSession::advance_frame()`] returns [`GgrsError::PredictionThreshold`] instead of advancing, if enabled
+    /// with [`SessionBuilder::with_rollback_events()`].
+    Stalled {
+        /// The frame the session could not advance from.
+        frame: Frame,
+        /// Why the session could not advance.
+        cause: StallCause,
+    },
     /// Sent whenever GGRS locally detected a discrepancy between local and remote checksums
     DesyncDetected {
         /// Frame of the checksums
@@ -164,9 +402,23 @@ where
         /// remote address of the endpoint.
         addr: T::Address,
     },
+    /// Sent when the socket of the session reported an error, see [`NonBlockingSocket::take_errors()`]. Each kind of error
+    /// is reported only once until the socket is replaced. If the socket broke, e.g. because the operating system closed it while the device slept,
+    /// bind a new one and hand it to [`P2PSession::replace_socket()`] or [`SpectatorSession::replace_socket()`].
+    SocketError {
+        /// The kind of the error.
+        kind: std::io::ErrorKind,
+    },
 }
 
 /// Requests that you can receive from the session. Handling them is mandatory.
+///
+/// During a rollback, the session resimulates frames that have been advanced before. If enabled with
+/// [`SessionBuilder::with_rollback_requests()`], all requests of a rollback are enclosed by [`BeginRollback`] and [`EndRollback`],
+/// so you can for example suppress sounds and particles for resimulated frames.
+///
+/// [`BeginRollback`]: GgrsRequest::BeginRollback
+/// [`EndRollback`]: GgrsRequest::EndRollback
 pub enum GgrsRequest<T>
 where
     T: Config,
@@ -186,11 +438,25 @@ where
         frame: Frame,
     },
     /// You should advance the gamestate with the `inputs` provided to you.
-    /// Disconnected players are indicated by having [`NULL_FRAME`] instead of the correct current frame in their input.
+    /// The [`InputStatus`] of each input tells whether it is confirmed, predicted or a dummy input of a disconnected player.
     AdvanceFrame {
         /// Contains inputs and input status for each player.
         inputs: Vec<(T::Input, InputStatus)>,
     },
+    /// A rollback begins. All following requests until [`EndRollback`] load and resimulate frames that have been advanced before.
+    /// Only sent if enabled with [`SessionBuilder::with_rollback_requests()`].
+    ///
+    /// [`EndRollback`]: GgrsRequest::EndRollback
+    BeginRollback {
+        /// The frame the session was at before the rollback. After the rollback, the session will be back at this frame.
+        from_frame: Frame,
+        /// The frame the session rolls back to.
+        to_frame: Frame,
+    },
+    /// The rollback has ended. Following [`AdvanceFrame`] requests advance new frames again.
+    ///
+    /// [`AdvanceFrame`]: GgrsRequest::AdvanceFrame
+    EndRollback,
 }
 
 // #############
@@ -199,6 +465,36 @@ where
 
 //  special thanks to james7132 for the idea of a config trait that bundles all generics
 
+/// A game state that can be converted to and from bytes. This allows GGRS to store saved states as compact deltas,
+/// see [`SessionBuilder::with_state_diffing()`].
+pub trait StateBytes: Sized {
+    /// Returns the byte representation of the state.
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Restores a state from bytes previously returned by [`StateBytes::to_bytes()`].
+    fn from_bytes(bytes: &[u8]) -> Self;
+}
+
+/// An input whose meaningful length varies from frame to frame, like a list of commands in a buffer sized for the worst case.
+/// With [`SessionBuilder::with_variable_length_inputs()`], GGRS only transmits the meaningful bytes of every input, prefixed
+/// by their length, and restores the input with zeros in place of the remaining bytes.
+pub trait VariableLengthInput {
+    /// Returns the number of leading bytes of the input that carry information, capped to the size of the input.
+    /// All bytes after them have to be zero, as remote clients will see zeros there. Debug builds assert this.
+    fn input_len(&self) -> usize;
+}
+
+/// An input that can absorb inputs added after it for the same frame, like a list of commands. This lets a game run its
+/// simulation in slow ticks, for example 10 ticks per second for an RTS, while gathering input every render frame,
+/// see [`SessionBuilder::with_input_coalescing()`].
+pub trait CoalescingInput {
+    /// Merges an input added later for the same frame into this one, for example by appending its commands.
+    fn coalesce(&mut self, newer: &Self);
+}
+
+/// [`CoalescingInput::coalesce()`] of the input type, if input coalescing is enabled.
+pub(crate) type CoalesceFn<I> = fn(&mut I, &I);
+
 /// Compile time parameterization for sessions.
 #[cfg(feature = "sync-send")]
 pub trait Config: 'static + Send + Sync {
@@ -206,11 +502,20 @@ pub trait Config: 'static + Send + Sync {
     /// transmitted over the network.
     ///
     /// Reminder: Types implementing [Pod] may not have the same byte representation
-    /// on platforms with different endianness. GGRS assumes that all players are
-    /// running with the same endianness when encoding and decoding inputs.
+    /// on platforms with different endianness. GGRS encodes its own protocol fields
+    /// with a fixed byte order (see [`wire`]), but sends inputs as they
+    /// are laid out in memory. For players on platforms with different endianness,
+    /// build your input from byte arrays or convert its fields with `to_le()`.
     ///
     /// [Pod]: bytemuck::Pod
-    type Input: Copy + Clone + PartialEq + bytemuck::Pod + bytemuck::Zeroable + Send + Sync;
+    type Input: Copy
+        + Clone
+        + PartialEq
+        + bytemuck::NoUninit
+        + bytemuck::CheckedBitPattern
+        + bytemuck::Zeroable
+        + Send
+        + Sync;
 
     /// The save state type for the session.
     type State: Clone + Send + Sync;
@@ -234,6 +539,50 @@ where
     /// This method should return all messages received since the last time this method was called.
     /// The pairs `(A, Message)` indicate from which address each packet was received.
     fn receive_all_messages(&mut self) -> Vec<(A, Message)>;
+
+    /// Returns at most `max_messages` of the messages received since the last time a message was returned, and stops early
+    /// once the received datagrams add up to `max_bytes`. The remaining messages wait in the socket for the next call, so
+    /// sessions don't parse more packets per poll than their receive budget allows. The default returns all messages like
+    /// [`receive_all_messages()`], in which case the session keeps the excess itself.
+    ///
+    /// [`receive_all_messages()`]: NonBlockingSocket::receive_all_messages
+    fn receive_messages(&mut self, max_messages: usize, max_bytes: usize) -> Vec<(A, Message)> {
+        let _ = (max_messages, max_bytes);
+        self.receive_all_messages()
+    }
+
+    /// Like [`receive_messages()`], but asks `accept` about the source address of every received datagram first and drops the
+    /// datagram if it returns `false`. Sessions use it to drop packets of unknown or flooding senders without parsing them.
+    /// Dropped datagrams count towards `max_messages` and `max_bytes` all the same, so a flood cannot hold up the session. The
+    /// default only filters the messages [`receive_messages()`] returns, sockets that parse datagrams themselves should override
+    /// it to filter them before parsing.
+    ///
+    /// [`receive_messages()`]: NonBlockingSocket::receive_messages
+    fn receive_messages_filtered(
+        &mut self,
+        max_messages: usize,
+        max_bytes: usize,
+        accept: &mut dyn FnMut(&A) -> bool,
+    ) -> Vec<(A, Message)> {
+        let mut received = self.receive_messages(max_messages, max_bytes);
+        received.retain(|(addr, _)| accept(addr));
+        received
+    }
+
+    /// Returns the errors the operating system reported for the socket since the last time this method was called, leaving out
+    /// [`WouldBlock`], which only means that no message is waiting. Sessions report them as [`GgrsEvent::SocketError`], so the
+    /// game can replace a broken socket. Sockets that cannot fail do not need to implement this.
+    ///
+    /// [`WouldBlock`]: std::io::ErrorKind::WouldBlock
+    fn take_errors(&mut self) -> Vec<std::io::ErrorKind> {
+        Vec::new()
+    }
+
+    /// Returns the local address the socket is actually bound to, e.g. to learn the port the operating system picked when
+    /// binding to port 0. Sockets that do not know their address return `None`, which is the default.
+    fn local_addr(&self) -> Option<A> {
+        None
+    }
 }
 
 /// Compile time parameterization for sessions.
@@ -243,8 +592,10 @@ pub trait Config: 'static {
     /// transmitted over the network.
     ///
     /// Reminder: Types implementing [Pod] may not have the same byte representation
-    /// on platforms with different endianness. GGRS assumes that all players are
-    /// running with the same endianness when encoding and decoding inputs.
+    /// on platforms with different endianness. GGRS encodes its own protocol fields
+    /// with a fixed byte order (see [`wire`]), but sends inputs as they
+    /// are laid out in memory. For players on platforms with different endianness,
+    /// build your input from byte arrays or convert its fields with `to_le()`.
     ///
     /// [Pod]: bytemuck::Pod
     type Input: Copy
@@ -276,4 +627,103 @@ where
     /// This method should return all messages received since the last time this method was called.
     /// The pairs `(A, Message)` indicate from which address each packet was received.
     fn receive_all_messages(&mut self) -> Vec<(A, Message)>;
+
+    /// Returns at most `max_messages` of the messages received since the last time a message was returned, and stops early
+    /// once the received datagrams add up to `max_bytes`. The remaining messages wait in the socket for the next call, so
+    /// sessions don't parse more packets per poll than their receive budget allows. The default returns all messages like
+    /// [`receive_all_messages()`], in which case the session keeps the excess itself.
+    ///
+    /// [`receive_all_messages()`]: NonBlockingSocket::receive_all_messages
+    fn receive_messages(&mut self, max_messages: usize, max_bytes: usize) -> Vec<(A, Message)> {
+        let _ = (max_messages, max_bytes);
+        self.receive_all_messages()
+    }
+
+    /// Like [`receive_messages()`], but asks `accept` about the source address of every received datagram first and drops the
+    /// datagram if it returns `false`. Sessions use it to drop packets of unknown or flooding senders without parsing them.
+    /// Dropped datagrams count towards `max_messages` and `max_bytes` all the same, so a flood cannot hold up the session. The
+    /// default only filters the messages [`receive_messages()`] returns, sockets that parse datagrams themselves should override
+    /// it to filter them before parsing.
+    ///
+    /// [`receive_messages()`]: NonBlockingSocket::receive_messages
+    fn receive_messages_filtered(
+        &mut self,
+        max_messages: usize,
+        max_bytes: usize,
+        accept: &mut dyn FnMut(&A) -> bool,
+    ) -> Vec<(A, Message)> {
+        let mut received = self.receive_messages(max_messages, max_bytes);
+        received.retain(|(addr, _)| accept(addr));
+        received
+    }
+
+    /// Returns the errors the operating system reported for the socket since the last time this method was called, leaving out
+    /// [`WouldBlock`], which only means that no message is waiting. Sessions report them as [`GgrsEvent::SocketError`], so the
+    /// game can replace a broken socket. Sockets that cannot fail do not need to implement this.
+    ///
+    /// [`WouldBlock`]: std::io::ErrorKind::WouldBlock
+    fn take_errors(&mut self) -> Vec<std::io::ErrorKind> {
+        Vec::new()
+    }
+
+    /// Returns the local address the socket is actually bound to, e.g. to learn the port the operating system picked when
+    /// binding to port 0. Sockets that do not know their address return `None`, which is the default.
+    fn local_addr(&self) -> Option<A> {
+        None
+    }
+}
+
+/// A [`Config`] for games whose input is a fixed number of bytes, like two bytes of button flags. The input is an
+/// `[u8; INPUT_SIZE]`, which is stored inline and copied without any allocation or length checks. `S` is the save state type
+/// and `A` the address type. Games with a structured input keep implementing [`Config`] themselves.
+///
+/// ```
+/// # use ggrs::{FixedInputConfig, SessionBuilder};
+/// type GameConfig = FixedInputConfig<2, Vec<u8>>;
+///
+/// let builder = SessionBuilder::<GameConfig>::new();
+/// ```
+pub struct FixedInputConfig<const INPUT_SIZE: usize, S, A = SocketAddr> {
+    _marker: PhantomData<fn() -> (S, A)>,
+}
+
+#[cfg(feature = "sync-send")]
+impl<const INPUT_SIZE: usize, S, A> Config for FixedInputConfig<INPUT_SIZE, S, A>
+where
+    S: Clone + Send + Sync + 'static,
+    A: Clone + PartialEq + Eq + Hash + Send + Sync + Debug + 'static,
+{
+    type Input = [u8; INPUT_SIZE];
+    type State = S;
+    type Address = A;
+}
+
+#[cfg(not(feature = "sync-send"))]
+impl<const INPUT_SIZE: usize, S, A> Config for FixedInputConfig<INPUT_SIZE, S, A>
+where
+    S: Clone + 'static,
+    A: Clone + PartialEq + Eq + Hash + Debug + 'static,
+{
+    type Input = [u8; INPUT_SIZE];
+    type State = S;
+    type Address = A;
+}
+
+// With the `sync-send` feature, all sessions have to be `Send + Sync`, so they can be moved to and shared between
+// other threads. This fails to compile as soon as any internal type (like an `Rc` or a `RefCell`) breaks that promise.
+#[cfg(feature = "sync-send")]
+#[allow(dead_code)]
+fn assert_sessions_send_sync<T: Config>() {
+    fn assert_send_sync<S: Send + Sync>() {}
+    assert_send_sync::<P2PSession<T>>();
+    assert_send_sync::<SpectatorSession<T>>();
+    assert_send_sync::<SyncTestSession<T>>();
+    assert_send_sync::<LocalSession<T>>();
+    assert_send_sync::<SessionBuilder<T>>();
+    assert_send_sync::<UdpNonBlockingSocket>();
+    #[cfg(feature = "quinn")]
+    assert_send_sync::<QuinnSocket>();
+    #[cfg(feature = "ggpo-compat")]
+    assert_send_sync::<GgpoCompatSocket>();
+    assert_send_sync::<ScheduledSocket<std::net::SocketAddr, UdpNonBlockingSocket>>();
 }
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+/// A [`MemoryReport`] breaks down the bytes a session currently holds, see for example [`P2PSession::memory_usage()`].
+///
+/// The sizes are shallow: a saved state counts with the size of its type plus the bytes GGRS allocated for it, but heap
+/// memory owned by the state itself (like the buffer of a `Vec` inside your state) is not counted. Buffers count with their
+/// capacity, not their length, so the report reflects what is actually allocated.
+///
+/// [`P2PSession::memory_usage()`]: crate::P2PSession::memory_usage
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryReport {
+    /// The bytes held by saved states, including the deltas and the newest state of [`SessionBuilder::with_state_diffing()`].
+    ///
+    /// [`SessionBuilder::with_state_diffing()`]: crate::SessionBuilder::with_state_diffing
+    pub saved_states: usize,
+    /// The bytes held by the input queues of all players, including attached input metadata.
+    pub input_queues: usize,
+    /// The bytes held by inputs and messages that wait to be sent or acknowledged, or that have been received but not processed yet.
+    pub pending_packets: usize,
+    /// The bytes held by the recorded [`Replay`], if any.
+    ///
+    /// [`Replay`]: crate::Replay
+    pub replay: usize,
+}
+
+impl MemoryReport {
+    /// Returns the sum of all categories.
+    pub fn total(&self) -> usize {
+        self.saved_states + self.input_queues + self.pending_packets + self.replay
+    }
+}
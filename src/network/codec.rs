@@ -0,0 +1,190 @@
+use std::fmt::Debug;
+
+/// A [`Codec`] compresses byte buffers, for example the input payloads GGRS sends over the network (see [`SessionBuilder::with_input_codec()`]).
+/// Since it works on plain bytes, you can also use it to compress your own serialized states before saving or sending them.
+/// All peers and spectators of a session need to use the same codec.
+///
+/// [`SessionBuilder::with_input_codec()`]: crate::SessionBuilder::with_input_codec
+#[cfg(feature = "sync-send")]
+pub trait Codec: Debug + Send + Sync {
+    /// Compresses the given bytes.
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Decompresses bytes previously compressed with [`Codec::compress()`]. Since the bytes might come from an untrusted source,
+    /// this returns `None` if they are malformed or if they would decompress to more than `max_len` bytes.
+    fn decompress(&self, data: &[u8], max_len: usize) -> Option<Vec<u8>>;
+
+    /// Identifies the format of the compressed bytes. Clients exchange it during synchronization and refuse to play with clients
+    /// using another codec. By default, this hashes the [`Debug`] representation of the codec; override it if the representation
+    /// holds settings that do not change the format, like a compression level.
+    fn format_id(&self) -> u32 {
+        debug_hash(self)
+    }
+}
+
+/// A [`Codec`] compresses byte buffers, for example the input payloads GGRS sends over the network (see [`SessionBuilder::with_input_codec()`]).
+/// Since it works on plain bytes, you can also use it to compress your own serialized states before saving or sending them.
+/// All peers and spectators of a session need to use the same codec.
+///
+/// [`SessionBuilder::with_input_codec()`]: crate::SessionBuilder::with_input_codec
+#[cfg(not(feature = "sync-send"))]
+pub trait Codec: Debug {
+    /// Compresses the given bytes.
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Decompresses bytes previously compressed with [`Codec::compress()`]. Since the bytes might come from an untrusted source,
+    /// this returns `None` if they are malformed or if they would decompress to more than `max_len` bytes.
+    fn decompress(&self, data: &[u8], max_len: usize) -> Option<Vec<u8>>;
+
+    /// Identifies the format of the compressed bytes. Clients exchange it during synchronization and refuse to play with clients
+    /// using another codec. By default, this hashes the [`Debug`] representation of the codec; override it if the representation
+    /// holds settings that do not change the format, like a compression level.
+    fn format_id(&self) -> u32 {
+        debug_hash(self)
+    }
+}
+
+/// Hashes the [`Debug`] representation with FNV-1a, which is stable across builds and platforms.
+fn debug_hash(value: &(impl Debug + ?Sized)) -> u32 {
+    format!("{value:?}")
+        .bytes()
+        .fold(0x811C_9DC5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+}
+
+/// A [`Codec`] that leaves the bytes as they are. This is the default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IdentityCodec;
+
+impl Codec for IdentityCodec {
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    fn decompress(&self, data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        (data.len() <= max_len).then(|| data.to_vec())
+    }
+
+    fn format_id(&self) -> u32 {
+        0
+    }
+}
+
+/// A [`Codec`] using the fast LZ4 block format. Requires the `lz4` feature.
+#[cfg(feature = "lz4")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Lz4Codec;
+
+#[cfg(feature = "lz4")]
+impl Codec for Lz4Codec {
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        lz4_flex::block::compress(data)
+    }
+
+    fn decompress(&self, data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        lz4_flex::block::decompress(data, max_len).ok()
+    }
+
+    fn format_id(&self) -> u32 {
+        1
+    }
+}
+
+/// A [`Codec`] using zstd, which compresses better than LZ4 at the cost of speed. Requires the `zstd` feature.
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZstdCodec {
+    level: i32,
+}
+
+#[cfg(feature = "zstd")]
+impl ZstdCodec {
+    /// Creates a new [`ZstdCodec`] with the given compression level, from 1 (fastest) to 22 (smallest).
+    pub fn new(level: i32) -> Self {
+        Self { level }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Default for ZstdCodec {
+    fn default() -> Self {
+        Self::new(zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Codec for ZstdCodec {
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        zstd::bulk::compress(data, self.level).expect("Compressing into a vec cannot fail")
+    }
+
+    fn decompress(&self, data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        zstd::bulk::decompress(data, max_len).ok()
+    }
+
+    // the compression level does not change the format
+    fn format_id(&self) -> u32 {
+        2
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod codec_tests {
+    use super::*;
+
+    fn roundtrip(codec: &dyn Codec) {
+        let data: Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+        let compressed = codec.compress(&data);
+        assert_eq!(
+            codec.decompress(&compressed, data.len()),
+            Some(data.clone())
+        );
+        // refuse to decompress more than allowed
+        assert_eq!(codec.decompress(&compressed, data.len() - 1), None);
+    }
+
+    #[test]
+    fn test_identity_roundtrip() {
+        roundtrip(&IdentityCodec);
+    }
+
+    #[test]
+    fn test_format_id() {
+        struct Custom;
+        impl Debug for Custom {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("a")
+            }
+        }
+        impl Codec for Custom {
+            fn compress(&self, data: &[u8]) -> Vec<u8> {
+                data.to_vec()
+            }
+            fn decompress(&self, data: &[u8], _max_len: usize) -> Option<Vec<u8>> {
+                Some(data.to_vec())
+            }
+        }
+        // the FNV-1a test vector of "a"
+        assert_eq!(Custom.format_id(), 0xE40C_292C);
+        assert_eq!(IdentityCodec.format_id(), 0);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4_roundtrip() {
+        roundtrip(&Lz4Codec);
+        assert_eq!(Lz4Codec.decompress(&[0xFF; 16], 1000), None);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_roundtrip() {
+        roundtrip(&ZstdCodec::default());
+        assert_eq!(ZstdCodec::default().decompress(&[0xFF; 16], 1000), None);
+    }
+}
@@ -19,12 +19,7 @@ pub(crate) fn delta_encode<'a>(
     let mut bytes = Vec::with_capacity(capacity);
 
     for input in pending_input {
-        let input_bytes = input;
-        assert_eq!(input_bytes.len(), ref_bytes.len());
-
-        for (b1, b2) in ref_bytes.iter().zip(input_bytes.iter()) {
-            bytes.push(b1 ^ b2);
-        }
+        xor_into(&mut bytes, ref_bytes, input);
     }
     bytes
 }
@@ -32,28 +27,156 @@ pub(crate) fn delta_encode<'a>(
 pub(crate) fn decode(
     reference: &[u8],
     data: &[u8],
+    max_inputs: usize,
 ) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
     // decode the RLE encoding first
-    let buf = bitfield_rle::decode(data)?;
+    let buf = rle_decode(reference, data, max_inputs)?;
 
     // decode the delta-encoding
     Ok(delta_decode(reference, &buf))
 }
 
+/// Encodes inputs like [`encode()`], but packs the delta-encoded bits of every frame into bitplanes before the RLE encoding:
+/// the first bit of all players, then the second bit of all players, and so on. Players of a frame mostly change the same few
+/// bits, so this puts the changed bits into few bytes and leaves longer runs of zero bytes for the RLE encoding.
+pub(crate) fn encode_bitplanes<'a>(
+    reference: &[u8],
+    pending_input: impl Iterator<Item = &'a Vec<u8>>,
+    players: usize,
+) -> Vec<u8> {
+    let buf = delta_encode(reference, pending_input);
+    let packed: Vec<u8> = buf
+        .chunks_exact(reference.len())
+        .flat_map(|frame| pack_bitplanes(frame, players))
+        .collect();
+    bitfield_rle::encode(packed)
+}
+
+/// Decodes inputs previously encoded with [`encode_bitplanes()`].
+pub(crate) fn decode_bitplanes(
+    reference: &[u8],
+    data: &[u8],
+    max_inputs: usize,
+    players: usize,
+) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    if !reference.len().is_multiple_of(players) {
+        return Err("reference input does not hold an input for every player".into());
+    }
+    let buf = rle_decode(reference, data, max_inputs)?;
+    let unpacked: Vec<u8> = buf
+        .chunks_exact(reference.len())
+        .flat_map(|frame| unpack_bitplanes(frame, players))
+        .collect();
+    Ok(delta_decode(reference, &unpacked))
+}
+
+/// Decodes the RLE encoding of at most `max_inputs` inputs of the size of the reference input.
+fn rle_decode(
+    reference: &[u8],
+    data: &[u8],
+    max_inputs: usize,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    // a few bytes of RLE encoded data can decode to huge buffers, so check the size before allocating
+    if bitfield_rle::decode_len(data)? > reference.len() * max_inputs {
+        return Err("encoded data contains too many inputs".into());
+    }
+
+    let buf = bitfield_rle::decode(data)?;
+    if reference.is_empty() || !buf.len().is_multiple_of(reference.len()) {
+        return Err("decoded input length does not match the reference input".into());
+    }
+    Ok(buf)
+}
+
+/// Transposes the inputs of a frame, `players` equally long inputs one after another, into bitplanes. Bit `b` of the input of
+/// player `p` moves to bit `b * players + p` of the result, counting bits from the lowest bit of the first byte.
+fn pack_bitplanes(frame: &[u8], players: usize) -> Vec<u8> {
+    let input_bits = frame.len() / players * 8;
+    let mut packed = vec![0; frame.len()];
+    for player in 0..players {
+        for bit in 0..input_bits {
+            let from = player * input_bits + bit;
+            if frame[from / 8] & (1 << (from % 8)) != 0 {
+                let to = bit * players + player;
+                packed[to / 8] |= 1 << (to % 8);
+            }
+        }
+    }
+    packed
+}
+
+/// Reverses [`pack_bitplanes()`].
+fn unpack_bitplanes(packed: &[u8], players: usize) -> Vec<u8> {
+    let input_bits = packed.len() / players * 8;
+    let mut frame = vec![0; packed.len()];
+    for player in 0..players {
+        for bit in 0..input_bits {
+            let from = bit * players + player;
+            if packed[from / 8] & (1 << (from % 8)) != 0 {
+                let to = player * input_bits + bit;
+                frame[to / 8] |= 1 << (to % 8);
+            }
+        }
+    }
+    frame
+}
+
 pub(crate) fn delta_decode(ref_bytes: &[u8], data: &[u8]) -> Vec<Vec<u8>> {
-    assert!(data.len() % ref_bytes.len() == 0);
-    let out_size = data.len() / ref_bytes.len();
-    let mut output = Vec::with_capacity(out_size);
-
-    for inp in 0..out_size {
-        let mut buffer = vec![0u8; ref_bytes.len()];
-        for i in 0..ref_bytes.len() {
-            buffer[i] = ref_bytes[i] ^ data[ref_bytes.len() * inp + i];
+    assert!(data.len().is_multiple_of(ref_bytes.len()));
+    data.chunks_exact(ref_bytes.len())
+        .map(|input| {
+            let mut buffer = Vec::with_capacity(ref_bytes.len());
+            xor_into(&mut buffer, ref_bytes, input);
+            buffer
+        })
+        .collect()
+}
+
+/// Encodes inputs of varying length: every input is prefixed by the number of its bytes as `u16`, then the buffer is
+/// RLE encoded like in [`encode()`]. Unlike [`encode()`], there is no reference input to delta-encode against.
+pub(crate) fn encode_prefixed<'a>(inputs: impl Iterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for input in inputs {
+        buf.extend_from_slice(&(input.len() as u16).to_le_bytes());
+        buf.extend_from_slice(input);
+    }
+    bitfield_rle::encode(buf)
+}
+
+/// Decodes inputs previously encoded with [`encode_prefixed()`], padding every input with zeros to `input_size` bytes.
+pub(crate) fn decode_prefixed(
+    data: &[u8],
+    input_size: usize,
+    max_inputs: usize,
+) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    if bitfield_rle::decode_len(data)? > (2 + input_size) * max_inputs {
+        return Err("encoded data contains too many inputs".into());
+    }
+
+    let buf = bitfield_rle::decode(data)?;
+    let mut rest = buf.as_slice();
+    let mut inputs = Vec::new();
+    while let Some((len, tail)) = rest.split_first_chunk::<2>() {
+        let len = u16::from_le_bytes(*len) as usize;
+        if len > input_size || len > tail.len() {
+            return Err("input length prefix exceeds the input size".into());
         }
-        output.push(buffer);
+        let (bytes, tail) = tail.split_at(len);
+        let mut input = vec![0; input_size];
+        input[..len].copy_from_slice(bytes);
+        inputs.push(input);
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        return Err("encoded data ends with a partial length prefix".into());
     }
+    Ok(inputs)
+}
 
-    output
+/// Appends the bytewise XOR of two equally long inputs to `out`.
+fn xor_into(out: &mut Vec<u8>, a: &[u8], b: &[u8]) {
+    assert_eq!(a.len(), b.len());
+    out.extend(a.iter().zip(b).map(|(x, y)| x ^ y));
 }
 
 // #########
@@ -84,8 +207,83 @@ mod compression_tests {
         let pend_inp = vec![inp0, inp1, inp2, inp3, inp4];
 
         let encoded = encode(&ref_input, pend_inp.iter());
-        let decoded = decode(&ref_input, &encoded).unwrap();
+        let decoded = decode(&ref_input, &encoded, pend_inp.len()).unwrap();
 
         assert!(pend_inp == decoded);
     }
+
+    #[test]
+    fn test_decode_mismatched_length() {
+        let ref_input = vec![0, 0, 0, 1];
+        let pend_inp: Vec<Vec<u8>> = vec![vec![0, 0, 1, 0]];
+        let encoded = encode(&ref_input, pend_inp.iter());
+
+        // decoding with a reference of a different size must fail gracefully
+        assert!(decode(&[0, 0, 0], &encoded, 1).is_err());
+        assert!(decode(&[], &encoded, 1).is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_prefixed() {
+        let inputs: Vec<&[u8]> = vec![&[1, 2, 3], &[], &[4, 5, 6, 7]];
+        let encoded = encode_prefixed(inputs.iter().copied());
+        let decoded = decode_prefixed(&encoded, 4, 3).unwrap();
+        assert_eq!(
+            decoded,
+            vec![vec![1, 2, 3, 0], vec![0; 4], vec![4, 5, 6, 7]]
+        );
+
+        // inputs longer than the input size and too many inputs are rejected
+        assert!(decode_prefixed(&encoded, 3, 3).is_err());
+        assert!(decode_prefixed(&encoded, 4, 1).is_err());
+        assert!(decode_prefixed(&bitfield_rle::encode(vec![1]), 4, 3).is_err());
+    }
+
+    #[test]
+    fn test_decode_too_many_inputs() {
+        let ref_input = vec![0, 0, 0, 1];
+        let pend_inp: Vec<Vec<u8>> = vec![vec![0, 0, 1, 0]; 5];
+        let encoded = encode(&ref_input, pend_inp.iter());
+
+        assert!(decode(&ref_input, &encoded, 4).is_err());
+        assert!(decode(&ref_input, &encoded, 5).is_ok());
+    }
+
+    #[test]
+    fn test_bitplanes_roundtrip() {
+        // 3 players with 2 byte inputs
+        let frame = vec![0b0000_0001, 0b1000_0000, 0xFF, 0x00, 0x5A, 0xC3];
+        let packed = pack_bitplanes(&frame, 3);
+        // the lowest bit of all players comes first
+        assert_eq!(packed[0] & 0b111, 0b011);
+        assert_eq!(unpack_bitplanes(&packed, 3), frame);
+
+        let ref_input: Vec<u8> = (0..8).collect();
+        let pend_inp: Vec<Vec<u8>> = (0..20u8)
+            .map(|i| (0..8).map(|p| i.wrapping_mul(37) ^ p).collect())
+            .collect();
+        let encoded = encode_bitplanes(&ref_input, pend_inp.iter(), 8);
+        assert_eq!(
+            decode_bitplanes(&ref_input, &encoded, 20, 8).unwrap(),
+            pend_inp
+        );
+        assert!(decode_bitplanes(&ref_input, &encoded, 19, 8).is_err());
+        assert!(decode_bitplanes(&ref_input, &encoded, 20, 3).is_err());
+    }
+
+    #[test]
+    fn test_bitplanes_smaller() {
+        // 8 players with 1 byte inputs, every player taps the same button now and then
+        let ref_input = vec![0; 8];
+        let pend_inp: Vec<Vec<u8>> = (0..8)
+            .map(|frame| (0..8).map(|p| ((frame + p) % 3 == 0) as u8).collect())
+            .collect();
+        let plain = encode(&ref_input, pend_inp.iter());
+        let packed = encode_bitplanes(&ref_input, pend_inp.iter(), 8);
+        assert!(packed.len() < plain.len());
+        assert_eq!(
+            decode_bitplanes(&ref_input, &packed, 8, 8).unwrap(),
+            pend_inp
+        );
+    }
 }
@@ -0,0 +1,728 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+};
+
+use crate::{
+    network::compression::{decode, encode},
+    network::messages::{
+        ConnectionStatus, Input, InputAck, Message, MessageBody, MessageHeader, QualityReply,
+        QualityReport, SyncReply, SyncRequest, MAX_PACKET_SIZE,
+    },
+    network::wire::Reader,
+    Clock, Frame, NonBlockingSocket, StdClock, MAX_FRAME, NULL_FRAME, PROTOCOL_VERSION,
+};
+
+const RECV_BUFFER_SIZE: usize = MAX_PACKET_SIZE;
+
+// the message types of GGPO
+const GGPO_SYNC_REQUEST: u8 = 1;
+const GGPO_SYNC_REPLY: u8 = 2;
+const GGPO_INPUT: u8 = 3;
+const GGPO_QUALITY_REPORT: u8 = 4;
+const GGPO_QUALITY_REPLY: u8 = 5;
+const GGPO_KEEP_ALIVE: u8 = 6;
+const GGPO_INPUT_ACK: u8 = 7;
+
+/// GGPO always sends the connection status of this many players.
+const GGPO_MAX_PLAYERS: usize = 4;
+/// The maximum number of bits of the encoded inputs in a single GGPO packet.
+const GGPO_MAX_COMPRESSED_BITS: usize = 4096;
+/// GGPO encodes the index of a changed input bit with this many bits, which limits the size of an input.
+const GGPO_NIBBLE_SIZE: usize = 8;
+/// The maximum number of inputs the session sends in one packet.
+const MAX_INPUTS_PER_PACKET: usize = 128;
+/// The number of sent pings kept per remote client, to restore the full time from the truncated pong GGPO echoes.
+const PING_HISTORY_SIZE: usize = 16;
+
+/// The state needed to translate the messages exchanged with a single remote client.
+#[derive(Debug, Default)]
+struct GgpoPeer {
+    /// GGPO drops packets whose sequence number skips too far ahead, so it counts up for every packet.
+    send_seq: u16,
+    /// The plain inputs the session sent, by frame. GGPO encodes every input as the bits changed to the previous one.
+    sent_inputs: BTreeMap<Frame, Vec<u8>>,
+    /// The plain inputs received from the remote client, by frame.
+    recv_inputs: BTreeMap<Frame, Vec<u8>>,
+    /// The first frame the remote client sent an input for. Until we acknowledge an input, GGPO encodes against a blank one.
+    first_recv_frame: Option<Frame>,
+    /// Pings the session sent with a quality report, as GGPO only echoes the lower 32 bits.
+    pings: VecDeque<u128>,
+}
+
+/// A non-blocking UDP socket that speaks the packet layout of the original GGPO instead of the GGRS wire format, so a GGRS
+/// session can play against an unmodified GGPO client. This allows migrating the clients of a game gradually. Listens to
+/// 0.0.0.0 on a given port. Requires the `ggpo-compat` feature.
+///
+/// Since GGPO only knows a subset of the GGRS messages, some features do not work with this socket:
+/// - Every client has a single local player, inputs are at most 32 bytes and the session has at most 4 players.
+/// - Messages GGPO does not know are not sent, so desync detection, out-of-band messages and a synchronized start are not
+///   available. Session tokens, input codecs, variable length inputs and input metadata are not supported either.
+/// - Values GGPO does not exchange are filled in on receipt: the remote client declares no FPS, no player ids and no
+///   handshake payload, and the time it sent an input at is the time of receipt.
+#[derive(Debug)]
+pub struct GgpoCompatSocket {
+    socket: UdpSocket,
+    buffer: [u8; RECV_BUFFER_SIZE],
+    send_buffer: Vec<u8>,
+    num_players: usize,
+    input_size: usize,
+    peers: HashMap<SocketAddr, GgpoPeer>,
+    errors: Vec<ErrorKind>,
+}
+
+impl GgpoCompatSocket {
+    /// Binds an UDP Socket to 0.0.0.0:port and set it to non-blocking mode. `num_players` and `input_size` have to match the
+    /// number of players of the session and the size of its [`Config::Input`], since the socket translates the inputs.
+    ///
+    /// # Errors
+    /// - Returns an error of kind [`InvalidInput`] if there are more than 4 players or the input is larger than 32 bytes.
+    /// - Returns an error if the socket cannot be bound.
+    ///
+    /// [`Config::Input`]: crate::Config::Input
+    /// [`InvalidInput`]: ErrorKind::InvalidInput
+    pub fn bind_to_port(
+        port: u16,
+        num_players: usize,
+        input_size: usize,
+    ) -> Result<Self, std::io::Error> {
+        if num_players > GGPO_MAX_PLAYERS {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "GGPO supports at most 4 players",
+            ));
+        }
+        if input_size == 0 || input_size * 8 > 1 << GGPO_NIBBLE_SIZE {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "GGPO supports inputs of 1 to 32 bytes",
+            ));
+        }
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            buffer: [0; RECV_BUFFER_SIZE],
+            send_buffer: Vec::with_capacity(RECV_BUFFER_SIZE),
+            num_players,
+            input_size,
+            peers: HashMap::new(),
+            errors: Vec::new(),
+        })
+    }
+
+    fn record_error(&mut self, kind: ErrorKind) {
+        if !self.errors.contains(&kind) {
+            self.errors.push(kind);
+        }
+    }
+}
+
+impl NonBlockingSocket<SocketAddr> for GgpoCompatSocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        self.send_buffer.clear();
+        let peer = self.peers.entry(*addr).or_default();
+        if peer.encode(msg, self.input_size, &mut self.send_buffer) {
+            // UDP is unreliable anyways, so if sending fails the message is lost just like any other dropped packet,
+            // but the error is reported, since the socket might be broken
+            if let Err(err) = self.socket.send_to(&self.send_buffer, addr) {
+                if err.kind() != ErrorKind::WouldBlock {
+                    self.record_error(err.kind());
+                }
+            }
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        let mut received_messages = Vec::new();
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                Ok((number_of_bytes, src_addr)) => {
+                    assert!(number_of_bytes <= RECV_BUFFER_SIZE);
+                    // only the addresses the session sends to keep state, so strangers cannot make the socket allocate.
+                    // Their packets are translated without state, which suffices for the first sync request of a remote
+                    // client the session has not sent to yet.
+                    let mut stranger = GgpoPeer::default();
+                    let peer = self.peers.get_mut(&src_addr).unwrap_or(&mut stranger);
+                    if let Some(msg) = peer.decode(
+                        &self.buffer[0..number_of_bytes],
+                        self.num_players,
+                        self.input_size,
+                    ) {
+                        received_messages.push((src_addr, msg));
+                    }
+                }
+                // there are no more messages
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return received_messages,
+                // datagram socket sometimes get this error as a result of calling the send_to method
+                Err(ref err) if err.kind() == ErrorKind::ConnectionReset => continue,
+                // all other errors are reported, the socket might be broken and need to be replaced
+                Err(err) => {
+                    self.record_error(err.kind());
+                    return received_messages;
+                }
+            }
+        }
+    }
+
+    fn take_errors(&mut self) -> Vec<ErrorKind> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+}
+
+impl GgpoPeer {
+    /// Appends the GGPO packet for the message to `buffer`. Returns `false` if the message has no GGPO equivalent.
+    fn encode(&mut self, msg: &Message, input_size: usize, buffer: &mut Vec<u8>) -> bool {
+        let msg_type = match &msg.body {
+            MessageBody::SyncRequest(_) => GGPO_SYNC_REQUEST,
+            MessageBody::SyncReply(_) => GGPO_SYNC_REPLY,
+            MessageBody::Input(_) => GGPO_INPUT,
+            MessageBody::InputAck(_) => GGPO_INPUT_ACK,
+            MessageBody::QualityReport(_) => GGPO_QUALITY_REPORT,
+            MessageBody::QualityReply(_) => GGPO_QUALITY_REPLY,
+            MessageBody::KeepAlive => GGPO_KEEP_ALIVE,
+            MessageBody::ChecksumReport(_)
+            | MessageBody::Reliable(_)
+            | MessageBody::ReliableAck(_)
+            | MessageBody::MtuProbe(_)
+            | MessageBody::MtuProbeAck(_) => return false,
+        };
+        buffer.extend_from_slice(&msg.header.magic.to_le_bytes());
+        buffer.extend_from_slice(&self.send_seq.to_le_bytes());
+        buffer.push(msg_type);
+
+        match &msg.body {
+            MessageBody::SyncRequest(body) => {
+                buffer.extend_from_slice(&body.random_request.to_le_bytes());
+                buffer.extend_from_slice(&0u16.to_le_bytes()); // remote_magic, unused by GGPO
+                buffer.push(0); // remote_endpoint, unused by GGPO
+            }
+            MessageBody::SyncReply(body) => {
+                buffer.extend_from_slice(&body.random_reply.to_le_bytes());
+            }
+            MessageBody::Input(body) if !self.encode_input(body, input_size, buffer) => {
+                return false;
+            }
+            MessageBody::Input(_) => (),
+            MessageBody::InputAck(body) => {
+                buffer.extend_from_slice(
+                    &bitfield(false, to_ggpo_frame(body.ack_frame), 0).to_le_bytes(),
+                );
+            }
+            MessageBody::QualityReport(body) => {
+                buffer.extend_from_slice(&body.frame_advantage.to_le_bytes());
+                buffer.extend_from_slice(&(body.ping as u32).to_le_bytes());
+                self.pings.push_back(body.ping);
+                if self.pings.len() > PING_HISTORY_SIZE {
+                    self.pings.pop_front();
+                }
+            }
+            MessageBody::QualityReply(body) => {
+                buffer.extend_from_slice(&(body.pong as u32).to_le_bytes());
+            }
+            _ => (),
+        }
+        self.send_seq = self.send_seq.wrapping_add(1);
+        true
+    }
+
+    /// Appends the body of a GGPO input packet, which encodes every input as the list of bits changed to the previous input.
+    fn encode_input(&mut self, body: &Input, input_size: usize, buffer: &mut Vec<u8>) -> bool {
+        // the session encoded the inputs against the last input the remote client acknowledged, which is the previous frame
+        let reference = self
+            .sent_inputs
+            .get(&(body.start_frame - 1))
+            .cloned()
+            .unwrap_or_else(|| vec![0; input_size]);
+        let Ok(inputs) = decode(&reference, &body.bytes, MAX_INPUTS_PER_PACKET) else {
+            return false;
+        };
+
+        let mut bits = BitWriter::default();
+        let mut last = &reference;
+        for input in &inputs {
+            for i in 0..input_size * 8 {
+                let value = bit(input, i);
+                if value != bit(last, i) {
+                    bits.push(true);
+                    bits.push(value);
+                    for n in 0..GGPO_NIBBLE_SIZE {
+                        bits.push(i & (1 << n) != 0);
+                    }
+                }
+            }
+            bits.push(false);
+            last = input;
+        }
+        if bits.len >= GGPO_MAX_COMPRESSED_BITS {
+            return false;
+        }
+
+        for (i, input) in inputs.iter().enumerate() {
+            self.sent_inputs
+                .insert(body.start_frame + i as Frame, input.clone());
+        }
+        // older inputs are never encoded against again, since the remote client acknowledged them
+        self.sent_inputs = self.sent_inputs.split_off(&(body.start_frame - 1));
+
+        for i in 0..GGPO_MAX_PLAYERS {
+            let status = body.peer_connect_status.get(i).copied().unwrap_or_default();
+            let raw = bitfield(status.disconnected, to_ggpo_frame(status.last_frame), 1);
+            buffer.extend_from_slice(&raw.to_le_bytes());
+        }
+        // like GGPO, a packet without inputs starts at frame 0
+        let (start_frame, size) = if inputs.is_empty() {
+            (0, 0)
+        } else {
+            (body.start_frame, input_size as u8)
+        };
+        buffer.extend_from_slice(&(start_frame as u32).to_le_bytes());
+        let raw = bitfield(body.disconnect_requested, to_ggpo_frame(body.ack_frame), 1);
+        buffer.extend_from_slice(&raw.to_le_bytes());
+        buffer.extend_from_slice(&(bits.len as u16).to_le_bytes());
+        buffer.push(size);
+        buffer.extend_from_slice(&bits.bytes);
+        true
+    }
+
+    /// Translates a GGPO packet into a message. Returns `None` if the packet is malformed or its inputs cannot be decoded.
+    fn decode(&mut self, bytes: &[u8], num_players: usize, input_size: usize) -> Option<Message> {
+        let mut reader = Reader::new(bytes);
+        let magic = u16::from_le_bytes(reader.array().ok()?);
+        let _seq = u16::from_le_bytes(reader.array().ok()?);
+        let [msg_type] = reader.array().ok()?;
+
+        let body = match msg_type {
+            GGPO_SYNC_REQUEST => {
+                let random_request = u32::from_le_bytes(reader.array().ok()?);
+                reader.slice(3).ok()?; // remote_magic and remote_endpoint, unused by GGPO
+                MessageBody::SyncRequest(SyncRequest {
+                    random_request,
+                    // GGPO does not exchange a version, the socket stands in for the GGRS wire format
+                    protocol_version: PROTOCOL_VERSION,
+                })
+            }
+            GGPO_SYNC_REPLY => MessageBody::SyncReply(SyncReply {
+                random_reply: u32::from_le_bytes(reader.array().ok()?),
+                time: StdClock.millis_since_epoch() as u64,
+                ..Default::default()
+            }),
+            GGPO_INPUT => {
+                MessageBody::Input(self.decode_input(&mut reader, num_players, input_size)?)
+            }
+            GGPO_QUALITY_REPORT => MessageBody::QualityReport(QualityReport {
+                frame_advantage: i8::from_le_bytes(reader.array().ok()?),
+                ping: u32::from_le_bytes(reader.array().ok()?) as u128,
+            }),
+            GGPO_QUALITY_REPLY => {
+                let pong = u32::from_le_bytes(reader.array().ok()?);
+                let pong = *self.pings.iter().find(|&&ping| ping as u32 == pong)?;
+                MessageBody::QualityReply(QualityReply { pong })
+            }
+            GGPO_KEEP_ALIVE => MessageBody::KeepAlive,
+            GGPO_INPUT_ACK => {
+                let (_, ack_frame) = from_bitfield(u32::from_le_bytes(reader.array().ok()?), 0);
+                MessageBody::InputAck(InputAck {
+                    ack_frame: from_ggpo_frame(ack_frame),
+                })
+            }
+            _ => return None,
+        };
+
+        if reader.remaining() > 0 {
+            return None;
+        }
+        Some(Message {
+            header: MessageHeader {
+                magic,
+                session_token: None,
+            },
+            body,
+        })
+    }
+
+    fn decode_input(
+        &mut self,
+        reader: &mut Reader,
+        num_players: usize,
+        input_size: usize,
+    ) -> Option<Input> {
+        let mut peer_connect_status = Vec::with_capacity(num_players);
+        for i in 0..GGPO_MAX_PLAYERS {
+            let (disconnected, last_frame) =
+                from_bitfield(u32::from_le_bytes(reader.array().ok()?), 1);
+            if i < num_players {
+                peer_connect_status.push(ConnectionStatus {
+                    disconnected,
+                    last_frame: from_ggpo_frame(last_frame),
+                });
+            }
+        }
+        let start_frame = u32::from_le_bytes(reader.array().ok()?) as Frame;
+        // the frame comes straight from the packet, so it is checked before any arithmetic with it
+        if !(0..MAX_FRAME).contains(&start_frame) {
+            return None;
+        }
+        let (disconnect_requested, ack_frame) =
+            from_bitfield(u32::from_le_bytes(reader.array().ok()?), 1);
+        let num_bits = u16::from_le_bytes(reader.array().ok()?) as usize;
+        let [size] = reader.array().ok()?;
+        let bits = reader.slice(num_bits.div_ceil(8)).ok()?;
+        if num_bits > 0 && size as usize != input_size {
+            return None;
+        }
+
+        // the remote client encoded the inputs starting from the last input we acknowledged, which is the previous frame
+        let reference =
+            if num_bits == 0 || start_frame <= *self.first_recv_frame.get_or_insert(start_frame) {
+                vec![0; input_size]
+            } else {
+                self.recv_inputs.get(&(start_frame - 1))?.clone()
+            };
+        let mut inputs = Vec::new();
+        let mut current = reference.clone();
+        let mut offset = 0;
+        let read_bit = |offset: &mut usize| -> Option<bool> {
+            if *offset >= num_bits {
+                return None;
+            }
+            let value = bit(bits, *offset);
+            *offset += 1;
+            Some(value)
+        };
+        while offset < num_bits {
+            while read_bit(&mut offset)? {
+                let value = read_bit(&mut offset)?;
+                let mut index = 0;
+                for n in 0..GGPO_NIBBLE_SIZE {
+                    index |= usize::from(read_bit(&mut offset)?) << n;
+                }
+                if index >= input_size * 8 {
+                    return None;
+                }
+                set_bit(&mut current, index, value);
+            }
+            inputs.push(current.clone());
+            if inputs.len() > MAX_INPUTS_PER_PACKET {
+                return None;
+            }
+        }
+
+        for (i, input) in inputs.iter().enumerate() {
+            let frame = start_frame.checked_add(Frame::try_from(i).ok()?)?;
+            self.recv_inputs.insert(frame, input.clone());
+        }
+        // older inputs are never decoded against again, since we acknowledged them
+        self.recv_inputs = self.recv_inputs.split_off(&(start_frame - 1));
+
+        Some(Input {
+            peer_connect_status,
+            disconnect_requested,
+            start_frame,
+            ack_frame: from_ggpo_frame(ack_frame),
+            sent_at: StdClock.millis_since_epoch() as u64,
+            bytes: encode(&reference, inputs.iter()),
+            metadata_frame: None,
+            metadata: Vec::new(),
+        })
+    }
+}
+
+/// Packs a flag into the lowest bit and a frame into the next 31 bits, like the bitfields of GGPO. `shift` is 0 for a frame
+/// without flag.
+fn bitfield(flag: bool, frame: Frame, shift: u32) -> u32 {
+    let frame = (frame as u32) & 0x7FFF_FFFF;
+    (frame << shift) | u32::from(flag && shift > 0)
+}
+
+/// Returns the frame GGPO sends for a frame that might be missing, which GGPO marks with [`NULL_FRAME`].
+fn to_ggpo_frame(frame: Option<Frame>) -> Frame {
+    frame.unwrap_or(NULL_FRAME)
+}
+
+/// Returns the frame GGPO sent, or `None` for [`NULL_FRAME`] and frames outside of any session.
+fn from_ggpo_frame(frame: Frame) -> Option<Frame> {
+    (0..MAX_FRAME).contains(&frame).then_some(frame)
+}
+
+/// Unpacks a flag and a sign-extended 31 bit frame packed with [`bitfield()`].
+fn from_bitfield(raw: u32, shift: u32) -> (bool, Frame) {
+    let frame = ((raw << (1 - shift)) as i32) >> 1;
+    (shift > 0 && raw & 1 == 1, frame)
+}
+
+/// Returns the bit at the given index, counting from the least significant bit of the first byte.
+fn bit(bytes: &[u8], index: usize) -> bool {
+    bytes[index / 8] & (1 << (index % 8)) != 0
+}
+
+fn set_bit(bytes: &mut [u8], index: usize, value: bool) {
+    if value {
+        bytes[index / 8] |= 1 << (index % 8);
+    } else {
+        bytes[index / 8] &= !(1 << (index % 8));
+    }
+}
+
+/// Writes single bits, least significant bit first, like the bit vector of GGPO.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: bool) {
+        if self.len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        set_bit(&mut self.bytes, self.len, value);
+        self.len += 1;
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod ggpo_socket_tests {
+    use super::*;
+
+    fn message(body: MessageBody) -> Message {
+        Message {
+            header: MessageHeader {
+                magic: 0xABCD,
+                session_token: None,
+            },
+            body,
+        }
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn test_ggpo_layout() {
+        let mut peer = GgpoPeer::default();
+        let mut buffer = Vec::new();
+        let sync_request = message(MessageBody::SyncRequest(SyncRequest {
+            random_request: 0xDEAD_BEEF,
+            protocol_version: PROTOCOL_VERSION,
+        }));
+        assert!(peer.encode(&sync_request, 1, &mut buffer));
+        assert_eq!(to_hex(&buffer), "cdab000001efbeadde000000");
+
+        // the sequence number counts up with every packet
+        buffer.clear();
+        let ack = message(MessageBody::InputAck(InputAck { ack_frame: None }));
+        assert!(peer.encode(&ack, 1, &mut buffer));
+        assert_eq!(to_hex(&buffer), "cdab010007ffffff7f");
+        assert_eq!(GgpoPeer::default().decode(&buffer, 2, 1), Some(ack));
+
+        // messages GGPO does not know are not sent
+        buffer.clear();
+        let checksum = message(MessageBody::ChecksumReport(Default::default()));
+        assert!(!peer.encode(&checksum, 1, &mut buffer));
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Packets in the layout of `UdpMsg` in `udp_msg.h` of GGPO: packed, little-endian, with bitfields filled from the
+    /// lowest bit. They are assembled field by field from that layout instead of by [`GgpoPeer::encode()`], so a mistake in
+    /// the encoder cannot hide in both the packets and the test.
+    #[test]
+    fn test_ggpo_golden_packets() {
+        let mut peer = GgpoPeer::default();
+        let header = MessageHeader {
+            magic: 0x1234,
+            session_token: None,
+        };
+
+        // magic, sequence number, type SyncRequest, random_request, remote_magic, remote_endpoint
+        let sync_request = from_hex(concat!("3412", "0000", "01", "04030201", "0000", "00"));
+        let msg = peer.decode(&sync_request, 2, 1).unwrap();
+        assert_eq!(
+            msg.body,
+            MessageBody::SyncRequest(SyncRequest {
+                random_request: 0x0102_0304,
+                protocol_version: PROTOCOL_VERSION,
+            })
+        );
+        assert_eq!(msg.header, header);
+
+        // type QualityReport, frame_advantage -2, ping 1000
+        let quality_report = from_hex(concat!("3412", "0200", "04", "fe", "e8030000"));
+        assert_eq!(
+            peer.decode(&quality_report, 2, 1).unwrap().body,
+            MessageBody::QualityReport(QualityReport {
+                frame_advantage: -2,
+                ping: 1000,
+            })
+        );
+
+        // type KeepAlive without a body
+        let keep_alive = from_hex(concat!("3412", "0300", "06"));
+        assert_eq!(
+            peer.decode(&keep_alive, 2, 1).unwrap().body,
+            MessageBody::KeepAlive
+        );
+
+        // type InputAck, ack_frame 5 in a 31 bit field
+        let input_ack = from_hex(concat!("3412", "0400", "07", "05000000"));
+        assert_eq!(
+            peer.decode(&input_ack, 2, 1).unwrap().body,
+            MessageBody::InputAck(InputAck { ack_frame: Some(5) })
+        );
+
+        // type Input: the connect status of 4 players (disconnected:1, last_frame:31), start_frame 0,
+        // disconnect_requested:1 and ack_frame:31 of -1, 32 bits of 1 byte inputs. Frame 0 sets bits 0 and 2 of the
+        // blank input, frame 1 clears bit 0 again: every changed bit is a 1, its new value and its index as 8 bits, and a
+        // 0 ends every frame.
+        let input = from_hex(concat!(
+            "3412", "0500", "03", "02000000", "feffffff", "feffffff", "feffffff", "00000000",
+            "feffffff", "2000", "01", "032c2000",
+        ));
+        let Some(Message {
+            body: MessageBody::Input(received),
+            ..
+        }) = peer.decode(&input, 2, 1)
+        else {
+            panic!("input packet could not be decoded");
+        };
+        assert_eq!(
+            received.peer_connect_status,
+            vec![
+                ConnectionStatus {
+                    disconnected: false,
+                    last_frame: Some(1),
+                },
+                ConnectionStatus {
+                    disconnected: false,
+                    last_frame: None,
+                },
+            ]
+        );
+        assert_eq!(received.start_frame, 0);
+        assert_eq!(received.ack_frame, None);
+        assert!(!received.disconnect_requested);
+        assert_eq!(
+            decode(&[0], &received.bytes, 2).unwrap(),
+            vec![vec![0b101], vec![0b100]]
+        );
+
+        // the encoder produces the same layout
+        let mut buffer = Vec::new();
+        let ack = message(MessageBody::InputAck(InputAck { ack_frame: Some(5) }));
+        assert!(GgpoPeer::default().encode(&ack, 1, &mut buffer));
+        assert_eq!(buffer[2..], from_hex(concat!("0000", "07", "05000000"))[..]);
+    }
+
+    #[test]
+    fn test_no_state_for_strangers() {
+        let mut socket = GgpoCompatSocket::bind_to_port(0, 2, 1).unwrap();
+        let addr = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            socket.local_addr().unwrap().port(),
+        );
+        let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let keep_alive = from_hex(concat!("3412", "0000", "06"));
+        for _ in 0..10 {
+            stranger.send_to(&keep_alive, addr).unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(socket.receive_all_messages().len(), 10);
+        assert!(socket.peers.is_empty());
+    }
+
+    #[test]
+    fn test_reject_out_of_range_frames() {
+        // a start frame beyond the last frame a session can reach, without inputs
+        let mut packet = from_hex(concat!("3412", "0000", "03"));
+        packet.extend_from_slice(&[0; 16]);
+        packet.extend_from_slice(&0x8000_0000u32.to_le_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 0, 0, 1]);
+        let mut peer = GgpoPeer::default();
+        assert!(peer.decode(&packet, 2, 1).is_none());
+
+        // the same with inputs, which would overflow the frame of the last input
+        let offset = 3 + 16;
+        packet[offset..offset + 4].copy_from_slice(&(MAX_FRAME as u32).to_le_bytes());
+        packet[offset + 8..offset + 10].copy_from_slice(&1u16.to_le_bytes());
+        packet.push(0);
+        assert!(peer.decode(&packet, 2, 1).is_none());
+    }
+
+    #[test]
+    fn test_input_roundtrip() {
+        let mut sender = GgpoPeer::default();
+        let mut receiver = GgpoPeer::default();
+        let inputs = [vec![0b0000_0101u8], vec![0b0000_0100], vec![0b1000_0100]];
+        let body = Input {
+            peer_connect_status: vec![
+                ConnectionStatus {
+                    disconnected: false,
+                    last_frame: Some(2),
+                },
+                ConnectionStatus {
+                    disconnected: true,
+                    last_frame: None,
+                },
+            ],
+            start_frame: 0,
+            bytes: encode(&[0], inputs.iter()),
+            ..Default::default()
+        };
+
+        let mut buffer = Vec::new();
+        assert!(sender.encode(&message(MessageBody::Input(body.clone())), 1, &mut buffer));
+        // two bits changed in the first frame, one in each of the others
+        let num_bits = u16::from_le_bytes([buffer[29], buffer[30]]);
+        assert_eq!(num_bits, 2 * 10 + 1 + 10 + 1 + 10 + 1);
+
+        let Some(Message {
+            body: MessageBody::Input(received),
+            ..
+        }) = receiver.decode(&buffer, 2, 1)
+        else {
+            panic!("input packet could not be decoded");
+        };
+        assert_eq!(received.peer_connect_status, body.peer_connect_status);
+        assert_eq!(received.ack_frame, None);
+        assert_eq!(decode(&[0], &received.bytes, 3).unwrap(), inputs);
+
+        // the next packet is encoded against the acknowledged input of frame 1
+        let body = Input {
+            peer_connect_status: body.peer_connect_status,
+            start_frame: 2,
+            bytes: encode(&inputs[1], [inputs[2].clone()].iter()),
+            ..Default::default()
+        };
+        buffer.clear();
+        assert!(sender.encode(&message(MessageBody::Input(body)), 1, &mut buffer));
+        let Some(Message {
+            body: MessageBody::Input(received),
+            ..
+        }) = receiver.decode(&buffer, 2, 1)
+        else {
+            panic!("input packet could not be decoded");
+        };
+        assert_eq!(received.start_frame, 2);
+        assert_eq!(
+            decode(&inputs[1], &received.bytes, 1).unwrap(),
+            vec![inputs[2].clone()]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use crate::Frame;
+
+use std::fmt::Debug;
+
+/// The maximum size in bytes of the payload set with [`SessionBuilder::with_handshake_payload()`], so it fits into a single
+/// synchronization packet.
+///
+/// [`SessionBuilder::with_handshake_payload()`]: crate::SessionBuilder::with_handshake_payload
+pub const MAX_HANDSHAKE_PAYLOAD_SIZE: usize = 1024;
+
+/// A session setting all clients have to share, which differs between the local and a remote client. Clients exchange these
+/// settings during synchronization and disconnect from each other on a mismatch, see [`GgrsEvent::HandshakeMismatch`].
+///
+/// [`GgrsEvent::HandshakeMismatch`]: crate::GgrsEvent::HandshakeMismatch
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HandshakeMismatch {
+    /// The clients start at different frames, see [`SessionBuilder::with_start_frame()`].
+    ///
+    /// [`SessionBuilder::with_start_frame()`]: crate::SessionBuilder::with_start_frame
+    StartFrame {
+        /// The start frame of the local client.
+        local: Frame,
+        /// The start frame of the remote client.
+        remote: Frame,
+    },
+    /// The clients compress their inputs differently, see [`SessionBuilder::with_input_codec()`].
+    ///
+    /// [`SessionBuilder::with_input_codec()`]: crate::SessionBuilder::with_input_codec
+    InputCodec {
+        /// The [`Codec::format_id()`] of the local codec.
+        ///
+        /// [`Codec::format_id()`]: crate::Codec::format_id
+        local: u32,
+        /// The [`Codec::format_id()`] of the remote codec.
+        ///
+        /// [`Codec::format_id()`]: crate::Codec::format_id
+        remote: u32,
+    },
+    /// Only one of the clients sends variable length inputs, see [`SessionBuilder::with_variable_length_inputs()`].
+    ///
+    /// [`SessionBuilder::with_variable_length_inputs()`]: crate::SessionBuilder::with_variable_length_inputs
+    VariableLengthInputs {
+        /// Whether the local client sends variable length inputs.
+        local: bool,
+        /// Whether the remote client sends variable length inputs.
+        remote: bool,
+    },
+}
+
+/// A [`HandshakeVerifier`] decides whether to play with a remote client, based on the application payload the client presented
+/// during synchronization, e.g. a build hash or the hash of its mod list. See [`SessionBuilder::with_handshake_verifier()`].
+/// It is implemented for all closures taking the address of the remote client and its payload.
+///
+/// [`SessionBuilder::with_handshake_verifier()`]: crate::SessionBuilder::with_handshake_verifier
+#[cfg(feature = "sync-send")]
+pub trait HandshakeVerifier<A>: Send + Sync {
+    /// Returns `true` to accept the remote client with the given payload, or `false` to reject it.
+    fn verify(&self, addr: &A, payload: &[u8]) -> bool;
+}
+
+/// A [`HandshakeVerifier`] decides whether to play with a remote client, based on the application payload the client presented
+/// during synchronization, e.g. a build hash or the hash of its mod list. See [`SessionBuilder::with_handshake_verifier()`].
+/// It is implemented for all closures taking the address of the remote client and its payload.
+///
+/// [`SessionBuilder::with_handshake_verifier()`]: crate::SessionBuilder::with_handshake_verifier
+#[cfg(not(feature = "sync-send"))]
+pub trait HandshakeVerifier<A> {
+    /// Returns `true` to accept the remote client with the given payload, or `false` to reject it.
+    fn verify(&self, addr: &A, payload: &[u8]) -> bool;
+}
+
+#[cfg(feature = "sync-send")]
+impl<A, F> HandshakeVerifier<A> for F
+where
+    F: Fn(&A, &[u8]) -> bool + Send + Sync,
+{
+    fn verify(&self, addr: &A, payload: &[u8]) -> bool {
+        self(addr, payload)
+    }
+}
+
+#[cfg(not(feature = "sync-send"))]
+impl<A, F> HandshakeVerifier<A> for F
+where
+    F: Fn(&A, &[u8]) -> bool,
+{
+    fn verify(&self, addr: &A, payload: &[u8]) -> bool {
+        self(addr, payload)
+    }
+}
+
+impl<A> Debug for dyn HandshakeVerifier<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HandshakeVerifier")
+    }
+}
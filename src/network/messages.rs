@@ -1,64 +1,55 @@
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 
-use crate::{Frame, NULL_FRAME};
+use crate::Frame;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// The maximum size in bytes of a single encoded [`Message`].
+pub(crate) const MAX_PACKET_SIZE: usize = 4096;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct ConnectionStatus {
     pub disconnected: bool,
-    pub last_frame: Frame,
-}
-
-impl Default for ConnectionStatus {
-    fn default() -> Self {
-        Self {
-            disconnected: false,
-            last_frame: NULL_FRAME,
-        }
-    }
+    pub last_frame: Option<Frame>, // the last frame of the player the sender has the input of, if any
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct SyncRequest {
-    pub random_request: u32, // please reply back with this random data
+    pub random_request: u32,   // please reply back with this random data
+    pub protocol_version: u32, // the wire protocol version of the requesting peer
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct SyncReply {
-    pub random_reply: u32, // here's your random data back
+    pub random_reply: u32,                 // here's your random data back
+    pub delay_based: bool, // the replying peer would like to run the session delay-based
+    pub fps: u32,          // the simulation fps the replying peer declared
+    pub time: u64, // the wall clock time of the replying peer in ms, to estimate the clock offset
+    pub player_ids: Vec<(u64, u128)>, // the persistent ids of the local players of the replying peer, by handle
+    pub payload: Vec<u8>, // the handshake payload of the replying peer, for the application to verify
+    pub input_delay: Option<u32>, // the input delay the replying peer offers to split fairly, if it would like to
+    pub round_trip_time: u32, // the round trip time in ms the replying peer measured so far, 0 if unknown
+    pub external_addr: Option<SocketAddr>, // the address the replying peer discovered it is reachable at, e.g. with STUN
+    pub start_frame: i32,                  // the frame the replying peer starts at
+    pub input_codec: u32, // the format id of the codec the replying peer compresses inputs with
+    pub variable_length_inputs: bool, // the replying peer sends variable length inputs
+    pub synchronized_start: bool, // the replying peer proposes a start time once synchronized
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct Input {
     pub peer_connect_status: Vec<ConnectionStatus>,
     pub disconnect_requested: bool,
     pub start_frame: Frame,
-    pub ack_frame: Frame,
+    pub ack_frame: Option<Frame>, // the last frame the sender received the input of, if any
+    pub sent_at: u64,             // the wall clock time of the sender in ms
     pub bytes: Vec<u8>,
+    pub metadata_frame: Option<Frame>, // the frame of the first input metadata block, if the packet has metadata
+    pub metadata: Vec<u8>,
 }
 
-impl Default for Input {
-    fn default() -> Self {
-        Self {
-            peer_connect_status: Vec::new(),
-            disconnect_requested: false,
-            start_frame: NULL_FRAME,
-            ack_frame: NULL_FRAME,
-            bytes: Vec::new(),
-        }
-    }
-}
-
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct InputAck {
-    pub ack_frame: Frame,
-}
-
-impl Default for InputAck {
-    fn default() -> Self {
-        Self {
-            ack_frame: NULL_FRAME,
-        }
-    }
+    pub ack_frame: Option<Frame>, // the last frame the sender received the input of, if any
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
@@ -78,9 +69,64 @@ pub(crate) struct ChecksumReport {
     pub frame: Frame,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum ReliablePayload {
+    StartTime(u64), // proposed start of the session in ms since the epoch, in the sender's clock
+    User(Vec<u8>),  // an out-of-band message of the application
+    // asks the receiver to take over a player of the sender, or to hand its player back to the sender, from the given frame on or later
+    HandOverRequest {
+        handle: u64,
+        frame: Frame,
+    },
+    // from the given frame on, the inputs sent by the sender include the player (added) or no longer do
+    HandleChange {
+        handle: u64,
+        frame: Frame,
+        added: bool,
+    },
+    InputDelay(u32), // the share of the input delay the sender decided for the receiver, see `with_fair_input_delay()`
+}
+
+impl ReliablePayload {
+    /// Returns the size in bytes of the data held by the payload.
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::StartTime(_)
+            | Self::HandOverRequest { .. }
+            | Self::HandleChange { .. }
+            | Self::InputDelay(_) => 0,
+            Self::User(data) => data.capacity(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Reliable {
+    pub seq: u32,
+    pub oldest_pending: u32, // the sender no longer resends messages with a lower sequence number
+    pub payload: ReliablePayload,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub(crate) struct ReliableAck {
+    pub ack: u32, // all reliable messages with a lower sequence number have been received
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub(crate) struct MtuProbe {
+    pub size: u32, // the size of the encoded packet, so the receiver can acknowledge it
+    pub padding: Vec<u8>, // pads the packet to its size
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub(crate) struct MtuProbeAck {
+    pub size: u32, // the size of the probe that made it through
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct MessageHeader {
     pub magic: u16,
+    pub session_token: Option<[u8; 16]>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -93,14 +139,29 @@ pub(crate) enum MessageBody {
     QualityReply(QualityReply),
     ChecksumReport(ChecksumReport),
     KeepAlive,
+    Reliable(Reliable),
+    ReliableAck(ReliableAck),
+    MtuProbe(MtuProbe),
+    MtuProbeAck(MtuProbeAck),
 }
 
 /// A messages that [`NonBlockingSocket`] sends and receives. When implementing [`NonBlockingSocket`],
 /// you should deserialize received messages into this `Message` type and pass them.
+/// [`Message::encode_into()`] and [`parse_packet()`] provide an allocation-friendly encoding that is compatible with `bincode`.
 ///
 /// [`NonBlockingSocket`]: crate::NonBlockingSocket
+/// [`parse_packet()`]: crate::parse_packet
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Message {
     pub(crate) header: MessageHeader,
     pub(crate) body: MessageBody,
 }
+
+impl Message {
+    /// Returns the session token the message was sent with, if any. See [`SessionBuilder::with_session_token()`].
+    ///
+    /// [`SessionBuilder::with_session_token()`]: crate::SessionBuilder::with_session_token
+    pub fn session_token(&self) -> Option<[u8; 16]> {
+        self.header.session_token
+    }
+}
@@ -1,5 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Frame;
+
 /// The `NetworkStats` struct contains statistics about the current session.
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NetworkStats {
     /// The length of the queue containing UDP packets which have not yet been acknowledged by the end client.
     /// The length of the send queue is a rough indication of the quality of the connection. The longer the send queue, the higher the round-trip time between the
@@ -7,7 +11,29 @@ pub struct NetworkStats {
     pub send_queue_len: usize,
     /// The roundtrip packet transmission time as calculated by GGRS.
     pub ping: u128,
-    /// The estimated bandwidth used between the two clients, in kilobits per second.
+    /// The median time in ms between sending an input and receiving its acknowledgement from the remote client, over the most recent inputs.
+    /// In contrast to [`ping`], this includes the time until the remote client processes the input, so a large difference between the two
+    /// points to frame pacing rather than the network. It is 0 until the first input has been acknowledged.
+    ///
+    /// [`ping`]: #structfield.ping
+    pub input_latency_p50: u128,
+    /// The 90th percentile of the input latency, see [`input_latency_p50`].
+    ///
+    /// [`input_latency_p50`]: #structfield.input_latency_p50
+    pub input_latency_p90: u128,
+    /// The 99th percentile of the input latency, see [`input_latency_p50`].
+    ///
+    /// [`input_latency_p50`]: #structfield.input_latency_p50
+    pub input_latency_p99: u128,
+    /// The median time in ms that recent input packets of the remote client took to arrive. It is measured with the remote send time
+    /// converted to local time via [`clock_offset`], so it is 0 until the clock offset is known.
+    ///
+    /// [`clock_offset`]: #structfield.clock_offset
+    pub input_transit_time: u128,
+    /// The estimated offset in ms of the remote client's wall clock to the local one (positive if the remote clock is ahead),
+    /// measured during synchronization. `None` if synchronization has not completed a roundtrip yet.
+    pub clock_offset: Option<i64>,
+    /// The average bandwidth used to send packets to the remote client, including the IP and UDP headers, in kilobytes per second.
     pub kbps_sent: usize,
 
     /// The number of frames GGRS calculates that the local client is behind the remote client at this instant in time.
@@ -18,6 +44,31 @@ pub struct NetworkStats {
     ///
     /// [`local_frames_behind`]: #structfield.local_frames_behind
     pub remote_frames_behind: i32,
+    /// The number of packets received from the remote client that were dropped because they exceeded the rate limit.
+    pub packets_dropped: usize,
+    /// The number of times sending inputs to the remote client was put off because the bandwidth limit set with
+    /// [`SessionBuilder::with_bandwidth_limit()`] was exceeded. The inputs are sent together with later inputs instead.
+    ///
+    /// [`SessionBuilder::with_bandwidth_limit()`]: crate::SessionBuilder#method.with_bandwidth_limit
+    pub sends_deferred: usize,
+    /// The number of messages sent with [`P2PSession::send_message()`] or internally that were given up on, because the
+    /// remote client did not acknowledge them despite repeated resends.
+    ///
+    /// [`P2PSession::send_message()`]: crate::P2PSession::send_message
+    pub messages_dropped: usize,
+    /// The average number of frames of inputs in the input packets sent to the remote client, rounded to the nearest frame.
+    /// It grows with unacknowledged inputs, which are sent with every packet until the remote client acknowledges them, and is at
+    /// least the redundancy set with [`SessionBuilder::with_input_redundancy()`] once enough inputs have been acknowledged.
+    ///
+    /// [`SessionBuilder::with_input_redundancy()`]: crate::SessionBuilder#method.with_input_redundancy
+    pub input_redundancy: usize,
+    /// The number of packets received from the remote client that the session took from its socket but did not process yet,
+    /// because its latest poll used up the budget set with [`SessionBuilder::with_receive_budget()`]. Packets a socket leaves
+    /// waiting in its own buffer, like [`UdpNonBlockingSocket`] does, are not counted.
+    ///
+    /// [`SessionBuilder::with_receive_budget()`]: crate::SessionBuilder#method.with_receive_budget
+    /// [`UdpNonBlockingSocket`]: crate::UdpNonBlockingSocket
+    pub receive_backlog: usize,
 }
 
 impl NetworkStats {
@@ -26,3 +77,19 @@ impl NetworkStats {
         Self::default()
     }
 }
+
+/// A `NetworkSample` is a snapshot of the connection to a remote client, recorded once per frame.
+/// A history of these samples can be used to render ping and frame advantage graphs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkSample {
+    /// The local frame at which this sample was recorded.
+    pub frame: Frame,
+    /// The roundtrip packet transmission time as calculated by GGRS.
+    pub ping: u128,
+    /// The number of frames GGRS calculates that the local client is behind the remote client.
+    pub local_frames_behind: i32,
+    /// The same as [`local_frames_behind`], but calculated from the perspective of the remote player.
+    ///
+    /// [`local_frames_behind`]: #structfield.local_frames_behind
+    pub remote_frames_behind: i32,
+}
@@ -0,0 +1,300 @@
+use std::sync::Arc;
+
+use instant::{Duration, Instant};
+
+use crate::{
+    clock::{self, Clock, StdClock},
+    network::messages::{
+        Message, MessageBody, MessageHeader, MtuProbe, MtuProbeAck, QualityReply, QualityReport,
+        MAX_PACKET_SIZE,
+    },
+    Config, NonBlockingSocket,
+};
+
+const PROBE_PING_INTERVAL: Duration = Duration::from_millis(20);
+/// The packet sizes tested to find the largest packet that makes it through to the remote client.
+const PROBE_PACKET_SIZES: [usize; 7] = [512, 1024, 1200, 1400, 1472, 2048, MAX_PACKET_SIZE];
+
+/// The `ConnectionQualityReport` summarizes a [`ConnectionProbe`], so it can be shown to players before they commit to a match.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ConnectionQualityReport {
+    /// The number of pings sent to the remote client.
+    pub pings_sent: usize,
+    /// The number of pings that were answered by the remote client.
+    pub pings_received: usize,
+    /// The average roundtrip time in ms.
+    pub ping: u128,
+    /// The average difference in ms between consecutive roundtrip times.
+    pub jitter: u128,
+    /// The fraction of pings that were not answered, between 0.0 and 1.0. It includes packets lost in both directions.
+    pub packet_loss: f32,
+    /// The size in bytes of the largest packet that reached the remote client, 0 if no packet made it through.
+    pub mtu_ceiling: usize,
+}
+
+impl ConnectionQualityReport {
+    /// Rates the connection with 0 (no connection) to 4 (excellent) bars.
+    pub fn bars(&self) -> u8 {
+        if self.pings_received == 0 {
+            0
+        } else if self.ping < 50 && self.packet_loss < 0.01 {
+            4
+        } else if self.ping < 100 && self.packet_loss < 0.03 {
+            3
+        } else if self.ping < 200 && self.packet_loss < 0.1 {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Returns the input delay (in frames at the given fps) that covers the one-way latency plus jitter of the connection,
+    /// which can be passed to [`SessionBuilder::with_input_delay()`].
+    ///
+    /// [`SessionBuilder::with_input_delay()`]: crate::SessionBuilder::with_input_delay
+    pub fn recommended_input_delay(&self, fps: usize) -> usize {
+        let latency = self.ping / 2 + self.jitter;
+        (latency * fps as u128).div_ceil(1000) as usize
+    }
+}
+
+/// A `ConnectionProbe` runs a short packet exchange with a remote client to measure the quality of the connection before starting a session.
+/// Both clients need to probe each other at the same time, since every probe also answers the packets of the other side.
+/// Call [`poll()`] regularly until it returns `true`, then [`report()`] contains the results.
+/// The probe uses the same socket and packet header as a session, so the socket can be handed to the [`SessionBuilder`] afterwards.
+/// If the session uses a session token, give it to the probe with [`with_session_token()`] as well, and likewise a custom
+/// [`Clock`] with [`with_clock()`].
+///
+/// [`poll()`]: ConnectionProbe#method.poll
+/// [`report()`]: ConnectionProbe#method.report
+/// [`with_session_token()`]: ConnectionProbe#method.with_session_token
+/// [`with_clock()`]: ConnectionProbe#method.with_clock
+/// [`SessionBuilder`]: crate::SessionBuilder
+pub struct ConnectionProbe<T>
+where
+    T: Config,
+{
+    remote_addr: T::Address,
+    header: MessageHeader,
+    clock: Arc<dyn Clock>,
+    start: Instant,
+    duration: Duration,
+    last_ping: Option<Instant>,
+    rtts: Vec<Duration>,
+    pings_sent: usize,
+    mtu_ceiling: usize,
+}
+
+impl<T: Config> ConnectionProbe<T> {
+    /// Creates a new probe that exchanges packets with `remote_addr` for the given duration, starting now.
+    pub fn new(remote_addr: T::Address, duration: Duration) -> Self {
+        Self {
+            remote_addr,
+            header: MessageHeader::default(),
+            clock: Arc::new(StdClock),
+            start: clock::now(&StdClock),
+            duration,
+            last_ping: None,
+            rtts: Vec::new(),
+            pings_sent: 0,
+            mtu_ceiling: 0,
+        }
+    }
+
+    /// Sends all probe packets with the given session token and drops received packets without it, like a session does with
+    /// [`SessionBuilder::with_session_token()`]. Both clients have to use the same token.
+    ///
+    /// [`SessionBuilder::with_session_token()`]: crate::SessionBuilder::with_session_token
+    pub fn with_session_token(mut self, session_token: [u8; 16]) -> Self {
+        self.header.session_token = Some(session_token);
+        self
+    }
+
+    /// Measures all times with the given [`Clock`] instead of the [`StdClock`], like a session does with
+    /// [`SessionBuilder::with_clock()`]. The probe duration starts over.
+    ///
+    /// [`SessionBuilder::with_clock()`]: crate::SessionBuilder::with_clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self.start = self.now();
+        self
+    }
+
+    /// Sends and receives probe packets. Returns `true` once the probe duration has elapsed.
+    pub fn poll(&mut self, socket: &mut impl NonBlockingSocket<T::Address>) -> bool {
+        for (from, msg) in socket.receive_all_messages() {
+            if from == self.remote_addr && msg.header.session_token == self.header.session_token {
+                self.handle_message(socket, &msg.body);
+            }
+        }
+
+        let now = self.now();
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= self.duration {
+            return true;
+        }
+
+        // stop sending shortly before the end, so the last pings are not counted as lost while they are still in flight
+        let drain_time = (self.duration / 4).min(Duration::from_millis(250));
+        let ping_due = self
+            .last_ping
+            .is_none_or(|last| now.saturating_duration_since(last) >= PROBE_PING_INTERVAL);
+        if ping_due && elapsed + drain_time < self.duration {
+            self.last_ping = Some(now);
+            self.send_pings(socket, elapsed);
+        }
+        false
+    }
+
+    /// Returns the results of the probe so far.
+    pub fn report(&self) -> ConnectionQualityReport {
+        let pings_received = self.rtts.len().min(self.pings_sent);
+        let packet_loss = match self.pings_sent {
+            0 => 0.0,
+            sent => 1.0 - pings_received as f32 / sent as f32,
+        };
+        let ping = match self.rtts.len() {
+            0 => 0,
+            len => (self.rtts.iter().sum::<Duration>() / len as u32).as_millis(),
+        };
+        let jitter = match self.rtts.len() {
+            0 | 1 => 0,
+            len => {
+                let diffs: Duration = self.rtts.windows(2).map(|w| w[0].abs_diff(w[1])).sum();
+                (diffs / (len - 1) as u32).as_millis()
+            }
+        };
+        ConnectionQualityReport {
+            pings_sent: self.pings_sent,
+            pings_received,
+            ping,
+            jitter,
+            packet_loss,
+            mtu_ceiling: self.mtu_ceiling,
+        }
+    }
+
+    fn send_pings(&mut self, socket: &mut impl NonBlockingSocket<T::Address>, elapsed: Duration) {
+        self.pings_sent += 1;
+        let ping = QualityReport {
+            frame_advantage: 0,
+            ping: elapsed.as_micros(),
+        };
+        self.send(socket, MessageBody::QualityReport(ping));
+
+        // pad probes to the packet sizes that have not made it through yet
+        let overhead = self.encoded_len(MessageBody::MtuProbe(MtuProbe::default()));
+        for size in PROBE_PACKET_SIZES
+            .into_iter()
+            .filter(|size| *size > self.mtu_ceiling)
+        {
+            let body = MtuProbe {
+                size: size as u32,
+                padding: vec![0; size - overhead],
+            };
+            self.send(socket, MessageBody::MtuProbe(body));
+        }
+    }
+
+    fn handle_message(
+        &mut self,
+        socket: &mut impl NonBlockingSocket<T::Address>,
+        body: &MessageBody,
+    ) {
+        match body {
+            MessageBody::QualityReport(report) => {
+                let reply = QualityReply { pong: report.ping };
+                self.send(socket, MessageBody::QualityReply(reply));
+            }
+            MessageBody::QualityReply(reply) => {
+                let sent = Duration::from_micros(reply.pong as u64);
+                let elapsed = self.now().saturating_duration_since(self.start);
+                self.rtts.push(elapsed.saturating_sub(sent));
+            }
+            MessageBody::MtuProbe(probe) => {
+                let ack = MtuProbeAck { size: probe.size };
+                self.send(socket, MessageBody::MtuProbeAck(ack));
+            }
+            MessageBody::MtuProbeAck(ack) => {
+                self.mtu_ceiling = self.mtu_ceiling.max(ack.size as usize);
+            }
+            _ => (),
+        }
+    }
+
+    fn now(&self) -> Instant {
+        clock::now(&*self.clock)
+    }
+
+    fn send(&self, socket: &mut impl NonBlockingSocket<T::Address>, body: MessageBody) {
+        let msg = Message {
+            header: self.header,
+            body,
+        };
+        socket.send_to(&msg, &self.remote_addr);
+    }
+
+    fn encoded_len(&self, body: MessageBody) -> usize {
+        let msg = Message {
+            header: self.header,
+            body,
+        };
+        let mut buffer = Vec::new();
+        msg.encode_into(&mut buffer);
+        buffer.len()
+    }
+}
+
+/// Runs a [`ConnectionProbe`] with the remote client for the given duration and returns its [`ConnectionQualityReport`].
+/// The probe packets carry the session token, if one is given, see [`ConnectionProbe::with_session_token()`].
+/// This blocks the calling thread, so it is not available on wasm. There, you can poll a [`ConnectionProbe`] yourself.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn probe<T: Config>(
+    socket: &mut impl NonBlockingSocket<T::Address>,
+    remote_addr: T::Address,
+    duration: Duration,
+    session_token: Option<[u8; 16]>,
+) -> ConnectionQualityReport {
+    let mut probe = ConnectionProbe::<T>::new(remote_addr, duration);
+    if let Some(session_token) = session_token {
+        probe = probe.with_session_token(session_token);
+    }
+    while !probe.poll(socket) {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    probe.report()
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod probe_tests {
+    use super::*;
+
+    #[test]
+    fn test_report_ratings() {
+        let mut report = ConnectionQualityReport {
+            pings_sent: 100,
+            pings_received: 100,
+            ping: 40,
+            jitter: 5,
+            packet_loss: 0.0,
+            mtu_ceiling: 1400,
+        };
+        assert_eq!(report.bars(), 4);
+        // 20ms one-way latency plus 5ms jitter are 1.5 frames at 60 fps
+        assert_eq!(report.recommended_input_delay(60), 2);
+
+        report.packet_loss = 0.05;
+        assert_eq!(report.bars(), 2);
+
+        report.ping = 300;
+        assert_eq!(report.bars(), 1);
+        assert_eq!(report.recommended_input_delay(60), 10);
+
+        report.pings_received = 0;
+        assert_eq!(report.bars(), 0);
+    }
+}
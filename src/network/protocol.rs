@@ -1,21 +1,32 @@
+use crate::clock::{self, Clock};
 use crate::frame_info::PlayerInput;
-use crate::network::compression::{decode, encode};
+use crate::network::codec::Codec;
+use crate::network::compression::{
+    decode, decode_bitplanes, decode_prefixed, encode, encode_bitplanes, encode_prefixed,
+};
+use crate::network::handshake::{HandshakeMismatch, HandshakeVerifier};
 use crate::network::messages::{
     ChecksumReport, ConnectionStatus, Input, InputAck, Message, MessageBody, MessageHeader,
-    QualityReply, QualityReport, SyncReply, SyncRequest,
+    QualityReply, QualityReport, Reliable, ReliablePayload, SyncReply, SyncRequest,
 };
+use crate::network::reliable::ReliableChannel;
+use crate::network::session_socket::SessionSocket;
 use crate::time_sync::TimeSync;
 use crate::{
-    Config, DesyncDetection, Frame, GgrsError, NonBlockingSocket, PlayerHandle, NULL_FRAME,
+    Config, ConnectionState, DesyncDetection, Frame, GgrsError, PlayerHandle, PlayerId, MAX_FRAME,
+    PROTOCOL_VERSION,
 };
 
 use instant::{Duration, Instant};
+use std::cmp::Ordering;
 use std::collections::vec_deque::Drain;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryFrom;
+use std::net::SocketAddr;
 use std::ops::Add;
+use std::sync::Arc;
 
-use super::network_stats::NetworkStats;
+use super::network_stats::{NetworkSample, NetworkStats};
 
 const UDP_HEADER_SIZE: usize = 28; // Size of IP + UDP headers
 const NUM_SYNC_PACKETS: u32 = 5;
@@ -25,77 +36,189 @@ const SYNC_RETRY_INTERVAL: Duration = Duration::from_millis(200);
 const RUNNING_RETRY_INTERVAL: Duration = Duration::from_millis(200);
 const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(200);
 const QUALITY_REPORT_INTERVAL: Duration = Duration::from_millis(200);
-const MAX_PAYLOAD: usize = 467; // 512 is max safe UDP payload, minus 45 bytes for the rest of the packet
-/// Number of old checksums to keep in memory
-pub const MAX_CHECKSUM_HISTORY_SIZE: usize = 32;
-
-fn millis_since_epoch() -> u128 {
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis()
+// the time window over which the frame rate of the remote client is measured
+const FPS_MEASUREMENT_INTERVAL: Duration = Duration::from_secs(2);
+// the amount of input latencies kept per endpoint to compute percentiles from
+const INPUT_LATENCY_SAMPLES: usize = 128;
+// the amount of network samples kept per endpoint, two seconds at 60 fps
+pub(crate) const NETWORK_HISTORY_SIZE: usize = 120;
+const MAX_PAYLOAD: usize = 442; // 512 is max safe UDP payload, minus 53 bytes for the rest of the packet and 17 bytes for the session token, including the input metadata
+/// The maximum size in bytes of the metadata attached to a single input.
+pub(crate) const MAX_INPUT_METADATA_SIZE: usize = 32;
+/// The amount of input metadata bytes after which the metadata of older frames is left out of a packet, as it has been sent before.
+/// The metadata counts towards `MAX_PAYLOAD`, so the inputs of a packet with metadata have less room.
+const MAX_METADATA_PAYLOAD: usize = 256;
+// a source sending more packets than this is either broken or hostile, so its excess packets are dropped
+const MAX_RECV_PACKETS_PER_SECOND: u32 = 1000;
+const RECV_RATE_WINDOW: Duration = Duration::from_secs(1);
+// with a bandwidth limit, unused budget builds up for this long, so short bursts of packets stay within the limit
+const BANDWIDTH_BURST: Duration = Duration::from_millis(250);
+/// Number of old checksums to keep in memory, unless set otherwise
+pub(crate) const DEFAULT_CHECKSUM_HISTORY_SIZE: usize = 32;
+// inputs of at least this many players of at most this size are packed into bitplanes, like the inputs a host broadcasts to spectators
+const BITPLANE_MIN_PLAYERS: usize = 4;
+const BITPLANE_MAX_INPUT_SIZE: usize = 8;
+
+/// Returns the number of players whose inputs of the given total size are packed into bitplanes, or `None` if they are
+/// encoded as they are. Both ends derive this from the players they exchange inputs for, so it is not sent.
+fn bitplane_players<T: Config>(input_size: usize) -> Option<usize> {
+    let size = std::mem::size_of::<T::Input>();
+    if size == 0 || size > BITPLANE_MAX_INPUT_SIZE {
+        return None;
     }
-    #[cfg(target_arch = "wasm32")]
-    {
-        js_sys::Date::new_0().get_time() as u128
+    let players = input_size / size;
+    (players >= BITPLANE_MIN_PLAYERS).then_some(players)
+}
+
+/// Returns the value at the given percentile (between 0 and 1) of the sorted values, or 0 if there are none.
+fn percentile(sorted: &[u128], percentile: f64) -> u128 {
+    if sorted.is_empty() {
+        return 0;
     }
+    let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+    sorted[index]
+}
+
+/// Returns the share of the total input delay proportional to the local latency, rounded to the nearest frame. Exact ties are
+/// rounded up.
+fn fair_share(total: usize, local_latency: u128, remote_latency: u128) -> usize {
+    // without both measurements, the delay is split evenly
+    let (local, remote) = match (local_latency, remote_latency) {
+        (0, _) | (_, 0) => (1, 1),
+        latencies => latencies,
+    };
+    let latency_sum = local + remote;
+    let share = total as u128 * local;
+    let round_up = 2 * (share % latency_sum) >= latency_sum;
+    (share / latency_sum) as usize + usize::from(round_up)
 }
 
 // byte-encoded data representing the inputs of a client, possibly for multiple players at the same time
 #[derive(Clone)]
 struct InputBytes {
-    /// The frame to which this info belongs to. -1/[`NULL_FRAME`] represents an invalid frame
-    pub frame: Frame,
+    /// The frame to which this info belongs to, `None` for the blank input before the first frame
+    pub frame: Option<Frame>,
     /// An input buffer that will hold input data
     pub bytes: Vec<u8>,
+    /// The time at which the input was handed to the endpoint, to measure the time until it is acknowledged.
+    pub created: Instant,
+    /// The metadata of all players, each prefixed by its length. Empty if no player attached metadata to the input.
+    pub metadata: Vec<u8>,
 }
 
 impl InputBytes {
-    fn zeroed<T: Config>(num_players: usize) -> Self {
+    /// Returns the bytes held by this input, including its buffers.
+    fn memory(&self) -> usize {
+        std::mem::size_of::<Self>() + self.bytes.capacity() + self.metadata.capacity()
+    }
+
+    fn zeroed<T: Config>(num_players: usize, created: Instant) -> Self {
         let size = core::mem::size_of::<T::Input>() * num_players;
         Self {
-            frame: NULL_FRAME,
+            frame: None,
             bytes: vec![0; size],
+            created,
+            metadata: Vec::new(),
         }
     }
 
+    /// Encodes the given inputs into `bytes`, reusing its allocation.
     fn from_inputs<T: Config>(
         num_players: usize,
         inputs: &HashMap<PlayerHandle, PlayerInput<T::Input>>,
+        metadata: &HashMap<PlayerHandle, Vec<u8>>,
+        mut bytes: Vec<u8>,
+        created: Instant,
     ) -> Self {
-        let mut bytes = Vec::new();
-        let mut frame = NULL_FRAME;
+        bytes.clear();
+        let mut encoded_metadata = Vec::new();
+        let has_metadata = inputs.keys().any(|handle| metadata.contains_key(handle));
+        let mut frame = None;
         // in ascending order
         for handle in 0..num_players {
             if let Some(input) = inputs.get(&handle) {
-                assert!(frame == NULL_FRAME || input.frame == NULL_FRAME || frame == input.frame);
-                if input.frame != NULL_FRAME {
-                    frame = input.frame;
-                }
+                assert!(frame.is_none() || input.frame.is_none() || frame == input.frame);
+                frame = frame.or(input.frame);
                 let byte_vec = bytemuck::bytes_of(&input.input);
                 bytes.extend_from_slice(byte_vec);
+
+                if has_metadata {
+                    let data = metadata
+                        .get(&handle)
+                        .map_or(&[][..], |data| data.as_slice());
+                    assert!(data.len() <= MAX_INPUT_METADATA_SIZE);
+                    encoded_metadata.push(data.len() as u8);
+                    encoded_metadata.extend_from_slice(data);
+                }
             }
         }
-        Self { frame, bytes }
+        Self {
+            frame,
+            bytes,
+            created,
+            metadata: encoded_metadata,
+        }
     }
 
-    fn to_player_inputs<T: Config>(&self, num_players: usize) -> Vec<PlayerInput<T::Input>> {
-        let mut player_inputs = Vec::new();
-        assert!(self.bytes.len() % num_players == 0);
+    /// Returns `None` if the bytes do not represent valid inputs for the given number of players.
+    fn to_player_inputs<T: Config>(
+        &self,
+        num_players: usize,
+    ) -> Option<Vec<PlayerInput<T::Input>>> {
+        if !self.bytes.len().is_multiple_of(num_players) {
+            return None;
+        }
+        let mut player_inputs = Vec::with_capacity(num_players);
         let size = self.bytes.len() / num_players;
         for p in 0..num_players {
             let start = p * size;
             let end = start + size;
-            let input = *bytemuck::checked::try_from_bytes::<T::Input>(&self.bytes[start..end])
-                .expect("Expected received data to be valid.");
-            player_inputs.push(PlayerInput::new(self.frame, input));
+            let input =
+                *bytemuck::checked::try_from_bytes::<T::Input>(&self.bytes[start..end]).ok()?;
+            player_inputs.push(PlayerInput {
+                frame: self.frame,
+                input,
+            });
         }
-        player_inputs
+        Some(player_inputs)
     }
 }
 
+/// Splits the metadata of an input packet into the blocks of consecutive frames, each prefixed by its length.
+fn split_metadata_frames(mut metadata: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut frames = Vec::new();
+    while !metadata.is_empty() {
+        let (len, rest) = metadata.split_first_chunk::<2>()?;
+        let len = u16::from_le_bytes(*len) as usize;
+        if rest.len() < len {
+            return None;
+        }
+        let (frame, rest) = rest.split_at(len);
+        frames.push(frame);
+        metadata = rest;
+    }
+    Some(frames)
+}
+
+/// Splits the metadata block of a single frame into the metadata of each player. An empty block means no player attached metadata.
+fn split_metadata_players(mut block: &[u8], num_players: usize) -> Option<Vec<Vec<u8>>> {
+    if block.is_empty() {
+        return Some(vec![Vec::new(); num_players]);
+    }
+    let mut players = Vec::with_capacity(num_players);
+    for _ in 0..num_players {
+        let (len, rest) = block.split_first()?;
+        let len = *len as usize;
+        if len > MAX_INPUT_METADATA_SIZE || rest.len() < len {
+            return None;
+        }
+        let (data, rest) = rest.split_at(len);
+        players.push(data.to_vec());
+        block = rest;
+    }
+    block.is_empty().then_some(players)
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) enum Event<T>
 where
@@ -109,6 +232,7 @@ where
     Input {
         input: PlayerInput<T::Input>,
         player: PlayerHandle,
+        metadata: Vec<u8>,
     },
     /// The remote client has disconnected.
     Disconnected,
@@ -116,12 +240,39 @@ where
     NetworkInterrupted { disconnect_timeout: u128 },
     /// Sent only after a `NetworkInterrupted` event, if communication has resumed.
     NetworkResumed,
+    /// The remote client advances frames at a rate that deviates from the local fps by more than the tolerance.
+    FpsMismatch {
+        declared_fps: u32,
+        observed_fps: f64,
+    },
+    /// The remote client uses a different wire protocol version, so the session will not synchronize with it.
+    ProtocolMismatch { remote_version: u32 },
+    /// The handshake verifier rejected the payload of the remote client, so the session will not synchronize with it.
+    HandshakeRejected,
+    /// The remote client uses a different value for a setting all clients have to share, so the session will not synchronize with it.
+    HandshakeMismatch { mismatch: HandshakeMismatch },
+    /// The handshake made no progress within the synchronization timeout, after sending `attempts` sync requests.
+    SynchronizationFailed { attempts: u32 },
+    /// The connection state has changed since the last poll.
+    ConnectionStateChanged { state: ConnectionState },
+    /// The remote client sent an out-of-band message. This event will not be forwarded to the user as an event.
+    MessageReceived { data: Vec<u8> },
+    /// The remote client asks us to take over one of its players, from the given frame on or later.
+    HandOverRequested { handle: PlayerHandle, frame: Frame },
+    /// From the given frame on, the remote client sends inputs for the player (added) or no longer does.
+    HandleChanged {
+        handle: PlayerHandle,
+        frame: Frame,
+        added: bool,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq)]
 enum ProtocolState {
     Initializing,
     Synchronizing,
+    /// The handshake made no progress within the synchronization timeout. Nothing is sent until synchronization is retried.
+    SyncFailed,
     Running,
     Disconnected,
     Shutdown,
@@ -132,18 +283,41 @@ where
     T: Config,
 {
     num_players: usize,
+    /// The players the remote client sends inputs for, including those it only will from a later frame on.
     handles: Vec<PlayerHandle>,
+    /// The players the remote client sends inputs for at the frame after the last received input, in ascending order.
+    recv_handles: Vec<PlayerHandle>,
+    /// The frame from which on the remote client sends inputs for `recv_handles`, `None` from the start.
+    recv_handles_frame: Option<Frame>,
+    /// Upcoming changes of the players the remote client sends inputs for, as the frame they apply from and the players
+    /// from then on, ordered by frame.
+    handle_changes: VecDeque<(Frame, Vec<PlayerHandle>)>,
     send_queue: VecDeque<Message>,
     event_queue: VecDeque<Event<T>>,
 
     // state
     state: ProtocolState,
     sync_remaining_roundtrips: u32,
-    sync_random_requests: HashSet<u32>,
+    sync_random_requests: HashMap<u32, Instant>,
+    protocol_mismatch_sent: bool,
+    /// Set once the handshake verifier rejected the remote client or the remote client disagreed on a setting all clients
+    /// have to share, which disconnects it.
+    handshake_rejected: bool,
+    /// If set, synchronization fails once the handshake made no progress for this long.
+    sync_timeout: Option<Duration>,
+    /// The last time the handshake made progress, i.e. synchronization started or a valid sync reply arrived.
+    sync_last_progress: Instant,
+    /// The number of sync requests sent since synchronization started.
+    sync_attempts: u32,
+    /// The last time a sync request was sent. Other messages don't count, since a remote client that already
+    /// finished synchronizing keeps us busy answering its quality reports.
+    sync_last_request: Instant,
     running_last_quality_report: Instant,
     running_last_input_recv: Instant,
     disconnect_notify_sent: bool,
     disconnect_event_sent: bool,
+    /// The connection state reported to the session with the last [`Event::ConnectionStateChanged`].
+    reported_state: Option<ConnectionState>,
 
     // constants
     disconnect_timeout: Duration,
@@ -151,33 +325,119 @@ where
     shutdown_timeout: Instant,
     fps: usize,
     magic: u16,
+    delay_based: bool,
+    session_token: Option<[u8; 16]>,
+    fps_mismatch_tolerance: Option<f64>,
+    input_codec: Arc<dyn Codec>,
+    /// Whether the local client proposes a start time once synchronized, see `propose_start_time()`.
+    synchronized_start: bool,
+    /// The source of time for all timers of the endpoint.
+    clock: Arc<dyn Clock>,
+    /// If set, only this many leading bytes of every input are sent, see [`crate::VariableLengthInput`].
+    input_len: Option<fn(&T::Input) -> usize>,
+    local_player_ids: Vec<(PlayerHandle, PlayerId)>,
+    /// The application payload sent to the other client during synchronization.
+    handshake_payload: Vec<u8>,
+    /// If set, decides whether to synchronize with the other client, based on its handshake payload.
+    handshake_verifier: Option<Arc<dyn HandshakeVerifier<T::Address>>>,
+    /// The address the application discovered the local client is reachable at from outside, sent during synchronization.
+    external_addr: Option<SocketAddr>,
+    /// The frame the local client starts at, which the other client has to start at as well.
+    start_frame: Frame,
 
     // the other client
     peer_addr: T::Address,
+    /// Alternative addresses of the other client, raced against `peer_addr` during synchronization.
+    candidate_addrs: Vec<T::Address>,
+    /// The address the other client answered on first. All further messages are exchanged over it.
+    active_addr: Option<T::Address>,
     remote_magic: u16,
+    remote_delay_based: bool,
+    remote_fps: u32,
+    /// If set, the local input delay is offered to be split fairly with the remote input delay.
+    fair_input_delay: Option<usize>,
+    /// The input delay the remote client offered to split fairly, and the round trip time in ms it measured.
+    remote_fair_input_delay: Option<usize>,
+    remote_round_trip_time: u128,
+    /// Our share of the input delays of both clients, once the client with the higher magic number decided the split.
+    fair_input_delay_share: Option<usize>,
+    /// Our share of the input delays of both clients, as the remote client decided it.
+    remote_input_delay_split: Option<usize>,
+    /// Whether the remote client proposes a start time once synchronized.
+    remote_synchronized_start: bool,
+    remote_player_ids: Vec<(PlayerHandle, PlayerId)>,
+    remote_external_addr: Option<SocketAddr>,
     peer_connect_status: Vec<ConnectionStatus>,
 
     // input compression
     pending_output: VecDeque<InputBytes>,
+    /// Inputs from the given frame on are held back until the remote client acknowledged the reliable message with the
+    /// given sequence number, which announces that the players we send inputs for change at that frame. They are never sent
+    /// in the same packet as inputs before that frame.
+    input_holds: Vec<(Frame, u32)>,
     last_acked_input: InputBytes,
+    /// Every input packet carries at least this many of the newest frames, even if some of them were already acknowledged.
+    input_redundancy: usize,
+    /// Acked inputs before `last_acked_input` that are kept to be resent for the input redundancy, oldest first.
+    acked_inputs: VecDeque<InputBytes>,
+    /// Inputs before the latest change of our players are never resent, as they are for different players.
+    redundancy_floor: Option<Frame>,
+    /// The number of input packets sent and the number of frames of inputs they carried in total.
+    input_packets_sent: usize,
+    input_frames_sent: usize,
+    /// Byte buffers of acked inputs, reused for encoding new inputs to avoid allocating every frame.
+    input_bytes_pool: Vec<Vec<u8>>,
     max_prediction: usize,
-    recv_inputs: HashMap<Frame, InputBytes>,
+    recv_inputs: HashMap<Option<Frame>, InputBytes>,
 
     // time sync
     time_sync_layer: TimeSync,
     local_frame_advantage: i32,
     remote_frame_advantage: i32,
+    /// Start time and last received frame of the current fps measurement.
+    fps_window_start: Option<(Instant, Frame)>,
 
     // network
     stats_start_time: u128,
     packets_sent: usize,
     bytes_sent: usize,
+    packets_dropped: usize,
+    /// The outgoing bandwidth limit in bytes per second, if set.
+    bandwidth_limit: Option<usize>,
+    /// The bytes that can still be sent within the bandwidth limit. Negative once the limit has been exceeded.
+    send_budget: f64,
+    last_budget_update: Instant,
+    /// The number of input packets that were held back because the bandwidth limit was exceeded.
+    sends_deferred: usize,
+    /// The number of inputs added to the pending output since it was last sent.
+    unsent_inputs: usize,
+    /// Reused to measure the encoded size of outgoing messages.
+    encode_buffer: Vec<u8>,
+    /// The times in ms between sending recent inputs and receiving their acknowledgement.
+    input_latencies: VecDeque<u128>,
+    /// The estimated offset in ms of the remote wall clock to the local one and the round trip time of the sync roundtrip it was measured in.
+    clock_offset: Option<(i64, u128)>,
+    /// Messages that are resent until the remote client acknowledges them.
+    reliable: ReliableChannel,
+    /// The start time the remote client proposed for a synchronized start, in its clock.
+    remote_start_time: Option<u64>,
+    /// The one-way times in ms that recent input packets of the remote client took to arrive, in local time.
+    input_transit_times: VecDeque<u128>,
+    /// The median of the input transit times, updated with every sample. A single late packet would skew the estimate of the
+    /// remote frame otherwise.
+    input_transit_median: Option<u128>,
+    /// Holds up to twice the history size, so old samples can be dropped in bulk while the newest ones stay contiguous.
+    network_history: Vec<NetworkSample>,
+    /// The start of the current rate limit window and the packets received in it, per source address. Only addresses the
+    /// endpoint handles are counted, so there are at most as many as it has candidate addresses.
+    recv_windows: HashMap<T::Address, (Instant, u32)>,
     round_trip_time: u128,
     last_send_time: Instant,
     last_recv_time: Instant,
 
     // debug desync
     pub(crate) pending_checksums: HashMap<Frame, u128>,
+    checksum_history_size: usize,
     desync_detection: DesyncDetection,
 }
 
@@ -187,18 +447,48 @@ impl<T: Config> PartialEq for UdpProtocol<T> {
     }
 }
 
+/// The settings every endpoint of a session is created with, built by the [`SessionBuilder`].
+/// Settings that only some endpoints need are applied with setters afterwards.
+///
+/// [`SessionBuilder`]: crate::SessionBuilder
+#[derive(Clone)]
+pub(crate) struct EndpointSettings {
+    pub num_players: usize,
+    pub max_prediction: usize,
+    pub disconnect_timeout: Duration,
+    pub disconnect_notify_start: Duration,
+    pub fps: usize,
+    pub desync_detection: DesyncDetection,
+    pub delay_based: bool,
+    pub session_token: Option<[u8; 16]>,
+    pub fps_mismatch_tolerance: Option<f64>,
+    pub input_codec: Arc<dyn Codec>,
+    pub local_player_ids: Vec<(PlayerHandle, PlayerId)>,
+    pub clock: Arc<dyn Clock>,
+}
+
 impl<T: Config> UdpProtocol<T> {
     pub(crate) fn new(
         mut handles: Vec<PlayerHandle>,
         peer_addr: T::Address,
-        num_players: usize,
         local_players: usize,
-        max_prediction: usize,
-        disconnect_timeout: Duration,
-        disconnect_notify_start: Duration,
-        fps: usize,
-        desync_detection: DesyncDetection,
+        settings: EndpointSettings,
     ) -> Self {
+        let EndpointSettings {
+            num_players,
+            max_prediction,
+            disconnect_timeout,
+            disconnect_notify_start,
+            fps,
+            desync_detection,
+            delay_based,
+            session_token,
+            fps_mismatch_tolerance,
+            input_codec,
+            local_player_ids,
+            clock,
+        } = settings;
+
         let mut magic = rand::random::<u16>();
         while magic == 0 {
             magic = rand::random::<u16>();
@@ -215,38 +505,80 @@ impl<T: Config> UdpProtocol<T> {
 
         // received input history
         let mut recv_inputs = HashMap::new();
-        recv_inputs.insert(NULL_FRAME, InputBytes::zeroed::<T>(recv_player_num));
+        let now = clock::now(&*clock);
+        recv_inputs.insert(None, InputBytes::zeroed::<T>(recv_player_num, now));
 
         Self {
             num_players,
+            recv_handles: handles.clone(),
+            recv_handles_frame: None,
             handles,
+            handle_changes: VecDeque::new(),
             send_queue: VecDeque::new(),
             event_queue: VecDeque::new(),
 
             // state
             state: ProtocolState::Initializing,
             sync_remaining_roundtrips: NUM_SYNC_PACKETS,
-            sync_random_requests: HashSet::new(),
-            running_last_quality_report: Instant::now(),
-            running_last_input_recv: Instant::now(),
+            sync_random_requests: HashMap::new(),
+            protocol_mismatch_sent: false,
+            handshake_rejected: false,
+            sync_timeout: None,
+            sync_last_progress: now,
+            sync_attempts: 0,
+            sync_last_request: now,
+            running_last_quality_report: now,
+            running_last_input_recv: now,
             disconnect_notify_sent: false,
             disconnect_event_sent: false,
+            reported_state: None,
 
             // constants
             disconnect_timeout,
             disconnect_notify_start,
-            shutdown_timeout: Instant::now(),
+            shutdown_timeout: now,
             fps,
             magic,
+            delay_based,
+            session_token,
+            fps_mismatch_tolerance,
+            input_codec,
+            synchronized_start: false,
+            clock,
+            input_len: None,
+            local_player_ids,
+            handshake_payload: Vec::new(),
+            handshake_verifier: None,
+            external_addr: None,
+            start_frame: 0,
 
             // the other client
+            active_addr: Some(peer_addr.clone()),
             peer_addr,
+            candidate_addrs: Vec::new(),
             remote_magic: 0,
+            remote_delay_based: false,
+            remote_fps: 0,
+            fair_input_delay: None,
+            remote_fair_input_delay: None,
+            fair_input_delay_share: None,
+            remote_input_delay_split: None,
+            remote_round_trip_time: 0,
+            remote_synchronized_start: false,
+            remote_player_ids: Vec::new(),
+            remote_external_addr: None,
             peer_connect_status,
 
             // input compression
             pending_output: VecDeque::with_capacity(PENDING_OUTPUT_SIZE),
-            last_acked_input: InputBytes::zeroed::<T>(local_players),
+            input_holds: Vec::new(),
+            last_acked_input: InputBytes::zeroed::<T>(local_players, now),
+            input_redundancy: 1,
+            acked_inputs: VecDeque::new(),
+            redundancy_floor: None,
+            input_packets_sent: 0,
+            input_frames_sent: 0,
+            input_bytes_pool: Vec::with_capacity(PENDING_OUTPUT_SIZE),
             max_prediction,
             recv_inputs,
 
@@ -254,30 +586,79 @@ impl<T: Config> UdpProtocol<T> {
             time_sync_layer: TimeSync::new(),
             local_frame_advantage: 0,
             remote_frame_advantage: 0,
+            fps_window_start: None,
 
             // network
             stats_start_time: 0,
             packets_sent: 0,
+            packets_dropped: 0,
+            bandwidth_limit: None,
+            send_budget: 0.0,
+            last_budget_update: now,
+            sends_deferred: 0,
+            unsent_inputs: 0,
+            encode_buffer: Vec::new(),
+            input_latencies: VecDeque::with_capacity(INPUT_LATENCY_SAMPLES),
+            clock_offset: None,
+            reliable: ReliableChannel::new(),
+            remote_start_time: None,
+            input_transit_times: VecDeque::with_capacity(INPUT_LATENCY_SAMPLES),
+            input_transit_median: None,
+            network_history: Vec::with_capacity(2 * NETWORK_HISTORY_SIZE),
+            recv_windows: HashMap::new(),
             bytes_sent: 0,
             round_trip_time: 0,
-            last_send_time: Instant::now(),
-            last_recv_time: Instant::now(),
+            last_send_time: now,
+            last_recv_time: now,
 
             // debug desync
             pending_checksums: HashMap::new(),
+            checksum_history_size: DEFAULT_CHECKSUM_HISTORY_SIZE,
             desync_detection,
         }
     }
 
     pub(crate) fn update_local_frame_advantage(&mut self, local_frame: Frame) {
-        if local_frame == NULL_FRAME || self.last_recv_frame() == NULL_FRAME {
+        let Some(last_recv_frame) = self.last_recv_frame() else {
             return;
-        }
-        // Estimate which frame the other client is on by looking at the last frame they gave us plus some delta for the packet roundtrip time.
-        let ping = i32::try_from(self.round_trip_time / 2).expect("Ping is higher than i32::MAX");
-        let remote_frame = self.last_recv_frame() + ((ping * self.fps as i32) / 1000);
+        };
+        // Estimate which frame the other client is on by looking at the last frame they gave us plus some delta for the time the packet took to arrive.
+        // If the clock offset is known, the median of the measured transit times is used instead of assuming half of the roundtrip time.
+        let transit_time = self
+            .input_transit_median
+            .unwrap_or(self.round_trip_time / 2);
+        let transit_frames = transit_time.saturating_mul(self.fps as u128) / 1000;
+        let remote_frame =
+            last_recv_frame.saturating_add(Frame::try_from(transit_frames).unwrap_or(Frame::MAX));
         // Our frame "advantage" is how many frames behind the remote client we are. (It's an advantage because they will have to predict more often)
-        self.local_frame_advantage = remote_frame - local_frame;
+        self.local_frame_advantage = remote_frame.saturating_sub(local_frame);
+
+        // record at most one sample per frame
+        if self
+            .network_history
+            .last()
+            .is_some_and(|sample| sample.frame >= local_frame)
+        {
+            return;
+        }
+        if self.network_history.len() == 2 * NETWORK_HISTORY_SIZE {
+            self.network_history.drain(..NETWORK_HISTORY_SIZE);
+        }
+        self.network_history.push(NetworkSample {
+            frame: local_frame,
+            ping: self.round_trip_time,
+            local_frames_behind: self.local_frame_advantage,
+            remote_frames_behind: self.remote_frame_advantage,
+        });
+    }
+
+    /// Returns the most recent network samples, oldest first.
+    pub(crate) fn network_history(&self) -> &[NetworkSample] {
+        let start = self
+            .network_history
+            .len()
+            .saturating_sub(NETWORK_HISTORY_SIZE);
+        &self.network_history[start..]
     }
 
     pub(crate) fn network_stats(&self) -> Result<NetworkStats, GgrsError> {
@@ -285,7 +666,7 @@ impl<T: Config> UdpProtocol<T> {
             return Err(GgrsError::NotSynchronized);
         }
 
-        let now = millis_since_epoch();
+        let now = self.clock.millis_since_epoch();
         let seconds = (now - self.stats_start_time) / 1000;
         if seconds == 0 {
             return Err(GgrsError::NotSynchronized);
@@ -295,12 +676,28 @@ impl<T: Config> UdpProtocol<T> {
         let bps = total_bytes_sent / seconds as usize;
         //let upd_overhead = (self.packets_sent * UDP_HEADER_SIZE) / self.bytes_sent;
 
+        let mut input_latencies: Vec<u128> = self.input_latencies.iter().copied().collect();
+        input_latencies.sort_unstable();
+
         Ok(NetworkStats {
             ping: self.round_trip_time,
+            input_latency_p50: percentile(&input_latencies, 0.5),
+            input_latency_p90: percentile(&input_latencies, 0.9),
+            input_latency_p99: percentile(&input_latencies, 0.99),
+            input_transit_time: self.input_transit_median.unwrap_or(0),
+            clock_offset: self.clock_offset.map(|(offset, _)| offset),
             send_queue_len: self.pending_output.len(),
             kbps_sent: bps / 1024,
             local_frames_behind: self.local_frame_advantage,
             remote_frames_behind: self.remote_frame_advantage,
+            packets_dropped: self.packets_dropped,
+            sends_deferred: self.sends_deferred,
+            messages_dropped: self.reliable.messages_dropped(),
+            input_redundancy: (self.input_frames_sent + self.input_packets_sent / 2)
+                .checked_div(self.input_packets_sent)
+                .unwrap_or_default(),
+            // the session holds the backlog and fills it in
+            receive_backlog: 0,
         })
     }
 
@@ -308,6 +705,116 @@ impl<T: Config> UdpProtocol<T> {
         &self.handles
     }
 
+    /// Returns the players the remote client sends inputs for at the given frame, in ascending order.
+    fn handles_at(&self, frame: Frame) -> &[PlayerHandle] {
+        self.handle_changes
+            .iter()
+            .rev()
+            .find(|(change_frame, _)| *change_frame <= frame)
+            .map_or(&self.recv_handles, |(_, handles)| handles)
+    }
+
+    /// Adds the player to or removes it from the players the remote client sends inputs for, from `frame` on.
+    /// Does nothing if the change is already known.
+    pub(crate) fn change_handles(&mut self, handle: PlayerHandle, frame: Frame, added: bool) {
+        if self.handles_at(frame).contains(&handle) == added {
+            return;
+        }
+        let index = self
+            .handle_changes
+            .partition_point(|(change_frame, _)| *change_frame < frame);
+        if self
+            .handle_changes
+            .get(index)
+            .is_none_or(|(change_frame, _)| *change_frame != frame)
+        {
+            let handles = self.handles_at(frame).to_vec();
+            self.handle_changes.insert(index, (frame, handles));
+        }
+        // the change carries over to all later changes
+        for (_, handles) in self.handle_changes.range_mut(index..) {
+            handles.retain(|&h| h != handle);
+            if added {
+                handles.push(handle);
+                handles.sort_unstable();
+            }
+        }
+        self.update_handles();
+        self.commit_handle_changes();
+    }
+
+    /// Returns whether the remote client sends inputs for no players from frame `start` up to, but not including, frame `end`.
+    fn sends_no_inputs_between(&self, start: Frame, end: Frame) -> bool {
+        self.handles_at(start).is_empty()
+            && self
+                .handle_changes
+                .iter()
+                .filter(|(frame, _)| (start..end).contains(frame))
+                .all(|(_, handles)| handles.is_empty())
+    }
+
+    /// Applies the changes of the players of the remote client that take effect with the next input we receive.
+    fn commit_handle_changes(&mut self) {
+        let next_frame = self
+            .last_recv_frame()
+            .map_or(self.start_frame, |frame| frame.saturating_add(1));
+        let mut committed = false;
+        while self
+            .handle_changes
+            .front()
+            .is_some_and(|(change_frame, _)| *change_frame <= next_frame)
+        {
+            let (frame, handles) = self
+                .handle_changes
+                .pop_front()
+                .expect("Change should exist");
+            self.recv_handles = handles;
+            self.recv_handles_frame = Some(frame);
+            committed = true;
+        }
+        if committed {
+            self.update_handles();
+        }
+    }
+
+    fn update_handles(&mut self) {
+        let mut handles = self.recv_handles.clone();
+        for (_, changed) in &self.handle_changes {
+            handles.extend_from_slice(changed);
+        }
+        handles.sort_unstable();
+        handles.dedup();
+        self.handles = handles;
+    }
+
+    /// Returns the bytes held by inputs and messages that wait to be sent or acknowledged, or that wait to be processed.
+    pub(crate) fn pending_memory(&self) -> usize {
+        let messages: usize = self
+            .send_queue
+            .iter()
+            .map(|msg| {
+                std::mem::size_of::<Message>()
+                    + match &msg.body {
+                        MessageBody::Input(input) => {
+                            input.bytes.capacity()
+                                + input.metadata.capacity()
+                                + input.peer_connect_status.capacity()
+                                    * std::mem::size_of::<ConnectionStatus>()
+                        }
+                        _ => 0,
+                    }
+            })
+            .sum();
+        let inputs: usize = self
+            .pending_output
+            .iter()
+            .chain(self.recv_inputs.values())
+            .map(InputBytes::memory)
+            .sum();
+        let pool: usize = self.input_bytes_pool.iter().map(Vec::capacity).sum();
+        messages + inputs + pool + self.reliable.memory()
+    }
+
     pub(crate) fn is_synchronized(&self) -> bool {
         self.state == ProtocolState::Running
             || self.state == ProtocolState::Disconnected
@@ -319,7 +826,133 @@ impl<T: Config> UdpProtocol<T> {
     }
 
     pub(crate) fn is_handling_message(&self, addr: &T::Address) -> bool {
-        self.peer_addr == *addr
+        match &self.active_addr {
+            Some(active_addr) => active_addr == addr,
+            None => self.peer_addr == *addr || self.candidate_addrs.contains(addr),
+        }
+    }
+
+    /// Returns whether a datagram from `from` should be parsed and handed to this endpoint: it has to come from an address the
+    /// endpoint handles and stay within the rate limit of its source. Datagrams over the rate limit are counted as dropped.
+    /// Called before the datagram is parsed, so a flood costs as little as possible and does not crowd out other sources.
+    pub(crate) fn accept_packet(&mut self, from: &T::Address) -> bool {
+        if !self.is_handling_message(from) {
+            return false;
+        }
+        if !self.within_recv_rate_limit(from) {
+            self.packets_dropped += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Sets alternative addresses of the other client. Synchronization requests are sent to all of them and the first address
+    /// the other client answers on is used from then on.
+    pub(crate) fn set_candidate_addrs(&mut self, candidate_addrs: Vec<T::Address>) {
+        self.active_addr = candidate_addrs.is_empty().then(|| self.peer_addr.clone());
+        self.candidate_addrs = candidate_addrs;
+    }
+
+    /// Limits the outgoing bandwidth to the given number of bytes per second, including the IP and UDP headers.
+    pub(crate) fn set_bandwidth_limit(&mut self, bytes_per_second: usize) {
+        self.bandwidth_limit = Some(bytes_per_second);
+        self.send_budget = bytes_per_second as f64 * BANDWIDTH_BURST.as_secs_f64();
+        self.last_budget_update = self.now();
+    }
+
+    /// Refills the send budget for the time that has passed and returns true if the bandwidth limit is exceeded.
+    fn over_bandwidth_limit(&mut self) -> bool {
+        let Some(limit) = self.bandwidth_limit else {
+            return false;
+        };
+        let now = self.now();
+        let elapsed = now.duration_since(self.last_budget_update).as_secs_f64();
+        self.last_budget_update = now;
+        let capacity = limit as f64 * BANDWIDTH_BURST.as_secs_f64();
+        self.send_budget = (self.send_budget + elapsed * limit as f64).min(capacity);
+        self.send_budget < 0.0
+    }
+
+    /// Sends the given application payload to the remote client during synchronization.
+    pub(crate) fn set_handshake_payload(&mut self, payload: Vec<u8>) {
+        self.handshake_payload = payload;
+    }
+
+    /// Sends the given external address to the remote client during synchronization.
+    pub(crate) fn set_external_addr(&mut self, addr: Option<SocketAddr>) {
+        self.external_addr = addr;
+    }
+
+    pub(crate) fn set_start_frame(&mut self, frame: Frame) {
+        self.start_frame = frame;
+    }
+
+    /// Only concludes the synchronization if the verifier accepts the handshake payload of the remote client.
+    pub(crate) fn set_handshake_verifier(
+        &mut self,
+        verifier: Arc<dyn HandshakeVerifier<T::Address>>,
+    ) {
+        self.handshake_verifier = Some(verifier);
+    }
+
+    /// Keeps up to the given number of checksums received from the remote client.
+    pub(crate) fn set_checksum_history_size(&mut self, size: usize) {
+        self.checksum_history_size = size;
+    }
+
+    /// Offers the remote client to split the given local input delay and its own fairly between both clients.
+    pub(crate) fn set_fair_input_delay(&mut self, input_delay: usize) {
+        self.fair_input_delay = Some(input_delay);
+    }
+
+    /// Lets synchronization fail with [`Event::SynchronizationFailed`] if the handshake makes no progress for the given duration.
+    pub(crate) fn set_sync_timeout(&mut self, timeout: Duration) {
+        self.sync_timeout = Some(timeout);
+    }
+
+    /// Lets every input packet carry at least the given number of the newest frames, including acknowledged ones. This does not
+    /// help against packet loss, since all unacknowledged inputs are sent with every packet anyway. Resent inputs need to be kept
+    /// by the remote client to be decoded, so at most a prediction window of them is resent.
+    pub(crate) fn set_input_redundancy(&mut self, frames: usize) {
+        self.input_redundancy = frames.clamp(1, self.max_prediction.max(1));
+    }
+
+    /// Returns the current instant of the clock of the endpoint.
+    fn now(&self) -> Instant {
+        clock::now(&*self.clock)
+    }
+
+    /// Returns the current time of the clock of the endpoint in ms since the epoch.
+    pub(crate) fn millis_since_epoch(&self) -> u128 {
+        self.clock.millis_since_epoch()
+    }
+
+    /// Sends only the meaningful bytes of every input, as determined by `input_len`, each prefixed by its length.
+    pub(crate) fn set_input_len(&mut self, input_len: fn(&T::Input) -> usize) {
+        self.input_len = Some(input_len);
+    }
+
+    /// Returns the address messages are exchanged over, or `None` if the other client has not answered on any candidate yet.
+    pub(crate) fn active_addr(&self) -> Option<&T::Address> {
+        self.active_addr.as_ref()
+    }
+
+    pub(crate) fn connection_state(&self) -> ConnectionState {
+        match self.state {
+            ProtocolState::Initializing | ProtocolState::Synchronizing => {
+                let completed = NUM_SYNC_PACKETS - self.sync_remaining_roundtrips;
+                ConnectionState::Synchronizing {
+                    progress: completed as f32 / NUM_SYNC_PACKETS as f32,
+                }
+            }
+            ProtocolState::SyncFailed => ConnectionState::SynchronizationFailed,
+            ProtocolState::Running if self.disconnect_event_sent => ConnectionState::Disconnected,
+            ProtocolState::Running if self.disconnect_notify_sent => ConnectionState::Interrupted {
+                elapsed: self.now().saturating_duration_since(self.last_recv_time),
+            },
+            ProtocolState::Running => ConnectionState::Running,
+            ProtocolState::Disconnected | ProtocolState::Shutdown => ConnectionState::Disconnected,
+        }
     }
 
     pub(crate) fn peer_connect_status(&self, handle: PlayerHandle) -> ConnectionStatus {
@@ -333,31 +966,133 @@ impl<T: Config> UdpProtocol<T> {
 
         self.state = ProtocolState::Disconnected;
         // schedule the timeout which will lead to shutdown
-        self.shutdown_timeout = Instant::now().add(Duration::from_millis(UDP_SHUTDOWN_TIMER))
+        self.shutdown_timeout = self.now().add(Duration::from_millis(UDP_SHUTDOWN_TIMER))
+    }
+
+    /// Asks the remote client to disconnect, as the local session is closed and will not answer anymore.
+    pub(crate) fn close(&mut self) {
+        if self.state == ProtocolState::Shutdown {
+            return;
+        }
+        self.send_disconnect_notice();
+        self.disconnect();
     }
 
     pub(crate) fn synchronize(&mut self) {
         assert_eq!(self.state, ProtocolState::Initializing);
         self.state = ProtocolState::Synchronizing;
         self.sync_remaining_roundtrips = NUM_SYNC_PACKETS;
-        self.stats_start_time = millis_since_epoch();
+        self.sync_last_progress = self.now();
+        self.sync_attempts = 0;
+        self.stats_start_time = self.clock.millis_since_epoch();
         self.send_sync_request();
     }
 
+    /// Starts over a synchronization that failed with [`Event::SynchronizationFailed`]. Returns `false` if it has not failed.
+    pub(crate) fn retry_synchronization(&mut self) -> bool {
+        if self.state != ProtocolState::SyncFailed {
+            return false;
+        }
+        self.state = ProtocolState::Initializing;
+        self.sync_random_requests.clear();
+        self.synchronize();
+        true
+    }
+
     pub(crate) fn average_frame_advantage(&self) -> i32 {
         self.time_sync_layer.average_frame_advantage()
     }
 
+    pub(crate) fn fractional_frame_advantage(&self) -> f32 {
+        self.time_sync_layer.fractional_frame_advantage()
+    }
+
+    /// Returns true if both this client and the remote client requested a delay-based session during synchronization.
+    pub(crate) fn delay_based_agreed(&self) -> bool {
+        self.delay_based && self.remote_delay_based
+    }
+
+    /// Splits the input delay of both clients, if both offered to split it fairly during synchronization. The delay is split
+    /// proportionally to the one-way latency each client measured, i.e. half its round trip time, so the client with the
+    /// slower connection delays its inputs longer and neither client gains a frame advantage. Both clients measure their
+    /// round trip times at different moments, so only the client with the higher magic number splits the delay and sends the
+    /// share of the other client, which waits for it. With equal magic numbers, both clients take half of the total.
+    pub(crate) fn split_fair_input_delay(&mut self) {
+        let (Some(local), Some(remote)) = (self.fair_input_delay, self.remote_fair_input_delay)
+        else {
+            return;
+        };
+        if self.fair_input_delay_share.is_some() {
+            return;
+        }
+        let total = local + remote;
+        match self.magic.cmp(&self.remote_magic) {
+            Ordering::Greater => {
+                let share = fair_share(total, self.round_trip_time, self.remote_round_trip_time);
+                self.fair_input_delay_share = Some(share);
+                // nothing but the start time is sent over the reliable channel before, so the split cannot be rejected
+                let remote_share = u32::try_from(total - share).unwrap_or(u32::MAX);
+                self.send_reliable(ReliablePayload::InputDelay(remote_share));
+            }
+            Ordering::Equal => self.fair_input_delay_share = Some(total / 2),
+            // never take more than both clients offered
+            Ordering::Less => {
+                self.fair_input_delay_share =
+                    self.remote_input_delay_split.map(|delay| delay.min(total))
+            }
+        }
+    }
+
+    /// Returns our share of the input delay of both clients, once it has been split, see `split_fair_input_delay()`.
+    pub(crate) fn fair_input_delay_share(&self) -> Option<usize> {
+        self.fair_input_delay_share
+    }
+
+    /// Returns true if both clients offered to split their input delay fairly, but we still wait for the split.
+    pub(crate) fn fair_input_delay_pending(&self) -> bool {
+        self.fair_input_delay.is_some()
+            && self.remote_fair_input_delay.is_some()
+            && self.fair_input_delay_share.is_none()
+    }
+
+    /// Returns the measured round trip time in frames, rounded up.
+    pub(crate) fn round_trip_frames(&self) -> usize {
+        let frames = self
+            .round_trip_time
+            .saturating_mul(self.fps as u128)
+            .div_ceil(1000);
+        usize::try_from(frames).unwrap_or(usize::MAX)
+    }
+
+    /// Returns the persistent ids the remote client sent for its players during synchronization.
+    pub(crate) fn remote_player_ids(&self) -> &[(PlayerHandle, PlayerId)] {
+        &self.remote_player_ids
+    }
+
+    /// Returns the external address the remote client sent during synchronization, if it knows one.
+    pub(crate) fn remote_external_addr(&self) -> Option<SocketAddr> {
+        self.remote_external_addr
+    }
+
     pub(crate) fn peer_addr(&self) -> T::Address {
         self.peer_addr.clone()
     }
 
-    pub(crate) fn poll(&mut self, connect_status: &[ConnectionStatus]) -> Drain<Event<T>> {
-        let now = Instant::now();
+    pub(crate) fn poll(&mut self, connect_status: &[ConnectionStatus]) -> Drain<'_, Event<T>> {
+        let now = self.now();
         match self.state {
-            ProtocolState::Synchronizing => {
-                // some time has passed, let us send another sync request
-                if self.last_send_time + SYNC_RETRY_INTERVAL < now {
+            ProtocolState::Synchronizing if !self.handshake_rejected => {
+                // give up if the handshake made no progress for too long, the application can retry
+                if self
+                    .sync_timeout
+                    .is_some_and(|timeout| self.sync_last_progress + timeout < now)
+                {
+                    self.state = ProtocolState::SyncFailed;
+                    self.event_queue.push_back(Event::SynchronizationFailed {
+                        attempts: self.sync_attempts,
+                    });
+                } else if self.sync_last_request + SYNC_RETRY_INTERVAL < now {
+                    // some time has passed, let us send another sync request
                     self.send_sync_request();
                 }
             }
@@ -365,7 +1100,7 @@ impl<T: Config> UdpProtocol<T> {
                 // resend pending inputs, if some time has passed without sending or receiving inputs
                 if self.running_last_input_recv + RUNNING_RETRY_INTERVAL < now {
                     self.send_pending_output(connect_status);
-                    self.running_last_input_recv = Instant::now();
+                    self.running_last_input_recv = self.now();
                 }
 
                 // periodically send a quality report
@@ -373,6 +1108,16 @@ impl<T: Config> UdpProtocol<T> {
                     self.send_quality_report();
                 }
 
+                // periodically compare the frame rate of the remote client with our own
+                if let Some(tolerance) = self.fps_mismatch_tolerance {
+                    self.check_fps_mismatch(tolerance, now);
+                }
+
+                // resend reliable messages the remote client has not acknowledged yet
+                for body in self.reliable.resends(now) {
+                    self.queue_message(MessageBody::Reliable(body));
+                }
+
                 // send keep alive packet if we didn't send a packet for some time
                 if self.last_send_time + KEEP_ALIVE_INTERVAL < now {
                     self.send_keep_alive();
@@ -398,23 +1143,60 @@ impl<T: Config> UdpProtocol<T> {
                 }
             }
             ProtocolState::Disconnected => {
-                if self.shutdown_timeout < Instant::now() {
+                if self.shutdown_timeout < self.now() {
                     self.state = ProtocolState::Shutdown;
                 }
             }
-            ProtocolState::Initializing | ProtocolState::Shutdown => (),
+            ProtocolState::Initializing
+            | ProtocolState::Synchronizing
+            | ProtocolState::SyncFailed
+            | ProtocolState::Shutdown => (),
+        }
+
+        // an interruption is only reported when it starts, not every time its duration grows
+        let state = self.connection_state();
+        let changed = match (self.reported_state, state) {
+            (Some(ConnectionState::Interrupted { .. }), ConnectionState::Interrupted { .. }) => {
+                false
+            }
+            (reported, state) => reported != Some(state),
+        };
+        if changed {
+            self.reported_state = Some(state);
+            self.event_queue
+                .push_back(Event::ConnectionStateChanged { state });
         }
         self.event_queue.drain(..)
     }
 
-    fn pop_pending_output(&mut self, ack_frame: Frame) {
+    fn pop_pending_output(&mut self, ack_frame: Option<Frame>) {
         while !self.pending_output.is_empty() {
             if let Some(input) = self.pending_output.front() {
                 if input.frame <= ack_frame {
-                    self.last_acked_input = self
+                    let acked_input = self
                         .pending_output
                         .pop_front()
                         .expect("Expected input to exist");
+                    if self.input_latencies.len() == INPUT_LATENCY_SAMPLES {
+                        self.input_latencies.pop_front();
+                    }
+                    let latency = self.now().saturating_duration_since(acked_input.created);
+                    self.input_latencies.push_back(latency.as_millis());
+                    let old_acked_input =
+                        std::mem::replace(&mut self.last_acked_input, acked_input);
+                    // keep the acked inputs that might still be resent
+                    if self.input_redundancy > 1 && old_acked_input.frame.is_some() {
+                        self.acked_inputs.push_back(old_acked_input);
+                        if self.acked_inputs.len() >= self.input_redundancy {
+                            let oldest = self
+                                .acked_inputs
+                                .pop_front()
+                                .expect("Expected input to exist");
+                            self.input_bytes_pool.push(oldest.bytes);
+                        }
+                    } else {
+                        self.input_bytes_pool.push(old_acked_input.bytes);
+                    }
                 } else {
                     break;
                 }
@@ -426,76 +1208,284 @@ impl<T: Config> UdpProtocol<T> {
      *  SENDING MESSAGES
      */
 
-    pub(crate) fn send_all_messages(
-        &mut self,
-        socket: &mut Box<dyn NonBlockingSocket<T::Address>>,
-    ) {
+    /// Exchanges all further messages with the other client over the given address, for example after its network changed.
+    pub(crate) fn remap_addr(&mut self, addr: T::Address) {
+        self.candidate_addrs.clear();
+        self.active_addr = Some(addr);
+    }
+
+    pub(crate) fn send_all_messages(&mut self, socket: &mut SessionSocket<T>) {
         if self.state == ProtocolState::Shutdown {
             self.send_queue.drain(..);
             return;
         }
 
         for msg in self.send_queue.drain(..) {
-            socket.send_to(&msg, &self.peer_addr);
+            match &self.active_addr {
+                Some(addr) => socket.send_to(&msg, addr),
+                // race all candidates until the other client answers on one of them
+                None => {
+                    socket.send_to(&msg, &self.peer_addr);
+                    for addr in self.candidate_addrs.iter() {
+                        socket.send_to(&msg, addr);
+                    }
+                }
+            }
         }
     }
 
     pub(crate) fn send_input(
         &mut self,
         inputs: &HashMap<PlayerHandle, PlayerInput<T::Input>>,
+        metadata: &HashMap<PlayerHandle, Vec<u8>>,
         connect_status: &[ConnectionStatus],
     ) {
         if self.state != ProtocolState::Running {
             return;
         }
 
-        let endpoint_data = InputBytes::from_inputs::<T>(self.num_players, inputs);
+        self.push_input(inputs, metadata);
+        self.send_pending_output(connect_status);
+    }
+
+    /// Adds the inputs to the pending output without sending them, so multiple frames can be sent in a single packet later.
+    pub(crate) fn push_input(
+        &mut self,
+        inputs: &HashMap<PlayerHandle, PlayerInput<T::Input>>,
+        metadata: &HashMap<PlayerHandle, Vec<u8>>,
+    ) {
+        // a client without local players, like a spectator running a P2P session, has no inputs to send
+        if self.state != ProtocolState::Running || inputs.is_empty() {
+            return;
+        }
+
+        let buffer = self.input_bytes_pool.pop().unwrap_or_default();
+        let endpoint_data =
+            InputBytes::from_inputs::<T>(self.num_players, inputs, metadata, buffer, self.now());
+        let frame = endpoint_data
+            .frame
+            .expect("UdpProtocol::push_input(): The inputs belong to no frame");
+
+        // after a time without local players, our inputs start over at a later frame, encoded against blank inputs. The remote
+        // client waited for our inputs before that time before it handed us a player again, so none of them are missing.
+        let last_frame = self
+            .pending_output
+            .back()
+            .map_or(self.last_acked_input.frame, |input| input.frame);
+        if last_frame.is_some_and(|last_frame| frame > last_frame + 1) {
+            let blank = InputBytes::zeroed::<T>(0, self.now());
+            let last_acked_input = std::mem::replace(&mut self.last_acked_input, blank);
+            self.input_bytes_pool.push(last_acked_input.bytes);
+            let skipped = self
+                .pending_output
+                .drain(..)
+                .chain(self.acked_inputs.drain(..));
+            self.input_bytes_pool
+                .extend(skipped.map(|input| input.bytes));
+        }
 
         // register the input and advantages in the time sync layer
         self.time_sync_layer.advance_frame(
-            endpoint_data.frame,
+            frame,
             self.local_frame_advantage,
             self.remote_frame_advantage,
         );
 
         self.pending_output.push_back(endpoint_data);
+        self.unsent_inputs += 1;
 
         // we should never have so much pending input for a remote player (if they didn't ack, we should stop at MAX_PREDICTION_THRESHOLD)
         // this is a spectator that didn't ack our input, we just disconnect them
         if self.pending_output.len() > PENDING_OUTPUT_SIZE {
             self.event_queue.push_back(Event::Disconnected);
         }
-
-        self.send_pending_output(connect_status);
     }
 
-    fn send_pending_output(&mut self, connect_status: &[ConnectionStatus]) {
+    pub(crate) fn send_pending_output(&mut self, connect_status: &[ConnectionStatus]) {
+        // over the bandwidth limit, new inputs are sent later in a single packet together with the next ones and inputs
+        // that were already sent are not resent. Hold back at most a prediction window worth of inputs, so the remote
+        // client does not stall waiting for them.
+        if !self.pending_output.is_empty()
+            && self.unsent_inputs < self.max_prediction
+            && self.over_bandwidth_limit()
+        {
+            self.sends_deferred += 1;
+            return;
+        }
+        self.unsent_inputs = 0;
+
         let mut body = Input::default();
 
+        // a change of our players only matters until the remote client knows about it and acked all inputs before it
+        let reliable = &self.reliable;
+        let last_acked_frame = self.last_acked_input.frame;
+        self.input_holds.retain(|&(frame, seq)| {
+            !reliable.is_acked(seq) || last_acked_frame.is_none_or(|acked| frame > acked + 1)
+        });
+        let holds: Vec<(Frame, bool)> = self
+            .input_holds
+            .iter()
+            .map(|&(frame, seq)| (frame, reliable.is_acked(seq)))
+            .collect();
+
         if let Some(input) = self.pending_output.front() {
-            assert!(
-                self.last_acked_input.frame == NULL_FRAME
-                    || self.last_acked_input.frame + 1 == input.frame
-            );
-            body.start_frame = input.frame;
-
-            // encode all pending inputs to a byte buffer
-            body.bytes = encode(
-                &self.last_acked_input.bytes,
-                self.pending_output.iter().map(|gi| &gi.bytes),
-            );
-
-            // the byte buffer should not exceed a certain size to guarantee a maximum UDP packet size
+            let start_frame = input
+                .frame
+                .expect("UdpProtocol::send_pending_output(): A pending input belongs to no frame");
+            assert!(self
+                .last_acked_input
+                .frame
+                .is_none_or(|acked| acked + 1 == start_frame));
+
+            // a packet only holds inputs for the same players, so inputs after a change of our players start a new packet.
+            // They wait until the remote client knows about the change.
+            let input_size = input.bytes.len();
+            let num_inputs = self
+                .pending_output
+                .iter()
+                .take_while(|gi| {
+                    holds.iter().all(|&(frame, acked)| {
+                        gi.frame < Some(frame) || (acked && frame <= start_frame)
+                    })
+                })
+                .count();
+            if num_inputs == 0 {
+                return;
+            }
+            let inputs = self.pending_output.range(..num_inputs);
+
+            // for the input redundancy, the newest acked inputs are resent in front of the pending ones. Each packet is encoded
+            // against the input before its first one, so the oldest acked input is never resent.
+            let acked: Vec<&InputBytes> = self
+                .acked_inputs
+                .iter()
+                .chain(std::iter::once(&self.last_acked_input))
+                .collect();
+            let redundancy_floor = self.redundancy_floor;
+            let mut num_resent = acked
+                .iter()
+                .skip(1)
+                .rev()
+                .take(self.input_redundancy.saturating_sub(num_inputs))
+                .take_while(|gi| gi.frame >= redundancy_floor && gi.bytes.len() == input_size)
+                .count();
+
+            // the metadata of the newest frames is attached, the metadata of older frames has been sent before. The amount of
+            // metadata bytes of the newest `n` frames, each behind a two byte length.
+            let has_metadata = inputs.clone().any(|gi| !gi.metadata.is_empty());
+            let metadata_frames = |budget: usize| {
+                let mut size = 0;
+                inputs
+                    .clone()
+                    .rev()
+                    .take_while(|gi| {
+                        size += 2 + gi.metadata.len();
+                        size <= budget
+                    })
+                    .count()
+            };
+            let mut num_metadata = if has_metadata {
+                metadata_frames(MAX_METADATA_PAYLOAD).max(1)
+            } else {
+                0
+            };
+            let metadata_size: usize = inputs
+                .clone()
+                .skip(num_inputs - num_metadata)
+                .map(|gi| 2 + gi.metadata.len())
+                .sum();
+
+            // encode all inputs to a byte buffer. The byte buffer and the metadata should not exceed a certain size to guarantee
+            // a maximum UDP packet size, so the acked inputs are left out if they don't fit.
+            let input_budget = MAX_PAYLOAD - metadata_size;
+            loop {
+                let resent = &acked[acked.len() - num_resent..];
+                let reference = acked[acked.len() - num_resent - 1];
+                body.start_frame = resent
+                    .first()
+                    .and_then(|gi| gi.frame)
+                    .unwrap_or(start_frame);
+                body.bytes = self.encode_inputs(
+                    reference,
+                    input_size,
+                    resent.iter().copied().chain(inputs.clone()),
+                );
+                if body.bytes.len() <= input_budget || num_resent == 0 {
+                    break;
+                }
+                num_resent = 0;
+            }
             assert!(body.bytes.len() <= MAX_PAYLOAD);
+            // if the pending inputs alone leave no room for all of the metadata, less of it is sent
+            if body.bytes.len() > input_budget {
+                num_metadata = metadata_frames(MAX_PAYLOAD - body.bytes.len());
+            }
+            body.bytes = self.input_codec.compress(&body.bytes);
+
+            if num_metadata > 0 {
+                let first = num_inputs - num_metadata;
+                body.metadata_frame = self.pending_output[first].frame;
+                for gi in inputs.clone().skip(first) {
+                    body.metadata
+                        .extend_from_slice(&(gi.metadata.len() as u16).to_le_bytes());
+                    body.metadata.extend_from_slice(&gi.metadata);
+                }
+            }
 
             body.ack_frame = self.last_recv_frame();
+            body.sent_at = self.clock.millis_since_epoch() as u64;
             body.disconnect_requested = self.state == ProtocolState::Disconnected;
             body.peer_connect_status = connect_status.to_owned();
 
+            self.input_packets_sent += 1;
+            self.input_frames_sent += num_resent + num_inputs;
             self.queue_message(MessageBody::Input(body));
         }
     }
 
+    /// Encodes the inputs, which are all of the given size, against the reference input before them.
+    fn encode_inputs<'a>(
+        &self,
+        reference: &InputBytes,
+        input_size: usize,
+        inputs: impl Iterator<Item = &'a InputBytes>,
+    ) -> Vec<u8> {
+        match self.input_len {
+            Some(input_len) => {
+                let size = std::mem::size_of::<T::Input>();
+                encode_prefixed(
+                    inputs
+                        .flat_map(|gi| gi.bytes.chunks_exact(size))
+                        .map(|bytes| {
+                            let len = bytemuck::checked::try_pod_read_unaligned::<T::Input>(bytes)
+                                .map_or(size, |input| input_len(&input).min(size));
+                            debug_assert!(
+                                bytes[len..].iter().all(|&byte| byte == 0),
+                                "VariableLengthInput::input_len() leaves out bytes that are not zero, remote clients would see zeros there"
+                            );
+                            &bytes[..len]
+                        }),
+                )
+            }
+            None => {
+                // after a change of our players, the inputs are encoded against blank inputs for the new players
+                let blank;
+                let reference = if reference.bytes.len() == input_size {
+                    &reference.bytes
+                } else {
+                    blank = vec![0; input_size];
+                    &blank
+                };
+                match bitplane_players::<T>(input_size) {
+                    Some(players) => {
+                        encode_bitplanes(reference, inputs.map(|gi| &gi.bytes), players)
+                    }
+                    None => encode(reference, inputs.map(|gi| &gi.bytes)),
+                }
+            }
+        }
+    }
+
     fn send_input_ack(&mut self) {
         let body = InputAck {
             ack_frame: self.last_recv_frame(),
@@ -504,25 +1494,102 @@ impl<T: Config> UdpProtocol<T> {
         self.queue_message(MessageBody::InputAck(body));
     }
 
+    pub(crate) fn set_synchronized_start(&mut self, enabled: bool) {
+        self.synchronized_start = enabled;
+    }
+
+    /// Returns whether the remote client proposes a start time once synchronized, as it announced during synchronization.
+    pub(crate) fn remote_synchronized_start(&self) -> bool {
+        self.remote_synchronized_start
+    }
+
+    /// Proposes a start time in ms since the epoch for a synchronized start to the remote client.
+    pub(crate) fn propose_start_time(&mut self, time: u64) {
+        // nothing else is sent over the reliable channel before, so the proposal cannot be rejected
+        self.send_reliable(ReliablePayload::StartTime(time));
+    }
+
+    /// Sends an out-of-band message of the application to the remote client. Returns `false` if too many messages are
+    /// still waiting for an acknowledgement.
+    pub(crate) fn send_message(&mut self, data: Vec<u8>) -> bool {
+        self.send_reliable(ReliablePayload::User(data)).is_some()
+    }
+
+    /// Asks the remote client to take over one of our players from `frame` on or later. Returns `false` if too many messages
+    /// are still waiting for an acknowledgement.
+    pub(crate) fn request_hand_over(&mut self, handle: PlayerHandle, frame: Frame) -> bool {
+        let payload = ReliablePayload::HandOverRequest {
+            handle: handle as u64,
+            frame,
+        };
+        self.send_reliable(payload).is_some()
+    }
+
+    /// Tells the remote client that from `frame` on, the inputs we send include the player or no longer do. Inputs from that
+    /// frame on are held back until the remote client acknowledged the change, so it decodes them for the right players.
+    /// Returns `false` if too many messages are still waiting for an acknowledgement.
+    pub(crate) fn announce_handle_change(
+        &mut self,
+        handle: PlayerHandle,
+        frame: Frame,
+        added: bool,
+    ) -> bool {
+        let payload = ReliablePayload::HandleChange {
+            handle: handle as u64,
+            frame,
+            added,
+        };
+        match self.send_reliable(payload) {
+            Some(seq) => {
+                self.input_holds.push((frame, seq));
+                self.redundancy_floor = self.redundancy_floor.max(Some(frame));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the start time the remote client proposed, converted to ms since the epoch in our clock.
+    /// `None` if the remote client has not proposed one yet or the clock offset is unknown.
+    pub(crate) fn remote_start_time(&self) -> Option<u128> {
+        let (offset, _) = self.clock_offset?;
+        let time = self.remote_start_time? as i64 - offset;
+        Some(time.max(0) as u128)
+    }
+
+    /// Sends the payload over the reliable channel and returns its sequence number, or `None` if too many messages are still
+    /// waiting for an acknowledgement.
+    fn send_reliable(&mut self, payload: ReliablePayload) -> Option<u32> {
+        let now = self.now();
+        let body = self.reliable.send(payload, now)?;
+        let seq = body.seq;
+        self.queue_message(MessageBody::Reliable(body));
+        Some(seq)
+    }
+
     fn send_keep_alive(&mut self) {
         self.queue_message(MessageBody::KeepAlive);
     }
 
     fn send_sync_request(&mut self) {
         let random_number = rand::random::<u32>();
-        self.sync_random_requests.insert(random_number);
+        self.sync_random_requests.insert(random_number, self.now());
+        self.sync_attempts += 1;
+        self.sync_last_request = self.now();
         let body = SyncRequest {
             random_request: random_number,
+            protocol_version: PROTOCOL_VERSION,
         };
         self.queue_message(MessageBody::SyncRequest(body));
     }
 
     fn send_quality_report(&mut self) {
-        self.running_last_quality_report = Instant::now();
+        self.running_last_quality_report = self.now();
         let body = QualityReport {
-            frame_advantage: i8::try_from(self.local_frame_advantage)
-                .expect("local_frame_advantage bigger than i8::MAX"),
-            ping: millis_since_epoch(),
+            frame_advantage: self
+                .local_frame_advantage
+                .clamp(i8::MIN as i32, i8::MAX as i32) as i8,
+            ping: self.clock.millis_since_epoch(),
         };
 
         self.queue_message(MessageBody::QualityReport(body));
@@ -530,12 +1597,20 @@ impl<T: Config> UdpProtocol<T> {
 
     fn queue_message(&mut self, body: MessageBody) {
         // set the header
-        let header = MessageHeader { magic: self.magic };
+        let header = MessageHeader {
+            magic: self.magic,
+            session_token: self.session_token,
+        };
         let msg = Message { header, body };
 
+        self.encode_buffer.clear();
+        msg.encode_into(&mut self.encode_buffer);
         self.packets_sent += 1;
-        self.last_send_time = Instant::now();
-        self.bytes_sent += std::mem::size_of_val(&msg);
+        self.last_send_time = self.now();
+        self.bytes_sent += self.encode_buffer.len();
+        if self.bandwidth_limit.is_some() {
+            self.send_budget -= (self.encode_buffer.len() + UDP_HEADER_SIZE) as f64;
+        }
 
         // add the packet to the back of the send queue
         self.send_queue.push_back(msg);
@@ -545,19 +1620,42 @@ impl<T: Config> UdpProtocol<T> {
      *  RECEIVING MESSAGES
      */
 
-    pub(crate) fn handle_message(&mut self, msg: &Message) {
+    /// Handles a message received from `from`. `received_at` is the time in ms since the epoch the session took the message from
+    /// the socket, which is earlier than now if it waited in the receive backlog, so that wait is not measured as latency.
+    pub(crate) fn handle_message(&mut self, from: &T::Address, msg: &Message, received_at: u128) {
         // don't handle messages if shutdown
         if self.state == ProtocolState::Shutdown {
             return;
         }
 
+        // ignore candidate addresses the other client did not answer on first
+        if !self.is_handling_message(from) {
+            return;
+        }
+
+        // filter packets of strangers, before touching any state
+        if msg.header.session_token != self.session_token {
+            return;
+        }
+
         // filter packets that don't match the magic if we have set it already
         if self.remote_magic != 0 && msg.header.magic != self.remote_magic {
             return;
         }
 
+        // a rejected remote client is only told again to disconnect
+        if self.handshake_rejected {
+            if matches!(
+                msg.body,
+                MessageBody::SyncRequest(_) | MessageBody::Input(_)
+            ) {
+                self.send_disconnect_notice();
+            }
+            return;
+        }
+
         // update time when we last received packages
-        self.last_recv_time = Instant::now();
+        self.last_recv_time = self.now();
 
         // if the connection has been marked as interrupted, send an event to signal we are receiving again
         if self.disconnect_notify_sent && self.state == ProtocolState::Running {
@@ -568,36 +1666,197 @@ impl<T: Config> UdpProtocol<T> {
         // handle the message
         match &msg.body {
             MessageBody::SyncRequest(body) => self.on_sync_request(*body),
-            MessageBody::SyncReply(body) => self.on_sync_reply(msg.header, *body),
-            MessageBody::Input(body) => self.on_input(body),
+            MessageBody::SyncReply(body) => {
+                // lock onto the first candidate address with a valid reply
+                if self.active_addr.is_none()
+                    && self.sync_random_requests.contains_key(&body.random_reply)
+                {
+                    self.active_addr = Some(from.clone());
+                }
+                self.on_sync_reply(msg.header, body, received_at)
+            }
+            MessageBody::Input(body) => self.on_input(body, received_at),
             MessageBody::InputAck(body) => self.on_input_ack(*body),
             MessageBody::QualityReport(body) => self.on_quality_report(body),
-            MessageBody::QualityReply(body) => self.on_quality_reply(body),
+            MessageBody::QualityReply(body) => self.on_quality_reply(body, received_at),
             MessageBody::ChecksumReport(body) => self.on_checksum_report(body),
             MessageBody::KeepAlive => (),
+            MessageBody::Reliable(body) => self.on_reliable(body),
+            MessageBody::ReliableAck(body) => self.reliable.on_ack(*body),
+            // late packets of a connection probe that ran on the same socket
+            MessageBody::MtuProbe(_) | MessageBody::MtuProbeAck(_) => (),
+        }
+    }
+
+    fn on_reliable(&mut self, body: &Reliable) {
+        for payload in self.reliable.on_message(body) {
+            match payload {
+                ReliablePayload::StartTime(time) => self.remote_start_time = Some(time),
+                // the split can arrive before we finished synchronizing, so it is only checked once we split ourselves
+                ReliablePayload::InputDelay(delay) => {
+                    self.remote_input_delay_split = Some(delay as usize)
+                }
+                ReliablePayload::User(data) => {
+                    self.event_queue.push_back(Event::MessageReceived { data })
+                }
+                // requests and changes of players that do not exist are malformed and dropped
+                ReliablePayload::HandOverRequest { handle, frame } => {
+                    if let Some(handle) = self.player_handle(handle) {
+                        self.event_queue
+                            .push_back(Event::HandOverRequested { handle, frame });
+                    }
+                }
+                // the acknowledgement below confirms to the remote client that inputs after the change can be decoded
+                ReliablePayload::HandleChange {
+                    handle,
+                    frame,
+                    added,
+                } => {
+                    if let Some(handle) = self.player_handle(handle) {
+                        self.change_handles(handle, frame, added);
+                        self.event_queue.push_back(Event::HandleChanged {
+                            handle,
+                            frame,
+                            added,
+                        });
+                    }
+                }
+            }
+        }
+        // acknowledge duplicates as well, since the previous acknowledgement might have been lost
+        let ack = self.reliable.ack();
+        self.queue_message(MessageBody::ReliableAck(ack));
+    }
+
+    /// Converts a player handle received from the remote client, `None` if there is no such player.
+    fn player_handle(&self, handle: u64) -> Option<PlayerHandle> {
+        usize::try_from(handle)
+            .ok()
+            .filter(|&handle| handle < self.num_players)
+    }
+
+    /// Counts a received packet from `from` and returns whether it is within the rate limit of that source in the current time
+    /// window.
+    fn within_recv_rate_limit(&mut self, from: &T::Address) -> bool {
+        let now = self.now();
+        let (window_start, count) = self.recv_windows.entry(from.clone()).or_insert((now, 0));
+        if now.duration_since(*window_start) >= RECV_RATE_WINDOW {
+            *window_start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= MAX_RECV_PACKETS_PER_SECOND
+    }
+
+    /// Measures the rate at which the remote client advances frames by the inputs received from it and
+    /// sends a `FpsMismatch` event if it deviates from the local fps by more than `tolerance` frames per second.
+    fn check_fps_mismatch(&mut self, tolerance: f64, now: Instant) {
+        let Some(last_recv_frame) = self.last_recv_frame() else {
+            return;
+        };
+
+        let Some((start_time, start_frame)) = self.fps_window_start else {
+            self.fps_window_start = Some((now, last_recv_frame));
+            return;
+        };
+
+        let elapsed = now.duration_since(start_time);
+        if elapsed < FPS_MEASUREMENT_INTERVAL {
+            return;
+        }
+
+        let observed_fps = (last_recv_frame - start_frame) as f64 / elapsed.as_secs_f64();
+        if (observed_fps - self.fps as f64).abs() > tolerance {
+            self.event_queue.push_back(Event::FpsMismatch {
+                declared_fps: self.remote_fps,
+                observed_fps,
+            });
         }
+        self.fps_window_start = Some((now, last_recv_frame));
     }
 
     /// Upon receiving a `SyncRequest`, answer with a `SyncReply` with the proper data
     fn on_sync_request(&mut self, body: SyncRequest) {
+        // after the synchronization failed, the remote client must not synchronize with us until we retry
+        if self.state == ProtocolState::SyncFailed {
+            return;
+        }
+        // never synchronize with a client that encodes messages differently
+        if body.protocol_version != PROTOCOL_VERSION {
+            if !self.protocol_mismatch_sent {
+                self.protocol_mismatch_sent = true;
+                self.event_queue.push_back(Event::ProtocolMismatch {
+                    remote_version: body.protocol_version,
+                });
+            }
+            return;
+        }
         let reply_body = SyncReply {
             random_reply: body.random_request,
+            delay_based: self.delay_based,
+            fps: u32::try_from(self.fps).unwrap_or(u32::MAX),
+            time: self.clock.millis_since_epoch() as u64,
+            player_ids: self
+                .local_player_ids
+                .iter()
+                .map(|(handle, id)| (*handle as u64, *id))
+                .collect(),
+            payload: self.handshake_payload.clone(),
+            input_delay: self
+                .fair_input_delay
+                .map(|delay| u32::try_from(delay).unwrap_or(u32::MAX)),
+            round_trip_time: u32::try_from(self.round_trip_time).unwrap_or(u32::MAX),
+            external_addr: self.external_addr,
+            start_frame: self.start_frame,
+            input_codec: self.input_codec.format_id(),
+            variable_length_inputs: self.input_len.is_some(),
+            synchronized_start: self.synchronized_start,
         };
         self.queue_message(MessageBody::SyncReply(reply_body));
     }
 
     /// Upon receiving a `SyncReply`, check validity and either continue the synchronization process or conclude synchronization.
-    fn on_sync_reply(&mut self, header: MessageHeader, body: SyncReply) {
+    fn on_sync_reply(&mut self, header: MessageHeader, body: &SyncReply, received_at: u128) {
         // ignore sync replies when not syncing
-        if self.state != ProtocolState::Synchronizing {
+        if self.state != ProtocolState::Synchronizing || self.handshake_rejected {
             return;
         }
         // this is not the correct reply
-        if !self.sync_random_requests.remove(&body.random_reply) {
+        let request_time = match self.sync_random_requests.remove(&body.random_reply) {
+            Some(request_time) => request_time,
+            None => return,
+        };
+        // never synchronize with a client that disagrees on a setting all clients have to share
+        if let Some(mismatch) = self.handshake_mismatch(body) {
+            self.refuse_handshake(Event::HandshakeMismatch { mismatch });
             return;
         }
+        // measure the round trip time, so it is already known once the session starts
+        let queued = self.clock.millis_since_epoch().saturating_sub(received_at);
+        self.round_trip_time = self
+            .now()
+            .duration_since(request_time)
+            .as_millis()
+            .saturating_sub(queued);
+        self.update_clock_offset(body.time);
+        self.remote_delay_based = body.delay_based;
+        self.remote_fps = body.fps;
+        self.remote_fair_input_delay = body.input_delay.map(|delay| delay as usize);
+        self.remote_round_trip_time = body.round_trip_time as u128;
+        self.remote_external_addr = body.external_addr;
+        self.remote_synchronized_start = body.synchronized_start;
+        // only accept ids for the players behind this endpoint
+        self.remote_player_ids = body
+            .player_ids
+            .iter()
+            .filter_map(|(handle, id)| {
+                let handle = PlayerHandle::try_from(*handle).ok()?;
+                self.handles.contains(&handle).then_some((handle, *id))
+            })
+            .collect();
         // the sync reply is good, so we send a sync request again until we have finished the required roundtrips. Then, we can conclude the syncing process.
         self.sync_remaining_roundtrips -= 1;
+        self.sync_last_progress = self.now();
         if self.sync_remaining_roundtrips > 0 {
             // register an event
             let evt = Event::Synchronizing {
@@ -608,6 +1867,13 @@ impl<T: Config> UdpProtocol<T> {
             // send another sync request
             self.send_sync_request();
         } else {
+            // let the application decide whether to play with the remote client before concluding
+            if let Some(verifier) = &self.handshake_verifier {
+                if !verifier.verify(&self.peer_addr, &body.payload) {
+                    self.refuse_handshake(Event::HandshakeRejected);
+                    return;
+                }
+            }
             // switch to running state
             self.state = ProtocolState::Running;
             // register an event
@@ -617,7 +1883,119 @@ impl<T: Config> UdpProtocol<T> {
         }
     }
 
-    fn on_input(&mut self, body: &Input) {
+    /// Compares the settings of the remote client that have to match the local ones.
+    fn handshake_mismatch(&self, body: &SyncReply) -> Option<HandshakeMismatch> {
+        if body.start_frame != self.start_frame {
+            return Some(HandshakeMismatch::StartFrame {
+                local: self.start_frame,
+                remote: body.start_frame,
+            });
+        }
+        let input_codec = self.input_codec.format_id();
+        if body.input_codec != input_codec {
+            return Some(HandshakeMismatch::InputCodec {
+                local: input_codec,
+                remote: body.input_codec,
+            });
+        }
+        if body.variable_length_inputs != self.input_len.is_some() {
+            return Some(HandshakeMismatch::VariableLengthInputs {
+                local: self.input_len.is_some(),
+                remote: body.variable_length_inputs,
+            });
+        }
+        None
+    }
+
+    /// Refuses to play with the remote client. Both sides disconnect, since the remote client might already consider itself
+    /// synchronized with us.
+    fn refuse_handshake(&mut self, event: Event<T>) {
+        self.handshake_rejected = true;
+        self.event_queue.push_back(event);
+        self.send_disconnect_notice();
+        if !self.disconnect_event_sent {
+            self.event_queue.push_back(Event::Disconnected);
+            self.disconnect_event_sent = true;
+        }
+    }
+
+    /// Sends an input message without inputs that asks the remote client to disconnect.
+    fn send_disconnect_notice(&mut self) {
+        let body = Input {
+            peer_connect_status: vec![ConnectionStatus::default(); self.peer_connect_status.len()],
+            disconnect_requested: true,
+            sent_at: self.clock.millis_since_epoch() as u64,
+            ..Default::default()
+        };
+        self.queue_message(MessageBody::Input(body));
+    }
+
+    /// Estimates the offset of the remote clock NTP-style, assuming the remote time was taken halfway through the roundtrip.
+    /// The sample of the fastest roundtrip is kept, since it has the smallest error.
+    fn update_clock_offset(&mut self, remote_time: u64) {
+        if self
+            .clock_offset
+            .is_some_and(|(_, rtt)| rtt <= self.round_trip_time)
+        {
+            return;
+        }
+        let local_time =
+            self.clock.millis_since_epoch() as i128 - (self.round_trip_time / 2) as i128;
+        // the remote time is sent by the remote client, so it can be anything
+        if let Ok(offset) = i64::try_from(remote_time as i128 - local_time) {
+            self.clock_offset = Some((offset, self.round_trip_time));
+        }
+    }
+
+    /// Converts the send time of a remote packet to local time to measure how long it took to arrive.
+    fn record_input_transit_time(&mut self, sent_at: u64, received_at: u128) {
+        let Some((offset, _)) = self.clock_offset else {
+            return;
+        };
+        let local_sent_at = sent_at as i128 - offset as i128;
+        let transit_time = (received_at as i128 - local_sent_at).max(0) as u128;
+        // the send time is sent by the remote client, so it can be anything
+        if !self.plausible_latency(transit_time) {
+            return;
+        }
+        if self.input_transit_times.len() == INPUT_LATENCY_SAMPLES {
+            self.input_transit_times.pop_front();
+        }
+        self.input_transit_times.push_back(transit_time);
+
+        // select the median in a copy on the stack, since this runs for every input packet
+        let mut samples = [0; INPUT_LATENCY_SAMPLES];
+        let samples = &mut samples[..self.input_transit_times.len()];
+        for (sample, transit_time) in samples.iter_mut().zip(&self.input_transit_times) {
+            *sample = *transit_time;
+        }
+        let middle = samples.len() / 2;
+        self.input_transit_median = Some(*samples.select_nth_unstable(middle).1);
+    }
+
+    fn on_input(&mut self, body: &Input, received_at: u128) {
+        // a malformed packet must never crash the game, so we drop it instead
+        if body.peer_connect_status.len() != self.peer_connect_status.len() {
+            return;
+        }
+
+        // a remote client never sends frames outside of a session, so the frame arithmetic below cannot overflow
+        let valid_frame = |frame: Frame| (0..MAX_FRAME).contains(&frame);
+        if !valid_frame(body.start_frame)
+            || body
+                .metadata_frame
+                .is_some_and(|metadata_frame| !valid_frame(metadata_frame))
+            || body
+                .peer_connect_status
+                .iter()
+                .filter_map(|status| status.last_frame)
+                .any(|last_frame| !valid_frame(last_frame))
+        {
+            return;
+        }
+
+        self.record_input_transit_time(body.sent_at, received_at);
+
         // drop pending outputs until the ack frame
         self.pop_pending_output(body.ack_frame);
 
@@ -640,55 +2018,156 @@ impl<T: Config> UdpProtocol<T> {
             }
         }
 
-        // if the encoded packet is decoded with an input we did not receive yet, we cannot recover
-        assert!(
-            self.last_recv_frame() == NULL_FRAME || self.last_recv_frame() + 1 >= body.start_frame
-        );
+        // if the encoded packet is decoded with an input we did not receive yet, we cannot recover. After a time without players
+        // though, the remote client starts over with inputs encoded against blank inputs.
+        if let Some(last_recv_frame) = self
+            .last_recv_frame()
+            .filter(|&last_recv_frame| last_recv_frame + 1 < body.start_frame)
+        {
+            if !self.sends_no_inputs_between(last_recv_frame + 1, body.start_frame) {
+                return;
+            }
+            let num_players = self.handles_at(body.start_frame).len();
+            let mut blank = InputBytes::zeroed::<T>(num_players, self.now());
+            blank.frame = Some(body.start_frame - 1);
+            self.recv_inputs.insert(blank.frame, blank);
+        }
 
-        // if we did not receive any input yet, we decode with the blank input,
+        // if we did not receive any input yet or the inputs start at the first frame, we decode with the blank input,
         // otherwise we use the input previous to the start of the encoded inputs
-        let decode_frame = if self.last_recv_frame() == NULL_FRAME {
-            NULL_FRAME
-        } else {
-            body.start_frame - 1
-        };
+        let decode_frame =
+            if self.last_recv_frame().is_none() || body.start_frame == self.start_frame {
+                None
+            } else {
+                Some(body.start_frame - 1)
+            };
+
+        // a late packet from before the last change of the remote players only holds inputs we already received
+        if Some(body.start_frame) < self.recv_handles_frame {
+            return;
+        }
+
+        // all inputs of a packet are for the same players
+        let handles = self.handles_at(body.start_frame).to_vec();
+        let input_size = std::mem::size_of::<T::Input>() * handles.len();
 
         // if we have the necessary input saved, we decode
         if let Some(decode_inp) = self.recv_inputs.get(&decode_frame) {
-            self.running_last_input_recv = Instant::now();
+            self.running_last_input_recv = self.now();
+
+            // the uncompressed payload of a remote never exceeds the maximum payload size
+            let Some(bytes) = self.input_codec.decompress(&body.bytes, MAX_PAYLOAD) else {
+                return;
+            };
+
+            // a remote never sends more inputs than fit into its pending output, so we refuse to decode more than that
+            let recv_inputs = match self.input_len {
+                Some(_) => {
+                    let num_players = handles.len();
+                    decode_prefixed(
+                        &bytes,
+                        std::mem::size_of::<T::Input>(),
+                        PENDING_OUTPUT_SIZE * num_players,
+                    )
+                    .ok()
+                    .filter(|inputs| inputs.len().is_multiple_of(num_players))
+                    .map(|inputs| {
+                        inputs
+                            .chunks_exact(num_players)
+                            .map(|c| c.concat())
+                            .collect()
+                    })
+                }
+                None => {
+                    // after a change of the remote players, the inputs are encoded against blank inputs for the new players
+                    let blank;
+                    let reference = if decode_inp.bytes.len() == input_size {
+                        &decode_inp.bytes
+                    } else {
+                        blank = vec![0; input_size];
+                        &blank
+                    };
+                    match bitplane_players::<T>(input_size) {
+                        Some(players) => {
+                            decode_bitplanes(reference, &bytes, PENDING_OUTPUT_SIZE, players).ok()
+                        }
+                        None => decode(reference, &bytes, PENDING_OUTPUT_SIZE).ok(),
+                    }
+                }
+            };
+            let Some(recv_inputs) = recv_inputs else {
+                return;
+            };
 
-            let recv_inputs = decode(&decode_inp.bytes, &body.bytes).expect("decoding failed");
+            let Some(metadata_frames) = split_metadata_frames(&body.metadata) else {
+                return;
+            };
 
+            // validate all inputs before using any of them
+            let last_recv_frame = self.last_recv_frame();
+            let mut new_inputs = Vec::with_capacity(recv_inputs.len());
             for (i, inp) in recv_inputs.into_iter().enumerate() {
                 let inp_frame = body.start_frame + i as i32;
                 // skip inputs that we don't need
-                if inp_frame <= self.last_recv_frame() {
+                if Some(inp_frame) <= last_recv_frame {
                     continue;
                 }
+                // no session simulates a frame past MAX_FRAME, even if the input delay of the remote client reaches it
+                if inp_frame >= MAX_FRAME {
+                    break;
+                }
 
                 let input_data = InputBytes {
-                    frame: inp_frame,
+                    frame: Some(inp_frame),
                     bytes: inp,
+                    created: self.now(),
+                    metadata: Vec::new(),
+                };
+                let Some(player_inputs) = input_data.to_player_inputs::<T>(handles.len()) else {
+                    return;
                 };
+                // metadata of older frames might have been left out of the packet
+                let metadata_block = body
+                    .metadata_frame
+                    .and_then(|metadata_frame| usize::try_from(inp_frame - metadata_frame).ok())
+                    .and_then(|i| metadata_frames.get(i))
+                    .copied()
+                    .unwrap_or_default();
+                let Some(player_metadata) = split_metadata_players(metadata_block, handles.len())
+                else {
+                    return;
+                };
+                new_inputs.push((input_data, player_inputs, player_metadata));
+            }
+
+            for (input_data, player_inputs, player_metadata) in new_inputs {
                 // send the input to the session
-                let player_inputs = input_data.to_player_inputs::<T>(self.handles.len());
                 self.recv_inputs.insert(input_data.frame, input_data);
 
-                for (i, player_input) in player_inputs.into_iter().enumerate() {
+                for (i, (player_input, metadata)) in
+                    player_inputs.into_iter().zip(player_metadata).enumerate()
+                {
                     self.event_queue.push_back(Event::Input {
                         input: player_input,
-                        player: self.handles[i],
+                        player: handles[i],
+                        metadata,
                     });
                 }
             }
 
-            // send an input ack
-            self.send_input_ack();
+            // send an input ack. Over the bandwidth limit, the inputs we send acknowledge them as well
+            if !self.over_bandwidth_limit() {
+                self.send_input_ack();
+            }
 
-            // delete received inputs that are too old
-            let last_recv_frame = self.last_recv_frame();
-            self.recv_inputs
-                .retain(|&k, _| k >= last_recv_frame - 2 * self.max_prediction as i32);
+            // delete received inputs that are too old, the blank input counts as the one before the start frame
+            if let Some(last_recv_frame) = self.last_recv_frame() {
+                let oldest_kept = last_recv_frame - 2 * self.max_prediction as i32;
+                let start_frame = self.start_frame;
+                self.recv_inputs
+                    .retain(|&frame, _| frame.unwrap_or(start_frame - 1) >= oldest_kept);
+            }
+            self.commit_handle_changes();
         }
     }
 
@@ -705,40 +2184,40 @@ impl<T: Config> UdpProtocol<T> {
     }
 
     /// Upon receiving a `QualityReply`, update network stats.
-    fn on_quality_reply(&mut self, body: &QualityReply) {
-        let millis = millis_since_epoch();
-        assert!(millis >= body.pong);
-        self.round_trip_time = millis - body.pong;
+    fn on_quality_reply(&mut self, body: &QualityReply, received_at: u128) {
+        // ignore replies from the future or the distant past, the remote might have tampered with them
+        if let Some(round_trip_time) = received_at.checked_sub(body.pong) {
+            if self.plausible_latency(round_trip_time) {
+                self.round_trip_time = round_trip_time;
+            }
+        }
+    }
+
+    /// Returns whether a latency in ms measured with a timestamp of the remote client is plausible. A connection that is still
+    /// alive cannot have a latency beyond the disconnect timeout, so such samples are tampered with or broken.
+    fn plausible_latency(&self, latency: u128) -> bool {
+        latency <= self.disconnect_timeout.as_millis()
     }
 
     /// Upon receiving a `ChecksumReport`, add it to the checksum history
     fn on_checksum_report(&mut self, body: &ChecksumReport) {
-        let interval = if let DesyncDetection::On { interval } = self.desync_detection {
-            interval
-        } else {
-            debug_assert!(
-                false,
-                "Received checksum report, but desync detection is off. Check
-                that configuration is consistent between peers."
-            );
-            1
+        // if desync detection is off, the configuration is inconsistent between peers and we ignore the report
+        let DesyncDetection::On { interval } = self.desync_detection else {
+            return;
         };
 
-        if self.pending_checksums.len() >= MAX_CHECKSUM_HISTORY_SIZE {
+        if self.pending_checksums.len() >= self.checksum_history_size {
             let oldest_frame_to_keep =
-                body.frame - (MAX_CHECKSUM_HISTORY_SIZE as i32 - 1) * interval as i32;
+                body.frame - (self.checksum_history_size as i32 - 1) * interval as i32;
             self.pending_checksums
                 .retain(|&frame, _| frame >= oldest_frame_to_keep);
         }
         self.pending_checksums.insert(body.frame, body.checksum);
     }
 
-    /// Returns the frame of the last received input
-    fn last_recv_frame(&self) -> Frame {
-        match self.recv_inputs.iter().max_by_key(|&(k, _)| k) {
-            Some((k, _)) => *k,
-            None => NULL_FRAME,
-        }
+    /// Returns the frame of the last received input, if any
+    fn last_recv_frame(&self) -> Option<Frame> {
+        self.recv_inputs.keys().max().copied().flatten()
     }
 
     pub(crate) fn send_checksum_report(&mut self, frame_to_send: Frame, checksum: u128) {
@@ -749,3 +2228,164 @@ impl<T: Config> UdpProtocol<T> {
         self.queue_message(MessageBody::ChecksumReport(body));
     }
 }
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod protocol_tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&[], 0.5), 0);
+        assert_eq!(percentile(&[7], 0.99), 7);
+
+        let values: Vec<u128> = (1..=100).collect();
+        assert_eq!(percentile(&values, 0.0), 1);
+        assert_eq!(percentile(&values, 0.5), 51);
+        assert_eq!(percentile(&values, 0.9), 90);
+        assert_eq!(percentile(&values, 0.99), 99);
+        assert_eq!(percentile(&values, 1.0), 100);
+    }
+
+    #[test]
+    fn test_fair_share() {
+        assert_eq!(fair_share(8, 30, 90), 2);
+        assert_eq!(fair_share(8, 90, 30), 6);
+        assert_eq!(fair_share(7, 55, 20), 5);
+        assert_eq!(fair_share(5, 40, 40), 3);
+        assert_eq!(fair_share(0, 10, 10), 0);
+        // without measurements, the delay is split evenly
+        assert_eq!(fair_share(4, 0, 100), 2);
+    }
+
+    #[test]
+    fn test_bitplane_players() {
+        type Small = crate::FixedInputConfig<2, u8>;
+        type Large = crate::FixedInputConfig<16, u8>;
+        assert_eq!(bitplane_players::<Small>(2 * 3), None);
+        assert_eq!(bitplane_players::<Small>(2 * 4), Some(4));
+        assert_eq!(bitplane_players::<Small>(2 * 8), Some(8));
+        assert_eq!(bitplane_players::<Large>(16 * 8), None);
+    }
+
+    type Cfg = crate::FixedInputConfig<1, u8>;
+
+    /// Creates an endpoint for the remote player 1 of a two player session.
+    fn endpoint(peer_addr: SocketAddr) -> UdpProtocol<Cfg> {
+        UdpProtocol::<Cfg>::new(
+            vec![1],
+            peer_addr,
+            1,
+            EndpointSettings {
+                num_players: 2,
+                max_prediction: 8,
+                disconnect_timeout: Duration::from_millis(2000),
+                disconnect_notify_start: Duration::from_millis(500),
+                fps: 60,
+                desync_detection: DesyncDetection::Off,
+                delay_based: false,
+                session_token: None,
+                fps_mismatch_tolerance: None,
+                input_codec: Arc::new(crate::IdentityCodec),
+                local_player_ids: Vec::new(),
+                clock: Arc::new(clock::StdClock),
+            },
+        )
+    }
+
+    #[test]
+    fn test_recv_rate_limit_per_source() {
+        let peer_addr = SocketAddr::from(([127, 0, 0, 1], 7777));
+        let candidate_addr = SocketAddr::from(([127, 0, 0, 2], 7777));
+        let stranger_addr = SocketAddr::from(([127, 0, 0, 3], 7777));
+        let mut endpoint = endpoint(peer_addr);
+        endpoint.set_candidate_addrs(vec![peer_addr, candidate_addr]);
+
+        // a flooding candidate address only uses up its own rate limit
+        for _ in 0..MAX_RECV_PACKETS_PER_SECOND {
+            assert!(endpoint.accept_packet(&candidate_addr));
+        }
+        assert!(!endpoint.accept_packet(&candidate_addr));
+        assert!(endpoint.accept_packet(&peer_addr));
+        assert_eq!(endpoint.packets_dropped, 1);
+
+        // packets of unknown addresses are neither accepted nor counted
+        assert!(!endpoint.accept_packet(&stranger_addr));
+        assert_eq!(endpoint.packets_dropped, 1);
+        assert_eq!(endpoint.recv_windows.len(), 2);
+    }
+
+    #[test]
+    fn test_input_with_forged_frames() {
+        let peer_addr = SocketAddr::from(([127, 0, 0, 1], 7777));
+        let mut endpoint = endpoint(peer_addr);
+        let input = vec![1];
+        let bytes = encode(&[0], std::iter::once(&input));
+        for (start_frame, metadata_frame, last_frame) in [
+            (Frame::MAX, None, None),
+            (0, Some(Frame::MIN), None),
+            (Frame::MIN, Some(0), None),
+            (0, None, Some(Frame::MAX)),
+        ] {
+            let msg = Message {
+                header: MessageHeader {
+                    magic: 0x1234,
+                    session_token: None,
+                },
+                body: MessageBody::Input(Input {
+                    peer_connect_status: vec![
+                        ConnectionStatus {
+                            disconnected: false,
+                            last_frame,
+                        };
+                        2
+                    ],
+                    start_frame,
+                    metadata_frame,
+                    bytes: bytes.clone(),
+                    ..Default::default()
+                }),
+            };
+            // the packet is dropped instead of overflowing the frame arithmetic
+            endpoint.handle_message(&peer_addr, &msg, 0);
+            assert!(endpoint
+                .poll(&[ConnectionStatus::default(); 2])
+                .all(|event| !matches!(event, Event::Input { .. })));
+            assert_eq!(endpoint.peer_connect_status(0), ConnectionStatus::default());
+        }
+    }
+
+    #[test]
+    fn test_input_past_max_frame() {
+        let peer_addr = SocketAddr::from(([127, 0, 0, 1], 7777));
+        let mut endpoint = endpoint(peer_addr);
+        let inputs = [vec![1], vec![2], vec![3]];
+        let msg = Message {
+            header: MessageHeader {
+                magic: 0x1234,
+                session_token: None,
+            },
+            body: MessageBody::Input(Input {
+                peer_connect_status: vec![ConnectionStatus::default(); 2],
+                start_frame: MAX_FRAME - 1,
+                bytes: encode(&[0], inputs.iter()),
+                ..Default::default()
+            }),
+        };
+        endpoint.handle_message(&peer_addr, &msg, 0);
+
+        // only the input of the last frame a session simulates is received
+        let frames: Vec<_> = endpoint
+            .poll(&[ConnectionStatus::default(); 2])
+            .filter_map(|event| match event {
+                Event::Input { input, .. } => Some(input.frame),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(frames, vec![Some(MAX_FRAME - 1)]);
+        assert_eq!(endpoint.last_recv_frame(), Some(MAX_FRAME - 1));
+    }
+}
@@ -0,0 +1,120 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use parking_lot::Mutex;
+use quinn::{Connection, Endpoint};
+use tokio::runtime::Handle;
+
+use crate::{network::wire::parse_packet, Message, NonBlockingSocket};
+
+/// A [`NonBlockingSocket`] that exchanges messages as unreliable QUIC datagrams over [`quinn`] connections, for games that
+/// already talk to their peers over QUIC. Connect to every remote client with [`QuinnSocket::connect()`] or
+/// [`QuinnSocket::accept()`] before starting the session.
+///
+/// Datagrams are neither retransmitted nor ordered, just like UDP packets, so GGRS handles their loss itself. Unlike UDP,
+/// QUIC applies congestion control to datagrams and drops those that do not fit into the path MTU instead of fragmenting them.
+/// Messages to addresses without a connection are dropped as well.
+///
+/// The socket has to be created inside of a [Tokio](tokio) runtime, which has to keep running in the background while the
+/// session is used, since it drives the connections and receives the datagrams.
+#[derive(Debug)]
+pub struct QuinnSocket {
+    endpoint: Endpoint,
+    runtime: Handle,
+    connections: HashMap<SocketAddr, Connection>,
+    /// Filled by one task per connection, which reads its datagrams.
+    received: Arc<Mutex<Vec<(SocketAddr, Message)>>>,
+}
+
+impl QuinnSocket {
+    /// Creates a socket that sends and receives datagrams over connections of the given endpoint.
+    ///
+    /// # Panics
+    /// Panics if called outside of a Tokio runtime.
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            runtime: Handle::current(),
+            connections: HashMap::new(),
+            received: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Returns the endpoint the connections are made with.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Connects to the remote client at the given address, which has to be accepting connections with
+    /// [`QuinnSocket::accept()`]. `server_name` is the name the certificate of the remote client is checked against.
+    ///
+    /// # Errors
+    /// - Returns an error if the endpoint has no client configuration or the connection could not be established.
+    pub async fn connect(
+        &mut self,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<(), std::io::Error> {
+        let connection = self
+            .endpoint
+            .connect(addr, server_name)
+            .map_err(std::io::Error::other)?
+            .await?;
+        self.add_connection(connection);
+        Ok(())
+    }
+
+    /// Waits for the next remote client to connect and returns its address, or `None` if the endpoint was closed.
+    ///
+    /// # Errors
+    /// - Returns an error if the connection could not be established.
+    pub async fn accept(&mut self) -> Option<Result<SocketAddr, std::io::Error>> {
+        let incoming = self.endpoint.accept().await?;
+        Some(match incoming.await {
+            Ok(connection) => {
+                let addr = connection.remote_address();
+                self.add_connection(connection);
+                Ok(addr)
+            }
+            Err(err) => Err(err.into()),
+        })
+    }
+
+    /// Adds a connection that was established elsewhere, replacing any previous connection to the same address.
+    /// Messages to and from its remote address are exchanged over it from now on.
+    pub fn add_connection(&mut self, connection: Connection) {
+        let addr = connection.remote_address();
+        let received = self.received.clone();
+        let reader = connection.clone();
+        self.runtime.spawn(async move {
+            // ends as soon as the connection is closed, like a UDP socket no longer receiving anything
+            while let Ok(datagram) = reader.read_datagram().await {
+                if let Ok(msg) = parse_packet(&datagram) {
+                    received.lock().push((addr, msg));
+                }
+            }
+        });
+        self.connections.insert(addr, connection);
+    }
+}
+
+impl NonBlockingSocket<SocketAddr> for QuinnSocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        let Some(connection) = self.connections.get(addr) else {
+            return;
+        };
+        let mut datagram = Vec::new();
+        msg.encode_into(&mut datagram);
+        // datagrams are unreliable anyways, so if sending fails the message is lost just like any other dropped packet
+        let _ = connection.send_datagram(datagram.into());
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        self.connections
+            .retain(|_, connection| connection.close_reason().is_none());
+        std::mem::take(&mut *self.received.lock())
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.endpoint.local_addr().ok()
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::VecDeque;
+
+use instant::{Duration, Instant};
+
+use crate::network::messages::{Reliable, ReliableAck, ReliablePayload};
+
+/// The time after which an unacknowledged message is sent again.
+const RESEND_INTERVAL: Duration = Duration::from_millis(200);
+/// How often a message is resent before the channel gives up on it, for about five seconds.
+const MAX_RESENDS: u32 = 25;
+/// The maximum number of messages waiting for an acknowledgement. Sending more fails until some have been acknowledged.
+pub(crate) const MAX_PENDING_MESSAGES: usize = 64;
+/// The maximum size in bytes of a single message sent with [`P2PSession::send_message()`], so it fits into a single packet.
+///
+/// [`P2PSession::send_message()`]: crate::P2PSession::send_message
+pub const MAX_MESSAGE_SIZE: usize = 1024;
+
+struct PendingMessage {
+    seq: u32,
+    payload: ReliablePayload,
+    last_send: Instant,
+    resends: u32,
+}
+
+/// A best-effort reliable sub-channel on top of the unreliable packets exchanged with a remote client. Every message gets a
+/// sequence number and is resent until the remote client acknowledges it, at most [`MAX_RESENDS`] times. The receiving side
+/// delivers messages exactly once and in order, skipping messages the sender gave up on.
+pub(crate) struct ReliableChannel {
+    next_send_seq: u32,
+    /// Sent messages that have not been acknowledged yet, ordered by their sequence number.
+    pending: VecDeque<PendingMessage>,
+    /// All messages with a lower sequence number have been delivered.
+    next_recv_seq: u32,
+    /// Messages that arrived before one with a lower sequence number, held back until they can be delivered in order. The
+    /// entry at index `i` holds the message with sequence number `next_recv_seq + i`, if it arrived.
+    out_of_order: VecDeque<Option<ReliablePayload>>,
+    /// The number of messages the channel gave up on resending.
+    messages_dropped: usize,
+}
+
+impl ReliableChannel {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_send_seq: 0,
+            pending: VecDeque::new(),
+            next_recv_seq: 0,
+            out_of_order: VecDeque::new(),
+            messages_dropped: 0,
+        }
+    }
+
+    /// Assigns the next sequence number to the payload and returns the message to send right away, or `None` if there are
+    /// already [`MAX_PENDING_MESSAGES`] messages waiting for an acknowledgement.
+    pub(crate) fn send(&mut self, payload: ReliablePayload, now: Instant) -> Option<Reliable> {
+        if self.pending.len() >= MAX_PENDING_MESSAGES {
+            return None;
+        }
+        let seq = self.next_send_seq;
+        self.next_send_seq = self.next_send_seq.wrapping_add(1);
+        self.pending.push_back(PendingMessage {
+            seq,
+            payload,
+            last_send: now,
+            resends: 0,
+        });
+        Some(self.message(self.pending.len() - 1))
+    }
+
+    /// Returns all messages that have not been acknowledged within the resend interval. Messages that have been resent too
+    /// often are given up on instead.
+    pub(crate) fn resends(&mut self, now: Instant) -> Vec<Reliable> {
+        let before = self.pending.len();
+        self.pending.retain(|msg| msg.resends < MAX_RESENDS);
+        self.messages_dropped += before - self.pending.len();
+
+        let mut resends = Vec::new();
+        for i in 0..self.pending.len() {
+            let msg = &mut self.pending[i];
+            if msg.last_send + RESEND_INTERVAL < now {
+                msg.last_send = now;
+                msg.resends += 1;
+                resends.push(self.message(i));
+            }
+        }
+        resends
+    }
+
+    /// Removes all messages the remote client acknowledged from the pending messages.
+    pub(crate) fn on_ack(&mut self, body: ReliableAck) {
+        // the acknowledgement covers the pending messages before it, which are at most the whole pending window behind it
+        while self.pending.front().is_some_and(|msg| {
+            body.ack.wrapping_sub(msg.seq).wrapping_sub(1) < MAX_PENDING_MESSAGES as u32
+        }) {
+            self.pending.pop_front();
+        }
+    }
+
+    /// Handles a message of the remote client and returns the payloads that can now be delivered, in order. Sequence numbers
+    /// wrap around, so they are only compared by how far they are ahead of the next one expected.
+    pub(crate) fn on_message(&mut self, body: &Reliable) -> Vec<ReliablePayload> {
+        // a message always lies in the pending window of its sender, anything else is malformed
+        if body.seq.wrapping_sub(body.oldest_pending) >= MAX_PENDING_MESSAGES as u32 {
+            return Vec::new();
+        }
+
+        let mut delivered = Vec::new();
+        // the sender gave up on all messages before its oldest pending one, so we stop waiting for them. An oldest pending
+        // message more than half the sequence space ahead is actually behind, from before the sequence numbers wrapped.
+        let skipped = body.oldest_pending.wrapping_sub(self.next_recv_seq);
+        if skipped > 0 && skipped <= u32::MAX / 2 {
+            let held_back = self.out_of_order.len().min(skipped as usize);
+            delivered.extend(self.out_of_order.drain(..held_back).flatten());
+            self.next_recv_seq = body.oldest_pending;
+        }
+
+        // duplicates and messages too far ahead are dropped, the latter will be resent
+        let offset = body.seq.wrapping_sub(self.next_recv_seq) as usize;
+        if offset >= MAX_PENDING_MESSAGES {
+            return delivered;
+        }
+        if self.out_of_order.len() <= offset {
+            self.out_of_order.resize(offset + 1, None);
+        }
+        self.out_of_order[offset] = Some(body.payload.clone());
+
+        while let Some(Some(_)) = self.out_of_order.front() {
+            delivered.extend(self.out_of_order.pop_front().flatten());
+            self.next_recv_seq = self.next_recv_seq.wrapping_add(1);
+        }
+        delivered
+    }
+
+    /// Returns whether the remote client acknowledged the message with the given sequence number, or the channel gave up on it.
+    pub(crate) fn is_acked(&self, seq: u32) -> bool {
+        self.pending
+            .front()
+            .is_none_or(|msg| msg.seq.wrapping_sub(seq) as i32 > 0)
+    }
+
+    /// Returns the acknowledgement for all messages delivered so far.
+    pub(crate) fn ack(&self) -> ReliableAck {
+        ReliableAck {
+            ack: self.next_recv_seq,
+        }
+    }
+
+    /// Returns the number of messages the channel gave up on resending.
+    pub(crate) fn messages_dropped(&self) -> usize {
+        self.messages_dropped
+    }
+
+    /// Returns the bytes held by pending and held back messages.
+    pub(crate) fn memory(&self) -> usize {
+        let pending: usize = self
+            .pending
+            .iter()
+            .map(|msg| std::mem::size_of::<PendingMessage>() + msg.payload.len())
+            .sum();
+        let held_back: usize = self
+            .out_of_order
+            .iter()
+            .flatten()
+            .map(|payload| std::mem::size_of::<ReliablePayload>() + payload.len())
+            .sum();
+        pending + held_back
+    }
+
+    fn message(&self, index: usize) -> Reliable {
+        let msg = &self.pending[index];
+        Reliable {
+            seq: msg.seq,
+            oldest_pending: self.pending[0].seq,
+            payload: msg.payload.clone(),
+        }
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod reliable_tests {
+    use super::*;
+
+    fn user(byte: u8) -> ReliablePayload {
+        ReliablePayload::User(vec![byte])
+    }
+
+    #[test]
+    fn test_delivers_in_order_once() {
+        let now = Instant::now();
+        let mut sender = ReliableChannel::new();
+        let mut receiver = ReliableChannel::new();
+
+        let first = sender.send(user(0), now).unwrap();
+        let second = sender.send(user(1), now).unwrap();
+        assert!(receiver.on_message(&second).is_empty());
+        assert_eq!(receiver.on_message(&first), vec![user(0), user(1)]);
+        assert!(receiver.on_message(&first).is_empty());
+
+        sender.on_ack(receiver.ack());
+        assert!(sender.resends(now + RESEND_INTERVAL * 2).is_empty());
+    }
+
+    #[test]
+    fn test_resends_until_acked() {
+        let mut now = Instant::now();
+        let mut sender = ReliableChannel::new();
+        let mut receiver = ReliableChannel::new();
+
+        sender.send(user(0), now).unwrap(); // lost
+        assert!(sender.resends(now).is_empty());
+        now += RESEND_INTERVAL * 2;
+        let resent = sender.resends(now);
+        assert_eq!(resent.len(), 1);
+        assert_eq!(receiver.on_message(&resent[0]), vec![user(0)]);
+
+        sender.on_ack(receiver.ack());
+        now += RESEND_INTERVAL * 2;
+        assert!(sender.resends(now).is_empty());
+    }
+
+    #[test]
+    fn test_gives_up_after_max_resends() {
+        let mut now = Instant::now();
+        let mut sender = ReliableChannel::new();
+        let mut receiver = ReliableChannel::new();
+
+        sender.send(user(0), now).unwrap(); // never arrives
+        for _ in 0..=MAX_RESENDS {
+            now += RESEND_INTERVAL * 2;
+            sender.resends(now);
+        }
+        assert_eq!(sender.messages_dropped(), 1);
+
+        // the receiver skips the message the sender gave up on
+        let next = sender.send(user(1), now).unwrap();
+        assert_eq!(receiver.on_message(&next), vec![user(1)]);
+    }
+
+    #[test]
+    fn test_bounded_pending() {
+        let now = Instant::now();
+        let mut sender = ReliableChannel::new();
+        for i in 0..MAX_PENDING_MESSAGES {
+            assert!(sender.send(user(i as u8), now).is_some());
+        }
+        assert!(sender.send(user(0), now).is_none());
+
+        sender.on_ack(ReliableAck { ack: 1 });
+        assert!(sender.send(user(0), now).is_some());
+    }
+
+    #[test]
+    fn test_sequence_numbers_wrap() {
+        let now = Instant::now();
+        let mut sender = ReliableChannel::new();
+        let mut receiver = ReliableChannel::new();
+        sender.next_send_seq = u32::MAX - 1;
+        receiver.next_recv_seq = u32::MAX - 1;
+
+        let messages: Vec<_> = (0..4).map(|i| sender.send(user(i), now).unwrap()).collect();
+        assert_eq!(messages[2].seq, 0);
+        assert!(receiver.on_message(&messages[3]).is_empty());
+        assert!(receiver.on_message(&messages[1]).is_empty());
+        assert_eq!(receiver.on_message(&messages[0]), vec![user(0), user(1)]);
+        assert_eq!(receiver.on_message(&messages[2]), vec![user(2), user(3)]);
+        assert!(receiver.on_message(&messages[0]).is_empty());
+        assert_eq!(receiver.ack().ack, 2);
+
+        assert!(!sender.is_acked(u32::MAX));
+        sender.on_ack(receiver.ack());
+        assert!(sender.is_acked(u32::MAX));
+        assert!(sender.is_acked(1));
+        assert!(sender.pending.is_empty());
+    }
+
+    #[test]
+    fn test_skips_given_up_messages_across_wrap() {
+        let mut receiver = ReliableChannel::new();
+        receiver.next_recv_seq = u32::MAX;
+        let message = |seq, oldest_pending| Reliable {
+            seq,
+            oldest_pending,
+            payload: user(seq as u8),
+        };
+
+        assert!(receiver.on_message(&message(1, u32::MAX)).is_empty());
+        // the sender gave up on `u32::MAX` and 0, so the held back message is delivered
+        assert_eq!(receiver.on_message(&message(2, 1)), vec![user(1), user(2)]);
+        assert_eq!(receiver.ack().ack, 3);
+
+        // an oldest pending message more than half the sequence space ahead is from before the wrap and ignored, as are
+        // messages outside the pending window of their sender
+        assert!(receiver
+            .on_message(&message(u32::MAX / 2 + 10, u32::MAX / 2 + 10))
+            .is_empty());
+        assert!(receiver.on_message(&message(4, u32::MAX - 100)).is_empty());
+        assert_eq!(receiver.ack().ack, 3);
+        assert_eq!(receiver.on_message(&message(3, 3)), vec![user(3)]);
+    }
+}
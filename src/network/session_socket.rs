@@ -0,0 +1,146 @@
+use std::collections::vec_deque::{Drain, VecDeque};
+
+#[cfg(feature = "net-trace")]
+use crate::network::trace::TracedSocket;
+use crate::{Config, Message, NonBlockingSocket};
+
+/// The socket given by the user, wrapped to report its messages to the packet observer of the session, if there is one.
+enum UserSocket<A> {
+    Plain(Box<dyn NonBlockingSocket<A>>),
+    #[cfg(feature = "net-trace")]
+    Traced(TracedSocket<A>),
+}
+
+/// The socket of a session. The socket given by the user can be replaced at runtime, while the messages of the new socket
+/// are reported to the packet observer of the session, if there is one.
+pub(crate) struct SessionSocket<T: Config> {
+    socket: UserSocket<T::Address>,
+    /// The kinds of the errors reported since the socket was given to the session.
+    reported_errors: Vec<std::io::ErrorKind>,
+}
+
+impl<T: Config> SessionSocket<T> {
+    pub(crate) fn plain(socket: Box<dyn NonBlockingSocket<T::Address>>) -> Self {
+        Self {
+            socket: UserSocket::Plain(socket),
+            reported_errors: Vec::new(),
+        }
+    }
+
+    #[cfg(feature = "net-trace")]
+    pub(crate) fn traced(socket: TracedSocket<T::Address>) -> Self {
+        Self {
+            socket: UserSocket::Traced(socket),
+            reported_errors: Vec::new(),
+        }
+    }
+
+    fn socket(&mut self) -> &mut dyn NonBlockingSocket<T::Address> {
+        match &mut self.socket {
+            UserSocket::Plain(socket) => socket.as_mut(),
+            #[cfg(feature = "net-trace")]
+            UserSocket::Traced(socket) => socket,
+        }
+    }
+
+    pub(crate) fn send_to(&mut self, msg: &Message, addr: &T::Address) {
+        self.socket().send_to(msg, addr);
+    }
+
+    pub(crate) fn receive_messages(
+        &mut self,
+        max_messages: usize,
+        max_bytes: usize,
+        accept: &mut dyn FnMut(&T::Address) -> bool,
+    ) -> Vec<(T::Address, Message)> {
+        self.socket()
+            .receive_messages_filtered(max_messages, max_bytes, accept)
+    }
+
+    /// Returns the kinds of the errors the socket reported since the last call that have not been reported since the socket was
+    /// given to the session.
+    pub(crate) fn take_errors(&mut self) -> Vec<std::io::ErrorKind> {
+        // a broken socket fails every send, so the errors would flood the event queue
+        let mut kinds = Vec::new();
+        for kind in self.socket().take_errors() {
+            if !self.reported_errors.contains(&kind) {
+                self.reported_errors.push(kind);
+                kinds.push(kind);
+            }
+        }
+        kinds
+    }
+
+    pub(crate) fn local_addr(&self) -> Option<T::Address> {
+        match &self.socket {
+            UserSocket::Plain(socket) => socket.local_addr(),
+            #[cfg(feature = "net-trace")]
+            UserSocket::Traced(socket) => socket.local_addr(),
+        }
+    }
+
+    /// Replaces the socket given by the user. Errors of the new socket are reported again, even if the old one reported them.
+    pub(crate) fn replace(&mut self, socket: Box<dyn NonBlockingSocket<T::Address>>) {
+        match &mut self.socket {
+            UserSocket::Plain(old) => *old = socket,
+            #[cfg(feature = "net-trace")]
+            UserSocket::Traced(traced) => traced.replace_socket(socket),
+        }
+        self.reported_errors.clear();
+    }
+}
+
+/// The packets a session took from its socket but did not process yet, since its receive budget was used up.
+pub(crate) struct ReceiveBacklog<T: Config> {
+    /// The packets with the time in ms since the epoch they were taken from the socket, oldest first.
+    packets: VecDeque<(T::Address, Message, u128)>,
+    /// The maximum number of received packets processed per poll.
+    budget: usize,
+    /// The maximum number of bytes taken from the socket per poll.
+    byte_budget: usize,
+}
+
+impl<T: Config> ReceiveBacklog<T> {
+    pub(crate) fn new(budget: usize, byte_budget: usize) -> Self {
+        Self {
+            packets: VecDeque::new(),
+            budget,
+            byte_budget,
+        }
+    }
+
+    /// Takes as many packets from the socket as the budget leaves after the backlog and returns the packets to process in this
+    /// poll, oldest first. Packets from addresses `accept` rejects are dropped before they are parsed.
+    pub(crate) fn receive(
+        &mut self,
+        socket: &mut SessionSocket<T>,
+        now: u128,
+        mut accept: impl FnMut(&T::Address) -> bool,
+    ) -> Drain<'_, (T::Address, Message, u128)> {
+        let room = self.budget.saturating_sub(self.packets.len());
+        if room > 0 {
+            let received = socket.receive_messages(room, self.byte_budget, &mut accept);
+            self.packets
+                .extend(received.into_iter().map(|(from, msg)| (from, msg, now)));
+        }
+        // a socket without a bounded receive returns everything, so only one more budget is kept for the next poll. The oldest
+        // packets are dropped, since they are stale by now and inputs are resent until they are acknowledged anyway.
+        let excess = self.packets.len().saturating_sub(2 * self.budget);
+        self.packets.drain(..excess);
+        let batch = self.budget.min(self.packets.len());
+        self.packets.drain(..batch)
+    }
+
+    /// Returns the number of waiting packets from addresses `is_from` accepts.
+    pub(crate) fn count(&self, is_from: impl Fn(&T::Address) -> bool) -> usize {
+        self.packets
+            .iter()
+            .filter(|(addr, _, _)| is_from(addr))
+            .count()
+    }
+
+    /// Returns the number of bytes allocated for waiting packets.
+    pub(crate) fn memory(&self) -> usize {
+        self.packets.capacity() * std::mem::size_of::<(T::Address, Message, u128)>()
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::ErrorKind;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::{network::messages::Message, GgrsError, NonBlockingSocket};
+
+type SessionKey = Option<[u8; 16]>;
+
+/// What a session has not picked up from the shared socket yet.
+#[derive(Debug)]
+struct SessionQueue<A> {
+    messages: Vec<(A, Message)>,
+    errors: Vec<ErrorKind>,
+}
+
+impl<A> Default for SessionQueue<A> {
+    fn default() -> Self {
+        Self {
+            messages: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SchedulerState<A, S> {
+    socket: S,
+    /// received messages and socket errors that have not been picked up by their session yet, keyed by session token
+    queues: HashMap<SessionKey, SessionQueue<A>>,
+}
+
+impl<A, S> SchedulerState<A, S> {
+    /// Routes received messages to the owning session. Messages with a token that no session is registered for are dropped.
+    fn route(&mut self, received: Vec<(A, Message)>) {
+        for (addr, msg) in received {
+            if let Some(queue) = self.queues.get_mut(&msg.session_token()) {
+                queue.messages.push((addr, msg));
+            }
+        }
+    }
+
+    /// Hands the errors of the shared socket to every session, since they all use the broken socket. Each session keeps every
+    /// kind only once, so a session that does not poll for a while does not pile up the errors of every failed send.
+    fn broadcast_errors(&mut self, errors: Vec<ErrorKind>) {
+        for queue in self.queues.values_mut() {
+            for kind in errors.iter() {
+                if !queue.errors.contains(kind) {
+                    queue.errors.push(*kind);
+                }
+            }
+        }
+    }
+
+    fn take_messages(&mut self, session_token: &SessionKey) -> Vec<(A, Message)> {
+        self.queues
+            .get_mut(session_token)
+            .map(|queue| std::mem::take(&mut queue.messages))
+            .unwrap_or_default()
+    }
+
+    fn take_errors(&mut self, session_token: &SessionKey) -> Vec<ErrorKind> {
+        self.queues
+            .get_mut(session_token)
+            .map(|queue| std::mem::take(&mut queue.errors))
+            .unwrap_or_default()
+    }
+}
+
+/// A [`SocketScheduler`] allows multiple sessions to share a single [`NonBlockingSocket`], for example a lobby doing connectivity checks
+/// alongside the match itself, or a client observing two matches at once. Every session gets its own [`ScheduledSocket`],
+/// which is identified by the session token set with [`SessionBuilder::with_session_token()`]. Received packets are routed to the
+/// session whose token they carry, while packets for tokens that no session is registered for are dropped. Errors of the shared
+/// socket are reported to every session.
+///
+/// [`SessionBuilder::with_session_token()`]: crate::SessionBuilder::with_session_token
+#[derive(Debug)]
+pub struct SocketScheduler<A, S> {
+    state: Arc<Mutex<SchedulerState<A, S>>>,
+}
+
+impl<A, S> SocketScheduler<A, S> {
+    /// Creates a new [`SocketScheduler`] that takes ownership of the given socket.
+    pub fn new(socket: S) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SchedulerState {
+                socket,
+                queues: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Returns a [`ScheduledSocket`] for the session with the given token, which can be passed to the [`SessionBuilder`] like any other socket.
+    /// The session has to be built with the same token. `None` registers the one session that does not use a session token.
+    /// Dropping the [`ScheduledSocket`] (usually together with its session) frees up the token again.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if a socket for the given token already exists.
+    ///
+    /// [`SessionBuilder`]: crate::SessionBuilder
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn socket_for(
+        &self,
+        session_token: Option<[u8; 16]>,
+    ) -> Result<ScheduledSocket<A, S>, GgrsError> {
+        let mut state = self.state.lock();
+        if state.queues.contains_key(&session_token) {
+            return Err(GgrsError::InvalidRequest {
+                info: "A socket for this session token already exists.".to_owned(),
+            });
+        }
+        state.queues.insert(session_token, SessionQueue::default());
+        Ok(ScheduledSocket {
+            session_token,
+            state: self.state.clone(),
+        })
+    }
+
+    /// Returns the number of sessions currently registered with this scheduler.
+    pub fn num_sessions(&self) -> usize {
+        self.state.lock().queues.len()
+    }
+}
+
+/// The share of a [`SocketScheduler`] that belongs to a single session. It implements [`NonBlockingSocket`], but only returns
+/// the messages that carry its session token.
+#[derive(Debug)]
+pub struct ScheduledSocket<A, S> {
+    session_token: SessionKey,
+    state: Arc<Mutex<SchedulerState<A, S>>>,
+}
+
+impl<A, S> ScheduledSocket<A, S> {
+    /// Returns the session token this socket receives messages for.
+    pub fn session_token(&self) -> Option<[u8; 16]> {
+        self.session_token
+    }
+}
+
+impl<A, S> Drop for ScheduledSocket<A, S> {
+    fn drop(&mut self) {
+        self.state.lock().queues.remove(&self.session_token);
+    }
+}
+
+#[cfg(feature = "sync-send")]
+impl<A, S> NonBlockingSocket<A> for ScheduledSocket<A, S>
+where
+    A: Clone + PartialEq + Eq + Hash + Send + Sync,
+    S: NonBlockingSocket<A>,
+{
+    fn send_to(&mut self, msg: &Message, addr: &A) {
+        self.state.lock().socket.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(A, Message)> {
+        let mut state = self.state.lock();
+        let received = state.socket.receive_all_messages();
+        state.route(received);
+        state.take_messages(&self.session_token)
+    }
+
+    fn take_errors(&mut self) -> Vec<ErrorKind> {
+        let mut state = self.state.lock();
+        let errors = state.socket.take_errors();
+        state.broadcast_errors(errors);
+        state.take_errors(&self.session_token)
+    }
+
+    fn local_addr(&self) -> Option<A> {
+        self.state.lock().socket.local_addr()
+    }
+}
+
+#[cfg(not(feature = "sync-send"))]
+impl<A, S> NonBlockingSocket<A> for ScheduledSocket<A, S>
+where
+    A: Clone + PartialEq + Eq + Hash,
+    S: NonBlockingSocket<A>,
+{
+    fn send_to(&mut self, msg: &Message, addr: &A) {
+        self.state.lock().socket.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(A, Message)> {
+        let mut state = self.state.lock();
+        let received = state.socket.receive_all_messages();
+        state.route(received);
+        state.take_messages(&self.session_token)
+    }
+
+    fn take_errors(&mut self) -> Vec<ErrorKind> {
+        let mut state = self.state.lock();
+        let errors = state.socket.take_errors();
+        state.broadcast_errors(errors);
+        state.take_errors(&self.session_token)
+    }
+
+    fn local_addr(&self) -> Option<A> {
+        self.state.lock().socket.local_addr()
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod socket_scheduler_tests {
+    use super::*;
+    use crate::network::messages::{MessageBody, MessageHeader};
+
+    #[derive(Default)]
+    struct QueueSocket {
+        incoming: Vec<(u8, Message)>,
+        sent: Vec<(u8, Message)>,
+        errors: Vec<ErrorKind>,
+    }
+
+    impl NonBlockingSocket<u8> for QueueSocket {
+        fn send_to(&mut self, msg: &Message, addr: &u8) {
+            self.sent.push((*addr, msg.clone()));
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(u8, Message)> {
+            std::mem::take(&mut self.incoming)
+        }
+
+        fn take_errors(&mut self) -> Vec<ErrorKind> {
+            std::mem::take(&mut self.errors)
+        }
+    }
+
+    fn message(session_token: Option<[u8; 16]>) -> Message {
+        Message {
+            header: MessageHeader {
+                magic: 1,
+                session_token,
+            },
+            body: MessageBody::KeepAlive,
+        }
+    }
+
+    #[test]
+    fn test_routes_by_session_token() {
+        let scheduler = SocketScheduler::<u8, _>::new(QueueSocket::default());
+        let mut sock_a = scheduler.socket_for(Some([1; 16])).unwrap();
+        let mut sock_b = scheduler.socket_for(Some([2; 16])).unwrap();
+
+        scheduler.state.lock().socket.incoming = vec![
+            (10, message(Some([1; 16]))),
+            (20, message(Some([2; 16]))),
+            (30, message(Some([3; 16]))),
+            (40, message(None)),
+            (11, message(Some([1; 16]))),
+        ];
+
+        // the first poll routes all messages, including the ones for the other session
+        let received_a = sock_a.receive_all_messages();
+        assert_eq!(
+            received_a.iter().map(|(addr, _)| *addr).collect::<Vec<_>>(),
+            vec![10, 11]
+        );
+        let received_b = sock_b.receive_all_messages();
+        assert_eq!(
+            received_b.iter().map(|(addr, _)| *addr).collect::<Vec<_>>(),
+            vec![20]
+        );
+        assert!(sock_a.receive_all_messages().is_empty());
+
+        sock_b.send_to(&message(Some([2; 16])), &20);
+        assert_eq!(scheduler.state.lock().socket.sent.len(), 1);
+    }
+
+    #[test]
+    fn test_errors_reach_every_session() {
+        let scheduler = SocketScheduler::<u8, _>::new(QueueSocket::default());
+        let mut sock_a = scheduler.socket_for(Some([1; 16])).unwrap();
+        let mut sock_b = scheduler.socket_for(Some([2; 16])).unwrap();
+
+        scheduler.state.lock().socket.errors = vec![
+            ErrorKind::NetworkUnreachable,
+            ErrorKind::NetworkUnreachable,
+            ErrorKind::ConnectionReset,
+        ];
+        let expected = vec![ErrorKind::NetworkUnreachable, ErrorKind::ConnectionReset];
+        assert_eq!(sock_a.take_errors(), expected);
+        assert_eq!(sock_b.take_errors(), expected);
+        assert!(sock_a.take_errors().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_token() {
+        let scheduler = SocketScheduler::<u8, _>::new(QueueSocket::default());
+        let sock = scheduler.socket_for(None).unwrap();
+        assert!(scheduler.socket_for(None).is_err());
+        assert_eq!(scheduler.num_sessions(), 1);
+
+        drop(sock);
+        assert_eq!(scheduler.num_sessions(), 0);
+        assert!(scheduler.socket_for(None).is_ok());
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    io::{Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+};
+
+use instant::{Duration, Instant};
+
+use crate::UdpNonBlockingSocket;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const BINDING_ERROR: u16 = 0x0111;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const HEADER_SIZE: usize = 20;
+
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+/// The binding request is sent again after this long without an answer, since UDP might have dropped it.
+const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(500);
+const TIMEOUT: Duration = Duration::from_secs(3);
+const RECV_BUFFER_SIZE: usize = 1024;
+
+/// Asks the given STUN server which public address the socket is reachable at from outside the local network, e.g. to hand it
+/// to [`SessionBuilder::with_external_addr()`] or to exchange it through a lobby, so the clients can punch holes into their NATs
+/// by sending to each other. The address belongs to this socket only, so pass the same socket to the session afterwards.
+///
+/// Call this before starting the session: messages of remote clients that arrive in the meantime are dropped. This blocks the
+/// calling thread until the server answered, for at most 3 seconds.
+///
+/// # Errors
+/// - Returns an error of kind [`TimedOut`] if the server did not answer in time.
+/// - Returns an error if the server address cannot be resolved to an address of the family of the socket, the server
+///   rejected the request or its answer does not contain an address.
+///
+/// [`SessionBuilder::with_external_addr()`]: crate::SessionBuilder::with_external_addr
+/// [`TimedOut`]: ErrorKind::TimedOut
+pub fn discover_public_addr(
+    socket: &UdpNonBlockingSocket,
+    stun_server: impl ToSocketAddrs,
+) -> Result<SocketAddr, Error> {
+    let socket = socket.udp_socket();
+    let local_addr = socket.local_addr()?;
+    let server_addr = stun_server
+        .to_socket_addrs()?
+        .find(|addr| addr.is_ipv4() == local_addr.is_ipv4())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::AddrNotAvailable,
+                "The STUN server has no address of the family of the socket.",
+            )
+        })?;
+
+    let transaction_id: [u8; 12] = rand::random();
+    let request = binding_request(&transaction_id);
+    let mut buffer = [0; RECV_BUFFER_SIZE];
+    // this blocks on a real socket before any session exists, so it waits in real time instead of reading a `Clock`
+    let start = Instant::now();
+    let mut next_send = start;
+    while start.elapsed() < TIMEOUT {
+        if Instant::now() >= next_send {
+            match socket.send_to(&request, server_addr) {
+                Ok(_) => (),
+                // the request is sent again with the next retransmission
+                Err(err) if err.kind() == ErrorKind::WouldBlock => (),
+                Err(err) => return Err(err),
+            }
+            next_send += RETRANSMIT_INTERVAL;
+        }
+        match socket.recv_from(&mut buffer) {
+            Ok((len, from)) if from == server_addr => {
+                if let Some(result) = parse_binding_response(&buffer[..len], &transaction_id) {
+                    return result;
+                }
+            }
+            // messages of remote clients that started early, they send them again once the session runs
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(1))
+            }
+            // datagram socket sometimes get this error as a result of calling the send_to method
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Err(Error::new(
+        ErrorKind::TimedOut,
+        "The STUN server did not answer.",
+    ))
+}
+
+/// Encodes a STUN binding request without attributes, see RFC 5389.
+fn binding_request(transaction_id: &[u8; 12]) -> [u8; HEADER_SIZE] {
+    let mut request = [0; HEADER_SIZE];
+    request[0..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+    // the message length stays 0, since there are no attributes
+    request[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request[8..20].copy_from_slice(transaction_id);
+    request
+}
+
+/// Returns the address in the response to the binding request with the given transaction id, or `None` if the packet is not
+/// such a response.
+fn parse_binding_response(
+    bytes: &[u8],
+    transaction_id: &[u8; 12],
+) -> Option<Result<SocketAddr, Error>> {
+    if bytes.len() < HEADER_SIZE
+        || bytes[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || bytes[8..20] != transaction_id[..]
+    {
+        return None;
+    }
+    let message_type = u16::from_be_bytes([bytes[0], bytes[1]]);
+    let len = usize::from(u16::from_be_bytes([bytes[2], bytes[3]]));
+    let attributes = bytes.get(HEADER_SIZE..HEADER_SIZE + len)?;
+    match message_type {
+        BINDING_SUCCESS => (),
+        BINDING_ERROR => {
+            return Some(Err(Error::other(
+                "The STUN server rejected the binding request.",
+            )))
+        }
+        _ => return None,
+    }
+
+    // old servers only send the mapped address without XOR, which some NATs rewrite, so the XORed one is preferred
+    let mut mapped_addr = None;
+    let mut rest = attributes;
+    while rest.len() >= 4 {
+        let attr_type = u16::from_be_bytes([rest[0], rest[1]]);
+        let attr_len = usize::from(u16::from_be_bytes([rest[2], rest[3]]));
+        let value = rest.get(4..4 + attr_len)?;
+        match attr_type {
+            ATTR_XOR_MAPPED_ADDRESS => {
+                if let Some(addr) = parse_address(value, Some(transaction_id)) {
+                    return Some(Ok(addr));
+                }
+            }
+            ATTR_MAPPED_ADDRESS => mapped_addr = mapped_addr.or(parse_address(value, None)),
+            _ => (),
+        }
+        // attributes are padded to a multiple of 4 bytes
+        rest = rest.get((4 + attr_len.next_multiple_of(4)).min(rest.len())..)?;
+    }
+    Some(mapped_addr.ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            "The answer of the STUN server contains no address.",
+        )
+    }))
+}
+
+/// Parses the value of a (XOR-)MAPPED-ADDRESS attribute. The XORed variant is given the transaction id.
+fn parse_address(value: &[u8], xor_transaction_id: Option<&[u8; 12]>) -> Option<SocketAddr> {
+    let mut mask = [0; 18];
+    if let Some(transaction_id) = xor_transaction_id {
+        // the port is XORed with the upper half of the magic cookie, the address with the cookie and the transaction id
+        mask[0..2].copy_from_slice(&MAGIC_COOKIE.to_be_bytes()[0..2]);
+        mask[2..6].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[6..18].copy_from_slice(transaction_id);
+    }
+    let family = *value.get(1)?;
+    let port = u16::from_be_bytes([value.get(2)? ^ mask[0], value.get(3)? ^ mask[1]]);
+    let ip = match family {
+        FAMILY_IPV4 => {
+            let mut octets: [u8; 4] = value.get(4..8)?.try_into().ok()?;
+            octets.iter_mut().zip(&mask[2..]).for_each(|(o, m)| *o ^= m);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        FAMILY_IPV6 => {
+            let mut octets: [u8; 16] = value.get(4..20)?.try_into().ok()?;
+            octets.iter_mut().zip(&mask[2..]).for_each(|(o, m)| *o ^= m);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod stun_tests {
+    use super::*;
+
+    const TRANSACTION_ID: [u8; 12] = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    /// The sample IPv4 response of RFC 5769, section 2.2.
+    const RESPONSE_IPV4: [u8; 80] = [
+        0x01, 0x01, 0x00, 0x3c, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6,
+        0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74, 0x20, 0x76,
+        0x65, 0x63, 0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1,
+        0x12, 0xa6, 0x43, 0x00, 0x08, 0x00, 0x14, 0x2b, 0x91, 0xf5, 0x99, 0xfd, 0x9e, 0x90, 0xc3,
+        0x8c, 0x74, 0x89, 0xf9, 0x2a, 0xf9, 0xba, 0x53, 0xf0, 0x6b, 0xe7, 0xd7, 0x80, 0x28, 0x00,
+        0x04, 0xc0, 0x7d, 0x4c, 0x96,
+    ];
+
+    /// The XOR-MAPPED-ADDRESS attribute of the sample IPv6 response of RFC 5769, section 2.3.
+    const XOR_MAPPED_IPV6: [u8; 24] = [
+        0x00, 0x20, 0x00, 0x14, 0x00, 0x02, 0xa1, 0x47, 0x01, 0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1,
+        0x79, 0xbc, 0x25, 0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+    ];
+
+    fn response(message_type: u16, attributes: &[u8]) -> Vec<u8> {
+        let mut bytes = binding_request(&TRANSACTION_ID).to_vec();
+        bytes[0..2].copy_from_slice(&message_type.to_be_bytes());
+        bytes[2..4].copy_from_slice(&(attributes.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(attributes);
+        bytes
+    }
+
+    #[test]
+    fn test_binding_request() {
+        let request = binding_request(&TRANSACTION_ID);
+        assert_eq!(
+            request[0..8],
+            [0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42]
+        );
+        assert_eq!(request[8..], TRANSACTION_ID);
+    }
+
+    #[test]
+    fn test_parse_rfc_5769_responses() {
+        let addr = parse_binding_response(&RESPONSE_IPV4, &TRANSACTION_ID)
+            .unwrap()
+            .unwrap();
+        assert_eq!(addr, "192.0.2.1:32853".parse().unwrap());
+
+        let bytes = response(BINDING_SUCCESS, &XOR_MAPPED_IPV6);
+        let addr = parse_binding_response(&bytes, &TRANSACTION_ID)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            addr,
+            "[2001:db8:1234:5678:11:2233:4455:6677]:32853"
+                .parse()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_mapped_address() {
+        let mapped = [
+            0x00, 0x01, 0x00, 0x08, 0x00, 0x01, 0x1b, 0x58, 203, 0, 113, 7,
+        ];
+        let bytes = response(BINDING_SUCCESS, &mapped);
+        let addr = parse_binding_response(&bytes, &TRANSACTION_ID)
+            .unwrap()
+            .unwrap();
+        assert_eq!(addr, "203.0.113.7:7000".parse().unwrap());
+    }
+
+    #[test]
+    fn test_ignore_other_packets() {
+        let mut other_transaction = RESPONSE_IPV4;
+        other_transaction[8] ^= 1;
+        assert!(parse_binding_response(&other_transaction, &TRANSACTION_ID).is_none());
+        assert!(parse_binding_response(&RESPONSE_IPV4[..19], &TRANSACTION_ID).is_none());
+        // the message length exceeds the packet
+        assert!(parse_binding_response(&RESPONSE_IPV4[..60], &TRANSACTION_ID).is_none());
+
+        let error = response(BINDING_ERROR, &[]);
+        assert!(parse_binding_response(&error, &TRANSACTION_ID)
+            .unwrap()
+            .is_err());
+        let empty = response(BINDING_SUCCESS, &[]);
+        assert!(parse_binding_response(&empty, &TRANSACTION_ID)
+            .unwrap()
+            .is_err());
+    }
+}
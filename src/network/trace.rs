@@ -0,0 +1,218 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+
+use crate::{Clock, Message, NonBlockingSocket};
+
+/// Whether a traced [`Message`] was received from or sent to a remote client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PacketDirection {
+    /// The message was received from the remote client.
+    Inbound,
+    /// The message was sent to the remote client.
+    Outbound,
+}
+
+/// A single message exchanged with a remote client, as reported to a [`PacketObserver`]. Since it can be serialized with any
+/// [`serde`] format, writing one [`PacketTrace`] per line as JSON gives a log that can be loaded into a timeline viewer.
+/// Requires the `net-trace` feature.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacketTrace<A> {
+    /// The time the message was sent or received, in ms since the unix epoch.
+    pub timestamp: u128,
+    /// Whether the message was received or sent.
+    pub direction: PacketDirection,
+    /// The address of the remote client the message was received from or sent to.
+    pub peer: A,
+    /// The decoded message.
+    pub message: Message,
+}
+
+/// A [`PacketObserver`] is notified of every message a session sends and receives, see
+/// [`SessionBuilder::with_packet_observer()`]. It is implemented for all closures taking a [`PacketTrace`].
+/// Requires the `net-trace` feature.
+///
+/// [`SessionBuilder::with_packet_observer()`]: crate::SessionBuilder::with_packet_observer
+#[cfg(feature = "sync-send")]
+pub trait PacketObserver<A>: Send + Sync {
+    /// Called with every message right after it was sent or received.
+    fn observe(&mut self, trace: &PacketTrace<A>);
+}
+
+/// A [`PacketObserver`] is notified of every message a session sends and receives, see
+/// [`SessionBuilder::with_packet_observer()`]. It is implemented for all closures taking a [`PacketTrace`].
+/// Requires the `net-trace` feature.
+///
+/// [`SessionBuilder::with_packet_observer()`]: crate::SessionBuilder::with_packet_observer
+#[cfg(not(feature = "sync-send"))]
+pub trait PacketObserver<A> {
+    /// Called with every message right after it was sent or received.
+    fn observe(&mut self, trace: &PacketTrace<A>);
+}
+
+#[cfg(feature = "sync-send")]
+impl<A, F> PacketObserver<A> for F
+where
+    F: FnMut(&PacketTrace<A>) + Send + Sync,
+{
+    fn observe(&mut self, trace: &PacketTrace<A>) {
+        self(trace)
+    }
+}
+
+#[cfg(not(feature = "sync-send"))]
+impl<A, F> PacketObserver<A> for F
+where
+    F: FnMut(&PacketTrace<A>),
+{
+    fn observe(&mut self, trace: &PacketTrace<A>) {
+        self(trace)
+    }
+}
+
+impl<A> Debug for dyn PacketObserver<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PacketObserver")
+    }
+}
+
+/// Wraps the socket of a session and reports every message passing through it to a [`PacketObserver`].
+pub(crate) struct TracedSocket<A> {
+    socket: Box<dyn NonBlockingSocket<A>>,
+    observer: Box<dyn PacketObserver<A>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<A> TracedSocket<A> {
+    pub(crate) fn new(
+        socket: Box<dyn NonBlockingSocket<A>>,
+        observer: Box<dyn PacketObserver<A>>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            socket,
+            observer,
+            clock,
+        }
+    }
+
+    /// Replaces the wrapped socket, while the observer keeps observing the messages of the new one.
+    pub(crate) fn replace_socket(&mut self, socket: Box<dyn NonBlockingSocket<A>>) {
+        self.socket = socket;
+    }
+
+    fn trace(&mut self, direction: PacketDirection, peer: &A, message: &Message)
+    where
+        A: Clone,
+    {
+        self.observer.observe(&PacketTrace {
+            timestamp: self.clock.millis_since_epoch(),
+            direction,
+            peer: peer.clone(),
+            message: message.clone(),
+        });
+    }
+}
+
+#[cfg(feature = "sync-send")]
+impl<A> NonBlockingSocket<A> for TracedSocket<A>
+where
+    A: Clone + PartialEq + Eq + Hash + Send + Sync,
+{
+    fn send_to(&mut self, msg: &Message, addr: &A) {
+        self.socket.send_to(msg, addr);
+        self.trace(PacketDirection::Outbound, addr, msg);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(A, Message)> {
+        let received = self.socket.receive_all_messages();
+        for (addr, msg) in &received {
+            self.trace(PacketDirection::Inbound, addr, msg);
+        }
+        received
+    }
+
+    fn receive_messages(&mut self, max_messages: usize, max_bytes: usize) -> Vec<(A, Message)> {
+        let received = self.socket.receive_messages(max_messages, max_bytes);
+        for (addr, msg) in &received {
+            self.trace(PacketDirection::Inbound, addr, msg);
+        }
+        received
+    }
+
+    fn receive_messages_filtered(
+        &mut self,
+        max_messages: usize,
+        max_bytes: usize,
+        accept: &mut dyn FnMut(&A) -> bool,
+    ) -> Vec<(A, Message)> {
+        let received = self
+            .socket
+            .receive_messages_filtered(max_messages, max_bytes, accept);
+        for (addr, msg) in &received {
+            self.trace(PacketDirection::Inbound, addr, msg);
+        }
+        received
+    }
+
+    fn take_errors(&mut self) -> Vec<std::io::ErrorKind> {
+        self.socket.take_errors()
+    }
+
+    fn local_addr(&self) -> Option<A> {
+        self.socket.local_addr()
+    }
+}
+
+#[cfg(not(feature = "sync-send"))]
+impl<A> NonBlockingSocket<A> for TracedSocket<A>
+where
+    A: Clone + PartialEq + Eq + Hash,
+{
+    fn send_to(&mut self, msg: &Message, addr: &A) {
+        self.socket.send_to(msg, addr);
+        self.trace(PacketDirection::Outbound, addr, msg);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(A, Message)> {
+        let received = self.socket.receive_all_messages();
+        for (addr, msg) in &received {
+            self.trace(PacketDirection::Inbound, addr, msg);
+        }
+        received
+    }
+
+    fn receive_messages(&mut self, max_messages: usize, max_bytes: usize) -> Vec<(A, Message)> {
+        let received = self.socket.receive_messages(max_messages, max_bytes);
+        for (addr, msg) in &received {
+            self.trace(PacketDirection::Inbound, addr, msg);
+        }
+        received
+    }
+
+    fn receive_messages_filtered(
+        &mut self,
+        max_messages: usize,
+        max_bytes: usize,
+        accept: &mut dyn FnMut(&A) -> bool,
+    ) -> Vec<(A, Message)> {
+        let received = self
+            .socket
+            .receive_messages_filtered(max_messages, max_bytes, accept);
+        for (addr, msg) in &received {
+            self.trace(PacketDirection::Inbound, addr, msg);
+        }
+        received
+    }
+
+    fn take_errors(&mut self) -> Vec<std::io::ErrorKind> {
+        self.socket.take_errors()
+    }
+
+    fn local_addr(&self) -> Option<A> {
+        self.socket.local_addr()
+    }
+}
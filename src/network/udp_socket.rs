@@ -3,53 +3,122 @@ use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
 };
 
-use crate::{network::messages::Message, NonBlockingSocket};
+use crate::{
+    network::messages::{Message, MAX_PACKET_SIZE},
+    network::wire::parse_packet,
+    NonBlockingSocket,
+};
 
-const RECV_BUFFER_SIZE: usize = 4096;
+const RECV_BUFFER_SIZE: usize = MAX_PACKET_SIZE;
 
 /// A simple non-blocking UDP socket tu use with GGRS Sessions. Listens to 0.0.0.0 on a given port.
 #[derive(Debug)]
 pub struct UdpNonBlockingSocket {
     socket: UdpSocket,
     buffer: [u8; RECV_BUFFER_SIZE],
+    send_buffer: Vec<u8>,
+    errors: Vec<ErrorKind>,
 }
 
 impl UdpNonBlockingSocket {
     /// Binds an UDP Socket to 0.0.0.0:port and set it to non-blocking mode.
     pub fn bind_to_port(port: u16) -> Result<Self, std::io::Error> {
-        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+        Self::bind_to_addr(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port))
+    }
+
+    /// Binds an UDP Socket to the given address and set it to non-blocking mode. Use this to listen on a specific interface.
+    pub fn bind_to_addr(addr: SocketAddr) -> Result<Self, std::io::Error> {
         let socket = UdpSocket::bind(addr)?;
         socket.set_nonblocking(true)?;
         Ok(Self {
             socket,
             buffer: [0; RECV_BUFFER_SIZE],
+            send_buffer: Vec::with_capacity(RECV_BUFFER_SIZE),
+            errors: Vec::new(),
         })
     }
+
+    /// Returns the wrapped socket, e.g. to exchange packets with a STUN server before the session starts.
+    #[cfg(feature = "stun")]
+    pub(crate) fn udp_socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    fn record_error(&mut self, kind: ErrorKind) {
+        if !self.errors.contains(&kind) {
+            self.errors.push(kind);
+        }
+    }
 }
 
 impl NonBlockingSocket<SocketAddr> for UdpNonBlockingSocket {
     fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
-        let buf = bincode::serialize(&msg).unwrap();
-        self.socket.send_to(&buf, addr).unwrap();
+        self.send_buffer.clear();
+        msg.encode_into(&mut self.send_buffer);
+        // UDP is unreliable anyways, so if sending fails the message is lost just like any other dropped packet,
+        // but the error is reported, since the socket might be broken
+        if let Err(err) = self.socket.send_to(&self.send_buffer, addr) {
+            if err.kind() != ErrorKind::WouldBlock {
+                self.record_error(err.kind());
+            }
+        }
     }
 
     fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        self.receive_messages(usize::MAX, usize::MAX)
+    }
+
+    fn receive_messages(
+        &mut self,
+        max_messages: usize,
+        max_bytes: usize,
+    ) -> Vec<(SocketAddr, Message)> {
+        self.receive_messages_filtered(max_messages, max_bytes, &mut |_| true)
+    }
+
+    fn receive_messages_filtered(
+        &mut self,
+        max_messages: usize,
+        max_bytes: usize,
+        accept: &mut dyn FnMut(&SocketAddr) -> bool,
+    ) -> Vec<(SocketAddr, Message)> {
         let mut received_messages = Vec::new();
-        loop {
+        let mut received_datagrams = 0;
+        let mut received_bytes = 0;
+        // datagrams over the budget stay in the buffer of the operating system, so they are not even parsed
+        while received_datagrams < max_messages && received_bytes < max_bytes {
             match self.socket.recv_from(&mut self.buffer) {
                 Ok((number_of_bytes, src_addr)) => {
                     assert!(number_of_bytes <= RECV_BUFFER_SIZE);
-                    if let Ok(msg) = bincode::deserialize(&self.buffer[0..number_of_bytes]) {
+                    received_datagrams += 1;
+                    received_bytes += number_of_bytes;
+                    // datagrams of senders the session does not accept are not even parsed
+                    if !accept(&src_addr) {
+                        continue;
+                    }
+                    if let Ok(msg) = parse_packet(&self.buffer[0..number_of_bytes]) {
                         received_messages.push((src_addr, msg));
                     }
                 }
                 // there are no more messages
-                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return received_messages,
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
                 // datagram socket sometimes get this error as a result of calling the send_to method
                 Err(ref err) if err.kind() == ErrorKind::ConnectionReset => continue,
-                // all other errors cause a panic
-                Err(err) => panic!("{:?}: {} on {:?}", err.kind(), err, &self.socket),
+                // all other errors are reported, the socket might be broken and need to be replaced
+                Err(err) => {
+                    self.record_error(err.kind());
+                    break;
+                }
             }
         }
+        received_messages
+    }
+
+    fn take_errors(&mut self) -> Vec<ErrorKind> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
     }
 }
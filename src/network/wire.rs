@@ -0,0 +1,869 @@
+//! The wire format of all messages GGRS exchanges between clients.
+//!
+//! The layout is frozen per [`PROTOCOL_VERSION`]: clients built with different GGRS versions can play together as long as
+//! they share the protocol version. A release that changes the layout below bumps the version once, which peers exchange
+//! during synchronization and refuse to connect on mismatch. The golden-bytes tests of this module pin the layout and run with
+//! the `wire-compat` feature.
+//!
+//! The encoding is byte-compatible with the default `bincode` format: little-endian fixed size integers, `u32` enum tags,
+//! `u64` length prefixes for sequences and a `u8` tag (0 or 1) before optional values. Every packet holds one message:
+//!
+//! | field           | encoding                                   |
+//! |-----------------|--------------------------------------------|
+//! | `magic`         | `u16`                                      |
+//! | `session_token` | optional `[u8; 16]`                        |
+//! | message tag     | `u32`, see below                           |
+//! | message body    | the fields of the message, in order        |
+//!
+//! | tag | message          | body                                                                                          |
+//! |-----|------------------|-----------------------------------------------------------------------------------------------|
+//! | 0   | `SyncRequest`    | `random_request: u32`, `protocol_version: u32`                                                |
+//! | 1   | `SyncReply`      | `random_reply: u32`, `delay_based: bool`, `fps: u32`, `time: u64`, `player_ids: [(u64, u128)]`, |
+//! |     |                  | `payload: [u8]`, `input_delay: optional u32`, `round_trip_time: u32`,                         |
+//! |     |                  | `external_addr: optional address` (see below), `start_frame: i32`, `input_codec: u32`,        |
+//! |     |                  | `variable_length_inputs: bool`, `synchronized_start: bool`                                    |
+//! | 2   | `Input`          | `peer_connect_status: [(bool, optional i32)]`, `disconnect_requested: bool`,                   |
+//! |     |                  | `start_frame: i32`, `ack_frame: optional i32`, `sent_at: u64`, `bytes: [u8]`,                 |
+//! |     |                  | `metadata_frame: optional i32`, `metadata: [u8]`                                              |
+//! | 3   | `InputAck`       | `ack_frame: optional i32`                                                                     |
+//! | 4   | `QualityReport`  | `frame_advantage: i8`, `ping: u128`                                                           |
+//! | 5   | `QualityReply`   | `pong: u128`                                                                                  |
+//! | 6   | `ChecksumReport` | `checksum: u128`, `frame: i32`                                                                |
+//! | 7   | `KeepAlive`      |                                                                                               |
+//! | 8   | `Reliable`       | `seq: u32`, `oldest_pending: u32`, payload tag `u32`, payload, see below                      |
+//! | 9   | `ReliableAck`    | `ack: u32`                                                                                    |
+//! | 10  | `MtuProbe`       | `size: u32`, `padding: [u8]`                                                                  |
+//! | 11  | `MtuProbeAck`    | `size: u32`                                                                                   |
+//!
+//! | payload tag | payload           | body                                           |
+//! |-------------|-------------------|------------------------------------------------|
+//! | 0           | `StartTime`       | `time: u64`                                    |
+//! | 1           | `User`            | `data: [u8]`                                   |
+//! | 2           | `HandOverRequest` | `handle: u64`, `frame: i32`                    |
+//! | 3           | `HandleChange`    | `handle: u64`, `frame: i32`, `added: bool`     |
+//! | 4           | `InputDelay`      | `delay: u32`                                   |
+//!
+//! | address tag | address | body                                 |
+//! |-------------|---------|--------------------------------------|
+//! | 0           | IPv4    | `ip: [u8; 4]` (no length), `port: u16`  |
+//! | 1           | IPv6    | `ip: [u8; 16]` (no length), `port: u16` |
+//!
+//! # Byte order
+//!
+//! All multi-byte fields, i.e. the header, frames, checksums, timestamps and length prefixes, are little-endian on every
+//! platform, independent of the byte order of the host, so clients on little- and big-endian machines understand each other.
+//! The same holds for the `u16` length prefixes of the frames in `Input::metadata`. The only exception are the inputs
+//! themselves: `Input::bytes` holds them as [`bytemuck`] lays them out in memory, see [`Config::Input`].
+//!
+//! # Inputs
+//!
+//! `Input::bytes` holds the inputs of consecutive frames, each the inputs of all players the sender sends for, XORed with the
+//! input of the frame before `start_frame` and then RLE encoded with `bitfield_rle`. If the inputs of at least 4 players of at
+//! most 8 bytes each are sent, like the inputs a host broadcasts to spectators, the bits of every frame are packed into
+//! bitplanes before the RLE encoding: bit `b` of the input of player `p` moves to bit `b * players + p` of the frame, counting
+//! from the lowest bit of the first byte. With variable length inputs, every input is prefixed by its length as `u16` instead
+//! and RLE encoded without the XOR. Finally, the [`Codec`] of the session compresses the result.
+//!
+//! [`Codec`]: crate::Codec
+//! [`Config::Input`]: crate::Config::Input
+
+use crate::network::messages::{
+    ChecksumReport, ConnectionStatus, Input, InputAck, Message, MessageBody, MessageHeader,
+    MtuProbe, MtuProbeAck, QualityReply, QualityReport, Reliable, ReliableAck, ReliablePayload,
+    SyncReply, SyncRequest, MAX_PACKET_SIZE,
+};
+use crate::{Frame, PacketError};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// The version of the wire format described in the [module documentation](self). Peers only synchronize with each other
+/// if they use the same protocol version. Only bumped once per release that changes the layout, relative to the previous
+/// release.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const TAG_SYNC_REQUEST: u32 = 0;
+const TAG_SYNC_REPLY: u32 = 1;
+const TAG_INPUT: u32 = 2;
+const TAG_INPUT_ACK: u32 = 3;
+const TAG_QUALITY_REPORT: u32 = 4;
+const TAG_QUALITY_REPLY: u32 = 5;
+const TAG_CHECKSUM_REPORT: u32 = 6;
+const TAG_KEEP_ALIVE: u32 = 7;
+const TAG_RELIABLE: u32 = 8;
+const TAG_RELIABLE_ACK: u32 = 9;
+const TAG_MTU_PROBE: u32 = 10;
+const TAG_MTU_PROBE_ACK: u32 = 11;
+
+const TAG_PAYLOAD_START_TIME: u32 = 0;
+const TAG_PAYLOAD_USER: u32 = 1;
+const TAG_PAYLOAD_HAND_OVER_REQUEST: u32 = 2;
+const TAG_PAYLOAD_HANDLE_CHANGE: u32 = 3;
+const TAG_PAYLOAD_INPUT_DELAY: u32 = 4;
+
+const ADDR_V4: u32 = 0;
+const ADDR_V6: u32 = 1;
+
+impl Message {
+    /// Appends the encoded message to `buffer`. Reusing the same buffer for multiple messages avoids allocating for every packet.
+    pub fn encode_into(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.header.magic.to_le_bytes());
+        match &self.header.session_token {
+            Some(token) => {
+                buffer.push(1);
+                buffer.extend_from_slice(token);
+            }
+            None => buffer.push(0),
+        }
+        match &self.body {
+            MessageBody::SyncRequest(body) => {
+                buffer.extend_from_slice(&TAG_SYNC_REQUEST.to_le_bytes());
+                buffer.extend_from_slice(&body.random_request.to_le_bytes());
+                buffer.extend_from_slice(&body.protocol_version.to_le_bytes());
+            }
+            MessageBody::SyncReply(body) => {
+                buffer.extend_from_slice(&TAG_SYNC_REPLY.to_le_bytes());
+                buffer.extend_from_slice(&body.random_reply.to_le_bytes());
+                buffer.push(body.delay_based as u8);
+                buffer.extend_from_slice(&body.fps.to_le_bytes());
+                buffer.extend_from_slice(&body.time.to_le_bytes());
+                buffer.extend_from_slice(&(body.player_ids.len() as u64).to_le_bytes());
+                for (handle, id) in &body.player_ids {
+                    buffer.extend_from_slice(&handle.to_le_bytes());
+                    buffer.extend_from_slice(&id.to_le_bytes());
+                }
+                buffer.extend_from_slice(&(body.payload.len() as u64).to_le_bytes());
+                buffer.extend_from_slice(&body.payload);
+                match body.input_delay {
+                    Some(delay) => {
+                        buffer.push(1);
+                        buffer.extend_from_slice(&delay.to_le_bytes());
+                    }
+                    None => buffer.push(0),
+                }
+                buffer.extend_from_slice(&body.round_trip_time.to_le_bytes());
+                match body.external_addr {
+                    Some(SocketAddr::V4(addr)) => {
+                        buffer.push(1);
+                        buffer.extend_from_slice(&ADDR_V4.to_le_bytes());
+                        buffer.extend_from_slice(&addr.ip().octets());
+                        buffer.extend_from_slice(&addr.port().to_le_bytes());
+                    }
+                    Some(SocketAddr::V6(addr)) => {
+                        buffer.push(1);
+                        buffer.extend_from_slice(&ADDR_V6.to_le_bytes());
+                        buffer.extend_from_slice(&addr.ip().octets());
+                        buffer.extend_from_slice(&addr.port().to_le_bytes());
+                    }
+                    None => buffer.push(0),
+                }
+                buffer.extend_from_slice(&body.start_frame.to_le_bytes());
+                buffer.extend_from_slice(&body.input_codec.to_le_bytes());
+                buffer.push(body.variable_length_inputs as u8);
+                buffer.push(body.synchronized_start as u8);
+            }
+            MessageBody::Input(body) => {
+                buffer.extend_from_slice(&TAG_INPUT.to_le_bytes());
+                buffer.extend_from_slice(&(body.peer_connect_status.len() as u64).to_le_bytes());
+                for status in &body.peer_connect_status {
+                    buffer.push(status.disconnected as u8);
+                    encode_optional_frame(buffer, status.last_frame);
+                }
+                buffer.push(body.disconnect_requested as u8);
+                buffer.extend_from_slice(&body.start_frame.to_le_bytes());
+                encode_optional_frame(buffer, body.ack_frame);
+                buffer.extend_from_slice(&body.sent_at.to_le_bytes());
+                buffer.extend_from_slice(&(body.bytes.len() as u64).to_le_bytes());
+                buffer.extend_from_slice(&body.bytes);
+                encode_optional_frame(buffer, body.metadata_frame);
+                buffer.extend_from_slice(&(body.metadata.len() as u64).to_le_bytes());
+                buffer.extend_from_slice(&body.metadata);
+            }
+            MessageBody::InputAck(body) => {
+                buffer.extend_from_slice(&TAG_INPUT_ACK.to_le_bytes());
+                encode_optional_frame(buffer, body.ack_frame);
+            }
+            MessageBody::QualityReport(body) => {
+                buffer.extend_from_slice(&TAG_QUALITY_REPORT.to_le_bytes());
+                buffer.extend_from_slice(&body.frame_advantage.to_le_bytes());
+                buffer.extend_from_slice(&body.ping.to_le_bytes());
+            }
+            MessageBody::QualityReply(body) => {
+                buffer.extend_from_slice(&TAG_QUALITY_REPLY.to_le_bytes());
+                buffer.extend_from_slice(&body.pong.to_le_bytes());
+            }
+            MessageBody::ChecksumReport(body) => {
+                buffer.extend_from_slice(&TAG_CHECKSUM_REPORT.to_le_bytes());
+                buffer.extend_from_slice(&body.checksum.to_le_bytes());
+                buffer.extend_from_slice(&body.frame.to_le_bytes());
+            }
+            MessageBody::KeepAlive => {
+                buffer.extend_from_slice(&TAG_KEEP_ALIVE.to_le_bytes());
+            }
+            MessageBody::Reliable(body) => {
+                buffer.extend_from_slice(&TAG_RELIABLE.to_le_bytes());
+                buffer.extend_from_slice(&body.seq.to_le_bytes());
+                buffer.extend_from_slice(&body.oldest_pending.to_le_bytes());
+                match &body.payload {
+                    ReliablePayload::StartTime(time) => {
+                        buffer.extend_from_slice(&TAG_PAYLOAD_START_TIME.to_le_bytes());
+                        buffer.extend_from_slice(&time.to_le_bytes());
+                    }
+                    ReliablePayload::User(data) => {
+                        buffer.extend_from_slice(&TAG_PAYLOAD_USER.to_le_bytes());
+                        buffer.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                        buffer.extend_from_slice(data);
+                    }
+                    ReliablePayload::HandOverRequest { handle, frame } => {
+                        buffer.extend_from_slice(&TAG_PAYLOAD_HAND_OVER_REQUEST.to_le_bytes());
+                        buffer.extend_from_slice(&handle.to_le_bytes());
+                        buffer.extend_from_slice(&frame.to_le_bytes());
+                    }
+                    ReliablePayload::HandleChange {
+                        handle,
+                        frame,
+                        added,
+                    } => {
+                        buffer.extend_from_slice(&TAG_PAYLOAD_HANDLE_CHANGE.to_le_bytes());
+                        buffer.extend_from_slice(&handle.to_le_bytes());
+                        buffer.extend_from_slice(&frame.to_le_bytes());
+                        buffer.push(u8::from(*added));
+                    }
+                    ReliablePayload::InputDelay(delay) => {
+                        buffer.extend_from_slice(&TAG_PAYLOAD_INPUT_DELAY.to_le_bytes());
+                        buffer.extend_from_slice(&delay.to_le_bytes());
+                    }
+                }
+            }
+            MessageBody::ReliableAck(body) => {
+                buffer.extend_from_slice(&TAG_RELIABLE_ACK.to_le_bytes());
+                buffer.extend_from_slice(&body.ack.to_le_bytes());
+            }
+            MessageBody::MtuProbe(body) => {
+                buffer.extend_from_slice(&TAG_MTU_PROBE.to_le_bytes());
+                buffer.extend_from_slice(&body.size.to_le_bytes());
+                buffer.extend_from_slice(&(body.padding.len() as u64).to_le_bytes());
+                buffer.extend_from_slice(&body.padding);
+            }
+            MessageBody::MtuProbeAck(body) => {
+                buffer.extend_from_slice(&TAG_MTU_PROBE_ACK.to_le_bytes());
+                buffer.extend_from_slice(&body.size.to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Appends a frame that might be missing, like the last frame of a player without inputs yet.
+fn encode_optional_frame(buffer: &mut Vec<u8>, frame: Option<Frame>) {
+    match frame {
+        Some(frame) => {
+            buffer.push(1);
+            buffer.extend_from_slice(&frame.to_le_bytes());
+        }
+        None => buffer.push(0),
+    }
+}
+
+/// Parses a message previously encoded with [`Message::encode_into()`]. Intended for packets received from untrusted sources:
+/// it never panics and allocates at most as many bytes as the packet is long.
+/// # Errors
+/// - Returns a [`PacketError`] if the bytes do not form exactly one valid message.
+pub fn parse_packet(bytes: &[u8]) -> Result<Message, PacketError> {
+    if bytes.len() > MAX_PACKET_SIZE {
+        return Err(PacketError::Oversized {
+            len: bytes.len(),
+            max: MAX_PACKET_SIZE,
+        });
+    }
+
+    let mut reader = Reader::new(bytes);
+    let header = MessageHeader {
+        magic: u16::from_le_bytes(reader.array()?),
+        session_token: match reader.array::<1>()? {
+            [0] => None,
+            [1] => Some(reader.array()?),
+            [value] => return Err(PacketError::InvalidOptionTag { value }),
+        },
+    };
+    let body = match u32::from_le_bytes(reader.array()?) {
+        TAG_SYNC_REQUEST => MessageBody::SyncRequest(SyncRequest {
+            random_request: u32::from_le_bytes(reader.array()?),
+            protocol_version: u32::from_le_bytes(reader.array()?),
+        }),
+        TAG_SYNC_REPLY => {
+            let random_reply = u32::from_le_bytes(reader.array()?);
+            let delay_based = reader.bool()?;
+            let fps = u32::from_le_bytes(reader.array()?);
+            let time = u64::from_le_bytes(reader.array()?);
+            let num_ids = reader.len()?;
+            let mut player_ids = Vec::with_capacity(num_ids.min(reader.remaining()));
+            for _ in 0..num_ids {
+                player_ids.push((
+                    u64::from_le_bytes(reader.array()?),
+                    u128::from_le_bytes(reader.array()?),
+                ));
+            }
+            let num_payload = reader.len()?;
+            let payload = reader.slice(num_payload)?.to_vec();
+            let input_delay = match reader.array::<1>()? {
+                [0] => None,
+                [1] => Some(u32::from_le_bytes(reader.array()?)),
+                [value] => return Err(PacketError::InvalidOptionTag { value }),
+            };
+            let round_trip_time = u32::from_le_bytes(reader.array()?);
+            let external_addr = match reader.array::<1>()? {
+                [0] => None,
+                [1] => Some(match u32::from_le_bytes(reader.array()?) {
+                    ADDR_V4 => SocketAddr::new(
+                        Ipv4Addr::from(reader.array::<4>()?).into(),
+                        u16::from_le_bytes(reader.array()?),
+                    ),
+                    ADDR_V6 => SocketAddr::new(
+                        Ipv6Addr::from(reader.array::<16>()?).into(),
+                        u16::from_le_bytes(reader.array()?),
+                    ),
+                    tag => return Err(PacketError::UnknownAddressFamily { tag }),
+                }),
+                [value] => return Err(PacketError::InvalidOptionTag { value }),
+            };
+            let start_frame = i32::from_le_bytes(reader.array()?);
+            let input_codec = u32::from_le_bytes(reader.array()?);
+            let variable_length_inputs = reader.bool()?;
+            let synchronized_start = reader.bool()?;
+            MessageBody::SyncReply(SyncReply {
+                random_reply,
+                delay_based,
+                fps,
+                time,
+                player_ids,
+                payload,
+                input_delay,
+                round_trip_time,
+                external_addr,
+                start_frame,
+                input_codec,
+                variable_length_inputs,
+                synchronized_start,
+            })
+        }
+        TAG_INPUT => {
+            let num_status = reader.len()?;
+            let mut peer_connect_status = Vec::with_capacity(num_status.min(reader.remaining()));
+            for _ in 0..num_status {
+                peer_connect_status.push(ConnectionStatus {
+                    disconnected: reader.bool()?,
+                    last_frame: reader.optional_frame()?,
+                });
+            }
+            let disconnect_requested = reader.bool()?;
+            let start_frame = Frame::from_le_bytes(reader.array()?);
+            let ack_frame = reader.optional_frame()?;
+            let sent_at = u64::from_le_bytes(reader.array()?);
+            let num_bytes = reader.len()?;
+            let bytes = reader.slice(num_bytes)?.to_vec();
+            let metadata_frame = reader.optional_frame()?;
+            let num_metadata = reader.len()?;
+            let metadata = reader.slice(num_metadata)?.to_vec();
+            MessageBody::Input(Input {
+                peer_connect_status,
+                disconnect_requested,
+                start_frame,
+                ack_frame,
+                sent_at,
+                bytes,
+                metadata_frame,
+                metadata,
+            })
+        }
+        TAG_INPUT_ACK => MessageBody::InputAck(InputAck {
+            ack_frame: reader.optional_frame()?,
+        }),
+        TAG_QUALITY_REPORT => MessageBody::QualityReport(QualityReport {
+            frame_advantage: i8::from_le_bytes(reader.array()?),
+            ping: u128::from_le_bytes(reader.array()?),
+        }),
+        TAG_QUALITY_REPLY => MessageBody::QualityReply(QualityReply {
+            pong: u128::from_le_bytes(reader.array()?),
+        }),
+        TAG_CHECKSUM_REPORT => MessageBody::ChecksumReport(ChecksumReport {
+            checksum: u128::from_le_bytes(reader.array()?),
+            frame: Frame::from_le_bytes(reader.array()?),
+        }),
+        TAG_KEEP_ALIVE => MessageBody::KeepAlive,
+        TAG_RELIABLE => {
+            let seq = u32::from_le_bytes(reader.array()?);
+            let oldest_pending = u32::from_le_bytes(reader.array()?);
+            let payload = match u32::from_le_bytes(reader.array()?) {
+                TAG_PAYLOAD_START_TIME => {
+                    ReliablePayload::StartTime(u64::from_le_bytes(reader.array()?))
+                }
+                TAG_PAYLOAD_USER => {
+                    let num_data = reader.len()?;
+                    ReliablePayload::User(reader.slice(num_data)?.to_vec())
+                }
+                TAG_PAYLOAD_HAND_OVER_REQUEST => ReliablePayload::HandOverRequest {
+                    handle: u64::from_le_bytes(reader.array()?),
+                    frame: Frame::from_le_bytes(reader.array()?),
+                },
+                TAG_PAYLOAD_HANDLE_CHANGE => ReliablePayload::HandleChange {
+                    handle: u64::from_le_bytes(reader.array()?),
+                    frame: Frame::from_le_bytes(reader.array()?),
+                    added: reader.bool()?,
+                },
+                TAG_PAYLOAD_INPUT_DELAY => {
+                    ReliablePayload::InputDelay(u32::from_le_bytes(reader.array()?))
+                }
+                tag => return Err(PacketError::UnknownMessageType { tag }),
+            };
+            MessageBody::Reliable(Reliable {
+                seq,
+                oldest_pending,
+                payload,
+            })
+        }
+        TAG_RELIABLE_ACK => MessageBody::ReliableAck(ReliableAck {
+            ack: u32::from_le_bytes(reader.array()?),
+        }),
+        TAG_MTU_PROBE => {
+            let size = u32::from_le_bytes(reader.array()?);
+            let num_padding = reader.len()?;
+            MessageBody::MtuProbe(MtuProbe {
+                size,
+                padding: reader.slice(num_padding)?.to_vec(),
+            })
+        }
+        TAG_MTU_PROBE_ACK => MessageBody::MtuProbeAck(MtuProbeAck {
+            size: u32::from_le_bytes(reader.array()?),
+        }),
+        tag => return Err(PacketError::UnknownMessageType { tag }),
+    };
+
+    if reader.remaining() > 0 {
+        return Err(PacketError::TrailingBytes {
+            len: reader.remaining(),
+        });
+    }
+
+    Ok(Message { header, body })
+}
+
+/// Reads values from the front of a byte slice, failing if the slice is too short.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub(crate) fn slice(&mut self, len: usize) -> Result<&'a [u8], PacketError> {
+        if len > self.bytes.len() {
+            return Err(PacketError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], PacketError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.slice(N)?);
+        Ok(array)
+    }
+
+    fn bool(&mut self) -> Result<bool, PacketError> {
+        match self.array::<1>()? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            [value] => Err(PacketError::InvalidBool { value }),
+        }
+    }
+
+    fn optional_frame(&mut self) -> Result<Option<Frame>, PacketError> {
+        match self.array::<1>()? {
+            [0] => Ok(None),
+            [1] => Ok(Some(Frame::from_le_bytes(self.array()?))),
+            [value] => Err(PacketError::InvalidOptionTag { value }),
+        }
+    }
+
+    fn len(&mut self) -> Result<usize, PacketError> {
+        // a length can never exceed the remaining bytes, which also bounds allocations
+        let len = u64::from_le_bytes(self.array()?);
+        match usize::try_from(len) {
+            Ok(len) if len <= self.remaining() => Ok(len),
+            _ => Err(PacketError::Truncated),
+        }
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod wire_tests {
+    use super::*;
+
+    fn messages() -> Vec<Message> {
+        let bodies = vec![
+            MessageBody::SyncRequest(SyncRequest {
+                random_request: 0xDEAD_BEEF,
+                protocol_version: PROTOCOL_VERSION,
+            }),
+            MessageBody::SyncReply(SyncReply {
+                random_reply: 42,
+                delay_based: true,
+                fps: 60,
+                time: 1_700_000_000_000,
+                player_ids: vec![(0, 7), (2, u128::MAX)],
+                payload: vec![0xAB, 0xCD, 0xEF],
+                input_delay: Some(3),
+                round_trip_time: 80,
+                external_addr: Some("203.0.113.7:7000".parse().unwrap()),
+                start_frame: 120,
+                input_codec: 0x0102_0304,
+                variable_length_inputs: true,
+                synchronized_start: true,
+            }),
+            MessageBody::Input(Input {
+                peer_connect_status: vec![
+                    ConnectionStatus::default(),
+                    ConnectionStatus {
+                        disconnected: true,
+                        last_frame: Some(1234),
+                    },
+                ],
+                disconnect_requested: false,
+                start_frame: 17,
+                ack_frame: None,
+                sent_at: 1_700_000_000_123,
+                bytes: vec![1, 2, 3, 255],
+                metadata_frame: Some(18),
+                metadata: vec![2, 0, 1, 7],
+            }),
+            MessageBody::Input(Input::default()),
+            MessageBody::InputAck(InputAck {
+                ack_frame: Some(99),
+            }),
+            MessageBody::QualityReport(QualityReport {
+                frame_advantage: -3,
+                ping: u128::MAX - 1,
+            }),
+            MessageBody::QualityReply(QualityReply { pong: 123_456 }),
+            MessageBody::ChecksumReport(ChecksumReport {
+                checksum: 0x0123_4567_89AB_CDEF_0123_4567_89AB_CDEF,
+                frame: 600,
+            }),
+            MessageBody::KeepAlive,
+            MessageBody::Reliable(Reliable {
+                seq: 7,
+                oldest_pending: 5,
+                payload: ReliablePayload::StartTime(1_700_000_000_000),
+            }),
+            MessageBody::Reliable(Reliable {
+                seq: 8,
+                oldest_pending: 5,
+                payload: ReliablePayload::User(vec![4, 2]),
+            }),
+            MessageBody::Reliable(Reliable {
+                seq: 9,
+                oldest_pending: 9,
+                payload: ReliablePayload::HandOverRequest {
+                    handle: 1,
+                    frame: 300,
+                },
+            }),
+            MessageBody::Reliable(Reliable {
+                seq: 10,
+                oldest_pending: 9,
+                payload: ReliablePayload::HandleChange {
+                    handle: 1,
+                    frame: 302,
+                    added: true,
+                },
+            }),
+            MessageBody::Reliable(Reliable {
+                seq: 11,
+                oldest_pending: 9,
+                payload: ReliablePayload::InputDelay(3),
+            }),
+            MessageBody::ReliableAck(ReliableAck { ack: 6 }),
+            MessageBody::SyncReply(SyncReply {
+                external_addr: Some("[2001:db8::7]:7000".parse().unwrap()),
+                ..Default::default()
+            }),
+            MessageBody::MtuProbe(MtuProbe {
+                size: 32,
+                padding: vec![0; 5],
+            }),
+            MessageBody::MtuProbeAck(MtuProbeAck { size: 1400 }),
+        ];
+        bodies
+            .into_iter()
+            .enumerate()
+            .map(|(i, body)| Message {
+                header: MessageHeader {
+                    magic: 0xABCD,
+                    session_token: (i % 2 == 0).then_some([i as u8; 16]),
+                },
+                body,
+            })
+            .collect()
+    }
+
+    /// The encoding of [`messages()`] under protocol version 1. If one of these fails, the wire format has changed and
+    /// [`PROTOCOL_VERSION`] needs to be bumped along with the golden bytes, unless it already was since the last release.
+    #[cfg(feature = "wire-compat")]
+    const GOLDEN_V1: [&str; 18] = [
+        "cdab010000000000000000000000000000000000000000efbeadde01000000",
+        concat!(
+            "cdab00010000002a000000013c0000000068e5cf8b01000002000000000000000000000000000000",
+            "070000000000000000000000000000000200000000000000ffffffffffffffffffffffffffffffff",
+            "0300000000000000abcdef0103000000500000000100000000cb007107581b78000000040302010101",
+        ),
+        concat!(
+            "cdab010202020202020202020202020202020202000000020000000000000000000101d204000000",
+            "11000000007b68e5cf8b0100000400000000000000010203ff011200000004000000000000000200",
+            "0107",
+        ),
+        concat!(
+            "cdab0002000000000000000000000000000000000000000000000000000000000000000000000000",
+            "000000000000",
+        ),
+        "cdab0104040404040404040404040404040404030000000163000000",
+        "cdab0004000000fdfeffffffffffffffffffffffffffffff",
+        "cdab01060606060606060606060606060606060500000040e20100000000000000000000000000",
+        "cdab0006000000efcdab8967452301efcdab896745230158020000",
+        "cdab010808080808080808080808080808080807000000",
+        "cdab00080000000700000005000000000000000068e5cf8b010000",
+        concat!(
+            "cdab010a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a080000000800000005000000010000000200000000",
+            "0000000402",
+        ),
+        "cdab000800000009000000090000000200000001000000000000002c010000",
+        concat!(
+            "cdab010c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c080000000a00000009000000030000000100000000",
+            "0000002e01000001",
+        ),
+        "cdab00080000000b000000090000000400000003000000",
+        "cdab010e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0e0900000006000000",
+        concat!(
+            "cdab0001000000000000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000010100000020010db8000000000000000000000007581b00000000000000000000",
+        ),
+        "cdab01101010101010101010101010101010100a0000002000000005000000000000000000000000",
+        "cdab000b00000078050000",
+    ];
+
+    #[cfg(feature = "wire-compat")]
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[cfg(feature = "wire-compat")]
+    #[test]
+    fn test_golden_bytes() {
+        assert_eq!(PROTOCOL_VERSION, 1);
+        assert_eq!(messages().len(), GOLDEN_V1.len());
+        for (msg, golden) in messages().into_iter().zip(GOLDEN_V1) {
+            let mut buffer = Vec::new();
+            msg.encode_into(&mut buffer);
+            assert_eq!(to_hex(&buffer), golden);
+
+            let bytes: Vec<u8> = (0..golden.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&golden[i..i + 2], 16).unwrap())
+                .collect();
+            assert_eq!(parse_packet(&bytes), Ok(msg));
+        }
+    }
+
+    #[test]
+    fn test_protocol_version_in_header() {
+        // the version and the position of the version in a sync request are what peers of different releases compare, so
+        // neither may change without the other side noticing
+        assert_eq!(PROTOCOL_VERSION, 1);
+        let request = Message {
+            header: MessageHeader {
+                magic: 0x0102,
+                session_token: None,
+            },
+            body: MessageBody::SyncRequest(SyncRequest {
+                random_request: 0x0304_0506,
+                protocol_version: PROTOCOL_VERSION,
+            }),
+        };
+        let expected = [
+            le(0x0102, 2),
+            vec![0],
+            le(TAG_SYNC_REQUEST as u128, 4),
+            le(0x0304_0506, 4),
+            le(1, 4),
+        ]
+        .concat();
+        let mut buffer = Vec::new();
+        request.encode_into(&mut buffer);
+        assert_eq!(buffer, expected);
+        assert_eq!(parse_packet(&expected), Ok(request));
+    }
+
+    #[test]
+    fn test_encoding_matches_bincode() {
+        for msg in messages() {
+            let mut buffer = Vec::new();
+            msg.encode_into(&mut buffer);
+            assert_eq!(buffer, bincode::serialize(&msg).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_decode_bincode() {
+        for msg in messages() {
+            let bytes = bincode::serialize(&msg).unwrap();
+            assert_eq!(parse_packet(&bytes), Ok(msg));
+        }
+    }
+
+    /// Writes `value` as `len` little-endian bytes without relying on the byte order of the host, so this also holds on
+    /// big-endian targets.
+    fn le(value: u128, len: usize) -> Vec<u8> {
+        (0..len).map(|i| (value >> (8 * i)) as u8).collect()
+    }
+
+    #[test]
+    fn test_explicit_byte_order() {
+        let header = MessageHeader {
+            magic: 0x0102,
+            session_token: None,
+        };
+        let checksum = Message {
+            header,
+            body: MessageBody::ChecksumReport(ChecksumReport {
+                checksum: 0x0102_0304_0506_0708_090A_0B0C_0D0E_0F10,
+                frame: -2,
+            }),
+        };
+        let expected = [
+            le(0x0102, 2),
+            vec![0],
+            le(TAG_CHECKSUM_REPORT as u128, 4),
+            le(0x0102_0304_0506_0708_090A_0B0C_0D0E_0F10, 16),
+            vec![0xFE, 0xFF, 0xFF, 0xFF],
+        ]
+        .concat();
+        let mut buffer = Vec::new();
+        checksum.encode_into(&mut buffer);
+        assert_eq!(buffer, expected);
+        assert_eq!(parse_packet(&expected), Ok(checksum));
+
+        let input = Message {
+            header,
+            body: MessageBody::Input(Input {
+                peer_connect_status: vec![ConnectionStatus {
+                    disconnected: false,
+                    last_frame: Some(0x0102_0304),
+                }],
+                start_frame: 0x0A0B_0C0D,
+                ack_frame: Some(0x0102_0304),
+                sent_at: 0x0102_0304_0506_0708,
+                ..Default::default()
+            }),
+        };
+        let expected = [
+            le(0x0102, 2),
+            vec![0],
+            le(TAG_INPUT as u128, 4),
+            le(1, 8),
+            vec![0, 1],
+            le(0x0102_0304, 4),
+            vec![0],
+            le(0x0A0B_0C0D, 4),
+            vec![1],
+            le(0x0102_0304, 4),
+            le(0x0102_0304_0506_0708, 8),
+            le(0, 8),
+            vec![0],
+            le(0, 8),
+        ]
+        .concat();
+        let mut buffer = Vec::new();
+        input.encode_into(&mut buffer);
+        assert_eq!(buffer, expected);
+        assert_eq!(parse_packet(&expected), Ok(input));
+    }
+
+    #[test]
+    fn test_encode_into_reused_buffer() {
+        let mut buffer = Vec::new();
+        for msg in messages() {
+            buffer.clear();
+            msg.encode_into(&mut buffer);
+            assert_eq!(parse_packet(&buffer), Ok(msg));
+        }
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        for msg in messages() {
+            let mut buffer = Vec::new();
+            msg.encode_into(&mut buffer);
+            for len in 0..buffer.len() {
+                assert_eq!(parse_packet(&buffer[..len]), Err(PacketError::Truncated));
+            }
+        }
+        // unknown message tag
+        assert_eq!(
+            parse_packet(&[0, 0, 0, 12, 0, 0, 0]),
+            Err(PacketError::UnknownMessageType { tag: 12 })
+        );
+        // invalid bool
+        assert_eq!(
+            parse_packet(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2]),
+            Err(PacketError::InvalidBool { value: 2 })
+        );
+        // invalid session token tag
+        assert_eq!(
+            parse_packet(&[0, 0, 2, 7, 0, 0, 0]),
+            Err(PacketError::InvalidOptionTag { value: 2 })
+        );
+        // sync reply with an unknown address family
+        let sync_reply = Message {
+            header: MessageHeader::default(),
+            body: MessageBody::SyncReply(SyncReply {
+                external_addr: Some("203.0.113.7:7000".parse().unwrap()),
+                ..Default::default()
+            }),
+        };
+        let mut buffer = Vec::new();
+        sync_reply.encode_into(&mut buffer);
+        // the address family follows the fixed size fields, the empty sequences and the option tags before it
+        let family = 2 + 1 + 4 + 4 + 1 + 4 + 8 + 8 + 8 + 1 + 4 + 1;
+        assert_eq!(buffer[family..family + 4], ADDR_V4.to_le_bytes());
+        buffer[family..family + 4].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(
+            parse_packet(&buffer),
+            Err(PacketError::UnknownAddressFamily { tag: 2 })
+        );
+        // keep alive with an extra byte
+        assert_eq!(
+            parse_packet(&[0, 0, 0, 7, 0, 0, 0, 0]),
+            Err(PacketError::TrailingBytes { len: 1 })
+        );
+        // input claiming a huge amount of connection states
+        let mut huge_input = vec![0, 0, 0, 2, 0, 0, 0];
+        huge_input.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(parse_packet(&huge_input), Err(PacketError::Truncated));
+        // oversized packet
+        assert_eq!(
+            parse_packet(&[0; MAX_PACKET_SIZE + 1]),
+            Err(PacketError::Oversized {
+                len: MAX_PACKET_SIZE + 1,
+                max: MAX_PACKET_SIZE
+            })
+        );
+    }
+}
@@ -0,0 +1,37 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Frame, PlayerHandle};
+
+/// A [`PollSummary`] describes what a call to [`P2PSession::poll_remote_clients()`] or
+/// [`SpectatorSession::poll_remote_clients()`] did, so test harnesses can assert on the behavior of the protocol and games can
+/// schedule work depending on it, e.g. start preparing a resimulation before calling `advance_frame()`.
+///
+/// [`P2PSession::poll_remote_clients()`]: crate::P2PSession::poll_remote_clients
+/// [`SpectatorSession::poll_remote_clients()`]: crate::SpectatorSession::poll_remote_clients
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PollSummary {
+    /// The number of packets received from remote clients and handled by the session. Packets from unknown addresses are not counted.
+    pub packets_received: usize,
+    /// The number of new inputs received for each player. Players without new inputs are left out.
+    pub inputs_received: BTreeMap<PlayerHandle, usize>,
+    /// The number of events queued to be returned by `events()`, including those that pushed older events out of the full queue.
+    pub events_queued: usize,
+    /// The earliest frame that will be resimulated with the next call to `advance_frame()` because a prediction turned out to be
+    /// wrong, or `None` if no rollback is scheduled. This includes mispredictions found by earlier polls that were not corrected
+    /// yet. A [`SpectatorSession`] never rolls back.
+    ///
+    /// [`SpectatorSession`]: crate::SpectatorSession
+    pub rollback_frame: Option<Frame>,
+}
+
+impl PollSummary {
+    /// Returns the number of new inputs received for the given player.
+    pub fn inputs_received_for(&self, player_handle: PlayerHandle) -> usize {
+        self.inputs_received
+            .get(&player_handle)
+            .copied()
+            .unwrap_or_default()
+    }
+}
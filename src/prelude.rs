@@ -0,0 +1,18 @@
+//! The types most applications need to run a session, to be imported all at once:
+//!
+//! ```
+//! use ggrs::prelude::*;
+//!
+//! let builder = SessionBuilder::<FixedInputConfig<1, u8>>::new().with_num_players(2);
+//! assert!(builder.add_player(PlayerType::Local, 0).is_ok());
+//! ```
+//!
+//! The prelude holds the sessions and their builder, the events and requests they hand out, the errors and the types
+//! describing players and frames. Everything else, like codecs, clocks or diagnostics, is imported from the crate root.
+
+pub use crate::{
+    Config, ConnectionState, DesyncDetection, FixedInputConfig, Frame, GameStateCell, GgrsError,
+    GgrsEvent, GgrsRequest, InputStatus, LocalSession, NetworkStats, NonBlockingSocket, P2PSession,
+    PlayerHandle, PlayerId, PlayerType, PollSummary, Session, SessionBuilder, SessionState,
+    SpectatorSession, StartupPolicy, SyncTestSession, UdpNonBlockingSocket, MAX_FRAME, NULL_FRAME,
+};
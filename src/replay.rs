@@ -0,0 +1,232 @@
+use crate::{Config, Frame, GgrsError};
+
+/// A [`Replay`] holds the confirmed inputs of all players for every frame of a match, starting at frame 0.
+/// Record one with [`SessionBuilder::with_replay_recording()`] and play it back with [`SyncTestSession::from_replay()`]
+/// to reproduce the exact same simulation locally, for example to track down a desync reported by a player.
+///
+/// [`SessionBuilder::with_replay_recording()`]: crate::SessionBuilder::with_replay_recording
+/// [`SyncTestSession::from_replay()`]: crate::SyncTestSession::from_replay
+pub struct Replay<T>
+where
+    T: Config,
+{
+    num_players: usize,
+    /// The inputs of all frames, `num_players` inputs per frame.
+    inputs: Vec<T::Input>,
+}
+
+impl<T: Config> Replay<T> {
+    /// Creates an empty [`Replay`] for the given number of players.
+    pub fn new(num_players: usize) -> Self {
+        Self {
+            num_players,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Returns the number of players whose inputs are recorded.
+    pub fn num_players(&self) -> usize {
+        self.num_players
+    }
+
+    /// Returns the number of recorded frames.
+    pub fn num_frames(&self) -> usize {
+        match self.num_players {
+            0 => 0,
+            n => self.inputs.len() / n,
+        }
+    }
+
+    /// Appends the inputs of all players for the next frame, ordered by player handle.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the number of inputs does not match the number of players.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn push_frame(&mut self, inputs: &[T::Input]) -> Result<(), GgrsError> {
+        if inputs.len() != self.num_players {
+            return Err(GgrsError::InvalidRequest {
+                info: "The replay requires exactly one input per player for every frame."
+                    .to_owned(),
+            });
+        }
+        self.inputs.extend_from_slice(inputs);
+        Ok(())
+    }
+
+    /// Returns the inputs of all players for the given frame, or `None` if the frame has not been recorded.
+    pub fn frame(&self, frame: Frame) -> Option<&[T::Input]> {
+        if frame < 0 || frame as usize >= self.num_frames() {
+            return None;
+        }
+        let start = frame as usize * self.num_players;
+        Some(&self.inputs[start..start + self.num_players])
+    }
+
+    /// Appends the frames of another replay, for example a chunk taken with [`P2PSession::take_replay_chunk()`].
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the replays were recorded with a different number of players.
+    ///
+    /// [`P2PSession::take_replay_chunk()`]: crate::P2PSession::take_replay_chunk
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn append(&mut self, other: Replay<T>) -> Result<(), GgrsError> {
+        if other.num_players != self.num_players {
+            return Err(GgrsError::InvalidRequest {
+                info: "Only replays with the same number of players can be joined.".to_owned(),
+            });
+        }
+        self.inputs.extend(other.inputs);
+        Ok(())
+    }
+
+    /// Returns the bytes allocated for the recorded inputs.
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.inputs.capacity() * std::mem::size_of::<T::Input>()
+    }
+
+    /// Serializes the replay, so it can be written to a file and sent along with a bug report.
+    /// Like inputs sent over the network, the encoding assumes all clients use the same endianness.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let input_size = std::mem::size_of::<T::Input>();
+        let mut bytes = Vec::with_capacity(8 + self.inputs.len() * input_size);
+        bytes.extend_from_slice(&(self.num_players as u32).to_le_bytes());
+        bytes.extend_from_slice(&(input_size as u32).to_le_bytes());
+        for input in self.inputs.iter() {
+            bytes.extend_from_slice(bytemuck::bytes_of(input));
+        }
+        bytes
+    }
+
+    /// Deserializes a replay previously serialized with [`Replay::to_bytes()`].
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the bytes are truncated, were recorded with a different input type or contain invalid inputs.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, GgrsError> {
+        let invalid = |info: &str| GgrsError::InvalidRequest {
+            info: info.to_owned(),
+        };
+        if bytes.len() < 8 {
+            return Err(invalid("The replay is missing its header."));
+        }
+        let num_players = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+        let input_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        if input_size != std::mem::size_of::<T::Input>() {
+            return Err(invalid(
+                "The replay was recorded with an input type of a different size.",
+            ));
+        }
+
+        let body = &bytes[8..];
+        let frame_size = num_players * input_size;
+        if frame_size == 0 || !body.len().is_multiple_of(frame_size) {
+            return Err(invalid("The replay is truncated."));
+        }
+        let inputs = body
+            .chunks_exact(input_size)
+            .map(bytemuck::checked::try_pod_read_unaligned::<T::Input>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid("The replay contains invalid inputs."))?;
+
+        Ok(Self {
+            num_players,
+            inputs,
+        })
+    }
+}
+
+impl<T: Config> Clone for Replay<T> {
+    fn clone(&self) -> Self {
+        Self {
+            num_players: self.num_players,
+            inputs: self.inputs.clone(),
+        }
+    }
+}
+
+impl<T: Config> std::fmt::Debug for Replay<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Replay")
+            .field("num_players", &self.num_players)
+            .field("num_frames", &self.num_frames())
+            .finish()
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod replay_tests {
+    use std::net::SocketAddr;
+
+    use bytemuck::{Pod, Zeroable};
+
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Copy, Clone, PartialEq, Debug, Pod, Zeroable)]
+    struct TestInput {
+        inp: u16,
+    }
+
+    struct TestConfig;
+
+    impl Config for TestConfig {
+        type Input = TestInput;
+        type State = Vec<u8>;
+        type Address = SocketAddr;
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut replay = Replay::<TestConfig>::new(2);
+        for i in 0..10 {
+            replay
+                .push_frame(&[TestInput { inp: i }, TestInput { inp: 100 + i }])
+                .unwrap();
+        }
+        assert!(replay.push_frame(&[TestInput { inp: 0 }]).is_err());
+        assert_eq!(replay.num_frames(), 10);
+
+        let decoded = Replay::<TestConfig>::from_bytes(&replay.to_bytes()).unwrap();
+        assert_eq!(decoded.num_players(), 2);
+        assert_eq!(decoded.num_frames(), 10);
+        assert_eq!(
+            decoded.frame(3),
+            Some(&[TestInput { inp: 3 }, TestInput { inp: 103 }][..])
+        );
+        assert_eq!(decoded.frame(10), None);
+    }
+
+    #[test]
+    fn test_append() {
+        let mut replay = Replay::<TestConfig>::new(1);
+        replay.push_frame(&[TestInput { inp: 0 }]).unwrap();
+        let mut chunk = Replay::<TestConfig>::new(1);
+        chunk.push_frame(&[TestInput { inp: 1 }]).unwrap();
+        replay.append(chunk).unwrap();
+        assert_eq!(replay.num_frames(), 2);
+        assert_eq!(replay.frame(1), Some(&[TestInput { inp: 1 }][..]));
+
+        assert!(replay.append(Replay::<TestConfig>::new(2)).is_err());
+    }
+
+    #[test]
+    fn test_from_invalid_bytes() {
+        let mut replay = Replay::<TestConfig>::new(2);
+        replay
+            .push_frame(&[TestInput { inp: 1 }, TestInput { inp: 2 }])
+            .unwrap();
+        let bytes = replay.to_bytes();
+
+        assert!(Replay::<TestConfig>::from_bytes(&bytes[..4]).is_err());
+        assert!(Replay::<TestConfig>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut wrong_size = bytes.clone();
+        wrong_size[4] = 4;
+        assert!(Replay::<TestConfig>::from_bytes(&wrong_size).is_err());
+    }
+}
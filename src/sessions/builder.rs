@@ -1,28 +1,55 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 use instant::Duration;
 
 use crate::{
-    network::protocol::UdpProtocol, sessions::p2p_session::PlayerRegistry, Config, DesyncDetection,
-    GgrsError, NonBlockingSocket, P2PSession, PlayerHandle, PlayerType, SpectatorSession,
-    SyncTestSession,
+    checksum::AutoChecksums,
+    input_queue::DEFAULT_INPUT_QUEUE_LENGTH,
+    network::{
+        codec::{Codec, IdentityCodec},
+        protocol::{EndpointSettings, UdpProtocol, DEFAULT_CHECKSUM_HISTORY_SIZE},
+        session_socket::{ReceiveBacklog, SessionSocket},
+    },
+    sessions::p2p_session::{P2PSettings, PlayerRegistry},
+    sessions::p2p_spectator_session::SpectatorSettings,
+    sessions::sync_test_session::SyncTestSettings,
+    state_diff::StateDiffing,
+    ChecksumKind, Clock, CoalesceFn, CoalescingInput, Config, DesyncDetection, Frame, GgrsError,
+    HandshakeVerifier, LocalSession, NonBlockingSocket, P2PSession, PlayerHandle, PlayerId,
+    PlayerType, SpectatorSession, StartupPolicy, StateBytes, StateHasher, StdClock,
+    SyncTestSession, VariableLengthInput, MAX_FRAME, MAX_HANDSHAKE_PAYLOAD_SIZE,
 };
-
-use super::p2p_spectator_session::SPECTATOR_BUFFER_SIZE;
+#[cfg(feature = "net-trace")]
+use crate::{network::trace::TracedSocket, PacketObserver};
 
 const DEFAULT_PLAYERS: usize = 2;
 const DEFAULT_SAVE_MODE: bool = false;
+const DEFAULT_SAVE_INTERVAL: usize = 1;
+const DEFAULT_SKIP_CONFIRMED_SAVES: bool = false;
+const DEFAULT_DELAY_BASED: bool = false;
+const DEFAULT_TIME_SYNC_SUGGESTIONS: bool = false;
 const DEFAULT_DETECTION_MODE: DesyncDetection = DesyncDetection::Off;
 const DEFAULT_INPUT_DELAY: usize = 0;
 const DEFAULT_DISCONNECT_TIMEOUT: Duration = Duration::from_millis(2000);
 const DEFAULT_DISCONNECT_NOTIFY_START: Duration = Duration::from_millis(500);
 const DEFAULT_FPS: usize = 60;
 const DEFAULT_MAX_PREDICTION_FRAMES: usize = 8;
+const DEFAULT_INPUT_METADATA_HISTORY_SIZE: usize = 128;
 const DEFAULT_CHECK_DISTANCE: usize = 2;
 // If the spectator is more than this amount of frames behind, it will advance the game two steps at a time to catch up
 const DEFAULT_MAX_FRAMES_BEHIND: usize = 10;
 // The amount of frames the spectator advances in a single step if too far behind
 const DEFAULT_CATCHUP_SPEED: usize = 1;
+// The amount of frames the host collects confirmed inputs for before sending them to spectators
+const DEFAULT_SPECTATOR_INPUT_INTERVAL: usize = 1;
+// The amount of inputs a spectator can buffer (a second worth of inputs)
+const DEFAULT_SPECTATOR_BUFFER_SIZE: usize = 60;
+// The amount of received packets a session processes per poll, the rest is left for the next poll
+const DEFAULT_RECEIVE_BUDGET: usize = 1024;
+// The amount of received bytes a session takes from its socket per poll
+const DEFAULT_RECEIVE_BYTE_BUDGET: usize = 1024 * 1024;
 // The amount of events a spectator can buffer; should never be an issue if the user polls the events at every step
 pub(crate) const MAX_EVENT_QUEUE_SIZE: usize = 100;
 
@@ -39,16 +66,90 @@ where
     /// FPS defines the expected update frequency of this session.
     fps: usize,
     sparse_saving: bool,
+    save_interval: usize,
+    /// If enabled, states that no rollback can load anymore are not saved.
+    skip_confirmed_saves: bool,
+    /// If enabled and agreed upon by all remote peers, the session runs delay-based without rollbacks.
+    delay_based: bool,
+    /// If enabled and agreed upon by all remote peers, the input delay of all peers is split fairly between them.
+    fair_input_delay: bool,
     desync_detection: DesyncDetection,
+    /// The desync detection mode of a spectator session, which is set separately from the one of P2P sessions.
+    spectator_desync_detection: DesyncDetection,
+    startup_policy: StartupPolicy,
+    /// The frame P2P and spectator sessions start at.
+    start_frame: Frame,
+    synchronized_start: Option<Duration>,
+    /// Alternative addresses of remote clients, by the address they were added with.
+    remote_candidates: HashMap<T::Address, Vec<T::Address>>,
+    time_sync_suggestions: bool,
+    /// If set, only packets carrying this token are accepted.
+    session_token: Option<[u8; 16]>,
+    /// The application payload presented to remote clients during synchronization.
+    handshake_payload: Vec<u8>,
+    /// If set, decides whether to synchronize with a remote client, based on its handshake payload.
+    handshake_verifier: Option<Arc<dyn HandshakeVerifier<T::Address>>>,
+    /// If set, the address presented to remote clients during synchronization as the one the local client is reachable at.
+    external_addr: Option<SocketAddr>,
+    /// If set, a [`crate::GgrsEvent::FpsMismatch`] is sent if a remote client deviates from the fps by more than this many frames per second.
+    fps_mismatch_tolerance: Option<f64>,
+    /// Compresses the input payloads of all packets.
+    input_codec: Arc<dyn Codec>,
+    /// The source of time for all timers of the session.
+    clock: Arc<dyn Clock>,
+    /// If set, the outgoing bandwidth to every remote client is limited to this many bytes per second.
+    bandwidth_limit: Option<usize>,
+    /// Every input packet carries at least this many of the newest frames.
+    input_redundancy: usize,
+    /// The maximum number of received packets processed per poll.
+    receive_budget: usize,
+    /// The maximum number of received bytes taken from the socket per poll.
+    receive_byte_budget: usize,
+    /// If set, every message sent and received is reported to this observer.
+    #[cfg(feature = "net-trace")]
+    packet_observer: Option<Box<dyn PacketObserver<T::Address>>>,
+    /// If set, only the meaningful bytes of every input are sent.
+    input_len: Option<fn(&T::Input) -> usize>,
+    /// If set, local inputs added for the same frame are merged instead of overwritten.
+    input_coalescing: Option<CoalesceFn<T::Input>>,
+    /// If set, the bits of the input that are predicted to repeat, all others are predicted to be cleared.
+    sticky_input_mask: Option<T::Input>,
     /// The time until a remote player gets disconnected.
     disconnect_timeout: Duration,
+    /// If set, the synchronization with a remote client fails once the handshake made no progress for this long.
+    sync_timeout: Option<Duration>,
     /// The time until the client will get a notification that a remote player is about to be disconnected.
     disconnect_notify_start: Duration,
     player_reg: PlayerRegistry<T>,
     input_delay: usize,
+    /// The number of inputs per player that can be held at the same time.
+    input_queue_length: usize,
+    /// The number of confirmed frames whose inputs are kept for [`P2PSession::inputs_since()`].
+    input_history: usize,
     check_dist: usize,
     max_frames_behind: usize,
     catchup_speed: usize,
+    spectator_input_interval: usize,
+    /// The number of frames a spectator can buffer inputs for.
+    spectator_buffer_size: usize,
+    /// If set, saved states are stored as deltas to the next newer state.
+    state_diffing: Option<StateDiffing<T::State>>,
+    /// If set, the checksums the user did not provide are computed from the bytes of the saved states.
+    auto_checksums: Option<AutoChecksums<T::State>>,
+    /// If enabled, the session records all confirmed inputs into a [`crate::Replay`].
+    replay_recording: bool,
+    /// The number of checksums kept per peer for desync detection.
+    checksum_history_size: usize,
+    /// The number of frames input metadata is kept for per player.
+    input_metadata_history_size: usize,
+    /// If enabled, the inputs of disconnected players are left out of the advance frame requests.
+    remove_disconnected_players: bool,
+    /// If set, a rollback resimulates at most this many frames per call to advance the frame.
+    max_resim_frames: Option<usize>,
+    /// If enabled, the session reports every rollback and stall with an event.
+    rollback_events: bool,
+    /// If enabled, the requests of a rollback are enclosed by requests marking its begin and end.
+    rollback_requests: bool,
 }
 
 impl<T: Config> Default for SessionBuilder<T> {
@@ -67,18 +168,59 @@ impl<T: Config> SessionBuilder<T> {
             max_prediction: DEFAULT_MAX_PREDICTION_FRAMES,
             fps: DEFAULT_FPS,
             sparse_saving: DEFAULT_SAVE_MODE,
+            save_interval: DEFAULT_SAVE_INTERVAL,
+            skip_confirmed_saves: DEFAULT_SKIP_CONFIRMED_SAVES,
+            delay_based: DEFAULT_DELAY_BASED,
+            fair_input_delay: false,
             desync_detection: DEFAULT_DETECTION_MODE,
+            spectator_desync_detection: DesyncDetection::Off,
+            startup_policy: StartupPolicy::default(),
+            start_frame: 0,
+            synchronized_start: None,
+            remote_candidates: HashMap::new(),
+            time_sync_suggestions: DEFAULT_TIME_SYNC_SUGGESTIONS,
+            session_token: None,
+            handshake_payload: Vec::new(),
+            external_addr: None,
+            handshake_verifier: None,
+            fps_mismatch_tolerance: None,
+            input_codec: Arc::new(IdentityCodec),
+            clock: Arc::new(StdClock),
+            bandwidth_limit: None,
+            input_redundancy: 1,
+            receive_budget: DEFAULT_RECEIVE_BUDGET,
+            receive_byte_budget: DEFAULT_RECEIVE_BYTE_BUDGET,
+            #[cfg(feature = "net-trace")]
+            packet_observer: None,
+            input_len: None,
+            input_coalescing: None,
+            sticky_input_mask: None,
             disconnect_timeout: DEFAULT_DISCONNECT_TIMEOUT,
+            sync_timeout: None,
             disconnect_notify_start: DEFAULT_DISCONNECT_NOTIFY_START,
             input_delay: DEFAULT_INPUT_DELAY,
+            input_queue_length: DEFAULT_INPUT_QUEUE_LENGTH,
+            input_history: 0,
             check_dist: DEFAULT_CHECK_DISTANCE,
             max_frames_behind: DEFAULT_MAX_FRAMES_BEHIND,
             catchup_speed: DEFAULT_CATCHUP_SPEED,
+            spectator_input_interval: DEFAULT_SPECTATOR_INPUT_INTERVAL,
+            spectator_buffer_size: DEFAULT_SPECTATOR_BUFFER_SIZE,
+            state_diffing: None,
+            auto_checksums: None,
+            replay_recording: false,
+            checksum_history_size: DEFAULT_CHECKSUM_HISTORY_SIZE,
+            input_metadata_history_size: DEFAULT_INPUT_METADATA_HISTORY_SIZE,
+            remove_disconnected_players: false,
+            max_resim_frames: None,
+            rollback_events: false,
+            rollback_requests: false,
         }
     }
 
     /// Must be called for each player in the session (e.g. in a 3 player session, must be called 3 times) before starting the session.
-    /// Player handles for players should be between 0 and `num_players`, spectator handles should be higher than `num_players`.
+    /// Player handles for players should be between 0 and `num_players`, spectator handles should be `num_players` or higher.
+    /// Every handle from 0 to `num_players - 1` has to be taken by a player before the session can start.
     /// Later, you will need the player handle to add input, change parameters or disconnect the player or spectator.
     ///
     /// # Errors
@@ -95,18 +237,18 @@ impl<T: Config> SessionBuilder<T> {
         // check if the player handle is already in use
         if self.player_reg.handles.contains_key(&player_handle) {
             return Err(GgrsError::InvalidRequest {
-                info: "Player handle already in use.".to_owned(),
+                info: format!("Player handle {} is already in use.", player_handle),
             });
         }
         // check if the player handle is valid for the given player type
         match player_type {
             PlayerType::Local => {
-                self.local_players += 1;
                 if player_handle >= self.num_players {
                     return Err(GgrsError::InvalidRequest {
                         info: "The player handle you provided is invalid. For a local player, the handle should be between 0 and num_players".to_owned(),
                     });
                 }
+                self.local_players += 1;
             }
             PlayerType::Remote(_) => {
                 if player_handle >= self.num_players {
@@ -127,6 +269,86 @@ impl<T: Config> SessionBuilder<T> {
         Ok(self)
     }
 
+    /// Adds a player that follows the input of the player `primary`, e.g. a second character driven by the same controller.
+    /// The player has the same [`PlayerType`] as the primary player and takes its input every frame, so you only add input
+    /// for the primary player. Its input is not sent to remote peers, who take it from the input of the primary player as
+    /// well. All peers need to link the same players. Spectators receive the input of linked players like any other, and in a
+    /// [`LocalSession`] or [`SyncTestSession`], you add input for the linked player as usual.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if a player with that handle has been added before or the handle is not below `num_players`.
+    /// - Returns [`InvalidRequest`] if `primary` has not been added as a local or remote player before or is linked itself.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn add_linked_player(
+        mut self,
+        player_handle: PlayerHandle,
+        primary: PlayerHandle,
+    ) -> Result<Self, GgrsError> {
+        if self.player_reg.handles.contains_key(&player_handle) {
+            return Err(GgrsError::InvalidRequest {
+                info: format!("Player handle {} is already in use.", player_handle),
+            });
+        }
+        if player_handle >= self.num_players {
+            return Err(GgrsError::InvalidRequest {
+                info: "The player handle you provided is invalid. For a linked player, the handle should be between 0 and num_players".to_owned(),
+            });
+        }
+        let player_type = match self.player_reg.handles.get(&primary) {
+            Some(player_type @ (PlayerType::Local | PlayerType::Remote(_)))
+                if !self.player_reg.links.contains_key(&primary) =>
+            {
+                player_type.clone()
+            }
+            _ => {
+                return Err(GgrsError::InvalidRequest {
+                    info: format!(
+                        "Player {} is not a local or remote player to link to.",
+                        primary
+                    ),
+                });
+            }
+        };
+        self.player_reg.handles.insert(player_handle, player_type);
+        self.player_reg.links.insert(player_handle, primary);
+        Ok(self)
+    }
+
+    /// Adds alternative addresses under which the remote client added with `addr` might be reachable, e.g. its LAN address,
+    /// its WAN address and a relay. During synchronization, GGRS sends requests to `addr` and all candidates and uses the first address
+    /// the remote client answers on for the rest of the session. This way, a LAN connection is preferred whenever it works.
+    /// Events and player types keep referring to the remote client by `addr`, see [`P2PSession::active_address()`] for the address in use.
+    /// This also works for the host of a [`SpectatorSession`].
+    ///
+    /// [`P2PSession::active_address()`]: P2PSession#method.active_address
+    pub fn with_remote_candidates(mut self, addr: T::Address, candidates: Vec<T::Address>) -> Self {
+        self.remote_candidates.insert(addr, candidates);
+        self
+    }
+
+    /// Attaches a persistent [`PlayerId`] to a local player. The id is sent to all peers and spectators during synchronization,
+    /// so they can recognize a player that rejoins under a different handle or address. See [`P2PSession::player_id()`].
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the handle has not been added as a local player before.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`P2PSession::player_id()`]: P2PSession#method.player_id
+    pub fn with_player_id(
+        mut self,
+        player_handle: PlayerHandle,
+        player_id: PlayerId,
+    ) -> Result<Self, GgrsError> {
+        if self.player_reg.handles.get(&player_handle) != Some(&PlayerType::Local) {
+            return Err(GgrsError::InvalidRequest {
+                info: "Player ids can only be attached to local players.".to_owned(),
+            });
+        }
+        self.player_reg.player_ids.insert(player_handle, player_id);
+        Ok(self)
+    }
+
     /// Change the maximum prediction window. Default is 8.
     ///
     /// # Errors
@@ -144,12 +366,59 @@ impl<T: Config> SessionBuilder<T> {
         Ok(self)
     }
 
+    /// Splits the bits of the input into "sticky" and "instant" ones to improve the prediction of remote inputs. The bits set in
+    /// `mask` are sticky: like without a mask, they are predicted to repeat the last received input, which suits held inputs like
+    /// movement. All other bits are instant: they are predicted to be cleared, which suits button taps that rarely last more than a
+    /// frame. For example, with an input of a movement byte and a button byte, a mask of `[0xFF, 0x00]` keeps moving but does
+    /// not press the buttons again during the prediction, which saves the rollbacks of mispredicted attacks in fighting games.
+    ///
+    /// If clearing the instant bits does not result in a valid input, e.g. because they are part of an enum, the last input is
+    /// repeated as it is. Spectators and sync test sessions do not predict inputs, so they ignore the mask.
+    pub fn with_sticky_input_mask(mut self, mask: T::Input) -> Self {
+        self.sticky_input_mask = Some(mask);
+        self
+    }
+
     /// Change the amount of frames GGRS will delay the inputs for local players.
+    /// Starting the session fails if the delayed inputs do not fit into the input queue, see [`SessionBuilder::with_input_queue_length()`].
     pub fn with_input_delay(mut self, delay: usize) -> Self {
         self.input_delay = delay;
         self
     }
 
+    /// Change the number of inputs GGRS can hold at the same time per player. Default is 128.
+    /// The queue has to hold all inputs that are not yet confirmed, including the inputs of remote players that are running ahead,
+    /// so starting a session fails unless the length is larger than `2 * max_prediction + input_delay + save_interval` plus the
+    /// input history set with [`with_input_history()`].
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the length is 0.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`with_input_history()`]: Self#method.with_input_history
+    pub fn with_input_queue_length(mut self, length: usize) -> Result<Self, GgrsError> {
+        if length == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Input queue length should be higher than 0.".to_owned(),
+            });
+        }
+        self.input_queue_length = length;
+        Ok(self)
+    }
+
+    /// Keeps the inputs of at least the last `frames` confirmed frames, so they can be queried with [`P2PSession::inputs_since()`]
+    /// or [`SyncTestSession::inputs_since()`], e.g. to rewind a training mode by a few seconds. Default is 0, which only keeps the
+    /// inputs rollbacks still need. The inputs are kept in the input queue, so starting the session fails unless the queue is long enough
+    /// to hold them as well, see [`with_input_queue_length()`].
+    ///
+    /// [`P2PSession::inputs_since()`]: P2PSession#method.inputs_since
+    /// [`SyncTestSession::inputs_since()`]: SyncTestSession#method.inputs_since
+    /// [`with_input_queue_length()`]: Self#method.with_input_queue_length
+    pub fn with_input_history(mut self, frames: usize) -> Self {
+        self.input_history = frames;
+        self
+    }
+
     /// Change number of total players. Default is 2.
     pub fn with_num_players(mut self, num_players: usize) -> Self {
         self.num_players = num_players;
@@ -164,19 +433,464 @@ impl<T: Config> SessionBuilder<T> {
         self
     }
 
+    /// Sets the save interval. States will only be saved on frames that are a multiple of this interval and rollbacks will resimulate from the latest
+    /// saved frame. This leads to less save requests at the cost of longer rollbacks, which is useful if saving your gamestate is very expensive.
+    /// Rollbacks may go back up to `interval - 1` frames further than the prediction window. Default is 1, saving every frame.
+    /// This has no effect with sparse saving turned on.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the interval is 0.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_save_interval(mut self, interval: usize) -> Result<Self, GgrsError> {
+        if interval == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Save interval should be higher than 0.".to_owned(),
+            });
+        }
+        self.save_interval = interval;
+        Ok(self)
+    }
+
+    /// Enables or disables skipping saves of confirmed frames. If enabled, the session does not request to save a frame whose
+    /// inputs are all known already, since no rollback can ever go back to it. With low latency, this skips most saves, which
+    /// is useful if saving your gamestate is expensive. States on the checksum interval of the desync detection are always
+    /// saved. Since fewer states are saved, [`P2PSession::force_rollback_to()`] may not find a state to load. Default is off.
+    /// This has no effect with sparse saving turned on.
+    ///
+    /// [`P2PSession::force_rollback_to()`]: crate::P2PSession#method.force_rollback_to
+    pub fn with_confirmed_save_skipping(mut self, enabled: bool) -> Self {
+        self.skip_confirmed_saves = enabled;
+        self
+    }
+
+    /// Sets the delay-based fallback mode. If turned on and all remote peers turned it on as well, the session will run delay-based once synchronized:
+    /// The input delay of local players is raised to the round trip time (in frames) measured during synchronization, no rollbacks happen and
+    /// [`advance_frame()`] returns [`PredictionThreshold`] until the inputs of all remote players for the current frame have arrived.
+    /// Local inputs are sent before stalling, so inputs added again for the same frame after a stall are discarded.
+    /// This is useful for clients that cannot afford resimulating frames. If any remote peer did not turn this on, the session runs with rollbacks as usual.
+    ///
+    /// [`advance_frame()`]: P2PSession#method.advance_frame
+    /// [`PredictionThreshold`]: GgrsError::PredictionThreshold
+    pub fn with_delay_based_fallback(mut self, delay_based: bool) -> Self {
+        self.delay_based = delay_based;
+        self
+    }
+
+    /// Enables fair input delay. If turned on and a remote peer turned it on as well, both split the sum of their input delays
+    /// set with [`with_input_delay()`] once synchronized, proportionally to the one-way latency each of them measured during
+    /// synchronization. Without it, a peer with a lower input delay sends its inputs earlier relative to the other peer's
+    /// and starts the match with a frame advantage over the whole session. As both peers measure at different moments, one
+    /// of them splits the delay and sends the other one its share, which waits for it before the session starts. With more than one remote peer, the local input
+    /// delay is the highest share among them and all remote peers need to turn this on. [`P2PSession::input_delay()`] returns
+    /// the negotiated delay.
+    ///
+    /// [`with_input_delay()`]: Self#method.with_input_delay
+    /// [`P2PSession::input_delay()`]: P2PSession#method.input_delay
+    pub fn with_fair_input_delay(mut self, enabled: bool) -> Self {
+        self.fair_input_delay = enabled;
+        self
+    }
+
+    /// Sets a shared session token. All packets are sent with this token and packets without it (or with a different one) are dropped
+    /// before they are processed, so stray packets of other sessions or clients that don't know the token are ignored. All peers and
+    /// spectators of a session need to use the same token, which should be distributed by your matchmaking server.
+    ///
+    /// The token is **not** authentication: it is sent in cleartext with every packet and nothing ties a packet to its content or
+    /// time, so anyone who can observe the traffic can read the token and send or replay packets with it. Sessions that need to
+    /// keep attackers on the path out have to use a socket that encrypts and authenticates the packets, like the `QuinnSocket` of the `quinn` feature.
+    pub fn with_session_token(mut self, session_token: [u8; 16]) -> Self {
+        self.session_token = Some(session_token);
+        self
+    }
+
+    /// Sets an application payload that is presented to every remote client during synchronization, like a build hash, the hash of
+    /// the active mods or of the selected character data. Remote clients can check it with [`with_handshake_verifier()`].
+    /// The payload is empty by default.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the payload is larger than [`MAX_HANDSHAKE_PAYLOAD_SIZE`].
+    ///
+    /// [`with_handshake_verifier()`]: Self#method.with_handshake_verifier
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_handshake_payload(mut self, payload: Vec<u8>) -> Result<Self, GgrsError> {
+        if payload.len() > MAX_HANDSHAKE_PAYLOAD_SIZE {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "The handshake payload cannot be larger than {} bytes.",
+                    MAX_HANDSHAKE_PAYLOAD_SIZE
+                ),
+            });
+        }
+        self.handshake_payload = payload;
+        Ok(self)
+    }
+
+    /// Sets a [`HandshakeVerifier`] that is called with the handshake payload of every remote client before the synchronization with it
+    /// concludes, see [`with_handshake_payload()`]. If it returns `false`, the session sends a [`GgrsEvent::HandshakeRejected`] and
+    /// disconnects the client, which is asked to disconnect as well. By default, all remote clients are accepted.
+    ///
+    /// [`with_handshake_payload()`]: Self#method.with_handshake_payload
+    /// [`GgrsEvent::HandshakeRejected`]: crate::GgrsEvent::HandshakeRejected
+    pub fn with_handshake_verifier(
+        mut self,
+        verifier: impl HandshakeVerifier<T::Address> + 'static,
+    ) -> Self {
+        self.handshake_verifier = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Sets the address the local client is reachable at from outside, as the application discovered it, e.g. with STUN. The
+    /// address is presented to every remote client during synchronization, where [`P2PSession::remote_external_addr()`]
+    /// and [`SpectatorSession::remote_external_addr()`] return it and the diagnostics report it, so a client that has to
+    /// reconnect can pass it on to [`with_remote_candidates()`]. By default, no address is presented.
+    ///
+    /// [`P2PSession::remote_external_addr()`]: crate::P2PSession#method.remote_external_addr
+    /// [`SpectatorSession::remote_external_addr()`]: crate::SpectatorSession#method.remote_external_addr
+    /// [`with_remote_candidates()`]: Self#method.with_remote_candidates
+    pub fn with_external_addr(mut self, addr: SocketAddr) -> Self {
+        self.external_addr = Some(addr);
+        self
+    }
+
+    /// Sets the [`Codec`] that compresses the input payloads of all packets. By default, payloads are not compressed beyond the
+    /// delta encoding GGRS always applies. All peers and spectators of a session need to use the same codec: clients whose codecs
+    /// differ in [`Codec::format_id()`] disconnect from each other during synchronization with a [`GgrsEvent::HandshakeMismatch`].
+    ///
+    /// [`GgrsEvent::HandshakeMismatch`]: crate::GgrsEvent::HandshakeMismatch
+    pub fn with_input_codec(mut self, codec: impl Codec + 'static) -> Self {
+        self.input_codec = Arc::new(codec);
+        self
+    }
+
+    /// Sets the [`Clock`] all timers of the session are based on, like the disconnect timeout, the ping and the frame advantage.
+    /// By default, the session uses the [`StdClock`]. A custom clock lets console ports use their own time APIs and test harnesses
+    /// control time completely.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Limits the outgoing bandwidth to every remote client and spectator to the given number of bytes per second, including the IP and UDP headers.
+    /// Over the limit, inputs are collected and sent together in fewer packets, input acknowledgements are left to the input packets
+    /// and the inputs for spectators are broadcast less often. This trades latency for bandwidth, so a limit below the usual usage
+    /// leads to more rollbacks. [`NetworkStats`] reports the bandwidth used and how often sends were put off. Packets to synchronize,
+    /// keep the connection alive and measure its quality are always sent.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the limit is 0.
+    ///
+    /// [`NetworkStats`]: crate::NetworkStats
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_bandwidth_limit(mut self, bytes_per_second: usize) -> Result<Self, GgrsError> {
+        if bytes_per_second == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Bandwidth limit should be higher than 0.".to_owned(),
+            });
+        }
+        self.bandwidth_limit = Some(bytes_per_second);
+        Ok(self)
+    }
+
+    /// Limits the number of received packets a session processes per call to `poll_remote_clients()`, which includes the polls
+    /// of `advance_frame()`. Packets over the budget are left in the socket and processed by the next poll, so a burst of
+    /// packets, e.g. after the game loop hitched, is spread over several frames instead of taking up the frame budget of a
+    /// single one. Sockets that don't implement [`NonBlockingSocket::receive_messages()`] return all their packets at once, in
+    /// which case the session keeps up to another budget of them for the next poll and drops older ones.
+    /// [`NetworkStats::receive_backlog`] reports the packets the session keeps. Default is 1024.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the budget is 0.
+    ///
+    /// [`NetworkStats::receive_backlog`]: crate::NetworkStats#structfield.receive_backlog
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_receive_budget(mut self, packets: usize) -> Result<Self, GgrsError> {
+        if packets == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Receive budget should be higher than 0.".to_owned(),
+            });
+        }
+        self.receive_budget = packets;
+        Ok(self)
+    }
+
+    /// Limits the number of bytes a session takes from its socket per call to `poll_remote_clients()`, like
+    /// [`with_receive_budget()`] limits the number of packets. The packet that crosses the limit is still processed, so every
+    /// poll makes progress. Only sockets that implement [`NonBlockingSocket::receive_messages()`] can leave the rest of their
+    /// packets waiting, which [`UdpNonBlockingSocket`] does. Default is 1 MiB.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the budget is 0.
+    ///
+    /// [`with_receive_budget()`]: Self#method.with_receive_budget
+    /// [`UdpNonBlockingSocket`]: crate::UdpNonBlockingSocket
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_receive_byte_budget(mut self, bytes: usize) -> Result<Self, GgrsError> {
+        if bytes == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Receive byte budget should be higher than 0.".to_owned(),
+            });
+        }
+        self.receive_byte_budget = bytes;
+        Ok(self)
+    }
+
+    /// Lets every input packet to a remote client or spectator carry at least the newest `frames` frames of inputs, even if the
+    /// remote client already acknowledged some of them. At most a prediction window of frames is resent and [`NetworkStats`]
+    /// reports the effective redundancy. By default, every packet carries only the unacknowledged inputs, which is a redundancy of 1.
+    ///
+    /// Note that this does not make the delivery of inputs more reliable: every packet already carries all inputs the remote client
+    /// has not acknowledged, so the inputs of a lost packet always arrive with the next one. The extra frames are inputs the
+    /// remote client is known to have, so a higher redundancy only costs bandwidth.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if `frames` is 0.
+    ///
+    /// [`NetworkStats`]: crate::NetworkStats
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_input_redundancy(mut self, frames: usize) -> Result<Self, GgrsError> {
+        if frames == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Input redundancy should be at least 1 frame.".to_owned(),
+            });
+        }
+        self.input_redundancy = frames;
+        Ok(self)
+    }
+
+    /// Registers a [`PacketObserver`] that is called with every message the session sends and receives, decoded and together with
+    /// the address of the remote client and a timestamp. This is meant for debugging the protocol, for example by writing the messages
+    /// to a file and inspecting them in a timeline. Requires the `net-trace` feature.
+    #[cfg(feature = "net-trace")]
+    pub fn with_packet_observer(
+        mut self,
+        observer: impl PacketObserver<T::Address> + 'static,
+    ) -> Self {
+        self.packet_observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Enables or disables replay recording. If enabled, the [`P2PSession`] records the confirmed inputs of all players for every frame
+    /// into a [`Replay`], which you can access via [`P2PSession::replay()`] and play back with [`SyncTestSession::from_replay()`].
+    /// The replay grows by one input per player and frame, so take the recorded frames out periodically with [`P2PSession::take_replay_chunk()`]
+    /// to keep the memory of long sessions bounded.
+    ///
+    /// [`Replay`]: crate::Replay
+    /// [`P2PSession::replay()`]: P2PSession#method.replay
+    /// [`P2PSession::take_replay_chunk()`]: P2PSession#method.take_replay_chunk
+    /// [`SyncTestSession::from_replay()`]: SyncTestSession#method.from_replay
+    pub fn with_replay_recording(mut self, enabled: bool) -> Self {
+        self.replay_recording = enabled;
+        self
+    }
+
+    /// Sets how many checksums are kept per peer for desync detection. Checksums older than this many desync detection intervals are
+    /// discarded without being compared, so raise it if the checksums of a peer can arrive later than that. Default is 32.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the size is 0.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_checksum_history_size(mut self, size: usize) -> Result<Self, GgrsError> {
+        if size == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Checksum history size should be higher than 0.".to_owned(),
+            });
+        }
+        self.checksum_history_size = size;
+        Ok(self)
+    }
+
+    /// Sets for how many of the most recent frames that carried input metadata it is kept per player, see [`P2PSession::input_metadata()`].
+    /// Default is 128.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the size is 0.
+    ///
+    /// [`P2PSession::input_metadata()`]: P2PSession#method.input_metadata
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_input_metadata_history_size(mut self, size: usize) -> Result<Self, GgrsError> {
+        if size == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Input metadata history size should be higher than 0.".to_owned(),
+            });
+        }
+        self.input_metadata_history_size = size;
+        Ok(self)
+    }
+
+    /// If enabled, the inputs of a remote player are left out of [`GgrsRequest::AdvanceFrame`] from the frame they disconnected on,
+    /// instead of being filled with dummy inputs marked [`InputStatus::Disconnected`]. The inputs of all players with higher handles
+    /// move up by one slot, which is announced with [`GgrsEvent::PlayerRemoved`]. [`P2PSession::input_handles()`] returns which
+    /// player each input belongs to for a given frame. This suits games that drop disconnected players instead of replacing them with an AI.
+    /// Since the inputs of spectators are sent before they are collapsed, this does not affect [`SpectatorSession`]s. Default is `false`.
+    ///
+    /// [`GgrsRequest::AdvanceFrame`]: crate::GgrsRequest::AdvanceFrame
+    /// [`InputStatus::Disconnected`]: crate::InputStatus::Disconnected
+    /// [`GgrsEvent::PlayerRemoved`]: crate::GgrsEvent::PlayerRemoved
+    /// [`P2PSession::input_handles()`]: P2PSession#method.input_handles
+    pub fn with_disconnected_player_removal(mut self, remove: bool) -> Self {
+        self.remove_disconnected_players = remove;
+        self
+    }
+
+    /// Limits how many frames a rollback resimulates per call to [`P2PSession::advance_frame()`]. If a rollback needs more frames, for
+    /// example after a long interruption, the next calls continue the resimulation instead of advancing new frames, until the session
+    /// is back at the frame it rolled back from. The session stays behind for a few calls, but no single call has to recompute all frames
+    /// at once, which avoids a hitch on slow machines. Local input added during these calls is only registered once the rollback has caught up.
+    /// By default, a rollback is always finished within a single call.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the number of frames is 0.
+    ///
+    /// [`P2PSession::advance_frame()`]: P2PSession#method.advance_frame
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_max_resim_frames_per_advance(mut self, frames: usize) -> Result<Self, GgrsError> {
+        if frames == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Maximum resimulated frames per advance should be higher than 0.".to_owned(),
+            });
+        }
+        self.max_resim_frames = Some(frames);
+        Ok(self)
+    }
+
+    /// If enabled, a [`P2PSession`] sends a [`GgrsEvent::Rollback`] for every reason of a rollback and a [`GgrsEvent::Stalled`]
+    /// for every stall. On a bad connection, these come every frame and push older events like [`GgrsEvent::Disconnected`] out
+    /// of the event queue, unless you drain it every frame. The counts are always available in [`P2PSession::rollback_stats()`].
+    /// Default is `false`.
+    ///
+    /// [`GgrsEvent::Rollback`]: crate::GgrsEvent::Rollback
+    /// [`GgrsEvent::Stalled`]: crate::GgrsEvent::Stalled
+    /// [`GgrsEvent::Disconnected`]: crate::GgrsEvent::Disconnected
+    /// [`P2PSession::rollback_stats()`]: P2PSession#method.rollback_stats
+    pub fn with_rollback_events(mut self, enabled: bool) -> Self {
+        self.rollback_events = enabled;
+        self
+    }
+
+    /// If enabled, a [`P2PSession`] or [`SyncTestSession`] encloses the requests of every rollback with a [`GgrsRequest::BeginRollback`]
+    /// and a [`GgrsRequest::EndRollback`], so you can tell resimulated frames apart from new ones, e.g. to suppress sounds and
+    /// particles while resimulating. Default is `false`.
+    ///
+    /// [`GgrsRequest::BeginRollback`]: crate::GgrsRequest::BeginRollback
+    /// [`GgrsRequest::EndRollback`]: crate::GgrsRequest::EndRollback
+    pub fn with_rollback_requests(mut self, enabled: bool) -> Self {
+        self.rollback_requests = enabled;
+        self
+    }
+
+    /// Sets how the session handles the first frames, for which no remote inputs can have arrived yet. The default is [`StartupPolicy::Predict`].
+    /// All peers need to use the same policy, so every match starts the same way regardless of how long the handshake took.
+    pub fn with_startup_policy(mut self, startup_policy: StartupPolicy) -> Self {
+        self.startup_policy = startup_policy;
+        self
+    }
+
+    /// Lets a [`P2PSession`] or [`SpectatorSession`] start at the given frame instead of frame 0, e.g. to resume a saved match.
+    /// Before starting the session, every peer and spectator loads the same state of that frame on its own; the first call to
+    /// [`P2PSession::advance_frame()`] then saves it, so rollbacks can go back to it. All inputs before the start frame are blank,
+    /// and a recorded [`Replay`] begins with the inputs of the start frame. All peers and spectators need to use the same frame:
+    /// clients with different start frames disconnect from each other during synchronization with a
+    /// [`GgrsEvent::HandshakeMismatch`].
+    /// Local and sync test sessions always start at frame 0.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the frame is negative or not below [`MAX_FRAME`].
+    ///
+    /// [`P2PSession::advance_frame()`]: P2PSession#method.advance_frame
+    /// [`Replay`]: crate::Replay
+    /// [`GgrsEvent::HandshakeMismatch`]: crate::GgrsEvent::HandshakeMismatch
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_start_frame(mut self, frame: Frame) -> Result<Self, GgrsError> {
+        if !(0..MAX_FRAME).contains(&frame) {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "Start frame {} must be between 0 and {}.",
+                    frame,
+                    MAX_FRAME - 1
+                ),
+            });
+        }
+        self.start_frame = frame;
+        Ok(self)
+    }
+
+    /// Enables a synchronized start: once all endpoints have synchronized, the peers agree on a wall-clock start time `countdown`
+    /// in the future, using the clock offsets measured during synchronization. The session stays in [`SessionState::Synchronizing`]
+    /// until that time, so all peers begin frame 0 within a few milliseconds of each other instead of whenever their handshake finished.
+    /// [`P2PSession::time_until_start()`] returns the time left, e.g. to show a countdown. For a countdown in frames, pass the frames
+    /// divided by the fps. Peers announce during synchronization whether they use it, so a peer without it starts right away and
+    /// the others do not wait for its proposal.
+    ///
+    /// [`SessionState::Synchronizing`]: crate::SessionState::Synchronizing
+    /// [`P2PSession::time_until_start()`]: P2PSession#method.time_until_start
+    pub fn with_synchronized_start(mut self, countdown: Duration) -> Self {
+        self.synchronized_start = Some(countdown);
+        self
+    }
+
     /// Sets the desync detection mode. With desync detection, the session will compare checksums for all peers to detect discrepancies / desyncs between peers
     /// If a desync is found the session will send a DesyncDetected event.
+    /// This does not apply to a [`SpectatorSession`], see [`with_spectator_desync_detection()`] instead.
+    ///
+    /// [`with_spectator_desync_detection()`]: Self#method.with_spectator_desync_detection
     pub fn with_desync_detection_mode(mut self, desync_detection: DesyncDetection) -> Self {
         self.desync_detection = desync_detection;
         self
     }
 
+    /// Sets the desync detection mode of a [`SpectatorSession`]. Default is [`DesyncDetection::Off`].
+    /// A spectator with desync detection asks you to save its state every `interval` frames and compares the checksums against
+    /// the ones of its host, which needs desync detection with the same interval. Only the checksum is needed, so the state itself
+    /// can be left out of the save. This way, a spectator can act as a referee that notices a host whose states diverge from the
+    /// inputs it broadcasts.
+    pub fn with_spectator_desync_detection(mut self, desync_detection: DesyncDetection) -> Self {
+        self.spectator_desync_detection = desync_detection;
+        self
+    }
+
+    /// Enables or disables time sync suggestions. If enabled, the session periodically sends a [`TimeSyncSuggestion`] event with a fractional
+    /// speed factor computed from the smoothed frame advantage. Games that support time dilation can use it to stay in sync with other clients
+    /// instead of skipping frames as suggested by [`WaitRecommendation`].
+    ///
+    /// [`TimeSyncSuggestion`]: crate::GgrsEvent::TimeSyncSuggestion
+    /// [`WaitRecommendation`]: crate::GgrsEvent::WaitRecommendation
+    pub fn with_time_sync_suggestions(mut self, time_sync_suggestions: bool) -> Self {
+        self.time_sync_suggestions = time_sync_suggestions;
+        self
+    }
+
+    /// Enables FPS mismatch detection. The session measures the rate at which every remote client advances frames and sends a [`FpsMismatch`] event
+    /// if it deviates from the FPS set via [`with_fps()`] by more than `tolerance` frames per second. The rate is measured over windows of two seconds.
+    /// The declared FPS of the remote client is exchanged during synchronization and included in the event.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the tolerance is negative or not finite.
+    ///
+    /// [`FpsMismatch`]: crate::GgrsEvent::FpsMismatch
+    /// [`with_fps()`]: Self#method.with_fps
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_fps_mismatch_detection(mut self, tolerance: f64) -> Result<Self, GgrsError> {
+        if !tolerance.is_finite() || tolerance < 0.0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "FPS mismatch tolerance should be a finite number of at least 0.".to_owned(),
+            });
+        }
+        self.fps_mismatch_tolerance = Some(tolerance);
+        Ok(self)
+    }
+
     /// Sets the disconnect timeout. The session will automatically disconnect from a remote peer if it has not received a packet in the timeout window.
     pub fn with_disconnect_timeout(mut self, timeout: Duration) -> Self {
         self.disconnect_timeout = timeout;
         self
     }
 
+    /// Lets the synchronization with a remote player or spectator of a [`P2PSession`] fail if the handshake made no progress for
+    /// `timeout`, e.g. because the remote client is unreachable. The session then sends a [`GgrsEvent::SynchronizationFailed`] and
+    /// stops synchronizing with the remote client, until you retry with [`P2PSession::retry_synchronization()`] or give up on it with
+    /// [`P2PSession::disconnect_player()`]. Until then, it also ignores the handshake of the remote client, so a remote client
+    /// without a timeout keeps waiting instead of starting alone. By default, the session keeps trying forever.
+    ///
+    /// [`GgrsEvent::SynchronizationFailed`]: crate::GgrsEvent::SynchronizationFailed
+    /// [`P2PSession::retry_synchronization()`]: P2PSession#method.retry_synchronization
+    /// [`P2PSession::disconnect_player()`]: P2PSession#method.disconnect_player
+    pub fn with_sync_timeout(mut self, timeout: Duration) -> Self {
+        self.sync_timeout = Some(timeout);
+        self
+    }
+
     /// Sets the time before the first notification will be sent in case of a prolonged period of no received packages.
     pub fn with_disconnect_notify_delay(mut self, notify_delay: Duration) -> Self {
         self.disconnect_notify_start = notify_delay;
@@ -198,6 +912,11 @@ impl<T: Config> SessionBuilder<T> {
         Ok(self)
     }
 
+    /// Returns the fps set with [`Self::with_fps()`].
+    pub(crate) fn fps(&self) -> usize {
+        self.fps
+    }
+
     /// Change the check distance. Default is 2.
     pub fn with_check_distance(mut self, check_distance: usize) -> Self {
         self.check_dist = check_distance;
@@ -214,9 +933,9 @@ impl<T: Config> SessionBuilder<T> {
             });
         }
 
-        if max_frames_behind >= SPECTATOR_BUFFER_SIZE {
+        if max_frames_behind >= self.spectator_buffer_size {
             return Err(GgrsError::InvalidRequest {
-                info: "Max frames behind cannot be larger or equal than the Spectator buffer size (60)"
+                info: "Max frames behind cannot be larger or equal than the Spectator buffer size"
                     .to_owned(),
             });
         }
@@ -243,20 +962,111 @@ impl<T: Config> SessionBuilder<T> {
         Ok(self)
     }
 
+    /// Sets the amount of frames the host collects confirmed inputs for before sending them to its spectators in a single packet.
+    /// Per default, this is set to 1, so inputs are sent every frame. Higher values save bandwidth when hosting many spectators,
+    /// at the cost of the spectators receiving inputs later.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the interval is 0 or not smaller than the Spectator buffer size
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_spectator_input_interval(mut self, interval: usize) -> Result<Self, GgrsError> {
+        if interval < 1 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Spectator input interval cannot be smaller than 1.".to_owned(),
+            });
+        }
+
+        if interval >= self.spectator_buffer_size {
+            return Err(GgrsError::InvalidRequest {
+                info: "Spectator input interval cannot be larger or equal than the Spectator buffer size"
+                    .to_owned(),
+            });
+        }
+        self.spectator_input_interval = interval;
+        Ok(self)
+    }
+
+    /// Sets the amount of frames a spectator can buffer received inputs for. Per default, this is set to 60, a second worth of inputs.
+    /// If the host runs further ahead than this, the spectator cannot continue and returns [`SpectatorTooFarBehind`].
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the size is not larger than the maximum frames behind or the spectator input interval
+    ///
+    /// [`SpectatorTooFarBehind`]: GgrsError::SpectatorTooFarBehind
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_spectator_buffer_size(mut self, size: usize) -> Result<Self, GgrsError> {
+        if size <= self.max_frames_behind {
+            return Err(GgrsError::InvalidRequest {
+                info: "Spectator buffer size must be larger than the maximum frames behind host"
+                    .to_owned(),
+            });
+        }
+
+        if size <= self.spectator_input_interval {
+            return Err(GgrsError::InvalidRequest {
+                info: "Spectator buffer size must be larger than the spectator input interval"
+                    .to_owned(),
+            });
+        }
+        self.spectator_buffer_size = size;
+        Ok(self)
+    }
+
+    /// Delays the broadcast to a spectator: the host only sends confirmed inputs to it once they have been confirmed for at least
+    /// `delay`, and keeps the confirmed inputs around until then. This way, the stream of a tournament match cannot be used to coach
+    /// players in real time. Other spectators are not affected. Per default, spectators receive inputs as soon as they are confirmed.
+    /// The delay is measured with the clock of the session, see [`with_clock()`].
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the handle has not been added as a spectator before.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`with_clock()`]: Self#method.with_clock
+    pub fn with_spectator_delay(
+        mut self,
+        player_handle: PlayerHandle,
+        delay: Duration,
+    ) -> Result<Self, GgrsError> {
+        let Some(PlayerType::Spectator(addr)) = self.player_reg.handles.get(&player_handle) else {
+            return Err(GgrsError::InvalidRequest {
+                info: "Broadcast delays can only be set for spectators.".to_owned(),
+            });
+        };
+        self.player_reg.spectator_delays.insert(addr.clone(), delay);
+        Ok(self)
+    }
+
     /// Consumes the builder to construct a [`P2PSession`] and starts synchronization of endpoints.
     /// # Errors
-    /// - Returns [`InvalidRequest`] if insufficient players have been registered.
+    /// - Returns [`InvalidRequest`] naming the missing handles if not every handle from 0 to `num_players - 1` has been added.
+    /// - Returns [`InvalidRequest`] if a player or spectator handle no longer fits the number of players set with [`with_num_players()`].
+    /// - Returns [`InvalidRequest`] if the input queue is too short for the prediction window, input delay and save interval.
+    /// - Returns [`InvalidRequest`] if the input queue is too short for the frames of a [`StartupPolicy::InitialDelay`].
+    /// - Returns [`InvalidRequest`] if sparse saving is combined with [`with_max_resim_frames_per_advance()`].
     ///
     /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`with_num_players()`]: Self::with_num_players
+    /// [`with_max_resim_frames_per_advance()`]: Self::with_max_resim_frames_per_advance
     pub fn start_p2p_session(
         mut self,
         socket: impl NonBlockingSocket<T::Address> + 'static,
     ) -> Result<P2PSession<T>, GgrsError> {
-        // check if all players are added
-        for player_handle in 0..self.num_players {
-            if !self.player_reg.handles.contains_key(&player_handle) {
-                return Err(GgrsError::InvalidRequest{
-                    info: "Not enough players have been added. Keep registering players up to the defined player number.".to_owned(),
+        self.check_player_handles()?;
+        self.check_input_queue_length()?;
+        // with sparse saving, a rollback saves a single state on the way, which might not be reached within the budget
+        if self.sparse_saving && self.max_resim_frames.is_some() {
+            return Err(GgrsError::InvalidRequest {
+                info: "Sparse saving cannot be combined with a maximum of resimulated frames per advance.".to_owned(),
+            });
+        }
+        // the blank inputs of the initial delay are only confirmed once the first real inputs arrive
+        if let StartupPolicy::InitialDelay { frames } = self.startup_policy {
+            let required = frames + 2 * self.max_prediction + self.input_delay;
+            if self.input_queue_length <= required {
+                return Err(GgrsError::InvalidRequest {
+                    info: format!(
+                        "Input queue length ({}) too small for an initial delay of {} frames, it must be larger than {}.",
+                        self.input_queue_length, frames, required
+                    ),
                 });
             }
         }
@@ -265,6 +1075,8 @@ impl<T: Config> SessionBuilder<T> {
         let mut addr_count = HashMap::<PlayerType<T::Address>, Vec<PlayerHandle>>::new();
         for (handle, player_type) in self.player_reg.handles.iter() {
             match player_type {
+                // the inputs of linked players are not sent on the wire
+                PlayerType::Remote(_) if self.player_reg.links.contains_key(handle) => (),
                 PlayerType::Remote(_) | PlayerType::Spectator(_) => addr_count
                     .entry(player_type.clone())
                     .or_insert_with(Vec::new)
@@ -282,24 +1094,53 @@ impl<T: Config> SessionBuilder<T> {
                         self.create_endpoint(handles, peer_addr.clone(), self.local_players),
                     );
                 }
+                // a spectator sends no inputs, its host sends inputs for all players
                 PlayerType::Spectator(peer_addr) => {
                     self.player_reg.spectators.insert(
                         peer_addr.clone(),
-                        self.create_endpoint(handles, peer_addr.clone(), self.num_players), // the host of the spectator sends inputs for all players
+                        self.create_endpoint(Vec::new(), peer_addr.clone(), self.num_players),
                     );
                 }
                 PlayerType::Local => (),
             }
         }
 
+        let socket = self.wrap_socket(Box::new(socket));
+        let settings = P2PSettings {
+            num_players: self.num_players,
+            max_prediction: self.max_prediction,
+            sparse_saving: self.sparse_saving,
+            delay_based: self.delay_based,
+            desync_detection: self.desync_detection,
+            input_delay: self.input_delay,
+            time_sync_suggestions: self.time_sync_suggestions,
+            spectator_input_interval: self.spectator_input_interval,
+            save_interval: self.save_interval,
+            skip_confirmed_saves: self.skip_confirmed_saves,
+            input_queue_length: self.input_queue_length,
+            input_history: self.input_history,
+            state_diffing: self.state_diffing,
+            auto_checksums: self.auto_checksums,
+            replay_recording: self.replay_recording,
+            startup_policy: self.startup_policy,
+            start_frame: self.start_frame,
+            fps: self.fps,
+            synchronized_start: self.synchronized_start,
+            input_coalescing: self.input_coalescing,
+            sticky_input_mask: self.sticky_input_mask,
+            checksum_history_size: self.checksum_history_size,
+            input_metadata_history_size: self.input_metadata_history_size,
+            remove_disconnected_players: self.remove_disconnected_players,
+            max_resim_frames: self.max_resim_frames,
+            rollback_events: self.rollback_events,
+            rollback_requests: self.rollback_requests,
+            clock: self.clock,
+        };
         Ok(P2PSession::<T>::new(
-            self.num_players,
-            self.max_prediction,
-            Box::new(socket),
+            settings,
+            socket,
             self.player_reg,
-            self.sparse_saving,
-            self.desync_detection,
-            self.input_delay,
+            ReceiveBacklog::new(self.receive_budget, self.receive_byte_budget),
         ))
     }
 
@@ -308,50 +1149,208 @@ impl<T: Config> SessionBuilder<T> {
     /// The host will broadcast all confirmed inputs to this session.
     /// This session can be used to spectate a session without contributing to the game input.
     pub fn start_spectator_session(
-        self,
+        mut self,
         host_addr: T::Address,
         socket: impl NonBlockingSocket<T::Address> + 'static,
     ) -> SpectatorSession<T> {
         // create host endpoint
         let mut host = UdpProtocol::new(
             (0..self.num_players).collect(),
-            host_addr,
-            self.num_players,
+            host_addr.clone(),
             1, //should not matter since the spectator is never sending
-            self.max_prediction,
-            self.disconnect_timeout,
-            self.disconnect_notify_start,
-            self.fps,
-            DesyncDetection::Off,
+            EndpointSettings {
+                desync_detection: self.spectator_desync_detection,
+                delay_based: false,
+                local_player_ids: Vec::new(),
+                ..self.endpoint_settings()
+            },
         );
+        if let Some(candidates) = self.remote_candidates.get(&host_addr) {
+            host.set_candidate_addrs(candidates.clone());
+        }
+        if let Some(input_len) = self.input_len {
+            host.set_input_len(input_len);
+        }
+        if let Some(limit) = self.bandwidth_limit {
+            host.set_bandwidth_limit(limit);
+        }
+        host.set_checksum_history_size(self.checksum_history_size);
+        self.set_handshake(&mut host);
         host.synchronize();
+        let socket = self.wrap_socket(Box::new(socket));
+        let settings = SpectatorSettings {
+            num_players: self.num_players,
+            max_frames_behind: self.max_frames_behind,
+            catchup_speed: self.catchup_speed,
+            buffer_size: self.spectator_buffer_size,
+            desync_detection: self.spectator_desync_detection,
+            checksum_history_size: self.checksum_history_size,
+            auto_checksums: self.auto_checksums,
+            start_frame: self.start_frame,
+        };
         SpectatorSession::new(
-            self.num_players,
-            Box::new(socket),
+            settings,
+            socket,
             host,
-            self.max_frames_behind,
-            self.catchup_speed,
+            ReceiveBacklog::new(self.receive_budget, self.receive_byte_budget),
         )
     }
 
+    /// Consumes the builder to construct a new [`LocalSession`] for offline play. All `num_players` players are local, and the session
+    /// uses the input delay, input queue length, input history, input coalescing and fps set on this builder. Players do not need to be added,
+    /// but adding them as [`PlayerType::Local`] lets the game set up offline and online sessions the same way.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if a remote player or spectator has been added.
+    /// - Returns [`InvalidRequest`] if the input queue is too short for the prediction window and input delay.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn start_local_session(self) -> Result<LocalSession<T>, GgrsError> {
+        if self
+            .player_reg
+            .handles
+            .values()
+            .any(|player_type| *player_type != PlayerType::Local)
+        {
+            return Err(GgrsError::InvalidRequest {
+                info: "A local session cannot have remote players or spectators.".to_owned(),
+            });
+        }
+        self.check_input_queue_length()?;
+        Ok(LocalSession::new(
+            self.num_players,
+            self.input_delay,
+            self.input_queue_length,
+            self.input_history,
+            self.input_coalescing,
+            self.fps,
+        ))
+    }
+
     /// Consumes the builder to construct a new [`SyncTestSession`]. During a [`SyncTestSession`], GGRS will simulate a rollback every frame
     /// and resimulate the last n states, where n is the given `check_distance`.
     /// The resimulated checksums will be compared with the original checksums and report if there was a mismatch.
     /// Due to the decentralized nature of saving and loading gamestates, checksum comparisons can only be made if `check_distance` is 2 or higher.
     /// This is a great way to test if your system runs deterministically.
     /// After creating the session, add a local player, set input delay for them and then start the session.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the check distance is not smaller than the prediction window.
+    /// - Returns [`InvalidRequest`] if the input queue is too short for the prediction window and input delay.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
     pub fn start_synctest_session(self) -> Result<SyncTestSession<T>, GgrsError> {
         if self.check_dist >= self.max_prediction {
             return Err(GgrsError::InvalidRequest {
                 info: "Check distance too big.".to_owned(),
             });
         }
-        Ok(SyncTestSession::new(
-            self.num_players,
-            self.max_prediction,
-            self.check_dist,
-            self.input_delay,
-        ))
+        self.check_input_queue_length()?;
+        Ok(SyncTestSession::new(SyncTestSettings {
+            num_players: self.num_players,
+            max_prediction: self.max_prediction,
+            check_distance: self.check_dist,
+            input_delay: self.input_delay,
+            input_queue_length: self.input_queue_length,
+            input_history: self.input_history,
+            state_diffing: self.state_diffing,
+            auto_checksums: self.auto_checksums,
+            input_coalescing: self.input_coalescing,
+            rollback_requests: self.rollback_requests,
+        }))
+    }
+
+    /// Makes sure the input queue can hold all inputs that are not confirmed yet: the local inputs up to the prediction window and input delay,
+    /// the remote inputs of peers running up to a prediction window ahead, the inputs since the last saved state and the input history.
+    fn check_input_queue_length(&self) -> Result<(), GgrsError> {
+        let required =
+            2 * self.max_prediction + self.input_delay + self.save_interval + self.input_history;
+        if self.input_queue_length <= required {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "Input queue length ({}) too small, it must be larger than 2 * max prediction + input delay + save interval + input history ({}).",
+                    self.input_queue_length, required
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the players take exactly the handles from 0 to `num_players - 1` and the spectators the handles above, which
+    /// might no longer hold if the number of players changed after adding them.
+    fn check_player_handles(&self) -> Result<(), GgrsError> {
+        let missing: Vec<PlayerHandle> = (0..self.num_players)
+            .filter(|handle| !self.player_reg.handles.contains_key(handle))
+            .collect();
+        if !missing.is_empty() {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "No players have been added for the handles {:?}. Every handle from 0 to {} needs a player.",
+                    missing,
+                    self.num_players - 1
+                ),
+            });
+        }
+
+        let mut misplaced: Vec<PlayerHandle> = self
+            .player_reg
+            .handles
+            .iter()
+            .filter(|(handle, player_type)| match player_type {
+                PlayerType::Local | PlayerType::Remote(_) => **handle >= self.num_players,
+                PlayerType::Spectator(_) => **handle < self.num_players,
+            })
+            .map(|(handle, _)| *handle)
+            .collect();
+        if !misplaced.is_empty() {
+            misplaced.sort_unstable();
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "The handles {:?} do not fit {} players. Players need handles below {} and spectators handles of {} or higher.",
+                    misplaced, self.num_players, self.num_players, self.num_players
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Wraps the socket so the packet observer sees every message passing through it, if one is registered.
+    fn wrap_socket(&mut self, socket: Box<dyn NonBlockingSocket<T::Address>>) -> SessionSocket<T> {
+        #[cfg(feature = "net-trace")]
+        if let Some(observer) = self.packet_observer.take() {
+            return SessionSocket::traced(TracedSocket::new(socket, observer, self.clock.clone()));
+        }
+        SessionSocket::plain(socket)
+    }
+
+    /// Passes the handshake payload, external address and verifier on to the given endpoint.
+    fn set_handshake(&self, endpoint: &mut UdpProtocol<T>) {
+        endpoint.set_handshake_payload(self.handshake_payload.clone());
+        endpoint.set_external_addr(self.external_addr);
+        endpoint.set_start_frame(self.start_frame);
+        if let Some(verifier) = &self.handshake_verifier {
+            endpoint.set_handshake_verifier(verifier.clone());
+        }
+    }
+
+    fn endpoint_settings(&self) -> EndpointSettings {
+        EndpointSettings {
+            num_players: self.num_players,
+            max_prediction: self.max_prediction,
+            disconnect_timeout: self.disconnect_timeout,
+            disconnect_notify_start: self.disconnect_notify_start,
+            fps: self.fps,
+            desync_detection: self.desync_detection,
+            delay_based: self.delay_based,
+            session_token: self.session_token,
+            fps_mismatch_tolerance: self.fps_mismatch_tolerance,
+            input_codec: self.input_codec.clone(),
+            local_player_ids: self
+                .player_reg
+                .player_ids
+                .iter()
+                .map(|(handle, id)| (*handle, *id))
+                .collect(),
+            clock: self.clock.clone(),
+        }
     }
 
     fn create_endpoint(
@@ -363,17 +1362,105 @@ impl<T: Config> SessionBuilder<T> {
         // create the endpoint, set parameters
         let mut endpoint = UdpProtocol::new(
             handles,
-            peer_addr,
-            self.num_players,
+            peer_addr.clone(),
             local_players,
-            self.max_prediction,
-            self.disconnect_timeout,
-            self.disconnect_notify_start,
-            self.fps,
-            self.desync_detection,
+            self.endpoint_settings(),
         );
+        endpoint.set_synchronized_start(self.synchronized_start.is_some());
+        if let Some(candidates) = self.remote_candidates.get(&peer_addr) {
+            endpoint.set_candidate_addrs(candidates.clone());
+        }
+        if let Some(input_len) = self.input_len {
+            endpoint.set_input_len(input_len);
+        }
+        if let Some(limit) = self.bandwidth_limit {
+            endpoint.set_bandwidth_limit(limit);
+        }
+        endpoint.set_input_redundancy(self.input_redundancy);
+        if self.fair_input_delay {
+            endpoint.set_fair_input_delay(self.input_delay);
+        }
+        endpoint.set_checksum_history_size(self.checksum_history_size);
+        if let Some(timeout) = self.sync_timeout {
+            endpoint.set_sync_timeout(timeout);
+        }
+        self.set_handshake(&mut endpoint);
         // start the synchronization
         endpoint.synchronize();
         endpoint
     }
 }
+
+impl<T: Config> SessionBuilder<T>
+where
+    T::State: StateBytes,
+{
+    /// Stores saved states as byte deltas to the next newer saved state instead of full copies, only the newest state is kept in
+    /// full. For large states that only change a little from frame to frame, this reduces the memory used for saved states
+    /// considerably, at the cost of converting every saved state to bytes once and every loaded state back from bytes, walking
+    /// back from the newest state. For small states, the conversions cost more than they save.
+    /// The `rollback` benchmark compares both modes.
+    ///
+    /// With state diffing, a [`GameStateCell`] only contains a loadable state while you handle the request it was handed to you with.
+    ///
+    /// [`GameStateCell`]: crate::GameStateCell
+    pub fn with_state_diffing(mut self, enabled: bool) -> Self {
+        self.state_diffing = enabled.then(StateDiffing::new);
+        self
+    }
+
+    /// Computes the checksum of every saved state the game saved without one, from the bytes returned by [`StateBytes::to_bytes()`].
+    /// This lets the [`SyncTestSession`] and desync detection work for games that cannot cheaply hash their own state.
+    /// Checksums the game provides itself are kept as they are. All peers and spectators of a session need to use the same
+    /// [`ChecksumKind`], or they will report desyncs for every compared frame.
+    pub fn with_auto_checksums(self, kind: ChecksumKind) -> Self {
+        self.with_state_hasher(kind)
+    }
+
+    /// Like [`with_auto_checksums()`], but computes the checksums with the given [`StateHasher`], for example a collision-resistant
+    /// `Blake3Hasher` of the `blake3` feature when the desync verdicts have to be trusted.
+    ///
+    /// [`with_auto_checksums()`]: Self#method.with_auto_checksums
+    pub fn with_state_hasher(mut self, hasher: impl StateHasher + 'static) -> Self {
+        self.auto_checksums = Some(AutoChecksums::new(Arc::new(hasher)));
+        self
+    }
+}
+
+impl<T: Config> SessionBuilder<T>
+where
+    T::Input: VariableLengthInput,
+{
+    /// Transmits only the meaningful bytes of every input, as returned by [`VariableLengthInput::input_len()`], each prefixed by its length.
+    /// For inputs sized for the worst case that are mostly empty, like a buffer of RTS commands, this saves a lot of bandwidth
+    /// compared to sending the whole input. The inputs in the session keep their full size, with zeros after the meaningful bytes.
+    /// All peers and spectators of a session need to enable this: clients that disagree disconnect from each other during
+    /// synchronization with a [`GgrsEvent::HandshakeMismatch`]. Inputs without any size are always sent as they are.
+    ///
+    /// [`GgrsEvent::HandshakeMismatch`]: crate::GgrsEvent::HandshakeMismatch
+    pub fn with_variable_length_inputs(mut self, enabled: bool) -> Self {
+        let sized = std::mem::size_of::<T::Input>() > 0;
+        self.input_len =
+            (enabled && sized).then_some(T::Input::input_len as fn(&T::Input) -> usize);
+        self
+    }
+}
+
+impl<T: Config> SessionBuilder<T>
+where
+    T::Input: CoalescingInput,
+{
+    /// Merges all local inputs added for the same frame with [`CoalescingInput::coalesce()`] instead of keeping only the last one.
+    /// This is meant for games whose simulation ticks are much slower than their render frames, like an RTS running 10 command
+    /// turns per second: add the commands of every render frame as they come in, and call `advance_frame()` once per tick.
+    /// Set the tick rate with [`with_fps()`], so time synchronization works with ticks as well.
+    ///
+    /// Inputs added while a session stalls after it already sent the input of the current tick are kept for the next tick,
+    /// so no command gets lost.
+    ///
+    /// [`with_fps()`]: Self#method.with_fps
+    pub fn with_input_coalescing(mut self, enabled: bool) -> Self {
+        self.input_coalescing = enabled.then_some(T::Input::coalesce as CoalesceFn<T::Input>);
+        self
+    }
+}
@@ -0,0 +1,95 @@
+use instant::{Duration, Instant};
+
+/// The most frames a single call to `frames_to_advance()` returns. Time beyond that, e.g. after the game was suspended, is
+/// dropped instead of being caught up in a burst that takes even longer to simulate.
+const MAX_FRAMES_PER_CALL: u32 = 8;
+
+/// A fixed-timestep accumulator: the time between calls is added up and handed out in whole frames at the fps of the session.
+/// Frames a wait recommendation asks to skip are taken out of the frames that would otherwise be advanced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct FramePacer {
+    frame_time: Duration,
+    last_call: Option<Instant>,
+    accumulated: Duration,
+    /// The frames still to skip for wait recommendations.
+    pending_skips: u32,
+}
+
+impl FramePacer {
+    pub(crate) fn new(fps: usize) -> Self {
+        Self {
+            frame_time: Duration::from_secs(1) / fps as u32,
+            last_call: None,
+            accumulated: Duration::ZERO,
+            pending_skips: 0,
+        }
+    }
+
+    /// Skips the given number of frames over the next calls, to let the other clients catch up.
+    pub(crate) fn skip_frames(&mut self, frames: u32) {
+        self.pending_skips = self.pending_skips.max(frames);
+    }
+
+    /// Returns the number of frames to advance at `now`. The first call always advances a single frame.
+    pub(crate) fn frames_to_advance(&mut self, now: Instant) -> usize {
+        let Some(last_call) = self.last_call.replace(now) else {
+            return 1;
+        };
+        self.accumulated += now.saturating_duration_since(last_call);
+        self.accumulated = self.accumulated.min(self.frame_time * MAX_FRAMES_PER_CALL);
+
+        let mut frames = 0;
+        while self.accumulated >= self.frame_time {
+            self.accumulated -= self.frame_time;
+            frames += 1;
+        }
+        let skipped = frames.min(self.pending_skips);
+        self.pending_skips -= skipped;
+        (frames - skipped) as usize
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod frame_pacer_tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_to_advance() {
+        let start = Instant::now();
+        let frame_time = Duration::from_secs(1) / 60;
+        let mut pacer = FramePacer::new(60);
+        assert_eq!(pacer.frames_to_advance(start), 1);
+
+        // a render frame rate of 144hz advances a frame every second or third render frame
+        let frames: usize = (1..=144)
+            .map(|i| pacer.frames_to_advance(start + Duration::from_secs(1) * i / 144))
+            .sum();
+        assert_eq!(frames, 60);
+
+        // slow render frames advance several frames at once, but not too many
+        let now = start + Duration::from_secs(1);
+        assert_eq!(pacer.frames_to_advance(now + frame_time * 3), 3);
+        assert_eq!(
+            pacer.frames_to_advance(now + Duration::from_secs(10)),
+            MAX_FRAMES_PER_CALL as usize
+        );
+    }
+
+    #[test]
+    fn test_skip_frames() {
+        let start = Instant::now();
+        let frame_time = Duration::from_secs(1) / 60;
+        let mut pacer = FramePacer::new(60);
+        pacer.frames_to_advance(start);
+
+        pacer.skip_frames(3);
+        let frames: Vec<_> = (1..=5)
+            .map(|i| pacer.frames_to_advance(start + frame_time * i))
+            .collect();
+        assert_eq!(frames, vec![0, 0, 0, 1, 1]);
+    }
+}
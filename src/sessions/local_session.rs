@@ -0,0 +1,253 @@
+use std::collections::vec_deque::Drain;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use instant::Instant;
+
+use crate::error::GgrsError;
+use crate::frame_info::PlayerInput;
+use crate::network::messages::ConnectionStatus;
+use crate::sessions::frame_pacer::FramePacer;
+use crate::sync_layer::SyncLayer;
+use crate::{
+    CoalesceFn, Config, Frame, GgrsEvent, GgrsRequest, MemoryReport, PlayerHandle, PollSummary,
+    SessionState,
+};
+
+/// A [`LocalSession`] runs a game with local players only, for offline modes. It never saves, loads or resimulates states,
+/// so every call to [`advance_frame()`] returns a single [`GgrsRequest::AdvanceFrame`]. Its methods mirror those of a
+/// [`P2PSession`], so the game can drive offline and online matches with the same code path.
+///
+/// [`advance_frame()`]: Self#method.advance_frame
+/// [`P2PSession`]: crate::P2PSession
+pub struct LocalSession<T>
+where
+    T: Config,
+{
+    num_players: usize,
+    sync_layer: SyncLayer<T>,
+    /// All players are local, so their inputs are always confirmed.
+    connect_status: Vec<ConnectionStatus>,
+    local_inputs: HashMap<PlayerHandle, PlayerInput<T::Input>>,
+    /// The players input has been added for since the last call to advance_frame, to catch inputs that are added twice.
+    added_local_inputs: HashSet<PlayerHandle>,
+    /// If set, local inputs added for the same frame are merged instead of overwritten.
+    input_coalescing: Option<CoalesceFn<T::Input>>,
+    /// Always empty, as nothing happens in a local session that the game would need to be notified of.
+    event_queue: VecDeque<GgrsEvent<T>>,
+    /// Hands out the frames to advance per render frame, see [`LocalSession::frames_to_advance()`].
+    frame_pacer: FramePacer,
+}
+
+impl<T: Config> LocalSession<T> {
+    pub(crate) fn new(
+        num_players: usize,
+        input_delay: usize,
+        input_queue_length: usize,
+        input_history: usize,
+        input_coalescing: Option<CoalesceFn<T::Input>>,
+        fps: usize,
+    ) -> Self {
+        // inputs are confirmed one frame after they are added, so the session never gets close to this prediction window
+        let mut sync_layer = SyncLayer::new(num_players, 2);
+        sync_layer.set_input_queue_length(input_queue_length);
+        sync_layer.set_input_history(input_history);
+        for handle in 0..num_players {
+            sync_layer.set_frame_delay(handle, input_delay);
+        }
+
+        Self {
+            num_players,
+            sync_layer,
+            connect_status: vec![ConnectionStatus::default(); num_players],
+            local_inputs: HashMap::new(),
+            added_local_inputs: HashSet::new(),
+            input_coalescing,
+            event_queue: VecDeque::new(),
+            frame_pacer: FramePacer::new(fps),
+        }
+    }
+
+    /// Registers local input for a player for the current frame. This should be successfully called exactly once for every player before calling [`advance_frame()`].
+    /// Adding input multiple times for the same player is only allowed if [`SessionBuilder::with_input_coalescing()`] merges them.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] when the given handle is not valid (i.e. not between 0 and num_players).
+    /// - Returns [`InputAlreadyAdded`] if input has already been added for the player since the last call to [`advance_frame()`] and input coalescing is off.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`SessionBuilder::with_input_coalescing()`]: crate::SessionBuilder#method.with_input_coalescing
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`InputAlreadyAdded`]: GgrsError::InputAlreadyAdded
+    pub fn add_local_input(
+        &mut self,
+        player_handle: PlayerHandle,
+        input: T::Input,
+    ) -> Result<(), GgrsError> {
+        if player_handle >= self.num_players {
+            return Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            });
+        }
+        if !self.added_local_inputs.insert(player_handle) && self.input_coalescing.is_none() {
+            return Err(GgrsError::InputAlreadyAdded {
+                handle: player_handle,
+                frame: self.sync_layer.current_frame(),
+            });
+        }
+        match (
+            self.input_coalescing,
+            self.local_inputs.get_mut(&player_handle),
+        ) {
+            (Some(coalesce), Some(player_input)) => coalesce(&mut player_input.input, &input),
+            _ => {
+                let player_input =
+                    PlayerInput::<T::Input>::new(self.sync_layer.current_frame(), input);
+                self.local_inputs.insert(player_handle, player_input);
+            }
+        }
+        Ok(())
+    }
+
+    /// Advances the game by a single frame. Returns a [`Vec<GgrsRequest>`] with a single [`GgrsRequest::AdvanceFrame`]
+    /// holding the inputs of all players.
+    ///
+    /// # Errors
+    /// - Returns [`MissingInput`] if no input has been added for a player.
+    /// - Returns [`FrameLimitReached`] if the session reached [`MAX_FRAME`].
+    ///
+    /// [`Vec<GgrsRequest>`]: GgrsRequest
+    /// [`MissingInput`]: GgrsError::MissingInput
+    /// [`FrameLimitReached`]: GgrsError::FrameLimitReached
+    /// [`MAX_FRAME`]: crate::MAX_FRAME
+    pub fn advance_frame(&mut self) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        // local input can be added again for the next call, even if this one fails
+        self.added_local_inputs.clear();
+
+        if self.sync_layer.frame_limit_reached() {
+            return Err(GgrsError::FrameLimitReached);
+        }
+
+        // we require inputs for all players
+        if let Some(handle) = (0..self.num_players).find(|h| !self.local_inputs.contains_key(h)) {
+            return Err(GgrsError::MissingInput {
+                handle,
+                frame: self.sync_layer.current_frame(),
+            });
+        }
+        for (handle, input) in self.local_inputs.drain() {
+            self.sync_layer.add_local_input(handle, input.input)?;
+        }
+
+        let inputs = self.sync_layer.synchronized_inputs(&self.connect_status);
+        self.sync_layer.advance_frame();
+
+        // all inputs up to the new frame are known, so they are confirmed right away
+        let current_frame = self.sync_layer.current_frame();
+        self.sync_layer
+            .set_last_confirmed_frame(Some(current_frame - 1), false);
+        for con_stat in &mut self.connect_status {
+            con_stat.last_frame = Some(current_frame);
+        }
+
+        Ok(vec![GgrsRequest::AdvanceFrame { inputs }])
+    }
+
+    /// Asks `poll_input` for the input of every player, then advances the frame like [`advance_frame()`], see
+    /// [`P2PSession::advance_frame_with_input()`].
+    ///
+    /// # Errors
+    /// - Returns the errors of [`advance_frame()`].
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`P2PSession::advance_frame_with_input()`]: crate::P2PSession#method.advance_frame_with_input
+    pub fn advance_frame_with_input(
+        &mut self,
+        mut poll_input: impl FnMut(PlayerHandle) -> T::Input,
+    ) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        for handle in 0..self.num_players {
+            self.add_local_input(handle, poll_input(handle))?;
+        }
+        self.advance_frame()
+    }
+
+    /// Returns how many times to call [`advance_frame()`] during the current render frame, see [`P2PSession::frames_to_advance()`].
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`P2PSession::frames_to_advance()`]: crate::P2PSession#method.frames_to_advance
+    pub fn frames_to_advance(&mut self, now: Instant) -> usize {
+        self.frame_pacer.frames_to_advance(now)
+    }
+
+    /// Does nothing and returns an empty [`PollSummary`], as there are no remote clients. Exists so the game loop of a
+    /// [`P2PSession`] works unchanged.
+    ///
+    /// [`P2PSession`]: crate::P2PSession
+    pub fn poll_remote_clients(&mut self) -> PollSummary {
+        PollSummary::default()
+    }
+
+    /// Returns all events that happened since last queried for events. A local session has no events, so this is always empty.
+    pub fn events(&mut self) -> Drain<'_, GgrsEvent<T>> {
+        self.event_queue.drain(..)
+    }
+
+    /// Returns the current [`SessionState`]. A local session is always running.
+    pub fn current_state(&self) -> SessionState {
+        SessionState::Running
+    }
+
+    /// Returns the current frame of the session.
+    pub fn current_frame(&self) -> Frame {
+        self.sync_layer.current_frame()
+    }
+
+    /// Returns the highest confirmed frame. All inputs are local, so every frame before the current one is confirmed.
+    pub fn confirmed_frame(&self) -> Frame {
+        self.sync_layer.current_frame() - 1
+    }
+
+    /// Returns the number of players this session was constructed with.
+    pub fn num_players(&self) -> usize {
+        self.num_players
+    }
+
+    /// Returns the handles of all players, which are all local.
+    pub fn local_player_handles(&self) -> Vec<PlayerHandle> {
+        (0..self.num_players).collect()
+    }
+
+    /// Returns the input delay in frames of the given player.
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] when the given handle is not valid (i.e. not between 0 and num_players).
+    ///
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    pub fn input_delay(&self, player_handle: PlayerHandle) -> Result<usize, GgrsError> {
+        self.sync_layer
+            .frame_delay(player_handle)
+            .ok_or(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            })
+    }
+
+    /// Returns the confirmed inputs of all players for every frame from `frame` up to the last confirmed frame, see
+    /// [`P2PSession::inputs_since()`].
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the inputs of `frame` are no longer kept.
+    ///
+    /// [`P2PSession::inputs_since()`]: crate::P2PSession#method.inputs_since
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn inputs_since(&self, frame: Frame) -> Result<Vec<Vec<T::Input>>, GgrsError> {
+        self.sync_layer.inputs_since(frame, &self.connect_status)
+    }
+
+    /// Returns how many bytes the session currently holds for its input queues. See [`MemoryReport`] for what is counted.
+    pub fn memory_usage(&self) -> MemoryReport {
+        MemoryReport {
+            saved_states: 0,
+            input_queues: self.sync_layer.input_queues_memory(),
+            pending_packets: 0,
+            replay: 0,
+        }
+    }
+}
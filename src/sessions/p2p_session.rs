@@ -1,24 +1,76 @@
+use crate::checksum::AutoChecksums;
+use crate::clock;
 use crate::error::GgrsError;
 use crate::frame_info::PlayerInput;
 use crate::network::messages::ConnectionStatus;
-use crate::network::network_stats::NetworkStats;
-use crate::network::protocol::{UdpProtocol, MAX_CHECKSUM_HISTORY_SIZE};
+use crate::network::network_stats::{NetworkSample, NetworkStats};
+use crate::network::protocol::{UdpProtocol, MAX_INPUT_METADATA_SIZE};
+use crate::network::reliable::MAX_MESSAGE_SIZE;
+use crate::network::session_socket::{ReceiveBacklog, SessionSocket};
+use crate::replay::Replay;
+use crate::sessions::frame_pacer::FramePacer;
+use crate::state_diff::StateDiffing;
 use crate::sync_layer::SyncLayer;
-use crate::DesyncDetection;
 use crate::{
-    network::protocol::Event, Config, Frame, GgrsEvent, GgrsRequest, NonBlockingSocket,
-    PlayerHandle, PlayerType, SessionState, NULL_FRAME,
+    network::protocol::Event, CoalesceFn, Config, ConnectionState, Frame, GgrsEvent, GgrsRequest,
+    InputStatus, MemoryReport, NonBlockingSocket, PlayerHandle, PlayerId, PlayerType, PollSummary,
+    SessionState, StartupPolicy, NULL_FRAME,
 };
+use crate::{Clock, DesyncDetection};
+use crate::{DiagnosticsReport, PeerDiagnostics, SessionSettings};
+
+use bytemuck::Zeroable;
+use instant::{Duration, Instant};
+use serde::{Deserialize, Serialize};
 
 use std::collections::vec_deque::Drain;
-use std::collections::HashMap;
 use std::collections::VecDeque;
-use std::convert::TryInto;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
 
 const RECOMMENDATION_INTERVAL: Frame = 60;
 const MIN_RECOMMENDATION: u32 = 3;
+const TIME_SYNC_SUGGESTION_INTERVAL: Frame = 10;
+// the amount of frames over which a time sync suggestion intends to make up for the current frame advantage
+const TIME_SYNC_CORRECTION_FRAMES: f64 = 60.0;
+const MAX_SPEED_ADJUSTMENT: f64 = 0.1;
 const MAX_EVENT_QUEUE_SIZE: usize = 100;
 
+/// The confirmed inputs of all players for a single frame, as broadcast to the spectators.
+struct BroadcastInputs<T: Config> {
+    frame: Frame,
+    /// The time the inputs were confirmed, which the broadcast delay of a spectator counts from.
+    confirmed_at: Instant,
+    inputs: HashMap<PlayerHandle, PlayerInput<T::Input>>,
+}
+
+/// The progress of a player being handed over between this client and the remote client at `addr`, see
+/// [`P2PSession::hand_over_player()`], [`P2PSession::promote_spectator()`] and [`P2PSession::demote_player()`].
+enum HandOver<A> {
+    /// We asked the remote client to take over our local player and wait for the frame it answers with.
+    Requested { addr: A, frame: Frame },
+    /// The remote client takes over our local player from `frame` on.
+    Giving { addr: A, frame: Frame },
+    /// We asked the remote client to hand its player over to us and wait for the frame it answers with.
+    Reclaiming { addr: A, frame: Frame },
+    /// We take over the player of the remote client from `frame` on.
+    Taking { addr: A, frame: Frame },
+}
+
+impl<A> HandOver<A> {
+    /// Returns the address of the other client.
+    fn addr(&self) -> &A {
+        match self {
+            Self::Requested { addr, .. }
+            | Self::Giving { addr, .. }
+            | Self::Reclaiming { addr, .. }
+            | Self::Taking { addr, .. } => addr,
+        }
+    }
+}
+
 pub(crate) struct PlayerRegistry<T>
 where
     T: Config,
@@ -26,6 +78,12 @@ where
     pub(crate) handles: HashMap<PlayerHandle, PlayerType<T::Address>>,
     pub(crate) remotes: HashMap<T::Address, UdpProtocol<T>>,
     pub(crate) spectators: HashMap<T::Address, UdpProtocol<T>>,
+    /// The persistent ids of the local players.
+    pub(crate) player_ids: HashMap<PlayerHandle, PlayerId>,
+    /// The time confirmed inputs are held back for before they are sent to a spectator, by address.
+    pub(crate) spectator_delays: HashMap<T::Address, Duration>,
+    /// Players that follow the input of another player on the same client, by handle. Their inputs are not sent on the wire.
+    pub(crate) links: HashMap<PlayerHandle, PlayerHandle>,
 }
 
 impl<T> std::fmt::Debug for PlayerRegistry<T>
@@ -37,6 +95,9 @@ where
             .field("handles", &self.handles)
             .field("remotes", &self.remotes.keys())
             .field("spectators", &self.spectators.keys())
+            .field("player_ids", &self.player_ids)
+            .field("spectator_delays", &self.spectator_delays)
+            .field("links", &self.links)
             .finish()
     }
 }
@@ -47,9 +108,23 @@ impl<T: Config> PlayerRegistry<T> {
             handles: HashMap::new(),
             remotes: HashMap::new(),
             spectators: HashMap::new(),
+            player_ids: HashMap::new(),
+            spectator_delays: HashMap::new(),
+            links: HashMap::new(),
         }
     }
 
+    /// Returns the players that follow the input of the given player.
+    pub(crate) fn linked_handles(&self, primary: PlayerHandle) -> Vec<PlayerHandle> {
+        let mut handles: Vec<PlayerHandle> = self
+            .links
+            .iter()
+            .filter_map(|(handle, p)| (*p == primary).then_some(*handle))
+            .collect();
+        handles.sort_unstable();
+        handles
+    }
+
     pub(crate) fn local_player_handles(&self) -> Vec<PlayerHandle> {
         self.handles
             .iter()
@@ -76,7 +151,7 @@ impl<T: Config> PlayerRegistry<T> {
         self.handles
             .iter()
             .filter_map(|(k, v)| match v {
-                PlayerType::Local => Some(*k),
+                PlayerType::Local => None,
                 PlayerType::Remote(_) => None,
                 PlayerType::Spectator(_) => Some(*k),
             })
@@ -98,7 +173,7 @@ impl<T: Config> PlayerRegistry<T> {
     }
 
     pub fn handles_by_address(&self, addr: T::Address) -> Vec<PlayerHandle> {
-        let handles: Vec<PlayerHandle> = self
+        let mut handles: Vec<PlayerHandle> = self
             .handles
             .iter()
             .filter_map(|(h, player_type)| match player_type {
@@ -108,10 +183,76 @@ impl<T: Config> PlayerRegistry<T> {
             })
             .filter_map(|(h, a)| if addr == *a { Some(*h) } else { None })
             .collect();
+        handles.sort_unstable();
         handles
     }
 }
 
+/// The `RollbackStats` count the rollbacks of a [`P2PSession`] since it started, see [`P2PSession::rollback_stats()`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollbackStats {
+    /// The number of rollbacks due to mispredicted inputs or disconnects.
+    pub rollbacks: usize,
+    /// The number of frames resimulated during rollbacks.
+    pub frames_resimulated: usize,
+    /// The number of times remote inputs arrived that matched all predictions, so the predicted frames were confirmed without a rollback.
+    pub rollbacks_avoided: usize,
+    /// The number of rollbacks that were merged into another one, since several players were mispredicted or a disconnect happened
+    /// before the same call to [`P2PSession::advance_frame()`]. The session then rolls back once to the earliest incorrect frame.
+    pub rollbacks_coalesced: usize,
+    /// The number of remote inputs that arrived after their frame had already been simulated with a wrong prediction,
+    /// see [`RollbackCause::LatePacket`].
+    pub late_packets: usize,
+}
+
+/// Why a [`P2PSession`] rolled back, see [`GgrsEvent::Rollback`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RollbackCause {
+    /// The input of a remote player arrived after the session had already simulated its frame with a wrong prediction.
+    /// A few late packets are normal, but if they pile up the packets of the player are delayed or lost on the way.
+    LatePacket {
+        /// The handle of the mispredicted player.
+        handle: PlayerHandle,
+        /// The number of frames simulated since the first frame that was predicted wrong.
+        frames_late: usize,
+    },
+    /// A remote player disconnected after the session had simulated frames assuming it was still connected.
+    Disconnect {
+        /// The handle of the disconnected player.
+        handle: PlayerHandle,
+    },
+    /// The rollback was requested with [`P2PSession::force_rollback_to()`].
+    Forced,
+}
+
+/// Why [`P2PSession::advance_frame()`] returned [`PredictionThreshold`] instead of advancing, see [`GgrsEvent::Stalled`].
+///
+/// [`PredictionThreshold`]: GgrsError::PredictionThreshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StallCause {
+    /// The session predicted as far ahead of the last received input of a player as it may. Either the remote client runs
+    /// slower than this one, for example due to CPU spikes or bad frame pacing, or its packets are delayed or lost.
+    PredictionThreshold {
+        /// The handle of the player whose inputs are the furthest behind.
+        handle: PlayerHandle,
+        /// The number of frames the session is ahead of the last input of the player.
+        frames_ahead: usize,
+    },
+    /// The session waits for the input of a remote player for the current frame, since it is delay-based or waits for the
+    /// first remote inputs, see [`StartupPolicy::WaitForRemoteInputs`].
+    MissingInput {
+        /// The handle of the player whose input is missing.
+        handle: PlayerHandle,
+    },
+    /// A player is being handed over between this and another client, see [`P2PSession::hand_over_player()`],
+    /// [`P2PSession::promote_spectator()`] and [`P2PSession::demote_player()`]. The session waits until both clients agreed on
+    /// the frame the player moves at, or until the last inputs of the previous client for the player have arrived.
+    HandOver {
+        /// The handle of the player being handed over.
+        handle: PlayerHandle,
+    },
+}
+
 /// A [`P2PSession`] provides all functionality to connect to remote clients in a peer-to-peer fashion, exchange inputs and handle the gamestate by saving, loading and advancing.
 pub struct P2PSession<T>
 where
@@ -125,52 +266,196 @@ where
     sync_layer: SyncLayer<T>,
     /// With sparse saving, the session will only request to save the minimum confirmed frame.
     sparse_saving: bool,
-
-    /// If we receive a disconnect from another client, we have to rollback from that frame on in order to prevent wrong predictions
-    disconnect_frame: Frame,
+    /// If set, frames that no rollback can load anymore are not saved.
+    skip_confirmed_saves: bool,
+    /// The user requested a delay-based session, if all remote peers agree.
+    delay_based_requested: bool,
+    /// Once running, the session is delay-based if all remote peers agreed to it. No rollbacks happen and the session stalls on missing inputs.
+    delay_based: bool,
+    /// The input delay for local players given by the user, raised if the session is delay-based.
+    input_delay: usize,
+    /// The frame for which local inputs have already been registered, as they are sent before stalling in delay-based mode
+    /// or while waiting for the first remote inputs.
+    last_registered_frame: Option<Frame>,
+    /// How the first frames are handled, before remote inputs can have arrived.
+    startup_policy: StartupPolicy,
+    /// If set, the session starts this long after all endpoints have synchronized, at a time agreed on with all peers.
+    synchronized_start: Option<Duration>,
+    /// The start time in ms since the epoch we proposed to the peers for a synchronized start.
+    proposed_start_time: Option<u128>,
+
+    /// If we receive a disconnect from another client or a rollback is forced, we have to rollback from that frame on
+    rollback_frame: Option<Frame>,
+    /// Why the session has to roll back to the `rollback_frame`.
+    rollback_causes: Vec<RollbackCause>,
+    /// If set, a rollback resimulates at most this many frames per call to advance_frame().
+    max_resim_frames: Option<usize>,
+    /// If enabled, every rollback and stall is reported with an event.
+    rollback_events: bool,
+    /// If enabled, the requests of every rollback are enclosed by `BeginRollback` and `EndRollback`.
+    rollback_requests: bool,
+    /// The frame an unfinished rollback has to resimulate up to before new frames are advanced.
+    resim_target: Option<Frame>,
+    /// The source of time, to agree on the start time of a synchronized start and to delay the inputs sent to spectators.
+    clock: Arc<dyn Clock>,
+    /// Counts the rollbacks done and avoided so far.
+    rollback_stats: RollbackStats,
+    /// The number of times the session could not advance because it reached the prediction threshold.
+    stalls: usize,
+    /// The number of desyncs detected so far.
+    desyncs: usize,
 
     /// Internal State of the Session.
     state: SessionState,
 
     /// The [`P2PSession`] uses this socket to send and receive all messages for remote players.
-    socket: Box<dyn NonBlockingSocket<T::Address>>,
+    socket: SessionSocket<T>,
+    /// Received packets left for the next poll, since the receive budget was used up.
+    receive_backlog: ReceiveBacklog<T>,
     /// Handles players and their endpoints
     player_reg: PlayerRegistry<T>,
     /// This struct contains information about remote players, like connection status and the frame of last received input.
     local_connect_status: Vec<ConnectionStatus>,
 
-    /// notes which inputs have already been sent to the spectators
+    /// notes which inputs have already been added to the spectator history
     next_spectator_frame: Frame,
+    /// The confirmed inputs that have not been sent to every spectator yet, which is longer for spectators with a broadcast delay.
+    spectator_history: VecDeque<BroadcastInputs<T>>,
+    /// The next frame to send to each spectator.
+    spectator_frames: HashMap<T::Address, Frame>,
+    /// If replay recording is enabled, contains the confirmed inputs of all frames since the last chunk was taken.
+    replay: Option<Replay<T>>,
+    /// The frame the replay starts at.
+    replay_chunk_start: Frame,
+    /// Confirmed inputs are sent to spectators in batches of this many frames.
+    spectator_input_interval: usize,
     /// The soonest frame on which the session can send a [`GgrsEvent::WaitRecommendation`] again.
     next_recommended_sleep: Frame,
     /// How many frames we estimate we are ahead of every remote client
     frames_ahead: i32,
+    /// If enabled, the session periodically sends a [`GgrsEvent::TimeSyncSuggestion`].
+    time_sync_suggestions: bool,
+    /// The soonest frame on which the session can send a [`GgrsEvent::TimeSyncSuggestion`] again.
+    next_time_sync_suggestion: Frame,
+    /// Hands out the frames to advance per render frame, see [`P2PSession::frames_to_advance()`].
+    frame_pacer: FramePacer,
 
     /// Contains all events to be forwarded to the user.
     event_queue: VecDeque<GgrsEvent<T>>,
+    /// The number of events discarded because the event queue was full.
+    events_discarded: usize,
+    /// Out-of-band messages of remote clients, in the order they were received.
+    received_messages: VecDeque<(T::Address, Vec<u8>)>,
     /// Contains all local inputs not yet sent into the system. This should have inputs for every local player before calling advance_frame
     local_inputs: HashMap<PlayerHandle, PlayerInput<T::Input>>,
+    /// The local players input has been added for since the last call to advance_frame, to catch inputs that are added twice.
+    added_local_inputs: HashSet<PlayerHandle>,
+    /// If set, local inputs added for the same frame are merged instead of overwritten.
+    input_coalescing: Option<CoalesceFn<T::Input>>,
+    /// The metadata attached to the local inputs not yet sent into the system.
+    local_metadata: HashMap<PlayerHandle, Vec<u8>>,
+    /// The metadata attached to the inputs of each player for the most recent frames, oldest first.
+    input_metadata: Vec<VecDeque<(Frame, Vec<u8>)>>,
+    /// The number of frames metadata is kept for per player.
+    input_metadata_history_size: usize,
+    /// If enabled, the inputs of disconnected players are left out of the advance frame requests.
+    remove_disconnected_players: bool,
+    /// Players being handed over between this and a remote client.
+    hand_overs: HashMap<PlayerHandle, HandOver<T::Address>>,
+    /// Remote inputs that arrived before the previous input of their player. After a hand-over, the inputs of the new client
+    /// can overtake the last ones of the previous client.
+    early_inputs: Vec<(PlayerHandle, PlayerInput<T::Input>, Vec<u8>)>,
+    /// The number of inputs the input queue of every player holds. Early inputs beyond it are dropped.
+    input_queue_length: usize,
+    /// Cleared input buffers of handled advance frame requests, see [`P2PSession::advance_frame_into()`].
+    input_buffers: Vec<Vec<(T::Input, InputStatus)>>,
+    /// Cleared input maps of inputs all spectators have been sent.
+    broadcast_buffers: Vec<HashMap<PlayerHandle, PlayerInput<T::Input>>>,
 
     /// With desync detection, the session will compare checksums for all peers to detect discrepancies / desyncs between peers
     desync_detection: DesyncDetection,
     /// Desync detection over the network
     local_checksum_history: HashMap<Frame, u128>,
+    /// The number of local checksums kept for comparison.
+    checksum_history_size: usize,
     /// The last frame we sent a checksum for
-    last_sent_checksum_frame: Frame,
+    last_sent_checksum_frame: Option<Frame>,
+}
+
+/// The settings a [`P2PSession`] is created with, built by the [`SessionBuilder`].
+///
+/// [`SessionBuilder`]: crate::SessionBuilder
+pub(crate) struct P2PSettings<T: Config> {
+    pub num_players: usize,
+    pub max_prediction: usize,
+    pub sparse_saving: bool,
+    pub delay_based: bool,
+    pub desync_detection: DesyncDetection,
+    pub input_delay: usize,
+    pub time_sync_suggestions: bool,
+    pub spectator_input_interval: usize,
+    pub save_interval: usize,
+    pub skip_confirmed_saves: bool,
+    pub input_queue_length: usize,
+    pub input_history: usize,
+    pub state_diffing: Option<StateDiffing<T::State>>,
+    pub auto_checksums: Option<AutoChecksums<T::State>>,
+    pub replay_recording: bool,
+    pub startup_policy: StartupPolicy,
+    pub start_frame: Frame,
+    pub fps: usize,
+    pub synchronized_start: Option<Duration>,
+    pub input_coalescing: Option<CoalesceFn<T::Input>>,
+    pub sticky_input_mask: Option<T::Input>,
+    pub checksum_history_size: usize,
+    pub input_metadata_history_size: usize,
+    pub remove_disconnected_players: bool,
+    pub max_resim_frames: Option<usize>,
+    pub rollback_events: bool,
+    pub rollback_requests: bool,
+    pub clock: Arc<dyn Clock>,
 }
 
 impl<T: Config> P2PSession<T> {
     /// Creates a new [`P2PSession`] for players who participate on the game input. After creating the session, add local and remote players,
     /// set input delay for local players and then start the session. The session will use the provided socket.
     pub(crate) fn new(
-        num_players: usize,
-        max_prediction: usize,
-        socket: Box<dyn NonBlockingSocket<T::Address>>,
+        settings: P2PSettings<T>,
+        socket: SessionSocket<T>,
         players: PlayerRegistry<T>,
-        sparse_saving: bool,
-        desync_detection: DesyncDetection,
-        input_delay: usize,
+        receive_backlog: ReceiveBacklog<T>,
     ) -> Self {
+        let P2PSettings {
+            num_players,
+            max_prediction,
+            sparse_saving,
+            delay_based,
+            desync_detection,
+            input_delay,
+            time_sync_suggestions,
+            spectator_input_interval,
+            save_interval,
+            skip_confirmed_saves,
+            input_queue_length,
+            input_history,
+            state_diffing,
+            auto_checksums,
+            replay_recording,
+            startup_policy,
+            start_frame,
+            fps,
+            synchronized_start,
+            input_coalescing,
+            sticky_input_mask,
+            checksum_history_size,
+            input_metadata_history_size,
+            remove_disconnected_players,
+            max_resim_frames,
+            rollback_events,
+            rollback_requests,
+            clock,
+        } = settings;
+
         // local connection status
         let mut local_connect_status = Vec::new();
         for _ in 0..num_players {
@@ -179,6 +464,21 @@ impl<T: Config> P2PSession<T> {
 
         // sync layer & set input delay
         let mut sync_layer = SyncLayer::new(num_players, max_prediction);
+        sync_layer.set_start_frame(start_frame);
+        sync_layer.set_input_queue_length(input_queue_length);
+        sync_layer.set_input_history(input_history);
+        if !sparse_saving {
+            sync_layer.set_save_interval(save_interval);
+        }
+        if let Some(state_diffing) = state_diffing {
+            sync_layer.set_state_diffing(state_diffing);
+        }
+        if let Some(auto_checksums) = auto_checksums {
+            sync_layer.set_auto_checksums(auto_checksums);
+        }
+        if let Some(mask) = sticky_input_mask {
+            sync_layer.set_sticky_input_mask(mask);
+        }
         for (player_handle, player_type) in players.handles.iter() {
             if let PlayerType::Local = player_type {
                 sync_layer.set_frame_delay(*player_handle, input_delay);
@@ -192,68 +492,248 @@ impl<T: Config> P2PSession<T> {
             SessionState::Synchronizing
         };
 
-        Self {
+        let mut session = Self {
             state,
             num_players,
             max_prediction,
             sparse_saving,
+            skip_confirmed_saves,
+            delay_based_requested: delay_based,
+            delay_based: false,
+            input_delay,
+            last_registered_frame: None,
+            startup_policy,
+            synchronized_start,
+            proposed_start_time: None,
             socket,
+            receive_backlog,
             local_connect_status,
             next_recommended_sleep: 0,
-            next_spectator_frame: 0,
+            next_spectator_frame: start_frame,
+            spectator_history: VecDeque::new(),
+            spectator_frames: HashMap::new(),
+            replay: replay_recording.then(|| Replay::new(num_players)),
+            replay_chunk_start: start_frame,
+            spectator_input_interval,
             frames_ahead: 0,
+            time_sync_suggestions,
+            next_time_sync_suggestion: 0,
+            frame_pacer: FramePacer::new(fps),
             sync_layer,
-            disconnect_frame: NULL_FRAME,
+            rollback_frame: None,
+            rollback_causes: Vec::new(),
+            max_resim_frames,
+            rollback_events,
+            rollback_requests,
+            resim_target: None,
+            clock,
+            rollback_stats: RollbackStats::default(),
+            stalls: 0,
+            desyncs: 0,
             player_reg: players,
             event_queue: VecDeque::new(),
+            events_discarded: 0,
+            received_messages: VecDeque::new(),
             local_inputs: HashMap::new(),
+            added_local_inputs: HashSet::new(),
+            input_coalescing,
+            local_metadata: HashMap::new(),
+            input_metadata: vec![VecDeque::new(); num_players],
+            input_metadata_history_size,
+            remove_disconnected_players,
+            hand_overs: HashMap::new(),
+            early_inputs: Vec::new(),
+            input_queue_length,
+            input_buffers: Vec::new(),
+            broadcast_buffers: Vec::new(),
             desync_detection,
             local_checksum_history: HashMap::new(),
-            last_sent_checksum_frame: NULL_FRAME,
+            checksum_history_size,
+            last_sent_checksum_frame: None,
+        };
+
+        // without remote endpoints, there is no one to negotiate with
+        if session.state == SessionState::Running {
+            session.negotiate_delay_based_mode();
         }
+        session
     }
 
-    /// Registers local input for a player for the current frame. This should be successfully called for every local player before calling [`advance_frame()`].
-    /// If this is called multiple times for the same player before advancing the frame, older given inputs will be overwritten.
+    /// Registers local input for a player for the current frame. This should be successfully called exactly once for every local player before calling [`advance_frame()`].
+    /// If [`advance_frame()`] fails, for example because the prediction threshold has been reached, the input can be added again for the next attempt.
+    /// Adding input multiple times for the same player is only allowed if [`SessionBuilder::with_input_coalescing()`] merges them.
     ///
     /// # Errors
-    /// - Returns [`InvalidRequest`] when the given handle does not refer to a local player.
+    /// - Returns [`InvalidPlayerHandle`] when the given handle does not refer to a local player.
+    /// - Returns [`InputAlreadyAdded`] if input has already been added for the player since the last call to [`advance_frame()`] and input coalescing is off.
+    /// - Returns [`InvalidRequest`] if the player follows the input of another player, see [`SessionBuilder::add_linked_player()`].
+    /// - Returns [`NotSynchronized`] if the session has been closed.
     ///
     /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`SessionBuilder::with_input_coalescing()`]: crate::SessionBuilder#method.with_input_coalescing
+    /// [`SessionBuilder::add_linked_player()`]: crate::SessionBuilder#method.add_linked_player
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`InputAlreadyAdded`]: GgrsError::InputAlreadyAdded
     /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`NotSynchronized`]: GgrsError::NotSynchronized
     pub fn add_local_input(
         &mut self,
         player_handle: PlayerHandle,
         input: T::Input,
     ) -> Result<(), GgrsError> {
+        if self.state == SessionState::Closed {
+            return Err(GgrsError::NotSynchronized);
+        }
         // make sure the input is for a registered local player
-        if !self
-            .player_reg
-            .local_player_handles()
-            .contains(&player_handle)
-        {
+        if self.player_reg.handles.get(&player_handle) != Some(&PlayerType::Local) {
+            return Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            });
+        }
+        if let Some(primary) = self.player_reg.links.get(&player_handle) {
             return Err(GgrsError::InvalidRequest {
-                info: "The player handle you provided is not referring to a local player."
-                    .to_owned(),
+                info: format!(
+                    "Player {} follows the input of player {}.",
+                    player_handle, primary
+                ),
+            });
+        }
+        let current_frame = self.sync_layer.current_frame();
+        if !self.added_local_inputs.insert(player_handle) && self.input_coalescing.is_none() {
+            return Err(GgrsError::InputAlreadyAdded {
+                handle: player_handle,
+                frame: current_frame,
+            });
+        }
+        match (
+            self.input_coalescing,
+            self.local_inputs.get_mut(&player_handle),
+        ) {
+            (Some(coalesce), Some(player_input)) => {
+                coalesce(&mut player_input.input, &input);
+                player_input.frame = Some(current_frame);
+            }
+            _ => {
+                let player_input = PlayerInput::<T::Input>::new(current_frame, input);
+                self.local_inputs.insert(player_handle, player_input);
+            }
+        }
+        self.local_metadata.remove(&player_handle);
+        Ok(())
+    }
+
+    /// Registers local input for a player for the current frame like [`add_local_input()`], together with a small opaque blob of up to 32 bytes.
+    /// The metadata is sent to all remote players along with the input, but it is not part of the synchronized simulation:
+    /// it is never handed to you in [`GgrsRequest::AdvanceFrame`] and never causes rollbacks. Use it for data that only flavors
+    /// the presentation, like voice activity flags. Query it with [`input_metadata()`]. Metadata is delivered on a best-effort basis,
+    /// so it might be missing for some frames of remote players.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] when the given handle does not refer to a local player.
+    /// - Returns [`InvalidRequest`] if the metadata is larger than 32 bytes.
+    ///
+    /// [`add_local_input()`]: Self#method.add_local_input
+    /// [`input_metadata()`]: Self#method.input_metadata
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn add_local_input_with_metadata(
+        &mut self,
+        player_handle: PlayerHandle,
+        input: T::Input,
+        metadata: &[u8],
+    ) -> Result<(), GgrsError> {
+        if metadata.len() > MAX_INPUT_METADATA_SIZE {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "Input metadata cannot be larger than {} bytes.",
+                    MAX_INPUT_METADATA_SIZE
+                ),
             });
         }
-        let player_input = PlayerInput::<T::Input>::new(self.sync_layer.current_frame(), input);
-        self.local_inputs.insert(player_handle, player_input);
+        self.add_local_input(player_handle, input)?;
+        if !metadata.is_empty() {
+            self.local_metadata.insert(player_handle, metadata.to_vec());
+        }
         Ok(())
     }
 
+    /// Returns the metadata attached to the input of the given player for the given frame, see [`add_local_input_with_metadata()`].
+    /// Returns `None` if no metadata was attached, it has not arrived (yet) or the frame is too old. Metadata is kept for as many of
+    /// the most recent frames that carried any as set with [`SessionBuilder::with_input_metadata_history_size()`], 128 by default.
+    ///
+    /// [`add_local_input_with_metadata()`]: Self#method.add_local_input_with_metadata
+    /// [`SessionBuilder::with_input_metadata_history_size()`]: crate::SessionBuilder#method.with_input_metadata_history_size
+    pub fn input_metadata(&self, player_handle: PlayerHandle, frame: Frame) -> Option<&[u8]> {
+        self.input_metadata
+            .get(player_handle)?
+            .iter()
+            .find(|(f, _)| *f == frame)
+            .map(|(_, metadata)| metadata.as_slice())
+    }
+
     /// You should call this to notify GGRS that you are ready to advance your gamestate by a single frame.
     /// Returns an order-sensitive [`Vec<GgrsRequest>`]. You should fulfill all requests in the exact order they are provided.
     /// Failure to do so will cause panics later.
     ///
     /// # Errors
-    /// - Returns [`InvalidRequest`] if the provided player handle refers to a remote player.
-    /// - Returns [`NotSynchronized`] if the session is not yet ready to accept input. In this case, you either need to start the session or wait for synchronization between clients.
+    /// - Returns [`MissingInput`] if no input has been added for a local player.
+    /// - Returns [`NotSynchronized`] if the session is not yet ready to accept input or has been closed. In this case, you either need to start the session or wait for synchronization between clients.
+    /// - Returns [`FrameLimitReached`] if the session reached [`MAX_FRAME`].
     ///
     /// [`Vec<GgrsRequest>`]: GgrsRequest
-    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`MissingInput`]: GgrsError::MissingInput
     /// [`NotSynchronized`]: GgrsError::NotSynchronized
+    /// [`FrameLimitReached`]: GgrsError::FrameLimitReached
+    /// [`MAX_FRAME`]: crate::MAX_FRAME
     pub fn advance_frame(&mut self) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        let mut requests = Vec::new();
+        self.advance_frame_into(&mut requests)?;
+        Ok(requests)
+    }
+
+    /// Advances the frame like [`advance_frame()`], but puts the requests into `requests` instead of a new [`Vec`]. The requests
+    /// of the previous call still in `requests` are cleared first, and the inputs of its [`GgrsRequest::AdvanceFrame`] requests are
+    /// reused for the new ones. Passing the same [`Vec`] every frame, the session allocates nothing to advance a frame once it runs
+    /// in a steady state. Encoding and decoding the packets exchanged with remote clients and spectators still allocates.
+    ///
+    /// # Errors
+    /// - Returns the errors of [`advance_frame()`]. `requests` is empty in that case.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    pub fn advance_frame_into(
+        &mut self,
+        requests: &mut Vec<GgrsRequest<T>>,
+    ) -> Result<(), GgrsError> {
+        self.recycle_requests(requests);
+        let result = self.advance_frame_requests(requests);
+        if result.is_err() {
+            self.recycle_requests(requests);
+        }
+        result
+    }
+
+    /// Removes all requests, keeping the inputs of advance frame requests for the next ones.
+    fn recycle_requests(&mut self, requests: &mut Vec<GgrsRequest<T>>) {
+        for request in requests.drain(..) {
+            if let GgrsRequest::AdvanceFrame { mut inputs } = request {
+                // a single call never advances more frames than the input queue holds
+                if self.input_buffers.len() < self.input_queue_length {
+                    inputs.clear();
+                    self.input_buffers.push(inputs);
+                }
+            }
+        }
+    }
+
+    fn advance_frame_requests(
+        &mut self,
+        requests: &mut Vec<GgrsRequest<T>>,
+    ) -> Result<(), GgrsError> {
+        // the requests of the previous call have been handled, so the saved states can be compacted
+        self.sync_layer.compact_saved_states();
+        // local input can be added again for the next call, even if this one fails
+        self.added_local_inputs.clear();
+
         // receive info from remote players, trigger events and send messages
         self.poll_remote_clients();
 
@@ -261,16 +741,90 @@ impl<T: Config> P2PSession<T> {
         if self.state != SessionState::Running {
             return Err(GgrsError::NotSynchronized);
         }
+        if self.sync_layer.frame_limit_reached() {
+            // the inputs of the last frame are only confirmed now, spectators need them to simulate up to the limit as well
+            self.send_confirmed_inputs_to_spectators(self.confirmed_input_frame());
+            return Err(GgrsError::FrameLimitReached);
+        }
 
-        // This list of requests will be returned to the user
-        let mut requests = Vec::new();
+        // an unfinished rollback has to catch up to the frame it started from before new frames are advanced
+        if let Some(target_frame) = self.resim_target {
+            return self.continue_resimulation(target_frame, requests);
+        }
+
+        /*
+         *  DESYNC DETECTION
+         */
+        // collect, send, compare and check the last checksums against the other peers. This happens before any rollback of
+        // this frame, since the states a rollback resimulates still hold the checksums of the mispredicted frames until the
+        // requests have been handled.
+        if self.desync_detection != DesyncDetection::Off {
+            self.check_checksum_send_interval();
+            self.compare_local_checksums_against_peers();
+        }
 
         /*
          * ROLLBACKS AND GAME STATE MANAGEMENT
          */
 
+        let current_frame = self.sync_layer.current_frame();
+        let start_frame = self.sync_layer.start_frame();
+        let in_initial_delay = matches!(
+            self.startup_policy,
+            StartupPolicy::InitialDelay { frames } if current_frame < start_frame + frames as Frame
+        );
+        let wait_for_remote_inputs = self.startup_policy == StartupPolicy::WaitForRemoteInputs
+            && current_frame == start_frame;
+
+        // in delay-based mode, states only need to be saved to compute checksums for desync detection
+        let save_states = !self.delay_based || self.desync_detection != DesyncDetection::Off;
+
+        // during the initial delay, all players have blank inputs, so there is nothing to send or to wait for.
+        // Every state is saved, so the first rollback after the initial delay finds a state to load regardless of the saving mode.
+        if in_initial_delay {
+            self.local_inputs.clear();
+            self.local_metadata.clear();
+            if save_states {
+                requests.push(self.sync_layer.save_current_state());
+            }
+            let mut inputs = self.input_buffers.pop().unwrap_or_default();
+            inputs.resize(
+                self.num_players,
+                (T::Input::zeroed(), InputStatus::Confirmed),
+            );
+            self.sync_layer.advance_frame();
+            requests.push(GgrsRequest::AdvanceFrame { inputs });
+            return Ok(());
+        }
+
+        // players being handed over wait for the other client before their inputs are registered
+        if self.last_registered_frame != Some(current_frame) {
+            if let Some(handle) = self.stalling_hand_over() {
+                self.stall(StallCause::HandOver { handle });
+                return Err(GgrsError::PredictionThreshold);
+            }
+        }
+
+        // in delay-based mode, we never predict. Local inputs are registered and sent right away (only once per frame, even if we stall),
+        // then we stall until we have the inputs of all remote players for the current frame
+        if self.delay_based || wait_for_remote_inputs {
+            if self.last_registered_frame == Some(self.sync_layer.current_frame()) {
+                // coalesced inputs are kept for the next frame instead
+                if self.input_coalescing.is_none() {
+                    self.local_inputs.clear();
+                    self.local_metadata.clear();
+                }
+            } else {
+                self.register_local_inputs()?;
+            }
+            if let Some(handle) = self.first_missing_remote_input() {
+                self.stall(StallCause::MissingInput { handle });
+                return Err(GgrsError::PredictionThreshold);
+            }
+        }
+
         // if we are in the first frame, we have to save the state
-        if self.sync_layer.current_frame() == 0 {
+        if current_frame == start_frame && save_states {
             requests.push(self.sync_layer.save_current_state());
         }
 
@@ -278,23 +832,55 @@ impl<T: Config> P2PSession<T> {
         self.update_player_disconnects();
 
         // find the confirmed frame for which we received all inputs
-        let confirmed_frame = self.confirmed_frame();
+        let confirmed_frame = self.confirmed_input_frame();
+
+        // stop before rolling back if the local inputs cannot be registered afterwards, as the requests of the rollback would be lost
+        if self.last_registered_frame != Some(current_frame)
+            && self
+                .sync_layer
+                .frames_until_prediction_threshold(confirmed_frame.min(Some(current_frame)))
+                == 0
+        {
+            let (handle, last_frame) = self.furthest_behind_player();
+            self.stall(StallCause::PredictionThreshold {
+                handle,
+                frames_ahead: (current_frame - last_frame.unwrap_or(start_frame - 1)) as usize,
+            });
+            return Err(GgrsError::PredictionThreshold);
+        }
 
         // check game consistency and rollback, if necessary.
         // The disconnect frame indicates if a rollback is necessary due to a previously disconnected player
-        let first_incorrect = self
+        if let Some(first_incorrect) = self
             .sync_layer
-            .check_simulation_consistency(self.disconnect_frame);
-        if first_incorrect != NULL_FRAME {
-            self.adjust_gamestate(first_incorrect, confirmed_frame, &mut requests);
-            self.disconnect_frame = NULL_FRAME;
+            .check_simulation_consistency(self.rollback_frame)
+        {
+            // if the first incorrect frame has not been simulated yet, there is nothing to correct.
+            // Delay-based sessions never roll back, as they have no saved states to load from.
+            if first_incorrect < self.sync_layer.current_frame() && !self.delay_based {
+                let causes = self.sync_layer.mispredictions().count()
+                    + usize::from(self.rollback_frame.is_some());
+                self.rollback_stats.rollbacks_coalesced += causes.saturating_sub(1);
+                self.adjust_gamestate(first_incorrect, confirmed_frame, requests)?;
+            }
+            self.rollback_frame = None;
+            self.rollback_causes.clear();
+            // the rollback exceeded the resimulation budget, the remaining frames follow with the next calls
+            if self.resim_target.is_some() {
+                return Ok(());
+            }
+        } else if self.sync_layer.take_predictions_confirmed() {
+            // all predictions were correct, the predicted frames are simply confirmed
+            self.rollback_stats.rollbacks_avoided += 1;
         }
 
-        let last_saved = self.sync_layer.last_saved_frame();
-        if self.sparse_saving {
-            self.check_last_saved_state(last_saved, confirmed_frame, &mut requests);
-        } else {
-            // without sparse saving, always save the current frame after correcting and rollbacking
+        if !save_states {
+            // delay-based sessions never roll back, so there is no need to save
+        } else if self.sparse_saving {
+            let saveable_frame = self.confirmed_frame_with_local_inputs();
+            self.check_last_saved_state(saveable_frame, requests)?;
+        } else if self.sync_layer.is_save_frame() && !self.can_skip_save() {
+            // without sparse saving, always save the current frame after correcting and rollbacking (if it is on the save interval)
             requests.push(self.sync_layer.save_current_state());
         }
 
@@ -302,89 +888,103 @@ impl<T: Config> P2PSession<T> {
          *  SEND OFF AND THROW AWAY INPUTS BEFORE THE CONFIRMED FRAME
          */
 
-        // send confirmed inputs to spectators before throwing them away
+        // send confirmed inputs to spectators and record them before throwing them away
         self.send_confirmed_inputs_to_spectators(confirmed_frame);
+        self.record_confirmed_inputs(confirmed_frame);
 
         // set the last confirmed frame and discard all saved inputs before that frame
         self.sync_layer
             .set_last_confirmed_frame(confirmed_frame, self.sparse_saving);
 
-        /*
-         *  DESYNC DETECTION
-         */
-        // collect, send, compare and check the last checksums against the other peers
-        if self.desync_detection != DesyncDetection::Off {
-            self.check_checksum_send_interval();
-            self.compare_local_checksums_against_peers();
-        }
-
         /*
          *  WAIT RECOMMENDATION
          */
 
         // check time sync between clients and send wait recommendation, if appropriate
         self.check_wait_recommendation();
+        if self.time_sync_suggestions {
+            self.check_time_sync_suggestion();
+        }
 
         /*
          *  INPUTS
          */
 
-        // register local inputs in the system and send them
-        for handle in self.player_reg.local_player_handles() {
-            match self.local_inputs.get_mut(&handle) {
-                Some(player_input) => {
-                    // send the input into the sync layer
-                    let actual_frame = self.sync_layer.add_local_input(handle, *player_input)?;
-                    assert!(actual_frame != NULL_FRAME);
-                    // if not dropped, send the input to all other clients, but with the correct frame (influenced by input delay)
-                    player_input.frame = actual_frame;
-                    self.local_connect_status[handle].last_frame = actual_frame;
-                }
-                None => {
-                    return Err(GgrsError::InvalidRequest {
-                        info: "Missing local input while calling advance_frame().".to_owned(),
-                    });
-                }
-            }
-        }
-
-        // send the inputs to all clients
-        for endpoint in self.player_reg.remotes.values_mut() {
-            // send the input directly
-            endpoint.send_input(&self.local_inputs, &self.local_connect_status);
-            endpoint.send_all_messages(&mut self.socket);
+        // register local inputs in the system and send them (unless we did this already before stalling)
+        if self.last_registered_frame != Some(current_frame) {
+            self.register_local_inputs()?;
         }
 
-        // clear the local inputs after sending them
-        self.local_inputs.clear();
-
         /*
          * ADVANCE THE STATE
          */
 
         // get correct inputs for the current frame
-        let inputs = self
-            .sync_layer
-            .synchronized_inputs(&self.local_connect_status);
+        let inputs = self.synchronized_inputs();
         // advance the frame count
         self.sync_layer.advance_frame();
         requests.push(GgrsRequest::AdvanceFrame { inputs });
 
-        Ok(requests)
+        Ok(())
+    }
+
+    /// Asks `poll_input` for the input of every local player, then advances the frame like [`advance_frame()`]. This is meant for
+    /// engines that pull inputs instead of pushing them: calling this once per tick instead of [`add_local_input()`] and [`advance_frame()`]
+    /// makes sure input is added exactly once per local player and always for the frame that is advanced.
+    /// The polled inputs are handled like inputs given to [`add_local_input()`], so they are merged if [`SessionBuilder::with_input_coalescing()`] is enabled.
+    ///
+    /// # Errors
+    /// - Returns the errors of [`advance_frame()`].
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`add_local_input()`]: Self#method.add_local_input
+    /// [`SessionBuilder::with_input_coalescing()`]: crate::SessionBuilder#method.with_input_coalescing
+    pub fn advance_frame_with_input(
+        &mut self,
+        mut poll_input: impl FnMut(PlayerHandle) -> T::Input,
+    ) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        for handle in self.player_reg.local_player_handles() {
+            self.add_local_input(handle, poll_input(handle))?;
+        }
+        self.advance_frame()
     }
 
     /// Should be called periodically by your application to give GGRS a chance to do internal work.
     /// GGRS will receive packets, distribute them to corresponding endpoints, handle all occurring events and send all outgoing packets.
-    pub fn poll_remote_clients(&mut self) {
-        // Get all packets and distribute them to associated endpoints.
+    /// Returns a [`PollSummary`] of the received packets and inputs, the queued events and the scheduled rollback.
+    pub fn poll_remote_clients(&mut self) -> PollSummary {
+        let mut summary = PollSummary::default();
+        if self.state == SessionState::Closed {
+            return summary;
+        }
+        // events discarded from the full queue have been queued all the same
+        let queued_events = self.event_queue.len() + self.events_discarded;
+
+        // Get all packets and distribute them to associated endpoints, as far as the receive budget allows.
         // The endpoints will handle their packets, which will trigger both events and UPD replies.
-        for (from_addr, msg) in &self.socket.receive_all_messages() {
-            if let Some(endpoint) = self.player_reg.remotes.get_mut(from_addr) {
-                endpoint.handle_message(msg);
-            }
-            if let Some(endpoint) = self.player_reg.spectators.get_mut(from_addr) {
-                endpoint.handle_message(msg);
+        let player_reg = &mut self.player_reg;
+        let received = self.receive_backlog.receive(
+            &mut self.socket,
+            self.clock.millis_since_epoch(),
+            |addr| {
+                player_reg
+                    .remotes
+                    .values_mut()
+                    .chain(player_reg.spectators.values_mut())
+                    .any(|endpoint| endpoint.accept_packet(addr))
+            },
+        );
+        for (from_addr, msg, received_at) in received {
+            let endpoints = player_reg
+                .remotes
+                .values_mut()
+                .chain(player_reg.spectators.values_mut());
+            for endpoint in endpoints {
+                if endpoint.is_handling_message(&from_addr) {
+                    endpoint.handle_message(&from_addr, &msg, received_at);
+                }
             }
+            summary.packets_received += 1;
         }
 
         // update frame information between remote players
@@ -397,25 +997,32 @@ impl<T: Config> P2PSession<T> {
         // run endpoint poll and get events from players and spectators. This will trigger additional packets to be sent.
         let mut events = VecDeque::new();
         for endpoint in self.player_reg.remotes.values_mut() {
-            let handles = endpoint.handles().clone();
             let addr = endpoint.peer_addr();
             for event in endpoint.poll(&self.local_connect_status) {
-                events.push_back((event, handles.clone(), addr.clone()))
+                events.push_back((event, addr.clone()))
             }
         }
         for endpoint in self.player_reg.spectators.values_mut() {
-            let handles = endpoint.handles().clone();
             let addr = endpoint.peer_addr();
             for event in endpoint.poll(&self.local_connect_status) {
-                events.push_back((event, handles.clone(), addr.clone()))
+                events.push_back((event, addr.clone()))
             }
         }
 
         // handle all events locally
-        for (event, handles, addr) in events.drain(..) {
-            self.handle_event(event, handles, addr);
+        for (event, addr) in events.drain(..) {
+            if let Event::Input { player, .. } = event {
+                for handle in std::iter::once(player).chain(self.player_reg.linked_handles(player))
+                {
+                    *summary.inputs_received.entry(handle).or_default() += 1;
+                }
+            }
+            self.handle_event(event, addr);
         }
 
+        // with a synchronized start or a fair input delay, the session starts once all peers agreed
+        self.check_initial_sync();
+
         // send all queued packets
         for endpoint in self.player_reg.remotes.values_mut() {
             endpoint.send_all_messages(&mut self.socket);
@@ -423,21 +1030,40 @@ impl<T: Config> P2PSession<T> {
         for endpoint in self.player_reg.spectators.values_mut() {
             endpoint.send_all_messages(&mut self.socket);
         }
+
+        // report the errors of the socket, including those of the sends since the last poll
+        for kind in self.socket.take_errors() {
+            self.event_queue.push_back(GgrsEvent::SocketError { kind });
+        }
+        self.trim_event_queue();
+
+        summary.events_queued = self.event_queue.len() + self.events_discarded - queued_events;
+        summary.rollback_frame = self.first_incorrect_frame();
+        summary
+    }
+
+    /// Replaces the socket of the session, e.g. after the operating system closed it while the device slept and the session
+    /// reported [`GgrsEvent::SocketError`]. The connections to all remotes and spectators are kept: the messages they
+    /// missed are sent again through the new socket. The remotes only accept messages from the address they know, so bind
+    /// the new socket to the same port, or to an address the remotes know from [`with_remote_candidates()`].
+    ///
+    /// [`with_remote_candidates()`]: crate::SessionBuilder#method.with_remote_candidates
+    pub fn replace_socket(&mut self, socket: impl NonBlockingSocket<T::Address> + 'static) {
+        self.socket.replace(Box::new(socket));
     }
 
     /// Disconnects a remote player and all other remote players with the same address from the session.
     /// # Errors
-    /// - Returns [`InvalidRequest`] if you try to disconnect a local player or the provided handle is invalid.
+    /// - Returns [`InvalidPlayerHandle`] if you try to disconnect a local player or the provided handle is invalid.
+    /// - Returns [`PlayerDisconnected`] if the player has already been disconnected.
     ///
-    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`PlayerDisconnected`]: GgrsError::PlayerDisconnected
     pub fn disconnect_player(&mut self, player_handle: PlayerHandle) -> Result<(), GgrsError> {
         match self.player_reg.handles.get(&player_handle) {
             // the local player cannot be disconnected
-            None => Err(GgrsError::InvalidRequest {
-                info: "Invalid Player Handle.".to_owned(),
-            }),
-            Some(PlayerType::Local) => Err(GgrsError::InvalidRequest {
-                info: "Local Player cannot be disconnected.".to_owned(),
+            None | Some(PlayerType::Local) => Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
             }),
             // a remote player can only be disconnected if not already disconnected, since there is some additional logic attached
             Some(PlayerType::Remote(_)) => {
@@ -446,67 +1072,864 @@ impl<T: Config> P2PSession<T> {
                     self.disconnect_player_at_frame(player_handle, last_frame);
                     return Ok(());
                 }
-                Err(GgrsError::InvalidRequest {
-                    info: "Player already disconnected.".to_owned(),
+                Err(GgrsError::PlayerDisconnected {
+                    handle: player_handle,
                 })
             }
             // disconnecting spectators is simpler
             Some(PlayerType::Spectator(_)) => {
-                self.disconnect_player_at_frame(player_handle, NULL_FRAME);
+                self.disconnect_player_at_frame(player_handle, None);
                 Ok(())
             }
         }
     }
 
-    /// Returns a [`NetworkStats`] struct that gives information about the quality of the network connection.
+    /// Starts the synchronization with a remote player or spectator over after it failed with [`GgrsEvent::SynchronizationFailed`].
+    /// The synchronization restarts for all players behind the same address.
     /// # Errors
-    /// - Returns [`InvalidRequest`] if the handle not referring to a remote player or spectator.
-    /// - Returns [`NotSynchronized`] if the session is not connected to other clients yet.
+    /// - Returns [`InvalidPlayerHandle`] if the handle does not refer to a remote player or spectator.
+    /// - Returns [`InvalidRequest`] if the synchronization with the player has not failed.
     ///
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
     /// [`InvalidRequest`]: GgrsError::InvalidRequest
-    /// [`NotSynchronized`]: GgrsError::NotSynchronized
-    pub fn network_stats(&self, player_handle: PlayerHandle) -> Result<NetworkStats, GgrsError> {
-        match self.player_reg.handles.get(&player_handle) {
-            Some(PlayerType::Remote(addr)) => self
-                .player_reg
-                .remotes
-                .get(addr)
-                .expect("Endpoint should exist for any registered player")
-                .network_stats(),
-            Some(PlayerType::Spectator(addr)) => self
-                .player_reg
-                .remotes
-                .get(addr)
-                .expect("Endpoint should exist for any registered player")
-                .network_stats(),
-            _ => Err(GgrsError::InvalidRequest {
-                info: "Given player handle not referring to a remote player or spectator"
-                    .to_owned(),
-            }),
+    pub fn retry_synchronization(&mut self, player_handle: PlayerHandle) -> Result<(), GgrsError> {
+        let endpoint = match self.player_reg.handles.get(&player_handle) {
+            Some(PlayerType::Remote(addr)) => self.player_reg.remotes.get_mut(addr),
+            Some(PlayerType::Spectator(addr)) => self.player_reg.spectators.get_mut(addr),
+            None | Some(PlayerType::Local) => None,
+        };
+        let Some(endpoint) = endpoint else {
+            return Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            });
+        };
+        if !endpoint.retry_synchronization() {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "The synchronization with player {} has not failed.",
+                    player_handle
+                ),
+            });
         }
+        Ok(())
     }
 
-    /// Returns the highest confirmed frame. We have received all input for this frame and it is thus correct.
-    pub fn confirmed_frame(&self) -> Frame {
-        let mut confirmed_frame = i32::MAX;
-
-        for con_stat in &self.local_connect_status {
-            if !con_stat.disconnected {
-                confirmed_frame = std::cmp::min(confirmed_frame, con_stat.last_frame);
+    /// Exchanges all further messages with a remote player or spectator over a new address, for example when its client rejoins
+    /// from another network or port, as told by your matchmaking. The remote client has to keep running the same session: its
+    /// endpoint, inputs and [`PlayerId`] carry over, so the player keeps their handle and character slot. A client that restarted
+    /// has lost its session and cannot rejoin this way. Packets from the new address are ignored until this is called. The
+    /// change applies to all players behind the same address, and [`active_address()`] reports the new address.
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] if the handle does not refer to a remote player or spectator.
+    /// - Returns [`PlayerDisconnected`] if the player has been disconnected.
+    /// - Returns [`InvalidRequest`] if another remote client uses the address.
+    ///
+    /// [`active_address()`]: Self#method.active_address
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`PlayerDisconnected`]: GgrsError::PlayerDisconnected
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn remap_player_address(
+        &mut self,
+        player_handle: PlayerHandle,
+        addr: T::Address,
+    ) -> Result<(), GgrsError> {
+        let (registered_addr, disconnected) = match self.player_reg.handles.get(&player_handle) {
+            Some(PlayerType::Remote(addr)) => (
+                addr.clone(),
+                self.local_connect_status[player_handle].disconnected,
+            ),
+            Some(PlayerType::Spectator(addr)) => (
+                addr.clone(),
+                self.player_reg.spectators[addr].connection_state()
+                    == ConnectionState::Disconnected,
+            ),
+            None | Some(PlayerType::Local) => {
+                return Err(GgrsError::InvalidPlayerHandle {
+                    handle: player_handle,
+                })
             }
+        };
+        if disconnected {
+            return Err(GgrsError::PlayerDisconnected {
+                handle: player_handle,
+            });
+        }
+        let taken = self
+            .player_reg
+            .remotes
+            .iter()
+            .chain(self.player_reg.spectators.iter())
+            .any(|(other_addr, endpoint)| {
+                *other_addr != registered_addr && endpoint.is_handling_message(&addr)
+            });
+        if taken {
+            return Err(GgrsError::InvalidRequest {
+                info: "Another remote client uses this address.".to_owned(),
+            });
         }
 
-        assert!(confirmed_frame < i32::MAX);
-        confirmed_frame
+        let endpoint = match self.player_reg.remotes.get_mut(&registered_addr) {
+            Some(endpoint) => endpoint,
+            None => self
+                .player_reg
+                .spectators
+                .get_mut(&registered_addr)
+                .expect("Endpoint should exist for any registered player"),
+        };
+        endpoint.remap_addr(addr);
+        Ok(())
     }
 
-    /// Returns the current frame of a session.
-    pub fn current_frame(&self) -> Frame {
-        self.sync_layer.current_frame()
-    }
+    /// Hands a local player over to the remote client that controls the player `to`, for example when a player of a couch
+    /// co-op match moves to their own device. The remote client answers with the frame the player moves at, at least a
+    /// round trip ahead, so both clients switch at the same frame without a rollback. Keep adding local input for the player
+    /// until this session sends a [`GgrsEvent::PlayerHandedOver`] for it. From then on, it is a remote player here and a
+    /// local player on the other client. All other remote clients follow along. While the clients agree on the frame, the
+    /// session might stall briefly with [`StallCause::HandOver`].
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] if `player_handle` does not refer to a local player or `to` does not refer to a remote player.
+    /// - Returns [`NotSynchronized`] if the session is not running.
+    /// - Returns [`PlayerDisconnected`] if the player `to` has been disconnected.
+    /// - Returns [`InvalidRequest`] if the player is already being handed over, it is the last local player, it is linked to
+    ///   another player or too many messages to the remote client are still waiting for an acknowledgement.
+    ///
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`NotSynchronized`]: GgrsError::NotSynchronized
+    /// [`PlayerDisconnected`]: GgrsError::PlayerDisconnected
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn hand_over_player(
+        &mut self,
+        player_handle: PlayerHandle,
+        to: PlayerHandle,
+    ) -> Result<(), GgrsError> {
+        self.check_local_hand_over(player_handle)?;
+        let Some(PlayerType::Remote(addr)) = self.player_reg.handles.get(&to) else {
+            return Err(GgrsError::InvalidPlayerHandle { handle: to });
+        };
+        let addr = addr.clone();
+        if self.state != SessionState::Running {
+            return Err(GgrsError::NotSynchronized);
+        }
+        if self.local_connect_status[to].disconnected {
+            return Err(GgrsError::PlayerDisconnected { handle: to });
+        }
+        if self
+            .hand_overs
+            .values()
+            .any(|hand_over| matches!(hand_over, HandOver::Reclaiming { addr: from, .. } if *from == addr))
+        {
+            return Err(GgrsError::InvalidRequest {
+                info: "A player of the remote client is being demoted.".to_owned(),
+            });
+        }
 
-    /// Returns the maximum prediction window of a session.
-    pub fn max_prediction(&self) -> usize {
+        let next_frame = self.next_input_frame();
+        let endpoint = self
+            .player_reg
+            .remotes
+            .get_mut(&addr)
+            .expect("Endpoint should exist for any registered player");
+        // the answer of the remote client can arrive before we added inputs up to that frame
+        let frame = next_frame + endpoint.round_trip_frames() as Frame + 1;
+        if !endpoint.request_hand_over(player_handle, frame) {
+            return Err(GgrsError::InvalidRequest {
+                info: "Too many messages are waiting to be acknowledged by the remote client."
+                    .to_owned(),
+            });
+        }
+        self.hand_overs
+            .insert(player_handle, HandOver::Requested { addr, frame });
+        Ok(())
+    }
+
+    /// Promotes a spectator to take over the local player `player_handle`, for example when the next challenger of a "winner
+    /// stays" queue steps in. The player is handed over like with [`hand_over_player()`]: keep adding local input for it until
+    /// this session sends a [`GgrsEvent::PlayerHandedOver`] for it. Up to that frame, the spectator gets the confirmed inputs of
+    /// all players right away, regardless of its broadcast delay, and from then on the inputs of our local players.
+    ///
+    /// The spectator has to run a [`P2PSession`] in which all players are remote players behind this session, since a
+    /// [`SpectatorSession`] cannot take over a player. As it only exchanges inputs with this session, no other remote client may
+    /// be connected. Disconnect the spectator to call the promotion off.
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] if `spectator_handle` does not refer to a spectator or `player_handle` does not refer
+    ///   to a local player.
+    /// - Returns [`NotSynchronized`] if the session is not running.
+    /// - Returns [`PlayerDisconnected`] if the spectator has been disconnected.
+    /// - Returns [`InvalidRequest`] if the player is already being handed over, it is the last local player, the session has
+    ///   linked players, another remote client is connected or too many messages to the spectator are still waiting for an
+    ///   acknowledgement.
+    ///
+    /// [`hand_over_player()`]: Self::hand_over_player
+    /// [`SpectatorSession`]: crate::SpectatorSession
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`NotSynchronized`]: GgrsError::NotSynchronized
+    /// [`PlayerDisconnected`]: GgrsError::PlayerDisconnected
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn promote_spectator(
+        &mut self,
+        spectator_handle: PlayerHandle,
+        player_handle: PlayerHandle,
+    ) -> Result<(), GgrsError> {
+        self.check_local_hand_over(player_handle)?;
+        let Some(PlayerType::Spectator(addr)) = self.player_reg.handles.get(&spectator_handle)
+        else {
+            return Err(GgrsError::InvalidPlayerHandle {
+                handle: spectator_handle,
+            });
+        };
+        let addr = addr.clone();
+        if self.state != SessionState::Running {
+            return Err(GgrsError::NotSynchronized);
+        }
+        if self
+            .hand_overs
+            .values()
+            .any(|hand_over| *hand_over.addr() == addr)
+        {
+            return Err(GgrsError::InvalidRequest {
+                info: "A player is already being handed over to the spectator.".to_owned(),
+            });
+        }
+        self.check_spectator_switch(&addr)?;
+
+        let next_frame = self.next_input_frame();
+        let endpoint = self
+            .player_reg
+            .spectators
+            .get_mut(&addr)
+            .expect("Endpoint should exist for any registered spectator");
+        if !endpoint.is_running() {
+            return Err(GgrsError::PlayerDisconnected {
+                handle: spectator_handle,
+            });
+        }
+        // the answer of the spectator can arrive before we added inputs up to that frame
+        let frame = next_frame + endpoint.round_trip_frames() as Frame + 1;
+        if !endpoint.request_hand_over(player_handle, frame) {
+            return Err(GgrsError::InvalidRequest {
+                info: "Too many messages are waiting to be acknowledged by the spectator."
+                    .to_owned(),
+            });
+        }
+        self.hand_overs
+            .insert(player_handle, HandOver::Requested { addr, frame });
+        Ok(())
+    }
+
+    /// Demotes the remote player `player_handle`, for example when the loser of a "winner stays" match makes room for the next
+    /// challenger, who is then promoted with [`promote_spectator()`]. This session takes the player over at a frame both clients
+    /// agree on, so add local input for it once this session sends a [`GgrsEvent::PlayerHandedOver`] for it, e.g. blank inputs
+    /// until the next challenger steps in.
+    ///
+    /// If it was the last player of the remote client, the client stays connected as a spectator: it gets the confirmed inputs
+    /// of all players from then on and is listed as a spectator in [`players()`]. As it only exchanges inputs with this session
+    /// then, no other remote client may be connected.
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] if `player_handle` does not refer to a remote player.
+    /// - Returns [`NotSynchronized`] if the session is not running.
+    /// - Returns [`PlayerDisconnected`] if the player has been disconnected.
+    /// - Returns [`InvalidRequest`] if a player of the remote client is already being handed over, the player is linked to
+    ///   another player, it is the last player of a remote client that cannot become a spectator or too many messages to the
+    ///   remote client are still waiting for an acknowledgement.
+    ///
+    /// [`promote_spectator()`]: Self::promote_spectator
+    /// [`players()`]: Self::players
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`NotSynchronized`]: GgrsError::NotSynchronized
+    /// [`PlayerDisconnected`]: GgrsError::PlayerDisconnected
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn demote_player(&mut self, player_handle: PlayerHandle) -> Result<(), GgrsError> {
+        let Some(PlayerType::Remote(addr)) = self.player_reg.handles.get(&player_handle) else {
+            return Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            });
+        };
+        let addr = addr.clone();
+        if self.player_reg.links.contains_key(&player_handle)
+            || !self.player_reg.linked_handles(player_handle).is_empty()
+        {
+            return Err(GgrsError::InvalidRequest {
+                info: "Linked players cannot be handed over.".to_owned(),
+            });
+        }
+        if self.state != SessionState::Running {
+            return Err(GgrsError::NotSynchronized);
+        }
+        if self.local_connect_status[player_handle].disconnected {
+            return Err(GgrsError::PlayerDisconnected {
+                handle: player_handle,
+            });
+        }
+        if self
+            .hand_overs
+            .values()
+            .any(|hand_over| *hand_over.addr() == addr)
+        {
+            return Err(GgrsError::InvalidRequest {
+                info: "A player of the remote client is already being handed over.".to_owned(),
+            });
+        }
+        // a remote client left without players becomes a spectator
+        if self.player_reg.handles_by_address(addr.clone()) == [player_handle] {
+            self.check_spectator_switch(&addr)?;
+        }
+
+        let next_frame = self.next_input_frame();
+        let endpoint = self
+            .player_reg
+            .remotes
+            .get_mut(&addr)
+            .expect("Endpoint should exist for any registered player");
+        // the answer of the remote client can arrive before we added inputs up to that frame
+        let frame = next_frame + endpoint.round_trip_frames() as Frame + 1;
+        if !endpoint.request_hand_over(player_handle, frame) {
+            return Err(GgrsError::InvalidRequest {
+                info: "Too many messages are waiting to be acknowledged by the remote client."
+                    .to_owned(),
+            });
+        }
+        self.hand_overs
+            .insert(player_handle, HandOver::Reclaiming { addr, frame });
+        Ok(())
+    }
+
+    /// Closes the session. It asks the remote clients and spectators to disconnect it, stops exchanging packets with them and cannot
+    /// advance any further. Sends a [`GgrsEvent::SessionStateChanged`] unless the session was already closed.
+    pub fn close(&mut self) {
+        if self.state == SessionState::Closed {
+            return;
+        }
+        for endpoint in self
+            .player_reg
+            .remotes
+            .values_mut()
+            .chain(self.player_reg.spectators.values_mut())
+        {
+            endpoint.close();
+            endpoint.send_all_messages(&mut self.socket);
+        }
+        self.state = SessionState::Closed;
+        self.event_queue.push_back(GgrsEvent::SessionStateChanged {
+            state: SessionState::Closed,
+        });
+    }
+
+    /// Sends an out-of-band message to a remote player, like a chat line or a lobby command. Messages are not part of the
+    /// simulation. They are resent until the remote client acknowledges them, and the remote session hands them out once and
+    /// in the order they were sent via [`received_messages()`]. Delivery is best-effort: a message that is not acknowledged
+    /// for about five seconds is given up on and counted in [`NetworkStats::messages_dropped`]. All remote players with the
+    /// same address share one connection, so the message is only delivered once to them.
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] if the handle does not refer to a remote player.
+    /// - Returns [`InvalidRequest`] if the message is larger than [`MAX_MESSAGE_SIZE`] or too many messages to the player are
+    ///   still waiting for an acknowledgement.
+    ///
+    /// [`received_messages()`]: Self#method.received_messages
+    /// [`NetworkStats::messages_dropped`]: NetworkStats#structfield.messages_dropped
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn send_message(
+        &mut self,
+        player_handle: PlayerHandle,
+        data: Vec<u8>,
+    ) -> Result<(), GgrsError> {
+        let Some(PlayerType::Remote(addr)) = self.player_reg.handles.get(&player_handle) else {
+            return Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            });
+        };
+        if data.len() > MAX_MESSAGE_SIZE {
+            return Err(GgrsError::InvalidRequest {
+                info: format!("Messages can be at most {} bytes long.", MAX_MESSAGE_SIZE),
+            });
+        }
+        let endpoint = self
+            .player_reg
+            .remotes
+            .get_mut(addr)
+            .expect("Endpoint should exist for any registered player");
+        if !endpoint.send_message(data) {
+            return Err(GgrsError::InvalidRequest {
+                info: "Too many messages are waiting to be acknowledged by the remote client."
+                    .to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns all out-of-band messages remote clients sent with [`send_message()`] since last queried, together with the
+    /// address they were received from. If the number of stored messages exceeds `MAX_EVENT_QUEUE_SIZE`, the oldest
+    /// messages will be discarded.
+    ///
+    /// [`send_message()`]: Self#method.send_message
+    pub fn received_messages(&mut self) -> Drain<'_, (T::Address, Vec<u8>)> {
+        self.received_messages.drain(..)
+    }
+
+    /// Returns a [`NetworkStats`] struct that gives information about the quality of the network connection.
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] if the handle not referring to a remote player or spectator.
+    /// - Returns [`NotSynchronized`] if the session is not connected to other clients yet.
+    ///
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`NotSynchronized`]: GgrsError::NotSynchronized
+    pub fn network_stats(&self, player_handle: PlayerHandle) -> Result<NetworkStats, GgrsError> {
+        let endpoint = match self.player_reg.handles.get(&player_handle) {
+            Some(PlayerType::Remote(addr)) => self.player_reg.remotes.get(addr),
+            Some(PlayerType::Spectator(addr)) => self.player_reg.spectators.get(addr),
+            _ => {
+                return Err(GgrsError::InvalidPlayerHandle {
+                    handle: player_handle,
+                })
+            }
+        };
+        let endpoint = endpoint.expect("Endpoint should exist for any registered player");
+        let mut stats = endpoint.network_stats()?;
+        stats.receive_backlog = self
+            .receive_backlog
+            .count(|addr| endpoint.is_handling_message(addr));
+        Ok(stats)
+    }
+
+    /// Returns the address the session exchanges messages with the given remote player or spectator over. This differs from the
+    /// address the player was added with if the remote client answered on one of its candidates first, see
+    /// [`SessionBuilder::with_remote_candidates()`]. `None` for local players, or if the remote client has not answered yet.
+    ///
+    /// [`SessionBuilder::with_remote_candidates()`]: crate::SessionBuilder#method.with_remote_candidates
+    pub fn active_address(&self, player_handle: PlayerHandle) -> Option<T::Address> {
+        let endpoint = match self.player_reg.handles.get(&player_handle)? {
+            PlayerType::Local => return None,
+            PlayerType::Remote(addr) => &self.player_reg.remotes[addr],
+            PlayerType::Spectator(addr) => &self.player_reg.spectators[addr],
+        };
+        endpoint.active_addr().cloned()
+    }
+
+    /// Returns the address the given remote player or spectator presented as reachable at from outside, as set with
+    /// [`SessionBuilder::with_external_addr()`] on its side. It is known once its endpoint has synchronized.
+    ///
+    /// [`SessionBuilder::with_external_addr()`]: crate::SessionBuilder#method.with_external_addr
+    pub fn remote_external_addr(&self, player_handle: PlayerHandle) -> Option<SocketAddr> {
+        match self.player_reg.handles.get(&player_handle)? {
+            PlayerType::Local => None,
+            PlayerType::Remote(addr) => self.player_reg.remotes[addr].remote_external_addr(),
+            PlayerType::Spectator(addr) => self.player_reg.spectators[addr].remote_external_addr(),
+        }
+    }
+
+    /// Returns the local address the socket of the session is bound to, if the socket knows it. See
+    /// [`NonBlockingSocket::local_addr()`].
+    pub fn local_addr(&self) -> Option<T::Address> {
+        self.socket.local_addr()
+    }
+
+    /// Returns the persistent [`PlayerId`] of the given player, if one was attached with [`SessionBuilder::with_player_id()`].
+    /// The ids of remote players are known once their endpoint has synchronized.
+    ///
+    /// [`SessionBuilder::with_player_id()`]: crate::SessionBuilder#method.with_player_id
+    pub fn player_id(&self, player_handle: PlayerHandle) -> Option<PlayerId> {
+        match self.player_reg.handles.get(&player_handle)? {
+            PlayerType::Local => self.player_reg.player_ids.get(&player_handle).copied(),
+            PlayerType::Remote(addr) => self.player_reg.remotes[addr]
+                .remote_player_ids()
+                .iter()
+                .find(|(handle, _)| *handle == player_handle)
+                .map(|(_, id)| *id),
+            PlayerType::Spectator(_) => None,
+        }
+    }
+
+    /// Returns the handle of the player with the given persistent [`PlayerId`], if that player is part of this session.
+    pub fn handle_by_player_id(&self, player_id: PlayerId) -> Option<PlayerHandle> {
+        (0..self.num_players).find(|handle| self.player_id(*handle) == Some(player_id))
+    }
+
+    /// Returns the [`ConnectionState`] of the given player or spectator, so you can show the connection of every player without
+    /// inferring it from events or errors. Local players are always [`ConnectionState::Running`].
+    ///
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] if the handle does not refer to a player or spectator.
+    ///
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    pub fn connection_status(
+        &self,
+        player_handle: PlayerHandle,
+    ) -> Result<ConnectionState, GgrsError> {
+        let endpoint = match self.player_reg.handles.get(&player_handle) {
+            Some(PlayerType::Local) => return Ok(ConnectionState::Running),
+            Some(PlayerType::Remote(addr)) => self.player_reg.remotes.get(addr),
+            Some(PlayerType::Spectator(addr)) => self.player_reg.spectators.get(addr),
+            None => {
+                return Err(GgrsError::InvalidPlayerHandle {
+                    handle: player_handle,
+                })
+            }
+        };
+        let endpoint = endpoint.expect("Endpoint should exist for any registered player");
+        if player_handle < self.num_players && self.local_connect_status[player_handle].disconnected
+        {
+            return Ok(ConnectionState::Disconnected);
+        }
+        Ok(endpoint.connection_state())
+    }
+
+    /// Returns the recent history of network samples for the given remote player, oldest first. A sample is recorded every frame while the
+    /// endpoint is running, so spikes that a periodic [`network_stats()`] query would miss show up here.
+    /// Samples are shared between all players behind the same address.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] if the handle does not refer to a remote player.
+    ///
+    /// [`network_stats()`]: Self#method.network_stats
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    pub fn network_history(
+        &self,
+        player_handle: PlayerHandle,
+    ) -> Result<&[NetworkSample], GgrsError> {
+        match self.player_reg.handles.get(&player_handle) {
+            Some(PlayerType::Remote(addr)) => Ok(self
+                .player_reg
+                .remotes
+                .get(addr)
+                .expect("Endpoint should exist for any registered player")
+                .network_history()),
+            _ => Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            }),
+        }
+    }
+
+    /// Rolls back to the given frame with the next call to [`advance_frame()`] and resimulates all frames since, just like after a
+    /// misprediction. Use it to rewind the game for reasons GGRS does not know about, for example a scripted rewind mechanic. As the
+    /// inputs are not changed, the resimulation only leads to a different state if your game state depends on more than the inputs.
+    /// Without sparse saving, the session loads the latest saved state up to the given frame. With sparse saving, it loads the
+    /// last saved state, so that has to be at or before the given frame. Confirmed inputs are discarded, so the session can only roll
+    /// back as far as the latest saved state before the last confirmed frame.
+    ///
+    /// # Errors
+    /// - Returns [`NotSynchronized`] if the session is not running yet.
+    /// - Returns [`InvalidRequest`] if the session is delay-based, as it does not roll back.
+    /// - Returns [`StateUnavailable`] if the frame is not before the current frame or the state to load is no longer saved.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`NotSynchronized`]: GgrsError::NotSynchronized
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`StateUnavailable`]: GgrsError::StateUnavailable
+    pub fn force_rollback_to(&mut self, frame: Frame) -> Result<(), GgrsError> {
+        if self.state != SessionState::Running {
+            return Err(GgrsError::NotSynchronized);
+        }
+        if self.delay_based {
+            return Err(GgrsError::InvalidRequest {
+                info: "A delay-based session does not roll back.".to_owned(),
+            });
+        }
+        let current_frame = self.sync_layer.current_frame();
+        let unavailable = GgrsError::StateUnavailable {
+            frame,
+            current_frame,
+        };
+        if frame < 0 || frame >= current_frame {
+            return Err(unavailable);
+        }
+        // the state has to be loadable and the inputs since then have to be kept to resimulate
+        let loadable = self.frame_to_load(frame).is_some_and(|frame_to_load| {
+            frame_to_load <= frame
+                && frame_to_load >= self.sync_layer.first_kept_frame()
+                && self.sync_layer.can_load_frame(frame_to_load)
+        });
+        if !loadable {
+            return Err(unavailable);
+        }
+
+        self.rollback_frame = Some(self.rollback_frame.map_or(frame, |f| f.min(frame)));
+        self.rollback_causes.push(RollbackCause::Forced);
+        Ok(())
+    }
+
+    /// Returns the range of frames that will be resimulated due to mispredictions with the next call to [`advance_frame()`], or `None` if no rollback is pending.
+    /// This is known as soon as the remote inputs have been received, e.g. after calling [`poll_remote_clients()`], so you can schedule the resimulation
+    /// work before the requests arrive. Since [`advance_frame()`] polls again, inputs received in the meantime can still extend the range.
+    /// In sparse saving mode, additional rollbacks to keep the saved state within the prediction window are not included.
+    /// With [`SessionBuilder::with_max_resim_frames_per_advance()`], the range includes the frames an unfinished rollback still has to
+    /// resimulate, of which the next call only resimulates the first few.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`poll_remote_clients()`]: Self#method.poll_remote_clients
+    /// [`SessionBuilder::with_max_resim_frames_per_advance()`]: crate::SessionBuilder#method.with_max_resim_frames_per_advance
+    pub fn pending_resimulation(&self) -> Option<Range<Frame>> {
+        if self.delay_based {
+            return None;
+        }
+        let current_frame = self.sync_layer.current_frame();
+        let target_frame = self.resim_target.unwrap_or(current_frame);
+        match self.first_incorrect_frame() {
+            Some(first_incorrect) if first_incorrect < current_frame => self
+                .frame_to_load(first_incorrect)
+                .map(|frame_to_load| frame_to_load..target_frame),
+            _ if target_frame > current_frame => Some(current_frame..target_frame),
+            _ => None,
+        }
+    }
+
+    /// Returns the earliest simulated frame for which a predicted input turned out to be wrong, or `None` if all predictions of the
+    /// received inputs were correct so far. Frames before it were predicted correctly and are not resimulated, apart from the frames
+    /// between it and the latest saved state before it, see [`pending_resimulation()`].
+    ///
+    /// [`pending_resimulation()`]: Self#method.pending_resimulation
+    pub fn first_incorrect_frame(&self) -> Option<Frame> {
+        self.sync_layer
+            .check_simulation_consistency(self.rollback_frame)
+            .filter(|&first_incorrect| first_incorrect < self.sync_layer.current_frame())
+    }
+
+    /// Returns how many rollbacks the session did and how many it avoided because all predictions were correct.
+    pub fn rollback_stats(&self) -> RollbackStats {
+        self.rollback_stats
+    }
+
+    /// Returns the time left until the session starts with a synchronized start, see [`SessionBuilder::with_synchronized_start()`].
+    /// `None` if the peers have not agreed on a start time yet, or if the session does not use a synchronized start.
+    ///
+    /// [`SessionBuilder::with_synchronized_start()`]: crate::SessionBuilder#method.with_synchronized_start
+    pub fn time_until_start(&self) -> Option<Duration> {
+        let start_time = self.agreed_start_time()?;
+        let remaining = start_time.saturating_sub(self.clock.millis_since_epoch());
+        Some(Duration::from_millis(remaining as u64))
+    }
+
+    /// Returns the highest confirmed frame. We have received all input for this frame and it is thus correct.
+    /// [`NULL_FRAME`] as long as not even the inputs of the start frame are confirmed.
+    pub fn confirmed_frame(&self) -> Frame {
+        self.confirmed_input_frame().unwrap_or(NULL_FRAME)
+    }
+
+    /// Returns the highest frame for which the inputs of all connected players have been received, if any.
+    fn confirmed_input_frame(&self) -> Option<Frame> {
+        // once all players disconnected, e.g. from a spectator without local players, the blank inputs of every frame are known
+        self.local_connect_status
+            .iter()
+            .filter(|con_stat| !con_stat.disconnected)
+            .map(|con_stat| con_stat.last_frame)
+            .min()
+            .unwrap_or(Some(self.sync_layer.current_frame()))
+    }
+
+    /// Returns the highest frame for which all inputs are known, where the local inputs for the current frame count as known even though
+    /// they are only registered after the rollback checks. The state of this frame only depends on inputs of earlier frames,
+    /// so it is safe to save, while using [`confirmed_frame()`] would roll back a frame just because of the local inputs.
+    ///
+    /// [`confirmed_frame()`]: Self#method.confirmed_frame
+    fn confirmed_frame_with_local_inputs(&self) -> Option<Frame> {
+        let local_handles = self.player_reg.local_player_handles();
+        let mut confirmed_frame = Some(Frame::MAX);
+        for (handle, con_stat) in self.local_connect_status.iter().enumerate() {
+            if con_stat.disconnected {
+                continue;
+            }
+            let mut last_frame = con_stat.last_frame;
+            if local_handles.contains(&handle) {
+                let delay = self.sync_layer.frame_delay(handle).unwrap_or(0);
+                last_frame = last_frame.max(Some(self.sync_layer.current_frame() + delay as Frame));
+            }
+            confirmed_frame = std::cmp::min(confirmed_frame, last_frame);
+        }
+        confirmed_frame
+    }
+
+    /// Returns whether saving the current frame can be skipped, because all inputs are known up to the next frame that is saved
+    /// and thus no rollback will load it. The states that desync detection compares are always saved.
+    fn can_skip_save(&self) -> bool {
+        if !self.skip_confirmed_saves {
+            return false;
+        }
+        if let DesyncDetection::On { interval } = self.desync_detection {
+            if self.sync_layer.current_frame() % interval as Frame == 0 {
+                return false;
+            }
+        }
+        let next_save_frame = self.sync_layer.next_save_frame();
+        self.confirmed_frame_with_local_inputs() >= Some(next_save_frame - 1)
+    }
+
+    /// Returns how many times to call [`advance_frame()`] during the current render frame, for render loops that do not run at the
+    /// fps set with [`SessionBuilder::with_fps()`]. The time since the last call is accumulated and handed out in whole frames, at
+    /// most a few at once. The frames a [`GgrsEvent::WaitRecommendation`] recommends to skip are already left out, so don't skip
+    /// them again. Always returns 0 while the session is not running.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`SessionBuilder::with_fps()`]: crate::SessionBuilder#method.with_fps
+    pub fn frames_to_advance(&mut self, now: Instant) -> usize {
+        if self.state != SessionState::Running {
+            return 0;
+        }
+        self.frame_pacer.frames_to_advance(now)
+    }
+
+    /// Returns the number of frames this session can still advance before reaching the prediction threshold, assuming no further
+    /// remote input arrives. Once this reaches 0, [`advance_frame()`] will return [`PredictionThreshold`] until remote input is received.
+    /// This allows you to react to an upcoming stall (e.g. by showing a connection indicator) before it happens.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`PredictionThreshold`]: GgrsError::PredictionThreshold
+    pub fn frames_until_stall(&self) -> usize {
+        self.sync_layer
+            .frames_until_prediction_threshold(self.confirmed_input_frame())
+    }
+
+    /// Returns by how many frames the newest input received from the given player lags behind the last advanced frame,
+    /// i.e. how many frames in a row the input of that player has been predicted. A value of 0 means the input of the last advanced frame is confirmed.
+    /// You can use this to visualize stale predictions, for example by rendering remote characters with some interpolation.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] if the handle does not refer to a local or remote player.
+    ///
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    pub fn prediction_age(&self, player_handle: PlayerHandle) -> Result<usize, GgrsError> {
+        if player_handle >= self.num_players {
+            return Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            });
+        }
+        let current_frame = self.sync_layer.current_frame();
+        let age = match self.sync_layer.last_added_frame(player_handle) {
+            Some(last_added_frame) => current_frame - 1 - last_added_frame,
+            // without any input, all frames so far have been predicted
+            None => current_frame,
+        };
+        Ok(age.max(0) as usize)
+    }
+
+    /// Returns the [`Replay`] of all confirmed inputs so far, or `None` if replay recording is turned off.
+    /// If chunks have been taken with [`take_replay_chunk()`], it only holds the frames recorded since.
+    /// See [`SessionBuilder::with_replay_recording()`].
+    ///
+    /// [`take_replay_chunk()`]: Self#method.take_replay_chunk
+    /// [`SessionBuilder::with_replay_recording()`]: crate::SessionBuilder::with_replay_recording
+    pub fn replay(&self) -> Option<&Replay<T>> {
+        self.replay.as_ref()
+    }
+
+    /// Takes the frames recorded since the last call out of the session and returns them as a [`Replay`], or `None` if replay
+    /// recording is turned off. Recording continues with the next frame. Write the chunks to disk periodically to keep the memory of long
+    /// sessions bounded, and join them in order with [`Replay::append()`] to get the replay of the whole session.
+    ///
+    /// [`Replay::append()`]: Replay#method.append
+    pub fn take_replay_chunk(&mut self) -> Option<Replay<T>> {
+        let replay = self.replay.as_mut()?;
+        let chunk = std::mem::replace(replay, Replay::new(self.num_players));
+        self.replay_chunk_start += chunk.num_frames() as Frame;
+        Some(chunk)
+    }
+
+    /// Returns how many bytes the session currently holds for saved states, input queues, pending packets and the replay.
+    /// Query it periodically to keep an eye on the memory budget of long sessions. See [`MemoryReport`] for what is counted.
+    pub fn memory_usage(&self) -> MemoryReport {
+        let metadata: usize = self
+            .input_metadata
+            .iter()
+            .map(|history| {
+                history.capacity() * std::mem::size_of::<(Frame, Vec<u8>)>()
+                    + history
+                        .iter()
+                        .map(|(_, bytes)| bytes.capacity())
+                        .sum::<usize>()
+            })
+            .sum();
+        MemoryReport {
+            saved_states: self.sync_layer.saved_states_memory(),
+            input_queues: self.sync_layer.input_queues_memory() + metadata,
+            pending_packets: self
+                .player_reg
+                .remotes
+                .values()
+                .chain(self.player_reg.spectators.values())
+                .map(|endpoint| endpoint.pending_memory())
+                .sum::<usize>()
+                + self.receive_backlog.memory(),
+            replay: self
+                .replay
+                .as_ref()
+                .map_or(0, |replay| replay.memory_usage()),
+        }
+    }
+
+    /// Returns a [`DiagnosticsReport`] with the rollbacks, stalls and desyncs since the session started, the network statistics of
+    /// every remote client and the settings of the session. Export it after a match to aggregate the netcode health across players.
+    pub fn export_diagnostics(&self) -> DiagnosticsReport {
+        let remotes = self.player_reg.remotes.values().map(|ep| (ep, false));
+        let spectators = self.player_reg.spectators.values().map(|ep| (ep, true));
+        let mut peers: Vec<PeerDiagnostics> = remotes
+            .chain(spectators)
+            .map(|(endpoint, spectator)| PeerDiagnostics {
+                // a spectator sends no inputs, so its endpoint decodes none
+                handles: if spectator {
+                    self.player_reg.handles_by_address(endpoint.peer_addr())
+                } else {
+                    endpoint.handles().clone()
+                },
+                spectator,
+                network_stats: endpoint.network_stats().ok(),
+                external_addr: endpoint.remote_external_addr(),
+            })
+            .collect();
+        peers.sort_by_key(|peer| peer.handles.first().copied());
+
+        DiagnosticsReport {
+            frame: self.current_frame(),
+            confirmed_frame: self.confirmed_frame(),
+            rollback_stats: self.rollback_stats,
+            stalls: self.stalls,
+            desyncs: self.desyncs,
+            peers,
+            memory: self.memory_usage(),
+            settings: SessionSettings {
+                num_players: self.num_players,
+                num_spectators: self.num_spectators(),
+                max_prediction: self.max_prediction,
+                input_delay: self.input_delay,
+                sparse_saving: self.sparse_saving,
+                delay_based: self.delay_based,
+                desync_detection: self.desync_detection,
+            },
+        }
+    }
+
+    /// Returns the confirmed inputs of all players for every frame from `frame` up to the last confirmed frame, ordered by frame and
+    /// then by player handle. Training modes can use them to rewind the game and let the player take over from there. Only the inputs
+    /// that rollbacks still need are kept by default, see [`SessionBuilder::with_input_history()`] to keep more.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the inputs of `frame` are no longer kept.
+    ///
+    /// [`SessionBuilder::with_input_history()`]: crate::SessionBuilder#method.with_input_history
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn inputs_since(&self, frame: Frame) -> Result<Vec<Vec<T::Input>>, GgrsError> {
+        self.sync_layer
+            .inputs_since(frame, &self.local_connect_status)
+    }
+
+    /// Returns a snapshot of the ring of saved states, with the frames, sizes and checksums of all slots but not the states
+    /// themselves. Requires the `debug-tools` feature. Query it once per frame to render the saved states and rollbacks in a debug overlay.
+    #[cfg(feature = "debug-tools")]
+    pub fn saved_states_snapshot(&self) -> crate::SavedStatesSnapshot {
+        self.sync_layer.saved_states_snapshot()
+    }
+
+    /// Returns a snapshot of the input queue of every player, ordered by player handle. Requires the `debug-tools` feature.
+    /// Query it once per frame to visualize how deep the session predicts and how often predictions turn out wrong.
+    #[cfg(feature = "debug-tools")]
+    pub fn input_queue_snapshots(&self) -> Vec<crate::InputQueueSnapshot> {
+        self.sync_layer.input_queue_snapshots()
+    }
+
+    /// Returns the current frame of a session.
+    pub fn current_frame(&self) -> Frame {
+        self.sync_layer.current_frame()
+    }
+
+    /// Returns the maximum prediction window of a session.
+    pub fn max_prediction(&self) -> usize {
         self.max_prediction
     }
 
@@ -515,8 +1938,14 @@ impl<T: Config> P2PSession<T> {
         self.state
     }
 
+    /// Returns true if the session runs delay-based, which is the case if delay-based fallback was turned on
+    /// for this session and all remote peers. This is only known once the session is running.
+    pub fn is_delay_based(&self) -> bool {
+        self.delay_based
+    }
+
     /// Returns all events that happened since last queried for events. If the number of stored events exceeds `MAX_EVENT_QUEUE_SIZE`, the oldest events will be discarded.
-    pub fn events(&mut self) -> Drain<GgrsEvent<T>> {
+    pub fn events(&mut self) -> Drain<'_, GgrsEvent<T>> {
         self.event_queue.drain(..)
     }
 
@@ -530,139 +1959,855 @@ impl<T: Config> P2PSession<T> {
         self.player_reg.num_spectators()
     }
 
-    /// Returns the handles of local players that have been added
-    pub fn local_player_handles(&self) -> Vec<PlayerHandle> {
-        self.player_reg.local_player_handles()
+    /// Returns the handles of the players whose inputs [`GgrsRequest::AdvanceFrame`] contains for the given frame, in order.
+    /// This is every player, unless disconnected players are removed, see [`SessionBuilder::with_disconnected_player_removal()`].
+    ///
+    /// [`SessionBuilder::with_disconnected_player_removal()`]: crate::SessionBuilder#method.with_disconnected_player_removal
+    pub fn input_handles(&self, frame: Frame) -> Vec<PlayerHandle> {
+        (0..self.num_players)
+            .filter(|&handle| {
+                let status = &self.local_connect_status[handle];
+                !self.remove_disconnected_players
+                    || !status.disconnected
+                    || status.last_frame >= Some(frame)
+            })
+            .collect()
+    }
+
+    /// Returns the handles of local players that have been added
+    pub fn local_player_handles(&self) -> Vec<PlayerHandle> {
+        self.player_reg.local_player_handles()
+    }
+
+    /// Returns the handles of remote players that have been added
+    pub fn remote_player_handles(&self) -> Vec<PlayerHandle> {
+        self.player_reg.remote_player_handles()
+    }
+
+    /// Returns the handles of spectators that have been added
+    pub fn spectator_handles(&self) -> Vec<PlayerHandle> {
+        self.player_reg.spectator_handles()
+    }
+
+    /// Returns all handles associated to a certain address
+    pub fn handles_by_address(&self, addr: T::Address) -> Vec<PlayerHandle> {
+        self.player_reg.handles_by_address(addr)
+    }
+
+    /// Returns all players and spectators of this session together with their [`PlayerType`], sorted by handle.
+    pub fn players(&self) -> Vec<(PlayerHandle, PlayerType<T::Address>)> {
+        let mut players: Vec<_> = self
+            .player_reg
+            .handles
+            .iter()
+            .map(|(handle, player_type)| (*handle, player_type.clone()))
+            .collect();
+        players.sort_unstable_by_key(|(handle, _)| *handle);
+        players
+    }
+
+    /// Returns the input delay in frames of the given player. Remote players delay their inputs on their own device, so their delay is always 0.
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] if the handle does not refer to a local or remote player.
+    ///
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    pub fn input_delay(&self, player_handle: PlayerHandle) -> Result<usize, GgrsError> {
+        self.sync_layer
+            .frame_delay(player_handle)
+            .ok_or(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            })
+    }
+
+    /// Returns the number of frames this session is estimated to be ahead of other sessions
+    pub fn frames_ahead(&self) -> i32 {
+        self.frames_ahead
+    }
+
+    /// Returns the inputs of all players for the current frame, without those of disconnected players if they are removed.
+    fn synchronized_inputs(&mut self) -> Vec<(T::Input, InputStatus)> {
+        let mut inputs = self.input_buffers.pop().unwrap_or_default();
+        self.sync_layer
+            .synchronized_inputs_into(&self.local_connect_status, &mut inputs);
+        if self.remove_disconnected_players {
+            inputs.retain(|(_, status)| *status != InputStatus::Disconnected);
+        }
+        inputs
+    }
+
+    fn disconnect_player_at_frame(
+        &mut self,
+        player_handle: PlayerHandle,
+        last_frame: Option<Frame>,
+    ) {
+        // disconnect the remote player
+        match self
+            .player_reg
+            .handles
+            .get(&player_handle)
+            .expect("Invalid player handle")
+        {
+            PlayerType::Remote(addr) => {
+                let addr = addr.clone();
+                // a player without inputs yet has none before the start frame either
+                let start_frame = self.sync_layer.start_frame();
+                let first_missing_frame = |last_frame: Option<Frame>| {
+                    last_frame.map_or(start_frame, |frame| (frame + 1).max(start_frame))
+                };
+
+                // mark the affected players as disconnected
+                for handle in self.player_reg.handles_by_address(addr.clone()) {
+                    self.early_inputs.retain(|(player, _, _)| *player != handle);
+                    let status = &mut self.local_connect_status[handle];
+                    if self.remove_disconnected_players && !status.disconnected {
+                        self.event_queue.push_back(GgrsEvent::PlayerRemoved {
+                            handle,
+                            frame: first_missing_frame(status.last_frame),
+                        });
+                    }
+                    status.disconnected = true;
+                }
+                self.player_reg
+                    .remotes
+                    .get_mut(&addr)
+                    .expect("There should be no address without registered endpoint")
+                    .disconnect();
+
+                let first_missing_frame = first_missing_frame(last_frame);
+                if self.sync_layer.current_frame() >= first_missing_frame {
+                    // remember to adjust simulation to account for the fact that the player disconnected a few frames ago,
+                    // resimulating with correct disconnect flags (to account for user having some AI kick in).
+                    self.rollback_frame = Some(
+                        self.rollback_frame
+                            .map_or(first_missing_frame, |frame| frame.min(first_missing_frame)),
+                    );
+                    self.rollback_causes.push(RollbackCause::Disconnect {
+                        handle: player_handle,
+                    });
+                }
+            }
+            PlayerType::Spectator(addr) => {
+                let endpoint = self
+                    .player_reg
+                    .spectators
+                    .get_mut(addr)
+                    .expect("There should be no address without registered endpoint");
+                endpoint.disconnect();
+            }
+            PlayerType::Local => (),
+        }
+
+        // check if all remotes are synchronized now
+        self.check_initial_sync();
+    }
+
+    /// Change the session state to [`SessionState::Running`] if all UDP endpoints are synchronized.
+    fn check_initial_sync(&mut self) {
+        // if we are not synchronizing, we don't need to do anything
+        if self.state != SessionState::Synchronizing {
+            return;
+        }
+
+        // if any endpoint is not synchronized, we continue synchronizing
+        for endpoint in self.player_reg.remotes.values_mut() {
+            if !endpoint.is_synchronized() {
+                return;
+            }
+        }
+        for endpoint in self.player_reg.spectators.values_mut() {
+            if !endpoint.is_synchronized() {
+                return;
+            }
+        }
+
+        // with a fair input delay, we wait until the input delay has been split with all peers
+        let mut split_pending = false;
+        for endpoint in self.player_reg.remotes.values_mut() {
+            endpoint.split_fair_input_delay();
+            // disconnected peers do not need to agree
+            split_pending |= endpoint.is_running() && endpoint.fair_input_delay_pending();
+        }
+
+        // with a synchronized start, we propose a start time to all peers and start once the agreed one has passed
+        if let Some(countdown) = self.synchronized_start {
+            if self.proposed_start_time.is_none() {
+                let start_time = self.clock.millis_since_epoch() + countdown.as_millis();
+                self.proposed_start_time = Some(start_time);
+                for endpoint in self.player_reg.remotes.values_mut() {
+                    endpoint.propose_start_time(start_time as u64);
+                }
+            }
+            if self
+                .agreed_start_time()
+                .is_none_or(|start_time| self.clock.millis_since_epoch() < start_time)
+            {
+                return;
+            }
+        }
+
+        if split_pending {
+            return;
+        }
+        self.start();
+    }
+
+    /// Everyone is synchronized, so we can change state and accept input.
+    fn start(&mut self) {
+        self.state = SessionState::Running;
+        self.event_queue.push_back(GgrsEvent::SessionStateChanged {
+            state: SessionState::Running,
+        });
+        self.negotiate_fair_input_delay();
+        self.negotiate_delay_based_mode();
+    }
+
+    /// Returns the agreed start time in ms since the epoch for a synchronized start: the latest start time proposed by us
+    /// and all remote peers. Since every peer converts the proposals to its own clock, all peers agree on the same moment.
+    /// `None` if not all proposals have arrived yet.
+    fn agreed_start_time(&self) -> Option<u128> {
+        let mut start_time = self.proposed_start_time?;
+        for endpoint in self.player_reg.remotes.values() {
+            // disconnected peers do not need to agree, and peers without a synchronized start never propose a time
+            if endpoint.is_running() && endpoint.remote_synchronized_start() {
+                start_time = start_time.max(endpoint.remote_start_time()?);
+            }
+        }
+        Some(start_time)
+    }
+
+    /// Sets the input delay of local players to our fair share of the input delays of us and the remote peers, if all remote peers
+    /// agreed to split it during synchronization. With multiple remote peers, the highest share is used.
+    fn negotiate_fair_input_delay(&mut self) {
+        let shares: Option<Vec<usize>> = self
+            .player_reg
+            .remotes
+            .values()
+            .map(|endpoint| endpoint.fair_input_delay_share())
+            .collect();
+        let Some(input_delay) = shares.and_then(|shares| shares.into_iter().max()) else {
+            return;
+        };
+        self.input_delay = input_delay;
+        for handle in self.player_reg.local_player_handles() {
+            self.sync_layer.set_frame_delay(handle, self.input_delay);
+        }
+    }
+
+    /// Switches the session into delay-based mode if it was requested locally and all remote peers agreed to it during synchronization.
+    /// The input delay of local players is raised to the highest round trip time (in frames) among the remote peers.
+    fn negotiate_delay_based_mode(&mut self) {
+        if !self.delay_based_requested
+            || !self
+                .player_reg
+                .remotes
+                .values()
+                .all(|endpoint| endpoint.delay_based_agreed())
+        {
+            return;
+        }
+
+        let round_trip_frames = self
+            .player_reg
+            .remotes
+            .values()
+            .map(|endpoint| endpoint.round_trip_frames())
+            .max()
+            .unwrap_or(0);
+        self.input_delay = std::cmp::max(self.input_delay, round_trip_frames);
+        for handle in self.player_reg.local_player_handles() {
+            self.sync_layer.set_frame_delay(handle, self.input_delay);
+        }
+        self.delay_based = true;
+    }
+
+    /// Registers the local inputs for the current frame in the sync layer and sends them to all remote clients.
+    fn register_local_inputs(&mut self) -> Result<(), GgrsError> {
+        // the input queue drops an input if the frame delay of the player shrank, which no session does for a local player
+        let dropped_input = || GgrsError::InvalidRequest {
+            info: "The local input was dropped, since the input delay of the player shrank."
+                .to_owned(),
+        };
+        // linked players take the input of their primary player, which is registered first
+        let local_handles = [false, true]
+            .into_iter()
+            .flat_map(|linked| (0..self.num_players).map(move |handle| (handle, linked)));
+        for (handle, linked) in local_handles {
+            if self.player_reg.handles.get(&handle) != Some(&PlayerType::Local)
+                || self.player_reg.links.contains_key(&handle) != linked
+            {
+                continue;
+            }
+            if let Some(&primary) = self.player_reg.links.get(&handle) {
+                let input = self.local_inputs[&primary].input;
+                let actual_frame = self
+                    .sync_layer
+                    .add_local_input(handle, input)?
+                    .ok_or_else(dropped_input)?;
+                self.local_connect_status[handle].last_frame = Some(actual_frame);
+                if let Some(metadata) = self.local_metadata.get(&primary) {
+                    Self::store_input_metadata(
+                        &mut self.input_metadata[handle],
+                        self.input_metadata_history_size,
+                        actual_frame,
+                        metadata.clone(),
+                    );
+                }
+                continue;
+            }
+            match self.local_inputs.get_mut(&handle) {
+                Some(player_input) => {
+                    // send the input into the sync layer
+                    let actual_frame = self
+                        .sync_layer
+                        .add_local_input(handle, player_input.input)?
+                        .ok_or_else(dropped_input)?;
+                    // send the input to all other clients, but with the correct frame (influenced by input delay)
+                    player_input.frame = Some(actual_frame);
+                    self.local_connect_status[handle].last_frame = Some(actual_frame);
+                    if let Some(metadata) = self.local_metadata.get(&handle) {
+                        Self::store_input_metadata(
+                            &mut self.input_metadata[handle],
+                            self.input_metadata_history_size,
+                            actual_frame,
+                            metadata.clone(),
+                        );
+                    }
+                }
+                None => {
+                    return Err(GgrsError::MissingInput {
+                        handle,
+                        frame: self.sync_layer.current_frame(),
+                    });
+                }
+            }
+        }
+
+        // send the inputs to all clients
+        for endpoint in self.player_reg.remotes.values_mut() {
+            // send the input directly
+            endpoint.send_input(
+                &self.local_inputs,
+                &self.local_metadata,
+                &self.local_connect_status,
+            );
+            endpoint.send_all_messages(&mut self.socket);
+        }
+
+        // clear the local inputs after sending them
+        self.local_inputs.clear();
+        self.local_metadata.clear();
+        self.last_registered_frame = Some(self.sync_layer.current_frame());
+
+        // players handed over switch sides before inputs for their first frame on the other client are added
+        self.apply_hand_overs();
+
+        Ok(())
+    }
+
+    /// Returns the frame the next local inputs are registered for, after input delay.
+    fn next_input_frame(&self) -> Frame {
+        let mut frame = self.sync_layer.current_frame();
+        if self.last_registered_frame == Some(frame) {
+            frame += 1;
+        }
+        // no inputs are registered during the initial delay
+        if let StartupPolicy::InitialDelay { frames } = self.startup_policy {
+            frame = frame.max(self.sync_layer.start_frame() + frames as Frame);
+        }
+        frame + self.input_delay as Frame
+    }
+
+    /// Checks that the local player can be handed over to another client.
+    fn check_local_hand_over(&self, player_handle: PlayerHandle) -> Result<(), GgrsError> {
+        let local_handles = self.player_reg.local_player_handles();
+        if !local_handles.contains(&player_handle) {
+            return Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            });
+        }
+        if self.player_reg.links.contains_key(&player_handle)
+            || !self.player_reg.linked_handles(player_handle).is_empty()
+        {
+            return Err(GgrsError::InvalidRequest {
+                info: "Linked players cannot be handed over.".to_owned(),
+            });
+        }
+        if self.hand_overs.contains_key(&player_handle) {
+            return Err(GgrsError::InvalidRequest {
+                info: "The player is already being handed over.".to_owned(),
+            });
+        }
+        // the session keeps sending inputs for at least one local player
+        let kept_players = local_handles
+            .iter()
+            .filter(|handle| {
+                !self.hand_overs.contains_key(handle) && !self.player_reg.links.contains_key(handle)
+            })
+            .count();
+        if kept_players <= 1 {
+            return Err(GgrsError::InvalidRequest {
+                info: "The last local player cannot be handed over.".to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the client at `addr` can switch between a spectator and a player. While it takes part as a player, it gets
+    /// the inputs of our local players instead of the confirmed inputs of all players, so it cannot exchange inputs with other
+    /// remote clients. Linked players are never sent to other players, so they cannot be part of the session either.
+    fn check_spectator_switch(&self, addr: &T::Address) -> Result<(), GgrsError> {
+        if !self.player_reg.links.is_empty() {
+            return Err(GgrsError::InvalidRequest {
+                info: "Spectators cannot switch to players in a session with linked players."
+                    .to_owned(),
+            });
+        }
+        if self
+            .player_reg
+            .remotes
+            .iter()
+            .any(|(remote_addr, endpoint)| remote_addr != addr && endpoint.is_running())
+        {
+            return Err(GgrsError::InvalidRequest {
+                info:
+                    "Spectators cannot switch to players while other remote clients are connected."
+                        .to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Answers the request of the remote client at `addr` to move a player between us from `frame` on or later: we take over its
+    /// player, or hand our local player over to it if it demoted the player, see [`P2PSession::demote_player()`]. Tells all
+    /// remote clients the frame.
+    fn accept_hand_over(&mut self, handle: PlayerHandle, frame: Frame, addr: T::Address) {
+        if self.state == SessionState::Running
+            && self.player_reg.handles.get(&handle) == Some(&PlayerType::Local)
+        {
+            self.give_back(handle, frame, addr);
+            return;
+        }
+        // the player might have been disconnected or moved in the meantime
+        if self.state != SessionState::Running
+            || self.player_reg.handles.get(&handle) != Some(&PlayerType::Remote(addr.clone()))
+            || self.local_connect_status[handle].disconnected
+            || self.hand_overs.contains_key(&handle)
+        {
+            return;
+        }
+        // leaves the application a frame to add local input for the player
+        let frame = frame.max(self.next_input_frame() + 1);
+        for (endpoint_addr, endpoint) in self.player_reg.remotes.iter_mut() {
+            if *endpoint_addr == addr {
+                endpoint.change_handles(handle, frame, false);
+            }
+            endpoint.announce_handle_change(handle, frame, true);
+        }
+        self.hand_overs
+            .insert(handle, HandOver::Taking { addr, frame });
+    }
+
+    /// Hands our local player back to the remote client at `addr` that demoted it, from `frame` on or later. Unlike a hand-over
+    /// we requested, this might be our last local player.
+    fn give_back(&mut self, handle: PlayerHandle, frame: Frame, addr: T::Address) {
+        if !self.player_reg.remotes.contains_key(&addr)
+            || self.player_reg.links.contains_key(&handle)
+            || !self.player_reg.linked_handles(handle).is_empty()
+            || self.hand_overs.contains_key(&handle)
+        {
+            return;
+        }
+        // we stop with the first frame we have not added inputs for yet
+        let frame = frame.max(self.next_input_frame());
+        for endpoint in self.player_reg.remotes.values_mut() {
+            endpoint.announce_handle_change(handle, frame, false);
+        }
+        self.hand_overs
+            .insert(handle, HandOver::Giving { addr, frame });
+        self.apply_hand_overs();
+    }
+
+    /// Handles a remote client announcing that it sends inputs for the player from `frame` on.
+    fn on_player_added(&mut self, handle: PlayerHandle, frame: Frame, addr: T::Address) {
+        match self.hand_overs.get(&handle) {
+            // the remote client accepted our request, we keep sending inputs for the player until the agreed frame
+            Some(HandOver::Requested { addr: to, .. }) if *to == addr => {
+                for endpoint in self.player_reg.remotes.values_mut() {
+                    endpoint.announce_handle_change(handle, frame, false);
+                }
+                // a promoted spectator gets the inputs of our local players from then on
+                let local_handles = self.player_reg.local_player_handles();
+                if let Some(endpoint) = self.player_reg.spectators.get_mut(&addr) {
+                    for removed in (0..self.num_players)
+                        .filter(|other| *other == handle || !local_handles.contains(other))
+                    {
+                        endpoint.announce_handle_change(removed, frame, false);
+                    }
+                }
+                self.hand_overs
+                    .insert(handle, HandOver::Giving { addr, frame });
+                self.apply_hand_overs();
+            }
+            // the player moved between two other clients. Disconnected players do not move, a demoted client is merely
+            // sent their inputs again.
+            _ => {
+                if let Some(player_type @ PlayerType::Remote(_)) =
+                    self.player_reg.handles.get_mut(&handle)
+                {
+                    if *player_type != PlayerType::Remote(addr.clone())
+                        && !self.local_connect_status[handle].disconnected
+                    {
+                        *player_type = PlayerType::Remote(addr);
+                        self.event_queue
+                            .push_back(GgrsEvent::PlayerHandedOver { handle, frame });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles a remote client announcing that it no longer sends inputs for the player from `frame` on. Only its answer to our
+    /// request to take the player back matters, the client taking over a player announces the move itself.
+    fn on_player_removed(&mut self, handle: PlayerHandle, frame: Frame, addr: T::Address) {
+        if !matches!(self.hand_overs.get(&handle), Some(HandOver::Reclaiming { addr: from, .. }) if *from == addr)
+        {
+            return;
+        }
+        for endpoint in self.player_reg.remotes.values_mut() {
+            endpoint.announce_handle_change(handle, frame, true);
+        }
+        // a remote client left without players gets the confirmed inputs of all players as a spectator from then on
+        if self.player_reg.handles_by_address(addr.clone()) == [handle] {
+            let local_handles = self.player_reg.local_player_handles();
+            let endpoint = self
+                .player_reg
+                .remotes
+                .get_mut(&addr)
+                .expect("Endpoint should exist for any registered player");
+            for added in (0..self.num_players)
+                .filter(|other| *other != handle && !local_handles.contains(other))
+            {
+                endpoint.announce_handle_change(added, frame, true);
+            }
+        }
+        self.hand_overs
+            .insert(handle, HandOver::Taking { addr, frame });
+    }
+
+    /// Moves the players whose hand-over frame the next local inputs are registered for to the other side.
+    fn apply_hand_overs(&mut self) {
+        let next_frame = self.next_input_frame();
+        let mut moved = Vec::new();
+        for (&handle, hand_over) in &self.hand_overs {
+            match hand_over {
+                HandOver::Giving { addr, frame } if *frame <= next_frame => {
+                    moved.push((handle, PlayerType::Remote(addr.clone()), *frame));
+                }
+                HandOver::Taking { frame, .. }
+                    if *frame <= next_frame
+                        && self.player_reg.handles.get(&handle) != Some(&PlayerType::Local) =>
+                {
+                    moved.push((handle, PlayerType::Local, *frame));
+                }
+                _ => (),
+            }
+        }
+
+        for (handle, player_type, frame) in moved {
+            if let PlayerType::Remote(addr) = &player_type {
+                // the inputs of the other client follow right after our last one
+                self.hand_overs.remove(&handle);
+                self.sync_layer.set_frame_delay(handle, 0);
+                self.local_inputs.remove(&handle);
+                self.local_metadata.remove(&handle);
+                if self.player_reg.spectators.contains_key(addr) {
+                    self.make_player(addr.clone(), frame);
+                }
+            }
+            self.player_reg.handles.insert(handle, player_type);
+            self.event_queue
+                .push_back(GgrsEvent::PlayerHandedOver { handle, frame });
+        }
     }
 
-    /// Returns the handles of remote players that have been added
-    pub fn remote_player_handles(&self) -> Vec<PlayerHandle> {
-        self.player_reg.remote_player_handles()
-    }
+    /// Returns a player whose hand-over keeps the local inputs from being registered. A player we take over is registered
+    /// once the last inputs of the previous client for it have arrived. If that client disconnected, the missing inputs are
+    /// blank. A player we requested to hand over is not registered beyond the requested frame before the remote client answered.
+    fn stalling_hand_over(&mut self) -> Option<PlayerHandle> {
+        let next_frame = self.next_input_frame();
+        let mut stalling = None;
+        let mut ready = Vec::new();
+        for (&handle, hand_over) in &self.hand_overs {
+            match hand_over {
+                HandOver::Requested { frame, .. } | HandOver::Reclaiming { frame, .. }
+                    if *frame <= next_frame =>
+                {
+                    stalling = Some(handle)
+                }
+                HandOver::Taking { addr, frame }
+                    if self.player_reg.handles.get(&handle) == Some(&PlayerType::Local) =>
+                {
+                    let first_missing_frame = self.local_connect_status[handle]
+                        .last_frame
+                        .map_or(self.sync_layer.start_frame(), |frame| frame + 1);
+                    let disconnected = self
+                        .player_reg
+                        .remotes
+                        .get(addr)
+                        .is_none_or(|endpoint| !endpoint.is_running());
+                    if first_missing_frame >= *frame || disconnected {
+                        ready.push((handle, addr.clone(), first_missing_frame..*frame));
+                    } else {
+                        stalling = Some(handle);
+                    }
+                }
+                _ => (),
+            }
+        }
 
-    /// Returns the handles of spectators that have been added
-    pub fn spectator_handles(&self) -> Vec<PlayerHandle> {
-        self.player_reg.spectator_handles()
-    }
+        for (handle, addr, missing_frames) in ready {
+            let frame = missing_frames.end;
+            for frame in missing_frames {
+                let input = PlayerInput::new(frame, T::Input::zeroed());
+                self.sync_layer.add_remote_input(handle, input);
+                self.local_connect_status[handle].last_frame = Some(frame);
+            }
+            self.hand_overs.remove(&handle);
+            self.sync_layer.set_frame_delay(handle, self.input_delay);
 
-    /// Returns all handles associated to a certain address
-    pub fn handles_by_address(&self, addr: T::Address) -> Vec<PlayerHandle> {
-        self.player_reg.handles_by_address(addr)
+            // a demoted client left without players stays as a spectator
+            let running = self
+                .player_reg
+                .remotes
+                .get(&addr)
+                .is_some_and(UdpProtocol::is_running);
+            if running
+                && self.player_reg.handles_by_address(addr.clone()).is_empty()
+                && !self
+                    .hand_overs
+                    .values()
+                    .any(|hand_over| *hand_over.addr() == addr)
+            {
+                self.make_spectator(addr, frame);
+            }
+        }
+        stalling
     }
 
-    /// Returns the number of frames this session is estimated to be ahead of other sessions
-    pub fn frames_ahead(&self) -> i32 {
-        self.frames_ahead
+    /// Moves the endpoint of a promoted spectator to the remote clients. The spectator gets the confirmed inputs of all players up
+    /// to `frame` right away, and the inputs of our local players from then on.
+    fn make_player(&mut self, addr: T::Address, frame: Frame) {
+        // the inputs of all players up to the frame are confirmed, since no other remote client sends inputs
+        self.send_confirmed_inputs_to_spectators(Some(frame - 1));
+        let mut endpoint = self
+            .player_reg
+            .spectators
+            .remove(&addr)
+            .expect("Endpoint should exist for any registered spectator");
+        let next_frame = self
+            .spectator_frames
+            .remove(&addr)
+            .unwrap_or(self.sync_layer.start_frame());
+        for inputs in self
+            .spectator_history
+            .iter()
+            .filter(|inputs| (next_frame..frame).contains(&inputs.frame))
+        {
+            endpoint.push_input(&inputs.inputs, &HashMap::new());
+        }
+        endpoint.send_pending_output(&self.local_connect_status);
+
+        self.player_reg
+            .handles
+            .retain(|_, player_type| *player_type != PlayerType::Spectator(addr.clone()));
+        self.player_reg.spectator_delays.remove(&addr);
+        self.player_reg.remotes.insert(addr, endpoint);
     }
 
-    fn disconnect_player_at_frame(&mut self, player_handle: PlayerHandle, last_frame: Frame) {
-        // disconnect the remote player
-        match self
+    /// Moves the endpoint of a demoted client left without players to the spectators. It gets the confirmed inputs of all
+    /// players from `frame` on, under the lowest free spectator handle.
+    fn make_spectator(&mut self, addr: T::Address, frame: Frame) {
+        let endpoint = self
             .player_reg
+            .remotes
+            .remove(&addr)
+            .expect("Endpoint should exist for any registered player");
+        // without spectators, no confirmed inputs have been kept for them
+        if self.num_spectators() == 0 {
+            self.next_spectator_frame = frame;
+            self.spectator_history.clear();
+        }
+        let handle = (self.num_players..)
+            .find(|handle| !self.player_reg.handles.contains_key(handle))
+            .expect("A spectator handle should be free");
+        self.player_reg
             .handles
-            .get(&player_handle)
-            .expect("Invalid player handle")
-        {
-            PlayerType::Remote(addr) => {
-                let endpoint = self
-                    .player_reg
-                    .remotes
-                    .get_mut(addr)
-                    .expect("There should be no address without registered endpoint");
-
-                // mark the affected players as disconnected
-                for &handle in endpoint.handles() {
-                    self.local_connect_status[handle].disconnected = true;
-                }
-                endpoint.disconnect();
+            .insert(handle, PlayerType::Spectator(addr.clone()));
+        self.player_reg.spectators.insert(addr.clone(), endpoint);
+        self.spectator_frames.insert(addr, frame);
+    }
 
-                if self.sync_layer.current_frame() > last_frame {
-                    // remember to adjust simulation to account for the fact that the player disconnected a few frames ago,
-                    // resimulating with correct disconnect flags (to account for user having some AI kick in).
-                    self.disconnect_frame = last_frame + 1;
-                }
-            }
-            PlayerType::Spectator(addr) => {
-                let endpoint = self
-                    .player_reg
-                    .spectators
-                    .get_mut(addr)
-                    .expect("There should be no address without registered endpoint");
-                endpoint.disconnect();
-            }
-            PlayerType::Local => (),
+    fn store_input_metadata(
+        history: &mut VecDeque<(Frame, Vec<u8>)>,
+        history_size: usize,
+        frame: Frame,
+        metadata: Vec<u8>,
+    ) {
+        if history.len() == history_size {
+            history.pop_front();
         }
+        history.push_back((frame, metadata));
+    }
 
-        // check if all remotes are synchronized now
-        self.check_initial_sync();
+    /// Returns true if the inputs of all connected remote players for the current frame have been received.
+    fn first_missing_remote_input(&self) -> Option<PlayerHandle> {
+        let current_frame = self.sync_layer.current_frame();
+        self.player_reg
+            .remote_player_handles()
+            .into_iter()
+            .find(|&handle| {
+                let con_stat = self.local_connect_status[handle];
+                !con_stat.disconnected && con_stat.last_frame < Some(current_frame)
+            })
     }
 
-    /// Change the session state to [`SessionState::Running`] if all UDP endpoints are synchronized.
-    fn check_initial_sync(&mut self) {
-        // if we are not synchronizing, we don't need to do anything
-        if self.state != SessionState::Synchronizing {
-            return;
-        }
+    /// Returns the connected player with the oldest last received input, which holds back the confirmed frame, and that frame.
+    fn furthest_behind_player(&self) -> (PlayerHandle, Option<Frame>) {
+        self.local_connect_status
+            .iter()
+            .enumerate()
+            .filter(|(_, con_stat)| !con_stat.disconnected)
+            .map(|(handle, con_stat)| (handle, con_stat.last_frame))
+            .min_by_key(|&(_, last_frame)| last_frame)
+            .expect("All players are disconnected")
+    }
 
-        // if any endpoint is not synchronized, we continue synchronizing
-        for endpoint in self.player_reg.remotes.values_mut() {
-            if !endpoint.is_synchronized() {
-                return;
-            }
+    /// Counts a stall and reports why it happened.
+    fn stall(&mut self, cause: StallCause) {
+        self.stalls += 1;
+        if self.rollback_events {
+            self.event_queue.push_back(GgrsEvent::Stalled {
+                frame: self.sync_layer.current_frame(),
+                cause,
+            });
         }
-        for endpoint in self.player_reg.spectators.values_mut() {
-            if !endpoint.is_synchronized() {
-                return;
+    }
+
+    /// Continues a rollback that exceeded the resimulation budget with the next frames up to `target_frame`. If inputs arrived
+    /// in the meantime that contradict the frames resimulated so far, the rollback starts over from the first incorrect frame.
+    fn continue_resimulation(
+        &mut self,
+        target_frame: Frame,
+        requests: &mut Vec<GgrsRequest<T>>,
+    ) -> Result<(), GgrsError> {
+        let confirmed_frame = self.confirmed_input_frame();
+        if let Some(first_incorrect) = self
+            .sync_layer
+            .check_simulation_consistency(self.rollback_frame)
+        {
+            self.rollback_frame = None;
+            if first_incorrect < self.sync_layer.current_frame() {
+                self.adjust_gamestate(first_incorrect, confirmed_frame, requests)?;
+                return Ok(());
             }
+            // the frames from the first incorrect one on have not been resimulated yet, so they will use the new inputs anyways
+            self.sync_layer.reset_prediction();
+            self.rollback_causes.clear();
         }
-
-        // everyone is synchronized, so we can change state and accept input
-        self.state = SessionState::Running;
+        self.resimulate(target_frame, confirmed_frame, false, requests);
+        Ok(())
     }
 
     /// Roll back to `min_confirmed` frame and resimulate the game with most up-to-date input data.
     fn adjust_gamestate(
         &mut self,
         first_incorrect: Frame,
-        min_confirmed: Frame,
+        min_confirmed: Option<Frame>,
         requests: &mut Vec<GgrsRequest<T>>,
-    ) {
+    ) -> Result<(), GgrsError> {
         let current_frame = self.sync_layer.current_frame();
-        // determine the frame to load
-        let frame_to_load = if self.sparse_saving {
-            // if sparse saving is turned on, we will rollback to the last saved state
-            self.sync_layer.last_saved_frame()
-        } else {
-            // otherwise, we will rollback to first_incorrect
-            first_incorrect
+        // an unfinished rollback that starts over still has to return to the frame it started from
+        let continued_from = self.resim_target.take();
+        let target_frame = continued_from.unwrap_or(current_frame);
+        let Some(frame_to_load) = self.frame_to_load(first_incorrect) else {
+            return Err(GgrsError::StateUnavailable {
+                frame: first_incorrect,
+                current_frame,
+            });
         };
 
         // we should always load a frame that is before or exactly the first incorrect frame
         assert!(frame_to_load <= first_incorrect);
-        let count = current_frame - frame_to_load;
+        let count = target_frame - frame_to_load;
+        self.rollback_stats.rollbacks += 1;
+        self.rollback_stats.frames_resimulated += count as usize;
 
         // request to load that frame
-        requests.push(self.sync_layer.load_frame(frame_to_load));
+        let load_request = self.sync_layer.load_frame(frame_to_load)?;
+        if continued_from.is_none() && self.rollback_requests {
+            requests.push(GgrsRequest::BeginRollback {
+                from_frame: target_frame,
+                to_frame: frame_to_load,
+            });
+        }
+        requests.push(load_request);
 
         // we are now at the desired frame
         assert_eq!(self.sync_layer.current_frame(), frame_to_load);
+        self.report_rollback_causes(target_frame, frame_to_load);
         self.sync_layer.reset_prediction();
 
-        // step forward to the previous current state, but with updated inputs
-        for i in 0..count {
-            let inputs = self
-                .sync_layer
-                .synchronized_inputs(&self.local_connect_status);
+        self.resimulate(target_frame, min_confirmed, true, requests);
+        Ok(())
+    }
+
+    /// Reports every reason for the rollback from `from_frame` to `to_frame` with its own [`GgrsEvent::Rollback`].
+    /// Must be called before the predictions are reset.
+    fn report_rollback_causes(&mut self, from_frame: Frame, to_frame: Frame) {
+        let late_packets = self
+            .sync_layer
+            .mispredictions()
+            .map(|(handle, first_incorrect)| RollbackCause::LatePacket {
+                handle,
+                frames_late: (from_frame - first_incorrect) as usize,
+            });
+        for cause in late_packets.chain(self.rollback_causes.drain(..)) {
+            if let RollbackCause::LatePacket { .. } = cause {
+                self.rollback_stats.late_packets += 1;
+            }
+            if self.rollback_events {
+                self.event_queue.push_back(GgrsEvent::Rollback {
+                    from_frame,
+                    to_frame,
+                    cause,
+                });
+            }
+        }
+    }
+
+    /// Steps forward towards `target_frame` with the most up-to-date inputs, but at most as many frames as the resimulation budget
+    /// allows. Ends the rollback once `target_frame` is reached, otherwise the next calls to advance_frame() continue from here.
+    fn resimulate(
+        &mut self,
+        target_frame: Frame,
+        min_confirmed: Option<Frame>,
+        just_loaded: bool,
+        requests: &mut Vec<GgrsRequest<T>>,
+    ) {
+        let budget = self.max_resim_frames.unwrap_or(usize::MAX);
+        let mut i = 0;
+        while self.sync_layer.current_frame() < target_frame && i < budget {
+            let inputs = self.synchronized_inputs();
 
             // decide whether to request a state save
             if self.sparse_saving {
                 // with sparse saving, we only save exactly the min_confirmed frame
-                if self.sync_layer.current_frame() == min_confirmed {
+                if Some(self.sync_layer.current_frame()) == min_confirmed {
                     requests.push(self.sync_layer.save_current_state());
                 }
             } else {
-                // without sparse saving, we save every state except the very first (just loaded that))
-                if i > 0 {
+                // without sparse saving, we save every state on the save interval except the very first (just loaded that))
+                if (i > 0 || !just_loaded) && self.sync_layer.is_save_frame() {
                     requests.push(self.sync_layer.save_current_state());
                 }
             }
@@ -670,38 +2815,136 @@ impl<T: Config> P2PSession<T> {
             // advance the frame
             self.sync_layer.advance_frame();
             requests.push(GgrsRequest::AdvanceFrame { inputs });
+            i += 1;
+        }
+
+        if self.sync_layer.current_frame() == target_frame {
+            if self.rollback_requests {
+                requests.push(GgrsRequest::EndRollback);
+            }
+            self.resim_target = None;
+        } else {
+            self.resim_target = Some(target_frame);
+        }
+    }
+
+    /// Determines the frame to roll back to in order to correct the given first incorrect frame, or `None` if no state has been saved yet.
+    fn frame_to_load(&self, first_incorrect: Frame) -> Option<Frame> {
+        if self.sparse_saving {
+            // if sparse saving is turned on, we will rollback to the last saved state
+            self.sync_layer.last_saved_frame()
+        } else {
+            // otherwise, we will rollback to the latest saved frame up to first_incorrect
+            Some(self.sync_layer.save_frame_at_or_before(first_incorrect))
         }
-        // after all this, we should have arrived at the same frame where we started
-        assert_eq!(self.sync_layer.current_frame(), current_frame);
     }
 
-    /// For each spectator, send all confirmed input up until the minimum confirmed frame.
-    fn send_confirmed_inputs_to_spectators(&mut self, confirmed_frame: Frame) {
+    /// For each spectator, send all confirmed input up until the minimum confirmed frame that is older than the broadcast delay
+    /// of the spectator. Inputs are only sent once a batch of `spectator_input_interval` frames is complete.
+    fn send_confirmed_inputs_to_spectators(&mut self, confirmed_frame: Option<Frame>) {
         if self.num_spectators() == 0 {
             return;
         }
 
-        while self.next_spectator_frame <= confirmed_frame {
-            let mut inputs = self
-                .sync_layer
-                .confirmed_inputs(self.next_spectator_frame, &self.local_connect_status);
-            assert_eq!(inputs.len(), self.num_players);
-
-            let mut input_map = HashMap::new();
-            for (handle, input) in inputs.iter_mut().enumerate() {
-                assert!(input.frame == NULL_FRAME || input.frame == self.next_spectator_frame);
-                input_map.insert(handle, *input);
+        let now = clock::now(&*self.clock);
+        while Some(self.next_spectator_frame) <= confirmed_frame {
+            let mut input_map = self.broadcast_buffers.pop().unwrap_or_default();
+            for handle in 0..self.num_players {
+                let input = self.sync_layer.confirmed_input(
+                    self.next_spectator_frame,
+                    handle,
+                    &self.local_connect_status,
+                );
+                assert!(input
+                    .frame
+                    .is_none_or(|frame| frame == self.next_spectator_frame));
+                input_map.insert(handle, input);
             }
 
-            // send it to all spectators
-            for endpoint in self.player_reg.spectators.values_mut() {
+            // keep it until all spectators have been sent it
+            self.spectator_history.push_back(BroadcastInputs {
+                frame: self.next_spectator_frame,
+                confirmed_at: now,
+                inputs: input_map,
+            });
+
+            // onto the next frame
+            self.next_spectator_frame += 1;
+        }
+
+        for (addr, endpoint) in self.player_reg.spectators.iter_mut() {
+            let delay = self
+                .player_reg
+                .spectator_delays
+                .get(addr)
+                .copied()
+                .unwrap_or_default();
+            let next_frame = self
+                .spectator_frames
+                .entry(addr.clone())
+                .or_insert(self.sync_layer.start_frame());
+
+            // queue all inputs that are old enough for this spectator
+            let mut batch_complete = false;
+            let first_frame = self
+                .spectator_history
+                .front()
+                .map_or(0, |inputs| inputs.frame);
+            let skip = (*next_frame - first_frame).max(0) as usize;
+            for inputs in self.spectator_history.iter().skip(skip) {
+                if now.saturating_duration_since(inputs.confirmed_at) < delay {
+                    break;
+                }
                 if endpoint.is_running() {
-                    endpoint.send_input(&input_map, &self.local_connect_status);
+                    endpoint.push_input(&inputs.inputs, &HashMap::new());
                 }
+                *next_frame = inputs.frame + 1;
+                batch_complete |= *next_frame % self.spectator_input_interval as i32 == 0;
             }
 
-            // onto the next frame
-            self.next_spectator_frame += 1;
+            // send all pending inputs at once
+            if batch_complete && endpoint.is_running() {
+                endpoint.send_pending_output(&self.local_connect_status);
+            }
+        }
+
+        // throw away the inputs every spectator has been sent
+        let sent_frame = self
+            .spectator_frames
+            .values()
+            .copied()
+            .min()
+            .unwrap_or(self.next_spectator_frame);
+        while self
+            .spectator_history
+            .front()
+            .is_some_and(|inputs| inputs.frame < sent_frame)
+        {
+            if let Some(BroadcastInputs { mut inputs, .. }) = self.spectator_history.pop_front() {
+                inputs.clear();
+                self.broadcast_buffers.push(inputs);
+            }
+        }
+    }
+
+    /// Appends the confirmed inputs of all players up until the minimum confirmed frame to the replay, if recording is enabled.
+    fn record_confirmed_inputs(&mut self, confirmed_frame: Option<Frame>) {
+        let Some(replay) = self.replay.as_mut() else {
+            return;
+        };
+
+        let mut frame = self.replay_chunk_start + replay.num_frames() as Frame;
+        while Some(frame) <= confirmed_frame {
+            let inputs: Vec<_> = self
+                .sync_layer
+                .confirmed_inputs(frame, &self.local_connect_status)
+                .iter()
+                .map(|input| input.input)
+                .collect();
+            replay
+                .push_frame(&inputs)
+                .expect("Confirmed inputs always contain all players");
+            frame += 1;
         }
     }
 
@@ -709,31 +2952,28 @@ impl<T: Config> P2PSession<T> {
     /// Disconnect players that are disconnected for other players and update the frame they disconnected
     fn update_player_disconnects(&mut self) {
         for handle in 0..self.num_players {
-            let mut queue_connected = true;
-            let mut queue_min_confirmed = i32::MAX;
-
             // check all player connection status for every remote player
-            for endpoint in self.player_reg.remotes.values() {
-                if !endpoint.is_running() {
-                    continue;
-                }
-                let con_status = endpoint.peer_connect_status(handle);
-                let connected = !con_status.disconnected;
-                let min_confirmed = con_status.last_frame;
-
-                queue_connected = queue_connected && connected;
-                queue_min_confirmed = std::cmp::min(queue_min_confirmed, min_confirmed);
-            }
+            let peer_statuses = || {
+                self.player_reg
+                    .remotes
+                    .values()
+                    .filter(|endpoint| endpoint.is_running())
+                    .map(|endpoint| endpoint.peer_connect_status(handle))
+            };
+            let queue_connected = peer_statuses().all(|con_status| !con_status.disconnected);
 
             // check our local info for that player
-            let local_connected = !self.local_connect_status[handle].disconnected;
-            let local_min_confirmed = self.local_connect_status[handle].last_frame;
-
-            if local_connected {
-                queue_min_confirmed = std::cmp::min(queue_min_confirmed, local_min_confirmed);
-            }
+            let local_status = self.local_connect_status[handle];
+            let local_connected = !local_status.disconnected;
+            let local_min_confirmed = local_status.last_frame;
 
             if !queue_connected {
+                let queue_min_confirmed = peer_statuses()
+                    .chain(local_connected.then_some(local_status))
+                    .map(|con_status| con_status.last_frame)
+                    .min()
+                    .expect("A remote client reported the player as disconnected");
+
                 // check to see if the remote disconnect is further back than we have disconnected that player.
                 // If so, we need to re-adjust. This can happen when we e.g. detect our own disconnect at frame n
                 // and later receive a disconnect notification for frame n-1.
@@ -765,52 +3005,84 @@ impl<T: Config> P2PSession<T> {
 
     fn check_wait_recommendation(&mut self) {
         self.frames_ahead = self.max_frame_advantage();
+        // a negative frame advantage never recommends waiting
+        let Ok(skip_frames) = u32::try_from(self.frames_ahead) else {
+            return;
+        };
         if self.sync_layer.current_frame() > self.next_recommended_sleep
-            && self.frames_ahead >= MIN_RECOMMENDATION as i32
+            && skip_frames >= MIN_RECOMMENDATION
         {
             self.next_recommended_sleep = self.sync_layer.current_frame() + RECOMMENDATION_INTERVAL;
-            self.event_queue.push_back(GgrsEvent::WaitRecommendation {
-                skip_frames: self
-                    .frames_ahead
-                    .try_into()
-                    .expect("frames ahead is negative despite being positive."),
-            });
+            self.frame_pacer.skip_frames(skip_frames);
+            self.event_queue
+                .push_back(GgrsEvent::WaitRecommendation { skip_frames });
+        }
+    }
+
+    /// Gather the smoothed fractional frame advantage from each connected remote player endpoint and return the maximum, if any remote player is connected.
+    fn max_fractional_frame_advantage(&self) -> Option<f32> {
+        self.player_reg
+            .remotes
+            .values()
+            .filter(|endpoint| {
+                endpoint
+                    .handles()
+                    .iter()
+                    .any(|&handle| !self.local_connect_status[handle].disconnected)
+            })
+            .map(|endpoint| endpoint.fractional_frame_advantage())
+            .reduce(f32::max)
+    }
+
+    fn check_time_sync_suggestion(&mut self) {
+        if self.sync_layer.current_frame() < self.next_time_sync_suggestion {
+            return;
+        }
+        if let Some(frame_advantage) = self.max_fractional_frame_advantage() {
+            self.next_time_sync_suggestion =
+                self.sync_layer.current_frame() + TIME_SYNC_SUGGESTION_INTERVAL;
+            // if we are ahead, slow down just enough to make up for it over the correction window, and vice versa
+            let speed_factor = (1.0 - frame_advantage as f64 / TIME_SYNC_CORRECTION_FRAMES)
+                .clamp(1.0 - MAX_SPEED_ADJUSTMENT, 1.0 + MAX_SPEED_ADJUSTMENT);
+            self.event_queue
+                .push_back(GgrsEvent::TimeSyncSuggestion { speed_factor });
         }
     }
 
     fn check_last_saved_state(
         &mut self,
-        last_saved: Frame,
-        confirmed_frame: Frame,
+        confirmed_frame: Option<Frame>,
         requests: &mut Vec<GgrsRequest<T>>,
-    ) {
+    ) -> Result<(), GgrsError> {
+        // the start frame is always saved before this is checked, without any saved state there is nothing to lose
+        let Some(last_saved) = self.sync_layer.last_saved_frame() else {
+            return Ok(());
+        };
         // in sparse saving mode, we need to make sure not to lose the last saved frame
         if self.sync_layer.current_frame() - last_saved >= self.max_prediction as i32 {
             // check if the current frame is confirmed, otherwise we need to roll back
-            if confirmed_frame >= self.sync_layer.current_frame() {
+            if confirmed_frame >= Some(self.sync_layer.current_frame()) {
                 // the current frame is confirmed, save it
                 requests.push(self.sync_layer.save_current_state());
             } else {
                 // roll back to the last saved state, resimulate and save on the way
-                self.adjust_gamestate(last_saved, confirmed_frame, requests);
+                self.adjust_gamestate(last_saved, confirmed_frame, requests)?;
             }
 
             // after all this, we should have saved the confirmed state
-            assert!(
-                confirmed_frame == NULL_FRAME
-                    || self.sync_layer.last_saved_frame()
-                        == std::cmp::min(confirmed_frame, self.sync_layer.current_frame())
-            );
+            assert!(confirmed_frame.is_none_or(|confirmed_frame| {
+                self.sync_layer.last_saved_frame()
+                    == Some(std::cmp::min(
+                        confirmed_frame,
+                        self.sync_layer.current_frame(),
+                    ))
+            }));
         }
+        Ok(())
     }
 
     /// Handle events received from the UDP endpoints. Most events are being forwarded to the user for notification, but some require action.
-    fn handle_event(
-        &mut self,
-        event: Event<T>,
-        player_handles: Vec<PlayerHandle>,
-        addr: T::Address,
-    ) {
+    fn handle_event(&mut self, event: Event<T>, addr: T::Address) {
         match event {
             // forward to user
             Event::Synchronizing { total, count } => {
@@ -829,18 +3101,100 @@ impl<T: Config> P2PSession<T> {
                 self.event_queue
                     .push_back(GgrsEvent::NetworkResumed { addr });
             }
+            // forward to user
+            Event::FpsMismatch {
+                declared_fps,
+                observed_fps,
+            } => {
+                self.event_queue.push_back(GgrsEvent::FpsMismatch {
+                    addr,
+                    declared_fps,
+                    observed_fps,
+                });
+            }
+            // forward to user
+            Event::ProtocolMismatch { remote_version } => {
+                self.event_queue.push_back(GgrsEvent::ProtocolMismatch {
+                    addr,
+                    remote_version,
+                });
+            }
+            Event::HandshakeRejected => {
+                self.event_queue
+                    .push_back(GgrsEvent::HandshakeRejected { addr });
+            }
+            Event::HandshakeMismatch { mismatch } => {
+                self.event_queue
+                    .push_back(GgrsEvent::HandshakeMismatch { addr, mismatch });
+            }
+            // forward to user, who decides whether to retry
+            Event::SynchronizationFailed { attempts } => {
+                // the address may no longer belong to any player, e.g. after its players were removed
+                if let Some(&handle) = self.player_reg.handles_by_address(addr).first() {
+                    self.event_queue
+                        .push_back(GgrsEvent::SynchronizationFailed { handle, attempts });
+                }
+            }
+            // forward to user
+            Event::ConnectionStateChanged { state } => {
+                self.event_queue
+                    .push_back(GgrsEvent::ConnectionStateChanged { addr, state });
+            }
+            // hand out to the user with the next call to received_messages()
+            Event::MessageReceived { data } => {
+                self.received_messages.push_back((addr, data));
+                if self.received_messages.len() > MAX_EVENT_QUEUE_SIZE {
+                    self.received_messages.pop_front();
+                }
+            }
             // check if all remotes are synced, then forward to user
             Event::Synchronized => {
                 self.check_initial_sync();
-                self.event_queue.push_back(GgrsEvent::Synchronized { addr });
+                self.event_queue
+                    .push_back(GgrsEvent::Synchronized { addr: addr.clone() });
+                if let Some(endpoint) = self.player_reg.remotes.get(&addr) {
+                    for (handle, player_id) in endpoint.remote_player_ids() {
+                        self.event_queue.push_back(GgrsEvent::PlayerIdentified {
+                            addr: addr.clone(),
+                            handle: *handle,
+                            player_id: *player_id,
+                        });
+                    }
+                }
+            }
+            // the remote client asks us to take over one of its players
+            Event::HandOverRequested { handle, frame } => {
+                self.accept_hand_over(handle, frame, addr);
+            }
+            // the endpoint already decodes the inputs for the new players
+            Event::HandleChanged {
+                handle,
+                frame,
+                added,
+            } => {
+                if added {
+                    self.on_player_added(handle, frame, addr);
+                } else {
+                    self.on_player_removed(handle, frame, addr);
+                }
             }
             // disconnect the player, then forward to user
             Event::Disconnected => {
-                for handle in player_handles {
+                // players that have not moved yet stay with us
+                self.hand_overs.retain(|handle, hand_over| match hand_over {
+                    HandOver::Requested { addr: to, .. }
+                    | HandOver::Giving { addr: to, .. }
+                    | HandOver::Reclaiming { addr: to, .. } => *to != addr,
+                    HandOver::Taking { addr: from, .. } => {
+                        *from != addr
+                            || self.player_reg.handles.get(handle) == Some(&PlayerType::Local)
+                    }
+                });
+                for handle in self.player_reg.handles_by_address(addr.clone()) {
                     let last_frame = if handle < self.num_players as PlayerHandle {
                         self.local_connect_status[handle].last_frame
                     } else {
-                        NULL_FRAME // spectator
+                        None // spectator
                     };
 
                     self.disconnect_player_at_frame(handle, last_frame);
@@ -849,27 +3203,85 @@ impl<T: Config> P2PSession<T> {
                 self.event_queue.push_back(GgrsEvent::Disconnected { addr });
             }
             // add the input and all associated information
-            Event::Input { input, player } => {
+            Event::Input {
+                input,
+                player,
+                metadata,
+            } => {
                 // input only comes from remote players, not spectators
                 assert!(player < self.num_players as PlayerHandle);
+                // linked players are not sent on the wire, they take the input of their primary player
+                for linked in self.player_reg.linked_handles(player) {
+                    if !self.local_connect_status[linked].disconnected {
+                        self.add_remote_input(linked, input, metadata.clone());
+                    }
+                }
                 if !self.local_connect_status[player].disconnected {
-                    // check if the input comes in the correct sequence
-                    let current_remote_frame = self.local_connect_status[player].last_frame;
-                    assert!(
-                        current_remote_frame == NULL_FRAME
-                            || current_remote_frame + 1 == input.frame
-                    );
-                    // update our info
-                    self.local_connect_status[player].last_frame = input.frame;
-                    // add the remote input
-                    self.sync_layer.add_remote_input(player, input);
+                    self.add_remote_input(player, input, metadata);
                 }
             }
         }
 
         // check event queue size and discard oldest events if too big
+        self.trim_event_queue();
+    }
+
+    /// Discards the oldest events while the event queue is too big.
+    fn trim_event_queue(&mut self) {
         while self.event_queue.len() > MAX_EVENT_QUEUE_SIZE {
             self.event_queue.pop_front();
+            self.events_discarded += 1;
+        }
+    }
+
+    /// Adds the input of a remote player. An input that arrives before the previous input of the player is held back until
+    /// that one has arrived: after a hand-over, the inputs of the new client can overtake the last ones of the previous client.
+    fn add_remote_input(
+        &mut self,
+        player: PlayerHandle,
+        input: PlayerInput<T::Input>,
+        metadata: Vec<u8>,
+    ) {
+        let frame = input
+            .frame
+            .expect("P2PSession::add_remote_input(): The remote input does not belong to a frame");
+        let current_remote_frame = self.local_connect_status[player].last_frame;
+        if current_remote_frame.is_some_and(|current| frame > current + 1) {
+            // a remote client never sends inputs the input queue cannot hold, so these are dropped instead of piling up
+            let in_window =
+                frame < self.sync_layer.first_kept_frame() + self.input_queue_length as Frame;
+            let buffered = self
+                .early_inputs
+                .iter()
+                .any(|(handle, early, _)| *handle == player && early.frame == input.frame);
+            if in_window && !buffered {
+                self.early_inputs.push((player, input, metadata));
+            }
+            return;
+        }
+        // check if the input comes in the correct sequence
+        assert!(current_remote_frame.is_none_or(|current| current + 1 == frame));
+        // update our info
+        self.local_connect_status[player].last_frame = Some(frame);
+        // add the remote input
+        self.sync_layer.add_remote_input(player, input);
+        if !metadata.is_empty() {
+            Self::store_input_metadata(
+                &mut self.input_metadata[player],
+                self.input_metadata_history_size,
+                frame,
+                metadata,
+            );
+        }
+
+        // the next input of the player might have arrived early
+        if let Some(index) = self
+            .early_inputs
+            .iter()
+            .position(|(handle, input, _)| *handle == player && input.frame == Some(frame + 1))
+        {
+            let (player, input, metadata) = self.early_inputs.swap_remove(index);
+            self.add_remote_input(player, input, metadata);
         }
     }
 
@@ -880,7 +3292,11 @@ impl<T: Config> P2PSession<T> {
                     let mut checked_frames = Vec::new();
 
                     for (&remote_frame, &remote_checksum) in &remote.pending_checksums {
-                        if remote_frame >= self.sync_layer.last_confirmed_frame() {
+                        if self
+                            .sync_layer
+                            .last_confirmed_frame()
+                            .is_none_or(|confirmed| remote_frame >= confirmed)
+                        {
                             // we're still waiting for inputs for this frame
                             continue;
                         }
@@ -888,6 +3304,7 @@ impl<T: Config> P2PSession<T> {
                             self.local_checksum_history.get(&remote_frame)
                         {
                             if local_checksum != remote_checksum {
+                                self.desyncs += 1;
                                 self.event_queue.push_back(GgrsEvent::DesyncDetected {
                                     frame: remote_frame,
                                     local_checksum,
@@ -911,32 +3328,47 @@ impl<T: Config> P2PSession<T> {
     fn check_checksum_send_interval(&mut self) {
         match self.desync_detection {
             DesyncDetection::On { interval } => {
-                let frame_to_send = if self.last_sent_checksum_frame == NULL_FRAME {
-                    interval as i32
-                } else {
-                    self.last_sent_checksum_frame + interval as i32
+                let frame_to_send = match self.last_sent_checksum_frame {
+                    Some(last_sent) => last_sent + interval as i32,
+                    None => {
+                        let start_frame = self.sync_layer.start_frame();
+                        start_frame - start_frame % interval as i32 + interval as i32
+                    }
                 };
 
-                if frame_to_send <= self.sync_layer.last_confirmed_frame()
-                    && frame_to_send < self.sync_layer.last_saved_frame()
-                {
-                    let cell = self
+                if self
+                    .sync_layer
+                    .last_confirmed_frame()
+                    .is_some_and(|confirmed| frame_to_send <= confirmed)
+                    && self
                         .sync_layer
-                        .saved_state_by_frame(frame_to_send)
-                        .unwrap_or_else(|| panic!("cell not found!: frame {frame_to_send}"));
+                        .last_saved_frame()
+                        .is_some_and(|last_saved| frame_to_send < last_saved)
+                {
+                    // the state might not have been saved (e.g. with sparse saving), in that case we skip this checksum
+                    let Some(cell) = self.sync_layer.saved_state_by_frame(frame_to_send) else {
+                        self.last_sent_checksum_frame = Some(frame_to_send);
+                        return;
+                    };
 
                     if let Some(checksum) = cell.checksum() {
-                        for remote in self.player_reg.remotes.values_mut() {
-                            remote.send_checksum_report(frame_to_send, checksum);
+                        // spectators can check their own states against ours
+                        for endpoint in self
+                            .player_reg
+                            .remotes
+                            .values_mut()
+                            .chain(self.player_reg.spectators.values_mut())
+                        {
+                            endpoint.send_checksum_report(frame_to_send, checksum);
                         }
-                        self.last_sent_checksum_frame = frame_to_send;
+                        self.last_sent_checksum_frame = Some(frame_to_send);
                         // collect locally for later comparison
                         self.local_checksum_history.insert(frame_to_send, checksum);
                     }
 
-                    if self.local_checksum_history.len() > MAX_CHECKSUM_HISTORY_SIZE {
+                    if self.local_checksum_history.len() > self.checksum_history_size {
                         let oldest_frame_to_keep = frame_to_send
-                            - (MAX_CHECKSUM_HISTORY_SIZE as i32 - 1) * interval as i32;
+                            - (self.checksum_history_size as i32 - 1) * interval as i32;
                         self.local_checksum_history
                             .retain(|&frame, _| frame >= oldest_frame_to_keep);
                     }
@@ -946,3 +3378,49 @@ impl<T: Config> P2PSession<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod p2p_session_tests {
+    use super::*;
+    use crate::{Message, SessionBuilder};
+
+    type Cfg = crate::FixedInputConfig<1, u8>;
+
+    struct NullSocket;
+
+    impl NonBlockingSocket<SocketAddr> for NullSocket {
+        fn send_to(&mut self, _msg: &Message, _addr: &SocketAddr) {}
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn test_early_inputs_are_bounded() -> Result<(), GgrsError> {
+        let remote_addr = SocketAddr::from(([127, 0, 0, 1], 7777));
+        let mut sess = SessionBuilder::<Cfg>::new()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(remote_addr), 1)?
+            .start_p2p_session(NullSocket)?;
+        let input = |frame: Frame| PlayerInput::new(frame, [frame as u8]);
+
+        sess.add_remote_input(1, input(0), Vec::new());
+        // a remote client floods far-future frames, every one of them twice
+        for frame in 2..10_000 {
+            sess.add_remote_input(1, input(frame), Vec::new());
+            sess.add_remote_input(1, input(frame), Vec::new());
+        }
+        let queue_length = sess.input_queue_length as Frame;
+        assert_eq!(sess.early_inputs.len(), queue_length as usize - 2);
+
+        // once the missing input arrives, the queue takes all buffered inputs
+        sess.add_remote_input(1, input(1), Vec::new());
+        assert!(sess.early_inputs.is_empty());
+        assert_eq!(
+            sess.local_connect_status[1].last_frame,
+            Some(queue_length - 1)
+        );
+        Ok(())
+    }
+}
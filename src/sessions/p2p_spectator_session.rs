@@ -1,20 +1,22 @@
 use std::collections::{vec_deque::Drain, VecDeque};
+use std::net::SocketAddr;
 
 use crate::{
+    checksum::AutoChecksums,
     frame_info::PlayerInput,
     network::{
         messages::ConnectionStatus,
         protocol::{Event, UdpProtocol},
+        session_socket::{ReceiveBacklog, SessionSocket},
     },
-    sessions::builder::MAX_EVENT_QUEUE_SIZE,
-    Config, Frame, GgrsError, GgrsEvent, GgrsRequest, InputStatus, NetworkStats, NonBlockingSocket,
-    SessionState, NULL_FRAME,
+    sessions::{builder::MAX_EVENT_QUEUE_SIZE, playback::PlaybackSpeed},
+    Config, ConnectionState, DesyncDetection, Frame, GameStateCell, GgrsError, GgrsEvent,
+    GgrsRequest, InputStatus, MemoryReport, NetworkSample, NetworkStats, NonBlockingSocket,
+    PlayerHandle, PlayerId, PollSummary, SessionState, MAX_FRAME,
 };
 
 // The amount of frames the spectator advances in a single step if not too far behind
 const NORMAL_SPEED: usize = 1;
-// The amount of inputs a spectator can buffer (a second worth of inputs)
-pub(crate) const SPECTATOR_BUFFER_SIZE: usize = 60;
 
 /// [`SpectatorSession`] provides all functionality to connect to a remote host in a peer-to-peer fashion.
 /// The host will broadcast all confirmed inputs to this session.
@@ -27,13 +29,40 @@ where
     num_players: usize,
     inputs: Vec<Vec<PlayerInput<T::Input>>>,
     host_connect_status: Vec<ConnectionStatus>,
-    socket: Box<dyn NonBlockingSocket<T::Address>>,
+    socket: SessionSocket<T>,
+    /// Received packets left for the next poll, since the receive budget was used up.
+    receive_backlog: ReceiveBacklog<T>,
     host: UdpProtocol<T>,
     event_queue: VecDeque<GgrsEvent<T>>,
+    /// The number of events discarded because the event queue was full.
+    events_discarded: usize,
     current_frame: Frame,
     last_recv_frame: Frame,
     max_frames_behind: usize,
     catchup_speed: usize,
+    playback: PlaybackSpeed,
+    /// With desync detection, the session checks its own states against the checksums the host sends.
+    desync_detection: DesyncDetection,
+    /// The cells of the states to check, oldest first, until the checksums of both sides are known.
+    checksum_cells: VecDeque<(Frame, GameStateCell<T::State>)>,
+    /// The number of states to check kept at most.
+    checksum_history_size: usize,
+    /// If set, the checksums the user did not provide are computed from the bytes of the saved states.
+    auto_checksums: Option<AutoChecksums<T::State>>,
+}
+
+/// The settings a [`SpectatorSession`] is created with, built by the [`SessionBuilder`].
+///
+/// [`SessionBuilder`]: crate::SessionBuilder
+pub(crate) struct SpectatorSettings<T: Config> {
+    pub num_players: usize,
+    pub max_frames_behind: usize,
+    pub catchup_speed: usize,
+    pub buffer_size: usize,
+    pub desync_detection: DesyncDetection,
+    pub checksum_history_size: usize,
+    pub auto_checksums: Option<AutoChecksums<T::State>>,
+    pub start_frame: Frame,
 }
 
 impl<T: Config> SpectatorSession<T> {
@@ -41,12 +70,22 @@ impl<T: Config> SpectatorSession<T> {
     /// The session will receive inputs from all players from the given host directly.
     /// The session will use the provided socket.
     pub(crate) fn new(
-        num_players: usize,
-        socket: Box<dyn NonBlockingSocket<T::Address>>,
+        settings: SpectatorSettings<T>,
+        socket: SessionSocket<T>,
         host: UdpProtocol<T>,
-        max_frames_behind: usize,
-        catchup_speed: usize,
+        receive_backlog: ReceiveBacklog<T>,
     ) -> Self {
+        let SpectatorSettings {
+            num_players,
+            max_frames_behind,
+            catchup_speed,
+            buffer_size,
+            desync_detection,
+            checksum_history_size,
+            auto_checksums,
+            start_frame,
+        } = settings;
+
         // host connection status
         let mut host_connect_status = Vec::new();
         for _ in 0..num_players {
@@ -56,18 +95,23 @@ impl<T: Config> SpectatorSession<T> {
         Self {
             state: SessionState::Synchronizing,
             num_players,
-            inputs: vec![
-                vec![PlayerInput::blank_input(NULL_FRAME); num_players];
-                SPECTATOR_BUFFER_SIZE
-            ],
+            inputs: vec![vec![PlayerInput::blank_input(None); num_players]; buffer_size],
             host_connect_status,
             socket,
+            receive_backlog,
             host,
             event_queue: VecDeque::new(),
-            current_frame: NULL_FRAME,
-            last_recv_frame: NULL_FRAME,
+            events_discarded: 0,
+            // the frame before the start frame has been simulated already
+            current_frame: start_frame - 1,
+            last_recv_frame: start_frame - 1,
             max_frames_behind,
             catchup_speed,
+            playback: PlaybackSpeed::default(),
+            desync_detection,
+            checksum_cells: VecDeque::new(),
+            checksum_history_size,
+            auto_checksums,
         }
     }
 
@@ -76,11 +120,47 @@ impl<T: Config> SpectatorSession<T> {
         self.state
     }
 
-    /// Returns the number of frames behind the host
+    /// Closes the session. It asks the host to disconnect it, stops exchanging packets with the host and cannot advance any further.
+    /// Sends a [`GgrsEvent::SessionStateChanged`] unless the session was already closed.
+    pub fn close(&mut self) {
+        if self.state == SessionState::Closed {
+            return;
+        }
+        self.host.close();
+        self.host.send_all_messages(&mut self.socket);
+        self.state = SessionState::Closed;
+        self.event_queue.push_back(GgrsEvent::SessionStateChanged {
+            state: SessionState::Closed,
+        });
+    }
+
+    /// Returns the current frame of a session.
+    pub fn current_frame(&self) -> Frame {
+        self.current_frame
+    }
+
+    /// Returns the number of frames behind the host, 0 until the first input of the host arrived.
     pub fn frames_behind_host(&self) -> usize {
-        let diff = self.last_recv_frame - self.current_frame;
-        assert!(diff >= 0);
-        diff as usize
+        usize::try_from(self.last_recv_frame - self.current_frame).unwrap_or(0)
+    }
+
+    /// Sets the speed at which the spectator watches the match, for example 0.25 for slow motion or 2.0 to fast forward.
+    /// Each call to [`advance_frame()`] then requests this many times the frames it would request at normal speed, carrying
+    /// fractions over to the next calls. The session never advances past the last input received from the host.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the speed is not a finite number greater than 0.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn set_playback_speed(&mut self, speed: f32) -> Result<(), GgrsError> {
+        self.playback.set_speed(speed)
+    }
+
+    /// Returns the playback speed set with [`set_playback_speed()`], 1.0 by default.
+    ///
+    /// [`set_playback_speed()`]: Self#method.set_playback_speed
+    pub fn playback_speed(&self) -> f32 {
+        self.playback.speed()
     }
 
     /// Used to fetch some statistics about the quality of the network connection.
@@ -89,11 +169,64 @@ impl<T: Config> SpectatorSession<T> {
     ///
     /// [`NotSynchronized`]: GgrsError::NotSynchronized
     pub fn network_stats(&self) -> Result<NetworkStats, GgrsError> {
-        self.host.network_stats()
+        let mut stats = self.host.network_stats()?;
+        stats.receive_backlog = self
+            .receive_backlog
+            .count(|addr| self.host.is_handling_message(addr));
+        Ok(stats)
+    }
+
+    /// Returns the [`ConnectionState`] of the connection to the host.
+    pub fn connection_status(&self) -> ConnectionState {
+        self.host.connection_state()
+    }
+
+    /// Returns the persistent [`PlayerId`] the host sent for the given player. The host only knows the ids of its own local players.
+    pub fn player_id(&self, player_handle: PlayerHandle) -> Option<PlayerId> {
+        self.host
+            .remote_player_ids()
+            .iter()
+            .find(|(handle, _)| *handle == player_handle)
+            .map(|(_, id)| *id)
+    }
+
+    /// Returns the address the host presented as reachable at from outside, as set with [`SessionBuilder::with_external_addr()`]
+    /// on its side. It is known once the session has synchronized with the host.
+    ///
+    /// [`SessionBuilder::with_external_addr()`]: crate::SessionBuilder#method.with_external_addr
+    pub fn remote_external_addr(&self) -> Option<SocketAddr> {
+        self.host.remote_external_addr()
+    }
+
+    /// Returns the local address the socket of the session is bound to, if the socket knows it. See
+    /// [`NonBlockingSocket::local_addr()`].
+    pub fn local_addr(&self) -> Option<T::Address> {
+        self.socket.local_addr()
+    }
+
+    /// Returns the recent history of network samples for the connection to the host, oldest first.
+    /// A sample is recorded for every frame of inputs received from the host.
+    pub fn network_history(&self) -> &[NetworkSample] {
+        self.host.network_history()
+    }
+
+    /// Returns how many bytes the session currently holds for buffered host inputs and pending packets.
+    /// See [`MemoryReport`] for what is counted.
+    pub fn memory_usage(&self) -> MemoryReport {
+        MemoryReport {
+            input_queues: self
+                .inputs
+                .iter()
+                .map(|frame| frame.capacity() * std::mem::size_of::<PlayerInput<T::Input>>())
+                .sum::<usize>()
+                + self.inputs.capacity() * std::mem::size_of::<Vec<PlayerInput<T::Input>>>(),
+            pending_packets: self.host.pending_memory() + self.receive_backlog.memory(),
+            ..MemoryReport::default()
+        }
     }
 
     /// Returns all events that happened since last queried for events. If the number of stored events exceeds `MAX_EVENT_QUEUE_SIZE`, the oldest events will be discarded.
-    pub fn events(&mut self) -> Drain<GgrsEvent<T>> {
+    pub fn events(&mut self) -> Drain<'_, GgrsEvent<T>> {
         self.event_queue.drain(..)
     }
 
@@ -101,11 +234,14 @@ impl<T: Config> SpectatorSession<T> {
     /// Returns an order-sensitive [`Vec<GgrsRequest>`]. You should fulfill all requests in the exact order they are provided.
     /// Failure to do so will cause panics later.
     /// # Errors
-    /// - Returns [`NotSynchronized`] if the session is not yet ready to accept input.
-    /// In this case, you either need to start the session or wait for synchronization between clients.
+    /// - Returns [`NotSynchronized`] if the session is not yet ready to accept input or has been closed.
+    ///   In this case, you either need to start the session or wait for synchronization between clients.
+    /// - Returns [`FrameLimitReached`] if the session reached [`MAX_FRAME`].
     ///
     /// [`Vec<GgrsRequest>`]: GgrsRequest
     /// [`NotSynchronized`]: GgrsError::NotSynchronized
+    /// [`FrameLimitReached`]: GgrsError::FrameLimitReached
+    /// [`MAX_FRAME`]: crate::MAX_FRAME
     pub fn advance_frame(&mut self) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
         // receive info from host, trigger events and send messages
         self.poll_remote_clients();
@@ -113,20 +249,43 @@ impl<T: Config> SpectatorSession<T> {
         if self.state != SessionState::Running {
             return Err(GgrsError::NotSynchronized);
         }
+        // like the host, the spectator stops before simulating the frame MAX_FRAME
+        if self.current_frame + 1 >= MAX_FRAME {
+            return Err(GgrsError::FrameLimitReached);
+        }
 
         let mut requests = Vec::new();
 
-        let frames_to_advance = if self.frames_behind_host() > self.max_frames_behind {
+        let frames_at_normal_speed = if self.frames_behind_host() > self.max_frames_behind {
             self.catchup_speed
         } else {
             NORMAL_SPEED
         };
-
+        // catching up stops at the frame limit as well
+        let frames_to_advance = self
+            .playback
+            .frames_to_advance(frames_at_normal_speed, self.frames_behind_host())
+            .min((MAX_FRAME - 1 - self.current_frame) as usize);
         for _ in 0..frames_to_advance {
             // get inputs for the next frame
             let frame_to_grab = self.current_frame + 1;
             let synced_inputs = self.inputs_at_frame(frame_to_grab)?;
 
+            // the host sends checksums for every interval, so we ask for the state of the same frames
+            if let DesyncDetection::On { interval } = self.desync_detection {
+                if frame_to_grab > 0 && frame_to_grab % interval as Frame == 0 {
+                    let cell = GameStateCell::default();
+                    self.checksum_cells.push_back((frame_to_grab, cell.clone()));
+                    if self.checksum_cells.len() > self.checksum_history_size {
+                        self.checksum_cells.pop_front();
+                    }
+                    requests.push(GgrsRequest::SaveGameState {
+                        cell,
+                        frame: frame_to_grab,
+                    });
+                }
+            }
+
             requests.push(GgrsRequest::AdvanceFrame {
                 inputs: synced_inputs,
             });
@@ -140,13 +299,26 @@ impl<T: Config> SpectatorSession<T> {
 
     /// Receive UDP packages, distribute them to corresponding UDP endpoints, handle all occurring events and send all outgoing UDP packages.
     /// Should be called periodically by your application to give GGRS a chance to do internal work like packet transmissions.
-    pub fn poll_remote_clients(&mut self) {
-        // Get all udp packets and distribute them to associated endpoints.
+    /// Returns a [`PollSummary`] of the received packets and inputs and the queued events.
+    pub fn poll_remote_clients(&mut self) -> PollSummary {
+        let mut summary = PollSummary::default();
+        if self.state == SessionState::Closed {
+            return summary;
+        }
+        // events discarded from the full queue have been queued all the same
+        let queued_events = self.event_queue.len() + self.events_discarded;
+
+        // Get all udp packets and distribute them to associated endpoints, as far as the receive budget allows.
         // The endpoints will handle their packets, which will trigger both events and UPD replies.
-        for (from, msg) in &self.socket.receive_all_messages() {
-            if self.host.is_handling_message(from) {
-                self.host.handle_message(msg);
-            }
+        let host = &mut self.host;
+        let received =
+            self.receive_backlog
+                .receive(&mut self.socket, host.millis_since_epoch(), |addr| {
+                    host.accept_packet(addr)
+                });
+        for (from, msg, received_at) in received {
+            summary.packets_received += 1;
+            host.handle_message(&from, &msg, received_at);
         }
 
         // run host poll and get events. This will trigger additional UDP packets to be sent.
@@ -158,11 +330,33 @@ impl<T: Config> SpectatorSession<T> {
 
         // handle all events locally
         for (event, addr) in events.drain(..) {
+            if let Event::Input { player, .. } = event {
+                *summary.inputs_received.entry(player).or_default() += 1;
+            }
             self.handle_event(event, addr);
         }
 
+        // compare the states saved since the last call against the checksums of the host
+        self.compare_checksums_against_host();
+
         // send out all pending UDP messages
         self.host.send_all_messages(&mut self.socket);
+
+        // report the errors of the socket, including those of the sends since the last poll
+        for kind in self.socket.take_errors() {
+            self.event_queue.push_back(GgrsEvent::SocketError { kind });
+        }
+        self.trim_event_queue();
+
+        summary.events_queued = self.event_queue.len() + self.events_discarded - queued_events;
+        summary
+    }
+
+    /// Replaces the socket of the session, e.g. after the operating system closed it while the device slept and the session
+    /// reported [`GgrsEvent::SocketError`]. The connection to the host is kept. The host only accepts messages from the
+    /// address it knows, so bind the new socket to the same port.
+    pub fn replace_socket(&mut self, socket: impl NonBlockingSocket<T::Address> + 'static) {
+        self.socket.replace(Box::new(socket));
     }
 
     /// Returns the number of players this session was constructed with.
@@ -170,19 +364,48 @@ impl<T: Config> SpectatorSession<T> {
         self.num_players
     }
 
+    /// Compares the checksums of the saved states against those the host sent for the same frames and reports any mismatch.
+    fn compare_checksums_against_host(&mut self) {
+        let addr = self.host.peer_addr();
+        let host_checksums = &mut self.host.pending_checksums;
+        let event_queue = &mut self.event_queue;
+        let auto_checksums = &self.auto_checksums;
+        self.checksum_cells.retain(|(frame, cell)| {
+            if let Some(auto_checksums) = auto_checksums {
+                auto_checksums.fill(cell);
+            }
+            // the state might not have been saved yet, or the checksum of the host has not arrived yet
+            let Some(local_checksum) = cell.checksum() else {
+                return true;
+            };
+            let Some(remote_checksum) = host_checksums.remove(frame) else {
+                return true;
+            };
+            if local_checksum != remote_checksum {
+                event_queue.push_back(GgrsEvent::DesyncDetected {
+                    frame: *frame,
+                    local_checksum,
+                    remote_checksum,
+                    addr: addr.clone(),
+                });
+            }
+            false
+        });
+    }
+
     fn inputs_at_frame(
         &self,
         frame_to_grab: Frame,
     ) -> Result<Vec<(T::Input, InputStatus)>, GgrsError> {
-        let player_inputs = &self.inputs[frame_to_grab as usize % SPECTATOR_BUFFER_SIZE];
+        let player_inputs = &self.inputs[frame_to_grab as usize % self.inputs.len()];
 
         // We haven't received the input from the host yet. Wait.
-        if player_inputs[0].frame < frame_to_grab {
+        if player_inputs[0].frame < Some(frame_to_grab) {
             return Err(GgrsError::PredictionThreshold);
         }
 
-        // The host is more than [`SPECTATOR_BUFFER_SIZE`] frames ahead of the spectator. The input we need is gone forever.
-        if player_inputs[0].frame > frame_to_grab {
+        // The host is more than the buffer size in frames ahead of the spectator. The input we need is gone forever.
+        if player_inputs[0].frame > Some(frame_to_grab) {
             return Err(GgrsError::SpectatorTooFarBehind);
         }
 
@@ -191,7 +414,7 @@ impl<T: Config> SpectatorSession<T> {
             .enumerate()
             .map(|(handle, player_input)| {
                 if self.host_connect_status[handle].disconnected
-                    && self.host_connect_status[handle].last_frame < frame_to_grab
+                    && self.host_connect_status[handle].last_frame < Some(frame_to_grab)
                 {
                     (player_input.input, InputStatus::Disconnected)
                 } else {
@@ -220,24 +443,75 @@ impl<T: Config> SpectatorSession<T> {
                 self.event_queue
                     .push_back(GgrsEvent::NetworkResumed { addr });
             }
+            // forward to user
+            Event::FpsMismatch {
+                declared_fps,
+                observed_fps,
+            } => {
+                self.event_queue.push_back(GgrsEvent::FpsMismatch {
+                    addr,
+                    declared_fps,
+                    observed_fps,
+                });
+            }
+            // forward to user
+            Event::ProtocolMismatch { remote_version } => {
+                self.event_queue.push_back(GgrsEvent::ProtocolMismatch {
+                    addr,
+                    remote_version,
+                });
+            }
+            Event::HandshakeRejected => {
+                self.event_queue
+                    .push_back(GgrsEvent::HandshakeRejected { addr });
+            }
+            Event::HandshakeMismatch { mismatch } => {
+                self.event_queue
+                    .push_back(GgrsEvent::HandshakeMismatch { addr, mismatch });
+            }
+            // forward to user
+            Event::ConnectionStateChanged { state } => {
+                self.event_queue
+                    .push_back(GgrsEvent::ConnectionStateChanged { addr, state });
+            }
+            // only players exchange out-of-band messages and hand over players, and only their synchronization times out
+            Event::MessageReceived { .. }
+            | Event::HandOverRequested { .. }
+            | Event::HandleChanged { .. }
+            | Event::SynchronizationFailed { .. } => (),
             // synced with the host, then forward to user
             Event::Synchronized => {
                 self.state = SessionState::Running;
-                self.event_queue.push_back(GgrsEvent::Synchronized { addr });
+                self.event_queue
+                    .push_back(GgrsEvent::Synchronized { addr: addr.clone() });
+                self.event_queue.push_back(GgrsEvent::SessionStateChanged {
+                    state: SessionState::Running,
+                });
+                for (handle, player_id) in self.host.remote_player_ids() {
+                    self.event_queue.push_back(GgrsEvent::PlayerIdentified {
+                        addr: addr.clone(),
+                        handle: *handle,
+                        player_id: *player_id,
+                    });
+                }
             }
             // disconnect the player, then forward to user
             Event::Disconnected => {
                 self.event_queue.push_back(GgrsEvent::Disconnected { addr });
             }
             // add the input and all associated information
-            Event::Input { input, player } => {
+            Event::Input { input, player, .. } => {
+                // drop inputs we cannot place, like a stray input from before the start frame
+                let Some(frame) = input.frame.filter(|&frame| frame >= self.last_recv_frame) else {
+                    return;
+                };
                 // save the input
-                self.inputs[input.frame as usize % SPECTATOR_BUFFER_SIZE][player] = input;
-                assert!(input.frame >= self.last_recv_frame);
-                self.last_recv_frame = input.frame;
+                let buffer_size = self.inputs.len();
+                self.inputs[frame as usize % buffer_size][player] = input;
+                self.last_recv_frame = frame;
 
                 // update the frame advantage
-                self.host.update_local_frame_advantage(input.frame);
+                self.host.update_local_frame_advantage(frame);
 
                 // update the host connection status
                 for i in 0..self.num_players {
@@ -247,8 +521,67 @@ impl<T: Config> SpectatorSession<T> {
         }
 
         // check event queue size and discard oldest events if too big
+        self.trim_event_queue();
+    }
+
+    /// Discards the oldest events while the event queue is too big.
+    fn trim_event_queue(&mut self) {
         while self.event_queue.len() > MAX_EVENT_QUEUE_SIZE {
             self.event_queue.pop_front();
+            self.events_discarded += 1;
+        }
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod spectator_session_tests {
+    use super::*;
+    use crate::{Message, SessionBuilder};
+
+    type Cfg = crate::FixedInputConfig<1, u8>;
+
+    struct NullSocket;
+
+    impl NonBlockingSocket<SocketAddr> for NullSocket {
+        fn send_to(&mut self, _msg: &Message, _addr: &SocketAddr) {}
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+            Vec::new()
         }
     }
+
+    #[test]
+    fn test_drop_inputs_it_cannot_place() {
+        let host_addr = SocketAddr::from(([127, 0, 0, 1], 7777));
+        let mut sess = SessionBuilder::<Cfg>::new()
+            .with_num_players(2)
+            .with_start_frame(10)
+            .unwrap()
+            .start_spectator_session(host_addr, NullSocket);
+
+        let input = |frame| Event::Input {
+            input: PlayerInput::new(frame, [frame as u8 + 1]),
+            player: 0,
+            metadata: Vec::new(),
+        };
+        sess.handle_event(input(10), host_addr);
+        // a stray input from before the start frame and an input without a frame are dropped
+        sess.handle_event(input(0), host_addr);
+        sess.handle_event(
+            Event::Input {
+                input: PlayerInput::blank_input(None),
+                player: 1,
+                metadata: Vec::new(),
+            },
+            host_addr,
+        );
+
+        assert_eq!(sess.last_recv_frame, 10);
+        assert_eq!(sess.inputs[10 % sess.inputs.len()][0].input, [11]);
+        assert_eq!(sess.inputs[0][0].input, [0]);
+    }
 }
@@ -0,0 +1,78 @@
+use crate::GgrsError;
+
+/// Scales the number of frames a session advances per call to `advance_frame()`. Fractional frames carry over to the
+/// next call, so a speed of 0.25 advances one frame every fourth call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PlaybackSpeed {
+    speed: f32,
+    progress: f32,
+}
+
+impl Default for PlaybackSpeed {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            progress: 0.0,
+        }
+    }
+}
+
+impl PlaybackSpeed {
+    pub(crate) fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub(crate) fn set_speed(&mut self, speed: f32) -> Result<(), GgrsError> {
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "The playback speed should be a finite number greater than 0.".to_owned(),
+            });
+        }
+        self.speed = speed;
+        self.progress = 0.0;
+        Ok(())
+    }
+
+    /// Returns how many of the `frames` the session would advance at normal speed it should advance now, but never more
+    /// than `available`, unless nothing is available at all. Progress beyond the available frames is dropped instead of
+    /// being caught up in a burst later.
+    pub(crate) fn frames_to_advance(&mut self, frames: usize, available: usize) -> usize {
+        self.progress += self.speed * frames as f32;
+        let wanted = self.progress.floor();
+        self.progress -= wanted;
+        let wanted = wanted as usize;
+        if wanted > available && available > 0 {
+            self.progress = 0.0;
+            return available;
+        }
+        wanted
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod playback_tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_to_advance() {
+        let mut playback = PlaybackSpeed::default();
+        assert_eq!(playback.frames_to_advance(1, 10), 1);
+
+        playback.set_speed(0.25).unwrap();
+        let frames: Vec<_> = (0..8).map(|_| playback.frames_to_advance(1, 10)).collect();
+        assert_eq!(frames, vec![0, 0, 0, 1, 0, 0, 0, 1]);
+
+        playback.set_speed(4.0).unwrap();
+        assert_eq!(playback.frames_to_advance(1, 10), 4);
+        assert_eq!(playback.frames_to_advance(1, 2), 2);
+        // nothing available, the session reports the missing inputs
+        assert_eq!(playback.frames_to_advance(1, 0), 4);
+
+        assert!(playback.set_speed(0.0).is_err());
+        assert!(playback.set_speed(f32::NAN).is_err());
+    }
+}
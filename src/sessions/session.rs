@@ -0,0 +1,181 @@
+use crate::{
+    Config, Frame, GgrsError, GgrsEvent, GgrsRequest, LocalSession, MemoryReport, P2PSession,
+    PlayerHandle, PollSummary, SessionState, SpectatorSession, SyncTestSession,
+};
+
+/// A [`Session`] holds any of the session types, so you can store "the current session" in a single field and drive it with
+/// the same game loop, no matter if it is a [`P2PSession`], a [`SpectatorSession`], a [`SyncTestSession`] or a [`LocalSession`].
+/// The methods cover what all sessions have in common. For anything specific to one type, match on the variant.
+// there is usually a single session, so boxing the larger variants would only add an indirection
+#[allow(clippy::large_enum_variant)]
+pub enum Session<T>
+where
+    T: Config,
+{
+    /// A [`P2PSession`].
+    P2P(P2PSession<T>),
+    /// A [`SpectatorSession`].
+    Spectator(SpectatorSession<T>),
+    /// A [`SyncTestSession`].
+    SyncTest(SyncTestSession<T>),
+    /// A [`LocalSession`].
+    Local(LocalSession<T>),
+}
+
+impl<T: Config> Session<T> {
+    /// Registers local input for a player for the current frame, see [`P2PSession::add_local_input()`],
+    /// [`SyncTestSession::add_local_input()`] and [`LocalSession::add_local_input()`].
+    ///
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] when the given handle does not refer to a local player.
+    /// - Returns [`InvalidRequest`] for a [`SpectatorSession`], which has no local players, or for the playback of a [`Replay`].
+    /// - Returns [`InputAlreadyAdded`] if input has already been added for the player since the frame was last advanced and input coalescing is off.
+    ///
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`InputAlreadyAdded`]: GgrsError::InputAlreadyAdded
+    /// [`Replay`]: crate::Replay
+    pub fn add_local_input(
+        &mut self,
+        player_handle: PlayerHandle,
+        input: T::Input,
+    ) -> Result<(), GgrsError> {
+        match self {
+            Session::P2P(session) => session.add_local_input(player_handle, input),
+            Session::Spectator(_) => Err(GgrsError::InvalidRequest {
+                info: "A spectator session has no local players.".to_owned(),
+            }),
+            Session::SyncTest(session) => session.add_local_input(player_handle, input),
+            Session::Local(session) => session.add_local_input(player_handle, input),
+        }
+    }
+
+    /// Advances the session by a frame and returns the requests to fulfill, see [`P2PSession::advance_frame()`],
+    /// [`SpectatorSession::advance_frame()`], [`SyncTestSession::advance_frame()`] and [`LocalSession::advance_frame()`].
+    ///
+    /// # Errors
+    /// - Returns the errors of the `advance_frame()` method of the wrapped session.
+    pub fn advance_frame(&mut self) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        match self {
+            Session::P2P(session) => session.advance_frame(),
+            Session::Spectator(session) => session.advance_frame(),
+            Session::SyncTest(session) => session.advance_frame(),
+            Session::Local(session) => session.advance_frame(),
+        }
+    }
+
+    /// Asks `poll_input` for the input of every local player, then advances the session by a frame, see
+    /// [`P2PSession::advance_frame_with_input()`], [`SyncTestSession::advance_frame_with_input()`] and
+    /// [`LocalSession::advance_frame_with_input()`].
+    /// A [`SpectatorSession`] has no local players, so `poll_input` is never called.
+    ///
+    /// # Errors
+    /// - Returns the errors of the `advance_frame()` method of the wrapped session.
+    pub fn advance_frame_with_input(
+        &mut self,
+        poll_input: impl FnMut(PlayerHandle) -> T::Input,
+    ) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        match self {
+            Session::P2P(session) => session.advance_frame_with_input(poll_input),
+            Session::Spectator(session) => session.advance_frame(),
+            Session::SyncTest(session) => session.advance_frame_with_input(poll_input),
+            Session::Local(session) => session.advance_frame_with_input(poll_input),
+        }
+    }
+
+    /// Receives and sends packets and returns a [`PollSummary`] of what happened. Does nothing and returns an empty summary for a
+    /// [`SyncTestSession`] or a [`LocalSession`], which have no remote clients.
+    pub fn poll_remote_clients(&mut self) -> PollSummary {
+        match self {
+            Session::P2P(session) => session.poll_remote_clients(),
+            Session::Spectator(session) => session.poll_remote_clients(),
+            Session::SyncTest(_) | Session::Local(_) => PollSummary::default(),
+        }
+    }
+
+    /// Returns all events that happened since last queried for events. A [`SyncTestSession`] and a [`LocalSession`] have no events.
+    pub fn events(&mut self) -> Vec<GgrsEvent<T>> {
+        match self {
+            Session::P2P(session) => session.events().collect(),
+            Session::Spectator(session) => session.events().collect(),
+            Session::SyncTest(_) | Session::Local(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the current [`SessionState`]. A [`SyncTestSession`] and a [`LocalSession`] are always running.
+    pub fn current_state(&self) -> SessionState {
+        match self {
+            Session::P2P(session) => session.current_state(),
+            Session::Spectator(session) => session.current_state(),
+            Session::SyncTest(_) | Session::Local(_) => SessionState::Running,
+        }
+    }
+
+    /// Returns the current frame of the session.
+    pub fn current_frame(&self) -> Frame {
+        match self {
+            Session::P2P(session) => session.current_frame(),
+            Session::Spectator(session) => session.current_frame(),
+            Session::SyncTest(session) => session.current_frame(),
+            Session::Local(session) => session.current_frame(),
+        }
+    }
+
+    /// Returns the number of players of the session.
+    pub fn num_players(&self) -> usize {
+        match self {
+            Session::P2P(session) => session.num_players(),
+            Session::Spectator(session) => session.num_players(),
+            Session::SyncTest(session) => session.num_players(),
+            Session::Local(session) => session.num_players(),
+        }
+    }
+
+    /// Returns the handles of the players you need to add local input for every frame. All players of a [`SyncTestSession`]
+    /// are local, unless it plays back a [`Replay`], as are all players of a [`LocalSession`]. A [`SpectatorSession`] has no local players.
+    ///
+    /// [`Replay`]: crate::Replay
+    pub fn local_player_handles(&self) -> Vec<PlayerHandle> {
+        match self {
+            Session::P2P(session) => session.local_player_handles(),
+            Session::Spectator(_) => Vec::new(),
+            Session::SyncTest(session) if session.is_replay() => Vec::new(),
+            Session::SyncTest(session) => (0..session.num_players()).collect(),
+            Session::Local(session) => session.local_player_handles(),
+        }
+    }
+
+    /// Returns how many bytes the session currently holds, see [`MemoryReport`].
+    pub fn memory_usage(&self) -> MemoryReport {
+        match self {
+            Session::P2P(session) => session.memory_usage(),
+            Session::Spectator(session) => session.memory_usage(),
+            Session::SyncTest(session) => session.memory_usage(),
+            Session::Local(session) => session.memory_usage(),
+        }
+    }
+}
+
+impl<T: Config> From<P2PSession<T>> for Session<T> {
+    fn from(session: P2PSession<T>) -> Self {
+        Session::P2P(session)
+    }
+}
+
+impl<T: Config> From<SpectatorSession<T>> for Session<T> {
+    fn from(session: SpectatorSession<T>) -> Self {
+        Session::Spectator(session)
+    }
+}
+
+impl<T: Config> From<SyncTestSession<T>> for Session<T> {
+    fn from(session: SyncTestSession<T>) -> Self {
+        Session::SyncTest(session)
+    }
+}
+
+impl<T: Config> From<LocalSession<T>> for Session<T> {
+    fn from(session: LocalSession<T>) -> Self {
+        Session::Local(session)
+    }
+}
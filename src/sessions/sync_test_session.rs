@@ -1,10 +1,55 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use crate::checksum::AutoChecksums;
 use crate::error::GgrsError;
 use crate::frame_info::PlayerInput;
+use crate::input_queue::DEFAULT_INPUT_QUEUE_LENGTH;
 use crate::network::messages::ConnectionStatus;
-use crate::sync_layer::SyncLayer;
-use crate::{Config, Frame, GgrsRequest, PlayerHandle};
+use crate::replay::Replay;
+use crate::sessions::playback::PlaybackSpeed;
+use crate::state_diff::StateDiffing;
+use crate::sync_layer::{GameStateCell, SyncLayer};
+use crate::{CoalesceFn, Config, Frame, GgrsRequest, MemoryReport, PlayerHandle};
+
+/// An [`InputScript`] provides the inputs of a player of a [`SyncTestSession`] that stands in for a remote player, see
+/// [`SyncTestSession::set_input_script()`]. It is implemented for all closures taking the frame and returning the input.
+///
+/// [`SyncTestSession::set_input_script()`]: SyncTestSession#method.set_input_script
+#[cfg(feature = "sync-send")]
+pub trait InputScript<I>: Send + Sync {
+    /// Returns the input of the player for the given frame, or `None` if the player disconnects at that frame.
+    fn input(&mut self, frame: Frame) -> Option<I>;
+}
+
+/// An [`InputScript`] provides the inputs of a player of a [`SyncTestSession`] that stands in for a remote player, see
+/// [`SyncTestSession::set_input_script()`]. It is implemented for all closures taking the frame and returning the input.
+///
+/// [`SyncTestSession::set_input_script()`]: SyncTestSession#method.set_input_script
+#[cfg(not(feature = "sync-send"))]
+pub trait InputScript<I> {
+    /// Returns the input of the player for the given frame, or `None` if the player disconnects at that frame.
+    fn input(&mut self, frame: Frame) -> Option<I>;
+}
+
+#[cfg(feature = "sync-send")]
+impl<I, F> InputScript<I> for F
+where
+    F: FnMut(Frame) -> Option<I> + Send + Sync,
+{
+    fn input(&mut self, frame: Frame) -> Option<I> {
+        self(frame)
+    }
+}
+
+#[cfg(not(feature = "sync-send"))]
+impl<I, F> InputScript<I> for F
+where
+    F: FnMut(Frame) -> Option<I>,
+{
+    fn input(&mut self, frame: Frame) -> Option<I> {
+        self(frame)
+    }
+}
 
 /// During a [`SyncTestSession`], GGRS will simulate a rollback every frame and resimulate the last n states, where n is the given check distance.
 /// The resimulated checksums will be compared with the original checksums and report if there was a mismatch.
@@ -19,24 +64,67 @@ where
     dummy_connect_status: Vec<ConnectionStatus>,
     checksum_history: HashMap<Frame, Option<u128>>,
     local_inputs: HashMap<PlayerHandle, PlayerInput<T::Input>>,
+    /// The players input has been added for since the last call to advance_frame, to catch inputs that are added twice.
+    added_local_inputs: HashSet<PlayerHandle>,
+    /// If set, local inputs added for the same frame are merged instead of overwritten.
+    input_coalescing: Option<CoalesceFn<T::Input>>,
+    /// If set, the inputs of all players are taken from this replay instead of being added by the user.
+    replay: Option<Replay<T>>,
+    playback: PlaybackSpeed,
+    /// The players whose inputs are taken from a script instead of being added by the user.
+    input_scripts: HashMap<PlayerHandle, Box<dyn InputScript<T::Input>>>,
+    /// If enabled, the requests of every rollback are enclosed by `BeginRollback` and `EndRollback`.
+    rollback_requests: bool,
+}
+
+/// The settings a [`SyncTestSession`] is created with, built by the [`SessionBuilder`].
+///
+/// [`SessionBuilder`]: crate::SessionBuilder
+pub(crate) struct SyncTestSettings<T: Config> {
+    pub num_players: usize,
+    pub max_prediction: usize,
+    pub check_distance: usize,
+    pub input_delay: usize,
+    pub input_queue_length: usize,
+    pub input_history: usize,
+    pub state_diffing: Option<StateDiffing<T::State>>,
+    pub auto_checksums: Option<AutoChecksums<T::State>>,
+    pub input_coalescing: Option<CoalesceFn<T::Input>>,
+    pub rollback_requests: bool,
 }
 
 impl<T: Config> SyncTestSession<T> {
-    pub(crate) fn new(
-        num_players: usize,
-        max_prediction: usize,
-        check_distance: usize,
-        input_delay: usize,
-    ) -> Self {
+    pub(crate) fn new(settings: SyncTestSettings<T>) -> Self {
+        let SyncTestSettings {
+            num_players,
+            max_prediction,
+            check_distance,
+            input_delay,
+            input_queue_length,
+            input_history,
+            state_diffing,
+            auto_checksums,
+            input_coalescing,
+            rollback_requests,
+        } = settings;
+
         let mut dummy_connect_status = Vec::new();
         for _ in 0..num_players {
             dummy_connect_status.push(ConnectionStatus::default());
         }
 
         let mut sync_layer = SyncLayer::new(num_players, max_prediction);
+        sync_layer.set_input_queue_length(input_queue_length);
+        sync_layer.set_input_history(input_history);
         for i in 0..num_players {
             sync_layer.set_frame_delay(i, input_delay);
         }
+        if let Some(state_diffing) = state_diffing {
+            sync_layer.set_state_diffing(state_diffing);
+        }
+        if let Some(auto_checksums) = auto_checksums {
+            sync_layer.set_auto_checksums(auto_checksums);
+        }
 
         Self {
             num_players,
@@ -46,30 +134,172 @@ impl<T: Config> SyncTestSession<T> {
             dummy_connect_status,
             checksum_history: HashMap::new(),
             local_inputs: HashMap::new(),
+            added_local_inputs: HashSet::new(),
+            input_coalescing,
+            replay: None,
+            playback: PlaybackSpeed::default(),
+            input_scripts: HashMap::new(),
+            rollback_requests,
+        }
+    }
+
+    /// Creates a [`SyncTestSession`] that plays back the inputs of a recorded [`Replay`] instead of inputs added by the user.
+    /// Every call to [`advance_frame()`] advances by one frame of the replay with the same rollback checks as a regular sync test,
+    /// so you can reproduce the simulation of a real match locally and find the frame where your game stops being deterministic.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the replay does not contain any players.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn from_replay(replay: Replay<T>, check_distance: usize) -> Result<Self, GgrsError> {
+        if replay.num_players() == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "The replay does not contain any players.".to_owned(),
+            });
+        }
+        // the prediction window only has to cover the simulated rollbacks
+        let max_prediction = check_distance + 1;
+        let input_queue_length = DEFAULT_INPUT_QUEUE_LENGTH.max(2 * max_prediction + 2);
+        let mut session = Self::new(SyncTestSettings {
+            num_players: replay.num_players(),
+            max_prediction,
+            check_distance,
+            input_delay: 0,
+            input_queue_length,
+            input_history: 0,
+            state_diffing: None,
+            auto_checksums: None,
+            input_coalescing: None,
+            rollback_requests: false,
+        });
+        session.replay = Some(replay);
+        Ok(session)
+    }
+
+    /// Returns true if this session plays back a [`Replay`].
+    pub fn is_replay(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Returns true if this session plays back a [`Replay`] and has advanced past its last frame.
+    pub fn is_replay_finished(&self) -> bool {
+        self.replay
+            .as_ref()
+            .is_some_and(|replay| self.current_frame() as usize >= replay.num_frames())
+    }
+
+    /// Sets the speed at which a [`Replay`] is played back, for example 0.25 for slow motion or 4.0 to fast forward.
+    /// Each call to [`advance_frame()`] then advances by this many frames of the replay, carrying fractions over to the next calls,
+    /// so a call can also return no requests at all. Every frame still goes through the same rollback checks.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the session does not play back a [`Replay`] or the speed is not a finite number greater than 0.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn set_playback_speed(&mut self, speed: f32) -> Result<(), GgrsError> {
+        if self.replay.is_none() {
+            return Err(GgrsError::InvalidRequest {
+                info: "Only the playback of a replay can change its speed.".to_owned(),
+            });
+        }
+        self.playback.set_speed(speed)
+    }
+
+    /// Returns the playback speed set with [`set_playback_speed()`], 1.0 by default.
+    ///
+    /// [`set_playback_speed()`]: Self#method.set_playback_speed
+    pub fn playback_speed(&self) -> f32 {
+        self.playback.speed()
+    }
+
+    /// Lets the player stand in for a remote player: its inputs are taken from the script instead of being added with
+    /// [`add_local_input()`]. The script is asked for the input of every frame once, when the frame is first simulated, and the
+    /// rollbacks of the sync test replay the same input. If it returns `None`, the player disconnects at that frame: from then
+    /// on, its inputs are handed to you with [`InputStatus::Disconnected`], including in rollbacks to the frames after it. This
+    /// tests how your game handles the inputs and disconnects of other players without a network. Recorded inputs can be
+    /// scripted from a [`Replay`], e.g. `move |frame| replay.frame(frame).map(|inputs| inputs[handle])`.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] when the given handle is not valid (i.e. not between 0 and num_players).
+    /// - Returns [`InvalidRequest`] if the session plays back a [`Replay`].
+    ///
+    /// [`add_local_input()`]: Self#method.add_local_input
+    /// [`InputStatus::Disconnected`]: crate::InputStatus::Disconnected
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn set_input_script(
+        &mut self,
+        player_handle: PlayerHandle,
+        script: impl InputScript<T::Input> + 'static,
+    ) -> Result<(), GgrsError> {
+        if self.replay.is_some() {
+            return Err(GgrsError::InvalidRequest {
+                info: "The inputs of a replay session are taken from the replay.".to_owned(),
+            });
+        }
+        if player_handle >= self.num_players {
+            return Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            });
         }
+        self.input_scripts.insert(player_handle, Box::new(script));
+        Ok(())
     }
 
-    /// Registers local input for a player for the current frame. This should be successfully called for every local player before calling [`advance_frame()`].
-    /// If this is called multiple times for the same player before advancing the frame, older given inputs will be overwritten.
-    /// In a sync test, all players are considered to be local, so you need to add input for all of them.
+    /// Registers local input for a player for the current frame. This should be successfully called exactly once for every local player before calling [`advance_frame()`].
+    /// Adding input multiple times for the same player is only allowed if [`SessionBuilder::with_input_coalescing()`] merges them.
+    /// In a sync test, all players are considered to be local, so you need to add input for all of them, except the players
+    /// whose inputs are scripted with [`set_input_script()`].
     ///
     /// # Errors
-    /// - Returns [`InvalidRequest`] when the given handle is not valid (i.e. not between 0 and num_players).
+    /// - Returns [`InvalidPlayerHandle`] when the given handle is not valid (i.e. not between 0 and num_players).
+    /// - Returns [`InvalidRequest`] if the session plays back a [`Replay`] or the inputs of the player are scripted.
+    /// - Returns [`InputAlreadyAdded`] if input has already been added for the player since the last call to [`advance_frame()`] and input coalescing is off.
     ///
+    /// [`set_input_script()`]: Self#method.set_input_script
     /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`SessionBuilder::with_input_coalescing()`]: crate::SessionBuilder#method.with_input_coalescing
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
     /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`InputAlreadyAdded`]: GgrsError::InputAlreadyAdded
     pub fn add_local_input(
         &mut self,
         player_handle: PlayerHandle,
         input: T::Input,
     ) -> Result<(), GgrsError> {
+        if self.replay.is_some() {
+            return Err(GgrsError::InvalidRequest {
+                info: "The inputs of a replay session are taken from the replay.".to_owned(),
+            });
+        }
         if player_handle >= self.num_players {
+            return Err(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            });
+        }
+        if self.input_scripts.contains_key(&player_handle) {
             return Err(GgrsError::InvalidRequest {
-                info: "The player handle you provided is not valid.".to_owned(),
+                info: format!("The inputs of player {} are scripted.", player_handle),
+            });
+        }
+        if !self.added_local_inputs.insert(player_handle) && self.input_coalescing.is_none() {
+            return Err(GgrsError::InputAlreadyAdded {
+                handle: player_handle,
+                frame: self.sync_layer.current_frame(),
             });
         }
-        let player_input = PlayerInput::<T::Input>::new(self.sync_layer.current_frame(), input);
-        self.local_inputs.insert(player_handle, player_input);
+        match (
+            self.input_coalescing,
+            self.local_inputs.get_mut(&player_handle),
+        ) {
+            (Some(coalesce), Some(player_input)) => coalesce(&mut player_input.input, &input),
+            _ => {
+                let player_input =
+                    PlayerInput::<T::Input>::new(self.sync_layer.current_frame(), input);
+                self.local_inputs.insert(player_handle, player_input);
+            }
+        }
         Ok(())
     }
 
@@ -78,11 +308,96 @@ impl<T: Config> SyncTestSession<T> {
     /// You should fulfill all requests in the exact order they are provided. Failure to do so will cause panics later.
     ///
     /// # Errors
+    /// - Returns [`MissingInput`] if no input has been added for a player.
     /// - Returns [`MismatchedChecksum`] if checksums don't match after resimulation.
+    /// - Returns [`InvalidRequest`] if the session plays back a [`Replay`] that has no more frames.
+    /// - Returns [`FrameLimitReached`] if the session reached [`MAX_FRAME`].
     ///
     /// [`Vec<GgrsRequest>`]: GgrsRequest
+    /// [`MissingInput`]: GgrsError::MissingInput
     /// [`MismatchedChecksum`]: GgrsError::MismatchedChecksum
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`FrameLimitReached`]: GgrsError::FrameLimitReached
+    /// [`MAX_FRAME`]: crate::MAX_FRAME
     pub fn advance_frame(&mut self) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        // the requests of the previous call have been handled, so the saved states can be compacted
+        self.sync_layer.compact_saved_states();
+        // local input can be added again for the next call, even if this one fails
+        self.added_local_inputs.clear();
+
+        let Some(replay) = &self.replay else {
+            return self.advance_single_frame();
+        };
+        let remaining = replay
+            .num_frames()
+            .saturating_sub(self.current_frame() as usize);
+        let mut requests = Vec::new();
+        for _ in 0..self.playback.frames_to_advance(1, remaining) {
+            requests.extend(self.advance_single_frame()?);
+        }
+        Ok(requests)
+    }
+
+    /// Asks `poll_input` for the input of every player without an input script, then advances the frame like [`advance_frame()`], see
+    /// [`P2PSession::advance_frame_with_input()`]. When playing back a [`Replay`], the inputs are taken from the replay and `poll_input` is never called.
+    ///
+    /// # Errors
+    /// - Returns the errors of [`advance_frame()`].
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`P2PSession::advance_frame_with_input()`]: crate::P2PSession#method.advance_frame_with_input
+    pub fn advance_frame_with_input(
+        &mut self,
+        mut poll_input: impl FnMut(PlayerHandle) -> T::Input,
+    ) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        if self.replay.is_none() {
+            for handle in 0..self.num_players {
+                if !self.input_scripts.contains_key(&handle) {
+                    self.add_local_input(handle, poll_input(handle))?;
+                }
+            }
+        }
+        self.advance_frame()
+    }
+
+    fn advance_single_frame(&mut self) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        if self.sync_layer.frame_limit_reached() {
+            return Err(GgrsError::FrameLimitReached);
+        }
+
+        // when playing back a replay, take the inputs of all players from it
+        if let Some(replay) = &self.replay {
+            let current_frame = self.sync_layer.current_frame();
+            let inputs = replay
+                .frame(current_frame)
+                .ok_or_else(|| GgrsError::InvalidRequest {
+                    info: "The replay has no more frames.".to_owned(),
+                })?;
+            for (handle, input) in inputs.iter().enumerate() {
+                self.local_inputs
+                    .insert(handle, PlayerInput::new(current_frame, *input));
+            }
+        }
+
+        // take the inputs of scripted players that are still connected from their scripts
+        let current_frame = self.sync_layer.current_frame();
+        for (&handle, script) in self.input_scripts.iter_mut() {
+            let con_stat = &mut self.dummy_connect_status[handle];
+            if con_stat.disconnected || self.local_inputs.contains_key(&handle) {
+                continue;
+            }
+            match script.input(current_frame) {
+                Some(input) => {
+                    self.local_inputs
+                        .insert(handle, PlayerInput::new(current_frame, input));
+                }
+                None => {
+                    con_stat.disconnected = true;
+                    con_stat.last_frame = (current_frame > 0).then(|| current_frame - 1);
+                }
+            }
+        }
+
         let mut requests = Vec::new();
 
         // if we advanced far enough into the game do comparisons and rollbacks
@@ -90,8 +405,16 @@ impl<T: Config> SyncTestSession<T> {
         if self.check_distance > 0 && current_frame > self.check_distance as i32 {
             // compare checksums of older frames to our checksum history (where only the first version of any checksum is recorded)
             let oldest_frame_to_check = current_frame - self.check_distance as Frame;
-            let mismatched_frames: Vec<_> = (oldest_frame_to_check..=current_frame)
-                .filter(|frame_to_check| !self.checksums_consistent(*frame_to_check))
+            self.checksum_history
+                .retain(|&frame, _| frame >= oldest_frame_to_check);
+            let checksum_history = &mut self.checksum_history;
+            let mismatched_frames: Vec<_> = self
+                .sync_layer
+                .saved_states()
+                .filter_map(|cell| Some((cell.frame()?, cell)))
+                .filter(|&(frame, _)| frame >= oldest_frame_to_check)
+                .filter(|(frame, cell)| !Self::checksums_consistent(checksum_history, *frame, cell))
+                .map(|(frame, _)| frame)
                 .collect();
 
             if !mismatched_frames.is_empty() {
@@ -103,19 +426,22 @@ impl<T: Config> SyncTestSession<T> {
 
             // simulate rollbacks according to the check_distance
             let frame_to = self.sync_layer.current_frame() - self.check_distance as i32;
-            self.adjust_gamestate(frame_to, &mut requests);
+            self.adjust_gamestate(frame_to, &mut requests)?;
         }
 
-        // we require inputs for all players
-        if self.num_players != self.local_inputs.len() {
-            return Err(GgrsError::InvalidRequest {
-                info: "Missing local input while calling advance_frame().".to_owned(),
+        // we require inputs for all players that are still connected
+        if let Some(handle) = (0..self.num_players).find(|h| {
+            !self.local_inputs.contains_key(h) && !self.dummy_connect_status[*h].disconnected
+        }) {
+            return Err(GgrsError::MissingInput {
+                handle,
+                frame: self.sync_layer.current_frame(),
             });
         }
         // pass all inputs into the sync layer
         for (&handle, &input) in self.local_inputs.iter() {
             // send the input into the sync layer
-            self.sync_layer.add_local_input(handle, input)?;
+            self.sync_layer.add_local_input(handle, input.input)?;
         }
         // clear local inputs after using them
         self.local_inputs.clear();
@@ -138,22 +464,85 @@ impl<T: Config> SyncTestSession<T> {
         // since this is a sync test, we "cheat" by setting the last confirmed state to the (current state - check_distance), so the sync layer won't complain about missing
         // inputs from other players
         let safe_frame = self.sync_layer.current_frame() - self.check_distance as i32;
+        let safe_frame = (safe_frame >= self.sync_layer.start_frame()).then_some(safe_frame);
 
         self.sync_layer.set_last_confirmed_frame(safe_frame, false);
 
         // also, we update the dummy connect status to pretend that we received inputs from all players
         for con_stat in &mut self.dummy_connect_status {
-            con_stat.last_frame = self.sync_layer.current_frame();
+            if !con_stat.disconnected {
+                con_stat.last_frame = Some(self.sync_layer.current_frame());
+            }
         }
 
         Ok(requests)
     }
 
+    /// Returns how many bytes the session currently holds for saved states, input queues and the replay it plays back.
+    /// See [`MemoryReport`] for what is counted.
+    pub fn memory_usage(&self) -> MemoryReport {
+        MemoryReport {
+            saved_states: self.sync_layer.saved_states_memory(),
+            input_queues: self.sync_layer.input_queues_memory(),
+            pending_packets: 0,
+            replay: self
+                .replay
+                .as_ref()
+                .map_or(0, |replay| replay.memory_usage()),
+        }
+    }
+
+    /// Returns the confirmed inputs of all players for every frame from `frame` up to the last confirmed frame, ordered by frame and
+    /// then by player handle. Training modes can use them to rewind the game and let the player take over from there. Only the inputs
+    /// that rollbacks still need are kept by default, see [`SessionBuilder::with_input_history()`] to keep more.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the inputs of `frame` are no longer kept.
+    ///
+    /// [`SessionBuilder::with_input_history()`]: crate::SessionBuilder#method.with_input_history
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn inputs_since(&self, frame: Frame) -> Result<Vec<Vec<T::Input>>, GgrsError> {
+        self.sync_layer
+            .inputs_since(frame, &self.dummy_connect_status)
+    }
+
+    /// Returns a snapshot of the ring of saved states, with the frames, sizes and checksums of all slots but not the states
+    /// themselves. Requires the `debug-tools` feature. Query it once per frame to render the saved states and rollbacks in a debug overlay.
+    #[cfg(feature = "debug-tools")]
+    pub fn saved_states_snapshot(&self) -> crate::SavedStatesSnapshot {
+        self.sync_layer.saved_states_snapshot()
+    }
+
+    /// Returns a snapshot of the input queue of every player, ordered by player handle. Requires the `debug-tools` feature.
+    /// Query it once per frame to visualize how deep the session predicts and how often predictions turn out wrong.
+    #[cfg(feature = "debug-tools")]
+    pub fn input_queue_snapshots(&self) -> Vec<crate::InputQueueSnapshot> {
+        self.sync_layer.input_queue_snapshots()
+    }
+
+    /// Returns the current frame of a session.
+    pub fn current_frame(&self) -> Frame {
+        self.sync_layer.current_frame()
+    }
+
     /// Returns the number of players this session was constructed with.
     pub fn num_players(&self) -> usize {
         self.num_players
     }
 
+    /// Returns the input delay in frames of the given player.
+    /// # Errors
+    /// - Returns [`InvalidPlayerHandle`] when the given handle is not valid (i.e. not between 0 and num_players).
+    ///
+    /// [`InvalidPlayerHandle`]: GgrsError::InvalidPlayerHandle
+    pub fn input_delay(&self, player_handle: PlayerHandle) -> Result<usize, GgrsError> {
+        self.sync_layer
+            .frame_delay(player_handle)
+            .ok_or(GgrsError::InvalidPlayerHandle {
+                handle: player_handle,
+            })
+    }
+
     /// Returns the maximum prediction window of a session.
     pub fn max_prediction(&self) -> usize {
         self.max_prediction
@@ -165,31 +554,37 @@ impl<T: Config> SyncTestSession<T> {
     }
 
     /// Updates the `checksum_history` and checks if the checksum is identical if it already has been recorded once
-    fn checksums_consistent(&mut self, frame_to_check: Frame) -> bool {
-        // remove entries older than the `check_distance`
-        let oldest_allowed_frame = self.sync_layer.current_frame() - self.check_distance as i32;
-        self.checksum_history
-            .retain(|&k, _| k >= oldest_allowed_frame);
-
-        match self.sync_layer.saved_state_by_frame(frame_to_check) {
-            Some(latest_cell) => match self.checksum_history.get(&latest_cell.frame()) {
-                Some(&cs) => cs == latest_cell.checksum(),
-                None => {
-                    self.checksum_history
-                        .insert(latest_cell.frame(), latest_cell.checksum());
-                    true
-                }
-            },
-            None => true,
+    fn checksums_consistent(
+        checksum_history: &mut HashMap<Frame, Option<u128>>,
+        frame: Frame,
+        cell: &GameStateCell<T::State>,
+    ) -> bool {
+        match checksum_history.get(&frame) {
+            Some(&cs) => cs == cell.checksum(),
+            None => {
+                checksum_history.insert(frame, cell.checksum());
+                true
+            }
         }
     }
 
-    fn adjust_gamestate(&mut self, frame_to: Frame, requests: &mut Vec<GgrsRequest<T>>) {
+    fn adjust_gamestate(
+        &mut self,
+        frame_to: Frame,
+        requests: &mut Vec<GgrsRequest<T>>,
+    ) -> Result<(), GgrsError> {
         let start_frame = self.sync_layer.current_frame();
         let count = start_frame - frame_to;
 
         // rollback to the first incorrect state
-        requests.push(self.sync_layer.load_frame(frame_to));
+        let load_request = self.sync_layer.load_frame(frame_to)?;
+        if self.rollback_requests {
+            requests.push(GgrsRequest::BeginRollback {
+                from_frame: start_frame,
+                to_frame: frame_to,
+            });
+        }
+        requests.push(load_request);
         self.sync_layer.reset_prediction();
         assert_eq!(self.sync_layer.current_frame(), frame_to);
 
@@ -208,6 +603,10 @@ impl<T: Config> SyncTestSession<T> {
 
             requests.push(GgrsRequest::AdvanceFrame { inputs });
         }
+        if self.rollback_requests {
+            requests.push(GgrsRequest::EndRollback);
+        }
         assert_eq!(self.sync_layer.current_frame(), start_frame);
+        Ok(())
     }
 }
@@ -0,0 +1,333 @@
+use crate::sync_layer::GameStateCell;
+use crate::{Frame, StateBytes};
+
+/// A saved state, stored as the RLE encoded XOR of its bytes with the bytes of the next newer saved state. The newest saved state
+/// is kept in full by [`StateDiffing`] instead.
+#[derive(Debug, Clone)]
+pub(crate) struct StateDelta {
+    /// The frame of the newer state the delta is taken against, or `None` for the newest state.
+    base: Option<Frame>,
+    len: usize,
+    bytes: Vec<u8>,
+}
+
+/// Stores saved states as deltas to the next newer saved state instead of full copies, so only the newest state is kept in full.
+/// This trades the CPU time of converting the states to bytes and back for memory, which pays off for large states that only
+/// change a little from frame to frame. Since rollbacks mostly load recent states, restoring a state only walks back a few deltas.
+pub(crate) struct StateDiffing<S> {
+    to_bytes: fn(&S) -> Vec<u8>,
+    from_bytes: fn(&[u8]) -> S,
+    /// The frame and bytes of the newest compacted state.
+    head: Option<(Frame, Vec<u8>)>,
+}
+
+impl<S: StateBytes> StateDiffing<S> {
+    pub(crate) fn new() -> Self {
+        Self {
+            to_bytes: S::to_bytes,
+            from_bytes: S::from_bytes,
+            head: None,
+        }
+    }
+}
+
+impl<S> StateDiffing<S> {
+    /// Returns the bytes allocated for the newest state.
+    pub(crate) fn head_memory(&self) -> usize {
+        self.head.as_ref().map_or(0, |(_, bytes)| bytes.capacity())
+    }
+}
+
+impl StateDelta {
+    /// Returns the bytes allocated for the encoded delta.
+    pub(crate) fn memory(&self) -> usize {
+        self.bytes.capacity()
+    }
+}
+
+impl<S: Clone> StateDiffing<S> {
+    /// Replaces the full states in the given cells, ordered from oldest to newest frame, with deltas. Must only be called once
+    /// the user has handled all requests for these cells, as a cell does not contain a state the user could load anymore
+    /// afterwards. States are only ever saved after loading the state before them, so a full state is either new or the same
+    /// state a delta of an older state was taken against.
+    pub(crate) fn compact(&mut self, cells: impl Iterator<Item = GameStateCell<S>>) {
+        let cells: Vec<(Frame, GameStateCell<S>)> = cells
+            .filter_map(|cell| {
+                let frame = cell.map_state(|state| {
+                    (state.data.is_some() || state.delta.is_some())
+                        .then_some(state.frame)
+                        .flatten()
+                })?;
+                Some((frame, cell))
+            })
+            .collect();
+        let Some(oldest_full) = cells
+            .iter()
+            .position(|(_, cell)| cell.map_state(|state| state.data.is_some()))
+        else {
+            return;
+        };
+
+        // walk from the newest state down to the one below the oldest full state, keeping the frame and bytes of the newer
+        // neighbour. The deltas of older states stay valid, as the state they were taken against did not change.
+        let mut old_head = self.head.take();
+        let mut newer: Option<(Frame, Vec<u8>)> = None;
+        let mut newer_was_full = false;
+        for (i, (frame, cell)) in cells.iter().enumerate().rev() {
+            if i < oldest_full && !newer_was_full {
+                break;
+            }
+            let was_full = cell.map_state(|state| {
+                let (bytes, was_full) = match state.data.take() {
+                    Some(data) => ((self.to_bytes)(&data), true),
+                    None => {
+                        let delta = state.delta.take()?;
+                        let bytes = match &delta.base {
+                            None => old_head
+                                .take()
+                                .filter(|(head, _)| head == frame)
+                                .map(|(_, bytes)| bytes),
+                            Some(_) => decode(
+                                &delta,
+                                newer.as_ref().map(|(frame, bytes)| (*frame, &bytes[..])),
+                            ),
+                        };
+                        // a delta against an unchanged state is kept as it is
+                        if !newer_was_full && delta.base.is_some() {
+                            state.delta = Some(delta);
+                        }
+                        // a state that cannot be decoded is dropped, loading it fails later on
+                        let Some(bytes) = bytes else {
+                            state.delta = None;
+                            return None;
+                        };
+                        (bytes, false)
+                    }
+                };
+                if state.delta.is_none() {
+                    state.delta = Some(match &newer {
+                        Some((newer_frame, newer_bytes)) => StateDelta {
+                            base: Some(*newer_frame),
+                            len: bytes.len(),
+                            bytes: bitfield_rle::encode(xor(newer_bytes, &bytes)),
+                        },
+                        None => StateDelta {
+                            base: None,
+                            len: bytes.len(),
+                            bytes: Vec::new(),
+                        },
+                    });
+                }
+                if newer.is_none() {
+                    self.head = Some((*frame, bytes.clone()));
+                }
+                newer = Some((*frame, bytes));
+                Some(was_full)
+            });
+            if let Some(was_full) = was_full {
+                newer_was_full = was_full;
+            }
+        }
+    }
+
+    /// Restores the full state of a compacted cell, so the user can load it. `cell_at` looks up the saved state of a frame, so
+    /// the restore follows the deltas from the cell up to the newest state without walking any other cells. Returns `None` if
+    /// one of these deltas cannot be decoded.
+    pub(crate) fn restore(
+        &self,
+        cell: &GameStateCell<S>,
+        cell_at: impl Fn(Frame) -> Option<GameStateCell<S>>,
+    ) -> Option<()> {
+        if cell.map_state(|state| state.delta.is_none()) {
+            return Some(());
+        }
+
+        // collect the cells from the cell up to the first one with known bytes, a full state or the newest state
+        let mut chain = vec![cell.clone()];
+        let mut bytes = loop {
+            let last = chain.last().expect("No cell");
+            let base = last.map_state(|state| match (&state.data, &state.delta) {
+                (Some(data), _) => Ok(Some((self.to_bytes)(data))),
+                (
+                    None,
+                    Some(StateDelta {
+                        base: Some(base), ..
+                    }),
+                ) => Err(Some(*base)),
+                (None, Some(delta)) => Ok(self
+                    .head
+                    .as_ref()
+                    .filter(|(head, bytes)| state.frame == Some(*head) && bytes.len() == delta.len)
+                    .map(|(_, bytes)| bytes.clone())),
+                (None, None) => Err(None),
+            });
+            match base {
+                Ok(bytes) => break bytes?,
+                Err(base) => chain.push(cell_at(base?)?),
+            }
+        };
+
+        // decode the deltas back down to the cell
+        for (newer, older) in chain.iter().rev().zip(chain.iter().rev().skip(1)) {
+            let newer_frame = newer.frame()?;
+            bytes = older
+                .map_state(|state| decode(state.delta.as_ref()?, Some((newer_frame, &bytes))))?;
+        }
+        cell.map_state(|state| {
+            state.delta = None;
+            state.data = Some((self.from_bytes)(&bytes));
+        });
+        Some(())
+    }
+}
+
+impl<S> std::fmt::Debug for StateDiffing<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateDiffing")
+            .field("head", &self.head.as_ref().map(|(frame, _)| frame))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Decodes a delta, given the frame and bytes of the next newer state. Returns `None` if the delta was not taken against that
+/// state or is corrupt.
+fn decode(delta: &StateDelta, newer: Option<(Frame, &[u8])>) -> Option<Vec<u8>> {
+    let (_, newer_bytes) = newer.filter(|(frame, _)| delta.base == Some(*frame))?;
+    let diff = bitfield_rle::decode(&delta.bytes).ok()?;
+    (diff.len() == delta.len).then(|| xor(newer_bytes, &diff))
+}
+
+/// Returns the bytewise XOR of `data` with `base`, where `base` is padded with zeros or cut to the length of `data`.
+fn xor(base: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut result = data.to_vec();
+    for (byte, key) in result.iter_mut().zip(base) {
+        *byte ^= key;
+    }
+    result
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod state_diff_tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct BigState(Vec<u8>);
+
+    impl StateBytes for BigState {
+        fn to_bytes(&self) -> Vec<u8> {
+            self.0.clone()
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Self {
+            Self(bytes.to_vec())
+        }
+    }
+
+    fn state(frame: usize, version: u8) -> BigState {
+        let mut bytes = vec![7; 1000 + frame];
+        bytes[frame] = frame as u8 ^ version;
+        BigState(bytes)
+    }
+
+    #[test]
+    fn test_compact_and_restore() {
+        let mut diffing = StateDiffing::<BigState>::new();
+        let cells: Vec<GameStateCell<BigState>> =
+            (0..20).map(|_| GameStateCell::default()).collect();
+        let states: Vec<BigState> = (0..20).map(|frame| state(frame, 0)).collect();
+
+        for (frame, (cell, state)) in cells.iter().zip(states.iter()).enumerate() {
+            cell.save(frame as i32, Some(state.clone()), None);
+            diffing.compact(cells[..=frame].iter().cloned());
+            // compacted states can not be loaded anymore
+            assert_eq!(cell.load(), None);
+        }
+        // only the newest state is kept in full, the others are small deltas against their neighbour
+        assert_eq!(diffing.head.as_ref().map(|(frame, _)| *frame), Some(19));
+        for cell in cells[..19].iter() {
+            assert!(cell.map_state(|state| state.delta.as_ref().unwrap().memory()) < 100);
+        }
+
+        for (cell, state) in cells.iter().zip(states.iter()).rev() {
+            assert_eq!(
+                diffing.restore(cell, |frame| cells.get(frame as usize).cloned()),
+                Some(())
+            );
+            assert_eq!(cell.load().as_ref(), Some(state));
+        }
+    }
+
+    #[test]
+    fn test_restore_only_looks_up_newer_states() {
+        let mut diffing = StateDiffing::<BigState>::new();
+        let cells: Vec<GameStateCell<BigState>> =
+            (0..20).map(|_| GameStateCell::default()).collect();
+        for (frame, cell) in cells.iter().enumerate() {
+            cell.save(frame as i32, Some(state(frame, 0)), None);
+            diffing.compact(cells[..=frame].iter().cloned());
+        }
+
+        let lookups = std::cell::Cell::new(Vec::new());
+        let cell_at = |frame: Frame| {
+            let mut frames = lookups.take();
+            frames.push(frame);
+            lookups.set(frames);
+            cells.get(frame as usize).cloned()
+        };
+        assert_eq!(diffing.restore(&cells[17], cell_at), Some(()));
+        assert_eq!(lookups.take(), vec![18, 19]);
+        assert_eq!(cells[17].load(), Some(state(17, 0)));
+    }
+
+    #[test]
+    fn test_restore_after_rollback() {
+        let mut diffing = StateDiffing::<BigState>::new();
+        let cells: Vec<GameStateCell<BigState>> =
+            (0..10).map(|_| GameStateCell::default()).collect();
+        for (frame, cell) in cells.iter().enumerate() {
+            cell.save(frame as i32, Some(state(frame, 0)), None);
+            diffing.compact(cells[..=frame].iter().cloned());
+        }
+
+        // load frame 5 and simulate the frames after it again with other results
+        assert_eq!(
+            diffing.restore(&cells[5], |frame| cells.get(frame as usize).cloned()),
+            Some(())
+        );
+        for (frame, cell) in cells.iter().enumerate().skip(6) {
+            cell.save(frame as i32, Some(state(frame, 1)), None);
+        }
+        diffing.compact(cells.iter().cloned());
+
+        for (frame, cell) in cells.iter().enumerate().rev() {
+            assert_eq!(
+                diffing.restore(cell, |frame| cells.get(frame as usize).cloned()),
+                Some(())
+            );
+            let expected = state(frame, if frame > 5 { 1 } else { 0 });
+            assert_eq!(cell.load(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_restore_broken_chain() {
+        let mut diffing = StateDiffing::<BigState>::new();
+        let cells: Vec<GameStateCell<BigState>> =
+            (0..4).map(|_| GameStateCell::default()).collect();
+        for (frame, cell) in cells.iter().enumerate() {
+            cell.save(frame as i32, Some(state(frame, 0)), None);
+            diffing.compact(cells[..=frame].iter().cloned());
+        }
+
+        // the state the delta of frame 1 was taken against is gone
+        cells[2].save(2, None, None);
+        assert_eq!(
+            diffing.restore(&cells[1], |frame| cells.get(frame as usize).cloned()),
+            None
+        );
+    }
+}
@@ -2,11 +2,13 @@ use bytemuck::Zeroable;
 use parking_lot::Mutex;
 use std::sync::Arc;
 
+use crate::checksum::AutoChecksums;
 use crate::error::GgrsError;
 use crate::frame_info::{GameState, PlayerInput};
-use crate::input_queue::InputQueue;
+use crate::input_queue::{InputQueue, DEFAULT_INPUT_QUEUE_LENGTH};
 use crate::network::messages::ConnectionStatus;
-use crate::{Config, Frame, GgrsRequest, InputStatus, PlayerHandle, NULL_FRAME};
+use crate::state_diff::StateDiffing;
+use crate::{Config, Frame, GgrsRequest, InputStatus, PlayerHandle, MAX_FRAME, NULL_FRAME};
 
 /// An [`Arc<Mutex>`] that you can [`save()`]/[`load()`] a `T` to/from. These will be handed to the user as part of a [`GgrsRequest`].
 ///
@@ -15,28 +17,34 @@ use crate::{Config, Frame, GgrsRequest, InputStatus, PlayerHandle, NULL_FRAME};
 pub struct GameStateCell<T: Clone>(Arc<Mutex<GameState<T>>>);
 
 impl<T: Clone> GameStateCell<T> {
-    /// Saves a `T` the user creates into the cell.
+    /// Saves a `T` the user creates into the cell. Pass the frame of the [`GgrsRequest::SaveGameState`] the cell came with.
+    /// A state saved for [`NULL_FRAME`] belongs to no frame, so the session never loads it.
     pub fn save(&self, frame: Frame, data: Option<T>, checksum: Option<u128>) {
         let mut state = self.0.lock();
-        assert!(frame != NULL_FRAME);
-        state.frame = frame;
+        state.frame = (frame != NULL_FRAME).then_some(frame);
         state.data = data;
         state.checksum = checksum;
+        state.delta = None;
     }
 
-    /// Loads a `T` that the user previously saved into.
+    /// Loads a `T` that the user previously saved into. With state diffing enabled, cells only contain a loadable `T`
+    /// while handling the [`GgrsRequest`] they were handed to you with.
     pub fn load(&self) -> Option<T> {
         let state = self.0.lock();
         state.data.clone()
     }
 
-    pub(crate) fn frame(&self) -> Frame {
+    pub(crate) fn frame(&self) -> Option<Frame> {
         self.0.lock().frame
     }
 
     pub(crate) fn checksum(&self) -> Option<u128> {
         self.0.lock().checksum
     }
+
+    pub(crate) fn map_state<R>(&self, f: impl FnOnce(&mut GameState<T>) -> R) -> R {
+        f(&mut self.0.lock())
+    }
 }
 
 impl<T: Clone> Default for GameStateCell<T> {
@@ -61,6 +69,37 @@ impl<T: Clone> std::fmt::Debug for GameStateCell<T> {
     }
 }
 
+/// A snapshot of a single slot of the saved states, without the state itself. Requires the `debug-tools` feature.
+#[cfg(feature = "debug-tools")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveSlotSnapshot {
+    /// The frame of the state saved in the slot, or `None` if nothing has been saved in it yet.
+    pub frame: Option<Frame>,
+    /// The checksum of the saved state, if one was provided or computed.
+    pub checksum: Option<u128>,
+    /// The bytes the slot holds, including the delta of a compacted state. Memory allocated by the state itself is not included.
+    pub size: usize,
+    /// Whether the state is stored as a delta to the next newer state, see [`SessionBuilder::with_state_diffing()`].
+    ///
+    /// [`SessionBuilder::with_state_diffing()`]: crate::SessionBuilder::with_state_diffing
+    pub compacted: bool,
+}
+
+/// A snapshot of the ring of saved states, for rendering the saved frames and rollbacks in a debug overlay.
+/// Requires the `debug-tools` feature.
+#[cfg(feature = "debug-tools")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedStatesSnapshot {
+    /// All slots in the order of the ring. The state of a frame is saved into the slot at `frame % slots.len()`.
+    pub slots: Vec<SaveSlotSnapshot>,
+    /// The index of the slot the state of the current frame is saved into.
+    pub head: usize,
+    /// The current frame of the session.
+    pub current_frame: Frame,
+    /// The frame of the most recently saved state, if any.
+    pub last_saved_frame: Option<Frame>,
+}
+
 #[derive(Clone)]
 pub(crate) struct SavedStates<T: Clone> {
     pub states: Vec<GameStateCell<T>>,
@@ -78,11 +117,35 @@ impl<T: Clone> SavedStates<T> {
         Self { states }
     }
 
+    /// Returns the number of states that can be held at once.
+    fn capacity(&self) -> usize {
+        self.states.len()
+    }
+
     fn get_cell(&self, frame: Frame) -> GameStateCell<T> {
         assert!(frame >= 0);
-        let pos = frame as usize % self.states.len();
+        let pos = frame as usize % self.capacity();
         self.states[pos].clone()
     }
+
+    /// Returns the cells holding the states of the frames that fit into the buffer up to `newest`, ordered from oldest to newest
+    /// frame. As the state of a frame is saved into the slot at `frame % capacity`, walking the ring from the slot after `newest`
+    /// visits these frames in order. Cells still holding an older frame, since no state was saved into their slot since then,
+    /// are skipped, as are cells of newer frames that are left over from before a rollback.
+    fn iter(&self, newest: Frame) -> impl DoubleEndedIterator<Item = GameStateCell<T>> + '_ {
+        let oldest = newest - self.capacity() as Frame + 1;
+        let (newer, older) = self
+            .states
+            .split_at((newest + 1).rem_euclid(self.capacity() as Frame) as usize);
+        older
+            .iter()
+            .chain(newer)
+            .filter(move |cell| {
+                cell.frame()
+                    .is_some_and(|frame| (oldest..=newest).contains(&frame))
+            })
+            .cloned()
+    }
 }
 
 pub(crate) struct SyncLayer<T>
@@ -91,11 +154,23 @@ where
 {
     num_players: usize,
     max_prediction: usize,
+    /// States are only saved on the start frame and on frames that are a multiple of this interval.
+    save_interval: usize,
     saved_states: SavedStates<T::State>,
-    last_confirmed_frame: Frame,
-    last_saved_frame: Frame,
+    /// If set, saved states are stored as deltas to the next newer state.
+    state_diffing: Option<StateDiffing<T::State>>,
+    /// If set, the checksums the user did not provide are computed from the bytes of the saved states.
+    auto_checksums: Option<AutoChecksums<T::State>>,
+    last_confirmed_frame: Option<Frame>,
+    last_saved_frame: Option<Frame>,
     current_frame: Frame,
+    /// The frame the session started at. No state before it is ever saved or loaded.
+    start_frame: Frame,
     input_queues: Vec<InputQueue<T>>,
+    /// The number of confirmed frames whose inputs are kept, even if rollbacks no longer need them.
+    input_history: usize,
+    /// If set, the bits of the input that are predicted to repeat, all others are predicted to be cleared.
+    sticky_input_mask: Option<T::Input>,
 }
 
 impl<T: Config> SyncLayer<T> {
@@ -104,16 +179,22 @@ impl<T: Config> SyncLayer<T> {
         // initialize input_queues
         let mut input_queues = Vec::new();
         for _ in 0..num_players {
-            input_queues.push(InputQueue::new());
+            input_queues.push(InputQueue::new(DEFAULT_INPUT_QUEUE_LENGTH));
         }
         Self {
             num_players,
             max_prediction,
-            last_confirmed_frame: NULL_FRAME,
-            last_saved_frame: NULL_FRAME,
+            save_interval: 1,
+            last_confirmed_frame: None,
+            last_saved_frame: None,
             current_frame: 0,
+            start_frame: 0,
             saved_states: SavedStates::new(max_prediction),
+            state_diffing: None,
+            auto_checksums: None,
             input_queues,
+            input_history: 0,
+            sticky_input_mask: None,
         }
     }
 
@@ -121,12 +202,38 @@ impl<T: Config> SyncLayer<T> {
         self.current_frame
     }
 
+    pub(crate) fn start_frame(&self) -> Frame {
+        self.start_frame
+    }
+
+    /// Lets the session start at the given frame instead of frame 0, with blank inputs before it. Must be called before any
+    /// input has been added.
+    pub(crate) fn set_start_frame(&mut self, frame: Frame) {
+        // only `P2PSession::new()` calls this, with a frame `SessionBuilder::with_start_frame()` validated
+        debug_assert!(self.current_frame == self.start_frame && self.last_saved_frame.is_none());
+        debug_assert!((0..MAX_FRAME).contains(&frame));
+        self.current_frame = frame;
+        self.start_frame = frame;
+        for queue in self.input_queues.iter_mut() {
+            queue.set_start_frame(frame);
+        }
+    }
+
     pub(crate) fn advance_frame(&mut self) {
+        assert!(
+            !self.frame_limit_reached(),
+            "SyncLayer::advance_frame(): The frame limit has been reached"
+        );
         self.current_frame += 1;
     }
 
+    /// Returns true if the current frame is [`MAX_FRAME`], so the session cannot advance any further.
+    pub(crate) fn frame_limit_reached(&self) -> bool {
+        self.current_frame >= MAX_FRAME
+    }
+
     pub(crate) fn save_current_state(&mut self) -> GgrsRequest<T> {
-        self.last_saved_frame = self.current_frame;
+        self.last_saved_frame = Some(self.current_frame);
         let cell = self.saved_states.get_cell(self.current_frame);
         GgrsRequest::SaveGameState {
             cell,
@@ -134,55 +241,276 @@ impl<T: Config> SyncLayer<T> {
         }
     }
 
+    /// Returns the frame of the newest input of the given player, or `None` if no input has been added yet.
+    pub(crate) fn last_added_frame(&self, player_handle: PlayerHandle) -> Option<Frame> {
+        self.input_queues[player_handle].last_added_frame()
+    }
+
+    /// Returns the frame delay of the given player, or `None` if there is no such player.
+    pub(crate) fn frame_delay(&self, player_handle: PlayerHandle) -> Option<usize> {
+        self.input_queues
+            .get(player_handle)
+            .map(|queue| queue.frame_delay())
+    }
+
+    /// Returns a snapshot of the ring of saved states.
+    #[cfg(feature = "debug-tools")]
+    pub(crate) fn saved_states_snapshot(&self) -> SavedStatesSnapshot {
+        let slots = self
+            .saved_states
+            .states
+            .iter()
+            .map(|cell| {
+                cell.map_state(|state| SaveSlotSnapshot {
+                    frame: state.frame,
+                    checksum: state.checksum,
+                    size: std::mem::size_of::<GameState<T::State>>()
+                        + state.delta.as_ref().map_or(0, |delta| delta.memory()),
+                    compacted: state.delta.is_some(),
+                })
+            })
+            .collect();
+        SavedStatesSnapshot {
+            slots,
+            head: self.current_frame as usize % self.saved_states.capacity(),
+            current_frame: self.current_frame,
+            last_saved_frame: self.last_saved_frame,
+        }
+    }
+
+    /// Returns a snapshot of the input queue of every player, ordered by player handle.
+    #[cfg(feature = "debug-tools")]
+    pub(crate) fn input_queue_snapshots(&self) -> Vec<crate::InputQueueSnapshot> {
+        self.input_queues
+            .iter()
+            .map(|queue| queue.snapshot())
+            .collect()
+    }
+
     pub(crate) fn set_frame_delay(&mut self, player_handle: PlayerHandle, delay: usize) {
         assert!(player_handle < self.num_players as PlayerHandle);
         self.input_queues[player_handle].set_frame_delay(delay);
     }
 
+    /// Only save states on frames that are a multiple of the given interval. As rollbacks might need to go back up to `save_interval - 1` frames
+    /// further than the prediction window, more states are kept.
+    pub(crate) fn set_save_interval(&mut self, save_interval: usize) {
+        assert!(save_interval > 0);
+        self.save_interval = save_interval;
+        self.saved_states = SavedStates::new(self.max_prediction + save_interval - 1);
+    }
+
+    /// Replaces the input queues of all players with queues of the given length. Must be called before any input has been added.
+    pub(crate) fn set_input_queue_length(&mut self, length: usize) {
+        assert!(self.current_frame == self.start_frame);
+        self.input_queues = (0..self.num_players)
+            .map(|_| {
+                let mut queue = InputQueue::new(length);
+                queue.set_start_frame(self.start_frame);
+                queue.set_sticky_mask(self.sticky_input_mask);
+                queue
+            })
+            .collect();
+    }
+
+    /// Lets the input queues of all players predict only the bits set in the mask to repeat, see [`InputQueue::set_sticky_mask()`].
+    pub(crate) fn set_sticky_input_mask(&mut self, mask: T::Input) {
+        self.sticky_input_mask = Some(mask);
+        for queue in &mut self.input_queues {
+            queue.set_sticky_mask(Some(mask));
+        }
+    }
+
+    /// Keeps the inputs of at least the given number of confirmed frames, see [`SyncLayer::inputs_since()`].
+    pub(crate) fn set_input_history(&mut self, frames: usize) {
+        self.input_history = frames;
+    }
+
+    /// Stores saved states as deltas to the next newer state, see [`SyncLayer::compact_saved_states()`].
+    pub(crate) fn set_state_diffing(&mut self, state_diffing: StateDiffing<T::State>) {
+        self.state_diffing = Some(state_diffing);
+    }
+
+    /// Computes the checksums of saved states the user did not provide, see [`SyncLayer::compact_saved_states()`].
+    pub(crate) fn set_auto_checksums(&mut self, auto_checksums: AutoChecksums<T::State>) {
+        self.auto_checksums = Some(auto_checksums);
+    }
+
+    /// If auto checksums are enabled, computes the missing checksums of the saved states. If state diffing is enabled, replaces the
+    /// saved states with deltas afterwards. Must be called before creating new requests, when the user has handled all requests
+    /// handed out before.
+    pub(crate) fn compact_saved_states(&mut self) {
+        let Some(newest) = self.last_saved_frame else {
+            return;
+        };
+        if let Some(auto_checksums) = &self.auto_checksums {
+            for cell in self.saved_states.iter(newest) {
+                auto_checksums.fill(&cell);
+            }
+        }
+        if let Some(state_diffing) = &mut self.state_diffing {
+            state_diffing.compact(self.saved_states.iter(newest));
+        }
+    }
+
+    /// Returns true if a state should be saved on the current frame.
+    pub(crate) fn is_save_frame(&self) -> bool {
+        self.current_frame == self.start_frame
+            || self.current_frame % self.save_interval as Frame == 0
+    }
+
+    /// Returns the first frame after the current one on which a state is saved.
+    pub(crate) fn next_save_frame(&self) -> Frame {
+        self.current_frame - self.current_frame % self.save_interval as Frame
+            + self.save_interval as Frame
+    }
+
+    /// Returns the latest frame at or before the given frame on which a state is saved.
+    pub(crate) fn save_frame_at_or_before(&self, frame: Frame) -> Frame {
+        std::cmp::max(
+            frame - frame % self.save_interval as Frame,
+            self.start_frame,
+        )
+    }
+
     pub(crate) fn reset_prediction(&mut self) {
         for i in 0..self.num_players {
             self.input_queues[i].reset_prediction();
         }
     }
 
-    /// Loads the gamestate indicated by `frame_to_load`.
-    pub(crate) fn load_frame(&mut self, frame_to_load: Frame) -> GgrsRequest<T> {
+    /// Returns whether any input queue had all of its predictions confirmed since the last call.
+    pub(crate) fn take_predictions_confirmed(&mut self) -> bool {
+        let mut confirmed = false;
+        for queue in self.input_queues.iter_mut() {
+            confirmed |= queue.take_predictions_confirmed();
+        }
+        confirmed
+    }
+
+    /// Returns whether the state of the given frame is saved and can be loaded with [`load_frame()`].
+    ///
+    /// [`load_frame()`]: Self::load_frame
+    pub(crate) fn can_load_frame(&self, frame: Frame) -> bool {
         // The state should not be the current state or the state should not be in the future or too far away in the past
-        assert!(
-            frame_to_load != NULL_FRAME
-                && frame_to_load < self.current_frame
-                && frame_to_load >= self.current_frame - self.max_prediction as i32
-        );
+        self.in_rollback_window(frame) && self.saved_state_by_frame(frame).is_some()
+    }
+
+    /// Returns whether the state of the given frame would still be in the ring of saved states.
+    fn in_rollback_window(&self, frame: Frame) -> bool {
+        frame >= self.start_frame
+            && frame < self.current_frame
+            && frame >= self.current_frame - self.saved_states.capacity() as i32
+    }
+
+    /// Returns the earliest frame whose inputs are still kept, so a rollback can resimulate from it.
+    pub(crate) fn first_kept_frame(&self) -> Frame {
+        // rollbacks start at the latest saved frame before the first incorrect frame, so we need to keep the inputs since then
+        self.last_confirmed_frame
+            .filter(|&frame| frame > self.start_frame)
+            .map_or(self.start_frame, |frame| {
+                let history_start = (frame - self.input_history as Frame).max(self.start_frame);
+                self.save_frame_at_or_before(frame).min(history_start)
+            })
+    }
+
+    /// Returns the confirmed inputs of all players for every frame from `frame` up to the last confirmed frame.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the inputs of `frame` have been discarded already.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub(crate) fn inputs_since(
+        &self,
+        frame: Frame,
+        connect_status: &[ConnectionStatus],
+    ) -> Result<Vec<Vec<T::Input>>, GgrsError> {
+        let first_kept_frame = self.first_kept_frame();
+        if frame < first_kept_frame {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "The inputs of frame {} are no longer kept, the earliest frame kept is {}.",
+                    frame, first_kept_frame
+                ),
+            });
+        }
+        let Some(last_confirmed_frame) = self.last_confirmed_frame else {
+            return Ok(Vec::new());
+        };
+        Ok((frame..=last_confirmed_frame)
+            .map(|frame| {
+                self.confirmed_inputs(frame, connect_status)
+                    .iter()
+                    .map(|input| input.input)
+                    .collect()
+            })
+            .collect())
+    }
 
-        let cell = self.saved_states.get_cell(frame_to_load);
-        assert_eq!(cell.0.lock().frame, frame_to_load);
+    /// Loads the gamestate indicated by `frame_to_load`.
+    /// # Errors
+    /// - Returns [`StateUnavailable`] if the frame is outside of the rollback window, no state has been saved for it or its state
+    ///   cannot be restored from the deltas of the state diffing.
+    ///
+    /// [`StateUnavailable`]: GgrsError::StateUnavailable
+    pub(crate) fn load_frame(&mut self, frame_to_load: Frame) -> Result<GgrsRequest<T>, GgrsError> {
+        let unavailable = || GgrsError::StateUnavailable {
+            frame: frame_to_load,
+            current_frame: self.current_frame,
+        };
+
+        let cell = self
+            .saved_state_by_frame(frame_to_load)
+            .filter(|_| self.in_rollback_window(frame_to_load))
+            .ok_or_else(unavailable)?;
+        if let Some(state_diffing) = &self.state_diffing {
+            state_diffing
+                .restore(&cell, |frame| self.saved_state_by_frame(frame))
+                .ok_or_else(unavailable)?;
+        }
         self.current_frame = frame_to_load;
 
-        GgrsRequest::LoadGameState {
+        Ok(GgrsRequest::LoadGameState {
             cell,
             frame: frame_to_load,
-        }
+        })
     }
 
-    /// Adds local input to the corresponding input queue. Checks if the prediction threshold has been reached. Returns the frame number where the input is actually added to.
-    /// This number will only be different if the input delay was set to a number higher than 0.
+    /// Adds local input for the current frame to the corresponding input queue. Checks if the prediction threshold has been reached. Returns the frame number where the input is actually added to,
+    /// or `None` if it was dropped. This number will only be different if the input delay was set to a number higher than 0.
     pub(crate) fn add_local_input(
         &mut self,
         player_handle: PlayerHandle,
-        input: PlayerInput<T::Input>,
-    ) -> Result<Frame, GgrsError> {
-        let frames_ahead = self.current_frame - self.last_confirmed_frame;
-        if self.current_frame >= self.max_prediction as i32
+        input: T::Input,
+    ) -> Result<Option<Frame>, GgrsError> {
+        let frames_ahead = self
+            .last_confirmed_frame
+            .map_or(self.current_frame - self.start_frame, |confirmed| {
+                self.current_frame - confirmed
+            });
+        if self.current_frame - self.start_frame >= self.max_prediction as i32
             && frames_ahead >= self.max_prediction as i32
         {
             return Err(GgrsError::PredictionThreshold);
         }
 
-        // The input provided should match the current frame, we account for input delay later
-        assert_eq!(input.frame, self.current_frame);
+        // the input delay is accounted for by the input queue
+        let input = PlayerInput::new(self.current_frame, input);
         Ok(self.input_queues[player_handle].add_input(input))
     }
 
+    /// Returns the number of frames local input can still be added for before the prediction threshold is reached,
+    /// assuming the confirmed frame does not advance beyond `confirmed_frame`.
+    pub(crate) fn frames_until_prediction_threshold(
+        &self,
+        confirmed_frame: Option<Frame>,
+    ) -> usize {
+        let max_prediction = self.max_prediction as i32;
+        let threshold_frame = confirmed_frame.map_or(self.start_frame, |confirmed| {
+            std::cmp::max(self.start_frame, confirmed)
+        }) + max_prediction;
+        std::cmp::max(threshold_frame - self.current_frame, 0) as usize
+    }
+
     /// Adds remote input to the corresponding input queue.
     /// Unlike `add_local_input`, this will not check for correct conditions, as remote inputs have already been checked on another device.
     pub(crate) fn add_remote_input(
@@ -198,15 +526,26 @@ impl<T: Config> SyncLayer<T> {
         &mut self,
         connect_status: &[ConnectionStatus],
     ) -> Vec<(T::Input, InputStatus)> {
-        let mut inputs = Vec::new();
+        let mut inputs = Vec::with_capacity(self.num_players);
+        self.synchronized_inputs_into(connect_status, &mut inputs);
+        inputs
+    }
+
+    /// Appends the inputs of all players for the current frame of the sync layer to `inputs`, like [`synchronized_inputs()`].
+    ///
+    /// [`synchronized_inputs()`]: Self::synchronized_inputs
+    pub(crate) fn synchronized_inputs_into(
+        &mut self,
+        connect_status: &[ConnectionStatus],
+        inputs: &mut Vec<(T::Input, InputStatus)>,
+    ) {
         for (i, con_stat) in connect_status.iter().enumerate() {
-            if con_stat.disconnected && con_stat.last_frame < self.current_frame {
+            if con_stat.disconnected && con_stat.last_frame < Some(self.current_frame) {
                 inputs.push((T::Input::zeroed(), InputStatus::Disconnected));
             } else {
                 inputs.push(self.input_queues[i].input(self.current_frame));
             }
         }
-        inputs
     }
 
     /// Returns confirmed inputs for all players for the current frame of the sync layer.
@@ -215,75 +554,131 @@ impl<T: Config> SyncLayer<T> {
         frame: Frame,
         connect_status: &[ConnectionStatus],
     ) -> Vec<PlayerInput<T::Input>> {
-        let mut inputs = Vec::new();
-        for (i, con_stat) in connect_status.iter().enumerate() {
-            if con_stat.disconnected && con_stat.last_frame < frame {
-                inputs.push(PlayerInput::blank_input(NULL_FRAME));
-            } else {
-                inputs.push(self.input_queues[i].confirmed_input(frame));
-            }
+        (0..connect_status.len())
+            .map(|player| self.confirmed_input(frame, player, connect_status))
+            .collect()
+    }
+
+    /// Returns the confirmed input of a single player for the given frame, a blank input if the player disconnected before.
+    pub(crate) fn confirmed_input(
+        &self,
+        frame: Frame,
+        player: PlayerHandle,
+        connect_status: &[ConnectionStatus],
+    ) -> PlayerInput<T::Input> {
+        let con_stat = connect_status[player];
+        if con_stat.disconnected && con_stat.last_frame < Some(frame) {
+            PlayerInput::blank_input(None)
+        } else {
+            self.input_queues[player].confirmed_input(frame)
         }
-        inputs
     }
 
     /// Sets the last confirmed frame to a given frame. By raising the last confirmed frame, we can discard all previous frames, as they are no longer necessary.
-    pub(crate) fn set_last_confirmed_frame(&mut self, mut frame: Frame, sparse_saving: bool) {
+    /// The frame is `None` as long as not even the inputs of the start frame are confirmed.
+    pub(crate) fn set_last_confirmed_frame(&mut self, frame: Option<Frame>, sparse_saving: bool) {
         // don't set the last confirmed frame after the first incorrect frame before a rollback has happened
-        let mut first_incorrect: Frame = NULL_FRAME;
-        for handle in 0..self.num_players {
-            first_incorrect = std::cmp::max(
-                first_incorrect,
-                self.input_queues[handle].first_incorrect_frame(),
-            );
-        }
+        let first_incorrect = self
+            .input_queues
+            .iter()
+            .filter_map(|queue| queue.first_incorrect_frame())
+            .min();
+
+        // inputs can arrive ahead of the current frame (e.g. due to input delay), but we must not discard inputs we did not request yet
+        let mut frame = frame.map(|frame| std::cmp::min(frame, self.current_frame));
 
         // if sparse saving option is turned on, don't set the last confirmed frame after the last saved frame
         if sparse_saving {
             frame = std::cmp::min(frame, self.last_saved_frame);
         }
 
-        // if we set the last confirmed frame beyond the first incorrect frame, we discard inputs that we need later for adjusting the gamestate.
-        assert!(first_incorrect == NULL_FRAME || first_incorrect >= frame);
+        // if we set the last confirmed frame beyond the first incorrect frame, we discard inputs that we need later for adjusting the gamestate,
+        // e.g. while a rollback is spread over several calls to `advance_frame()`
+        if first_incorrect.is_some() {
+            frame = std::cmp::min(frame, first_incorrect);
+        }
 
         self.last_confirmed_frame = frame;
-        if self.last_confirmed_frame > 0 {
+        let discard_frame = self.first_kept_frame();
+        if discard_frame > self.start_frame {
             for i in 0..self.num_players {
-                self.input_queues[i].discard_confirmed_frames(frame - 1);
+                self.input_queues[i].discard_confirmed_frames(discard_frame - 1);
             }
         }
     }
 
-    /// Finds the earliest incorrect frame detected by the individual input queues
-    pub(crate) fn check_simulation_consistency(&self, mut first_incorrect: Frame) -> Frame {
-        for handle in 0..self.num_players {
-            let incorrect = self.input_queues[handle].first_incorrect_frame();
-            if incorrect != NULL_FRAME
-                && (first_incorrect == NULL_FRAME || incorrect < first_incorrect)
-            {
-                first_incorrect = incorrect;
-            }
-        }
-        first_incorrect
+    /// Finds the earliest incorrect frame detected by the individual input queues, or the given frame if it is earlier.
+    pub(crate) fn check_simulation_consistency(
+        &self,
+        first_incorrect: Option<Frame>,
+    ) -> Option<Frame> {
+        self.input_queues
+            .iter()
+            .filter_map(|queue| queue.first_incorrect_frame())
+            .chain(first_incorrect)
+            .min()
     }
 
-    /// Returns a gamestate through given frame
+    /// Returns every player with a mispredicted input, together with the first frame that was predicted wrong.
+    pub(crate) fn mispredictions(&self) -> impl Iterator<Item = (PlayerHandle, Frame)> + '_ {
+        self.input_queues
+            .iter()
+            .enumerate()
+            .filter_map(|(handle, queue)| Some((handle, queue.first_incorrect_frame()?)))
+    }
+
+    /// Returns the cell the state of the given frame was saved into, if it is still there. The cell is found in constant time,
+    /// as the state of a frame is always saved into the slot at `frame % capacity`.
     pub(crate) fn saved_state_by_frame(&self, frame: Frame) -> Option<GameStateCell<T::State>> {
+        if frame < 0 {
+            return None;
+        }
         let cell = self.saved_states.get_cell(frame);
+        (cell.frame() == Some(frame)).then_some(cell)
+    }
 
-        if cell.0.lock().frame == frame {
-            Some(cell)
-        } else {
-            None
-        }
+    /// Returns the saved states up to the last saved frame, ordered from oldest to newest frame.
+    pub(crate) fn saved_states(&self) -> impl Iterator<Item = GameStateCell<T::State>> + '_ {
+        self.last_saved_frame
+            .into_iter()
+            .flat_map(|newest| self.saved_states.iter(newest))
+    }
+
+    /// Returns the bytes held by the saved states, including the deltas and the newest state of the state diffing.
+    pub(crate) fn saved_states_memory(&self) -> usize {
+        let cells: usize = self
+            .saved_states
+            .states
+            .iter()
+            .map(|cell| {
+                cell.map_state(|state| {
+                    std::mem::size_of::<GameState<T::State>>()
+                        + state.delta.as_ref().map_or(0, |delta| delta.memory())
+                })
+            })
+            .sum();
+        let head = self
+            .state_diffing
+            .as_ref()
+            .map_or(0, |diffing| diffing.head_memory());
+        cells + head
     }
 
-    /// Returns the latest saved frame
-    pub(crate) fn last_saved_frame(&self) -> Frame {
+    /// Returns the bytes held by the input queues of all players.
+    pub(crate) fn input_queues_memory(&self) -> usize {
+        self.input_queues
+            .iter()
+            .map(|queue| queue.memory_usage())
+            .sum()
+    }
+
+    /// Returns the latest saved frame, if any
+    pub(crate) fn last_saved_frame(&self) -> Option<Frame> {
         self.last_saved_frame
     }
 
-    /// Returns the latest confirmed frame
-    pub(crate) fn last_confirmed_frame(&self) -> Frame {
+    /// Returns the latest confirmed frame, if any
+    pub(crate) fn last_confirmed_frame(&self) -> Option<Frame> {
         self.last_confirmed_frame
     }
 }
@@ -318,10 +713,226 @@ mod sync_layer_tests {
     fn test_reach_prediction_threshold() {
         let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
         for i in 0..20 {
-            let game_input = PlayerInput::new(i, TestInput { inp: i as u8 });
-            sync_layer.add_local_input(0, game_input).unwrap(); // should crash at frame 7
+            sync_layer
+                .add_local_input(0, TestInput { inp: i as u8 })
+                .unwrap(); // should crash at frame 7
+            sync_layer.advance_frame();
+        }
+    }
+
+    #[test]
+    fn test_frames_until_prediction_threshold() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        for i in 0..8 {
+            assert_eq!(sync_layer.frames_until_prediction_threshold(None), 8 - i);
+            sync_layer
+                .add_local_input(0, TestInput { inp: i as u8 })
+                .unwrap();
             sync_layer.advance_frame();
         }
+        assert_eq!(sync_layer.frames_until_prediction_threshold(None), 0);
+        assert_eq!(sync_layer.frames_until_prediction_threshold(Some(3)), 3);
+    }
+
+    #[test]
+    fn test_set_last_confirmed_frame() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        for _ in 0..4 {
+            sync_layer.save_current_state();
+            for handle in 0..2 {
+                sync_layer
+                    .add_local_input(handle, TestInput { inp: 0 })
+                    .unwrap();
+            }
+            sync_layer.advance_frame();
+        }
+        sync_layer.set_last_confirmed_frame(None, false);
+        assert_eq!(sync_layer.last_confirmed_frame(), None);
+        // confirmed inputs can be ahead of the current frame
+        sync_layer.set_last_confirmed_frame(Some(10), false);
+        assert_eq!(sync_layer.last_confirmed_frame(), Some(4));
+        sync_layer.set_last_confirmed_frame(Some(10), true);
+        assert_eq!(sync_layer.last_confirmed_frame(), Some(3));
+        assert_eq!(sync_layer.check_simulation_consistency(None), None);
+        assert_eq!(sync_layer.check_simulation_consistency(Some(2)), Some(2));
+    }
+
+    #[test]
+    fn test_last_confirmed_frame_stops_at_misprediction() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        let connect_status = [ConnectionStatus::default(); 2];
+        for _ in 0..4 {
+            sync_layer.add_local_input(0, TestInput { inp: 0 }).unwrap();
+            sync_layer.synchronized_inputs(&connect_status);
+            sync_layer.advance_frame();
+        }
+        // the inputs of player 1 were predicted wrong from frame 2 on, which the rollback still needs
+        for i in 0..4 {
+            let inp = if i < 2 { 0 } else { 1 };
+            sync_layer.add_remote_input(1, PlayerInput::new(i, TestInput { inp }));
+        }
+        sync_layer.set_last_confirmed_frame(Some(3), false);
+        assert_eq!(sync_layer.last_confirmed_frame(), Some(2));
+    }
+
+    #[test]
+    fn test_last_confirmed_frame_stops_at_earliest_misprediction() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(3, 8);
+        let connect_status = [ConnectionStatus::default(); 3];
+        for _ in 0..4 {
+            sync_layer.add_local_input(0, TestInput { inp: 0 }).unwrap();
+            sync_layer.synchronized_inputs(&connect_status);
+            sync_layer.advance_frame();
+        }
+        // player 1 was predicted wrong from frame 2 on, player 2 already from frame 1 on
+        for i in 0..4 {
+            let inp = if i < 2 { 0 } else { 1 };
+            sync_layer.add_remote_input(1, PlayerInput::new(i, TestInput { inp }));
+            let inp = if i < 1 { 0 } else { 1 };
+            sync_layer.add_remote_input(2, PlayerInput::new(i, TestInput { inp }));
+        }
+        sync_layer.set_last_confirmed_frame(Some(3), false);
+        assert_eq!(sync_layer.last_confirmed_frame(), Some(1));
+    }
+
+    #[test]
+    fn test_confirmed_inputs_ahead_are_kept() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        for i in 0..10 {
+            sync_layer.add_remote_input(1, PlayerInput::new(i, TestInput { inp: i as u8 }));
+        }
+        let connect_status = [ConnectionStatus::default(); 2];
+        for i in 0..10 {
+            sync_layer.save_current_state();
+            sync_layer.add_local_input(0, TestInput { inp: 0 }).unwrap();
+            // the remote inputs are confirmed up to frame 9 before the local client requested them
+            sync_layer.set_last_confirmed_frame(Some(9), false);
+            let inputs = sync_layer.synchronized_inputs(&connect_status);
+            assert!(inputs[1].0 == TestInput { inp: i as u8 });
+            assert_eq!(inputs[1].1, InputStatus::Confirmed);
+            sync_layer.advance_frame();
+        }
+    }
+
+    #[test]
+    fn test_frame_limit() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        sync_layer.current_frame = MAX_FRAME - 3;
+        // the saved states keep working up to the last frame
+        while !sync_layer.frame_limit_reached() {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                cell.save(frame, Some((MAX_FRAME - frame) as u8), None);
+            }
+            sync_layer.advance_frame();
+        }
+        assert_eq!(sync_layer.current_frame(), MAX_FRAME);
+
+        match sync_layer.load_frame(MAX_FRAME - 2) {
+            Ok(GgrsRequest::LoadGameState { cell, frame }) => {
+                assert_eq!(frame, MAX_FRAME - 2);
+                assert_eq!(cell.load(), Some(2));
+            }
+            _ => panic!("Expected the state of the frame to be loaded"),
+        }
+    }
+
+    #[cfg(feature = "debug-tools")]
+    #[test]
+    fn test_saved_states_snapshot() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 4);
+        for i in 0..6 {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                cell.save(frame, Some(i as u8), Some(i as u128));
+            }
+            sync_layer.advance_frame();
+        }
+
+        let snapshot = sync_layer.saved_states_snapshot();
+        assert_eq!(snapshot.current_frame, 6);
+        assert_eq!(snapshot.last_saved_frame, Some(5));
+        assert_eq!(snapshot.head, 2);
+        let frames: Vec<_> = snapshot.slots.iter().map(|slot| slot.frame).collect();
+        assert_eq!(frames, [Some(4), Some(5), Some(2), Some(3)]);
+        assert!(snapshot
+            .slots
+            .iter()
+            .all(|slot| slot.checksum == slot.frame.map(|frame| frame as u128) && !slot.compacted));
+    }
+
+    #[test]
+    fn test_load_frame_errors() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        for i in 0..10 {
+            if i != 5 {
+                if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state()
+                {
+                    cell.save(frame, Some(i as u8), None);
+                }
+            }
+            sync_layer.advance_frame();
+        }
+
+        // outside of the rollback window
+        assert!(sync_layer.load_frame(NULL_FRAME).is_err());
+        assert!(sync_layer.load_frame(1).is_err());
+        assert!(sync_layer.load_frame(10).is_err());
+        // never saved
+        assert!(matches!(
+            sync_layer.load_frame(5),
+            Err(GgrsError::StateUnavailable {
+                frame: 5,
+                current_frame: 10
+            })
+        ));
+        assert_eq!(sync_layer.current_frame(), 10);
+
+        match sync_layer.load_frame(6) {
+            Ok(GgrsRequest::LoadGameState { cell, frame }) => {
+                assert_eq!(frame, 6);
+                assert_eq!(cell.load(), Some(6));
+            }
+            _ => panic!("expected a load request"),
+        }
+        assert_eq!(sync_layer.current_frame(), 6);
+    }
+
+    #[test]
+    fn test_saved_states_in_order() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        assert_eq!(sync_layer.saved_states.capacity(), 8);
+        assert_eq!(sync_layer.saved_states().count(), 0);
+
+        for i in 0..20 {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                cell.save(frame, Some(i as u8), None);
+            }
+            sync_layer.advance_frame();
+        }
+
+        // only the newest states fit into the buffer, oldest first
+        let frames: Vec<_> = sync_layer.saved_states().map(|cell| cell.frame()).collect();
+        assert_eq!(frames, (12..20).map(Some).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_saved_states_skip_old_slots() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 6);
+        sync_layer.set_save_interval(3);
+        assert_eq!(sync_layer.saved_states.capacity(), 8);
+
+        for i in 0..25 {
+            if sync_layer.is_save_frame() {
+                if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state()
+                {
+                    cell.save(frame, Some(i as u8), None);
+                }
+            }
+            sync_layer.advance_frame();
+        }
+
+        // the capacity is no multiple of the interval, so some slots still hold states that are too old to be loaded
+        let frames: Vec<_> = sync_layer.saved_states().map(|cell| cell.frame()).collect();
+        assert_eq!(frames, vec![Some(18), Some(21), Some(24)]);
     }
 
     #[test]
@@ -342,8 +953,8 @@ mod sync_layer_tests {
             sync_layer.add_remote_input(0, game_input);
             sync_layer.add_remote_input(1, game_input);
             // update the dummy connect status
-            dummy_connect_status[0].last_frame = i;
-            dummy_connect_status[1].last_frame = i;
+            dummy_connect_status[0].last_frame = Some(i);
+            dummy_connect_status[1].last_frame = Some(i);
 
             if i >= 3 {
                 let sync_inputs = sync_layer.synchronized_inputs(&dummy_connect_status);
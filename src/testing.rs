@@ -0,0 +1,555 @@
+//! Helpers to test your game with GGRS in your own CI, without real sockets.
+//!
+//! A [`LoopbackNetwork`] connects any number of [`LoopbackSocket`]s in memory. It runs on a simulated clock and delays, reorders
+//! and drops packets according to its settings, driven by a seeded random number generator, so a failing run can be reproduced.
+//! A [`SoakTest`] uses it to drive two [`P2PSession`]s running your actual game for as many frames as you like and reports the
+//! desyncs it detected:
+//!
+//! ```ignore
+//! let network = LoopbackNetwork::new(42)
+//!     .with_latency(Duration::from_millis(150))
+//!     .with_packet_loss(0.05)?;
+//! let mut soak = SoakTest::new(network, [addr_a, addr_b], SessionBuilder::new, [Game::new(), Game::new()])?;
+//! let report = soak.run(100_000);
+//! assert_eq!(report.frames, 100_000);
+//! assert!(report.desyncs.is_empty());
+//! ```
+//!
+//! While a session is polled or advanced inside [`LoopbackNetwork::enter()`], all its timers, like the disconnect timeout, run on the
+//! simulated clock as well. A soak test therefore runs as fast as your game can simulate, no matter the latency.
+
+use std::hash::Hash;
+use std::ops::Range;
+use std::sync::Arc;
+
+use instant::{Duration, Instant};
+use parking_lot::Mutex;
+
+use crate::{clock, Clock, StdClock};
+use crate::{
+    Config, DesyncDetection, Frame, GgrsError, GgrsEvent, GgrsRequest, Message, NonBlockingSocket,
+    P2PSession, PlayerHandle, PlayerType, SessionBuilder, SessionState,
+};
+
+/// A [`SoakTest`] stops early if neither session advanced for this long in simulated time.
+const MAX_STALL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct InFlight<A> {
+    deliver_at: Duration,
+    from: A,
+    to: A,
+    msg: Message,
+}
+
+#[derive(Debug)]
+struct NetworkState<A> {
+    /// The wall clock time the simulated clock started at.
+    start: Instant,
+    start_millis: u128,
+    now: Duration,
+    latency: Duration,
+    jitter: Duration,
+    packet_loss: f64,
+    /// Windows of simulated time in which all packets are dropped.
+    outages: Vec<Range<Duration>>,
+    /// While set, packets are delayed but never dropped.
+    lossless: bool,
+    rng: u64,
+    in_flight: Vec<InFlight<A>>,
+    packets_sent: usize,
+    packets_dropped: usize,
+}
+
+impl<A> NetworkState<A> {
+    /// Returns the next number of a xorshift64* generator, which is good enough to decide the fate of packets.
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a random number in `[0, 1)`.
+    fn next_unit(&mut self) -> f64 {
+        (self.next_random() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn send(&mut self, from: A, to: A, msg: Message) {
+        self.packets_sent += 1;
+        // draw the random numbers for every packet, so the fate of a packet does not depend on whether earlier ones were lost
+        let lost = self.next_unit() < self.packet_loss;
+        let jitter = self.jitter.mul_f64(self.next_unit());
+        let in_outage = self.outages.iter().any(|outage| outage.contains(&self.now));
+        if !self.lossless && (lost || in_outage) {
+            self.packets_dropped += 1;
+            return;
+        }
+        self.in_flight.push(InFlight {
+            deliver_at: self.now + self.latency + jitter,
+            from,
+            to,
+            msg,
+        });
+    }
+}
+
+impl<A: PartialEq> NetworkState<A> {
+    /// Removes and returns at most `max_messages` packets for `addr` that have arrived by now, in the order they arrived, and
+    /// stops early once their encoded sizes add up to `max_bytes`. The other arrived packets are kept for the next call.
+    fn receive(&mut self, addr: &A, max_messages: usize, max_bytes: usize) -> Vec<(A, Message)> {
+        let now = self.now;
+        let mut arrived = Vec::new();
+        let mut i = 0;
+        while i < self.in_flight.len() {
+            if self.in_flight[i].to == *addr && self.in_flight[i].deliver_at <= now {
+                arrived.push(self.in_flight.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        arrived.sort_by_key(|packet| packet.deliver_at);
+        let mut received = Vec::new();
+        let mut received_bytes = 0;
+        let mut buffer = Vec::new();
+        let mut arrived = arrived.into_iter();
+        while received.len() < max_messages && received_bytes < max_bytes {
+            let Some(packet) = arrived.next() else {
+                break;
+            };
+            if max_bytes != usize::MAX {
+                buffer.clear();
+                packet.msg.encode_into(&mut buffer);
+                received_bytes += buffer.len();
+            }
+            received.push((packet.from, packet.msg));
+        }
+        self.in_flight.extend(arrived);
+        received
+    }
+}
+
+/// An in-memory network with a simulated clock. Packets sent over its [`LoopbackSocket`]s arrive once the clock has advanced
+/// by the configured latency, and are dropped at random with the configured packet loss. By default, packets arrive instantly
+/// and are never lost.
+#[derive(Debug)]
+pub struct LoopbackNetwork<A> {
+    state: Arc<Mutex<NetworkState<A>>>,
+}
+
+impl<A> Clone for LoopbackNetwork<A> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<A> LoopbackNetwork<A> {
+    /// Creates a new [`LoopbackNetwork`]. Networks with the same seed and settings drop and delay the same packets.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(NetworkState {
+                start: clock::now(&StdClock),
+                start_millis: StdClock.millis_since_epoch(),
+                now: Duration::ZERO,
+                latency: Duration::ZERO,
+                jitter: Duration::ZERO,
+                packet_loss: 0.0,
+                outages: Vec::new(),
+                lossless: false,
+                // xorshift gets stuck on zero
+                rng: seed.max(1),
+                in_flight: Vec::new(),
+                packets_sent: 0,
+                packets_dropped: 0,
+            })),
+        }
+    }
+
+    /// Sets the one-way latency of every packet.
+    pub fn with_latency(self, latency: Duration) -> Self {
+        self.state.lock().latency = latency;
+        self
+    }
+
+    /// Sets the maximum random delay added to the latency of a packet. Packets can arrive out of order with jitter.
+    pub fn with_jitter(self, jitter: Duration) -> Self {
+        self.state.lock().jitter = jitter;
+        self
+    }
+
+    /// Sets the share of packets that are dropped, from 0 (none) to 1 (all).
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the packet loss is not between 0 and 1.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_packet_loss(self, packet_loss: f64) -> Result<Self, GgrsError> {
+        if !(0.0..=1.0).contains(&packet_loss) {
+            return Err(GgrsError::InvalidRequest {
+                info: "The packet loss should be between 0 and 1.".to_owned(),
+            });
+        }
+        self.state.lock().packet_loss = packet_loss;
+        Ok(self)
+    }
+
+    /// Drops all packets sent during the given window of simulated time, as if the connection dropped out.
+    pub fn with_outage(self, outage: Range<Duration>) -> Self {
+        self.state.lock().outages.push(outage);
+        self
+    }
+
+    /// Returns a [`LoopbackSocket`] that sends from and receives for the given address.
+    pub fn socket(&self, addr: A) -> LoopbackSocket<A> {
+        LoopbackSocket {
+            addr,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Returns the current simulated time.
+    pub fn now(&self) -> Duration {
+        self.state.lock().now
+    }
+
+    /// Advances the simulated clock, so packets that arrive in the meantime can be received.
+    pub fn advance(&self, duration: Duration) {
+        self.state.lock().now += duration;
+    }
+
+    /// Runs `f` with the clock of GGRS set to the simulated time on this thread. Poll and advance your sessions inside of it,
+    /// so their timers follow the simulated clock instead of the wall clock.
+    pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        let (start, start_millis, now) = {
+            let state = self.state.lock();
+            (state.start, state.start_millis, state.now)
+        };
+        clock::simulate(start, start_millis, now, f)
+    }
+
+    /// Returns the number of packets sent over the network so far.
+    pub fn packets_sent(&self) -> usize {
+        self.state.lock().packets_sent
+    }
+
+    /// Returns the number of packets the network dropped so far.
+    pub fn packets_dropped(&self) -> usize {
+        self.state.lock().packets_dropped
+    }
+
+    fn set_lossless(&self, lossless: bool) {
+        self.state.lock().lossless = lossless;
+    }
+}
+
+/// A [`NonBlockingSocket`] that sends and receives over a [`LoopbackNetwork`].
+#[derive(Debug)]
+pub struct LoopbackSocket<A> {
+    addr: A,
+    state: Arc<Mutex<NetworkState<A>>>,
+}
+
+impl<A> LoopbackSocket<A> {
+    /// Returns the address this socket sends from and receives for.
+    pub fn addr(&self) -> &A {
+        &self.addr
+    }
+}
+
+#[cfg(feature = "sync-send")]
+impl<A> NonBlockingSocket<A> for LoopbackSocket<A>
+where
+    A: Clone + PartialEq + Eq + Hash + Send + Sync,
+{
+    fn send_to(&mut self, msg: &Message, addr: &A) {
+        self.state
+            .lock()
+            .send(self.addr.clone(), addr.clone(), msg.clone());
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(A, Message)> {
+        self.state
+            .lock()
+            .receive(&self.addr, usize::MAX, usize::MAX)
+    }
+
+    fn receive_messages(&mut self, max_messages: usize, max_bytes: usize) -> Vec<(A, Message)> {
+        self.state
+            .lock()
+            .receive(&self.addr, max_messages, max_bytes)
+    }
+
+    fn local_addr(&self) -> Option<A> {
+        Some(self.addr.clone())
+    }
+}
+
+#[cfg(not(feature = "sync-send"))]
+impl<A> NonBlockingSocket<A> for LoopbackSocket<A>
+where
+    A: Clone + PartialEq + Eq + Hash,
+{
+    fn send_to(&mut self, msg: &Message, addr: &A) {
+        self.state
+            .lock()
+            .send(self.addr.clone(), addr.clone(), msg.clone());
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(A, Message)> {
+        self.state
+            .lock()
+            .receive(&self.addr, usize::MAX, usize::MAX)
+    }
+
+    fn receive_messages(&mut self, max_messages: usize, max_bytes: usize) -> Vec<(A, Message)> {
+        self.state
+            .lock()
+            .receive(&self.addr, max_messages, max_bytes)
+    }
+
+    fn local_addr(&self) -> Option<A> {
+        Some(self.addr.clone())
+    }
+}
+
+/// The game a [`SoakTest`] runs, one instance per session.
+pub trait SoakGame<T: Config> {
+    /// Returns the input of the local player with the given handle for the given frame.
+    fn local_input(&mut self, player_handle: PlayerHandle, frame: Frame) -> T::Input;
+
+    /// Fulfills the requests of the session, exactly like your game loop does. Save your states with a checksum, as the
+    /// [`SoakTest`] detects desyncs by comparing them.
+    fn handle_requests(&mut self, requests: Vec<GgrsRequest<T>>);
+}
+
+/// The outcome of [`SoakTest::run()`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SoakReport {
+    /// The lowest frame both sessions have reached.
+    pub frames: Frame,
+    /// The frames at which a desync was detected, by either session.
+    pub desyncs: Vec<Frame>,
+    /// The number of rollbacks of both sessions together.
+    pub rollbacks: usize,
+    /// The number of times a session could not advance because it reached the prediction threshold.
+    pub stalls: usize,
+    /// The number of packets sent over the network.
+    pub packets_sent: usize,
+    /// The number of packets the network dropped.
+    pub packets_dropped: usize,
+}
+
+/// A [`SoakTest`] runs two [`P2PSession`]s with one local player each against each other over a [`LoopbackNetwork`], advancing
+/// the simulated clock by one frame per step. The sessions synchronize over a lossless network first, then run with the
+/// configured latency and packet loss.
+pub struct SoakTest<T: Config, G> {
+    network: LoopbackNetwork<T::Address>,
+    sessions: [P2PSession<T>; 2],
+    games: [G; 2],
+    frame_duration: Duration,
+    report: SoakReport,
+}
+
+impl<T, G> SoakTest<T, G>
+where
+    T: Config,
+    G: SoakGame<T>,
+    LoopbackSocket<T::Address>: NonBlockingSocket<T::Address>,
+{
+    /// Creates a new [`SoakTest`] with two sessions at the given addresses. `builder` is called for every session and should
+    /// return a [`SessionBuilder`] with your usual settings, but without players: the session at `addrs[i]` gets the local
+    /// player with handle `i`. Desync detection is turned on for every frame.
+    ///
+    /// # Errors
+    /// - Returns any error of [`SessionBuilder::start_p2p_session()`].
+    pub fn new(
+        network: LoopbackNetwork<T::Address>,
+        addrs: [T::Address; 2],
+        mut builder: impl FnMut() -> SessionBuilder<T>,
+        games: [G; 2],
+    ) -> Result<Self, GgrsError> {
+        let frame_duration = Duration::from_secs(1) / builder().fps() as u32;
+        let mut start = |local: PlayerHandle| -> Result<P2PSession<T>, GgrsError> {
+            let remote = 1 - local;
+            builder()
+                .add_player(PlayerType::Local, local)?
+                .add_player(PlayerType::Remote(addrs[remote].clone()), remote)?
+                .with_desync_detection_mode(DesyncDetection::On { interval: 1 })
+                .start_p2p_session(network.socket(addrs[local].clone()))
+        };
+        let sessions = network.enter(|| Ok::<_, GgrsError>([start(0)?, start(1)?]))?;
+        Ok(Self {
+            network,
+            sessions,
+            games,
+            frame_duration,
+            report: SoakReport::default(),
+        })
+    }
+
+    /// Runs the sessions until both reached the given frame and returns what happened so far. Stops early if neither session
+    /// advanced for ten seconds of simulated time, for example because a session disconnected.
+    pub fn run(&mut self, frames: Frame) -> SoakReport {
+        let mut stalled_for = Duration::ZERO;
+        while self.report.frames < frames && stalled_for < MAX_STALL {
+            if self.step() {
+                stalled_for = Duration::ZERO;
+            } else {
+                stalled_for += self.frame_duration;
+            }
+        }
+        self.report.clone()
+    }
+
+    /// Returns the session with the local player of the given handle.
+    pub fn session(&self, player_handle: PlayerHandle) -> &P2PSession<T> {
+        &self.sessions[player_handle]
+    }
+
+    /// Returns the session with the local player of the given handle mutably, for example to take the recorded replay.
+    pub fn session_mut(&mut self, player_handle: PlayerHandle) -> &mut P2PSession<T> {
+        &mut self.sessions[player_handle]
+    }
+
+    /// Returns the game of the session with the local player of the given handle.
+    pub fn game(&self, player_handle: PlayerHandle) -> &G {
+        &self.games[player_handle]
+    }
+
+    /// Advances the clock by one frame and both sessions by up to one frame. Returns whether any session advanced.
+    fn step(&mut self) -> bool {
+        let running = self
+            .sessions
+            .iter()
+            .all(|session| session.current_state() == SessionState::Running);
+        self.network.set_lossless(!running);
+        self.network.advance(self.frame_duration);
+
+        let network = self.network.clone();
+        let advanced = network.enter(|| {
+            let mut advanced = false;
+            for (handle, (session, game)) in
+                self.sessions.iter_mut().zip(&mut self.games).enumerate()
+            {
+                session.poll_remote_clients();
+                for event in session.events() {
+                    if let GgrsEvent::DesyncDetected { frame, .. } = event {
+                        self.report.desyncs.push(frame);
+                    }
+                }
+                if session.current_state() != SessionState::Running {
+                    continue;
+                }
+
+                let input = game.local_input(handle, session.current_frame());
+                if session.add_local_input(handle, input).is_err() {
+                    continue;
+                }
+                match session.advance_frame() {
+                    Ok(requests) => {
+                        game.handle_requests(requests);
+                        advanced = true;
+                    }
+                    Err(GgrsError::PredictionThreshold) => self.report.stalls += 1,
+                    Err(_) => (),
+                }
+            }
+            advanced
+        });
+
+        self.report.frames = self
+            .sessions
+            .iter()
+            .map(|session| session.current_frame())
+            .min()
+            .unwrap_or_default();
+        self.report.rollbacks = self
+            .sessions
+            .iter()
+            .map(|session| session.rollback_stats().rollbacks)
+            .sum();
+        self.report.packets_sent = self.network.packets_sent();
+        self.report.packets_dropped = self.network.packets_dropped();
+        advanced
+    }
+}
+
+impl<T: Config, G> std::fmt::Debug for SoakTest<T, G> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SoakTest")
+            .field("frame_duration", &self.frame_duration)
+            .field("report", &self.report)
+            .finish_non_exhaustive()
+    }
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod testing_tests {
+    use super::*;
+    use crate::network::messages::{MessageBody, MessageHeader};
+
+    fn keep_alive() -> Message {
+        Message {
+            header: MessageHeader::default(),
+            body: MessageBody::KeepAlive,
+        }
+    }
+
+    #[test]
+    fn test_latency() {
+        let network = LoopbackNetwork::new(1).with_latency(Duration::from_millis(100));
+        let mut a = network.socket(1u8);
+        let mut b = network.socket(2u8);
+
+        a.send_to(&keep_alive(), &2);
+        assert!(b.receive_all_messages().is_empty());
+        network.advance(Duration::from_millis(99));
+        assert!(b.receive_all_messages().is_empty());
+        network.advance(Duration::from_millis(1));
+        let received = b.receive_all_messages();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0, 1);
+        assert!(a.receive_all_messages().is_empty());
+    }
+
+    #[test]
+    fn test_packet_loss_is_seeded() {
+        let received = |seed: u64| {
+            let network = LoopbackNetwork::new(seed).with_packet_loss(0.3).unwrap();
+            let mut a = network.socket(1u8);
+            let mut b = network.socket(2u8);
+            (0..100)
+                .map(|_| {
+                    a.send_to(&keep_alive(), &2);
+                    b.receive_all_messages().len()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(received(5), received(5));
+        assert_ne!(received(5), received(6));
+        let lost = received(5).iter().filter(|&&n| n == 0).count();
+        assert!((10..50).contains(&lost));
+        assert!(LoopbackNetwork::<u8>::new(5).with_packet_loss(1.5).is_err());
+    }
+
+    #[test]
+    fn test_outage() {
+        let network = LoopbackNetwork::new(1)
+            .with_outage(Duration::from_millis(10)..Duration::from_millis(20));
+        let mut a = network.socket(1u8);
+        let mut b = network.socket(2u8);
+
+        network.advance(Duration::from_millis(10));
+        a.send_to(&keep_alive(), &2);
+        network.advance(Duration::from_millis(10));
+        a.send_to(&keep_alive(), &2);
+        assert_eq!(b.receive_all_messages().len(), 1);
+        assert_eq!(network.packets_dropped(), 1);
+    }
+}
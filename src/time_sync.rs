@@ -28,6 +28,10 @@ impl TimeSync {
     }
 
     pub(crate) fn average_frame_advantage(&self) -> i32 {
+        self.fractional_frame_advantage() as i32
+    }
+
+    pub(crate) fn fractional_frame_advantage(&self) -> f32 {
         // average local and remote frame advantages
         let local_sum: i32 = self.local.iter().sum();
         let local_avg = local_sum as f32 / self.local.len() as f32;
@@ -35,7 +39,7 @@ impl TimeSync {
         let remote_avg = remote_sum as f32 / self.remote.len() as f32;
 
         // meet in the middle
-        ((remote_avg - local_avg) / 2.0) as i32
+        (remote_avg - local_avg) / 2.0
     }
 }
 
@@ -100,6 +104,20 @@ mod sync_layer_tests {
         assert_eq!(time_sync.average_frame_advantage(), 4);
     }
 
+    #[test]
+    fn test_fractional_frame_advantage() {
+        let mut time_sync = TimeSync::default();
+
+        for i in 0..60 {
+            let local_adv = -1;
+            let remote_adv = 2;
+            time_sync.advance_frame(i, local_adv, remote_adv)
+        }
+
+        assert_eq!(time_sync.fractional_frame_advantage(), 1.5);
+        assert_eq!(time_sync.average_frame_advantage(), 1);
+    }
+
     #[test]
     fn test_advance_frame_big_remote_advantage() {
         let mut time_sync = TimeSync::default();
@@ -3,7 +3,11 @@ use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 
-use ggrs::{Config, Frame, GameStateCell, GgrsRequest, InputStatus};
+use ggrs::testing::SoakGame;
+use ggrs::{
+    CoalescingInput, Config, Frame, GameStateCell, GgrsRequest, InputStatus, PlayerHandle,
+    StateBytes, VariableLengthInput,
+};
 
 fn calculate_hash<T: Hash>(t: &T) -> u64 {
     let mut s = DefaultHasher::new();
@@ -22,6 +26,19 @@ pub struct StubInput {
     pub inp: u32,
 }
 
+impl VariableLengthInput for StubInput {
+    // little-endian: the high bytes of small numbers are zero
+    fn input_len(&self) -> usize {
+        4 - self.inp.leading_zeros() as usize / 8
+    }
+}
+
+impl CoalescingInput for StubInput {
+    fn coalesce(&mut self, newer: &Self) {
+        self.inp += newer.inp;
+    }
+}
+
 pub struct StubConfig;
 
 impl Config for StubConfig {
@@ -31,7 +48,7 @@ impl Config for StubConfig {
 }
 
 impl GameStub {
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::new_without_default)]
     pub fn new() -> GameStub {
         GameStub {
             gs: StateStub { frame: 0, state: 0 },
@@ -45,6 +62,7 @@ impl GameStub {
                 GgrsRequest::LoadGameState { cell, .. } => self.load_game_state(cell),
                 GgrsRequest::SaveGameState { cell, frame } => self.save_game_state(cell, frame),
                 GgrsRequest::AdvanceFrame { inputs } => self.advance_frame(inputs),
+                GgrsRequest::BeginRollback { .. } | GgrsRequest::EndRollback => (),
             }
         }
     }
@@ -64,13 +82,40 @@ impl GameStub {
     }
 }
 
+/// Inputs that change often enough to cause mispredictions.
+fn soak_input(player_handle: PlayerHandle, frame: Frame) -> StubInput {
+    StubInput {
+        inp: (frame as u32 / 5 + player_handle as u32) % 3,
+    }
+}
+
+impl SoakGame<StubConfig> for GameStub {
+    fn local_input(&mut self, player_handle: PlayerHandle, frame: Frame) -> StubInput {
+        soak_input(player_handle, frame)
+    }
+
+    fn handle_requests(&mut self, requests: Vec<GgrsRequest<StubConfig>>) {
+        GameStub::handle_requests(self, requests);
+    }
+}
+
+impl SoakGame<StubConfig> for RandomChecksumGameStub {
+    fn local_input(&mut self, player_handle: PlayerHandle, frame: Frame) -> StubInput {
+        soak_input(player_handle, frame)
+    }
+
+    fn handle_requests(&mut self, requests: Vec<GgrsRequest<StubConfig>>) {
+        RandomChecksumGameStub::handle_requests(self, requests);
+    }
+}
+
 pub struct RandomChecksumGameStub {
     pub gs: StateStub,
     rng: ThreadRng,
 }
 
 impl RandomChecksumGameStub {
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::new_without_default)]
     pub fn new() -> RandomChecksumGameStub {
         RandomChecksumGameStub {
             gs: StateStub { frame: 0, state: 0 },
@@ -85,6 +130,7 @@ impl RandomChecksumGameStub {
                 GgrsRequest::LoadGameState { cell, .. } => self.load_game_state(cell),
                 GgrsRequest::SaveGameState { cell, frame } => self.save_game_state(cell, frame),
                 GgrsRequest::AdvanceFrame { inputs } => self.advance_frame(inputs),
+                GgrsRequest::BeginRollback { .. } | GgrsRequest::EndRollback => (),
             }
         }
     }
@@ -111,12 +157,25 @@ pub struct StateStub {
     pub state: i32,
 }
 
+impl StateBytes for StateStub {
+    fn to_bytes(&self) -> Vec<u8> {
+        [self.frame.to_le_bytes(), self.state.to_le_bytes()].concat()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            frame: i32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            state: i32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        }
+    }
+}
+
 impl StateStub {
-    fn advance_frame(&mut self, inputs: Vec<(StubInput, InputStatus)>) {
+    pub fn advance_frame(&mut self, inputs: Vec<(StubInput, InputStatus)>) {
         let p0_inputs = inputs[0].0.inp;
         let p1_inputs = inputs[1].0.inp;
 
-        if (p0_inputs + p1_inputs) % 2 == 0 {
+        if (p0_inputs + p1_inputs).is_multiple_of(2) {
             self.state += 2;
         } else {
             self.state -= 1;
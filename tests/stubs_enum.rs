@@ -37,7 +37,7 @@ impl Config for StubEnumConfig {
 }
 
 impl GameStubEnum {
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::new_without_default)]
     pub fn new() -> GameStubEnum {
         GameStubEnum {
             gs: StateStubEnum { frame: 0, state: 0 },
@@ -51,6 +51,7 @@ impl GameStubEnum {
                 GgrsRequest::LoadGameState { cell, .. } => self.load_game_state(cell),
                 GgrsRequest::SaveGameState { cell, frame } => self.save_game_state(cell, frame),
                 GgrsRequest::AdvanceFrame { inputs } => self.advance_frame(inputs),
+                GgrsRequest::BeginRollback { .. } | GgrsRequest::EndRollback => (),
             }
         }
     }
@@ -0,0 +1,185 @@
+use ggrs::testing::LoopbackNetwork;
+use ggrs::{
+    FixedInputConfig, GgrsError, GgrsRequest, P2PSession, PlayerType, SessionBuilder, SessionState,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+// an input byte buffer holds the input of a single frame, all other buffers of the sending client hold more or less
+const INPUT_SIZE: usize = 37;
+
+type TestConfig = FixedInputConfig<INPUT_SIZE, u64>;
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static INPUT_BUFFERS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts all allocations and those of input byte buffers on the current thread while counting is enabled.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.try_with(Cell::get).unwrap_or(false) {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            if layout.size() == INPUT_SIZE {
+                let _ = INPUT_BUFFERS.try_with(|count| count.set(count.get() + 1));
+            }
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if COUNTING.try_with(Cell::get).unwrap_or(false) {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Runs `f` and returns the number of allocations it made on the current thread.
+fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    COUNTING.with(|counting| counting.set(true));
+    let result = f();
+    COUNTING.with(|counting| counting.set(false));
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn advance(sess: &mut P2PSession<TestConfig>) -> Result<(), GgrsError> {
+    sess.poll_remote_clients();
+    let local = sess.local_player_handles()[0];
+    sess.add_local_input(local, [7; INPUT_SIZE])?;
+    for request in sess.advance_frame()? {
+        if let GgrsRequest::SaveGameState { cell, frame } = request {
+            cell.save(frame, Some(0), None);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_input_buffers_are_reused() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(17);
+
+    let mut sessions = network.enter(|| -> Result<_, GgrsError> {
+        Ok(vec![
+            // packets hold the inputs of at least three frames, so they are never as large as an input byte buffer
+            SessionBuilder::<TestConfig>::new()
+                .with_input_redundancy(3)?
+                .add_player(PlayerType::Local, 0)?
+                .add_player(PlayerType::Remote(addr2), 1)?
+                .start_p2p_session(network.socket(addr1))?,
+            SessionBuilder::<TestConfig>::new()
+                .add_player(PlayerType::Remote(addr1), 0)?
+                .add_player(PlayerType::Local, 1)?
+                .start_p2p_session(network.socket(addr2))?,
+        ])
+    })?;
+
+    // both clients advance until the pool of input buffers is filled
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            sessions
+                .iter_mut()
+                .for_each(|sess| drop(sess.poll_remote_clients()))
+        });
+    }
+    for _ in 0..100 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| sessions.iter_mut().try_for_each(advance))?;
+    }
+    // the second client stops, the first client receives the last inputs of the second one
+    for _ in 0..2 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            sessions
+                .iter_mut()
+                .for_each(|sess| drop(sess.poll_remote_clients()))
+        });
+    }
+
+    // in the steady state, the first client reuses the buffers of acknowledged inputs for its new inputs
+    for _ in 0..5 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| -> Result<(), GgrsError> {
+            sessions[1].poll_remote_clients();
+            COUNTING.with(|counting| counting.set(true));
+            let result = advance(&mut sessions[0]);
+            COUNTING.with(|counting| counting.set(false));
+            result
+        })?;
+    }
+    assert_eq!(INPUT_BUFFERS.with(Cell::get), 0);
+
+    Ok(())
+}
+
+/// Advances the frame with the requests of the previous frame still in `requests`, which hand their inputs back to the session.
+fn advance_into(
+    sess: &mut P2PSession<TestConfig>,
+    frame: u8,
+    requests: &mut Vec<GgrsRequest<TestConfig>>,
+) -> Result<(), GgrsError> {
+    for handle in 0..2 {
+        sess.add_local_input(handle, [frame; INPUT_SIZE])?;
+    }
+    sess.advance_frame_into(requests)?;
+    for request in requests.iter() {
+        if let GgrsRequest::SaveGameState { cell, frame } = request {
+            cell.save(*frame, Some(*frame as u64), None);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_no_allocations_in_steady_state() -> Result<(), GgrsError> {
+    let mut sess = SessionBuilder::<TestConfig>::new()
+        .with_input_delay(2)
+        // confirmed inputs are kept for a while, so the session can be rolled back
+        .with_input_history(8)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(
+            LoopbackNetwork::new(17).socket(SocketAddr::from(([127, 0, 0, 1], 7777))),
+        )?;
+    assert_eq!(sess.current_state(), SessionState::Running);
+
+    // the session fills its input queues, saved states and buffers, including those a rollback needs
+    let mut requests = Vec::new();
+    for frame in 0..100 {
+        if frame % 10 == 9 {
+            sess.force_rollback_to(sess.current_frame() - 5)?;
+        }
+        advance_into(&mut sess, frame, &mut requests)?;
+    }
+
+    // in the steady state, every allocation made while advancing, rolling back and handling the requests is counted
+    let (result, allocations) = count_allocations(|| -> Result<(), GgrsError> {
+        for frame in 100..200 {
+            if frame % 10 == 9 {
+                sess.force_rollback_to(sess.current_frame() - 5)?;
+            }
+            advance_into(&mut sess, frame, &mut requests)?;
+        }
+        Ok(())
+    });
+    result?;
+    assert_eq!(allocations, 0);
+
+    Ok(())
+}
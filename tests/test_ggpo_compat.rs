@@ -0,0 +1,68 @@
+#![cfg(feature = "ggpo-compat")]
+
+mod stubs;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use ggrs::{GgpoCompatSocket, GgrsError, PlayerType, SessionBuilder, SessionState};
+use serial_test::serial;
+use stubs::{StubConfig, StubInput};
+
+#[test]
+fn test_bind_limits() {
+    let input_size = std::mem::size_of::<StubInput>();
+    assert!(GgpoCompatSocket::bind_to_port(7777, 5, input_size).is_err());
+    assert!(GgpoCompatSocket::bind_to_port(7777, 2, 33).is_err());
+}
+
+#[test]
+#[serial]
+fn test_p2p_over_ggpo_layout() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let input_size = std::mem::size_of::<StubInput>();
+
+    let socket1 = GgpoCompatSocket::bind_to_port(7777, 2, input_size).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_input_history(30)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+    let socket2 = GgpoCompatSocket::bind_to_port(8888, 2, input_size).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_input_delay(2)
+        .with_input_history(30)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+
+    // inputs change every frame, so the packets carry changed bits in both directions
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..100 {
+        sess1.add_local_input(0, StubInput { inp: i * 3 })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i * 7 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+
+    // both sessions agree on all confirmed inputs
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    let confirmed = sess1.confirmed_frame().min(sess2.confirmed_frame());
+    assert!(confirmed > 80);
+    let inputs1 = sess1.inputs_since(confirmed - 20)?;
+    let inputs2 = sess2.inputs_since(confirmed - 20)?;
+    assert!(inputs1[..20] == inputs2[..20]);
+
+    Ok(())
+}
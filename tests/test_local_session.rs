@@ -0,0 +1,121 @@
+mod stubs;
+
+use ggrs::{
+    GgrsError, GgrsRequest, InputStatus, PlayerType, Session, SessionBuilder, SessionState,
+};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use stubs::{StubConfig, StubInput};
+
+#[test]
+fn test_create_session() -> Result<(), GgrsError> {
+    assert!(SessionBuilder::<StubConfig>::new()
+        .start_local_session()
+        .is_ok());
+    assert!(SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_local_session()
+        .is_ok());
+
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    assert!(matches!(
+        SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(remote_addr), 1)?
+            .start_local_session(),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_advance_frame() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::<StubConfig>::new().start_local_session()?;
+
+    assert!(matches!(
+        sess.advance_frame(),
+        Err(GgrsError::MissingInput { handle: 0, .. })
+    ));
+    for i in 0..200 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        let requests = sess.advance_frame()?;
+
+        // there is nothing to save or roll back
+        assert_eq!(requests.len(), 1);
+        assert!(matches!(
+            &requests[0],
+            GgrsRequest::AdvanceFrame { inputs }
+                if inputs.iter().all(|&(input, status)| input.inp == i && status == InputStatus::Confirmed)
+        ));
+        stub.handle_requests(requests);
+    }
+    assert_eq!(sess.current_frame(), 200);
+    assert_eq!(sess.confirmed_frame(), 199);
+    assert_eq!(stub.gs.frame, 200);
+    assert_eq!(sess.memory_usage().saved_states, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_input_delay() -> Result<(), GgrsError> {
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_input_delay(2)
+        .start_local_session()?;
+    assert_eq!(sess.input_delay(0)?, 2);
+
+    for i in 0..10 {
+        let requests = sess.advance_frame_with_input(|_| StubInput { inp: i + 1 })?;
+        let expected = if i < 2 { 0 } else { i - 1 };
+        assert!(matches!(
+            &requests[0],
+            GgrsRequest::AdvanceFrame { inputs } if inputs.iter().all(|(input, _)| input.inp == expected)
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_session_enum() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess: Session<StubConfig> = SessionBuilder::new().start_local_session()?.into();
+
+    assert_eq!(sess.current_state(), SessionState::Running);
+    assert_eq!(sess.local_player_handles(), vec![0, 1]);
+    for i in 0..20 {
+        sess.poll_remote_clients();
+        for handle in sess.local_player_handles() {
+            sess.add_local_input(handle, StubInput { inp: i })?;
+        }
+        stub.handle_requests(sess.advance_frame()?);
+        assert!(sess.events().is_empty());
+    }
+    assert_eq!(sess.current_frame(), 20);
+    assert_eq!(stub.gs.frame, 20);
+    assert!(matches!(sess, Session::Local(_)));
+
+    Ok(())
+}
+
+#[test]
+fn test_frames_to_advance() -> Result<(), GgrsError> {
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_fps(30)?
+        .start_local_session()?;
+    let start = Instant::now();
+
+    // at 30 fps, a frame is advanced every 33ms
+    assert_eq!(sess.frames_to_advance(start), 1);
+    assert_eq!(sess.frames_to_advance(start + Duration::from_millis(20)), 0);
+    assert_eq!(sess.frames_to_advance(start + Duration::from_millis(40)), 1);
+    assert_eq!(
+        sess.frames_to_advance(start + Duration::from_millis(140)),
+        3
+    );
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "net-trace")]
+
+mod stubs;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ggrs::testing::LoopbackNetwork;
+use ggrs::{GgrsError, PacketDirection, PacketTrace, PlayerType, SessionBuilder, SessionState};
+use stubs::StubConfig;
+
+#[test]
+fn test_packet_observer() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(5).with_latency(Duration::from_millis(20));
+
+    let traces = Arc::new(Mutex::new(Vec::new()));
+    let observer = {
+        let traces = traces.clone();
+        move |trace: &PacketTrace<SocketAddr>| traces.lock().unwrap().push(trace.clone())
+    };
+    let (mut sess1, mut sess2) = network.enter(|| {
+        let sess1 = SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .with_packet_observer(observer)
+            .start_p2p_session(network.socket(addr1))?;
+        let sess2 = SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(network.socket(addr2))?;
+        Ok::<_, GgrsError>((sess1, sess2))
+    })?;
+
+    for _ in 0..50 {
+        network.enter(|| {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        });
+        network.advance(Duration::from_millis(16));
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+
+    let traces = traces.lock().unwrap();
+    let sent = traces
+        .iter()
+        .filter(|trace| trace.direction == PacketDirection::Outbound)
+        .count();
+    let received = traces.len() - sent;
+    assert!(sent > 0);
+    assert!(received > 0);
+    assert!(traces.iter().all(|trace| trace.peer == addr2));
+    assert!(traces
+        .windows(2)
+        .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+
+    // every trace can be written to a file and read back
+    let bytes = bincode::serialize(&traces[0]).unwrap();
+    let decoded: PacketTrace<SocketAddr> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(decoded, traces[0]);
+
+    Ok(())
+}
@@ -1,11 +1,19 @@
 mod stubs;
 
+use ggrs::testing::{LoopbackNetwork, LoopbackSocket, SoakGame, SoakTest};
 use ggrs::{
-    DesyncDetection, GgrsError, GgrsEvent, PlayerType, SessionBuilder, SessionState,
+    Clock, Codec, ConnectionProbe, ConnectionState, DesyncDetection, DiagnosticsReport, Frame,
+    GgrsError, GgrsEvent, GgrsRequest, HandshakeMismatch, InputStatus, Message, NonBlockingSocket,
+    P2PSession, PlayerHandle, PlayerType, Replay, RollbackCause, RollbackStats, SessionBuilder,
+    SessionState, SocketScheduler, StallCause, StartupPolicy, SyncTestSession,
     UdpNonBlockingSocket,
 };
 use serial_test::serial;
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use stubs::{StubConfig, StubInput};
 
 #[test]
@@ -43,6 +51,83 @@ fn test_start_session() -> Result<(), GgrsError> {
     Ok(())
 }
 
+#[test]
+#[serial]
+fn test_player_handle_checks() -> Result<(), GgrsError> {
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8090);
+
+    // duplicate handles are rejected right away
+    let builder = SessionBuilder::<StubConfig>::new().add_player(PlayerType::Local, 0)?;
+    assert!(builder
+        .add_player(PlayerType::Remote(remote_addr), 0)
+        .is_err());
+
+    // gaps are reported with all missing handles
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let result = SessionBuilder::<StubConfig>::new()
+        .with_num_players(4)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 2)?
+        .start_p2p_session(socket);
+    match result {
+        Err(GgrsError::InvalidRequest { info }) => assert!(info.contains("[1, 3]")),
+        _ => panic!("expected the missing handles to be reported"),
+    }
+
+    // handles that no longer fit after changing the number of players
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let result = SessionBuilder::<StubConfig>::new()
+        .with_num_players(3)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .add_player(PlayerType::Spectator(spec_addr), 3)?
+        .add_player(PlayerType::Remote(remote_addr), 2)?
+        .with_num_players(2)
+        .start_p2p_session(socket);
+    match result {
+        Err(GgrsError::InvalidRequest { info }) => assert!(info.contains("[2]")),
+        _ => panic!("expected the misplaced handle to be reported"),
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_introspection() -> Result<(), GgrsError> {
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8090);
+
+    let sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .add_player(PlayerType::Spectator(spec_addr), 2)?
+        .with_input_delay(3)
+        .start_p2p_session(socket)?;
+
+    assert_eq!(
+        sess.players(),
+        vec![
+            (0, PlayerType::Local),
+            (1, PlayerType::Remote(remote_addr)),
+            (2, PlayerType::Spectator(spec_addr)),
+        ]
+    );
+    assert_eq!(sess.local_player_handles(), vec![0]);
+    assert_eq!(sess.spectator_handles(), vec![2]);
+    assert_eq!(sess.input_delay(0)?, 3);
+    assert_eq!(sess.input_delay(1)?, 0);
+    assert_eq!(
+        sess.input_delay(2),
+        Err(GgrsError::InvalidPlayerHandle { handle: 2 })
+    );
+    assert_eq!(sess.current_frame(), 0);
+
+    Ok(())
+}
+
 #[test]
 #[serial]
 fn test_disconnect_player() -> Result<(), GgrsError> {
@@ -56,15 +141,137 @@ fn test_disconnect_player() -> Result<(), GgrsError> {
         .add_player(PlayerType::Spectator(spec_addr), 2)?
         .start_p2p_session(socket)?;
 
-    assert!(sess.disconnect_player(5).is_err()); // invalid handle
-    assert!(sess.disconnect_player(0).is_err()); // for now, local players cannot be disconnected
+    assert_eq!(
+        sess.disconnect_player(5),
+        Err(GgrsError::InvalidPlayerHandle { handle: 5 })
+    ); // invalid handle
+    assert_eq!(
+        sess.disconnect_player(0),
+        Err(GgrsError::InvalidPlayerHandle { handle: 0 })
+    ); // for now, local players cannot be disconnected
     assert!(sess.disconnect_player(1).is_ok());
-    assert!(sess.disconnect_player(1).is_err()); // already disconnected
+    assert_eq!(
+        sess.disconnect_player(1),
+        Err(GgrsError::PlayerDisconnected { handle: 1 })
+    ); // already disconnected
     assert!(sess.disconnect_player(2).is_ok());
 
     Ok(())
 }
 
+#[test]
+fn test_disconnect_without_rollback() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let addr3 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+    let network = LoopbackNetwork::new(14);
+
+    let mut sessions = Vec::new();
+    for (local, local_addr, remote) in [(0, addr1, addr2), (1, addr2, addr1)] {
+        let session = network.enter(|| {
+            SessionBuilder::<StubConfig>::new()
+                .with_num_players(3)
+                .add_player(PlayerType::Local, local)?
+                .add_player(PlayerType::Remote(remote), 1 - local)?
+                .add_player(PlayerType::Remote(addr3), 2)?
+                .start_p2p_session(network.socket(local_addr))
+        })?;
+        sessions.push(session);
+    }
+    // the third player never shows up, so it is disconnected before its first frame, which has not been simulated yet
+    for sess in sessions.iter_mut() {
+        sess.disconnect_player(2)?;
+    }
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            sessions
+                .iter_mut()
+                .for_each(|sess| drop(sess.poll_remote_clients()))
+        });
+    }
+
+    let mut stubs = [stubs::GameStub::new(), stubs::GameStub::new()];
+    for i in 0..10 {
+        for (handle, (sess, stub)) in sessions.iter_mut().zip(stubs.iter_mut()).enumerate() {
+            sess.add_local_input(handle, StubInput { inp: i })?;
+            let requests = network.enter(|| sess.advance_frame())?;
+            // there is nothing to roll back before the first frame has been simulated
+            if i == 0 {
+                assert!(!requests
+                    .iter()
+                    .any(|request| matches!(request, GgrsRequest::LoadGameState { .. })));
+            }
+            stub.handle_requests(requests);
+        }
+        network.advance(Duration::from_millis(16));
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_disconnected_player_removal() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let addr3 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_num_players(3)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .add_player(PlayerType::Remote(addr3), 2)?
+        .with_disconnected_player_removal(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_num_players(3)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .add_player(PlayerType::Remote(addr3), 2)?
+        .with_disconnected_player_removal(true)
+        .start_p2p_session(socket2)?;
+
+    // the third player never shows up
+    sess1.disconnect_player(2)?;
+    sess2.disconnect_player(2)?;
+    assert_eq!(sess1.input_handles(0), vec![0, 1]);
+    assert!(sess1.events().any(|event| matches!(
+        event,
+        GgrsEvent::PlayerRemoved {
+            handle: 2,
+            frame: 0
+        }
+    )));
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..10 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        let requests1 = sess1.advance_frame()?;
+        let requests2 = sess2.advance_frame()?;
+        for request in requests1.iter().chain(requests2.iter()) {
+            if let GgrsRequest::AdvanceFrame { inputs } = request {
+                assert_eq!(inputs.len(), 2);
+            }
+        }
+        stub1.handle_requests(requests1);
+        stub2.handle_requests(requests2);
+    }
+
+    Ok(())
+}
+
 #[test]
 #[serial]
 fn test_synchronize_p2p_sessions() -> Result<(), GgrsError> {
@@ -99,7 +306,7 @@ fn test_synchronize_p2p_sessions() -> Result<(), GgrsError> {
 
 #[test]
 #[serial]
-fn test_advance_frame_p2p_sessions() -> Result<(), GgrsError> {
+fn test_prediction_age() -> Result<(), GgrsError> {
     let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
     let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
 
@@ -115,206 +322,4346 @@ fn test_advance_frame_p2p_sessions() -> Result<(), GgrsError> {
         .add_player(PlayerType::Local, 1)?
         .start_p2p_session(socket2)?;
 
-    assert!(sess1.current_state() == SessionState::Synchronizing);
-    assert!(sess2.current_state() == SessionState::Synchronizing);
-
     for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
     }
 
-    assert!(sess1.current_state() == SessionState::Running);
-    assert!(sess2.current_state() == SessionState::Running);
+    assert_eq!(
+        sess1.prediction_age(2),
+        Err(GgrsError::InvalidPlayerHandle { handle: 2 })
+    );
 
+    // without input from the remote player, every advanced frame is predicted
     let mut stub1 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+    assert_eq!(sess1.prediction_age(0)?, 0);
+    assert_eq!(sess1.prediction_age(1)?, 3);
+
+    // once the remote inputs arrive, the predictions are confirmed
     let mut stub2 = stubs::GameStub::new();
-    let reps = 10;
-    for i in 0..reps {
+    for i in 0..3 {
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.prediction_age(1)?, 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_pending_resimulation() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_rollback_requests(true)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
+    }
 
-        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
-        let requests1 = sess1.advance_frame().unwrap();
-        stub1.handle_requests(requests1);
-        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
-        let requests2 = sess2.advance_frame().unwrap();
-        stub2.handle_requests(requests2);
+    // sess1 predicts the remote input for 3 frames
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+    assert_eq!(sess1.pending_resimulation(), None);
 
-        // gamestate evolves
-        assert_eq!(stub1.gs.frame, i as i32 + 1);
-        assert_eq!(stub2.gs.frame, i as i32 + 1);
+    // the actual remote inputs differ from the predictions
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess2.add_local_input(1, StubInput { inp: i + 1 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
     }
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.pending_resimulation(), Some(0..3));
+
+    sess1.add_local_input(0, StubInput { inp: 3 })?;
+    let requests = sess1.advance_frame()?;
+    assert!(matches!(
+        requests[0],
+        GgrsRequest::BeginRollback {
+            from_frame: 3,
+            to_frame: 0
+        }
+    ));
+    stub1.handle_requests(requests);
+    assert_eq!(sess1.pending_resimulation(), None);
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn test_desyncs_detected() -> Result<(), GgrsError> {
+fn test_rollback_to_first_incorrect_frame() -> Result<(), GgrsError> {
     let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
     let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
-    let desync_mode = DesyncDetection::On { interval: 100 };
 
     let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
     let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_rollback_requests(true)
+        .with_rollback_events(true)
         .add_player(PlayerType::Local, 0)?
         .add_player(PlayerType::Remote(addr2), 1)?
-        .with_desync_detection_mode(desync_mode)
         .start_p2p_session(socket1)?;
 
     let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
     let mut sess2 = SessionBuilder::<StubConfig>::new()
         .add_player(PlayerType::Remote(addr1), 0)?
         .add_player(PlayerType::Local, 1)?
-        .with_desync_detection_mode(desync_mode)
         .start_p2p_session(socket2)?;
 
-    while sess1.current_state() != SessionState::Running
-        && sess2.current_state() != SessionState::Running
-    {
+    for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
     }
 
-    // drain events
-    assert!(sess1.events().chain(sess2.events()).all(|e| match e {
-        GgrsEvent::Synchronizing { .. } | GgrsEvent::Synchronized { .. } => true,
-        _ => false,
-    }));
-
+    // sess1 predicts the remote input for 4 frames
     let mut stub1 = stubs::GameStub::new();
+    for i in 0..4 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+
+    // the remote input only changes in frame 2, so the predictions for frames 0 and 1 were correct
     let mut stub2 = stubs::GameStub::new();
+    for inp in [0, 0, 7, 7] {
+        sess2.add_local_input(1, StubInput { inp })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    assert_eq!(sess1.first_incorrect_frame(), None);
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.first_incorrect_frame(), Some(2));
+    assert_eq!(sess1.pending_resimulation(), Some(2..4));
 
-    // run normally for some frames (past first desync interval)
-    for i in 0..110 {
-        sess1.poll_remote_clients();
-        sess2.poll_remote_clients();
+    sess1.add_local_input(0, StubInput { inp: 4 })?;
+    let requests = sess1.advance_frame()?;
+    assert!(matches!(
+        requests[0],
+        GgrsRequest::BeginRollback {
+            from_frame: 4,
+            to_frame: 2
+        }
+    ));
+    stub1.handle_requests(requests);
+    assert_eq!(sess1.first_incorrect_frame(), None);
+    assert_eq!(
+        sess1.rollback_stats(),
+        RollbackStats {
+            rollbacks: 1,
+            frames_resimulated: 2,
+            rollbacks_avoided: 0,
+            rollbacks_coalesced: 0,
+            late_packets: 1,
+        }
+    );
+    assert!(sess1.events().any(|event| matches!(
+        event,
+        GgrsEvent::Rollback {
+            from_frame: 4,
+            to_frame: 2,
+            cause: RollbackCause::LatePacket {
+                handle: 1,
+                frames_late: 2,
+            },
+        }
+    )));
 
-        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
-        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+    // the next remote inputs match the predictions, so there is no rollback
+    for _ in 0..2 {
+        sess1.add_local_input(0, StubInput { inp: 5 })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+    for _ in 0..3 {
+        sess2.add_local_input(1, StubInput { inp: 7 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.first_incorrect_frame(), None);
+    sess1.add_local_input(0, StubInput { inp: 5 })?;
+    let requests = sess1.advance_frame()?;
+    assert!(!requests
+        .iter()
+        .any(|request| matches!(request, GgrsRequest::BeginRollback { .. })));
+    stub1.handle_requests(requests);
+    assert_eq!(sess1.rollback_stats().rollbacks, 1);
+    assert_eq!(sess1.rollback_stats().rollbacks_avoided, 1);
 
-        let requests1 = sess1.advance_frame().unwrap();
-        let requests2 = sess2.advance_frame().unwrap();
+    Ok(())
+}
 
-        stub1.handle_requests(requests1);
-        stub2.handle_requests(requests2);
-    }
+#[test]
+#[serial]
+fn test_max_resim_frames_per_advance() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let desync_mode = DesyncDetection::On { interval: 1 };
 
-    // check that there are no unexpected events yet
-    assert_eq!(sess1.events().len(), 0);
-    assert_eq!(sess2.events().len(), 0);
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_max_resim_frames_per_advance(0)
+        .is_err());
 
-    // run for some more frames
-    for _ in 0..100 {
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_desync_detection_mode(desync_mode)
+        .with_max_resim_frames_per_advance(2)?
+        .with_rollback_requests(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
+    }
 
-        // mess up state for peer 1
-        stub1.gs.state = 1234;
-
-        // keep input steady (to avoid loads, which would restore valid state)
-        sess1.add_local_input(0, StubInput { inp: 0 }).unwrap();
-        sess2.add_local_input(1, StubInput { inp: 1 }).unwrap();
+    // sess1 predicts 6 frames, all of them wrong
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..6 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+    for _ in 0..6 {
+        sess2.add_local_input(1, StubInput { inp: 7 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.pending_resimulation(), Some(0..6));
 
-        let requests1 = sess1.advance_frame().unwrap();
-        let requests2 = sess2.advance_frame().unwrap();
+    // the rollback is spread over three calls, without advancing new frames
+    for (call, frame) in [2, 4, 6].into_iter().enumerate() {
+        sess1.add_local_input(0, StubInput { inp: 6 })?;
+        let requests = sess1.advance_frame()?;
+        let advances = requests
+            .iter()
+            .filter(|request| matches!(request, GgrsRequest::AdvanceFrame { .. }))
+            .count();
+        assert_eq!(advances, 2);
+        assert_eq!(
+            matches!(requests[0], GgrsRequest::BeginRollback { .. }),
+            call == 0
+        );
+        assert_eq!(
+            matches!(requests.last(), Some(GgrsRequest::EndRollback)),
+            frame == 6
+        );
+        stub1.handle_requests(requests);
+        assert_eq!(sess1.current_frame(), frame);
+    }
+    assert_eq!(sess1.pending_resimulation(), None);
 
-        stub1.handle_requests(requests1);
-        stub2.handle_requests(requests2);
+    // afterwards, both sessions agree on every frame
+    for i in 6..30 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 7 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
     }
+    assert_eq!(sess1.current_frame(), 30);
+    assert!(!sess1
+        .events()
+        .chain(sess2.events())
+        .any(|event| matches!(event, GgrsEvent::DesyncDetected { .. })));
 
-    // check that we got desync events
-    let sess1_events: Vec<_> = sess1.events().collect();
-    let sess2_events: Vec<_> = sess2.events().collect();
-    assert_eq!(sess1_events.len(), 1);
-    assert_eq!(sess2_events.len(), 1);
+    Ok(())
+}
 
-    let GgrsEvent::DesyncDetected {
-        frame: desync_frame1,
-        local_checksum: desync_local_checksum1,
-        remote_checksum: desync_remote_checksum1,
-        addr: desync_addr1,
-    } = sess1_events[0]
-    else {
-        panic!("no desync for peer 1");
-    };
-    assert_eq!(desync_frame1, 200);
-    assert_eq!(desync_addr1, addr2);
-    assert_ne!(desync_local_checksum1, desync_remote_checksum1);
+#[test]
+#[serial]
+fn test_rollbacks_coalesced() -> Result<(), GgrsError> {
+    let addrs = [7777, 8888, 9999]
+        .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port));
 
-    let GgrsEvent::DesyncDetected {
-        frame: desync_frame2,
-        local_checksum: desync_local_checksum2,
-        remote_checksum: desync_remote_checksum2,
-        addr: desync_addr2,
-    } = sess2_events[0]
-    else {
-        panic!("no desync for peer 2");
-    };
-    assert_eq!(desync_frame2, 200);
-    assert_eq!(desync_addr2, addr1);
-    assert_ne!(desync_local_checksum2, desync_remote_checksum2);
+    let mut sessions = Vec::new();
+    for local in 0..3 {
+        let mut builder = SessionBuilder::<StubConfig>::new().with_num_players(3);
+        for (handle, addr) in addrs.iter().enumerate() {
+            let player_type = if handle == local {
+                PlayerType::Local
+            } else {
+                PlayerType::Remote(*addr)
+            };
+            builder = builder.add_player(player_type, handle)?;
+        }
+        let socket = UdpNonBlockingSocket::bind_to_port(addrs[local].port()).unwrap();
+        sessions.push(builder.start_p2p_session(socket)?);
+    }
+    for _ in 0..50 {
+        for sess in sessions.iter_mut() {
+            sess.poll_remote_clients();
+        }
+    }
+    assert!(sessions
+        .iter()
+        .all(|sess| sess.current_state() == SessionState::Running));
 
-    // check that checksums match
-    assert_eq!(desync_remote_checksum1, desync_local_checksum2);
-    assert_eq!(desync_remote_checksum2, desync_local_checksum1);
+    // the first session predicts the remote inputs for a few frames
+    let mut stubs = [(); 3].map(|_| stubs::GameStub::new());
+    for _ in 0..4 {
+        sessions[0].add_local_input(0, StubInput { inp: 0 })?;
+        stubs[0].handle_requests(sessions[0].advance_frame()?);
+    }
+
+    // then both remote players' inputs arrive in several batches, all mispredicted
+    for batch in 0..2 {
+        for handle in 1..3 {
+            for _ in 0..2 {
+                sessions[handle].add_local_input(handle, StubInput { inp: 1 })?;
+                stubs[handle].handle_requests(sessions[handle].advance_frame()?);
+            }
+        }
+        if batch == 0 {
+            sessions[0].poll_remote_clients();
+        }
+    }
+
+    // a single rollback to the earliest incorrect frame corrects all of them
+    sessions[0].add_local_input(0, StubInput { inp: 0 })?;
+    let requests = sessions[0].advance_frame()?;
+    let loads = requests
+        .iter()
+        .filter(|request| matches!(request, GgrsRequest::LoadGameState { .. }))
+        .count();
+    assert_eq!(loads, 1);
+    stubs[0].handle_requests(requests);
+    assert_eq!(sessions[0].rollback_stats().rollbacks, 1);
+    assert_eq!(sessions[0].rollback_stats().rollbacks_coalesced, 1);
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn test_desyncs_and_input_delay_no_panic() -> Result<(), GgrsError> {
+fn test_network_history() -> Result<(), GgrsError> {
     let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
     let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
-    let desync_mode = DesyncDetection::On { interval: 100 };
 
     let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
     let mut sess1 = SessionBuilder::<StubConfig>::new()
         .add_player(PlayerType::Local, 0)?
         .add_player(PlayerType::Remote(addr2), 1)?
-        .with_input_delay(5)
-        .with_desync_detection_mode(desync_mode)
         .start_p2p_session(socket1)?;
 
     let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
     let mut sess2 = SessionBuilder::<StubConfig>::new()
         .add_player(PlayerType::Remote(addr1), 0)?
         .add_player(PlayerType::Local, 1)?
-        .with_input_delay(5)
-        .with_desync_detection_mode(desync_mode)
         .start_p2p_session(socket2)?;
 
-    while sess1.current_state() != SessionState::Running
-        && sess2.current_state() != SessionState::Running
-    {
+    assert_eq!(
+        sess1.network_history(0),
+        Err(GgrsError::InvalidPlayerHandle { handle: 0 })
+    );
+    assert!(sess1.network_history(1)?.is_empty());
+
+    for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
     }
 
-    // drain events
-    assert!(sess1.events().chain(sess2.events()).all(|e| match e {
-        GgrsEvent::Synchronizing { .. } | GgrsEvent::Synchronized { .. } => true,
-        _ => false,
-    }));
-
     let mut stub1 = stubs::GameStub::new();
     let mut stub2 = stubs::GameStub::new();
-
-    // run normally for some frames (past first desync interval)
-    for i in 0..150 {
+    for i in 0..300 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
 
-        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
-        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
 
-        let requests1 = sess1.advance_frame().unwrap();
-        let requests2 = sess2.advance_frame().unwrap();
+    // only the most recent samples are kept, at most one per frame
+    let history = sess1.network_history(1)?;
+    assert_eq!(history.len(), 120);
+    assert!(history.windows(2).all(|w| w[0].frame < w[1].frame));
+    assert!(history.last().unwrap().frame <= sess1.current_frame());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_advance_frame_p2p_sessions() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    assert!(sess1.current_state() == SessionState::Synchronizing);
+    assert!(sess2.current_state() == SessionState::Synchronizing);
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
 
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let reps = 10;
+    for i in 0..reps {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
+        let requests1 = sess1.advance_frame().unwrap();
         stub1.handle_requests(requests1);
+        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+        let requests2 = sess2.advance_frame().unwrap();
         stub2.handle_requests(requests2);
+
+        // gamestate evolves
+        assert_eq!(stub1.gs.frame, i as i32 + 1);
+        assert_eq!(stub2.gs.frame, i as i32 + 1);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_state_diffing() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_state_diffing(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_state_diffing(true)
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        // differing inputs cause mispredictions and thus rollbacks to compacted states
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i / 3 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+
+    // after all inputs arrived, both sessions agree on the state
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    sess2.add_local_input(1, StubInput { inp: 0 })?;
+    stub2.handle_requests(sess2.advance_frame()?);
+    assert!(sess1.confirmed_frame() >= 49);
+    assert!(sess2.confirmed_frame() >= 49);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_replay_recording() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_replay_recording(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+    assert!(sess2.replay().is_none());
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i / 3 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+
+    // the replay contains all confirmed frames
+    let replay = sess1.replay().unwrap();
+    assert_eq!(replay.num_frames() as i32, sess1.confirmed_frame() + 1);
+    assert!(replay.num_frames() > 40);
+    for frame in 0..replay.num_frames() as i32 {
+        let inputs = replay.frame(frame).unwrap();
+        assert_eq!(inputs[0].inp, frame as u32);
+        assert_eq!(inputs[1].inp, frame as u32 / 3);
+    }
+
+    // the replay can be played back in a sync test
+    let replay = Replay::<StubConfig>::from_bytes(&replay.to_bytes())?;
+    let num_frames = replay.num_frames();
+    let mut sess = SyncTestSession::from_replay(replay, 4)?;
+    let mut stub = stubs::GameStub::new();
+    while !sess.is_replay_finished() {
+        stub.handle_requests(sess.advance_frame()?);
+    }
+    assert_eq!(stub.gs.frame, num_frames as i32);
+    assert!(sess.advance_frame().is_err());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_input_metadata() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert!(sess1
+        .add_local_input_with_metadata(0, StubInput { inp: 0 }, &[0; 33])
+        .is_err());
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..20 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        // only attach metadata to every other frame
+        if i % 2 == 0 {
+            sess1.add_local_input_with_metadata(0, StubInput { inp: i }, &[i as u8, 42])?;
+        } else {
+            sess1.add_local_input(0, StubInput { inp: i })?;
+        }
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    for frame in 0..20 {
+        let expected = (frame % 2 == 0).then(|| vec![frame as u8, 42]);
+        assert_eq!(sess1.input_metadata(0, frame).map(<[u8]>::to_vec), expected);
+        assert_eq!(sess2.input_metadata(0, frame).map(<[u8]>::to_vec), expected);
+        assert_eq!(sess2.input_metadata(1, frame), None);
+    }
+
+    Ok(())
+}
+
+/// Forwards to a loopback socket and records the size of the largest packet sent.
+struct SizeRecordingSocket {
+    inner: LoopbackSocket<SocketAddr>,
+    largest: Arc<AtomicU64>,
+}
+
+impl NonBlockingSocket<SocketAddr> for SizeRecordingSocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        let mut buffer = Vec::new();
+        msg.encode_into(&mut buffer);
+        self.largest
+            .fetch_max(buffer.len() as u64, Ordering::Relaxed);
+        self.inner.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        self.inner.receive_all_messages()
+    }
+}
+
+#[test]
+fn test_input_metadata_fits_packet() -> Result<(), GgrsError> {
+    type LargeInputConfig = ggrs::FixedInputConfig<32, u64>;
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(15);
+    let largest = Arc::new(AtomicU64::new(0));
+
+    let socket1 = SizeRecordingSocket {
+        inner: network.socket(addr1),
+        largest: largest.clone(),
+    };
+    let mut sess1 = network.enter(|| {
+        SessionBuilder::<LargeInputConfig>::new()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .start_p2p_session(socket1)
+    })?;
+    let mut sess2 = network.enter(|| {
+        SessionBuilder::<LargeInputConfig>::new()
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(network.socket(addr2))
+    })?;
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        });
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+
+    // the second client stops acking, so all inputs of the first client stay pending and each packet holds the most
+    // inputs and metadata possible
+    let mut frame = 0u8;
+    loop {
+        let input = std::array::from_fn(|i| frame.wrapping_mul(37) ^ (i as u8).wrapping_mul(101));
+        sess1.add_local_input_with_metadata(0, input, &[frame; 32])?;
+        match network.enter(|| sess1.advance_frame()) {
+            Ok(requests) => {
+                for request in requests {
+                    if let GgrsRequest::SaveGameState { cell, frame } = request {
+                        cell.save(frame, Some(0), None);
+                    }
+                }
+            }
+            Err(GgrsError::PredictionThreshold) => break,
+            Err(e) => return Err(e),
+        }
+        frame += 1;
+        network.advance(Duration::from_millis(16));
+    }
+
+    // 512 bytes is the maximum safe UDP payload
+    assert!(largest.load(Ordering::Relaxed) <= 512);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_connection_status() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_disconnect_timeout(Duration::from_millis(200))
+        .with_disconnect_notify_delay(Duration::from_millis(50))
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    assert_eq!(sess1.connection_status(0)?, ConnectionState::Running);
+    assert!(matches!(
+        sess1.connection_status(1)?,
+        ConnectionState::Synchronizing { .. }
+    ));
+    assert!(sess1.connection_status(2).is_err());
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.connection_status(1)?, ConnectionState::Running);
+
+    // stop polling the second session until the first one disconnects it
+    let start = Instant::now();
+    while sess1.connection_status(1)? != ConnectionState::Disconnected {
+        sess1.poll_remote_clients();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    let states: Vec<_> = sess1
+        .events()
+        .filter_map(|event| match event {
+            GgrsEvent::ConnectionStateChanged { addr, state } => {
+                assert_eq!(addr, addr2);
+                Some(state)
+            }
+            _ => None,
+        })
+        .collect();
+    assert!(matches!(
+        states.first(),
+        Some(ConnectionState::Synchronizing { progress }) if *progress == 0.0
+    ));
+    let last_states = &states[states.len() - 3..];
+    assert_eq!(last_states[0], ConnectionState::Running);
+    assert!(matches!(
+        last_states[1],
+        ConnectionState::Interrupted { .. }
+    ));
+    assert_eq!(last_states[2], ConnectionState::Disconnected);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_session_state_changes() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    assert_eq!(sess1.current_state(), SessionState::Synchronizing);
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    assert!(matches!(
+        sess1.advance_frame(),
+        Err(GgrsError::NotSynchronized)
+    ));
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+
+    sess1.close();
+    sess1.close();
+    assert_eq!(sess1.current_state(), SessionState::Closed);
+    assert!(matches!(
+        sess1.add_local_input(0, StubInput { inp: 0 }),
+        Err(GgrsError::NotSynchronized)
+    ));
+    assert!(matches!(
+        sess1.advance_frame(),
+        Err(GgrsError::NotSynchronized)
+    ));
+
+    // the remote client is asked to disconnect the closed session
+    for _ in 0..10 {
+        sess2.poll_remote_clients();
     }
+    assert!(sess2
+        .events()
+        .any(|event| matches!(event, GgrsEvent::Disconnected { addr } if addr == addr1)));
+
+    let states: Vec<_> = sess1
+        .events()
+        .filter_map(|event| match event {
+            GgrsEvent::SessionStateChanged { state } => Some(state),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(states, [SessionState::Running, SessionState::Closed]);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_player_ids() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    // only local players can have an id
+    assert!(SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_player_id(1, 42)
+        .is_err());
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .with_player_id(0, 1234)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_player_id(1, u128::MAX)?
+        .start_p2p_session(socket2)?;
+
+    assert_eq!(sess1.player_id(0), Some(1234));
+    assert_eq!(sess1.player_id(1), None);
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert_eq!(sess1.player_id(1), Some(u128::MAX));
+    assert_eq!(sess2.player_id(0), Some(1234));
+    assert_eq!(sess2.handle_by_player_id(1234), Some(0));
+    assert_eq!(sess2.handle_by_player_id(5), None);
+    assert!(sess1.events().any(|event| matches!(
+        event,
+        GgrsEvent::PlayerIdentified { addr, handle: 1, player_id: u128::MAX } if addr == addr2
+    )));
+
+    Ok(())
+}
+
+#[test]
+fn test_remap_player_address() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let addr3 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+    let network = LoopbackNetwork::new(16);
+
+    let mut sess1 = network.enter(|| {
+        SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .start_p2p_session(network.socket(addr1))
+    })?;
+    let mut sess2 = network.enter(|| {
+        SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .with_player_id(1, 42)?
+            .start_p2p_session(network.socket(addr2))
+    })?;
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        });
+    }
+
+    assert!(matches!(
+        sess1.remap_player_address(0, addr3),
+        Err(GgrsError::InvalidPlayerHandle { handle: 0 })
+    ));
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..60 {
+        // the second client moves to another port, which the first client learns about later
+        if i == 20 {
+            sess2.replace_socket(network.socket(addr3));
+        }
+        if i == 30 {
+            sess1.remap_player_address(1, addr3)?;
+        }
+        network
+            .enter(|| -> Result<(), GgrsError> {
+                sess1.poll_remote_clients();
+                sess2.poll_remote_clients();
+                sess1.add_local_input(0, StubInput { inp: i })?;
+                stub1.handle_requests(sess1.advance_frame()?);
+                sess2.add_local_input(1, StubInput { inp: i })?;
+                stub2.handle_requests(sess2.advance_frame()?);
+                Ok(())
+            })
+            .or_else(|e| match e {
+                GgrsError::PredictionThreshold => Ok(()),
+                e => Err(e),
+            })?;
+        network.advance(Duration::from_millis(16));
+    }
+
+    // the player kept their handle and id, and the session caught up after the move
+    assert_eq!(sess1.active_address(1), Some(addr3));
+    assert_eq!(sess1.handle_by_player_id(42), Some(1));
+    assert_eq!(sess1.connection_status(1)?, ConnectionState::Running);
+    assert!(sess1.confirmed_frame() > 40);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_startup_policy_initial_delay() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let policy = StartupPolicy::InitialDelay { frames: 5 };
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_startup_policy(policy)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_startup_policy(policy)
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the first frames are advanced with blank inputs, local inputs are ignored
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..25 {
+        sess1.add_local_input(0, StubInput { inp: i + 1 })?;
+        let requests = sess1.advance_frame()?;
+        if i < 5 {
+            assert!(matches!(
+                requests.last(),
+                Some(GgrsRequest::AdvanceFrame { inputs })
+                    if inputs.iter().all(|input| *input == (StubInput { inp: 0 }, InputStatus::Confirmed))
+            ));
+        }
+        stub1.handle_requests(requests);
+        sess2.add_local_input(1, StubInput { inp: i + 1 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    assert_eq!(sess1.current_frame(), 25);
+    assert_eq!(sess2.current_frame(), 25);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_startup_policy_wait_for_remote_inputs() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_rollback_events(true)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_startup_policy(StartupPolicy::WaitForRemoteInputs)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_startup_policy(StartupPolicy::WaitForRemoteInputs)
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the local input is sent before stalling, so the remote client can start
+    sess1.add_local_input(0, StubInput { inp: 1 })?;
+    assert_eq!(
+        sess1.advance_frame().err(),
+        Some(GgrsError::PredictionThreshold)
+    );
+    assert!(sess1.events().any(|event| matches!(
+        event,
+        GgrsEvent::Stalled {
+            frame: 0,
+            cause: StallCause::MissingInput { handle: 1 },
+        }
+    )));
+    sess2.add_local_input(1, StubInput { inp: 2 })?;
+    let requests = sess2.advance_frame()?;
+    assert!(matches!(
+        requests.last(),
+        Some(GgrsRequest::AdvanceFrame { inputs })
+            if inputs[0] == (StubInput { inp: 1 }, InputStatus::Confirmed)
+    ));
+
+    sess1.add_local_input(0, StubInput { inp: 1 })?;
+    let requests = sess1.advance_frame()?;
+    assert!(matches!(
+        requests.last(),
+        Some(GgrsRequest::AdvanceFrame { inputs })
+            if inputs[1] == (StubInput { inp: 2 }, InputStatus::Confirmed)
+    ));
+
+    // after the first frame, the session predicts as usual
+    for i in 0..5 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        sess1.advance_frame()?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_synchronized_start() -> Result<(), GgrsError> {
+    let countdown = Duration::from_millis(300);
+    let latency = Duration::from_millis(20);
+    let started = synchronized_start([Some(countdown), Some(countdown)], latency)?;
+
+    // both sessions wait for the countdown and start at about the same time
+    assert!(started[0] >= countdown);
+    let difference = started[0].abs_diff(started[1]);
+    assert!(difference <= Duration::from_millis(5));
+
+    Ok(())
+}
+
+#[test]
+fn test_synchronized_start_one_sided() -> Result<(), GgrsError> {
+    let countdown = Duration::from_millis(300);
+    let latency = Duration::from_millis(20);
+    let started = synchronized_start([Some(countdown), None], latency)?;
+
+    // the session without a synchronized start does not propose a start time, so the other one does not wait for it
+    assert!(started[0] >= countdown);
+    assert!(started[1] < countdown);
+
+    Ok(())
+}
+
+/// Synchronizes two sessions with the given synchronized start countdowns over a network with the given latency and returns
+/// the simulated time at which each of them started.
+fn synchronized_start(
+    countdowns: [Option<Duration>; 2],
+    latency: Duration,
+) -> Result<[Duration; 2], GgrsError> {
+    let addrs = [
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777),
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888),
+    ];
+    let network = LoopbackNetwork::new(6).with_latency(latency);
+    let mut sessions = Vec::new();
+    for (i, countdown) in countdowns.into_iter().enumerate() {
+        let mut builder = SessionBuilder::<StubConfig>::new();
+        if let Some(countdown) = countdown {
+            builder = builder.with_synchronized_start(countdown);
+        }
+        let builder = if i == 0 {
+            builder
+                .add_player(PlayerType::Local, 0)?
+                .add_player(PlayerType::Remote(addrs[1]), 1)?
+        } else {
+            builder
+                .add_player(PlayerType::Remote(addrs[0]), 0)?
+                .add_player(PlayerType::Local, 1)?
+        };
+        sessions.push(network.enter(|| builder.start_p2p_session(network.socket(addrs[i])))?);
+    }
+    assert_eq!(sessions[0].time_until_start(), None);
+
+    let mut started = [None, None];
+    let mut elapsed = Duration::ZERO;
+    while started.contains(&None) {
+        network.advance(Duration::from_millis(1));
+        elapsed += Duration::from_millis(1);
+        network.enter(|| {
+            for ((sess, started), countdown) in
+                sessions.iter_mut().zip(&mut started).zip(countdowns)
+            {
+                sess.poll_remote_clients();
+                if started.is_none() && sess.current_state() == SessionState::Running {
+                    *started = Some(elapsed);
+                }
+                // the agreed start is the latest proposal, which the remote client may have made a one-way trip later
+                if started.is_none() {
+                    assert!(sess
+                        .time_until_start()
+                        .is_none_or(|left| left <= countdown.unwrap_or_default() + latency));
+                }
+            }
+        });
+        assert!(elapsed < Duration::from_secs(2));
+    }
+    for (sess, countdown) in sessions.iter().zip(countdowns) {
+        let expected = countdown.map(|_| Duration::ZERO);
+        assert_eq!(network.enter(|| sess.time_until_start()), expected);
+    }
+    Ok(started.map(Option::unwrap))
+}
+
+#[test]
+#[serial]
+fn test_remote_candidates() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    // nobody listens on this address, so the second client only answers on its candidate
+    let unreachable = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(unreachable), 1)?
+        .with_remote_candidates(unreachable, vec![addr2])
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    assert_eq!(sess1.active_address(1), None);
+    assert_eq!(sess2.active_address(0), Some(addr1));
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+    assert_eq!(sess1.active_address(1), Some(addr2));
+    // events keep referring to the address the player was added with
+    assert!(sess1
+        .events()
+        .any(|event| matches!(event, GgrsEvent::Synchronized { addr } if addr == unreachable)));
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..10 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    sess1.poll_remote_clients();
+    assert!(sess1.confirmed_frame() > 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_protocol_mismatch() -> Result<(), GgrsError> {
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    // a sync request from a client speaking a newer protocol version
+    let mut request = vec![0, 0, 0];
+    request.extend_from_slice(&0u32.to_le_bytes());
+    request.extend_from_slice(&42u32.to_le_bytes());
+    request.extend_from_slice(&(ggrs::PROTOCOL_VERSION + 1).to_le_bytes());
+    let remote = std::net::UdpSocket::bind(addr2).unwrap();
+    remote.set_nonblocking(true).unwrap();
+    for _ in 0..3 {
+        remote
+            .send_to(&request, SocketAddr::new(addr2.ip(), 7777))
+            .unwrap();
+    }
+
+    std::thread::sleep(Duration::from_millis(20));
+    sess1.poll_remote_clients();
+    let mismatches = sess1
+        .events()
+        .filter(|event| {
+            matches!(event, GgrsEvent::ProtocolMismatch { addr, remote_version }
+                if *addr == addr2 && *remote_version == ggrs::PROTOCOL_VERSION + 1)
+        })
+        .count();
+    assert_eq!(mismatches, 1);
+
+    // the requests were not answered, only the sync requests of the session arrived
+    let mut buffer = [0; 4096];
+    while let Ok(len) = remote.recv(&mut buffer) {
+        assert!(ggrs::parse_packet(&buffer[..len]).is_ok());
+        assert_eq!(buffer[3..7], 0u32.to_le_bytes());
+    }
+    assert_eq!(sess1.current_state(), SessionState::Synchronizing);
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_variable_length_inputs() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_variable_length_inputs(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_variable_length_inputs(true)
+        .with_replay_recording(true)
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the inputs grow from zero to four meaningful bytes
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..40u32 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i.pow(6) })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 3 * i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the inputs arrived intact
+    let replay = sess2.replay().unwrap();
+    assert!(replay.num_frames() > 30);
+    for frame in 0..replay.num_frames() as i32 {
+        let inputs = replay.frame(frame).unwrap();
+        assert_eq!(inputs[0].inp, (frame as u32).pow(6));
+        assert_eq!(inputs[1].inp, 3 * frame as u32);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_variable_length_inputs_mismatch() -> Result<(), GgrsError> {
+    assert_handshake_mismatch(
+        SessionBuilder::new(),
+        SessionBuilder::new().with_variable_length_inputs(true),
+        [
+            HandshakeMismatch::VariableLengthInputs {
+                local: false,
+                remote: true,
+            },
+            HandshakeMismatch::VariableLengthInputs {
+                local: true,
+                remote: false,
+            },
+        ],
+    )
+}
+
+/// Lets two sessions built from the given builders synchronize with each other and asserts that both report the mismatch
+/// as seen from their side and disconnect.
+fn assert_handshake_mismatch(
+    builder1: SessionBuilder<StubConfig>,
+    builder2: SessionBuilder<StubConfig>,
+    mismatches: [HandshakeMismatch; 2],
+) -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(2);
+    let mut sessions = network.enter(|| -> Result<_, GgrsError> {
+        let sess1 = builder1
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .start_p2p_session(network.socket(addr1))?;
+        let sess2 = builder2
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(network.socket(addr2))?;
+        Ok([sess1, sess2])
+    })?;
+
+    let mut events = [Vec::new(), Vec::new()];
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            for (sess, events) in sessions.iter_mut().zip(&mut events) {
+                sess.poll_remote_clients();
+                events.extend(sess.events());
+            }
+        });
+    }
+
+    for (events, expected) in events.iter().zip(mismatches) {
+        assert!(events.iter().any(|event| matches!(
+            event,
+            GgrsEvent::HandshakeMismatch { mismatch, .. } if *mismatch == expected
+        )));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, GgrsEvent::Disconnected { .. })));
+    }
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_input_coalescing() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    // 10 command turns per second, the first one waits for the remote commands
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_fps(10)?
+        .with_input_coalescing(true)
+        .with_startup_policy(StartupPolicy::WaitForRemoteInputs)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_fps(10)?
+        .with_input_coalescing(true)
+        .with_replay_recording(true)
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+
+    // the first turn is sent, then the session stalls. Commands given meanwhile go into the next turn.
+    sess1.add_local_input(0, StubInput { inp: 1 })?;
+    assert!(matches!(
+        sess1.advance_frame().err(),
+        Some(GgrsError::PredictionThreshold)
+    ));
+    sess1.add_local_input(0, StubInput { inp: 100 })?;
+
+    // several commands within a turn are merged
+    sess2.add_local_input(1, StubInput { inp: 7 })?;
+    sess2.add_local_input(1, StubInput { inp: 8 })?;
+    stub2.handle_requests(sess2.advance_frame()?);
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    stub1.handle_requests(sess1.advance_frame()?);
+
+    for i in 0..10 {
+        sess1.add_local_input(
+            0,
+            StubInput {
+                inp: 5 * (i == 0) as u32,
+            },
+        )?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 0 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let replay = sess2.replay().unwrap();
+    assert!(replay.num_frames() > 2);
+    assert_eq!(replay.frame(0).unwrap()[0].inp, 1);
+    assert_eq!(replay.frame(0).unwrap()[1].inp, 15);
+    assert_eq!(replay.frame(1).unwrap()[0].inp, 105);
+    assert_eq!(replay.frame(2).unwrap()[0].inp, 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_no_rollbacks_with_local_players_only() -> Result<(), GgrsError> {
+    // local inputs are always known, so no combination of input delay and saving mode may cause a rollback
+    for (input_delay, sparse_saving) in [(0, false), (0, true), (1, true), (3, false), (3, true)] {
+        let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+        let mut sess = SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Local, 1)?
+            .with_input_delay(input_delay)
+            .with_sparse_saving_mode(sparse_saving)
+            .start_p2p_session(socket)?;
+
+        let mut stub = stubs::GameStub::new();
+        for i in 0..100 {
+            sess.add_local_input(0, StubInput { inp: i % 7 })?;
+            sess.add_local_input(1, StubInput { inp: i % 5 })?;
+            let requests = sess.advance_frame()?;
+            assert!(!requests
+                .iter()
+                .any(|request| matches!(request, GgrsRequest::BeginRollback { .. })));
+            stub.handle_requests(requests);
+        }
+        assert_eq!(stub.gs.frame, 100);
+        assert_eq!(sess.rollback_stats(), RollbackStats::default());
+    }
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_memory_usage() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_replay_recording(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let before = sess1.memory_usage();
+    assert_eq!(before.replay, 0);
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..100u32 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+
+    let report = sess1.memory_usage();
+    assert!(report.saved_states > 0);
+    assert!(report.input_queues > 0);
+    // two inputs of four bytes for every confirmed frame
+    let replay_frames = sess1.replay().unwrap().num_frames();
+    assert!(report.replay >= replay_frames * 8);
+    assert_eq!(
+        report.total(),
+        report.saved_states + report.input_queues + report.pending_packets + report.replay
+    );
+    // the replay is not recorded on the second peer
+    assert_eq!(sess2.memory_usage().replay, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_soak_with_packet_loss() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let network = LoopbackNetwork::new(42)
+        .with_latency(Duration::from_millis(150))
+        .with_jitter(Duration::from_millis(20))
+        .with_packet_loss(0.05)?;
+    let games = [stubs::GameStub::new(), stubs::GameStub::new()];
+    let mut soak = SoakTest::new(network, [addr1, addr2], SessionBuilder::new, games)?;
+
+    let report = soak.run(2000);
+    assert!(report.frames >= 2000);
+    assert!(report.desyncs.is_empty());
+    assert!(report.rollbacks > 0);
+    assert!(report.packets_dropped > 0);
+    assert_eq!(soak.game(0).gs.frame, soak.session(0).current_frame());
+
+    Ok(())
+}
+
+#[test]
+fn test_soak_with_bandwidth_limit() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let network = LoopbackNetwork::new(3).with_latency(Duration::from_millis(50));
+    let games = [stubs::GameStub::new(), stubs::GameStub::new()];
+    let mut unlimited = SoakTest::new(network, [addr1, addr2], SessionBuilder::new, games)?;
+    let unlimited_report = unlimited.run(600);
+
+    let network = LoopbackNetwork::new(3).with_latency(Duration::from_millis(50));
+    let games = [stubs::GameStub::new(), stubs::GameStub::new()];
+    let builder = || SessionBuilder::new().with_bandwidth_limit(1500).unwrap();
+    let mut limited = SoakTest::new(network.clone(), [addr1, addr2], builder, games)?;
+    let report = limited.run(600);
+
+    assert!(report.frames >= 600);
+    assert!(report.desyncs.is_empty());
+    assert!(report.packets_sent < unlimited_report.packets_sent);
+    let stats = network.enter(|| limited.session(0).network_stats(1))?;
+    assert!(stats.sends_deferred > 0);
+    assert!(stats.kbps_sent <= 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_soak_with_input_redundancy() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let network = LoopbackNetwork::new(5)
+        .with_latency(Duration::from_millis(10))
+        .with_packet_loss(0.1)?;
+    let games = [stubs::GameStub::new(), stubs::GameStub::new()];
+    let mut plain = SoakTest::new(network.clone(), [addr1, addr2], SessionBuilder::new, games)?;
+    plain.run(600);
+    let plain_stats = network.enter(|| plain.session(0).network_stats(1))?;
+
+    let network = LoopbackNetwork::new(5)
+        .with_latency(Duration::from_millis(10))
+        .with_packet_loss(0.1)?;
+    let games = [stubs::GameStub::new(), stubs::GameStub::new()];
+    let builder = || SessionBuilder::new().with_input_redundancy(6).unwrap();
+    let mut redundant = SoakTest::new(network.clone(), [addr1, addr2], builder, games)?;
+    let report = redundant.run(600);
+
+    assert!(report.frames >= 600);
+    assert!(report.desyncs.is_empty());
+    let stats = network.enter(|| redundant.session(0).network_stats(1))?;
+    assert!(stats.input_redundancy >= 6);
+    assert!(stats.input_redundancy > plain_stats.input_redundancy);
+
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_input_redundancy(0)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_export_diagnostics() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let network = LoopbackNetwork::new(11)
+        .with_latency(Duration::from_millis(100))
+        .with_packet_loss(0.05)?;
+    let games = [stubs::GameStub::new(), stubs::GameStub::new()];
+    let mut soak = SoakTest::new(network.clone(), [addr1, addr2], SessionBuilder::new, games)?;
+    let report = soak.run(300);
+
+    let diagnostics: Vec<DiagnosticsReport> = network.enter(|| {
+        (0..2)
+            .map(|h| soak.session(h).export_diagnostics())
+            .collect()
+    });
+    let rollbacks: usize = diagnostics.iter().map(|d| d.rollback_stats.rollbacks).sum();
+    let stalls: usize = diagnostics.iter().map(|d| d.stalls).sum();
+    assert_eq!(rollbacks, report.rollbacks);
+    assert_eq!(stalls, report.stalls);
+
+    let diagnostics = &diagnostics[0];
+    assert_eq!(diagnostics.desyncs, 0);
+    assert_eq!(diagnostics.settings.num_players, 2);
+    assert_eq!(diagnostics.peers.len(), 1);
+    assert_eq!(diagnostics.peers[0].handles, vec![1]);
+    assert!(!diagnostics.peers[0].spectator);
+    assert!(diagnostics.peers[0].network_stats.is_some());
+
+    let bytes = bincode::serialize(diagnostics).unwrap();
+    let decoded: DiagnosticsReport = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(&decoded, diagnostics);
+
+    Ok(())
+}
+
+#[test]
+fn test_memory_stays_flat_in_long_sessions() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    // a low frame rate keeps the ten simulated hours quick to run
+    let fps = 2;
+    let frames_per_hour = 3600 * fps as i32;
+    let network = LoopbackNetwork::new(5).with_latency(Duration::from_millis(60));
+    let games = [stubs::GameStub::new(), stubs::GameStub::new()];
+    let builder = || {
+        SessionBuilder::new()
+            .with_fps(fps)
+            .unwrap()
+            .with_replay_recording(true)
+    };
+    let mut soak = SoakTest::new(network, [addr1, addr2], builder, games)?;
+
+    let mut replay = Replay::new(2);
+    let mut first_hour = None;
+    for hour in 1..=10 {
+        let report = soak.run(hour * frames_per_hour);
+        assert!(report.frames >= hour * frames_per_hour);
+        assert!(report.desyncs.is_empty());
+
+        let memory = soak.session(0).memory_usage();
+        assert_eq!(*first_hour.get_or_insert(memory), memory);
+        replay.append(soak.session_mut(0).take_replay_chunk().unwrap())?;
+    }
+    assert!(replay.num_frames() as i32 >= 10 * frames_per_hour - 10);
+
+    Ok(())
+}
+
+#[test]
+fn test_prediction_threshold_keeps_rollback() -> Result<(), GgrsError> {
+    let addrs: Vec<_> = [7777, 8888, 9999]
+        .into_iter()
+        .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port))
+        .collect();
+    let network = LoopbackNetwork::new(15);
+    let mut sessions = Vec::new();
+    for local in 0..3 {
+        let mut builder = SessionBuilder::<StubConfig>::new()
+            .with_num_players(3)
+            .with_max_prediction_window(4)?;
+        for (handle, addr) in addrs.iter().enumerate() {
+            let player = match handle == local {
+                true => PlayerType::Local,
+                false => PlayerType::Remote(*addr),
+            };
+            builder = builder.add_player(player, handle)?;
+        }
+        sessions.push(network.enter(|| builder.start_p2p_session(network.socket(addrs[local])))?);
+    }
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            sessions
+                .iter_mut()
+                .for_each(|sess| drop(sess.poll_remote_clients()))
+        });
+    }
+    assert!(sessions
+        .iter()
+        .all(|sess| sess.current_state() == SessionState::Running));
+
+    // the third client hitches from now on, so the first one runs into the prediction threshold
+    let mut stubs = [stubs::GameStub::new(), stubs::GameStub::new()];
+    loop {
+        sessions[0].add_local_input(0, StubInput { inp: 0 })?;
+        match network.enter(|| sessions[0].advance_frame()) {
+            Ok(requests) => stubs[0].handle_requests(requests),
+            Err(GgrsError::PredictionThreshold) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    // the inputs of the second client contradict the predictions of the first one
+    for i in 0..4 {
+        sessions[1].add_local_input(1, StubInput { inp: i + 1 })?;
+        let requests = network.enter(|| sessions[1].advance_frame())?;
+        stubs[1].handle_requests(requests);
+    }
+    network.enter(|| sessions[0].poll_remote_clients());
+    assert!(sessions[0].first_incorrect_frame().is_some());
+
+    // the rollback cannot be followed by the next frame, so it is kept for later instead of being dropped
+    sessions[0].add_local_input(0, StubInput { inp: 0 })?;
+    assert!(matches!(
+        network.enter(|| sessions[0].advance_frame()),
+        Err(GgrsError::PredictionThreshold)
+    ));
+    assert!(sessions[0].first_incorrect_frame().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_no_false_desyncs_during_rollbacks() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(16)
+        .with_latency(Duration::from_millis(150))
+        .with_jitter(Duration::from_millis(20));
+
+    let mut sessions = Vec::new();
+    for (local, local_addr, remote) in [(0, addr1, addr2), (1, addr2, addr1)] {
+        let session = network.enter(|| {
+            SessionBuilder::<StubConfig>::new()
+                .with_desync_detection_mode(DesyncDetection::On { interval: 1 })
+                .add_player(PlayerType::Local, local)?
+                .add_player(PlayerType::Remote(remote), 1 - local)?
+                .start_p2p_session(network.socket(local_addr))
+        })?;
+        sessions.push(session);
+    }
+
+    let mut stubs = [stubs::GameStub::new(), stubs::GameStub::new()];
+    let mut rollbacks = 0;
+    for _ in 0..600 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| -> Result<(), GgrsError> {
+            for (handle, sess) in sessions.iter_mut().enumerate() {
+                sess.poll_remote_clients();
+                // checksums of states a rollback is about to resimulate must not be compared
+                for event in sess.events() {
+                    assert!(!matches!(event, GgrsEvent::DesyncDetected { .. }));
+                }
+                if sess.current_state() != SessionState::Running {
+                    continue;
+                }
+                let frame = sess.current_frame() as u32;
+                sess.add_local_input(
+                    handle,
+                    StubInput {
+                        inp: (frame / 5 + handle as u32) % 3,
+                    },
+                )?;
+                let requests = match sess.advance_frame() {
+                    Ok(requests) => requests,
+                    Err(GgrsError::PredictionThreshold) => continue,
+                    Err(e) => return Err(e),
+                };
+                rollbacks += requests
+                    .iter()
+                    .filter(|request| matches!(request, GgrsRequest::LoadGameState { .. }))
+                    .count();
+                stubs[handle].handle_requests(requests);
+            }
+            Ok(())
+        })?;
+    }
+    assert!(rollbacks > 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_soak_detects_desyncs() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let network = LoopbackNetwork::new(7).with_latency(Duration::from_millis(50));
+    let games = [
+        stubs::RandomChecksumGameStub::new(),
+        stubs::RandomChecksumGameStub::new(),
+    ];
+    let mut soak = SoakTest::new(network, [addr1, addr2], SessionBuilder::new, games)?;
+
+    let report = soak.run(200);
+    assert!(!report.desyncs.is_empty());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_advance_frame_with_input() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        let mut polled = Vec::new();
+        let requests1 = sess1.advance_frame_with_input(|handle| {
+            polled.push(handle);
+            StubInput { inp: i }
+        })?;
+        stub1.handle_requests(requests1);
+        let requests2 = sess2.advance_frame_with_input(|handle| {
+            polled.push(handle);
+            StubInput { inp: i }
+        })?;
+        stub2.handle_requests(requests2);
+
+        // every local player is polled exactly once per frame
+        assert_eq!(polled, vec![0, 1]);
+        assert_eq!(stub1.gs.frame, i as i32 + 1);
+        assert_eq!(stub2.gs.frame, i as i32 + 1);
+    }
+
+    // polling does not mix with adding input yourself
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    assert!(matches!(
+        sess1.advance_frame_with_input(|_| StubInput { inp: 0 }),
+        Err(GgrsError::InputAlreadyAdded {
+            handle: 0,
+            frame: 10
+        })
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_force_rollback_to() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_rollback_requests(true)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    assert!(matches!(
+        sess1.force_rollback_to(0),
+        Err(GgrsError::NotSynchronized)
+    ));
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the remote client does not advance, so all frames are predicted
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..5 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+
+    assert!(matches!(
+        sess1.force_rollback_to(5),
+        Err(GgrsError::StateUnavailable {
+            frame: 5,
+            current_frame: 5
+        })
+    ));
+    sess1.force_rollback_to(2)?;
+    assert_eq!(sess1.pending_resimulation(), Some(2..5));
+
+    sess1.add_local_input(0, StubInput { inp: 5 })?;
+    let requests = sess1.advance_frame()?;
+    assert!(matches!(
+        requests[0],
+        GgrsRequest::BeginRollback {
+            from_frame: 5,
+            to_frame: 2
+        }
+    ));
+    stub1.handle_requests(requests);
+    assert_eq!(stub1.gs.frame, 6);
+    assert_eq!(sess1.rollback_stats().rollbacks, 1);
+    assert_eq!(sess1.rollback_stats().frames_resimulated, 3);
+    assert_eq!(sess1.pending_resimulation(), None);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_save_interval() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_save_interval(0)
+        .is_err());
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_save_interval(4)?
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_save_interval(4)?
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the remote input changes every frame, so sess1 mispredicts and rolls back all the time
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut loaded_frames = Vec::new();
+    for i in 0..40 {
+        sess1.add_local_input(0, StubInput { inp: 0 })?;
+        let requests = sess1.advance_frame()?;
+        for request in &requests {
+            match request {
+                GgrsRequest::SaveGameState { frame, .. } => assert_eq!(frame % 4, 0),
+                GgrsRequest::LoadGameState { frame, .. } => loaded_frames.push(*frame),
+                _ => (),
+            }
+        }
+        stub1.handle_requests(requests);
+
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+
+    // rollbacks start at the latest saved frame
+    assert!(!loaded_frames.is_empty());
+    assert!(loaded_frames.iter().all(|frame| frame % 4 == 0));
+
+    // resimulating from older saves leads to the same state as in the session without mispredictions (the last input is predicted correctly)
+    sess1.poll_remote_clients();
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    sess2.poll_remote_clients();
+    sess2.add_local_input(1, StubInput { inp: 39 })?;
+    stub2.handle_requests(sess2.advance_frame()?);
+    assert_eq!(stub1.gs.frame, stub2.gs.frame);
+    assert_eq!(stub1.gs.state, stub2.gs.state);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_frames_until_stall() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_rollback_events(true)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    let max_prediction = sess1.max_prediction();
+    assert_eq!(sess1.frames_until_stall(), max_prediction);
+
+    // advance only the first session, so it never receives remote input
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..max_prediction {
+        sess1.add_local_input(0, StubInput { inp: i as u32 })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        assert_eq!(sess1.frames_until_stall(), max_prediction - i - 1);
+    }
+
+    // the next frame should stall, since no input of the remote player arrived for any frame so far
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    assert_eq!(
+        sess1.advance_frame().err(),
+        Some(GgrsError::PredictionThreshold)
+    );
+    assert!(sess1.events().any(|event| matches!(
+        event,
+        GgrsEvent::Stalled {
+            frame,
+            cause: StallCause::PredictionThreshold {
+                handle: 1,
+                frames_ahead,
+            },
+        } if frame == max_prediction as i32 && frames_ahead == max_prediction + 1
+    )));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_delay_based_fallback() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_input_delay(2)
+        .with_delay_based_fallback(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_input_delay(2)
+        .with_delay_based_fallback(true)
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+    assert!(sess1.is_delay_based());
+    assert!(sess2.is_delay_based());
+
+    // without any remote input, the session stalls right away instead of predicting
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    assert_eq!(
+        sess1.advance_frame().err(),
+        Some(GgrsError::PredictionThreshold)
+    );
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut iterations = 0;
+    while stub1.gs.frame < 20 || stub2.gs.frame < 20 {
+        iterations += 1;
+        assert!(
+            iterations < 1000,
+            "delay-based sessions did not make progress"
+        );
+
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        for (sess, stub, handle) in [(&mut sess1, &mut stub1, 0), (&mut sess2, &mut stub2, 1)] {
+            if stub.gs.frame >= 20 {
+                continue;
+            }
+            sess.add_local_input(
+                handle,
+                StubInput {
+                    inp: stub.gs.frame as u32,
+                },
+            )?;
+            let Ok(requests) = sess.advance_frame() else {
+                continue;
+            };
+
+            // no saves, no loads, only confirmed inputs
+            assert_eq!(requests.len(), 1);
+            let GgrsRequest::AdvanceFrame { inputs } = &requests[0] else {
+                panic!("expected only an advance frame request");
+            };
+            assert!(inputs
+                .iter()
+                .all(|(_, status)| *status == InputStatus::Confirmed));
+            stub.handle_requests(requests);
+        }
+    }
+
+    assert_eq!(stub1.gs.state, stub2.gs.state);
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_delay_based_fallback_not_agreed() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_delay_based_fallback(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+    assert!(!sess1.is_delay_based());
+    assert!(!sess2.is_delay_based());
+
+    // the session predicts as usual
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    assert!(sess1.advance_frame().is_ok());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_fps_mismatch_detection() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_fps_mismatch_detection(-1.0)
+        .is_err());
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_fps_mismatch_detection(5.0)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_fps(30)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // both sessions advance much faster than 60 fps, so the remote is detected as too fast once the measurement is done
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let start = Instant::now();
+    let mut mismatch = None;
+    while mismatch.is_none() && start.elapsed() < Duration::from_secs(5) {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        // the sessions might need to wait for each other
+        sess1.add_local_input(0, StubInput { inp: 0 })?;
+        if let Ok(requests) = sess1.advance_frame() {
+            stub1.handle_requests(requests);
+        }
+        sess2.add_local_input(1, StubInput { inp: 0 })?;
+        if let Ok(requests) = sess2.advance_frame() {
+            stub2.handle_requests(requests);
+        }
+
+        mismatch = sess1.events().find_map(|event| match event {
+            GgrsEvent::FpsMismatch {
+                addr,
+                declared_fps,
+                observed_fps,
+            } => Some((addr, declared_fps, observed_fps)),
+            _ => None,
+        });
+        std::thread::sleep(Duration::from_millis(2));
+    }
+
+    let (addr, declared_fps, observed_fps) = mismatch.expect("no fps mismatch detected");
+    assert_eq!(addr, addr2);
+    assert_eq!(declared_fps, 30);
+    assert!(observed_fps > 65.0);
+
+    // detection is only active if enabled
+    assert!(!sess2
+        .events()
+        .any(|event| matches!(event, GgrsEvent::FpsMismatch { .. })));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_time_sync_suggestions() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_time_sync_suggestions(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..30 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
+        stub1.handle_requests(sess1.advance_frame().unwrap());
+        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+        stub2.handle_requests(sess2.advance_frame().unwrap());
+    }
+
+    let suggestions: Vec<f64> = sess1
+        .events()
+        .filter_map(|event| match event {
+            GgrsEvent::TimeSyncSuggestion { speed_factor } => Some(speed_factor),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(suggestions.len(), 3);
+    assert!(suggestions
+        .iter()
+        .all(|speed_factor| (0.9..=1.1).contains(speed_factor)));
+
+    // suggestions are only sent if enabled
+    assert!(!sess2
+        .events()
+        .any(|event| matches!(event, GgrsEvent::TimeSyncSuggestion { .. })));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_desyncs_detected() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let desync_mode = DesyncDetection::On { interval: 100 };
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket2)?;
+
+    while sess1.current_state() != SessionState::Running
+        && sess2.current_state() != SessionState::Running
+    {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // drain events
+    assert!(sess1.events().chain(sess2.events()).all(|e| matches!(
+        e,
+        GgrsEvent::Synchronizing { .. }
+            | GgrsEvent::Synchronized { .. }
+            | GgrsEvent::ConnectionStateChanged { .. }
+            | GgrsEvent::SessionStateChanged { .. }
+    )));
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+
+    // run normally for some frames (past first desync interval)
+    for i in 0..110 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
+        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+
+        let requests1 = sess1.advance_frame().unwrap();
+        let requests2 = sess2.advance_frame().unwrap();
+
+        stub1.handle_requests(requests1);
+        stub2.handle_requests(requests2);
+    }
+
+    // check that there are no unexpected events yet
+    assert_eq!(sess1.events().len(), 0);
+    assert_eq!(sess2.events().len(), 0);
+
+    // run for some more frames
+    for _ in 0..100 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        // mess up state for peer 1
+        stub1.gs.state = 1234;
+
+        // keep input steady (to avoid loads, which would restore valid state)
+        sess1.add_local_input(0, StubInput { inp: 0 }).unwrap();
+        sess2.add_local_input(1, StubInput { inp: 1 }).unwrap();
+
+        let requests1 = sess1.advance_frame().unwrap();
+        let requests2 = sess2.advance_frame().unwrap();
+
+        stub1.handle_requests(requests1);
+        stub2.handle_requests(requests2);
+    }
+
+    // check that we got desync events
+    let sess1_events: Vec<_> = sess1.events().collect();
+    let sess2_events: Vec<_> = sess2.events().collect();
+    assert_eq!(sess1_events.len(), 1);
+    assert_eq!(sess2_events.len(), 1);
+
+    let GgrsEvent::DesyncDetected {
+        frame: desync_frame1,
+        local_checksum: desync_local_checksum1,
+        remote_checksum: desync_remote_checksum1,
+        addr: desync_addr1,
+    } = sess1_events[0]
+    else {
+        panic!("no desync for peer 1");
+    };
+    assert_eq!(desync_frame1, 200);
+    assert_eq!(desync_addr1, addr2);
+    assert_ne!(desync_local_checksum1, desync_remote_checksum1);
+
+    let GgrsEvent::DesyncDetected {
+        frame: desync_frame2,
+        local_checksum: desync_local_checksum2,
+        remote_checksum: desync_remote_checksum2,
+        addr: desync_addr2,
+    } = sess2_events[0]
+    else {
+        panic!("no desync for peer 2");
+    };
+    assert_eq!(desync_frame2, 200);
+    assert_eq!(desync_addr2, addr1);
+    assert_ne!(desync_local_checksum2, desync_remote_checksum2);
+
+    // check that checksums match
+    assert_eq!(desync_remote_checksum1, desync_local_checksum2);
+    assert_eq!(desync_remote_checksum2, desync_local_checksum1);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_desyncs_and_input_delay_no_panic() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let desync_mode = DesyncDetection::On { interval: 100 };
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_input_delay(5)
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_input_delay(5)
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket2)?;
+
+    while sess1.current_state() != SessionState::Running
+        && sess2.current_state() != SessionState::Running
+    {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // drain events
+    assert!(sess1.events().chain(sess2.events()).all(|e| matches!(
+        e,
+        GgrsEvent::Synchronizing { .. }
+            | GgrsEvent::Synchronized { .. }
+            | GgrsEvent::ConnectionStateChanged { .. }
+            | GgrsEvent::SessionStateChanged { .. }
+    )));
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+
+    // run normally for some frames (past first desync interval)
+    for i in 0..150 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
+        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+
+        let requests1 = sess1.advance_frame().unwrap();
+        let requests2 = sess2.advance_frame().unwrap();
+
+        stub1.handle_requests(requests1);
+        stub2.handle_requests(requests2);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_session_token() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    for (token1, token2, expected_state) in [
+        ([1; 16], [2; 16], SessionState::Synchronizing),
+        ([1; 16], [1; 16], SessionState::Running),
+    ] {
+        let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+        let mut sess1 = SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .with_session_token(token1)
+            .start_p2p_session(socket1)?;
+
+        let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+        let mut sess2 = SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .with_session_token(token2)
+            .start_p2p_session(socket2)?;
+
+        for _ in 0..50 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+
+        // with different tokens, the sessions reject each other
+        assert_eq!(sess1.current_state(), expected_state);
+        assert_eq!(sess2.current_state(), expected_state);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_socket_scheduler() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let scheduler1 = SocketScheduler::new(UdpNonBlockingSocket::bind_to_port(7777).unwrap());
+    let scheduler2 = SocketScheduler::new(UdpNonBlockingSocket::bind_to_port(8888).unwrap());
+
+    // two matches between the same two ports
+    let mut sessions = Vec::new();
+    for token in [[1; 16], [2; 16]] {
+        let sess1 = SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .with_session_token(token)
+            .start_p2p_session(scheduler1.socket_for(Some(token))?)?;
+        let sess2 = SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .with_session_token(token)
+            .start_p2p_session(scheduler2.socket_for(Some(token))?)?;
+        sessions.push(sess1);
+        sessions.push(sess2);
+    }
+    assert!(scheduler1.socket_for(Some([1; 16])).is_err());
+
+    for _ in 0..50 {
+        for sess in sessions.iter_mut() {
+            sess.poll_remote_clients();
+        }
+    }
+
+    for sess in sessions.iter() {
+        assert_eq!(sess.current_state(), SessionState::Running);
+    }
+
+    // dropping the sessions frees up their tokens
+    drop(sessions);
+    assert_eq!(scheduler1.num_sessions(), 0);
+    assert_eq!(scheduler2.num_sessions(), 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_connection_probe() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let mut socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+
+    let duration = Duration::from_millis(300);
+    let mut probe1 =
+        ConnectionProbe::<StubConfig>::new(addr2, duration).with_session_token([7; 16]);
+    let mut probe2 =
+        ConnectionProbe::<StubConfig>::new(addr1, duration).with_session_token([7; 16]);
+    loop {
+        let done1 = probe1.poll(&mut socket1);
+        let done2 = probe2.poll(&mut socket2);
+        if done1 && done2 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    for report in [probe1.report(), probe2.report()] {
+        assert!(report.pings_sent > 0);
+        assert!(report.pings_received > 0);
+        assert!(report.packet_loss < 0.5);
+        // loopback carries even the largest packets
+        assert_eq!(report.mtu_ceiling, 4096);
+        assert!(report.bars() > 0);
+    }
+
+    // packets with another session token are not answered
+    let duration = Duration::from_millis(100);
+    let mut probe1 =
+        ConnectionProbe::<StubConfig>::new(addr2, duration).with_session_token([7; 16]);
+    let mut probe2 = ConnectionProbe::<StubConfig>::new(addr1, duration);
+    while !(probe1.poll(&mut socket1) & probe2.poll(&mut socket2)) {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    for report in [probe1.report(), probe2.report()] {
+        assert!(report.pings_sent > 0);
+        assert_eq!(report.pings_received, 0);
+        assert_eq!(report.mtu_ceiling, 0);
+    }
+}
+
+#[test]
+fn test_connection_probe_clock() {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(31).with_latency(Duration::from_millis(30));
+    let mut socket1 = network.socket(addr1);
+    let mut socket2 = network.socket(addr2);
+
+    // the probes measure the simulated time of the network
+    let duration = Duration::from_millis(500);
+    let (mut probe1, mut probe2) = network.enter(|| {
+        (
+            ConnectionProbe::<StubConfig>::new(addr2, duration),
+            ConnectionProbe::<StubConfig>::new(addr1, duration),
+        )
+    });
+    let mut steps = 0;
+    while !network.enter(|| probe1.poll(&mut socket1) & probe2.poll(&mut socket2)) {
+        network.advance(Duration::from_millis(5));
+        steps += 1;
+    }
+    assert_eq!(steps, 100);
+    for report in [probe1.report(), probe2.report()] {
+        assert!(report.pings_received > 0);
+        assert!((60..70).contains(&report.ping));
+    }
+
+    // a custom clock that stands still never ends the probe
+    let clock = ManualClock::default();
+    let mut probe = ConnectionProbe::<StubConfig>::new(addr2, duration).with_clock(clock.clone());
+    assert!(!network.enter(|| probe.poll(&mut socket1)));
+    network.advance(duration);
+    assert!(!network.enter(|| probe.poll(&mut socket1)));
+    clock.advance(duration);
+    assert!(network.enter(|| probe.poll(&mut socket1)));
+}
+
+#[test]
+fn test_clock_offset() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(23)
+        .with_latency(Duration::from_millis(20))
+        .with_jitter(Duration::from_millis(10));
+
+    let mut sessions = Vec::new();
+    for (local, local_addr, remote) in [(0, addr1, addr2), (1, addr2, addr1)] {
+        let session = network.enter(|| {
+            SessionBuilder::<StubConfig>::new()
+                .add_player(PlayerType::Local, local)?
+                .add_player(PlayerType::Remote(remote), 1 - local)?
+                .start_p2p_session(network.socket(local_addr))
+        })?;
+        sessions.push(session);
+    }
+    for _ in 0..1000 {
+        network.advance(Duration::from_millis(1));
+        network.enter(|| {
+            sessions
+                .iter_mut()
+                .for_each(|sess| drop(sess.poll_remote_clients()))
+        });
+    }
+    assert!(sessions
+        .iter()
+        .all(|sess| sess.current_state() == SessionState::Running));
+
+    // network stats are only available after a second of the simulated clock
+    let mut stubs = [stubs::GameStub::new(), stubs::GameStub::new()];
+    for i in 0..1100 {
+        network.advance(Duration::from_millis(1));
+        for (handle, (sess, stub)) in sessions.iter_mut().zip(stubs.iter_mut()).enumerate() {
+            network.enter(|| -> Result<(), GgrsError> {
+                sess.poll_remote_clients();
+                if i % 16 == 0 {
+                    sess.add_local_input(handle, StubInput { inp: i })?;
+                    stub.handle_requests(sess.advance_frame()?);
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    // both sessions share the same clock, and the median transit time is not thrown off by the jitter
+    let stats = network.enter(|| {
+        Ok::<_, GgrsError>([sessions[0].network_stats(1)?, sessions[1].network_stats(0)?])
+    })?;
+    for stats in stats {
+        let clock_offset = stats.clock_offset.expect("clock offset should be known");
+        assert!(clock_offset.abs() <= 5);
+        assert!((20..=30).contains(&stats.input_transit_time));
+    }
+
+    Ok(())
+}
+
+/// Wraps a socket and, if tampering is enabled, follows every sent message with a quality reply claiming that the quality
+/// report it answers was sent at the start of the epoch.
+struct TamperingSocket {
+    inner: LoopbackSocket<SocketAddr>,
+    tampering: Arc<AtomicBool>,
+}
+
+impl NonBlockingSocket<SocketAddr> for TamperingSocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        self.inner.send_to(msg, addr);
+        if !self.tampering.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut buffer = Vec::new();
+        msg.encode_into(&mut buffer);
+        // the magic of the sender, no session token, the tag of a quality reply and a pong of 0
+        let mut reply = buffer[..2].to_vec();
+        reply.push(0);
+        reply.extend_from_slice(&5u32.to_le_bytes());
+        reply.extend_from_slice(&0u128.to_le_bytes());
+        self.inner
+            .send_to(&ggrs::parse_packet(&reply).unwrap(), addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        self.inner.receive_all_messages()
+    }
+}
+
+#[test]
+fn test_tampered_quality_reply() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(29).with_latency(Duration::from_millis(10));
+    let tampering = Arc::new(AtomicBool::new(false));
+
+    let mut sessions = network.enter(|| -> Result<_, GgrsError> {
+        Ok(vec![
+            SessionBuilder::<StubConfig>::new()
+                .add_player(PlayerType::Local, 0)?
+                .add_player(PlayerType::Remote(addr2), 1)?
+                .start_p2p_session(network.socket(addr1))?,
+            SessionBuilder::<StubConfig>::new()
+                .add_player(PlayerType::Remote(addr1), 0)?
+                .add_player(PlayerType::Local, 1)?
+                .start_p2p_session(TamperingSocket {
+                    inner: network.socket(addr2),
+                    tampering: tampering.clone(),
+                })?,
+        ])
+    })?;
+    for _ in 0..100 {
+        network.advance(Duration::from_millis(5));
+        network.enter(|| {
+            sessions
+                .iter_mut()
+                .for_each(|sess| drop(sess.poll_remote_clients()))
+        });
+    }
+
+    // the replies claim a round trip time of decades, which must neither crash the first client nor be measured
+    tampering.store(true, Ordering::Relaxed);
+    let mut stubs = [stubs::GameStub::new(), stubs::GameStub::new()];
+    for i in 0..100 {
+        network.advance(Duration::from_millis(16));
+        for (handle, (sess, stub)) in sessions.iter_mut().zip(stubs.iter_mut()).enumerate() {
+            network.enter(|| -> Result<(), GgrsError> {
+                sess.poll_remote_clients();
+                sess.add_local_input(handle, StubInput { inp: i })?;
+                stub.handle_requests(sess.advance_frame()?);
+                Ok(())
+            })?;
+        }
+    }
+
+    let stats = network.enter(|| sessions[0].network_stats(1))?;
+    assert!(stats.ping < 100);
+    assert!(stats.local_frames_behind.abs() < 10);
+
+    Ok(())
+}
+
+/// Reverses the payload, so a session without the same codec cannot decode it.
+#[derive(Debug)]
+struct ReverseCodec;
+
+impl Codec for ReverseCodec {
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        data.iter().rev().copied().collect()
+    }
+
+    fn decompress(&self, data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        (data.len() <= max_len).then(|| self.compress(data))
+    }
+}
+
+#[test]
+#[serial]
+fn test_input_codec() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_input_codec(ReverseCodec)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_input_codec(ReverseCodec)
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+
+    // the inputs of the remote player arrived and were decoded
+    assert!(sess1.confirmed_frame() > 0);
+    assert!(sess2.confirmed_frame() > 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_input_codec_mismatch() -> Result<(), GgrsError> {
+    // both sides refuse to play with a client whose inputs they cannot decode
+    let reverse = ReverseCodec.format_id();
+    let identity = ggrs::IdentityCodec.format_id();
+    assert_ne!(reverse, identity);
+    assert_handshake_mismatch(
+        SessionBuilder::new().with_input_codec(ReverseCodec),
+        SessionBuilder::new(),
+        [
+            HandshakeMismatch::InputCodec {
+                local: reverse,
+                remote: identity,
+            },
+            HandshakeMismatch::InputCodec {
+                local: identity,
+                remote: reverse,
+            },
+        ],
+    )
+}
+
+/// Wraps a socket and, if flooding is enabled, duplicates every received message many times.
+struct FloodedSocket {
+    inner: UdpNonBlockingSocket,
+    flooding: Arc<AtomicBool>,
+}
+
+impl NonBlockingSocket<SocketAddr> for FloodedSocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        self.inner.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        let messages = self.inner.receive_all_messages();
+        if !self.flooding.load(Ordering::Relaxed) {
+            return messages;
+        }
+        messages
+            .into_iter()
+            .flat_map(|msg| std::iter::repeat_n(msg, 2000))
+            .collect()
+    }
+}
+
+#[test]
+#[serial]
+fn test_flood_protection() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let flooding = Arc::new(AtomicBool::new(false));
+    let socket1 = FloodedSocket {
+        inner: UdpNonBlockingSocket::bind_to_port(7777).unwrap(),
+        flooding: flooding.clone(),
+    };
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+
+    // network stats are only available after a second
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+
+    flooding.store(true, Ordering::Relaxed);
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..5 {
+        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+        stub2.handle_requests(sess2.advance_frame().unwrap());
+        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
+        stub1.handle_requests(sess1.advance_frame().unwrap());
+    }
+
+    let stats = sess1.network_stats(1)?;
+    assert!(stats.packets_dropped > 0);
+    assert_eq!(sess2.network_stats(0)?.packets_dropped, 0);
+
+    Ok(())
+}
+
+/// A clock that only moves forward when the test says so.
+#[derive(Debug, Clone, Default)]
+struct ManualClock {
+    micros: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    fn advance(&self, duration: Duration) {
+        self.micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_micros(&self) -> u64 {
+        self.micros.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+#[serial]
+fn test_custom_clock() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let clock = ManualClock::default();
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_disconnect_timeout(Duration::from_secs(10))
+        .with_clock(clock.clone())
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.connection_status(1)?, ConnectionState::Running);
+
+    // the timeout follows the clock of the session, not the time that actually passed
+    clock.advance(Duration::from_secs(9));
+    sess1.poll_remote_clients();
+    assert_eq!(
+        sess1.connection_status(1)?,
+        ConnectionState::Interrupted {
+            elapsed: Duration::from_secs(9)
+        }
+    );
+    // the duration of the interruption grows, but the interruption is only announced once
+    clock.advance(Duration::from_millis(500));
+    sess1.poll_remote_clients();
+    assert_eq!(
+        sess1.connection_status(1)?,
+        ConnectionState::Interrupted {
+            elapsed: Duration::from_millis(9500)
+        }
+    );
+    let interruptions = sess1
+        .events()
+        .filter(|event| {
+            matches!(
+                event,
+                GgrsEvent::ConnectionStateChanged {
+                    state: ConnectionState::Interrupted { .. },
+                    ..
+                }
+            )
+        })
+        .count();
+    assert_eq!(interruptions, 1);
+    clock.advance(Duration::from_millis(1500));
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.connection_status(1)?, ConnectionState::Disconnected);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_handshake_verifier() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_handshake_payload(vec![0; ggrs::MAX_HANDSHAKE_PAYLOAD_SIZE + 1])
+        .is_err());
+
+    // every client presents its build and only accepts the first one
+    let builder = |build: &[u8]| -> Result<SessionBuilder<StubConfig>, GgrsError> {
+        Ok(SessionBuilder::<StubConfig>::new()
+            .with_handshake_payload(build.to_vec())?
+            .with_handshake_verifier(|_: &SocketAddr, payload: &[u8]| payload == b"build-1"))
+    };
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = builder(b"build-1")?
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = builder(b"build-2")?
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the first session rejects the second one, which accepts the first one, but both end up disconnected
+    let events1: Vec<_> = sess1.events().collect();
+    let rejections = events1
+        .iter()
+        .filter(|event| matches!(event, GgrsEvent::HandshakeRejected { addr } if *addr == addr2))
+        .count();
+    assert_eq!(rejections, 1);
+    assert!(events1
+        .iter()
+        .any(|event| matches!(event, GgrsEvent::Disconnected { addr } if *addr == addr2)));
+    let events2: Vec<_> = sess2.events().collect();
+    assert!(events2
+        .iter()
+        .all(|event| !matches!(event, GgrsEvent::HandshakeRejected { .. })));
+    assert!(events2
+        .iter()
+        .any(|event| matches!(event, GgrsEvent::Disconnected { addr } if *addr == addr1)));
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_send_message() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert!(matches!(
+        sess1.send_message(0, b"hello".to_vec()),
+        Err(GgrsError::InvalidPlayerHandle { handle: 0 })
+    ));
+    assert!(matches!(
+        sess1.send_message(1, vec![0; ggrs::MAX_MESSAGE_SIZE + 1]),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+    sess1.send_message(1, b"hello".to_vec())?;
+    sess1.send_message(1, b"world".to_vec())?;
+    sess2.send_message(0, b"hi".to_vec())?;
+
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // every message arrives once and in order
+    let received: Vec<_> = sess2.received_messages().collect();
+    assert_eq!(
+        received,
+        vec![(addr1, b"hello".to_vec()), (addr1, b"world".to_vec())]
+    );
+    let received: Vec<_> = sess1.received_messages().collect();
+    assert_eq!(received, vec![(addr2, b"hi".to_vec())]);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_confirmed_save_skipping() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_confirmed_save_skipping(true)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_confirmed_save_skipping(true)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // sess2 always advances first, so sess1 knows all inputs of the frames it advances while sess2 has to predict
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut saves1 = 0;
+    let mut saves2 = 0;
+    let count_saves = |requests: &[GgrsRequest<StubConfig>]| {
+        requests
+            .iter()
+            .filter(|request| matches!(request, GgrsRequest::SaveGameState { .. }))
+            .count()
+    };
+    for i in 0..40 {
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        let requests = sess2.advance_frame()?;
+        saves2 += count_saves(&requests);
+        stub2.handle_requests(requests);
+
+        sess1.add_local_input(0, StubInput { inp: 0 })?;
+        let requests = sess1.advance_frame()?;
+        saves1 += count_saves(&requests);
+        stub1.handle_requests(requests);
+    }
+
+    // frames that might be rolled back to are still saved
+    assert!(saves1 < 20);
+    assert!(saves2 >= 40);
+
+    // sess2 catches up with the last input of sess1 and loads its saved states on the way
+    sess2.poll_remote_clients();
+    sess2.add_local_input(1, StubInput { inp: 40 })?;
+    stub2.handle_requests(sess2.advance_frame()?);
+    sess1.poll_remote_clients();
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert_eq!(stub1.gs.frame, stub2.gs.frame);
+    assert_eq!(stub1.gs.state, stub2.gs.state);
+
+    Ok(())
+}
+
+#[test]
+fn test_hand_over_player() -> Result<(), GgrsError> {
+    let addrs: Vec<SocketAddr> = (0..3)
+        .map(|i| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7000 + i))
+        .collect();
+    // the first client has players 0 and 1, the others have players 2 and 3
+    let owner = [0, 0, 1, 2];
+
+    let network = LoopbackNetwork::new(5).with_latency(Duration::from_millis(40));
+    let mut sessions = network.enter(|| {
+        (0..3)
+            .map(|client| {
+                let mut builder = SessionBuilder::<StubConfig>::new()
+                    .with_num_players(4)
+                    .with_input_history(100);
+                for (handle, &owner) in owner.iter().enumerate() {
+                    let player_type = if owner == client {
+                        PlayerType::Local
+                    } else {
+                        PlayerType::Remote(addrs[owner])
+                    };
+                    builder = builder.add_player(player_type, handle)?;
+                }
+                builder.start_p2p_session(network.socket(addrs[client]))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+    let mut stubs: Vec<_> = (0..3).map(|_| stubs::GameStub::new()).collect();
+    let mut hand_over_frames = [None; 3];
+
+    for step in 0..180 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| -> Result<(), GgrsError> {
+            if step == 100 {
+                // player 1 moves to the second client
+                sessions[0].hand_over_player(1, 2)?;
+                assert!(matches!(
+                    sessions[0].hand_over_player(0, 2),
+                    Err(GgrsError::InvalidRequest { .. })
+                ));
+            }
+            for (client, (sess, stub)) in sessions.iter_mut().zip(&mut stubs).enumerate() {
+                sess.poll_remote_clients();
+                for event in sess.events() {
+                    if let GgrsEvent::PlayerHandedOver { handle, frame } = event {
+                        assert_eq!(handle, 1);
+                        hand_over_frames[client] = Some(frame);
+                    }
+                }
+                if sess.current_state() != SessionState::Running {
+                    continue;
+                }
+                // every player's input depends on the frame only, no matter which client adds it
+                let frame = sess.current_frame() as u32;
+                for handle in sess.local_player_handles() {
+                    sess.add_local_input(
+                        handle,
+                        StubInput {
+                            inp: frame * 4 + handle as u32,
+                        },
+                    )?;
+                }
+                match sess.advance_frame() {
+                    Ok(requests) => stub.handle_requests(requests),
+                    Err(GgrsError::PredictionThreshold) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    let mut local_handles = sessions[1].local_player_handles();
+    local_handles.sort_unstable();
+    assert_eq!(sessions[0].local_player_handles(), vec![0]);
+    assert_eq!(local_handles, vec![1, 2]);
+    assert_eq!(sessions[2].players()[1], (1, PlayerType::Remote(addrs[1])));
+    // inputs are acknowledged a round trip of the simulated network later
+    let stats = network.enter(|| sessions[0].network_stats(2))?;
+    assert!((80..200).contains(&stats.input_latency_p50));
+    // all clients agree on the frame the player moved at
+    let frame = hand_over_frames[0].expect("The player should have been handed over");
+    assert!(hand_over_frames.iter().all(|f| *f == Some(frame)));
+
+    // the confirmed inputs of the player continue seamlessly across the hand-over
+    for sess in &sessions {
+        let first_frame = sess.confirmed_frame() - 90;
+        assert!(first_frame < frame - 10 && sess.confirmed_frame() > frame + 10);
+        let inputs = sess.inputs_since(first_frame)?;
+        for (i, inputs) in inputs.iter().enumerate() {
+            let frame = (first_frame as usize + i) as u32;
+            for (handle, input) in inputs.iter().enumerate() {
+                assert_eq!(input.inp, frame * 4 + handle as u32);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_promote_spectator_and_demote_player() -> Result<(), GgrsError> {
+    let addrs: Vec<SocketAddr> = (0..3)
+        .map(|i| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7000 + i))
+        .collect();
+
+    // the host has player 0, the second client player 1 and the third client spectates with a P2P session
+    let network = LoopbackNetwork::new(7).with_latency(Duration::from_millis(40));
+    let mut sessions = network.enter(|| -> Result<_, GgrsError> {
+        let builder = || {
+            SessionBuilder::<StubConfig>::new()
+                .with_input_history(400)
+                .with_input_queue_length(512)
+        };
+        let host = builder()?
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addrs[1]), 1)?
+            .add_player(PlayerType::Spectator(addrs[2]), 2)?
+            .start_p2p_session(network.socket(addrs[0]))?;
+        let player = builder()?
+            .add_player(PlayerType::Remote(addrs[0]), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(network.socket(addrs[1]))?;
+        let spectator = builder()?
+            .add_player(PlayerType::Remote(addrs[0]), 0)?
+            .add_player(PlayerType::Remote(addrs[0]), 1)?
+            .start_p2p_session(network.socket(addrs[2]))?;
+        Ok(vec![host, player, spectator])
+    })?;
+    let mut stubs: Vec<_> = (0..3).map(|_| stubs::GameStub::new()).collect();
+    let mut hand_over_frames: Vec<Vec<Frame>> = vec![Vec::new(); 3];
+
+    for step in 0..420 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| -> Result<(), GgrsError> {
+            match step {
+                0 => assert!(matches!(
+                    sessions[0].demote_player(1),
+                    Err(GgrsError::NotSynchronized)
+                )),
+                // the second client becomes a spectator
+                80 => {
+                    assert!(matches!(
+                        sessions[0].promote_spectator(2, 0),
+                        Err(GgrsError::InvalidRequest { .. })
+                    ));
+                    assert!(matches!(
+                        sessions[0].demote_player(0),
+                        Err(GgrsError::InvalidPlayerHandle { handle: 0 })
+                    ));
+                    sessions[0].demote_player(1)?;
+                    assert!(matches!(
+                        sessions[0].demote_player(1),
+                        Err(GgrsError::InvalidRequest { .. })
+                    ));
+                }
+                // the third client takes over player 1
+                160 => {
+                    let spectator = (3, PlayerType::Spectator(addrs[1]));
+                    assert!(sessions[0].players().contains(&spectator));
+                    sessions[0].promote_spectator(2, 1)?;
+                }
+                // and hands it back to the second client
+                240 => sessions[0].demote_player(1)?,
+                320 => {
+                    let spectator = (2, PlayerType::Spectator(addrs[2]));
+                    assert!(sessions[0].players().contains(&spectator));
+                    sessions[0].promote_spectator(3, 1)?;
+                }
+                _ => (),
+            }
+            for (client, (sess, stub)) in sessions.iter_mut().zip(&mut stubs).enumerate() {
+                sess.poll_remote_clients();
+                for event in sess.events() {
+                    if let GgrsEvent::PlayerHandedOver { handle, frame } = event {
+                        assert_eq!(handle, 1);
+                        hand_over_frames[client].push(frame);
+                    }
+                }
+                if sess.current_state() != SessionState::Running {
+                    continue;
+                }
+                // every player's input depends on the frame only, no matter which client adds it
+                let frame = sess.current_frame() as u32;
+                for handle in sess.local_player_handles() {
+                    sess.add_local_input(
+                        handle,
+                        StubInput {
+                            inp: frame * 4 + handle as u32,
+                        },
+                    )?;
+                }
+                match sess.advance_frame() {
+                    Ok(requests) => stub.handle_requests(requests),
+                    Err(GgrsError::PredictionThreshold) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    assert_eq!(sessions[0].local_player_handles(), vec![0]);
+    assert_eq!(sessions[1].local_player_handles(), vec![1]);
+    assert!(sessions[2].local_player_handles().is_empty());
+    assert_eq!(
+        sessions[0].players(),
+        vec![
+            (0, PlayerType::Local),
+            (1, PlayerType::Remote(addrs[1])),
+            (2, PlayerType::Spectator(addrs[2])),
+        ]
+    );
+    // the clients taking part in a move agree on its frame
+    assert_eq!(hand_over_frames[0].len(), 4);
+    assert_eq!(hand_over_frames[1], [0, 3].map(|i| hand_over_frames[0][i]));
+    assert_eq!(hand_over_frames[2], [1, 2].map(|i| hand_over_frames[0][i]));
+
+    // the confirmed inputs of the player continue seamlessly across all moves
+    for sess in &sessions {
+        let first_frame = (sess.confirmed_frame() - 390).max(0);
+        assert!(first_frame < hand_over_frames[0][0] - 10);
+        assert!(sess.confirmed_frame() > hand_over_frames[0][3] + 10);
+        let inputs = sess.inputs_since(first_frame)?;
+        for (i, inputs) in inputs.iter().enumerate() {
+            let frame = (first_frame as usize + i) as u32;
+            for (handle, input) in inputs.iter().enumerate() {
+                assert_eq!(input.inp, frame * 4 + handle as u32);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_start_frame() -> Result<(), GgrsError> {
+    let addrs: Vec<SocketAddr> = (0..3)
+        .map(|i| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7000 + i))
+        .collect();
+    let start_frame = 1001;
+
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_start_frame(-1)
+        .is_err());
+
+    let network = LoopbackNetwork::new(9).with_latency(Duration::from_millis(40));
+    let (mut sessions, mut spectator) = network.enter(|| -> Result<_, GgrsError> {
+        let builder = || {
+            SessionBuilder::<StubConfig>::new()
+                .with_save_interval(4)?
+                .with_desync_detection_mode(DesyncDetection::On { interval: 10 })
+                .with_start_frame(start_frame)
+        };
+        let sess1 = builder()?
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addrs[1]), 1)?
+            .add_player(PlayerType::Spectator(addrs[2]), 2)?
+            .start_p2p_session(network.socket(addrs[0]))?;
+        let sess2 = builder()?
+            .add_player(PlayerType::Remote(addrs[0]), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(network.socket(addrs[1]))?;
+        let spectator = builder()?.start_spectator_session(addrs[0], network.socket(addrs[2]));
+        Ok((vec![sess1, sess2], spectator))
+    })?;
+
+    // every peer resumes the match from the same state
+    let resumed = stubs::StateStub {
+        frame: start_frame,
+        state: 7,
+    };
+    let mut stubs: Vec<_> = (0..3).map(|_| stubs::GameStub::new()).collect();
+    for stub in &mut stubs {
+        stub.gs = resumed;
+    }
+    let mut saved_states = vec![HashMap::new(); 2];
+    assert_eq!(sessions[0].current_frame(), start_frame);
+    assert_eq!(spectator.current_frame(), start_frame - 1);
+
+    for _ in 0..150 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| -> Result<(), GgrsError> {
+            for (handle, sess) in sessions.iter_mut().enumerate() {
+                sess.poll_remote_clients();
+                for event in sess.events() {
+                    assert!(!matches!(event, GgrsEvent::DesyncDetected { .. }));
+                }
+                if sess.current_state() != SessionState::Running {
+                    continue;
+                }
+                // the inputs change often, so the sessions mispredict and roll back
+                let frame = sess.current_frame() as u32;
+                sess.add_local_input(
+                    handle,
+                    StubInput {
+                        inp: frame / 3 + handle as u32,
+                    },
+                )?;
+                let requests = match sess.advance_frame() {
+                    Ok(requests) => requests,
+                    Err(GgrsError::PredictionThreshold) => continue,
+                    Err(e) => return Err(e),
+                };
+                for request in requests {
+                    match &request {
+                        GgrsRequest::SaveGameState { frame, .. } => {
+                            assert!(*frame == start_frame || frame % 4 == 0);
+                            saved_states[handle]
+                                .insert(*frame, (stubs[handle].gs.frame, stubs[handle].gs.state));
+                        }
+                        GgrsRequest::LoadGameState { frame, .. } => {
+                            assert!(*frame >= start_frame);
+                        }
+                        _ => (),
+                    }
+                    stubs[handle].handle_requests(vec![request]);
+                }
+            }
+
+            spectator.poll_remote_clients();
+            for event in spectator.events() {
+                assert!(!matches!(event, GgrsEvent::DesyncDetected { .. }));
+            }
+            if spectator.current_state() == SessionState::Running {
+                match spectator.advance_frame() {
+                    Ok(requests) => stubs[2].handle_requests(requests),
+                    Err(GgrsError::PredictionThreshold) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    // the start frame is saved first, and the peers agree on all confirmed states since
+    assert_eq!(saved_states[0][&start_frame], (start_frame, 7));
+    let confirmed_frame = sessions[0]
+        .confirmed_frame()
+        .min(sessions[1].confirmed_frame());
+    assert!(confirmed_frame > start_frame + 50);
+    assert!(sessions[0].rollback_stats().rollbacks > 0);
+    for frame in (start_frame..=confirmed_frame).filter(|frame| frame % 4 == 0) {
+        assert_eq!(saved_states[0][&frame], saved_states[1][&frame]);
+    }
+    assert!(spectator.current_frame() > start_frame + 50);
+
+    Ok(())
+}
+
+#[test]
+fn test_start_frame_mismatch() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(10).with_latency(Duration::from_millis(20));
+    let mut sessions = network.enter(|| -> Result<_, GgrsError> {
+        let sess1 = SessionBuilder::<StubConfig>::new()
+            .with_start_frame(1000)?
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .start_p2p_session(network.socket(addr1))?;
+        let sess2 = SessionBuilder::<StubConfig>::new()
+            .with_start_frame(1001)?
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(network.socket(addr2))?;
+        Ok([sess1, sess2])
+    })?;
+
+    // both sides refuse to play with each other instead of running into inputs they cannot place
+    let mut stubs = [stubs::GameStub::new(), stubs::GameStub::new()];
+    stubs[0].gs.frame = 1000;
+    stubs[1].gs.frame = 1001;
+    let mut events = [Vec::new(), Vec::new()];
+    for _ in 0..100 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| -> Result<(), GgrsError> {
+            for (handle, sess) in sessions.iter_mut().enumerate() {
+                sess.poll_remote_clients();
+                events[handle].extend(sess.events());
+                if sess.current_state() != SessionState::Running {
+                    continue;
+                }
+                sess.add_local_input(handle, StubInput { inp: 1 })?;
+                stubs[handle].handle_requests(sess.advance_frame()?);
+            }
+            Ok(())
+        })?;
+    }
+
+    for (handle, (local, remote)) in [(1000, 1001), (1001, 1000)].into_iter().enumerate() {
+        let mismatches: Vec<_> = events[handle]
+            .iter()
+            .filter_map(|event| match event {
+                GgrsEvent::HandshakeMismatch { mismatch, .. } => Some(*mismatch),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            mismatches,
+            vec![HandshakeMismatch::StartFrame { local, remote }]
+        );
+        assert!(events[handle]
+            .iter()
+            .any(|event| matches!(event, GgrsEvent::Disconnected { .. })));
+        assert!(events[handle]
+            .iter()
+            .all(|event| !matches!(event, GgrsEvent::Synchronized { .. })));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_timeout() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let addr3 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+    let addr4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 6666);
+    let network = LoopbackNetwork::new(3);
+    let builder = || SessionBuilder::<StubConfig>::new().with_sync_timeout(Duration::from_secs(1));
+
+    // the second client is not there yet, so the handshake makes no progress
+    let mut sess1 = network.enter(|| {
+        builder()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .start_p2p_session(network.socket(addr1))
+    })?;
+    let mut failures = Vec::new();
+    for _ in 0..15 {
+        network.advance(Duration::from_millis(100));
+        network.enter(|| sess1.poll_remote_clients());
+        failures.extend(sess1.events().filter_map(|event| match event {
+            GgrsEvent::SynchronizationFailed { handle, attempts } => Some((handle, attempts)),
+            _ => None,
+        }));
+    }
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, 1);
+    assert!(failures[0].1 > 1);
+    assert_eq!(
+        sess1.connection_status(1)?,
+        ConnectionState::SynchronizationFailed
+    );
+    assert_eq!(sess1.current_state(), SessionState::Synchronizing);
+
+    assert!(matches!(
+        sess1.retry_synchronization(0),
+        Err(GgrsError::InvalidPlayerHandle { handle: 0 })
+    ));
+    network.enter(|| sess1.retry_synchronization(1))?;
+    assert!(matches!(
+        sess1.retry_synchronization(1),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+
+    // once the second client shows up, the retried synchronization succeeds
+    let mut sess2 = network.enter(|| {
+        builder()
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(network.socket(addr2))
+    })?;
+    for _ in 0..20 {
+        network.advance(Duration::from_millis(50));
+        network.enter(|| {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        });
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+
+    // giving up on an unreachable client lets the session start without it
+    let mut sess3 = network.enter(|| {
+        builder()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr4), 1)?
+            .start_p2p_session(network.socket(addr3))
+    })?;
+    for _ in 0..15 {
+        network.advance(Duration::from_millis(100));
+        network.enter(|| sess3.poll_remote_clients());
+    }
+    assert_eq!(
+        sess3.connection_status(1)?,
+        ConnectionState::SynchronizationFailed
+    );
+    sess3.disconnect_player(1)?;
+    network.enter(|| sess3.poll_remote_clients());
+    assert_eq!(sess3.current_state(), SessionState::Running);
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_timeout_one_sided() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(4);
+
+    // only the first client gives up on the handshake, before the second one shows up
+    let mut sess1 = network.enter(|| {
+        SessionBuilder::<StubConfig>::new()
+            .with_sync_timeout(Duration::from_secs(1))
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .start_p2p_session(network.socket(addr1))
+    })?;
+    for _ in 0..15 {
+        network.advance(Duration::from_millis(100));
+        network.enter(|| sess1.poll_remote_clients());
+    }
+    let mut sess2 = network.enter(|| {
+        SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(network.socket(addr2))
+    })?;
+
+    // the failed client does not answer, so the other one does not start without it
+    for _ in 0..20 {
+        network.advance(Duration::from_millis(50));
+        network.enter(|| {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        });
+    }
+    assert_eq!(
+        sess1.connection_status(1)?,
+        ConnectionState::SynchronizationFailed
+    );
+    assert_eq!(sess1.current_state(), SessionState::Synchronizing);
+    assert_eq!(sess2.current_state(), SessionState::Synchronizing);
+
+    // both start once the failed client retries
+    network.enter(|| sess1.retry_synchronization(1))?;
+    for _ in 0..20 {
+        network.advance(Duration::from_millis(50));
+        network.enter(|| {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        });
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+
+    Ok(())
+}
+
+#[test]
+fn test_fair_input_delay() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let start = |fair: [bool; 2], seed, jitter| -> Result<Vec<P2PSession<StubConfig>>, GgrsError> {
+        let network = LoopbackNetwork::new(seed)
+            .with_latency(Duration::from_millis(40))
+            .with_jitter(jitter);
+        let mut sessions = Vec::new();
+        for (i, (delay, local_addr, remote_addr)) in [(0, addr1, addr2), (5, addr2, addr1)]
+            .into_iter()
+            .enumerate()
+        {
+            let session = network.enter(|| {
+                SessionBuilder::<StubConfig>::new()
+                    .with_input_delay(delay)
+                    .with_fair_input_delay(fair[i])
+                    .add_player(PlayerType::Local, i)?
+                    .add_player(PlayerType::Remote(remote_addr), 1 - i)?
+                    .start_p2p_session(network.socket(local_addr))
+            })?;
+            sessions.push(session);
+        }
+        for _ in 0..100 {
+            network.advance(Duration::from_millis(20));
+            network.enter(|| {
+                sessions.iter_mut().for_each(|sess| {
+                    sess.poll_remote_clients();
+                })
+            });
+        }
+        assert!(sessions
+            .iter()
+            .all(|sess| sess.current_state() == SessionState::Running));
+        Ok(sessions)
+    };
+
+    // with the same latency in both directions, the total delay is split evenly and the odd frame goes to one of the peers
+    let sessions = start([true, true], 8, Duration::ZERO)?;
+    let delays = [sessions[0].input_delay(0)?, sessions[1].input_delay(1)?];
+    assert_eq!(delays[0] + delays[1], 5);
+    assert!(delays.contains(&2) && delays.contains(&3));
+    assert_eq!(sessions[0].input_delay(1)?, 0);
+
+    // with jitter, both peers measure different round trip times, but still agree on the split
+    for seed in 0..20 {
+        let sessions = start([true, true], seed, Duration::from_millis(60))?;
+        let delays = [sessions[0].input_delay(0)?, sessions[1].input_delay(1)?];
+        assert_eq!(delays[0] + delays[1], 5);
+    }
+
+    // both peers need to agree
+    let sessions = start([true, false], 8, Duration::ZERO)?;
+    assert_eq!(sessions[0].input_delay(0)?, 0);
+    assert_eq!(sessions[1].input_delay(1)?, 5);
+
+    Ok(())
+}
+
+#[test]
+fn test_linked_players() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(4).with_latency(Duration::from_millis(20));
+
+    // player 1 follows the input of player 0 on the first client
+    let builder = || {
+        SessionBuilder::<StubConfig>::new()
+            .with_num_players(3)
+            .with_replay_recording(true)
+    };
+    let mut sess1 = network.enter(|| {
+        builder()
+            .add_player(PlayerType::Local, 0)?
+            .add_linked_player(1, 0)?
+            .add_player(PlayerType::Remote(addr2), 2)?
+            .start_p2p_session(network.socket(addr1))
+    })?;
+    let mut sess2 = network.enter(|| {
+        builder()
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_linked_player(1, 0)?
+            .add_player(PlayerType::Local, 2)?
+            .start_p2p_session(network.socket(addr2))
+    })?;
+    assert!(builder().add_linked_player(1, 0).is_err());
+    assert!(builder()
+        .add_player(PlayerType::Local, 0)?
+        .add_linked_player(1, 0)?
+        .add_linked_player(2, 1)
+        .is_err());
+
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(20));
+        network.enter(|| {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        });
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess1.local_player_handles().len(), 2);
+    assert!(matches!(
+        sess1.add_local_input(1, StubInput { inp: 0 }),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..60 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| -> Result<(), GgrsError> {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+            sess1.add_local_input(0, StubInput { inp: i })?;
+            stub1.handle_requests(sess1.advance_frame()?);
+            sess2.add_local_input(2, StubInput { inp: 100 + i })?;
+            stub2.handle_requests(sess2.advance_frame()?);
+            Ok(())
+        })?;
+    }
+
+    // both clients expand the input of player 0 to player 1
+    for sess in [&sess1, &sess2] {
+        let replay = sess.replay().unwrap();
+        assert!(replay.num_frames() > 40);
+        for frame in 0..replay.num_frames() as i32 {
+            let inputs = replay.frame(frame).unwrap();
+            assert_eq!(inputs[0].inp, frame as u32);
+            assert_eq!(inputs[1].inp, frame as u32);
+            assert_eq!(inputs[2].inp, 100 + frame as u32);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_poll_summary() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(6).with_latency(Duration::from_millis(20));
+
+    let mut sessions = Vec::new();
+    for (i, (local_addr, remote_addr)) in [(addr1, addr2), (addr2, addr1)].into_iter().enumerate() {
+        let session = network.enter(|| {
+            SessionBuilder::<StubConfig>::new()
+                .add_player(PlayerType::Local, i)?
+                .add_player(PlayerType::Remote(remote_addr), 1 - i)?
+                .start_p2p_session(network.socket(local_addr))
+        })?;
+        sessions.push(session);
+    }
+
+    // nothing has arrived yet
+    let summary = network.enter(|| sessions[0].poll_remote_clients());
+    assert_eq!(summary.packets_received, 0);
+    assert!(summary.inputs_received.is_empty());
+
+    let mut events_queued = summary.events_queued;
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(20));
+        network.enter(|| {
+            events_queued += sessions[0].poll_remote_clients().events_queued;
+            sessions[1].poll_remote_clients();
+        });
+    }
+    assert!(sessions
+        .iter()
+        .all(|sess| sess.current_state() == SessionState::Running));
+    assert_eq!(events_queued, sessions[0].events().count());
+
+    // the second client sends an input that differs from the prediction of the first one
+    network.enter(|| -> Result<(), GgrsError> {
+        sessions[0].add_local_input(0, StubInput { inp: 0 })?;
+        sessions[0].advance_frame()?;
+        sessions[1].add_local_input(1, StubInput { inp: 7 })?;
+        sessions[1].advance_frame()?;
+        Ok(())
+    })?;
+    network.advance(Duration::from_millis(20));
+    let summary = network.enter(|| sessions[0].poll_remote_clients());
+    assert!(summary.packets_received > 0);
+    assert_eq!(summary.inputs_received_for(1), 1);
+    assert_eq!(summary.inputs_received_for(0), 0);
+    assert_eq!(summary.rollback_frame, Some(0));
+
+    // the rollback is still scheduled until the next frame is advanced
+    let summary = network.enter(|| sessions[0].poll_remote_clients());
+    assert!(summary.inputs_received.is_empty());
+    assert_eq!(summary.rollback_frame, Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn test_poll_summary_full_event_queue() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(19);
+
+    let mut sessions = Vec::new();
+    for (i, (local_addr, remote_addr)) in [(addr1, addr2), (addr2, addr1)].into_iter().enumerate() {
+        let session = network.enter(|| {
+            SessionBuilder::<StubConfig>::new()
+                .with_rollback_events(true)
+                .add_player(PlayerType::Local, i)?
+                .add_player(PlayerType::Remote(remote_addr), 1 - i)?
+                .start_p2p_session(network.socket(local_addr))
+        })?;
+        sessions.push(session);
+    }
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(20));
+        network.enter(|| {
+            sessions
+                .iter_mut()
+                .for_each(|sess| drop(sess.poll_remote_clients()))
+        });
+    }
+    assert_eq!(sessions[0].current_state(), SessionState::Running);
+    drop(sessions[0].events());
+
+    // the first client stalls without the inputs of the second one until its event queue is full of stalls
+    let mut stub = stubs::GameStub::new();
+    network.enter(|| -> Result<(), GgrsError> {
+        for i in 0..200 {
+            sessions[0].add_local_input(0, StubInput { inp: i })?;
+            match sessions[0].advance_frame() {
+                Ok(requests) => stub.handle_requests(requests),
+                Err(GgrsError::PredictionThreshold) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    })?;
+
+    // the events of the disconnect push stalls out of the queue, but are counted all the same
+    network.enter(|| sessions[1].close());
+    network.advance(Duration::from_millis(20));
+    let summary = network.enter(|| sessions[0].poll_remote_clients());
+    let new_events = sessions[0]
+        .events()
+        .filter(|event| !matches!(event, GgrsEvent::Stalled { .. }))
+        .count();
+    assert!(new_events > 0);
+    assert_eq!(summary.events_queued, new_events);
+
+    Ok(())
+}
+
+#[test]
+fn test_sticky_input_mask() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    // the upper bits hold a direction that changes rarely, the lowest bit a button tapped for a single frame
+    struct TapGame(stubs::GameStub);
+    impl SoakGame<StubConfig> for TapGame {
+        fn local_input(&mut self, _player_handle: PlayerHandle, frame: Frame) -> StubInput {
+            let direction = (frame as u32 / 60) << 8;
+            StubInput {
+                inp: direction | (frame % 7 == 0) as u32,
+            }
+        }
+
+        fn handle_requests(&mut self, requests: Vec<GgrsRequest<StubConfig>>) {
+            self.0.handle_requests(requests);
+        }
+    }
+
+    let run = |builder: fn() -> SessionBuilder<StubConfig>| -> Result<usize, GgrsError> {
+        let network = LoopbackNetwork::new(9).with_latency(Duration::from_millis(50));
+        let games = [
+            TapGame(stubs::GameStub::new()),
+            TapGame(stubs::GameStub::new()),
+        ];
+        let mut soak = SoakTest::new(network, [addr1, addr2], builder, games)?;
+        let report = soak.run(600);
+        assert!(report.frames >= 600);
+        assert!(report.desyncs.is_empty());
+        Ok(report.rollbacks)
+    };
+
+    // without the mask, a tap is mispredicted twice: when it is pressed and when it is released
+    let repeated = run(SessionBuilder::new)?;
+    let masked = run(|| SessionBuilder::new().with_sticky_input_mask(StubInput { inp: !1 }))?;
+    assert!(masked * 3 < repeated * 2);
+
+    Ok(())
+}
+
+/// Wraps a loopback socket, but only implements the unbounded receive, so the session has to keep the packets over its budget.
+struct UnboundedSocket(LoopbackSocket<SocketAddr>);
+
+impl NonBlockingSocket<SocketAddr> for UnboundedSocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        self.0.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        self.0.receive_all_messages()
+    }
+}
+
+/// Starts two running sessions with the given receive budgets, lets the second one advance six frames while the first one
+/// hitches and returns both sessions.
+fn hitched_sessions(
+    network: &LoopbackNetwork<SocketAddr>,
+    socket1: impl NonBlockingSocket<SocketAddr> + 'static,
+    packets: usize,
+    bytes: usize,
+) -> Result<Vec<P2PSession<StubConfig>>, GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let builder = |i: usize, remote_addr| {
+        SessionBuilder::<StubConfig>::new()
+            .with_receive_budget(packets)?
+            .with_receive_byte_budget(bytes)?
+            .add_player(PlayerType::Local, i)?
+            .add_player(PlayerType::Remote(remote_addr), 1 - i)
+    };
+    let mut sessions = network.enter(|| -> Result<_, GgrsError> {
+        Ok(vec![
+            builder(0, addr2)?.start_p2p_session(socket1)?,
+            builder(1, addr1)?.start_p2p_session(network.socket(addr2))?,
+        ])
+    })?;
+    for _ in 0..100 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            for sess in sessions.iter_mut() {
+                sess.poll_remote_clients();
+            }
+        });
+    }
+    assert!(sessions
+        .iter()
+        .all(|sess| sess.current_state() == SessionState::Running));
+
+    // the second client keeps sending while the first one hitches
+    for i in 0..6 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| sessions[1].advance_frame_with_input(|_| StubInput { inp: i }))?;
+    }
+    network.advance(Duration::from_millis(16));
+    Ok(sessions)
+}
+
+#[test]
+fn test_receive_budget() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let network = LoopbackNetwork::new(12).with_latency(Duration::from_millis(10));
+
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_receive_budget(0)
+        .is_err());
+
+    let mut sessions = hitched_sessions(&network, network.socket(addr1), 2, usize::MAX)?;
+    let summary = network.enter(|| sessions[0].poll_remote_clients());
+    assert_eq!(summary.packets_received, 2);
+    // the loopback socket keeps the packets over the budget itself, so the session has none waiting
+    let stats = network.enter(|| sessions[0].network_stats(1))?;
+    assert_eq!(stats.receive_backlog, 0);
+
+    // the rest is processed by the following polls
+    let mut received = summary.packets_received;
+    loop {
+        let summary = network.enter(|| sessions[0].poll_remote_clients());
+        assert!(summary.packets_received <= 2);
+        if summary.packets_received == 0 {
+            break;
+        }
+        received += summary.packets_received;
+    }
+    assert!(received >= 6);
+
+    Ok(())
+}
+
+#[test]
+fn test_receive_byte_budget() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let network = LoopbackNetwork::new(12).with_latency(Duration::from_millis(10));
+
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_receive_byte_budget(0)
+        .is_err());
+
+    // every packet crosses a budget of a single byte, so each poll processes exactly one
+    let mut sessions = hitched_sessions(&network, network.socket(addr1), 1024, 1)?;
+    for _ in 0..6 {
+        let summary = network.enter(|| sessions[0].poll_remote_clients());
+        assert_eq!(summary.packets_received, 1);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_receive_backlog_capped() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let stranger = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+    let network = LoopbackNetwork::new(12).with_latency(Duration::from_millis(10));
+
+    let mut sessions = hitched_sessions(
+        &network,
+        UnboundedSocket(network.socket(addr1)),
+        2,
+        usize::MAX,
+    )?;
+    // packets of unknown addresses are dropped before they are queued, so copies of the packets from a stranger don't count
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let (mut socket2, mut stranger_socket) = (network.socket(addr2), network.socket(stranger));
+    for (_, msg) in network.socket(addr1).receive_all_messages() {
+        socket2.send_to(&msg, &addr1);
+        stranger_socket.send_to(&msg, &addr1);
+    }
+    network.advance(Duration::from_millis(16));
+
+    // the socket returns all packets, the session processes two and keeps only two more for the next poll
+    let summary = network.enter(|| sessions[0].poll_remote_clients());
+    assert_eq!(summary.packets_received, 2);
+    let stats = network.enter(|| sessions[0].network_stats(1))?;
+    assert_eq!(stats.receive_backlog, 2);
+
+    let summary = network.enter(|| sessions[0].poll_remote_clients());
+    assert_eq!(summary.packets_received, 2);
+    let stats = network.enter(|| sessions[0].network_stats(1))?;
+    assert_eq!(stats.receive_backlog, 0);
+
+    Ok(())
+}
+
+/// Wraps a loopback socket and, once broken, fails every send and receive like a socket the operating system closed.
+struct BreakingSocket {
+    inner: LoopbackSocket<SocketAddr>,
+    broken: Arc<AtomicBool>,
+    errors: Vec<std::io::ErrorKind>,
+}
+
+impl NonBlockingSocket<SocketAddr> for BreakingSocket {
+    fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+        if self.broken.load(Ordering::Relaxed) {
+            self.errors.push(std::io::ErrorKind::BrokenPipe);
+        } else {
+            self.inner.send_to(msg, addr);
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        if self.broken.load(Ordering::Relaxed) {
+            self.errors.push(std::io::ErrorKind::BrokenPipe);
+            return Vec::new();
+        }
+        self.inner.receive_all_messages()
+    }
+
+    fn take_errors(&mut self) -> Vec<std::io::ErrorKind> {
+        std::mem::take(&mut self.errors)
+    }
+}
+
+#[test]
+fn test_replace_socket() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(13).with_latency(Duration::from_millis(10));
+
+    let broken = Arc::new(AtomicBool::new(false));
+    let socket1 = BreakingSocket {
+        inner: network.socket(addr1),
+        broken: broken.clone(),
+        errors: Vec::new(),
+    };
+    let mut sess1 = network.enter(|| {
+        SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .start_p2p_session(socket1)
+    })?;
+    let mut sess2 = network.enter(|| {
+        SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(network.socket(addr2))
+    })?;
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        });
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    sess1.events().for_each(drop);
+
+    // every send and receive fails, but each kind of error is reported only once
+    broken.store(true, Ordering::Relaxed);
+    network.advance(Duration::from_millis(16));
+    network.enter(|| sess1.poll_remote_clients());
+    let errors: Vec<_> = sess1
+        .events()
+        .filter(|event| matches!(event, GgrsEvent::SocketError { .. }))
+        .collect();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(
+        errors[0],
+        GgrsEvent::SocketError {
+            kind: std::io::ErrorKind::BrokenPipe
+        }
+    ));
+    for _ in 0..3 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| sess1.poll_remote_clients());
+    }
+    assert!(sess1
+        .events()
+        .all(|event| !matches!(event, GgrsEvent::SocketError { .. })));
+
+    // the errors of a replacing socket are reported again
+    sess1.replace_socket(BreakingSocket {
+        inner: network.socket(addr1),
+        broken: broken.clone(),
+        errors: Vec::new(),
+    });
+    network.advance(Duration::from_millis(16));
+    network.enter(|| sess1.poll_remote_clients());
+    assert_eq!(
+        sess1
+            .events()
+            .filter(|event| matches!(event, GgrsEvent::SocketError { .. }))
+            .count(),
+        1
+    );
+
+    // a new socket bound to the same address picks up where the broken one left off
+    sess1.replace_socket(network.socket(addr1));
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..60 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+            // the sessions may have to wait for each other, which is no error here
+            if let Ok(requests) = sess1.advance_frame_with_input(|_| StubInput { inp: i }) {
+                stub1.handle_requests(requests);
+            }
+            if let Ok(requests) = sess2.advance_frame_with_input(|_| StubInput { inp: i }) {
+                stub2.handle_requests(requests);
+            }
+        });
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert!(sess1.confirmed_frame() > 40);
+    assert!(sess1
+        .events()
+        .all(|event| !matches!(event, GgrsEvent::SocketError { .. })));
+
+    Ok(())
+}
+
+#[test]
+fn test_external_addr() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let external_addr: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+    let network = LoopbackNetwork::new(14).with_latency(Duration::from_millis(10));
+
+    let mut sess1 = network.enter(|| {
+        SessionBuilder::<StubConfig>::new()
+            .with_external_addr(external_addr)
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .start_p2p_session(network.socket(addr1))
+    })?;
+    let mut sess2 = network.enter(|| {
+        SessionBuilder::<StubConfig>::new()
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(network.socket(addr2))
+    })?;
+    assert_eq!(sess2.remote_external_addr(0), None);
+    for _ in 0..50 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        });
+    }
+    assert_eq!(sess2.current_state(), SessionState::Running);
+
+    assert_eq!(sess1.local_addr(), Some(addr1));
+    assert_eq!(sess2.remote_external_addr(0), Some(external_addr));
+    assert_eq!(sess1.remote_external_addr(1), None);
+    assert_eq!(sess2.remote_external_addr(1), None);
+    let report = sess2.export_diagnostics();
+    assert_eq!(report.peers[0].external_addr, Some(external_addr));
+
+    // the operating system picks the port when binding to port 0
+    let socket = UdpNonBlockingSocket::bind_to_port(0).unwrap();
+    let local_addr = socket.local_addr().unwrap();
+    assert_ne!(local_addr.port(), 0);
+    let sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket)?;
+    assert_eq!(sess.local_addr(), Some(local_addr));
 
     Ok(())
 }
@@ -1,9 +1,16 @@
 mod stubs;
 
-use ggrs::{GgrsError, PlayerType, SessionBuilder, SessionState, UdpNonBlockingSocket};
+use ggrs::testing::LoopbackNetwork;
+use ggrs::{
+    Clock, DesyncDetection, GgrsError, GgrsEvent, GgrsRequest, PlayerType, Session, SessionBuilder,
+    SessionState, UdpNonBlockingSocket, MAX_FRAME,
+};
 use serial_test::serial;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use stubs::StubConfig;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use stubs::{StubConfig, StubInput};
 
 #[test]
 #[serial]
@@ -14,6 +21,27 @@ fn test_start_session() {
     assert!(spec_sess.current_state() == SessionState::Synchronizing);
 }
 
+#[test]
+#[serial]
+fn test_session_enum() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let socket = UdpNonBlockingSocket::bind_to_port(9999).unwrap();
+    let mut sess: Session<StubConfig> = SessionBuilder::new()
+        .start_spectator_session(host_addr, socket)
+        .into();
+
+    assert_eq!(sess.current_state(), SessionState::Synchronizing);
+    assert!(sess.local_player_handles().is_empty());
+    assert!(matches!(
+        sess.add_local_input(0, StubInput { inp: 0 }),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+    assert!(matches!(
+        sess.advance_frame(),
+        Err(GgrsError::NotSynchronized)
+    ));
+}
+
 #[test]
 #[serial]
 fn test_synchronize_with_host() -> Result<(), GgrsError> {
@@ -44,3 +72,408 @@ fn test_synchronize_with_host() -> Result<(), GgrsError> {
 
     Ok(())
 }
+
+#[test]
+fn test_spectator_buffer_size() -> Result<(), GgrsError> {
+    // the default maximum frames behind is 10
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_spectator_buffer_size(10)
+        .is_err());
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_spectator_buffer_size(120)?
+        .with_max_frames_behind(100)?
+        .with_spectator_input_interval(60)
+        .is_ok());
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_spectator_buffer_size(30)?
+        .with_max_frames_behind(30)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_spectator_input_interval() -> Result<(), GgrsError> {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_spectator_input_interval(0)
+        .is_err());
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_spectator_input_interval(60)
+        .is_err());
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut host_sess = SessionBuilder::<StubConfig>::new()
+        .with_spectator_input_interval(4)?
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .add_player(PlayerType::Spectator(spec_addr), 2)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut spec_sess =
+        SessionBuilder::<StubConfig>::new().start_spectator_session(host_addr, socket2);
+
+    for _ in 0..50 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(host_sess.current_state(), SessionState::Running);
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+
+    let mut host_stub = stubs::GameStub::new();
+    let mut spec_stub = stubs::GameStub::new();
+    for i in 0..20 {
+        host_sess.add_local_input(0, StubInput { inp: i })?;
+        host_sess.add_local_input(1, StubInput { inp: i })?;
+        host_stub.handle_requests(host_sess.advance_frame()?);
+        spec_sess.poll_remote_clients();
+        while let Ok(requests) = spec_sess.advance_frame() {
+            spec_stub.handle_requests(requests);
+        }
+    }
+
+    // the host confirmed 19 frames, but only complete batches of 4 frames have been sent
+    assert_eq!(spec_stub.gs.frame, 16);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_spectator_playback_speed() -> Result<(), GgrsError> {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut host_sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .add_player(PlayerType::Spectator(spec_addr), 2)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut spec_sess = SessionBuilder::<StubConfig>::new()
+        .with_max_frames_behind(30)?
+        .start_spectator_session(host_addr, socket2);
+    assert!(spec_sess.set_playback_speed(0.0).is_err());
+    assert_eq!(spec_sess.playback_speed(), 1.0);
+
+    for _ in 0..50 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+
+    let mut host_stub = stubs::GameStub::new();
+    for i in 0..20 {
+        host_sess.add_local_input(0, StubInput { inp: i })?;
+        host_sess.add_local_input(1, StubInput { inp: i })?;
+        host_stub.handle_requests(host_sess.advance_frame()?);
+    }
+    for _ in 0..10 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+
+    // slow motion advances one frame every other call
+    let mut spec_stub = stubs::GameStub::new();
+    spec_sess.set_playback_speed(0.5)?;
+    for _ in 0..4 {
+        spec_stub.handle_requests(spec_sess.advance_frame()?);
+    }
+    assert_eq!(spec_stub.gs.frame, 2);
+
+    // fast forward, but never past the inputs received from the host
+    spec_sess.set_playback_speed(4.0)?;
+    spec_stub.handle_requests(spec_sess.advance_frame()?);
+    assert_eq!(spec_stub.gs.frame, 6);
+    while let Ok(requests) = spec_sess.advance_frame() {
+        spec_stub.handle_requests(requests);
+    }
+    assert_eq!(spec_sess.frames_behind_host(), 0);
+    assert_eq!(spec_stub.gs.frame, spec_sess.current_frame() + 1);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_spectator_desync_detection() -> Result<(), GgrsError> {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let desync_mode = DesyncDetection::On { interval: 2 };
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut host_sess = SessionBuilder::<StubConfig>::new()
+        .with_desync_detection_mode(desync_mode)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .add_player(PlayerType::Spectator(spec_addr), 2)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut spec_sess = SessionBuilder::<StubConfig>::new()
+        .with_spectator_desync_detection(desync_mode)
+        .start_spectator_session(host_addr, socket2);
+
+    for _ in 0..50 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+
+    let mut host_stub = stubs::GameStub::new();
+    let mut spec_stub = stubs::GameStub::new();
+    let mut desync_frames = Vec::new();
+    for i in 0..40 {
+        // the spectator agrees with the host as long as both simulate the same inputs, but not once the states diverge
+        if i == 20 {
+            assert!(desync_frames.is_empty());
+            spec_stub.gs.state += 1;
+        }
+        host_sess.add_local_input(0, StubInput { inp: i })?;
+        host_sess.add_local_input(1, StubInput { inp: i })?;
+        host_stub.handle_requests(host_sess.advance_frame()?);
+        for _ in 0..2 {
+            spec_sess.poll_remote_clients();
+            host_sess.poll_remote_clients();
+        }
+        while let Ok(requests) = spec_sess.advance_frame() {
+            spec_stub.handle_requests(requests);
+        }
+        desync_frames.extend(spec_sess.events().filter_map(|event| match event {
+            GgrsEvent::DesyncDetected { frame, addr, .. } => {
+                assert_eq!(addr, host_addr);
+                Some(frame)
+            }
+            _ => None,
+        }));
+    }
+    assert!(!desync_frames.is_empty());
+
+    Ok(())
+}
+
+/// A clock that only moves forward when the test says so.
+#[derive(Debug, Clone, Default)]
+struct ManualClock {
+    micros: Arc<AtomicU64>,
+}
+
+impl Clock for ManualClock {
+    fn now_micros(&self) -> u64 {
+        self.micros.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+#[serial]
+fn test_spectator_delay() -> Result<(), GgrsError> {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let spec_addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    assert!(SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .with_spectator_delay(0, Duration::from_secs(1))
+        .is_err());
+
+    let clock = ManualClock::default();
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut host_sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .add_player(PlayerType::Spectator(spec_addr1), 2)?
+        .add_player(PlayerType::Spectator(spec_addr2), 3)?
+        .with_spectator_delay(2, Duration::from_secs(1))?
+        .with_clock(clock.clone())
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut spec_sess1 =
+        SessionBuilder::<StubConfig>::new().start_spectator_session(host_addr, socket2);
+    let socket3 = UdpNonBlockingSocket::bind_to_port(9999).unwrap();
+    let mut spec_sess2 =
+        SessionBuilder::<StubConfig>::new().start_spectator_session(host_addr, socket3);
+
+    for _ in 0..50 {
+        spec_sess1.poll_remote_clients();
+        spec_sess2.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(host_sess.current_state(), SessionState::Running);
+
+    let mut host_stub = stubs::GameStub::new();
+    let mut spec_stub1 = stubs::GameStub::new();
+    let mut spec_stub2 = stubs::GameStub::new();
+    let mut advance_spectators = |host_sess: &mut ggrs::P2PSession<StubConfig>| {
+        for _ in 0..10 {
+            host_sess.poll_remote_clients();
+            spec_sess1.poll_remote_clients();
+            spec_sess2.poll_remote_clients();
+        }
+        while let Ok(requests) = spec_sess1.advance_frame() {
+            spec_stub1.handle_requests(requests);
+        }
+        while let Ok(requests) = spec_sess2.advance_frame() {
+            spec_stub2.handle_requests(requests);
+        }
+        (spec_stub1.gs.frame, spec_stub2.gs.frame)
+    };
+
+    for i in 0..20 {
+        host_sess.add_local_input(0, StubInput { inp: i })?;
+        host_sess.add_local_input(1, StubInput { inp: i })?;
+        host_stub.handle_requests(host_sess.advance_frame()?);
+    }
+
+    // the delayed spectator has not received any inputs yet, the other one is up to date
+    let (frame1, frame2) = advance_spectators(&mut host_sess);
+    assert_eq!(frame1, 0);
+    assert!(frame2 > 0);
+
+    // once the delay has passed, the held back inputs are sent as well, except for the frame confirmed just now
+    clock
+        .micros
+        .fetch_add(Duration::from_secs(1).as_micros() as u64, Ordering::Relaxed);
+    host_sess.add_local_input(0, StubInput { inp: 20 })?;
+    host_sess.add_local_input(1, StubInput { inp: 20 })?;
+    host_stub.handle_requests(host_sess.advance_frame()?);
+    let (frame1, frame2) = advance_spectators(&mut host_sess);
+    assert_eq!(frame1 + 1, frame2);
+
+    Ok(())
+}
+
+#[test]
+fn test_spectator_frame_limit() -> Result<(), GgrsError> {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(18);
+
+    // a spectator far behind catches up several frames at once, but not past the limit
+    let start_frame = MAX_FRAME - 20;
+    let (mut host_sess, mut spec_sess) = network.enter(|| -> Result<_, GgrsError> {
+        let host_sess = SessionBuilder::<StubConfig>::new()
+            .with_start_frame(start_frame)?
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Local, 1)?
+            .add_player(PlayerType::Spectator(spec_addr), 2)?
+            .start_p2p_session(network.socket(host_addr))?;
+        let spec_sess = SessionBuilder::<StubConfig>::new()
+            .with_start_frame(start_frame)?
+            .with_max_frames_behind(4)?
+            .with_catchup_speed(3)?
+            .start_spectator_session(host_addr, network.socket(spec_addr));
+        Ok((host_sess, spec_sess))
+    })?;
+    // no input of the host arrived yet
+    assert_eq!(spec_sess.frames_behind_host(), 0);
+
+    for _ in 0..100 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| -> Result<(), GgrsError> {
+            host_sess.poll_remote_clients();
+            spec_sess.poll_remote_clients();
+            if host_sess.current_state() != SessionState::Running {
+                return Ok(());
+            }
+            match host_sess.advance_frame_with_input(|_| StubInput { inp: 0 }) {
+                Ok(requests) => {
+                    for request in requests {
+                        if let GgrsRequest::SaveGameState { cell, frame } = request {
+                            cell.save(frame, None, None);
+                        }
+                    }
+                    Ok(())
+                }
+                // the host keeps trying, which hands the inputs of the last frame to the spectator
+                Err(GgrsError::FrameLimitReached) => Ok(()),
+                Err(e) => Err(e),
+            }
+        })?;
+    }
+    assert_eq!(host_sess.current_frame(), MAX_FRAME);
+
+    // the spectator simulates the same frames as the host
+    for _ in 0..100 {
+        network.advance(Duration::from_millis(16));
+        let result = network.enter(|| {
+            host_sess.poll_remote_clients();
+            spec_sess.poll_remote_clients();
+            spec_sess.advance_frame()
+        });
+        match result {
+            Ok(_) | Err(GgrsError::PredictionThreshold) => {
+                assert!(spec_sess.current_frame() < MAX_FRAME)
+            }
+            Err(GgrsError::FrameLimitReached) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    assert_eq!(spec_sess.current_frame(), MAX_FRAME - 1);
+    assert!(matches!(
+        network.enter(|| spec_sess.advance_frame()),
+        Err(GgrsError::FrameLimitReached)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_spectator_many_players() -> Result<(), GgrsError> {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let network = LoopbackNetwork::new(11).with_latency(Duration::from_millis(20));
+
+    // the inputs of this many players are packed into bitplanes when broadcast to the spectator
+    let num_players = 6;
+    let (mut host_sess, mut spec_sess) = network.enter(|| -> Result<_, GgrsError> {
+        let mut builder = SessionBuilder::<StubConfig>::new().with_num_players(num_players);
+        for handle in 0..num_players {
+            builder = builder.add_player(PlayerType::Local, handle)?;
+        }
+        let host_sess = builder
+            .add_player(PlayerType::Spectator(spec_addr), num_players)?
+            .start_p2p_session(network.socket(host_addr))?;
+        let spec_sess = SessionBuilder::<StubConfig>::new()
+            .with_num_players(num_players)
+            .start_spectator_session(host_addr, network.socket(spec_addr));
+        Ok((host_sess, spec_sess))
+    })?;
+
+    let input = |frame: i32, handle: usize| StubInput {
+        inp: ((frame as u32 / 5) << (handle * 3)) ^ (frame % 4 == handle as i32 % 4) as u32,
+    };
+    let mut host_stub = stubs::GameStub::new();
+    let mut spec_frame = 0;
+    for _ in 0..200 {
+        network.advance(Duration::from_millis(16));
+        network.enter(|| -> Result<(), GgrsError> {
+            host_sess.poll_remote_clients();
+            spec_sess.poll_remote_clients();
+            if host_sess.current_state() == SessionState::Running {
+                let frame = host_sess.current_frame();
+                let requests = host_sess.advance_frame_with_input(|handle| input(frame, handle))?;
+                host_stub.handle_requests(requests);
+            }
+            while let Ok(requests) = spec_sess.advance_frame() {
+                for request in requests {
+                    if let GgrsRequest::AdvanceFrame { inputs } = request {
+                        for (handle, (received, _)) in inputs.iter().enumerate() {
+                            assert!(*received == input(spec_frame, handle));
+                        }
+                        spec_frame += 1;
+                    }
+                }
+            }
+            Ok(())
+        })?;
+    }
+    assert!(spec_frame > 100);
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "quinn")]
+
+mod stubs;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ggrs::{GgrsError, PlayerType, QuinnSocket, SessionBuilder, SessionState};
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use quinn::{ClientConfig, Endpoint, ServerConfig};
+use stubs::{StubConfig, StubInput};
+
+/// Creates an endpoint accepting connections with a self-signed certificate and one trusting that certificate.
+fn endpoints() -> (Endpoint, Endpoint) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let cert = CertificateDer::from(certified.cert);
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()));
+    let server_config = ServerConfig::with_single_cert(vec![cert.clone()], key).unwrap();
+    let server = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+
+    let mut roots = quinn::rustls::RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let mut client = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    client
+        .set_default_client_config(ClientConfig::with_root_certificates(Arc::new(roots)).unwrap());
+    (server, client)
+}
+
+#[test]
+fn test_quinn_p2p_session() -> Result<(), GgrsError> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _guard = runtime.enter();
+
+    let (server, client) = endpoints();
+    let addr1: SocketAddr = server.local_addr().unwrap();
+    let addr2: SocketAddr = client.local_addr().unwrap();
+    let mut socket1 = QuinnSocket::new(server);
+    let mut socket2 = QuinnSocket::new(client);
+    let (accepted, connected) = runtime
+        .block_on(async { tokio::join!(socket1.accept(), socket2.connect(addr1, "localhost")) });
+    assert_eq!(accepted.unwrap().unwrap(), addr2);
+    connected.unwrap();
+
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    // the datagrams arrive in the background, so give them some time
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while sess1.current_state() != SessionState::Running
+        || sess2.current_state() != SessionState::Running
+    {
+        assert!(Instant::now() < deadline);
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(stub1.gs.frame, 10);
+    assert_eq!(stub2.gs.frame, 10);
+
+    // all inputs are confirmed eventually
+    while sess1.confirmed_frame() < 9 || sess2.confirmed_frame() < 9 {
+        assert!(Instant::now() < deadline);
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "stun")]
+
+use std::net::{SocketAddr, UdpSocket};
+
+use ggrs::{discover_public_addr, NonBlockingSocket, UdpNonBlockingSocket};
+
+/// Runs a STUN server on a thread that answers the given number of binding requests with the address they came from,
+/// after ignoring the first `ignored` ones.
+fn spawn_stun_server(ignored: usize, answered: usize) -> SocketAddr {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        for i in 0..ignored + answered {
+            let (len, from) = server.recv_from(&mut buffer).unwrap();
+            if i < ignored || len != 20 {
+                continue;
+            }
+            let SocketAddr::V4(from) = from else {
+                continue;
+            };
+            // a success response with the XOR-MAPPED-ADDRESS of the sender
+            let mut response = buffer[..20].to_vec();
+            response[0..4].copy_from_slice(&[0x01, 0x01, 0x00, 0x0c]);
+            response.extend_from_slice(&[0x00, 0x20, 0x00, 0x08, 0x00, 0x01]);
+            response.extend_from_slice(&(from.port() ^ 0x2112).to_be_bytes());
+            let ip = u32::from(*from.ip()) ^ 0x2112_A442;
+            response.extend_from_slice(&ip.to_be_bytes());
+            server.send_to(&response, from).unwrap();
+        }
+    });
+    addr
+}
+
+#[test]
+fn test_discover_public_addr() {
+    let server_addr = spawn_stun_server(0, 1);
+    let socket = UdpNonBlockingSocket::bind_to_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+    let public_addr = discover_public_addr(&socket, server_addr).unwrap();
+    assert_eq!(Some(public_addr), socket.local_addr());
+}
+
+#[test]
+fn test_discover_public_addr_retransmits() {
+    // the first request is lost, so the answer comes for the second one
+    let server_addr = spawn_stun_server(1, 1);
+    let socket = UdpNonBlockingSocket::bind_to_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+    let public_addr = discover_public_addr(&socket, server_addr).unwrap();
+    assert_eq!(Some(public_addr), socket.local_addr());
+}
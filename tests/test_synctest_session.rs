@@ -1,8 +1,10 @@
 mod stubs;
-mod stubs_enum;
 
-use ggrs::{GgrsError, GgrsRequest, SessionBuilder};
-use stubs::{StubConfig, StubInput};
+use ggrs::{
+    ChecksumKind, FixedInputConfig, GgrsError, GgrsRequest, InputStatus, Replay, Session,
+    SessionBuilder, SessionState, StateHasher, SyncTestSession,
+};
+use stubs::{StateStub, StubConfig, StubInput};
 
 #[test]
 fn test_create_session() {
@@ -11,6 +13,41 @@ fn test_create_session() {
         .is_ok());
 }
 
+#[test]
+fn test_introspection() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_input_delay(2)
+        .start_synctest_session()?;
+
+    assert_eq!(sess.input_delay(0)?, 2);
+    assert_eq!(sess.input_delay(1)?, 2);
+    assert_eq!(
+        sess.input_delay(2),
+        Err(GgrsError::InvalidPlayerHandle { handle: 2 })
+    );
+
+    // advancing without the input of every player fails
+    sess.add_local_input(0, StubInput { inp: 0 })?;
+    assert!(matches!(
+        sess.advance_frame(),
+        Err(GgrsError::MissingInput {
+            handle: 1,
+            frame: 0
+        })
+    ));
+
+    for i in 0..10 {
+        assert_eq!(sess.current_frame(), i as i32);
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(sess.advance_frame()?);
+    }
+    assert_eq!(sess.current_frame(), 10);
+
+    Ok(())
+}
+
 #[test]
 fn test_advance_frame_no_rollbacks() -> Result<(), GgrsError> {
     let check_distance = 0;
@@ -31,6 +68,50 @@ fn test_advance_frame_no_rollbacks() -> Result<(), GgrsError> {
     Ok(())
 }
 
+#[test]
+fn test_add_input_twice() -> Result<(), GgrsError> {
+    let mut sess = SessionBuilder::<StubConfig>::new().start_synctest_session()?;
+
+    sess.add_local_input(0, StubInput { inp: 0 })?;
+    assert_eq!(
+        sess.add_local_input(0, StubInput { inp: 1 }),
+        Err(GgrsError::InputAlreadyAdded {
+            handle: 0,
+            frame: 0
+        })
+    );
+    sess.add_local_input(1, StubInput { inp: 0 })?;
+    sess.advance_frame()?;
+
+    // input can be added again for the next frame
+    sess.add_local_input(0, StubInput { inp: 1 })?;
+    sess.add_local_input(1, StubInput { inp: 1 })?;
+    sess.advance_frame()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_advance_frame_with_input() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_check_distance(2)
+        .start_synctest_session()?;
+
+    for i in 0..50 {
+        let mut polled = Vec::new();
+        let requests = sess.advance_frame_with_input(|handle| {
+            polled.push(handle);
+            StubInput { inp: i }
+        })?;
+        assert_eq!(polled, vec![0, 1]);
+        stub.handle_requests(requests);
+        assert_eq!(stub.gs.frame, i as i32 + 1);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_advance_frame_with_rollbacks() -> Result<(), GgrsError> {
     let check_distance = 2;
@@ -64,6 +145,41 @@ fn test_advance_frame_with_rollbacks() -> Result<(), GgrsError> {
     Ok(())
 }
 
+#[test]
+fn test_advance_frame_with_rollback_requests() -> Result<(), GgrsError> {
+    let check_distance = 2;
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_check_distance(check_distance)
+        .with_rollback_requests(true)
+        .start_synctest_session()?;
+
+    for i in 0..20 {
+        sess.add_local_input(0, StubInput { inp: i as u32 })?;
+        sess.add_local_input(1, StubInput { inp: i as u32 })?;
+        let requests = sess.advance_frame()?;
+        if i > check_distance {
+            assert_eq!(requests.len(), 8); // begin, load, advance, save, advance, end, save, advance
+            assert!(matches!(
+                requests[0],
+                GgrsRequest::BeginRollback { from_frame, to_frame } if from_frame == i as i32 && to_frame == i as i32 - check_distance as i32
+            )); // rollback
+            assert!(matches!(requests[1], GgrsRequest::LoadGameState { .. })); // rollback
+            assert!(matches!(requests[2], GgrsRequest::AdvanceFrame { .. })); // rollback
+            assert!(matches!(requests[3], GgrsRequest::SaveGameState { .. })); // rollback
+            assert!(matches!(requests[4], GgrsRequest::AdvanceFrame { .. })); // rollback
+            assert!(matches!(requests[5], GgrsRequest::EndRollback)); // rollback
+            assert!(matches!(requests[6], GgrsRequest::SaveGameState { .. }));
+            assert!(matches!(requests[7], GgrsRequest::AdvanceFrame { .. }));
+        }
+
+        stub.handle_requests(requests);
+        assert_eq!(stub.gs.frame, i as i32 + 1); // frame should have advanced
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_advance_frames_with_delayed_input() -> Result<(), GgrsError> {
     let check_distance = 7;
@@ -84,6 +200,119 @@ fn test_advance_frames_with_delayed_input() -> Result<(), GgrsError> {
     Ok(())
 }
 
+#[test]
+fn test_advance_frames_with_short_input_queue() -> Result<(), GgrsError> {
+    let check_distance = 7;
+    // 2 * 8 (prediction window) + 2 (input delay) + 1 (save interval) inputs have to fit
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_input_delay(2)
+        .with_input_queue_length(19)?
+        .start_synctest_session()
+        .is_err());
+
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_check_distance(check_distance)
+        .with_input_delay(2)
+        .with_input_queue_length(20)?
+        .start_synctest_session()?;
+
+    for i in 0..200 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        let requests = sess.advance_frame()?;
+        stub.handle_requests(requests);
+        assert_eq!(stub.gs.frame, i as i32 + 1); // frame should have advanced
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_advance_frames_with_state_diffing() -> Result<(), GgrsError> {
+    let check_distance = 7;
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_check_distance(check_distance)
+        .with_state_diffing(true)
+        .start_synctest_session()?;
+
+    // every frame rolls back, so all loaded states have to be restored from their deltas
+    for i in 0..200 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        let requests = sess.advance_frame()?;
+        stub.handle_requests(requests);
+        assert_eq!(stub.gs.frame, i as i32 + 1); // frame should have advanced
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_replay_playback_speed() -> Result<(), GgrsError> {
+    let mut replay = Replay::<StubConfig>::new(2);
+    for i in 0..40 {
+        replay.push_frame(&[StubInput { inp: i }, StubInput { inp: i / 2 }])?;
+    }
+
+    // regular sync tests have no replay to speed up
+    let mut sess = SessionBuilder::<StubConfig>::new().start_synctest_session()?;
+    assert!(sess.set_playback_speed(2.0).is_err());
+
+    let mut sess = SyncTestSession::from_replay(replay.clone(), 4)?;
+    assert!(sess.set_playback_speed(-1.0).is_err());
+    sess.set_playback_speed(0.25)?;
+    let mut stub = stubs::GameStub::new();
+    for _ in 0..8 {
+        stub.handle_requests(sess.advance_frame()?);
+    }
+    assert_eq!(stub.gs.frame, 2);
+
+    // fast forward to the end, the last call only advances by the remaining frames
+    sess.set_playback_speed(4.0)?;
+    let mut calls = 0;
+    while !sess.is_replay_finished() {
+        stub.handle_requests(sess.advance_frame()?);
+        calls += 1;
+    }
+    assert_eq!(calls, 10);
+    assert_eq!(stub.gs.frame, 40);
+
+    // the simulation is the same as at normal speed
+    let mut sess = SyncTestSession::from_replay(replay, 4)?;
+    let mut normal_stub = stubs::GameStub::new();
+    while !sess.is_replay_finished() {
+        normal_stub.handle_requests(sess.advance_frame()?);
+    }
+    assert_eq!(normal_stub.gs.frame, stub.gs.frame);
+    assert_eq!(normal_stub.gs.state, stub.gs.state);
+
+    Ok(())
+}
+
+#[test]
+fn test_session_enum() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess: Session<StubConfig> = SessionBuilder::new().start_synctest_session()?.into();
+
+    assert_eq!(sess.current_state(), SessionState::Running);
+    assert_eq!(sess.local_player_handles(), vec![0, 1]);
+    for i in 0..20 {
+        sess.poll_remote_clients();
+        for handle in sess.local_player_handles() {
+            sess.add_local_input(handle, StubInput { inp: i })?;
+        }
+        stub.handle_requests(sess.advance_frame()?);
+        assert!(sess.events().is_empty());
+    }
+    assert_eq!(sess.current_frame(), 20);
+    assert_eq!(stub.gs.frame, 20);
+    assert!(matches!(sess, Session::SyncTest(ref s) if s.check_distance() == 2));
+
+    Ok(())
+}
+
 #[test]
 #[should_panic]
 fn test_advance_frames_with_random_checksums() {
@@ -101,3 +330,208 @@ fn test_advance_frames_with_random_checksums() {
         assert_eq!(stub.gs.frame, i as i32 + 1);
     }
 }
+
+#[test]
+fn test_fixed_input_config() -> Result<(), GgrsError> {
+    // a 3 byte input, which bytemuck has no dedicated array impl for
+    let mut sess = SessionBuilder::<FixedInputConfig<3, u64>>::new()
+        .with_check_distance(4)
+        .start_synctest_session()?;
+
+    let mut state = 0u64;
+    for i in 0..50u8 {
+        sess.add_local_input(0, [i, i % 3, 1])?;
+        sess.add_local_input(1, [i % 5, 0, 2])?;
+        for request in sess.advance_frame()? {
+            match request {
+                GgrsRequest::SaveGameState { cell, frame } => {
+                    cell.save(frame, Some(state), Some(state as u128))
+                }
+                GgrsRequest::LoadGameState { cell, .. } => state = cell.load().unwrap(),
+                GgrsRequest::AdvanceFrame { inputs } => {
+                    for (input, _) in inputs {
+                        state = state
+                            .wrapping_mul(31)
+                            .wrapping_add(
+                                u32::from_le_bytes([input[0], input[1], input[2], 0]) as u64
+                            );
+                    }
+                }
+                GgrsRequest::BeginRollback { .. } | GgrsRequest::EndRollback => (),
+            }
+        }
+    }
+    assert_eq!(sess.current_frame(), 50);
+
+    Ok(())
+}
+
+/// Handles the requests like [`stubs::GameStub`], but saves the states without checksums. If `corrupt` is set, every saved state
+/// is off by the number of states saved so far.
+fn handle_requests_without_checksums(
+    gs: &mut StateStub,
+    saves: &mut i32,
+    corrupt: bool,
+    requests: Vec<GgrsRequest<StubConfig>>,
+) {
+    for request in requests {
+        match request {
+            GgrsRequest::SaveGameState { cell, frame } => {
+                *saves += 1;
+                let mut state = *gs;
+                if corrupt {
+                    state.state += *saves;
+                }
+                cell.save(frame, Some(state), None);
+            }
+            GgrsRequest::LoadGameState { cell, .. } => *gs = cell.load().unwrap(),
+            GgrsRequest::AdvanceFrame { inputs } => gs.advance_frame(inputs),
+            GgrsRequest::BeginRollback { .. } | GgrsRequest::EndRollback => (),
+        }
+    }
+}
+
+#[test]
+fn test_auto_checksums() -> Result<(), GgrsError> {
+    for kind in [ChecksumKind::Fletcher32, ChecksumKind::XxHash64] {
+        // the checksums are computed before the states are compacted
+        let mut sess = SessionBuilder::<StubConfig>::new()
+            .with_check_distance(4)
+            .with_state_diffing(true)
+            .with_auto_checksums(kind)
+            .start_synctest_session()?;
+        let (mut gs, mut saves) = (StateStub::default(), 0);
+        for i in 0..50 {
+            sess.add_local_input(0, StubInput { inp: i })?;
+            sess.add_local_input(1, StubInput { inp: i % 3 })?;
+            handle_requests_without_checksums(&mut gs, &mut saves, false, sess.advance_frame()?);
+        }
+
+        // states that differ after resimulating are caught without the game computing any checksum
+        let mut sess = SessionBuilder::<StubConfig>::new()
+            .with_check_distance(4)
+            .with_auto_checksums(kind)
+            .start_synctest_session()?;
+        let (mut gs, mut saves) = (StateStub::default(), 0);
+        let result = (0..50).try_for_each(|i| {
+            sess.add_local_input(0, StubInput { inp: i })?;
+            sess.add_local_input(1, StubInput { inp: i % 3 })?;
+            handle_requests_without_checksums(&mut gs, &mut saves, true, sess.advance_frame()?);
+            Ok(())
+        });
+        assert!(matches!(result, Err(GgrsError::MismatchedChecksum { .. })));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_state_hasher() -> Result<(), GgrsError> {
+    // a hasher that cannot tell any states apart
+    #[derive(Debug)]
+    struct ConstantHasher;
+
+    impl StateHasher for ConstantHasher {
+        fn hash(&self, _bytes: &[u8]) -> u128 {
+            7
+        }
+    }
+
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_check_distance(4)
+        .with_state_hasher(ConstantHasher)
+        .start_synctest_session()?;
+    let (mut gs, mut saves) = (StateStub::default(), 0);
+    for i in 0..50 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i % 3 })?;
+        handle_requests_without_checksums(&mut gs, &mut saves, true, sess.advance_frame()?);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_inputs_since() -> Result<(), GgrsError> {
+    // the input history has to fit into the input queue
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_input_history(120)
+        .start_synctest_session()
+        .is_err());
+
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_check_distance(2)
+        .with_input_history(30)
+        .start_synctest_session()?;
+
+    for i in 0..50 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: 100 + i })?;
+        stub.handle_requests(sess.advance_frame()?);
+    }
+
+    // with a check distance of 2, the inputs up to frame 48 are confirmed, and the 30 frames before are kept
+    let inputs = sess.inputs_since(20)?;
+    assert_eq!(inputs.len(), 29);
+    for (frame, inputs) in (20..).zip(&inputs) {
+        assert!(inputs == &[StubInput { inp: frame }, StubInput { inp: 100 + frame }]);
+    }
+    assert!(sess.inputs_since(49)?.is_empty());
+    assert!(matches!(
+        sess.inputs_since(17),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_input_script() -> Result<(), GgrsError> {
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_check_distance(3)
+        .start_synctest_session()?;
+
+    // player 1 stands in for a remote player that disconnects at frame 20
+    let mut asked = Vec::new();
+    sess.set_input_script(1, move |frame| {
+        asked.push(frame);
+        assert_eq!(asked.iter().filter(|&&f| f == frame).count(), 1);
+        (frame < 20).then_some(StubInput {
+            inp: 100 + frame as u32,
+        })
+    })?;
+    assert!(matches!(
+        sess.set_input_script(2, |_| None),
+        Err(GgrsError::InvalidPlayerHandle { handle: 2 })
+    ));
+    assert!(matches!(
+        sess.add_local_input(1, StubInput { inp: 0 }),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+
+    let mut stub = stubs::GameStub::new();
+    for i in 0..40 {
+        let requests = sess.advance_frame_with_input(|_| StubInput { inp: i })?;
+        // the newest frame is advanced last, after the rollback
+        let Some(GgrsRequest::AdvanceFrame { inputs }) = requests.last() else {
+            panic!("Expected the frame to be advanced");
+        };
+        assert!(inputs[0] == (StubInput { inp: i }, InputStatus::Confirmed));
+        if i < 20 {
+            assert!(inputs[1] == (StubInput { inp: 100 + i }, InputStatus::Confirmed));
+        } else {
+            assert_eq!(inputs[1].1, InputStatus::Disconnected);
+        }
+        // rollbacks across the disconnect hand out the same inputs
+        for request in &requests {
+            if let GgrsRequest::AdvanceFrame { inputs } = request {
+                assert_ne!(inputs[1].1, InputStatus::Predicted);
+            }
+        }
+        stub.handle_requests(requests);
+    }
+    assert_eq!(sess.current_frame(), 40);
+
+    Ok(())
+}